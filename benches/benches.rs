@@ -2,7 +2,8 @@ use hpke::{
     aead::{Aead as AeadTrait, AeadCtxR, AeadTag},
     kdf::Kdf as KdfTrait,
     kem::Kem as KemTrait,
-    setup_receiver, setup_sender, OpModeR, OpModeS, PskBundle,
+    setup_receiver, setup_sender, setup_sender_with_handle, OpModeR, OpModeS, PskBundle,
+    RecipientHandle,
 };
 
 use criterion::{black_box, criterion_main, Criterion};
@@ -13,6 +14,8 @@ use std::time::Instant;
 const AAD_LEN: usize = 64;
 // Length of plaintext and ciphertext for all seal/open benchmarks
 const MSG_LEN: usize = 64;
+// Plaintext lengths for the small-message seal/open benchmarks
+const SMALL_MSG_LENS: &[usize] = &[32, 64];
 // Length of PSK. Since we're only testing the 128-bit security level, make it 128 bits
 const PSK_LEN: usize = 16;
 
@@ -48,7 +51,7 @@ where
 
     // Construct all the opmodes we'll use in setup_sender and setup_receiver
     let opmodes = ["base", "auth", "psk", "authpsk"];
-    let opmodes_s = vec![
+    let opmodes_s = [
         OpModeS::Base,
         OpModeS::Auth((sk_sender.clone(), pk_sender.clone())),
         OpModeS::Psk(psk_bundle),
@@ -76,17 +79,34 @@ where
         });
     }
 
+    // Bench setup_sender_with_handle() for each opmode. The handle is made once, outside the
+    // loop, since the point is to amortize it over many calls
+    let handle = RecipientHandle::<Kem>::new(&pk_recip);
+    for (mode, opmode_s) in opmodes.iter().zip(opmodes_s.iter()) {
+        let bench_name = format!("setup_sender_with_handle[mode={}]", mode);
+        group.bench_function(bench_name, |b| {
+            b.iter(|| {
+                setup_sender_with_handle::<Aead, Kdf, Kem, _>(
+                    opmode_s,
+                    &handle,
+                    b"bench setup sender",
+                    &mut csprng,
+                )
+            })
+        });
+    }
+
+    // Bench making the handle itself, so it's clear how many calls it takes to pay for itself
+    group.bench_function("RecipientHandle::new", |b| {
+        b.iter(|| RecipientHandle::<Kem>::new(&pk_recip))
+    });
+
     // Collect the encapsulated keys from each setup_sender under each opmode. We will pass these
     // to setup_receiver in a moment
     let encapped_keys = opmodes_s.iter().map(|opmode_s| {
-        setup_sender::<Aead, Kdf, Kem, _>(
-            &opmode_s,
-            &pk_recip,
-            b"bench setup receiver",
-            &mut csprng,
-        )
-        .unwrap()
-        .0
+        setup_sender::<Aead, Kdf, Kem, _>(opmode_s, &pk_recip, b"bench setup receiver", &mut csprng)
+            .unwrap()
+            .0
     });
 
     // Bench setup_receiver for each opmode
@@ -147,15 +167,42 @@ where
             let start = Instant::now();
             for (mut ciphertext, aad, tag) in ciphertext_aad_tags.into_iter() {
                 // black_box makes sure the compiler doesn't optimize away this computation
-                black_box(
-                    decryption_ctx
-                        .open_in_place_detached(&mut ciphertext, &aad, &tag)
-                        .unwrap(),
-                );
+                black_box(decryption_ctx.open_in_place_detached(&mut ciphertext, &aad, &tag))
+                    .unwrap();
             }
             start.elapsed()
         });
     });
+
+    // Bench the allocating seal() and open() on small messages. At these sizes, the fixed
+    // per-call overhead is a large fraction of the total, so this is where it shows up.
+    for &msg_len in SMALL_MSG_LENS {
+        let bench_name = format!("seal[msglen={},aadlen={}]", msg_len, AAD_LEN);
+        group.bench_function(bench_name, |b| {
+            // Pick random inputs
+            let mut plaintext = vec![0u8; msg_len];
+            let mut aad = [0u8; AAD_LEN];
+            csprng.fill_bytes(&mut plaintext);
+            csprng.fill_bytes(&mut aad);
+
+            b.iter(|| encryption_ctx.seal(&plaintext, &aad).unwrap())
+        });
+
+        let bench_name = format!("open[msglen={},aadlen={}]", msg_len, AAD_LEN);
+        group.bench_function(bench_name, |b| {
+            b.iter_custom(|iters| {
+                // Same idea as the open_in_place_detached bench above
+                let (mut decryption_ctx, ciphertext_aads) =
+                    make_decryption_ctx_with_sealed::<Aead, Kdf, Kem>(iters as usize, msg_len);
+
+                let start = Instant::now();
+                for (ciphertext, aad) in ciphertext_aads.iter() {
+                    black_box(decryption_ctx.open(ciphertext, aad)).unwrap();
+                }
+                start.elapsed()
+            });
+        });
+    }
 }
 
 // A tuple of (ciphertext, aad, auth_tag) resulting from a call to seal()
@@ -207,6 +254,47 @@ where
     (decryption_ctx, ciphertext_aad_tags)
 }
 
+// A tuple of (ciphertext, aad) resulting from a call to seal()
+type CiphertextAad = (Vec<u8>, [u8; AAD_LEN]);
+
+// Constructs a decryption context with num_ciphertexts many (ciphertext, aad) pairs, each with a
+// msg_len-byte plaintext, that are decryptable in sequence
+fn make_decryption_ctx_with_sealed<Aead, Kdf, Kem>(
+    num_ciphertexts: usize,
+    msg_len: usize,
+) -> (AeadCtxR<Aead, Kdf, Kem>, Vec<CiphertextAad>)
+where
+    Aead: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut csprng = StdRng::from_entropy();
+
+    // Make up the recipient's keypair and setup an encryption context
+    let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+    let (encapped_key, mut encryption_ctx) =
+        setup_sender::<Aead, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"bench open", &mut csprng)
+            .unwrap();
+
+    // Seal num_ciphertexts many random (plaintext, aad) pairs
+    let ciphertext_aads = (0..num_ciphertexts)
+        .map(|_| {
+            let mut plaintext = vec![0u8; msg_len];
+            let mut aad = [0u8; AAD_LEN];
+            csprng.fill_bytes(&mut plaintext);
+            csprng.fill_bytes(&mut aad);
+            (encryption_ctx.seal(&plaintext, &aad).unwrap(), aad)
+        })
+        .collect();
+
+    // Build the recipient's decryption context from the sender's encapsulated key
+    let decryption_ctx =
+        setup_receiver::<Aead, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"bench open")
+            .unwrap();
+
+    (decryption_ctx, ciphertext_aads)
+}
+
 pub fn benches() {
     let mut c = Criterion::default().configure_from_args();
 
@@ -217,6 +305,14 @@ pub fn benches() {
         &mut c,
     );
 
+    // The secp256k1 ciphersuite at the 128-bit security level is AES-GCM-128, HKDF-SHA256, and
+    // ECDH-K256
+    #[cfg(feature = "k256")]
+    bench_ciphersuite::<hpke::aead::AesGcm128, hpke::kdf::HkdfSha256, hpke::kem::DhK256HkdfSha256>(
+        "secp256k1[seclevel=128]",
+        &mut c,
+    );
+
     // Non-NIST ciphersuite at the 128-bit security level is ChaCha20Poly1305, HKDF-SHA256, and X25519
     #[cfg(feature = "x25519")]
     bench_ciphersuite::<
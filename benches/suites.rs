@@ -0,0 +1,392 @@
+//! Benchmarks every KEM, AEAD, and ciphersuite that's compiled in, and writes a machine-readable
+//! summary of the results for tracking performance between releases.
+//!
+//! The KEM operations only depend on the KEM, and seal/open only depend on the AEAD, so those are
+//! benched once per KEM and once per AEAD. Setup depends on all three, so it's benched for every
+//! (KEM, KDF, AEAD) combination.
+//!
+//! After a `cargo bench` run, one JSON object per benchmark is written, one per line, to
+//! `hpke-summary.jsonl` in criterion's output directory, or to the path in the
+//! `HPKE_BENCH_SUMMARY` environment variable.
+
+use hpke::{
+    aead::{Aead as AeadTrait, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    setup_receiver, setup_sender, OpModeR, OpModeS,
+};
+
+use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+// Plaintext lengths for the seal/open benchmarks
+const MSG_LENS: &[usize] = &[16, 64, 1024, 16 * 1024, 64 * 1024];
+// Length of AAD for all seal/open benchmarks
+const AAD_LEN: usize = 64;
+// The open benchmark seals its ciphertexts ahead of time. This caps how many bytes of them it
+// holds at once, so large messages don't need gigabytes.
+const MAX_PRESEALED_BYTES: usize = 4 * 1024 * 1024;
+
+// Benches keypair generation, encapsulation, and decapsulation for the KEM `Kem`
+fn bench_kem<Kem: KemTrait>(kem_name: &str, c: &mut Criterion, ids: &mut Vec<String>) {
+    let mut csprng = StdRng::from_entropy();
+    let group_name = format!("kem/{}", kem_name);
+    let mut group = c.benchmark_group(&group_name);
+
+    group.bench_function("gen_keypair", |b| b.iter(|| Kem::gen_keypair(&mut csprng)));
+
+    let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+    group.bench_function("encap", |b| {
+        b.iter(|| Kem::encap(&pk_recip, None, &mut csprng).unwrap())
+    });
+
+    let (_, encapped_key) = Kem::encap(&pk_recip, None, &mut csprng).unwrap();
+    group.bench_function("decap", |b| {
+        b.iter(|| Kem::decap(&sk_recip, None, &encapped_key).unwrap())
+    });
+
+    group.finish();
+    for f in ["gen_keypair", "encap", "decap"] {
+        ids.push(format!("{}/{}", group_name, f));
+    }
+}
+
+// Benches seal_in_place_detached() and open_in_place_detached() for the AEAD `A` at every length
+// in MSG_LENS. The KDF and KEM only matter for setup, which happens outside the timed section.
+fn bench_aead<A, Kdf, Kem>(aead_name: &str, c: &mut Criterion, ids: &mut Vec<String>)
+where
+    A: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut csprng = StdRng::from_entropy();
+    let group_name = format!("aead/{}", aead_name);
+    let mut group = c.benchmark_group(&group_name);
+
+    let (_, pk_recip) = Kem::gen_keypair(&mut csprng);
+    let (_, mut sender_ctx) =
+        setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"bench seal", &mut csprng)
+            .unwrap();
+
+    for &msg_len in MSG_LENS {
+        group.throughput(Throughput::Bytes(msg_len as u64));
+
+        group.bench_with_input(BenchmarkId::new("seal", msg_len), &msg_len, |b, &len| {
+            let mut plaintext = vec![0u8; len];
+            let mut aad = [0u8; AAD_LEN];
+            csprng.fill_bytes(&mut plaintext);
+            csprng.fill_bytes(&mut aad);
+
+            b.iter(|| {
+                sender_ctx
+                    .seal_in_place_detached(&mut plaintext, &aad)
+                    .unwrap()
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("open", msg_len), &msg_len, |b, &len| {
+            b.iter_custom(|iters| {
+                // Contexts open in sequence, so every ciphertext has to be sealed ahead of time.
+                // Do it in chunks of at most MAX_PRESEALED_BYTES, and only time the opening.
+                let chunk_len = (MAX_PRESEALED_BYTES / len).max(1) as u64;
+                let mut elapsed = Duration::ZERO;
+                let mut remaining = iters;
+                while remaining > 0 {
+                    let n = remaining.min(chunk_len) as usize;
+                    let mut sealed = seal_in_sequence::<A, Kdf, Kem>(n, len);
+
+                    let start = Instant::now();
+                    for (ciphertext, aad, tag) in sealed.ciphertexts.iter_mut() {
+                        black_box(
+                            sealed
+                                .receiver_ctx
+                                .open_in_place_detached(ciphertext, aad, tag),
+                        )
+                        .unwrap();
+                    }
+                    elapsed += start.elapsed();
+
+                    remaining -= n as u64;
+                }
+                elapsed
+            })
+        });
+
+        ids.push(format!("{}/seal/{}", group_name, msg_len));
+        ids.push(format!("{}/open/{}", group_name, msg_len));
+    }
+
+    group.finish();
+}
+
+// A receiver context and the ciphertexts, AADs, and tags it can open, in order
+struct Sealed<A: AeadTrait, Kdf: KdfTrait, Kem: KemTrait> {
+    receiver_ctx: hpke::aead::AeadCtxR<A, Kdf, Kem>,
+    ciphertexts: Vec<(Vec<u8>, [u8; AAD_LEN], AeadTag<A>)>,
+}
+
+// Sets up a fresh sender and receiver, and seals n random msg_len-byte plaintexts
+fn seal_in_sequence<A, Kdf, Kem>(n: usize, msg_len: usize) -> Sealed<A, Kdf, Kem>
+where
+    A: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut csprng = StdRng::from_entropy();
+    let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+    let (encapped_key, mut sender_ctx) =
+        setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"bench open", &mut csprng)
+            .unwrap();
+    let receiver_ctx =
+        setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"bench open")
+            .unwrap();
+
+    let ciphertexts = (0..n)
+        .map(|_| {
+            let mut buf = vec![0u8; msg_len];
+            let mut aad = [0u8; AAD_LEN];
+            csprng.fill_bytes(&mut buf);
+            csprng.fill_bytes(&mut aad);
+            let tag = sender_ctx.seal_in_place_detached(&mut buf, &aad).unwrap();
+            (buf, aad, tag)
+        })
+        .collect();
+
+    Sealed {
+        receiver_ctx,
+        ciphertexts,
+    }
+}
+
+// Benches setup_sender() and setup_receiver() in Base mode for the full ciphersuite
+fn bench_suite<A, Kdf, Kem>(suite_name: &str, c: &mut Criterion, ids: &mut Vec<String>)
+where
+    A: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut csprng = StdRng::from_entropy();
+    let group_name = format!("suite/{}", suite_name);
+    let mut group = c.benchmark_group(&group_name);
+
+    let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+    group.bench_function("setup_sender", |b| {
+        b.iter(|| {
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"bench setup", &mut csprng)
+                .unwrap()
+        })
+    });
+
+    let (encapped_key, _) =
+        setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"bench setup", &mut csprng)
+            .unwrap();
+    group.bench_function("setup_receiver", |b| {
+        b.iter(|| {
+            setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"bench setup")
+                .unwrap()
+        })
+    });
+
+    group.finish();
+    for f in ["setup_sender", "setup_receiver"] {
+        ids.push(format!("{}/{}", group_name, f));
+    }
+}
+
+// Runs bench_suite for the KEM $kem with every KDF and every AEAD
+macro_rules! bench_all_suites_for_kem {
+    ($kem:ident, $c:expr, $ids:expr) => {
+        bench_all_suites_for_kem!(
+            @kdfs $kem, $c, $ids,
+            [HkdfSha256, HkdfSha384, HkdfSha512]
+        );
+    };
+    (@kdfs $kem:ident, $c:expr, $ids:expr, [$($kdf:ident),*]) => {
+        $(
+            bench_all_suites_for_kem!(@aeads $kem, $kdf, $c, $ids, [
+                AesGcm128, AesGcm256, ChaCha20Poly1305, AesOcb128, AesOcb256
+            ]);
+        )*
+    };
+    (@aeads $kem:ident, $kdf:ident, $c:expr, $ids:expr, [$($aead:ident),*]) => {
+        $(
+            bench_suite::<hpke::aead::$aead, hpke::kdf::$kdf, hpke::kem::$kem>(
+                concat!(stringify!($kem), ",", stringify!($kdf), ",", stringify!($aead)),
+                $c,
+                $ids,
+            );
+        )*
+    };
+}
+
+// Runs bench_aead for every AEAD, with the KEM $kem for setup
+macro_rules! bench_all_aeads {
+    ($kem:ident, $c:expr, $ids:expr, [$($aead:ident),*]) => {
+        $(
+            bench_aead::<hpke::aead::$aead, hpke::kdf::HkdfSha256, hpke::kem::$kem>(
+                stringify!($aead),
+                $c,
+                $ids,
+            );
+        )*
+    };
+}
+
+// Criterion's output directory, found the same way criterion finds it
+fn criterion_home() -> PathBuf {
+    if let Some(dir) = env::var_os("CRITERION_HOME") {
+        return PathBuf::from(dir);
+    }
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target"));
+    target_dir.join("criterion")
+}
+
+// Collects the paths of every benchmark.json that criterion wrote for the latest run
+fn find_benchmark_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_benchmark_files(&path, out);
+        } else if path.file_name().is_some_and(|f| f == "benchmark.json")
+            && path
+                .parent()
+                .and_then(|p| p.file_name())
+                .is_some_and(|d| d == "new")
+        {
+            out.push(path);
+        }
+    }
+}
+
+// Writes one line of JSON per benchmark in `ids` that ran since `started`, with the point
+// estimates and 95% confidence intervals criterion computed, in nanoseconds per iteration.
+// Benchmarks filtered out on the command line keep their old results on disk, which is why the
+// start time matters.
+fn write_summary(ids: &[String], started: SystemTime) {
+    let home = criterion_home();
+    let wanted: HashSet<&str> = ids.iter().map(String::as_str).collect();
+
+    let mut files = Vec::new();
+    find_benchmark_files(&home, &mut files);
+    let mut lines = Vec::new();
+    for benchmark_file in files {
+        let read_json = |path: &Path| -> Option<serde_json::Value> {
+            serde_json::from_slice(&fs::read(path).ok()?).ok()
+        };
+        let Some(benchmark) = read_json(&benchmark_file) else {
+            continue;
+        };
+        let Some(full_id) = benchmark["full_id"].as_str() else {
+            continue;
+        };
+        let modified = fs::metadata(&benchmark_file).and_then(|m| m.modified());
+        if !wanted.contains(full_id) || modified.map_or(true, |t| t < started) {
+            continue;
+        }
+        let Some(estimates) = read_json(&benchmark_file.with_file_name("estimates.json")) else {
+            continue;
+        };
+
+        let estimate = |name: &str| {
+            serde_json::json!({
+                "point_estimate": estimates[name]["point_estimate"],
+                "lower_bound": estimates[name]["confidence_interval"]["lower_bound"],
+                "upper_bound": estimates[name]["confidence_interval"]["upper_bound"],
+            })
+        };
+        let line = serde_json::json!({
+            "id": full_id,
+            "hpke_version": env!("CARGO_PKG_VERSION"),
+            "unit": "ns",
+            "mean": estimate("mean"),
+            "median": estimate("median"),
+            "std_dev": estimate("std_dev"),
+            "throughput_bytes": benchmark["throughput"]["Bytes"],
+        });
+        lines.push((full_id.to_string(), line.to_string()));
+    }
+    lines.sort();
+
+    let path = env::var_os("HPKE_BENCH_SUMMARY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join("hpke-summary.jsonl"));
+    let mut f = fs::File::create(&path).expect("couldn't create benchmark summary");
+    for (_, line) in lines {
+        writeln!(f, "{}", line).unwrap();
+    }
+    println!("Wrote benchmark summary to {}", path.display());
+}
+
+pub fn benches() {
+    let mut c = Criterion::default().configure_from_args();
+    let mut ids = Vec::new();
+    let started = SystemTime::now();
+
+    #[cfg(feature = "x25519")]
+    {
+        bench_kem::<hpke::kem::X25519HkdfSha256>("X25519HkdfSha256", &mut c, &mut ids);
+        bench_all_suites_for_kem!(X25519HkdfSha256, &mut c, &mut ids);
+    }
+    #[cfg(feature = "p256")]
+    {
+        bench_kem::<hpke::kem::DhP256HkdfSha256>("DhP256HkdfSha256", &mut c, &mut ids);
+        bench_all_suites_for_kem!(DhP256HkdfSha256, &mut c, &mut ids);
+    }
+    #[cfg(feature = "k256")]
+    {
+        bench_kem::<hpke::kem::DhK256HkdfSha256>("DhK256HkdfSha256", &mut c, &mut ids);
+        bench_all_suites_for_kem!(DhK256HkdfSha256, &mut c, &mut ids);
+    }
+
+    // The AEAD benches need some KEM for setup. Any will do.
+    #[cfg(feature = "x25519")]
+    bench_all_aeads!(
+        X25519HkdfSha256,
+        &mut c,
+        &mut ids,
+        [AesGcm128, AesGcm256, ChaCha20Poly1305, AesOcb128, AesOcb256]
+    );
+    #[cfg(all(feature = "reduced-round", feature = "x25519"))]
+    bench_all_aeads!(
+        X25519HkdfSha256,
+        &mut c,
+        &mut ids,
+        [ChaCha12Poly1305, ChaCha8Poly1305]
+    );
+    #[cfg(all(not(feature = "x25519"), feature = "p256"))]
+    bench_all_aeads!(
+        DhP256HkdfSha256,
+        &mut c,
+        &mut ids,
+        [AesGcm128, AesGcm256, ChaCha20Poly1305, AesOcb128, AesOcb256]
+    );
+    #[cfg(all(not(feature = "x25519"), not(feature = "p256"), feature = "k256"))]
+    bench_all_aeads!(
+        DhK256HkdfSha256,
+        &mut c,
+        &mut ids,
+        [AesGcm128, AesGcm256, ChaCha20Poly1305, AesOcb128, AesOcb256]
+    );
+
+    c.final_summary();
+
+    // Criterion only measures, and writes results, under `cargo bench`. Under `cargo test`, it
+    // runs each benchmark once as a smoke test, so there's nothing to summarize.
+    if env::args().any(|arg| arg == "--bench") {
+        write_summary(&ids, started);
+    }
+}
+
+criterion::criterion_main!(benches);
@@ -324,13 +324,13 @@ struct AgileOpModeR<'a> {
 }
 
 impl<'a> AgileOpModeR<'a> {
-    fn try_lift<Kem: KemTrait, Kdf: KdfTrait>(self) -> Result<OpModeR<'a, Kem>, AgileHpkeError> {
+    fn try_lift<Kem: KemTrait>(self) -> Result<OpModeR<'a, Kem>, AgileHpkeError> {
         let res = match self.op_mode_ty {
             AgileOpModeRTy::Base => OpModeR::Base,
-            AgileOpModeRTy::Psk(bundle) => OpModeR::Psk(bundle.try_lift::<Kdf>()?),
+            AgileOpModeRTy::Psk(bundle) => OpModeR::Psk(bundle.try_lift()?),
             AgileOpModeRTy::Auth(pk) => OpModeR::Auth(pk.try_lift::<Kem>()?),
             AgileOpModeRTy::AuthPsk(pk, bundle) => {
-                OpModeR::AuthPsk(pk.try_lift::<Kem>()?, bundle.try_lift::<Kdf>()?)
+                OpModeR::AuthPsk(pk.try_lift::<Kem>()?, bundle.try_lift()?)
             }
         };
 
@@ -339,27 +339,16 @@ impl<'a> AgileOpModeR<'a> {
 
     fn validate(&self) -> Result<(), AgileHpkeError> {
         match &self.op_mode_ty {
-            AgileOpModeRTy::Auth(pk) => {
-                if pk.kem_alg != self.kem_alg {
-                    return Err(AgileHpkeError::AlgMismatch(
-                        (self.kem_alg.name(), "AgileOpModeR::kem_alg"),
-                        (
-                            pk.kem_alg.name(),
-                            "AgileOpModeR::op_mode_ty::AgilePublicKey::kem_alg",
-                        ),
-                    ));
-                }
-            }
-            AgileOpModeRTy::AuthPsk(pk, _) => {
-                if pk.kem_alg != self.kem_alg {
-                    return Err(AgileHpkeError::AlgMismatch(
-                        (self.kem_alg.name(), "AgileOpModeR::kem_alg"),
-                        (
-                            pk.kem_alg.name(),
-                            "AgileOpModeR::op_mode_ty::AgilePublicKey::kem_alg",
-                        ),
-                    ));
-                }
+            AgileOpModeRTy::Auth(pk) | AgileOpModeRTy::AuthPsk(pk, _)
+                if pk.kem_alg != self.kem_alg =>
+            {
+                return Err(AgileHpkeError::AlgMismatch(
+                    (self.kem_alg.name(), "AgileOpModeR::kem_alg"),
+                    (
+                        pk.kem_alg.name(),
+                        "AgileOpModeR::op_mode_ty::AgilePublicKey::kem_alg",
+                    ),
+                ));
             }
             _ => (),
         }
@@ -383,13 +372,13 @@ struct AgileOpModeS<'a> {
 }
 
 impl<'a> AgileOpModeS<'a> {
-    fn try_lift<Kem: KemTrait, Kdf: KdfTrait>(self) -> Result<OpModeS<'a, Kem>, AgileHpkeError> {
+    fn try_lift<Kem: KemTrait>(self) -> Result<OpModeS<'a, Kem>, AgileHpkeError> {
         let res = match self.op_mode_ty {
             AgileOpModeSTy::Base => OpModeS::Base,
-            AgileOpModeSTy::Psk(bundle) => OpModeS::Psk(bundle.try_lift::<Kdf>()?),
+            AgileOpModeSTy::Psk(bundle) => OpModeS::Psk(bundle.try_lift()?),
             AgileOpModeSTy::Auth(keypair) => OpModeS::Auth(keypair.try_lift::<Kem>()?),
             AgileOpModeSTy::AuthPsk(keypair, bundle) => {
-                OpModeS::AuthPsk(keypair.try_lift::<Kem>()?, bundle.try_lift::<Kdf>()?)
+                OpModeS::AuthPsk(keypair.try_lift::<Kem>()?, bundle.try_lift()?)
             }
         };
 
@@ -441,7 +430,7 @@ enum AgileOpModeSTy<'a> {
 struct AgilePskBundle<'a>(PskBundle<'a>);
 
 impl<'a> AgilePskBundle<'a> {
-    fn try_lift<Kdf: KdfTrait>(self) -> Result<PskBundle<'a>, AgileHpkeError> {
+    fn try_lift(self) -> Result<PskBundle<'a>, AgileHpkeError> {
         Ok(self.0)
     }
 }
@@ -529,7 +518,7 @@ where
     R: CryptoRng + RngCore,
 {
     let kem_alg = mode.kem_alg;
-    let mode = mode.clone().try_lift::<Kem, Kdf>()?;
+    let mode = mode.clone().try_lift::<Kem>()?;
     let pk_recip = pk_recip.try_lift::<Kem>()?;
 
     let (encapped_key, aead_ctx) = setup_sender::<A, Kdf, Kem, _>(&mode, &pk_recip, info, csprng)?;
@@ -594,6 +583,7 @@ fn agile_setup_sender<R: CryptoRng + RngCore>(
 
 // The leg work of agile_setup_receiver. The Dummy type parameter is so that it can be used with
 // the hpke_dispatch! macro. The macro expects its callback function to have 4 type parameters
+#[allow(clippy::extra_unused_type_parameters)]
 fn do_setup_receiver<A, Kdf, Kem, Dummy>(
     mode: &AgileOpModeR,
     recip_keypair: &AgileKeypair,
@@ -605,7 +595,7 @@ where
     Kdf: 'static + KdfTrait,
     Kem: 'static + KemTrait,
 {
-    let mode = mode.clone().try_lift::<Kem, Kdf>()?;
+    let mode = mode.clone().try_lift::<Kem>()?;
     let (sk_recip, _) = recip_keypair.try_lift::<Kem>()?;
     let encapped_key = encapped_key.try_lift::<Kem>()?;
 
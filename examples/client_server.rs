@@ -91,10 +91,8 @@ fn server_decrypt_msg(
         .open_in_place_detached(&mut ciphertext_copy, associated_data, &tag)
         .expect("invalid ciphertext!");
 
-    // Rename for clarity
-    let plaintext = ciphertext_copy;
-
-    plaintext
+    // The buffer now holds the plaintext
+    ciphertext_copy
 }
 
 fn main() {
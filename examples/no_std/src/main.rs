@@ -0,0 +1,96 @@
+// This is the client_server example, redone for an embedded recipient with no allocator and no
+// std. Everything lives on the stack, and the only APIs used are the ones that are available with
+// `default-features = false`:
+//  * `setup_receiver`, and `setup_sender_deterministic` to stand in for a sender
+//  * `AeadCtxS::seal_in_place_detached` and `AeadCtxR::open_in_place_detached`
+//  * Serialization to and from fixed-size `GenericArray`s
+//
+// The process exit code is 0 if the message made the round trip, and 1 otherwise. On Linux and
+// macOS, the binary links against the platform C library for its entry point. On a real device,
+// replace `main` with your board's entry point and drop the C library.
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+use hpke::{
+    aead::{AeadTag, ChaCha20Poly1305},
+    kdf::HkdfSha256,
+    kem::X25519HkdfSha256,
+    setup_receiver, setup_sender_deterministic, Deserializable, Kem as KemTrait, OpModeR, OpModeS,
+    Serializable,
+};
+
+type Kem = X25519HkdfSha256;
+type Aead = ChaCha20Poly1305;
+type Kdf = HkdfSha256;
+
+const INFO_STR: &[u8] = b"no_std example session";
+const MSG: &[u8] = b"hello from a device with no heap";
+const AAD: &[u8] = b"firmware v1";
+
+// There's no std to pull in the C library, so ask for it explicitly. It provides the process entry
+// point that calls main, and the memcpy/memset that the compiler emits calls to.
+#[cfg(unix)]
+#[link(name = "c")]
+extern "C" {}
+
+// The prebuilt core library is compiled with unwinding, so it references this symbol even though
+// panics abort here. It's never called.
+#[no_mangle]
+extern "C" fn rust_eh_personality() {}
+
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    loop {}
+}
+
+#[no_mangle]
+pub extern "C" fn main(_argc: isize, _argv: *const *const u8) -> isize {
+    match round_trip() {
+        Some(()) => 0,
+        None => 1,
+    }
+}
+
+fn round_trip() -> Option<()> {
+    // The recipient's keypair. On a device, the IKM would come from a hardware RNG or be
+    // provisioned at the factory.
+    let (sk_recip, pk_recip) = Kem::derive_keypair(&[0x42; 32]);
+
+    // Stand in for the sender, who has an RNG. setup_sender_deterministic takes the ephemeral IKM
+    // directly. Never reuse this IKM in real code. A real sender would call setup_sender.
+    let (encapped_key, mut sender_ctx) = setup_sender_deterministic::<Aead, Kdf, Kem>(
+        &OpModeS::Base,
+        &pk_recip,
+        INFO_STR,
+        &[0x17; 32],
+    )
+    .ok()?;
+
+    // Encrypt into a stack buffer
+    let mut buf = [0u8; MSG.len()];
+    buf.copy_from_slice(MSG);
+    let tag = sender_ctx.seal_in_place_detached(&mut buf, AAD).ok()?;
+
+    // What goes over the wire: the encapped key, the ciphertext, and the tag, all fixed-size
+    let encapped_key_bytes = encapped_key.to_bytes();
+    let tag_bytes = tag.to_bytes();
+
+    // Now the recipient's side. Deserialize and decrypt in place.
+    let encapped_key = <Kem as KemTrait>::EncappedKey::from_bytes(&encapped_key_bytes).ok()?;
+    let tag = AeadTag::<Aead>::from_bytes(&tag_bytes).ok()?;
+    let mut receiver_ctx =
+        setup_receiver::<Aead, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, INFO_STR)
+            .ok()?;
+    receiver_ctx
+        .open_in_place_detached(&mut buf, AAD, &tag)
+        .ok()?;
+
+    if buf == MSG {
+        Some(())
+    } else {
+        None
+    }
+}
@@ -4,16 +4,27 @@ use crate::{
     kdf::{Kdf as KdfTrait, LabeledExpand, SimpleHkdf},
     kem::Kem as KemTrait,
     setup::ExporterSecret,
+    trace,
     util::{enforce_equal_len, full_suite_id, FullSuiteId},
-    Deserializable, HpkeError, Serializable, Vec,
+    Deserializable, HpkeError, Serializable,
 };
 
-use core::{default::Default, marker::PhantomData, u8};
+#[cfg(feature = "alloc")]
+use crate::Vec;
+#[cfg(feature = "alloc")]
+use zeroize::Zeroizing;
+
+use core::{default::Default, marker::PhantomData};
 
 use aead::{AeadCore as BaseAeadCore, AeadInPlace as BaseAeadInPlace, NewAead as BaseNewAead};
-use byteorder::{BigEndian, ByteOrder};
 use generic_array::GenericArray;
-use zeroize::Zeroize;
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+#[cfg(feature = "bytes")]
+use bytes::BytesMut;
+#[cfg(feature = "secrecy")]
+use secrecy::{ExposeSecretMut, SecretBox};
 
 /// Represents authenticated encryption functionality
 pub trait Aead {
@@ -25,6 +36,28 @@ pub trait Aead {
     const AEAD_ID: u16;
 }
 
+/// The AEAD `A` under the algorithm identifier `AEAD_ID` instead of its own. This is for private
+/// deployments that assign their own code points. The ID goes into the suite ID of every key
+/// schedule, so contexts made under an overridden ID can't talk to ones made under `A`'s ID.
+///
+/// ```
+/// use hpke::aead::{Aead, ChaCha20Poly1305, CustomAeadId};
+///
+/// type PrivateChaCha = CustomAeadId<ChaCha20Poly1305, 0xFF10>;
+/// assert_eq!(PrivateChaCha::AEAD_ID, 0xFF10);
+/// ```
+///
+/// The KDF counterpart is [`crate::kdf::CustomKdfId`]. For DHKEMs, pick the KEM ID with
+/// [`crate::kem::DhKem`].
+pub struct CustomAeadId<A, const AEAD_ID: u16>(PhantomData<fn() -> A>);
+
+impl<A: Aead, const AEAD_ID: u16> Aead for CustomAeadId<A, AEAD_ID> {
+    #[doc(hidden)]
+    type AeadImpl = A::AeadImpl;
+
+    const AEAD_ID: u16 = AEAD_ID;
+}
+
 // A nonce is a bytestring you only use for encryption once
 pub(crate) struct AeadNonce<A: Aead>(
     pub(crate) GenericArray<u8, <A::AeadImpl as BaseAeadCore>::NonceSize>,
@@ -51,10 +84,33 @@ impl<A: Aead> Drop for AeadNonce<A> {
         self.0.zeroize();
     }
 }
+impl<A: Aead> ZeroizeOnDrop for AeadNonce<A> {}
 
-pub(crate) struct AeadKey<A: Aead>(
-    pub(crate) GenericArray<u8, <A::AeadImpl as aead::NewAead>::KeySize>,
-);
+/// A symmetric key for the AEAD `A`. This is what `export_aead_key` returns. It is zeroized on
+/// drop.
+pub struct AeadKey<A: Aead>(pub(crate) GenericArray<u8, <A::AeadImpl as aead::NewAead>::KeySize>);
+
+impl<A: Aead> AeadKey<A> {
+    /// Returns the bytes of this key
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the RustCrypto cipher for this key, e.g., an `aes_gcm::Aes128Gcm` for `AesGcm128`,
+    /// for use with the `aead::Aead` and `aead::AeadInPlace` traits. The `aead` crate is
+    /// re-exported as `hpke::rustcrypto_aead`.
+    pub fn to_cipher(&self) -> A::AeadImpl {
+        <A::AeadImpl as BaseNewAead>::new(&self.0)
+    }
+}
+
+// Lets the key be passed wherever RustCrypto takes an `aead::Key`. The copy isn't zeroized on
+// drop, so prefer to_cipher where that's enough.
+impl<A: Aead> From<&AeadKey<A>> for aead::Key<A::AeadImpl> {
+    fn from(key: &AeadKey<A>) -> aead::Key<A::AeadImpl> {
+        key.0.clone()
+    }
+}
 
 // We use this to get an empty buffer we can read key material into
 impl<A: Aead> Default for AeadKey<A> {
@@ -69,6 +125,51 @@ impl<A: Aead> Drop for AeadKey<A> {
         self.0.zeroize();
     }
 }
+impl<A: Aead> ZeroizeOnDrop for AeadKey<A> {}
+
+/// A nonce-sized secret for the AEAD `A`, e.g., a base nonce for a record layer that XORs in a
+/// sequence number the way HPKE does. This is what `export_nonce` returns. It is zeroized on
+/// drop.
+pub struct NonceSeed<A: Aead>(GenericArray<u8, <A::AeadImpl as BaseAeadCore>::NonceSize>);
+
+impl<A: Aead> NonceSeed<A> {
+    /// Returns the bytes of this nonce seed
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// Zero out nonce seeds on drop
+impl<A: Aead> Drop for NonceSeed<A> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+impl<A: Aead> ZeroizeOnDrop for NonceSeed<A> {}
+
+// The typed exports below are ordinary exports with these exporter contexts:
+//   export_aead_key::<B>(ctx) = Export(concat("aead_key", I2OSP(B::AEAD_ID, 2), ctx), Nk)
+//   export_nonce::<B>(ctx) = Export(concat("aead_nonce", I2OSP(B::AEAD_ID, 2), ctx), Nn)
+// The prefixes differ before either one ends, and the AEAD ID is fixed-length, so no two
+// (type, AEAD, ctx) triples share an exporter context. The size comes from B, so it always fits.
+const AEAD_KEY_EXPORT_LABEL: &[u8] = b"aead_key";
+const NONCE_EXPORT_LABEL: &[u8] = b"aead_nonce";
+
+// Keys for ciphers from outside this crate have no AEAD ID to bind to, so they're bound to their
+// key size instead:
+//   export_to_aead::<C>(ctx) = Export(concat("aead_ext", I2OSP(Nk, 2), ctx), Nk)
+// with Nk the key size of C. This is prefix-free with the labels above.
+const EXTERNAL_AEAD_KEY_EXPORT_LABEL: &[u8] = b"aead_ext";
+
+// Forked contexts are made from exports with these exporter contexts, with i the fork index:
+//   key = Export(concat("fork_key", I2OSP(i, 8)), Nk)
+//   base_nonce = Export(concat("fork_nonce", I2OSP(i, 8)), Nn)
+//   exporter_secret = Export(concat("fork_exp", I2OSP(i, 8)), Nh)
+// These are prefix-free with each other and with the typed export labels above. ExporterReader,
+// in exporter_reader.rs, uses one more prefix, "exp_stream".
+const FORK_KEY_LABEL: &[u8] = b"fork_key";
+const FORK_NONCE_LABEL: &[u8] = b"fork_nonce";
+const FORK_EXPORTER_LABEL: &[u8] = b"fork_exp";
 
 /// A sequence counter. This is set to `u64` instead of the true nonce size of an AEAD for two
 /// reasons:
@@ -103,24 +204,39 @@ fn increment_seq(seq: &Seq) -> Option<Seq> {
 /// Derives a nonce from the base nonce and a "sequence number". The sequence number is treated as
 /// a big-endian integer with length equal to the nonce length.
 fn mix_nonce<A: Aead>(base_nonce: &AeadNonce<A>, seq: &Seq) -> AeadNonce<A> {
-    // Write `seq` in big-endian order into a byte buffer that's the size of a nonce
-    let mut seq_buf = AeadNonce::<A>::default();
-    // We just write to the last seq_size bytes. This is necessary because our AEAD nonces (>= 96
-    // bits) are always bigger than the sequence buffer (64 bits). We write to the last 64 bits
-    // because this is a big-endian number.
+    // Start with a copy of the base nonce, and XOR `seq` in big-endian order into its last
+    // seq_size bytes. This is necessary because our AEAD nonces (>= 96 bits) are always bigger
+    // than the sequence buffer (64 bits), so the leading bytes of the big-endian seq are all zero.
+    // Doing it in place saves building a separate seq buffer and a third array for the result.
+    let mut nonce = AeadNonce::<A>(base_nonce.0.clone());
+    let seq_size = core::mem::size_of::<Seq>();
+    let nonce_size = nonce.0.len();
+    let seq_bytes = seq.0.to_be_bytes();
+    for (nonce_byte, seq_byte) in nonce.0[nonce_size - seq_size..]
+        .iter_mut()
+        .zip(seq_bytes.iter())
+    {
+        *nonce_byte ^= seq_byte;
+    }
+
+    nonce
+}
+
+/// Steps a nonce computed for sequence number `from` to the one for `to`. Since a nonce is the
+/// base nonce XORed with the sequence number, this is just XORing in `from ^ to`, which, for
+/// consecutive sequence numbers, usually touches one byte. It's the same as `mix_nonce` with `to`,
+/// without going back to the base nonce.
+#[cfg(feature = "alloc")]
+fn step_nonce<A: Aead>(nonce: &mut AeadNonce<A>, from: u64, to: u64) {
     let seq_size = core::mem::size_of::<Seq>();
-    let nonce_size = base_nonce.0.len();
-    BigEndian::write_u64(&mut seq_buf.0[nonce_size - seq_size..], seq.0);
-
-    // XOR the base nonce bytes with the sequence bytes
-    let new_nonce_iter = base_nonce
-        .0
-        .iter()
-        .zip(seq_buf.0.iter())
-        .map(|(nonce_byte, seq_byte)| nonce_byte ^ seq_byte);
-
-    // This cannot fail, as the length of AeadNonce<A> is precisely the length of Seq
-    AeadNonce(GenericArray::from_exact_iter(new_nonce_iter).unwrap())
+    let nonce_size = nonce.0.len();
+    let diff_bytes = (from ^ to).to_be_bytes();
+    for (nonce_byte, diff_byte) in nonce.0[nonce_size - seq_size..]
+        .iter_mut()
+        .zip(diff_bytes.iter())
+    {
+        *nonce_byte ^= diff_byte;
+    }
 }
 
 /// An authenticated encryption tag
@@ -132,6 +248,28 @@ impl<A: Aead> Default for AeadTag<A> {
     }
 }
 
+impl<A: Aead> AeadTag<A> {
+    /// Checks that this tag equals `other`, in constant time. Compare tags with this, not by
+    /// comparing their bytes with `==`, which can return as soon as a byte differs.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if the tags are equal, and `Err(HpkeError::OpenError)` otherwise.
+    pub fn verify(&self, other: &AeadTag<A>) -> Result<(), HpkeError> {
+        if bool::from(self.ct_eq(other)) {
+            Ok(())
+        } else {
+            Err(HpkeError::OpenError)
+        }
+    }
+}
+
+impl<A: Aead> ConstantTimeEq for AeadTag<A> {
+    fn ct_eq(&self, other: &AeadTag<A>) -> Choice {
+        self.0.as_slice().ct_eq(other.0.as_slice())
+    }
+}
+
 impl<A: Aead> Serializable for AeadTag<A> {
     type OutputSize = <A::AeadImpl as BaseAeadCore>::TagSize;
 
@@ -167,6 +305,13 @@ pub(crate) struct AeadCtx<A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
     src_kem: PhantomData<Kem>,
     /// The full ID of the ciphersuite that created this `AeadCtx`. Used for context binding.
     suite_id: FullSuiteId,
+    /// The longest plaintext this context will seal or open, if there's a limit
+    max_msg_len: Option<usize>,
+    /// How `seal_padded` pads plaintexts
+    padding: PaddingPolicy,
+    /// Identifies this context's key and base nonce in the nonce reuse registry
+    #[cfg(feature = "nonce-reuse-check")]
+    nonce_check_id: nonce_check::NonceCheckId,
 }
 
 // Necessary for test_setup_soundness
@@ -181,6 +326,10 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> Clone for AeadCtx<A, Kdf, Kem> {
             seq: self.seq.clone(),
             src_kem: PhantomData,
             suite_id: self.suite_id,
+            max_msg_len: self.max_msg_len,
+            padding: self.padding,
+            #[cfg(feature = "nonce-reuse-check")]
+            nonce_check_id: self.nonce_check_id,
         }
     }
 }
@@ -196,11 +345,15 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtx<A, Kdf, Kem> {
         AeadCtx {
             overflowed: false,
             encryptor: <A::AeadImpl as aead::NewAead>::new(&key.0),
+            #[cfg(feature = "nonce-reuse-check")]
+            nonce_check_id: nonce_check::nonce_check_id(&suite_id, key, &base_nonce),
             base_nonce,
             exporter_secret,
             seq: <Seq as Default>::default(),
             src_kem: PhantomData,
             suite_id,
+            max_msg_len: None,
+            padding: PaddingPolicy::default(),
         }
     }
 
@@ -229,15 +382,160 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtx<A, Kdf, Kem> {
             .labeled_expand(&self.suite_id, b"sec", exporter_ctx, out_buf)
             .map_err(|_| HpkeError::KdfOutputTooLong)
     }
+
+    /// Returns the sequence number of the next message. This doesn't change once the last
+    /// possible message is done, at `u64::MAX`.
+    pub(crate) fn seq(&self) -> u64 {
+        self.seq.0
+    }
+
+    /// Returns how many more messages this context can handle, saturating at `u64::MAX`
+    pub(crate) fn messages_remaining(&self) -> u64 {
+        if self.overflowed {
+            0
+        } else {
+            // Every sequence number up to and including u64::MAX can be used once
+            (u64::MAX - self.seq.0).saturating_add(1)
+        }
+    }
+
+    /// Returns `(kem_id, kdf_id, aead_id)` of the ciphersuite that made this context
+    pub(crate) fn suite_ids(&self) -> (u16, u16, u16) {
+        (Kem::KEM_ID, Kdf::KDF_ID, A::AEAD_ID)
+    }
+
+    /// Checks a plaintext length, or a ciphertext length without the tag, against the limit set
+    /// with `set_max_message_len`
+    pub(crate) fn check_msg_len(&self, msg_len: usize) -> Result<(), HpkeError> {
+        match self.max_msg_len {
+            Some(limit) if msg_len > limit => Err(HpkeError::MessageTooLarge(limit, msg_len)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Opens `ciphertext` in place, as the ciphertext sealed with sequence number `seq`. This
+    /// doesn't look at or change this context's own sequence number.
+    pub(crate) fn open_in_place_detached_at_seq(
+        &self,
+        seq: u64,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+    ) -> Result<(), HpkeError> {
+        self.check_msg_len(ciphertext.len())?;
+        let nonce = mix_nonce::<A>(&self.base_nonce, &Seq(seq));
+        let decrypt_res = self
+            .encryptor
+            .decrypt_in_place_detached(&nonce.0, aad, ciphertext, &tag.0);
+
+        if decrypt_res.is_err() {
+            // Whatever the AEAD left in the buffer is discarded the same way, whether it bailed
+            // out before decrypting or after, so every failure looks alike to the caller
+            ciphertext.zeroize();
+            return Err(trace::open_error::<A, Kdf, Kem>(seq, HpkeError::OpenError));
+        }
+        Ok(())
+    }
+
+    /// Like `export`, where the exporter context is the concatenation of `exporter_ctx_parts`
+    fn export_multi(&self, exporter_ctx_parts: &[&[u8]], out_buf: &mut [u8]) {
+        // Same as in export()
+        let hkdf_ctx = SimpleHkdf::<Kdf>::from_prk(self.exporter_secret.0.as_slice()).unwrap();
+        // Callers only ask for AEAD key and nonce sizes, which are way under the limit
+        hkdf_ctx
+            .labeled_expand_multi(&self.suite_id, b"sec", exporter_ctx_parts, out_buf)
+            .expect("exported value len is way too big")
+    }
+
+    /// Exports a key for the AEAD `B`. See `AeadCtxS::export_aead_key`.
+    pub(crate) fn export_aead_key<B: Aead>(&self, exporter_ctx: &[u8]) -> AeadKey<B> {
+        let mut key = AeadKey::<B>::default();
+        self.export_multi(
+            &[
+                AEAD_KEY_EXPORT_LABEL,
+                &B::AEAD_ID.to_be_bytes(),
+                exporter_ctx,
+            ],
+            key.0.as_mut_slice(),
+        );
+        key
+    }
+
+    /// Derives the `index`-th forked context. See `AeadCtxS::fork_at`.
+    pub(crate) fn fork_at(&self, index: u64) -> AeadCtx<A, Kdf, Kem> {
+        let index_bytes = index.to_be_bytes();
+
+        let mut key = AeadKey::<A>::default();
+        let mut base_nonce = AeadNonce::<A>::default();
+        let mut exporter_secret = <ExporterSecret<Kdf> as Default>::default();
+        self.export_multi(&[FORK_KEY_LABEL, &index_bytes], key.0.as_mut_slice());
+        self.export_multi(
+            &[FORK_NONCE_LABEL, &index_bytes],
+            base_nonce.0.as_mut_slice(),
+        );
+        self.export_multi(
+            &[FORK_EXPORTER_LABEL, &index_bytes],
+            exporter_secret.0.as_mut_slice(),
+        );
+
+        // Sub-contexts keep the parent's size limit and padding policy
+        let mut ctx = AeadCtx::new(&key, base_nonce, exporter_secret);
+        ctx.max_msg_len = self.max_msg_len;
+        ctx.padding = self.padding;
+        ctx
+    }
+
+    /// Exports a nonce seed for the AEAD `B`. See `AeadCtxS::export_nonce`.
+    pub(crate) fn export_nonce<B: Aead>(&self, exporter_ctx: &[u8]) -> NonceSeed<B> {
+        let mut nonce = NonceSeed::<B>(GenericArray::default());
+        self.export_multi(
+            &[NONCE_EXPORT_LABEL, &B::AEAD_ID.to_be_bytes(), exporter_ctx],
+            nonce.0.as_mut_slice(),
+        );
+        nonce
+    }
+
+    /// Exports a key for the RustCrypto cipher `C`, and makes the cipher. See
+    /// `AeadCtxS::export_to_aead`.
+    pub(crate) fn export_to_aead<C: BaseNewAead>(&self, exporter_ctx: &[u8]) -> C {
+        let mut key = aead::Key::<C>::default();
+        // Key sizes are two bytes at most, and nowhere near the export limit
+        let key_len = key.len() as u16;
+        self.export_multi(
+            &[
+                EXTERNAL_AEAD_KEY_EXPORT_LABEL,
+                &key_len.to_be_bytes(),
+                exporter_ctx,
+            ],
+            key.as_mut_slice(),
+        );
+        let cipher = C::new(&key);
+        key.zeroize();
+        cipher
+    }
+
+    /// Like `export`, but allocates a `len`-byte buffer for the output and wraps it in a
+    /// `SecretBox`
+    #[cfg(feature = "secrecy")]
+    pub(crate) fn export_secret(
+        &self,
+        exporter_ctx: &[u8],
+        len: usize,
+    ) -> Result<SecretBox<[u8]>, HpkeError> {
+        // Wrap the buffer before filling it, so that it's zeroized even if export fails
+        let mut secret = SecretBox::new(vec![0u8; len].into_boxed_slice());
+        self.export(exporter_ctx, secret.expose_secret_mut())?;
+        Ok(secret)
+    }
 }
 
 /// The HPKE receiver's context. This is what you use to `open` ciphertexts and `export` secrets.
-pub struct AeadCtxR<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(AeadCtx<A, Kdf, Kem>);
+pub struct AeadCtxR<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(AeadCtx<A, Kdf, Kem>, ReplayWindow);
 
 // AeadCtx -> AeadCtxR via wrapping
 impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> From<AeadCtx<A, Kdf, Kem>> for AeadCtxR<A, Kdf, Kem> {
     fn from(ctx: AeadCtx<A, Kdf, Kem>) -> AeadCtxR<A, Kdf, Kem> {
-        AeadCtxR(ctx)
+        AeadCtxR(ctx, ReplayWindow::default())
     }
 }
 
@@ -264,30 +562,28 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
     /// Return Value
     /// ============
     /// Returns `Ok(())` on success. If this context has been used for so many encryptions that the
-    /// sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If this happens,
-    /// `ciphertext` will be unmodified. If the tag fails to validate, returns
-    /// `Err(HpkeError::OpenError)`. If this happens, `ciphertext` is in an undefined state.
+    /// sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If `ciphertext`
+    /// is longer than the limit set with `set_max_message_len`, returns
+    /// `Err(HpkeError::MessageTooLarge)`. In both cases, `ciphertext` will be unmodified. If the
+    /// ciphertext doesn't authenticate, for any reason, returns `Err(HpkeError::OpenError)`, and
+    /// `ciphertext` is zeroed. The sequence number doesn't advance, so the next ciphertext can
+    /// still be opened.
     pub fn open_in_place_detached(
         &mut self,
         ciphertext: &mut [u8],
         aad: &[u8],
         tag: &AeadTag<A>,
     ) -> Result<(), HpkeError> {
+        self.0.check_msg_len(ciphertext.len())?;
+
         if self.0.overflowed {
             // If the sequence counter overflowed, we've been used for too long. Shut down.
-            Err(HpkeError::MessageLimitReached)
+            Err(trace::limit_reached::<A, Kdf, Kem>("receiver"))
         } else {
-            // Compute the nonce and do the encryption in place
-            let nonce = mix_nonce::<A>(&self.0.base_nonce, &self.0.seq);
-            let decrypt_res = self
-                .0
-                .encryptor
-                .decrypt_in_place_detached(&nonce.0, aad, ciphertext, &tag.0);
-
-            if decrypt_res.is_err() {
-                // Opening failed due to a bad tag
-                return Err(HpkeError::OpenError);
-            }
+            // Decrypt in place with the nonce for the current sequence number. This zeroes
+            // ciphertext on failure.
+            self.0
+                .open_in_place_detached_at_seq(self.0.seq.0, ciphertext, aad, tag)?;
 
             // Opening was a success. Try to increment the sequence counter. If it fails, this was
             // our last decryption.
@@ -300,36 +596,218 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
         }
     }
 
-    /// Opens the given ciphertext and returns a plaintext
+    /// Opens a ciphertext that's in a caller-owned buffer, as `seal` outputs it, i.e., with the tag
+    /// appended. The plaintext overwrites the front of `buf`, and the returned slice points at it.
+    /// This doesn't allocate, so it suits packet paths that open into a reused receive buffer.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the plaintext, which is `buf` without its last `AeadTag::size()` bytes, on success.
+    /// Returns `Err(HpkeError::OpenError)` if `buf` is shorter than a tag. Otherwise, returns the
+    /// errors `open_in_place_detached` does. If the tag fails to validate, the ciphertext part of
+    /// `buf` is zeroed.
+    pub fn open_in_place<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        aad: &[u8],
+    ) -> Result<&'a mut [u8], HpkeError> {
+        let (ciphertext_len, tag) = {
+            let (ciphertext, tag) = split_tag::<A>(buf)?;
+            (ciphertext.len(), tag)
+        };
+        let plaintext = &mut buf[..ciphertext_len];
+        self.open_in_place_detached(plaintext, aad, &tag)?;
+        Ok(plaintext)
+    }
+
+    /// Opens the given ciphertext and returns a plaintext. The plaintext is an ordinary `Vec`,
+    /// which implements `zeroize::Zeroize`. If it's sensitive, use `open_zeroizing` instead, or
+    /// zeroize it when you're done with it.
     ///
     /// Return Value
     /// ============
     /// Returns `Ok(())` on success. If this context has been used for so many encryptions that the
-    /// sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If the tag fails
-    /// to validate, returns `Err(HpkeError::OpenError)`.
+    /// sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If the
+    /// ciphertext, minus the tag, is longer than the limit set with `set_max_message_len`,
+    /// returns `Err(HpkeError::MessageTooLarge)` without allocating. If the tag fails to validate,
+    /// returns `Err(HpkeError::OpenError)`.
+    #[cfg(feature = "alloc")]
     pub fn open(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
-        // Make sure the auth'd ciphertext is long enough to contain a tag. If it isn't, it's
-        // certainly not valid.
-        let tag_len = AeadTag::<A>::size();
-        let msg_len = ciphertext
-            .len()
-            .checked_sub(tag_len)
-            .ok_or(HpkeError::OpenError)?;
-
-        // Now deconstruct the auth'd ciphertext
-        let (ciphertext, tag_slice) = ciphertext.split_at(msg_len);
+        // Deconstruct the auth'd ciphertext, and check its size before copying it
+        let (ciphertext, tag) = split_tag::<A>(ciphertext)?;
+        self.0.check_msg_len(ciphertext.len())?;
         let mut buf = ciphertext.to_vec();
-        let tag = {
-            let mut t = <AeadTag<A> as Default>::default();
-            t.0.copy_from_slice(tag_slice);
-            t
-        };
 
         // Decrypt and return the decrypted buffer
         self.open_in_place_detached(&mut buf, aad, &tag)?;
         Ok(buf)
     }
 
+    /// Same as `open`, but returns the plaintext in a `zeroize::Zeroizing`, so it's zeroized when
+    /// it's dropped. The buffer is zeroized on failure too. Note that cloning the plaintext out of
+    /// the wrapper, or growing it, makes copies that aren't zeroized.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(plaintext)` on success. Returns the errors `open` does.
+    #[cfg(feature = "alloc")]
+    pub fn open_zeroizing(
+        &mut self,
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, HpkeError> {
+        let (ciphertext, tag) = split_tag::<A>(ciphertext)?;
+        self.0.check_msg_len(ciphertext.len())?;
+        // Wrap the buffer before decrypting into it, so that it's zeroized on every path
+        let mut buf = Zeroizing::new(ciphertext.to_vec());
+
+        self.open_in_place_detached(&mut buf, aad, &tag)?;
+        Ok(buf)
+    }
+
+    /// Opens a ciphertext made by `AeadCtxS::seal_padded`, and returns the plaintext with the
+    /// padding removed. This works whatever padding policy the sender used.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(plaintext)` on success. Returns the errors `open` does, and
+    /// `Err(HpkeError::OpenError)` if the ciphertext opens but isn't padded. In that case, the
+    /// sequence number has still advanced.
+    #[cfg(feature = "alloc")]
+    pub fn open_padded(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        let mut plaintext = self.open(ciphertext, aad)?;
+        let len = padding::unpadded_len(&plaintext)?;
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+
+    /// Opens the ciphertext in `buf` in place, as `seal` or `AeadCtxS::seal_bytes` outputs it,
+    /// and then strips the tag, so `buf` ends up holding the plaintext. The buffer keeps its
+    /// capacity, so it can be reused for the next message.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. Otherwise, returns the errors `open_in_place` does. If the tag
+    /// fails to validate, the ciphertext part of `buf` is zeroed.
+    #[cfg(feature = "bytes")]
+    pub fn open_bytes(&mut self, buf: &mut BytesMut, aad: &[u8]) -> Result<(), HpkeError> {
+        let plaintext_len = self.open_in_place(buf, aad)?.len();
+        buf.truncate(plaintext_len);
+        Ok(())
+    }
+
+    /// Sets the longest message this context will open, or removes the limit if `max_len` is
+    /// `None`. The limit is on the plaintext length, i.e., the ciphertext length minus the tag.
+    /// Longer ciphertexts are rejected with `Err(HpkeError::MessageTooLarge)` before anything is
+    /// allocated or decrypted, so use this when ciphertexts come from an untrusted source. There
+    /// is no limit by default.
+    pub fn set_max_message_len(&mut self, max_len: Option<usize>) {
+        self.0.max_msg_len = max_len;
+    }
+
+    /// Returns the limit set with `set_max_message_len`, if any
+    pub fn max_message_len(&self) -> Option<usize> {
+        self.0.max_msg_len
+    }
+
+    /// Returns the number of messages this context has opened with `open` and
+    /// `open_in_place_detached`, which is also the sequence number the next one must have been
+    /// sealed with. Opening with `open_at` doesn't change this. None of these getters reveal
+    /// anything secret, so they're safe to log.
+    pub fn seq(&self) -> u64 {
+        self.0.seq()
+    }
+
+    /// Returns how many more messages `open` will take before returning
+    /// `Err(HpkeError::MessageLimitReached)`. This saturates at `u64::MAX`, which is one short of
+    /// the true count for a fresh context.
+    pub fn messages_remaining(&self) -> u64 {
+        self.0.messages_remaining()
+    }
+
+    /// Returns `(kem_id, kdf_id, aead_id)` of the ciphersuite that made this context
+    pub fn suite_ids(&self) -> (u16, u16, u16) {
+        self.0.suite_ids()
+    }
+
+    /// Sets how far behind the highest sequence number opened with `open_at` a ciphertext can be
+    /// and still be opened with `open_at`. This resets the record of which sequence numbers were
+    /// opened. The default is 64.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If `depth` is 0 or more than `MAX_REPLAY_WINDOW`, returns
+    /// `Err(HpkeError::ValidationError)`, and the window is unchanged.
+    pub fn set_replay_window(&mut self, depth: usize) -> Result<(), HpkeError> {
+        if depth == 0 || depth > MAX_REPLAY_WINDOW {
+            return Err(HpkeError::ValidationError);
+        }
+        self.1 = ReplayWindow::new(depth);
+        Ok(())
+    }
+
+    /// Does a "detached open in place" of the ciphertext that was sealed with sequence number
+    /// `seq`, i.e., the `seq`-th ciphertext the sender sealed, counting from 0. This is for
+    /// transports that reorder or drop messages, like datagrams. Replays are caught with a
+    /// sliding window, as in IPsec: each sequence number can be opened at most once, and one
+    /// that's too far behind the highest opened so far is rejected. See `set_replay_window`.
+    ///
+    /// This is separate from the sequence counter that `open_in_place_detached` and `open` use,
+    /// so use either those or the `_at` methods on a given context, not both.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If `seq` was already opened or is outside the window, returns
+    /// `Err(HpkeError::ReplayedMessage)`. If `seq` is `u64::MAX`, returns
+    /// `Err(HpkeError::MessageLimitReached)`. In both cases, `ciphertext` is unmodified. If the
+    /// tag fails to validate, returns `Err(HpkeError::OpenError)`. If this happens, `ciphertext`
+    /// is zeroed, and `seq` can still be opened later.
+    pub fn open_in_place_detached_at(
+        &mut self,
+        seq: u64,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+    ) -> Result<(), HpkeError> {
+        // The window stores seq + 1, and AeadCtxR can't get past u64::MAX either
+        if seq == u64::MAX {
+            return Err(trace::limit_reached::<A, Kdf, Kem>("receiver"));
+        }
+        self.1.check(seq)?;
+
+        self.0
+            .open_in_place_detached_at_seq(seq, ciphertext, aad, tag)?;
+
+        // Only record authentic ciphertexts, so that forgeries can't use up sequence numbers
+        self.1.record(seq);
+        Ok(())
+    }
+
+    /// Opens the ciphertext that was sealed with sequence number `seq`, and returns a plaintext.
+    /// See `open_in_place_detached_at`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the plaintext on success. If `seq` was already opened or is outside the window,
+    /// returns `Err(HpkeError::ReplayedMessage)`. If `seq` is `u64::MAX`, returns
+    /// `Err(HpkeError::MessageLimitReached)`. If the tag fails to validate, returns
+    /// `Err(HpkeError::OpenError)`.
+    #[cfg(feature = "alloc")]
+    pub fn open_at(
+        &mut self,
+        seq: u64,
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, HpkeError> {
+        let (ciphertext, tag) = split_tag::<A>(ciphertext)?;
+        self.0.check_msg_len(ciphertext.len())?;
+        let mut buf = ciphertext.to_vec();
+
+        // Decrypt and return the decrypted buffer
+        self.open_in_place_detached_at(seq, &mut buf, aad, &tag)?;
+        Ok(buf)
+    }
+
     /// Fills a given buffer with secret bytes derived from this encryption context. This value
     /// does not depend on sequence number, so it is constant for the lifetime of this context.
     ///
@@ -343,6 +821,58 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
         // Pass to AeadCtx
         self.0.export(info, out_buf)
     }
+
+    /// Returns `len` secret bytes derived from this encryption context, wrapped in a `SecretBox`.
+    /// The bytes are the same ones `export` would write. The `SecretBox` zeroizes them on drop
+    /// and keeps them out of `Debug` output. Use `ExposeSecret` to read them.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the secret on success. If `len` is more than 255x the digest size of the underlying
+    /// hash function, returns an `Err(HpkeError::KdfOutputTooLong)`.
+    #[cfg(feature = "secrecy")]
+    pub fn export_secret(&self, info: &[u8], len: usize) -> Result<SecretBox<[u8]>, HpkeError> {
+        // Pass to AeadCtx
+        self.0.export_secret(info, len)
+    }
+
+    /// Derives a key for the AEAD `B` from this encryption context. `B` need not be the AEAD this
+    /// context uses. The key is the right size for `B`, and it's bound to `B` and
+    /// `exporter_ctx`, so it never equals a key exported for another AEAD or context, or a nonce
+    /// from `export_nonce`. Like `export`, this does not depend on the sequence number.
+    ///
+    /// This is the same as `export` with the exporter context `"aead_key" || I2OSP(B::AEAD_ID, 2)
+    /// || exporter_ctx`, for interop with other implementations.
+    pub fn export_aead_key<B: Aead>(&self, exporter_ctx: &[u8]) -> AeadKey<B> {
+        self.0.export_aead_key(exporter_ctx)
+    }
+
+    /// Derives a nonce-sized secret for the AEAD `B` from this encryption context, e.g., a base
+    /// nonce for a record layer. See `export_aead_key`. This is the same as `export` with the
+    /// exporter context `"aead_nonce" || I2OSP(B::AEAD_ID, 2) || exporter_ctx`.
+    pub fn export_nonce<B: Aead>(&self, exporter_ctx: &[u8]) -> NonceSeed<B> {
+        self.0.export_nonce(exporter_ctx)
+    }
+
+    /// Derives a key for the RustCrypto cipher `C` from this encryption context, and returns the
+    /// cipher. This matches the sender's `AeadCtxS::export_to_aead`. See there, including its
+    /// DANGER section.
+    pub fn export_to_aead<C: BaseNewAead>(&self, exporter_ctx: &[u8]) -> C {
+        self.0.export_to_aead(exporter_ctx)
+    }
+
+    /// Derives the `index`-th sub-context of this context. This matches the sender's
+    /// `AeadCtxS::fork_at` with the same `index`. Use it to open what was sealed with that
+    /// sub-context.
+    pub fn fork_at(&self, index: u64) -> AeadCtxR<A, Kdf, Kem> {
+        self.0.fork_at(index).into()
+    }
+
+    /// Derives `n` sub-contexts, for indices `0..n`. This matches the sender's `AeadCtxS::fork`.
+    #[cfg(feature = "alloc")]
+    pub fn fork(&self, n: usize) -> Vec<AeadCtxR<A, Kdf, Kem>> {
+        (0..n as u64).map(|i| self.fork_at(i)).collect()
+    }
 }
 
 /// The HPKE senders's context. This is what you use to `seal` plaintexts and `export` secrets.
@@ -363,6 +893,15 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> Clone for AeadCtxS<A, Kdf, Kem> {
     }
 }
 
+#[cfg(all(any(test, feature = "hazmat"), feature = "nonce-reuse-check"))]
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
+    /// Clears the nonce reuse registry's record of this context's key. Besides tests, convergent
+    /// sealing uses this, since it repeats a (key, nonce) pair only for the same plaintext.
+    pub(crate) fn forget_sealed_nonces(&self) {
+        nonce_check::forget_seals(&self.0.nonce_check_id);
+    }
+}
+
 impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
     // RFC 9180 §5.2
     // def ContextS.Seal(aad, pt):
@@ -376,18 +915,30 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
     /// Return Value
     /// ============
     /// Returns `Ok(tag)` on success.  If this context has been used for so many encryptions that
-    /// the sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If this
-    /// happens, `plaintext` will be unmodified. If an error happened during encryption, returns
-    /// `Err(HpkeError::SealError)`. If this happens, the contents of `plaintext` is undefined.
+    /// the sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If
+    /// `plaintext` is longer than the limit set with `set_max_message_len`, returns
+    /// `Err(HpkeError::MessageTooLarge)`. In both cases, `plaintext` will be unmodified. If an
+    /// error happened during encryption, returns `Err(HpkeError::SealError)`. If this happens, the
+    /// contents of `plaintext` is undefined.
+    ///
+    /// Panics
+    /// ======
+    /// With the `nonce-reuse-check` feature, panics if any sender context in this process has
+    /// already sealed with this context's key and the current sequence number
     pub fn seal_in_place_detached(
         &mut self,
         plaintext: &mut [u8],
         aad: &[u8],
     ) -> Result<AeadTag<A>, HpkeError> {
+        self.0.check_msg_len(plaintext.len())?;
+
         if self.0.overflowed {
             // If the sequence counter overflowed, we've been used for far too long. Shut down.
-            Err(HpkeError::MessageLimitReached)
+            Err(trace::limit_reached::<A, Kdf, Kem>("sender"))
         } else {
+            #[cfg(feature = "nonce-reuse-check")]
+            nonce_check::record_seal(&self.0.nonce_check_id, self.0.seq.0);
+
             // Compute the nonce and do the encryption in place
             let nonce = mix_nonce::<A>(&self.0.base_nonce, &self.0.seq);
             let tag = self
@@ -412,24 +963,179 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
     /// Return Value
     /// ============
     /// Returns `Ok(ciphertext)` on success.  If this context has been used for so many encryptions
-    /// that the sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If an
-    /// error happened during encryption, returns `Err(HpkeError::SealError)`.
+    /// that the sequence number overflowed, returns `Err(HpkeError::MessageLimitReached)`. If
+    /// `plaintext` is longer than the limit set with `set_max_message_len`, returns
+    /// `Err(HpkeError::MessageTooLarge)`. If an error happened during encryption, returns
+    /// `Err(HpkeError::SealError)`.
+    #[cfg(feature = "alloc")]
     pub fn seal(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
         let msg_len = plaintext.len();
         let tag_len = AeadTag::<A>::size();
+        // Check the size before allocating
+        self.0.check_msg_len(msg_len)?;
 
-        // Make a buffer that can hold a ciphertext + tag. Copy in the plaintext
-        let mut buf = vec![0u8; msg_len + tag_len];
-        buf[..msg_len].copy_from_slice(plaintext);
+        // Make a buffer that can hold a ciphertext + tag, so that appending the tag doesn't
+        // reallocate. Copy in the plaintext. There's no need to zero-fill it first.
+        let mut buf = Vec::with_capacity(msg_len + tag_len);
+        buf.extend_from_slice(plaintext);
 
         // Seal with a detached tag
-        let tag = self.seal_in_place_detached(&mut buf[..plaintext.len()], aad)?;
+        let tag = self.seal_in_place_detached(&mut buf, aad)?;
         // Then append the tag to the end of the buffer. The buffer is now the auth'd ciphertext
-        buf[msg_len..msg_len + tag_len].copy_from_slice(&tag.0);
+        buf.extend_from_slice(&tag.0);
 
         Ok(buf)
     }
 
+    /// Pads the plaintext per this context's padding policy, then seals it. Open the result with
+    /// `AeadCtxR::open_padded`. The limit set with `set_max_message_len` applies to the padded
+    /// length.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(ciphertext)` on success. If the policy is `PaddingPolicy::Fixed` and the
+    /// plaintext doesn't fit, returns `Err(HpkeError::MessageTooLarge)`. Otherwise, returns the
+    /// errors `seal` does.
+    #[cfg(feature = "alloc")]
+    pub fn seal_padded(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        // Pad into a buffer with room for the tag, so appending it doesn't reallocate
+        let mut buf = padding::pad(&self.0.padding, plaintext, AeadTag::<A>::size())?;
+        let tag = self.seal_in_place_detached(&mut buf, aad)?;
+        buf.extend_from_slice(&tag.0);
+        Ok(buf)
+    }
+
+    /// Seals the plaintext in `buf` in place, then appends the tag to it, so `buf` ends up holding
+    /// the ciphertext as `seal` outputs it. Reserve `AeadTag::size()` bytes of spare capacity
+    /// beforehand, e.g., with `BytesMut::with_capacity`, and appending the tag won't reallocate.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. Otherwise, returns the errors `seal_in_place_detached` does,
+    /// and leaves `buf` unmodified.
+    #[cfg(feature = "bytes")]
+    pub fn seal_bytes(&mut self, buf: &mut BytesMut, aad: &[u8]) -> Result<(), HpkeError> {
+        let tag = self.seal_in_place_detached(buf, aad)?;
+        buf.extend_from_slice(&tag.0);
+        Ok(())
+    }
+
+    /// Seals every `(plaintext, aad)` pair in `msgs`, in order, as if by calling `seal` on each,
+    /// and returns the ciphertexts concatenated into one buffer. Ciphertext `i` is
+    /// `msgs[i].0.len() + AeadTag::size()` bytes long, so split the buffer with those lengths.
+    /// Each piece opens with `AeadCtxR::open`, in order.
+    ///
+    /// This is for batches of small messages, where the per-call overhead of `seal` is a large
+    /// fraction of the cost. All the checks happen once, up front, the output is allocated once,
+    /// and each nonce is stepped from the last instead of being recomputed from the base nonce.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the concatenated ciphertexts on success. If any plaintext is longer than the limit
+    /// set with `set_max_message_len`, returns `Err(HpkeError::MessageTooLarge)`. If this context
+    /// has fewer than `msgs.len()` messages left, returns `Err(HpkeError::MessageLimitReached)`. In
+    /// both cases, nothing is sealed and the sequence number is unchanged. If an error happened
+    /// during encryption, returns `Err(HpkeError::SealError)`. The messages before the failing one
+    /// were sealed, and their sequence numbers are used up.
+    ///
+    /// Panics
+    /// ======
+    /// With the `nonce-reuse-check` feature, panics if any sender context in this process has
+    /// already sealed with this context's key and any of the sequence numbers this uses
+    #[cfg(feature = "alloc")]
+    pub fn seal_many(&mut self, msgs: &[(&[u8], &[u8])]) -> Result<Vec<u8>, HpkeError> {
+        let tag_len = AeadTag::<A>::size();
+        for (plaintext, _) in msgs {
+            self.0.check_msg_len(plaintext.len())?;
+        }
+        // The true count of messages left is one more than messages_remaining() for a fresh
+        // context, so count in u128
+        let remaining = if self.0.overflowed {
+            0
+        } else {
+            u64::MAX as u128 - self.0.seq.0 as u128 + 1
+        };
+        if msgs.len() as u128 > remaining {
+            return Err(trace::limit_reached::<A, Kdf, Kem>("sender"));
+        }
+        if msgs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let total_len = msgs.iter().fold(0usize, |acc, (pt, _)| {
+            acc.saturating_add(pt.len() + tag_len)
+        });
+        let mut buf = Vec::with_capacity(total_len);
+
+        let mut nonce = mix_nonce::<A>(&self.0.base_nonce, &self.0.seq);
+        for (i, (plaintext, aad)) in msgs.iter().enumerate() {
+            if i > 0 {
+                step_nonce::<A>(&mut nonce, self.0.seq.0 - 1, self.0.seq.0);
+            }
+
+            #[cfg(feature = "nonce-reuse-check")]
+            nonce_check::record_seal(&self.0.nonce_check_id, self.0.seq.0);
+
+            let start = buf.len();
+            buf.extend_from_slice(plaintext);
+            let tag = self
+                .0
+                .encryptor
+                .encrypt_in_place_detached(&nonce.0, aad, &mut buf[start..])
+                .map_err(|_| HpkeError::SealError)?;
+            buf.extend_from_slice(&tag);
+
+            // The count was checked above, so this only overflows after the very last message
+            match increment_seq(&self.0.seq) {
+                Some(new_seq) => self.0.seq = new_seq,
+                None => self.0.overflowed = true,
+            }
+        }
+
+        Ok(buf)
+    }
+
+    /// Sets how `seal_padded` pads plaintexts. The default is `PaddingPolicy::Padme`. Forks of
+    /// this context inherit it.
+    pub fn set_padding_policy(&mut self, policy: PaddingPolicy) {
+        self.0.padding = policy;
+    }
+
+    /// Returns the policy set with `set_padding_policy`
+    pub fn padding_policy(&self) -> PaddingPolicy {
+        self.0.padding
+    }
+
+    /// Sets the longest plaintext this context will seal, or removes the limit if `max_len` is
+    /// `None`. Longer plaintexts are rejected with `Err(HpkeError::MessageTooLarge)`. Set the same
+    /// limit on the receiver with `AeadCtxR::set_max_message_len`. There is no limit by default.
+    pub fn set_max_message_len(&mut self, max_len: Option<usize>) {
+        self.0.max_msg_len = max_len;
+    }
+
+    /// Returns the limit set with `set_max_message_len`, if any
+    pub fn max_message_len(&self) -> Option<usize> {
+        self.0.max_msg_len
+    }
+
+    /// Returns the number of messages this context has sealed, which is also the sequence number
+    /// the next one gets. None of these getters reveal anything secret, so they're safe to log.
+    pub fn seq(&self) -> u64 {
+        self.0.seq()
+    }
+
+    /// Returns how many more messages this context will seal before returning
+    /// `Err(HpkeError::MessageLimitReached)`. This saturates at `u64::MAX`, which is one short of
+    /// the true count for a fresh context.
+    pub fn messages_remaining(&self) -> u64 {
+        self.0.messages_remaining()
+    }
+
+    /// Returns `(kem_id, kdf_id, aead_id)` of the ciphersuite that made this context
+    pub fn suite_ids(&self) -> (u16, u16, u16) {
+        self.0.suite_ids()
+    }
+
     /// Fills a given buffer with secret bytes derived from this encryption context. This value
     /// does not depend on sequence number, so it is constant for the lifetime of this context.
     ///
@@ -442,18 +1148,178 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
         // Pass to AeadCtx
         self.0.export(info, out_buf)
     }
+
+    /// Returns `len` secret bytes derived from this encryption context, wrapped in a `SecretBox`.
+    /// The bytes are the same ones `export` would write. The `SecretBox` zeroizes them on drop
+    /// and keeps them out of `Debug` output. Use `ExposeSecret` to read them.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the secret on success. If `len` is more than 255x the digest size of the underlying
+    /// hash function, returns an `Err(HpkeError::KdfOutputTooLong)`.
+    #[cfg(feature = "secrecy")]
+    pub fn export_secret(&self, info: &[u8], len: usize) -> Result<SecretBox<[u8]>, HpkeError> {
+        // Pass to AeadCtx
+        self.0.export_secret(info, len)
+    }
+
+    /// Derives a key for the AEAD `B` from this encryption context. `B` need not be the AEAD this
+    /// context uses. The key is the right size for `B`, and it's bound to `B` and
+    /// `exporter_ctx`, so it never equals a key exported for another AEAD or context, or a nonce
+    /// from `export_nonce`. Like `export`, this does not depend on the sequence number.
+    ///
+    /// This is the same as `export` with the exporter context `"aead_key" || I2OSP(B::AEAD_ID, 2)
+    /// || exporter_ctx`, for interop with other implementations.
+    pub fn export_aead_key<B: Aead>(&self, exporter_ctx: &[u8]) -> AeadKey<B> {
+        self.0.export_aead_key(exporter_ctx)
+    }
+
+    /// Derives a nonce-sized secret for the AEAD `B` from this encryption context, e.g., a base
+    /// nonce for a record layer. See `export_aead_key`. This is the same as `export` with the
+    /// exporter context `"aead_nonce" || I2OSP(B::AEAD_ID, 2) || exporter_ctx`.
+    pub fn export_nonce<B: Aead>(&self, exporter_ctx: &[u8]) -> NonceSeed<B> {
+        self.0.export_nonce(exporter_ctx)
+    }
+
+    /// Derives a key for the RustCrypto cipher `C`, any implementor of `aead::NewAead`, from this
+    /// encryption context, and returns the cipher, ready for the `aead::Aead` traits. The
+    /// receiver gets the same cipher from `AeadCtxR::export_to_aead`. Like `export`, this does
+    /// not depend on the sequence number.
+    ///
+    /// The key is bound to `exporter_ctx` and the key size of `C`, but not to `C` itself, since
+    /// ciphers from outside this crate have no AEAD ID. So use a distinct `exporter_ctx` for
+    /// each cipher. For this crate's AEADs, `export_aead_key` and `AeadKey::to_cipher` bind the
+    /// AEAD ID as well.
+    ///
+    /// This is the same as `export` with the exporter context `"aead_ext" || I2OSP(Nk, 2) ||
+    /// exporter_ctx`, where `Nk` is the key size of `C`, for interop with other implementations.
+    ///
+    /// DANGER
+    /// ======
+    /// The sender and receiver get the same key from the same `exporter_ctx`, and the nonces are
+    /// up to the caller. If both sides encrypt with it, a nonce one side uses can also be used by
+    /// the other, which breaks the confidentiality and integrity of everything under the key. Give
+    /// each direction its own `exporter_ctx`, e.g., `b"attachments s2r"` and `b"attachments r2s"`,
+    /// or only ever encrypt on one side.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "alloc", feature = "x25519"))]
+    /// # {
+    /// # use rand::{rngs::StdRng, SeedableRng};
+    /// use hpke::{
+    ///     aead::ChaCha20Poly1305,
+    ///     kdf::HkdfSha256,
+    ///     kem::X25519HkdfSha256,
+    ///     rustcrypto_aead::{generic_array::GenericArray, Aead},
+    ///     Kem, OpModeR, OpModeS,
+    /// };
+    ///
+    /// type A = ChaCha20Poly1305;
+    /// type Kdf = HkdfSha256;
+    /// type K = X25519HkdfSha256;
+    ///
+    /// let mut csprng = StdRng::from_entropy();
+    /// let (sk_recip, pk_recip) = K::gen_keypair(&mut csprng);
+    /// let (encapped_key, sender_ctx) =
+    ///     hpke::setup_sender::<A, Kdf, K, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+    ///         .unwrap();
+    /// let receiver_ctx =
+    ///     hpke::setup_receiver::<A, Kdf, K>(&OpModeR::Base, &sk_recip, &encapped_key, b"info")
+    ///         .unwrap();
+    ///
+    /// // Any RustCrypto AEAD works here, not just the one this context uses
+    /// type Cipher = aes_gcm::Aes256Gcm;
+    /// let nonce = GenericArray::from_slice(&[7u8; 12]);
+    /// let ciphertext = sender_ctx
+    ///     .export_to_aead::<Cipher>(b"attachments")
+    ///     .encrypt(nonce, &b"hello"[..])
+    ///     .unwrap();
+    /// let plaintext = receiver_ctx
+    ///     .export_to_aead::<Cipher>(b"attachments")
+    ///     .decrypt(nonce, &ciphertext[..])
+    ///     .unwrap();
+    /// assert_eq!(plaintext, b"hello");
+    /// # }
+    /// ```
+    pub fn export_to_aead<C: BaseNewAead>(&self, exporter_ctx: &[u8]) -> C {
+        self.0.export_to_aead(exporter_ctx)
+    }
+
+    /// Derives the `index`-th sub-context of this context. Sub-contexts have their own keys,
+    /// nonces, and sequence numbers, so each can be given to a different thread to `seal` with,
+    /// and none of them ever reuses a nonce of another. They don't depend on this context's
+    /// sequence number, and this context can still be used alongside them.
+    ///
+    /// The recipient gets the matching context from `AeadCtxR::fork_at` with the same `index`.
+    /// Every index gives a distinct sub-context, so don't hand the same index to two writers.
+    pub fn fork_at(&self, index: u64) -> AeadCtxS<A, Kdf, Kem> {
+        self.0.fork_at(index).into()
+    }
+
+    /// Derives `n` sub-contexts, for indices `0..n`. This is the same as calling `fork_at` for
+    /// each index. See `fork_at`.
+    #[cfg(feature = "alloc")]
+    pub fn fork(&self, n: usize) -> Vec<AeadCtxS<A, Kdf, Kem>> {
+        (0..n as u64).map(|i| self.fork_at(i)).collect()
+    }
+}
+
+/// Splits an auth'd ciphertext into the ciphertext and the tag
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::OpenError)` if `ciphertext` is too short to contain a tag, since it's
+/// certainly not valid.
+pub(crate) fn split_tag<A: Aead>(ciphertext: &[u8]) -> Result<(&[u8], AeadTag<A>), HpkeError> {
+    let tag_len = AeadTag::<A>::size();
+    let msg_len = ciphertext
+        .len()
+        .checked_sub(tag_len)
+        .ok_or(HpkeError::OpenError)?;
+
+    let (ciphertext, tag_slice) = ciphertext.split_at(msg_len);
+    Ok((
+        ciphertext,
+        AeadTag(GenericArray::clone_from_slice(tag_slice)),
+    ))
 }
 
+#[cfg(feature = "std")]
+mod exporter_reader;
+#[cfg(feature = "std")]
+pub use exporter_reader::ExporterReader;
+mod fixed;
+#[cfg(feature = "nonce-reuse-check")]
+mod nonce_check;
+mod nonce_strategy;
+pub use nonce_strategy::{NonceStrategy, RandomNonces, SequenceNonces};
+mod padding;
+pub use padding::PaddingPolicy;
+mod replay;
+pub(crate) use replay::ReplayWindow;
+pub use replay::MAX_REPLAY_WINDOW;
+
 // Export all the AEAD implementations
 mod aes_gcm;
+mod aes_ocb;
+#[cfg(feature = "aws-lc")]
+mod aws_lc;
 mod chacha20_poly1305;
 mod export_only;
 #[doc(inline)]
-pub use crate::aead::{aes_gcm::*, chacha20_poly1305::*, export_only::*};
+pub use crate::aead::{aes_gcm::*, aes_ocb::*, chacha20_poly1305::*, export_only::*};
+
+// The replay window is made of 64-bit atomics
+#[cfg(target_has_atomic = "64")]
+mod sync_ctx;
+#[cfg(target_has_atomic = "64")]
+pub use sync_ctx::SyncAeadCtxR;
 
 #[cfg(test)]
 mod test {
-    use super::{AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, ExportOnlyAead, Seq};
+    use super::{AeadTag, AesGcm128, AesGcm256, AesOcb128, ChaCha20Poly1305, Seq};
+    #[cfg(feature = "alloc")]
+    use super::{AesOcb256, ExportOnlyAead};
     use crate::{
         kdf::HkdfSha256, test_util::gen_ctx_simple_pair, Deserializable, HpkeError, Serializable,
     };
@@ -479,6 +1345,7 @@ mod test {
     /// Tests that encryption context secret export does not change behavior based on the
     /// underlying sequence number This logic is cipher-agnostic, so we don't make the test generic
     /// over ciphers.
+    #[cfg(feature = "alloc")]
     macro_rules! test_export_idempotence {
         ($test_name:ident, $kem_ty:ty) => {
             #[test]
@@ -515,6 +1382,7 @@ mod test {
 
     /// Tests that anything other than `export()` called on an `ExportOnly` context results in a
     /// panic
+    #[cfg(feature = "alloc")]
     macro_rules! test_exportonly_panics {
         ($test_name1:ident, $test_name2:ident, $kem_ty:ty) => {
             #[should_panic]
@@ -548,6 +1416,7 @@ mod test {
 
     /// Tests that sequence overflowing causes an error. This logic is cipher-agnostic, so we don't
     /// make the test generic over ciphers.
+    #[cfg(feature = "alloc")]
     macro_rules! test_overflow {
         ($test_name:ident, $kem_ty:ty) => {
             #[test]
@@ -615,6 +1484,7 @@ mod test {
     }
 
     /// Tests that `open()` can decrypt things properly encrypted with `seal()`
+    #[cfg(feature = "alloc")]
     macro_rules! test_ctx_correctness {
         ($test_name:ident, $aead_ty:ty, $kem_ty:ty) => {
             #[test]
@@ -634,6 +1504,23 @@ mod test {
                 // Make sure seal() isn't a no-op
                 assert_ne!(&ciphertext, msg);
 
+                // Decrypt a copy in place with a copy of the receiver context. The plaintext is
+                // the front of the buffer.
+                let mut receiver_copy = receiver_ctx.clone();
+                let mut buf = ciphertext.clone();
+                let plaintext = receiver_copy
+                    .open_in_place(&mut buf, aad)
+                    .expect("open_in_place() failed");
+                assert_eq!(plaintext, msg);
+                assert!(receiver_copy.open_in_place(&mut [0u8; 3], aad).is_err());
+
+                // The zeroizing variant gives the same plaintext
+                let decrypted = receiver_ctx
+                    .clone()
+                    .open_zeroizing(&ciphertext, aad)
+                    .expect("open_zeroizing() failed");
+                assert_eq!(&decrypted[..], &msg[..]);
+
                 // Decrypt with the receiver context
                 let decrypted = receiver_ctx.open(&ciphertext, aad).expect("open() failed");
                 assert_eq!(&decrypted, msg);
@@ -655,11 +1542,582 @@ mod test {
         };
     }
 
+    /// Tests that `export_secret` returns the same bytes as `export`, on both ends, and fails the
+    /// same way when asked for too much
+    #[cfg(all(feature = "secrecy", feature = "x25519-dalek"))]
+    #[test]
+    fn test_export_secret() {
+        use secrecy::ExposeSecret;
+
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+
+        let mut expected = [0u8; 42];
+        sender_ctx
+            .export(b"test_export_secret", &mut expected)
+            .unwrap();
+
+        let sender_secret = sender_ctx.export_secret(b"test_export_secret", 42).unwrap();
+        let receiver_secret = receiver_ctx
+            .export_secret(b"test_export_secret", 42)
+            .unwrap();
+        assert_eq!(sender_secret.expose_secret(), &expected[..]);
+        assert_eq!(receiver_secret.expose_secret(), &expected[..]);
+
+        // HKDF-SHA256 can output at most 255 * 32 bytes
+        assert_eq!(
+            sender_ctx.export_secret(b"", 255 * 32 + 1).err(),
+            Some(HpkeError::KdfOutputTooLong)
+        );
+    }
+
+    /// Tests that the typed exports agree on both ends, have the right sizes, are separated by
+    /// type and AEAD, and are the same as the documented plain exports
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_typed_export() {
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let exporter_ctx = b"record layer";
+
+        let key128 = sender_ctx.export_aead_key::<AesGcm128>(exporter_ctx);
+        let key256 = sender_ctx.export_aead_key::<AesGcm256>(exporter_ctx);
+        let nonce = sender_ctx.export_nonce::<AesGcm128>(exporter_ctx);
+        assert_eq!(key128.as_bytes().len(), 16);
+        assert_eq!(key256.as_bytes().len(), 32);
+        assert_eq!(nonce.as_bytes().len(), 12);
+
+        // The receiver gets the same values
+        assert_eq!(
+            receiver_ctx
+                .export_aead_key::<AesGcm128>(exporter_ctx)
+                .as_bytes(),
+            key128.as_bytes()
+        );
+        assert_eq!(
+            receiver_ctx
+                .export_nonce::<AesGcm128>(exporter_ctx)
+                .as_bytes(),
+            nonce.as_bytes()
+        );
+
+        // Different AEADs, types, and contexts give different values
+        assert_ne!(&key256.as_bytes()[..16], key128.as_bytes());
+        assert_ne!(nonce.as_bytes(), &key128.as_bytes()[..12]);
+        assert_ne!(
+            sender_ctx.export_aead_key::<AesGcm128>(b"other").as_bytes(),
+            key128.as_bytes()
+        );
+
+        // They're plain exports with a prefixed exporter context
+        let mut expected_key = [0u8; 16];
+        sender_ctx
+            .export(b"aead_key\x00\x01record layer", &mut expected_key)
+            .unwrap();
+        assert_eq!(key128.as_bytes(), expected_key);
+        let mut expected_nonce = [0u8; 12];
+        sender_ctx
+            .export(b"aead_nonce\x00\x01record layer", &mut expected_nonce)
+            .unwrap();
+        assert_eq!(nonce.as_bytes(), expected_nonce);
+    }
+
+    /// Tests that RustCrypto ciphers exported on both ends interoperate, that their keys are the
+    /// documented plain exports, and that AeadKey converts to the matching cipher and key
+    #[cfg(all(feature = "x25519-dalek", feature = "alloc"))]
+    #[test]
+    fn test_export_to_aead() {
+        use aead::{Aead as _, NewAead as _};
+
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let nonce = generic_array::GenericArray::from_slice(&[3u8; 12]);
+
+        let ciphertext = sender_ctx
+            .export_to_aead::<aes_gcm::Aes256Gcm>(b"files")
+            .encrypt(nonce, &b"msg"[..])
+            .unwrap();
+        let opened = receiver_ctx
+            .export_to_aead::<aes_gcm::Aes256Gcm>(b"files")
+            .decrypt(nonce, &ciphertext[..])
+            .unwrap();
+        assert_eq!(opened, b"msg");
+
+        // The key is a plain export with a prefixed exporter context
+        let mut expected_key = [0u8; 32];
+        sender_ctx
+            .export(b"aead_ext\x00\x20files", &mut expected_key)
+            .unwrap();
+        let expected =
+            aes_gcm::Aes256Gcm::new(generic_array::GenericArray::from_slice(&expected_key));
+        assert_eq!(expected.decrypt(nonce, &ciphertext[..]).unwrap(), b"msg");
+
+        // Another exporter context or key size gives another key
+        assert!(receiver_ctx
+            .export_to_aead::<aes_gcm::Aes256Gcm>(b"filez")
+            .decrypt(nonce, &ciphertext[..])
+            .is_err());
+        let ciphertext128 = sender_ctx
+            .export_to_aead::<aes_gcm::Aes128Gcm>(b"files")
+            .encrypt(nonce, &b"msg"[..])
+            .unwrap();
+        let mut expected_key = [0u8; 16];
+        sender_ctx
+            .export(b"aead_ext\x00\x10files", &mut expected_key)
+            .unwrap();
+        let expected =
+            aes_gcm::Aes128Gcm::new(generic_array::GenericArray::from_slice(&expected_key));
+        assert_eq!(expected.decrypt(nonce, &ciphertext128[..]).unwrap(), b"msg");
+        assert_ne!(ciphertext128, ciphertext);
+
+        // AeadKey::to_cipher and the From impl use the key as is
+        let key = sender_ctx.export_aead_key::<AesGcm256>(b"files");
+        let ciphertext = key.to_cipher().encrypt(nonce, &b"msg"[..]).unwrap();
+        let raw_key: aead::Key<aes_gcm::Aes256Gcm> = (&key).into();
+        assert_eq!(raw_key.as_slice(), key.as_bytes());
+        assert_eq!(
+            aes_gcm::Aes256Gcm::new(&raw_key)
+                .decrypt(nonce, &ciphertext[..])
+                .unwrap(),
+            b"msg"
+        );
+    }
+
+    /// Tests that forked contexts match up between sender and receiver, and that they're
+    /// independent of each other and of the parent context
+    #[cfg(all(feature = "x25519-dalek", feature = "alloc"))]
+    #[test]
+    fn test_fork() {
+        use crate::Vec;
+
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let msg = b"one of many chunks";
+        let aad = b"upload 7";
+
+        let mut sender_forks = sender_ctx.fork(3);
+        let mut receiver_forks = receiver_ctx.fork(3);
+        assert_eq!(sender_forks.len(), 3);
+
+        // Every fork seals the same message to a different ciphertext, and so does the parent
+        let mut ciphertexts: Vec<Vec<u8>> = sender_forks
+            .iter_mut()
+            .map(|ctx| ctx.seal(msg, aad).unwrap())
+            .collect();
+        ciphertexts.push(sender_ctx.seal(msg, aad).unwrap());
+        for i in 0..ciphertexts.len() {
+            for j in i + 1..ciphertexts.len() {
+                assert_ne!(ciphertexts[i], ciphertexts[j]);
+            }
+        }
+
+        // Only the matching receiver fork can open each one
+        assert!(receiver_forks[1].open(&ciphertexts[0], aad).is_err());
+        for (ctx, ciphertext) in receiver_forks.iter_mut().zip(ciphertexts.iter()) {
+            assert_eq!(ctx.open(ciphertext, aad).unwrap(), msg);
+        }
+        assert_eq!(receiver_ctx.open(&ciphertexts[3], aad).unwrap(), msg);
+
+        // fork_at gives the same context as the corresponding element of fork, on both sides.
+        // Resealing the same message under sender_forks[2]'s first nonce is harmless, so clear the
+        // nonce reuse registry's record of it first.
+        #[cfg(feature = "nonce-reuse-check")]
+        sender_forks[2].forget_sealed_nonces();
+        assert_eq!(
+            sender_ctx.fork_at(2).seal(msg, aad).unwrap(),
+            ciphertexts[2]
+        );
+        let mut fork2 = receiver_ctx.fork_at(2);
+        assert_eq!(fork2.open(&ciphertexts[2], aad).unwrap(), msg);
+    }
+
+    /// Tests that padded seals round-trip under each policy, that their lengths follow the policy,
+    /// and that unpadded ciphertexts don't open as padded ones
+    #[cfg(all(feature = "x25519-dalek", feature = "alloc"))]
+    #[test]
+    fn test_seal_padded() {
+        use super::PaddingPolicy;
+
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let aad = b"aad";
+
+        for (policy, msg_len, ct_len) in [
+            (PaddingPolicy::Padme, 0, 1),
+            (PaddingPolicy::Padme, 99, 104),
+            (PaddingPolicy::Block(32), 5, 32),
+            (PaddingPolicy::Block(32), 32, 64),
+            (PaddingPolicy::Fixed(256), 200, 256),
+        ] {
+            sender_ctx.set_padding_policy(policy);
+            assert_eq!(sender_ctx.padding_policy(), policy);
+            let msg = vec![0x80u8; msg_len];
+            let ciphertext = sender_ctx.seal_padded(&msg, aad).unwrap();
+            assert_eq!(ciphertext.len(), ct_len + 16);
+            assert_eq!(receiver_ctx.open_padded(&ciphertext, aad).unwrap(), msg);
+        }
+
+        // Doesn't fit, and forks inherit the policy
+        assert_eq!(
+            sender_ctx.seal_padded(&[0u8; 256], aad).err(),
+            Some(HpkeError::MessageTooLarge(255, 256))
+        );
+        assert_eq!(
+            sender_ctx.fork_at(0).padding_policy(),
+            PaddingPolicy::Fixed(256)
+        );
+
+        // A plain seal of something that doesn't end in the marker isn't a padded message
+        let ciphertext = sender_ctx.seal(b"no padding", aad).unwrap();
+        assert_eq!(
+            receiver_ctx.open_padded(&ciphertext, aad).err(),
+            Some(HpkeError::OpenError)
+        );
+    }
+
+    /// Tests the sequence number, remaining message count, and suite ID getters
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_introspection() {
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        assert_eq!(sender_ctx.suite_ids(), (0x0020, 0x0001, 0x0003));
+        assert_eq!(receiver_ctx.suite_ids(), sender_ctx.suite_ids());
+        assert_eq!(sender_ctx.seq(), 0);
+        assert_eq!(sender_ctx.messages_remaining(), u64::MAX);
+
+        let mut buf = *b"msg";
+        let tag = sender_ctx.seal_in_place_detached(&mut buf, b"").unwrap();
+        sender_ctx
+            .seal_in_place_detached(&mut [0u8; 3], b"")
+            .unwrap();
+        assert_eq!(sender_ctx.seq(), 2);
+        assert_eq!(sender_ctx.messages_remaining(), u64::MAX - 1);
+
+        receiver_ctx
+            .open_in_place_detached(&mut buf, b"", &tag)
+            .unwrap();
+        assert_eq!(receiver_ctx.seq(), 1);
+
+        // One message left at the last sequence number, and none after it
+        sender_ctx.0.seq = Seq(u64::MAX);
+        assert_eq!(sender_ctx.messages_remaining(), 1);
+        sender_ctx
+            .seal_in_place_detached(&mut [0u8; 3], b"")
+            .unwrap();
+        assert_eq!(sender_ctx.seq(), u64::MAX);
+        assert_eq!(sender_ctx.messages_remaining(), 0);
+    }
+
+    /// Tests that `open_at` opens out of order within the replay window, and rejects replays and
+    /// sequence numbers that fell out of the window
+    #[cfg(all(feature = "x25519-dalek", feature = "alloc"))]
+    #[test]
+    fn test_open_at_replay_window() {
+        use crate::Vec;
+
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let aad = b"datagram";
+        let ciphertexts: Vec<Vec<u8>> = (0u8..20)
+            .map(|i| sender_ctx.seal(&[i], aad).unwrap())
+            .collect();
+
+        assert_eq!(
+            receiver_ctx.set_replay_window(0),
+            Err(HpkeError::ValidationError)
+        );
+        assert_eq!(
+            receiver_ctx.set_replay_window(super::MAX_REPLAY_WINDOW + 1),
+            Err(HpkeError::ValidationError)
+        );
+        receiver_ctx.set_replay_window(8).unwrap();
+
+        // Reordered and dropped messages are fine
+        for seq in [3, 1, 2, 7, 4] {
+            let plaintext = receiver_ctx
+                .open_at(seq, &ciphertexts[seq as usize], aad)
+                .unwrap();
+            assert_eq!(plaintext, [seq as u8]);
+        }
+
+        // Replays aren't
+        assert_eq!(
+            receiver_ctx.open_at(2, &ciphertexts[2], aad),
+            Err(HpkeError::ReplayedMessage)
+        );
+
+        // A forgery doesn't use up its sequence number
+        assert_eq!(
+            receiver_ctx.open_at(5, &ciphertexts[6], aad),
+            Err(HpkeError::OpenError)
+        );
+        assert_eq!(receiver_ctx.open_at(5, &ciphertexts[5], aad).unwrap(), [5]);
+
+        // Once 16 is opened, the window is 9..=16, so 0 and 8 are too old, but 9 is fine
+        receiver_ctx.open_at(16, &ciphertexts[16], aad).unwrap();
+        assert_eq!(
+            receiver_ctx.open_at(0, &ciphertexts[0], aad),
+            Err(HpkeError::ReplayedMessage)
+        );
+        assert_eq!(
+            receiver_ctx.open_at(8, &ciphertexts[8], aad),
+            Err(HpkeError::ReplayedMessage)
+        );
+        assert_eq!(receiver_ctx.open_at(9, &ciphertexts[9], aad).unwrap(), [9]);
+    }
+
+    /// Tests that seal_bytes and open_bytes round-trip through one buffer without reallocating,
+    /// and interoperate with seal and open
+    #[cfg(all(feature = "bytes", feature = "alloc", feature = "x25519-dalek"))]
+    #[test]
+    fn test_seal_open_bytes() {
+        use bytes::BytesMut;
+
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let msg = b"datagram";
+
+        let mut buf = BytesMut::with_capacity(msg.len() + AeadTag::<A>::size());
+        buf.extend_from_slice(msg);
+        let ptr = buf.as_ptr();
+        sender_ctx.seal_bytes(&mut buf, b"aad").unwrap();
+        assert_eq!(buf.len(), msg.len() + AeadTag::<A>::size());
+        assert_eq!(buf.as_ptr(), ptr);
+
+        receiver_ctx.open_bytes(&mut buf, b"aad").unwrap();
+        assert_eq!(&buf[..], msg);
+        assert_eq!(buf.as_ptr(), ptr);
+
+        // The formats are the same as seal and open
+        let ciphertext = sender_ctx.seal(msg, b"aad").unwrap();
+        let mut buf = BytesMut::from(&ciphertext[..]);
+        receiver_ctx.open_bytes(&mut buf, b"aad").unwrap();
+        assert_eq!(&buf[..], msg);
+
+        let mut buf = BytesMut::from(&msg[..]);
+        sender_ctx.seal_bytes(&mut buf, b"aad").unwrap();
+        assert_eq!(receiver_ctx.open(&buf, b"aad").unwrap(), msg);
+        assert_eq!(
+            receiver_ctx.open_bytes(&mut BytesMut::from(&b"short"[..]), b"aad"),
+            Err(HpkeError::OpenError)
+        );
+    }
+
+    /// Tests that seal_many produces the same ciphertexts as sealing one by one, and that its
+    /// up-front checks leave the context untouched
+    #[cfg(all(feature = "alloc", feature = "x25519-dalek"))]
+    #[test]
+    fn test_seal_many() {
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = AesGcm128;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let tag_len = AeadTag::<A>::size();
+
+        // Start just below a byte boundary of the sequence number, so stepping the nonce carries
+        sender_ctx.0.seq.0 = 254;
+        receiver_ctx.0.seq.0 = 254;
+
+        let msgs: [(&[u8], &[u8]); 4] = [(b"a", b"1"), (b"", b"2"), (b"ccc", b""), (b"dd", b"4")];
+        let ciphertexts = sender_ctx.seal_many(&msgs).unwrap();
+        assert_eq!(ciphertexts.len(), 6 + 4 * tag_len);
+        assert_eq!(sender_ctx.seq(), 258);
+
+        let mut rest = &ciphertexts[..];
+        for (plaintext, aad) in msgs {
+            let (ciphertext, tail) = rest.split_at(plaintext.len() + tag_len);
+            assert_eq!(receiver_ctx.open(ciphertext, aad).unwrap(), plaintext);
+            rest = tail;
+        }
+        assert!(rest.is_empty());
+        assert_eq!(sender_ctx.seal_many(&[]).unwrap(), b"");
+
+        // One oversized message fails the whole batch before anything is sealed
+        sender_ctx.set_max_message_len(Some(2));
+        assert_eq!(
+            sender_ctx.seal_many(&msgs),
+            Err(HpkeError::MessageTooLarge(2, 3))
+        );
+        assert_eq!(sender_ctx.seq(), 258);
+        sender_ctx.set_max_message_len(None);
+
+        // So does a batch bigger than what's left. Exactly what's left works.
+        sender_ctx.0.seq.0 = u64::MAX - 2;
+        receiver_ctx.0.seq.0 = u64::MAX - 2;
+        assert_eq!(
+            sender_ctx.seal_many(&msgs),
+            Err(HpkeError::MessageLimitReached)
+        );
+        assert_eq!(sender_ctx.0.seq.0, u64::MAX - 2);
+        let ciphertexts = sender_ctx.seal_many(&msgs[..3]).unwrap();
+        assert!(sender_ctx.0.overflowed);
+        let mut rest = &ciphertexts[..];
+        for (plaintext, aad) in &msgs[..3] {
+            let (ciphertext, tail) = rest.split_at(plaintext.len() + tag_len);
+            assert_eq!(receiver_ctx.open(ciphertext, aad).unwrap(), *plaintext);
+            rest = tail;
+        }
+        assert_eq!(
+            sender_ctx.seal_many(&msgs[..1]),
+            Err(HpkeError::MessageLimitReached)
+        );
+    }
+
+    /// Tests that the size limit rejects long messages on both sides, without touching the
+    /// sequence numbers, and that forks keep it
+    #[cfg(all(feature = "x25519-dalek", feature = "alloc"))]
+    #[test]
+    fn test_max_message_len() {
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+        type A = ChaCha20Poly1305;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let aad = b"size-limited";
+        let long_msg = [7u8; 17];
+        let long_ciphertext = sender_ctx.seal(&long_msg, aad).unwrap();
+
+        sender_ctx.set_max_message_len(Some(16));
+        receiver_ctx.set_max_message_len(Some(16));
+        assert_eq!(receiver_ctx.max_message_len(), Some(16));
+
+        // Messages over the limit are rejected, in place or not
+        assert_eq!(
+            sender_ctx.seal(&long_msg, aad),
+            Err(HpkeError::MessageTooLarge(16, 17))
+        );
+        let mut buf = long_msg;
+        assert_eq!(
+            sender_ctx.seal_in_place_detached(&mut buf, aad).err(),
+            Some(HpkeError::MessageTooLarge(16, 17))
+        );
+        assert_eq!(buf, long_msg);
+        assert_eq!(
+            receiver_ctx.open(&long_ciphertext, aad),
+            Err(HpkeError::MessageTooLarge(16, 17))
+        );
+        assert_eq!(
+            receiver_ctx.open_at(0, &long_ciphertext, aad),
+            Err(HpkeError::MessageTooLarge(16, 17))
+        );
+
+        // Messages at the limit are fine, and the rejections didn't use up sequence numbers
+        let mut forked_sender = sender_ctx.fork_at(0);
+        let forked_receiver = receiver_ctx.fork_at(0);
+        let ciphertext = sender_ctx.seal(&long_msg[..16], aad).unwrap();
+        receiver_ctx.set_max_message_len(None);
+        assert_eq!(receiver_ctx.open(&long_ciphertext, aad).unwrap(), long_msg);
+        assert_eq!(
+            receiver_ctx.open(&ciphertext, aad).unwrap(),
+            &long_msg[..16]
+        );
+
+        // Forks inherit the limit
+        assert_eq!(
+            forked_sender.seal(&long_msg, aad),
+            Err(HpkeError::MessageTooLarge(16, 17))
+        );
+        assert_eq!(forked_receiver.max_message_len(), Some(16));
+    }
+
+    /// Tests that every way a ciphertext can fail to authenticate gives the same error and zeroes
+    /// the buffer, and that none of them advance the sequence number. Also tests AeadTag::verify.
+    #[cfg(feature = "x25519-dalek")]
+    macro_rules! test_open_failure_uniform {
+        ($test_name:ident, $aead_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = crate::kem::X25519HkdfSha256;
+                type Kdf = HkdfSha256;
+                type A = $aead_ty;
+
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let msg = *b"attack at dawn!!";
+                let aad = b"aad";
+                let mut ciphertext = msg;
+                let tag = sender_ctx
+                    .seal_in_place_detached(&mut ciphertext, aad)
+                    .unwrap();
+
+                let mut bad_tag_bytes = tag.to_bytes();
+                bad_tag_bytes[0] ^= 1;
+                let bad_tag = AeadTag::<A>::from_bytes(&bad_tag_bytes).unwrap();
+                let same_tag = AeadTag::<A>::from_bytes(&tag.to_bytes()).unwrap();
+                assert_eq!(tag.verify(&same_tag), Ok(()));
+                assert_eq!(tag.verify(&bad_tag), Err(HpkeError::OpenError));
+
+                // A flipped ciphertext bit, the wrong AAD, and a flipped tag bit
+                let mut flipped_ciphertext = ciphertext;
+                flipped_ciphertext[5] ^= 0x80;
+                let forgeries = [
+                    (flipped_ciphertext, &aad[..], &tag),
+                    (ciphertext, &b"other aad"[..], &tag),
+                    (ciphertext, &aad[..], &bad_tag),
+                ];
+                for (mut buf, aad, tag) in forgeries {
+                    assert_eq!(
+                        receiver_ctx.open_in_place_detached(&mut buf, aad, tag),
+                        Err(HpkeError::OpenError)
+                    );
+                    assert_eq!(buf, [0u8; 16]);
+                }
+
+                // A truncated ciphertext
+                let mut buf = ciphertext;
+                assert_eq!(
+                    receiver_ctx.open_in_place_detached(&mut buf[..15], aad, &tag),
+                    Err(HpkeError::OpenError)
+                );
+                assert_eq!(buf[..15], [0u8; 15]);
+
+                // The real ciphertext still opens at sequence number 0
+                let mut buf = ciphertext;
+                receiver_ctx
+                    .open_in_place_detached(&mut buf, aad, &tag)
+                    .unwrap();
+                assert_eq!(buf, msg);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_open_failure_uniform!(test_open_failure_uniform_chacha, ChaCha20Poly1305);
+    #[cfg(feature = "x25519-dalek")]
+    test_open_failure_uniform!(test_open_failure_uniform_aes128, AesGcm128);
+    #[cfg(feature = "x25519-dalek")]
+    test_open_failure_uniform!(test_open_failure_uniform_ocb128, AesOcb128);
+
     test_invalid_nonce!(test_invalid_nonce_aes128, AesGcm128);
     test_invalid_nonce!(test_invalid_nonce_aes256, AesGcm128);
     test_invalid_nonce!(test_invalid_nonce_chacha, ChaCha20Poly1305);
 
-    #[cfg(feature = "x25519-dalek")]
+    #[cfg(all(feature = "alloc", feature = "x25519-dalek"))]
     mod x25519_tests {
         use super::*;
 
@@ -686,9 +2144,31 @@ mod test {
             ChaCha20Poly1305,
             crate::kem::X25519HkdfSha256
         );
+        test_ctx_correctness!(
+            test_ctx_correctness_ocb128_x25519,
+            AesOcb128,
+            crate::kem::X25519HkdfSha256
+        );
+        test_ctx_correctness!(
+            test_ctx_correctness_ocb256_x25519,
+            AesOcb256,
+            crate::kem::X25519HkdfSha256
+        );
+        #[cfg(feature = "reduced-round")]
+        test_ctx_correctness!(
+            test_ctx_correctness_chacha12_x25519,
+            crate::aead::ChaCha12Poly1305,
+            crate::kem::X25519HkdfSha256
+        );
+        #[cfg(feature = "reduced-round")]
+        test_ctx_correctness!(
+            test_ctx_correctness_chacha8_x25519,
+            crate::aead::ChaCha8Poly1305,
+            crate::kem::X25519HkdfSha256
+        );
     }
 
-    #[cfg(feature = "p256")]
+    #[cfg(all(feature = "alloc", feature = "p256"))]
     mod p256_tests {
         use super::*;
 
@@ -715,5 +2195,15 @@ mod test {
             ChaCha20Poly1305,
             crate::kem::DhP256HkdfSha256
         );
+        test_ctx_correctness!(
+            test_ctx_correctness_ocb128_p256,
+            AesOcb128,
+            crate::kem::DhP256HkdfSha256
+        );
+        test_ctx_correctness!(
+            test_ctx_correctness_ocb256_p256,
+            AesOcb256,
+            crate::kem::DhP256HkdfSha256
+        );
     }
 }
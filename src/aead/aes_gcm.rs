@@ -4,7 +4,10 @@ use crate::aead::Aead;
 pub struct AesGcm128;
 
 impl Aead for AesGcm128 {
+    #[cfg(not(feature = "aws-lc"))]
     type AeadImpl = aes_gcm::Aes128Gcm;
+    #[cfg(feature = "aws-lc")]
+    type AeadImpl = super::aws_lc::AwsLcAes128Gcm;
 
     // RFC 9180 §7.3: AES-128-GCM
     const AEAD_ID: u16 = 0x0001;
@@ -14,8 +17,106 @@ impl Aead for AesGcm128 {
 pub struct AesGcm256 {}
 
 impl Aead for AesGcm256 {
+    #[cfg(not(feature = "aws-lc"))]
     type AeadImpl = aes_gcm::Aes256Gcm;
+    #[cfg(feature = "aws-lc")]
+    type AeadImpl = super::aws_lc::AwsLcAes256Gcm;
 
     // RFC 9180 §7.3: AES-256-GCM
     const AEAD_ID: u16 = 0x0002;
 }
+
+/// Which implementations the AES-GCM AEADs above use on this machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AesGcmBackend {
+    /// Whether the AES block cipher uses the AES-NI instructions. If not, it uses a constant-time
+    /// bitsliced software implementation.
+    pub aes_ni: bool,
+    /// Whether GHASH uses the carryless multiplication (CLMUL) instructions. If not, it uses a
+    /// constant-time software implementation.
+    pub clmul: bool,
+}
+
+impl AesGcmBackend {
+    /// Returns whether both halves of AES-GCM run on dedicated hardware instructions
+    pub fn is_hardware(&self) -> bool {
+        self.aes_ni && self.clmul
+    }
+}
+
+// These are the same CPU feature sets that the aes and polyval crates check before picking their
+// hardware backends. cpufeatures caches the answer, so these are cheap after the first call.
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "aes-force-soft")
+))]
+cpufeatures::new!(aes_intrinsics, "aes");
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "aes-force-soft")
+))]
+cpufeatures::new!(clmul_intrinsics, "pclmulqdq", "sse4.1");
+
+/// Reports which implementations AES-GCM uses on this machine. The choice is made at runtime: if
+/// the CPU supports AES-NI and CLMUL, those are used, and otherwise AES-GCM transparently falls
+/// back to constant-time software. The two halves are chosen independently.
+///
+/// Hardware support is only used on x86 and x86-64. Everywhere else, and whenever the
+/// `aes-force-soft` feature is set, this reports software for both. With the `aws-lc` feature,
+/// AES-GCM doesn't use these implementations at all, and this only reports what the CPU supports.
+pub fn aes_gcm_backend() -> AesGcmBackend {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(feature = "aes-force-soft")
+    ))]
+    {
+        AesGcmBackend {
+            aes_ni: aes_intrinsics::get(),
+            clmul: clmul_intrinsics::get(),
+        }
+    }
+
+    #[cfg(not(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(feature = "aes-force-soft")
+    )))]
+    {
+        AesGcmBackend {
+            aes_ni: false,
+            clmul: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::aes_gcm_backend;
+
+    /// Tests that the reported backend matches what the CPU actually supports
+    #[test]
+    fn test_aes_gcm_backend() {
+        let backend = aes_gcm_backend();
+
+        #[cfg(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            not(feature = "aes-force-soft")
+        ))]
+        {
+            extern crate std;
+            assert_eq!(backend.aes_ni, std::is_x86_feature_detected!("aes"));
+            assert_eq!(
+                backend.clmul,
+                std::is_x86_feature_detected!("pclmulqdq")
+                    && std::is_x86_feature_detected!("sse4.1")
+            );
+        }
+
+        #[cfg(not(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            not(feature = "aes-force-soft")
+        )))]
+        assert!(!backend.aes_ni && !backend.clmul);
+
+        assert_eq!(backend.is_hardware(), backend.aes_ni && backend.clmul);
+    }
+}
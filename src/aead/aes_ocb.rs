@@ -0,0 +1,437 @@
+use crate::aead::Aead;
+
+use aead::{AeadCore as BaseAeadCore, AeadInPlace as BaseAeadInPlace, NewAead as BaseNewAead};
+use aes::{BlockCipher, BlockDecrypt, BlockEncrypt, NewBlockCipher};
+use generic_array::{typenum, GenericArray};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+// OCB3 works on 128-bit blocks
+type Block = [u8; 16];
+
+// The number of L_i values to precompute. Block i uses L_{ntz(i)}, and block indices fit in 64
+// bits, so ntz(i) < 64.
+const NUM_L: usize = 64;
+
+/// OCB3 with 128-bit tags and 96-bit nonces, as specified in RFC 7253, over the block cipher `C`.
+/// This is the underlying implementation of `AesOcb128` and `AesOcb256`.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct Ocb3<C> {
+    cipher: C,
+    /// L_* = ENCIPHER(K, zeros(128))
+    l_star: Block,
+    /// L_$ = double(L_*)
+    l_dollar: Block,
+    /// L_0 = double(L_$), and L_i = double(L_{i-1})
+    l: [Block; NUM_L],
+}
+
+impl<C> Drop for Ocb3<C> {
+    fn drop(&mut self) {
+        // The block ciphers from the aes crate don't zeroize their round keys, so do it here. A
+        // cipher with no drop glue owns no pointers that need freeing, and it's never read again.
+        if !core::mem::needs_drop::<C>() {
+            // SAFETY: C has no drop glue, so it holds no Drop impls or owning pointers, and
+            // nothing reads the zeroed value, since this is the last use of self
+            unsafe { zeroize::zeroize_flat_type(&mut self.cipher as *mut C) };
+        }
+        self.l_star.zeroize();
+        self.l_dollar.zeroize();
+        self.l.iter_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+// RFC 7253 §2: double(S) = S << 1 if the first bit of S is 0, and (S << 1) xor
+// zeros(120) || 10000111 otherwise. The reduction is done without branching on the secret bit.
+fn double(block: &Block) -> Block {
+    let mut out = [0u8; 16];
+    for i in 0..15 {
+        out[i] = (block[i] << 1) | (block[i + 1] >> 7);
+    }
+    out[15] = (block[15] << 1) ^ (0x87 & 0u8.wrapping_sub(block[0] >> 7));
+    out
+}
+
+fn xor_in_place(a: &mut [u8], b: &[u8]) {
+    a.iter_mut().zip(b).for_each(|(x, y)| *x ^= y);
+}
+
+impl<C> Ocb3<C>
+where
+    C: BlockCipher<BlockSize = typenum::U16> + BlockEncrypt + BlockDecrypt,
+{
+    fn encipher(&self, block: &mut Block) {
+        self.cipher
+            .encrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    fn decipher(&self, block: &mut Block) {
+        self.cipher
+            .decrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    // RFC 7253 §4.2:
+    //   Nonce = num2str(TAGLEN mod 128,7) || zeros(120-bitlen(N)) || 1 || N
+    //   bottom = str2num(Nonce[123..128])
+    //   Ktop = ENCIPHER(K, Nonce[1..122] || zeros(6))
+    //   Stretch = Ktop || (Ktop[1..64] xor Ktop[9..72])
+    //   Offset_0 = Stretch[1+bottom..128+bottom]
+    fn initial_offset(&self, nonce: &[u8]) -> Block {
+        // TAGLEN is 128 and N is 96 bits, so this is 31 zero bits, a 1 bit, then N
+        let mut nonce_block = [0u8; 16];
+        nonce_block[3] = 1;
+        nonce_block[4..].copy_from_slice(nonce);
+
+        let bottom = (nonce_block[15] & 0x3f) as usize;
+        nonce_block[15] &= 0xc0;
+        let mut ktop = nonce_block;
+        self.encipher(&mut ktop);
+
+        let mut stretch = [0u8; 24];
+        stretch[..16].copy_from_slice(&ktop);
+        for i in 0..8 {
+            stretch[16 + i] = ktop[i] ^ ktop[i + 1];
+        }
+
+        // Shift Stretch left by `bottom` bits and take the first 128
+        let (byte_shift, bit_shift) = (bottom / 8, bottom % 8);
+        let mut offset = [0u8; 16];
+        for (i, b) in offset.iter_mut().enumerate() {
+            *b = stretch[i + byte_shift] << bit_shift;
+            if bit_shift > 0 {
+                *b |= stretch[i + byte_shift + 1] >> (8 - bit_shift);
+            }
+        }
+
+        ktop.zeroize();
+        stretch.zeroize();
+        offset
+    }
+
+    // RFC 7253 §4.1: HASH(K, A)
+    fn hash(&self, aad: &[u8]) -> Block {
+        let mut sum = [0u8; 16];
+        let mut offset = [0u8; 16];
+
+        // Sum_i = Sum_{i-1} xor ENCIPHER(K, A_i xor Offset_i) for every full block
+        let mut chunks = aad.chunks_exact(16);
+        for (i, chunk) in (1u64..).zip(&mut chunks) {
+            xor_in_place(&mut offset, &self.l[i.trailing_zeros() as usize]);
+            let mut block = offset;
+            xor_in_place(&mut block, chunk);
+            self.encipher(&mut block);
+            xor_in_place(&mut sum, &block);
+        }
+
+        // The final partial block, if any, is padded with 10*
+        let rem = chunks.remainder();
+        if !rem.is_empty() {
+            xor_in_place(&mut offset, &self.l_star);
+            let mut block = [0u8; 16];
+            block[..rem.len()].copy_from_slice(rem);
+            block[rem.len()] = 0x80;
+            xor_in_place(&mut block, &offset);
+            self.encipher(&mut block);
+            xor_in_place(&mut sum, &block);
+        }
+
+        sum
+    }
+
+    // Encrypts or decrypts `buffer` in place and returns the tag, which is computed over the
+    // plaintext either way. This is RFC 7253 §4.2 and §4.3 at once.
+    fn crypt_in_place(&self, nonce: &[u8], aad: &[u8], buffer: &mut [u8], encrypt: bool) -> Block {
+        let mut offset = self.initial_offset(nonce);
+        let mut checksum = [0u8; 16];
+
+        // Offset_i = Offset_{i-1} xor L_{ntz(i)}
+        // C_i = Offset_i xor ENCIPHER(K, P_i xor Offset_i)
+        // P_i = Offset_i xor DECIPHER(K, C_i xor Offset_i)
+        // Checksum_i = Checksum_{i-1} xor P_i
+        let mut chunks = buffer.chunks_exact_mut(16);
+        for (i, chunk) in (1u64..).zip(&mut chunks) {
+            xor_in_place(&mut offset, &self.l[i.trailing_zeros() as usize]);
+            if encrypt {
+                xor_in_place(&mut checksum, chunk);
+            }
+
+            let mut block = offset;
+            xor_in_place(&mut block, chunk);
+            if encrypt {
+                self.encipher(&mut block);
+            } else {
+                self.decipher(&mut block);
+            }
+            xor_in_place(&mut block, &offset);
+            chunk.copy_from_slice(&block);
+
+            if !encrypt {
+                xor_in_place(&mut checksum, chunk);
+            }
+        }
+
+        // Offset_* = Offset_m xor L_*
+        // Pad = ENCIPHER(K, Offset_*)
+        // C_* = P_* xor Pad[1..bitlen(P_*)]
+        // Checksum_* = Checksum_m xor (P_* || 1 || zeros(127-bitlen(P_*)))
+        let rem = chunks.into_remainder();
+        if !rem.is_empty() {
+            xor_in_place(&mut offset, &self.l_star);
+            let mut pad = offset;
+            self.encipher(&mut pad);
+
+            if encrypt {
+                xor_in_place(&mut checksum, rem);
+            }
+            xor_in_place(rem, &pad);
+            if !encrypt {
+                xor_in_place(&mut checksum, rem);
+            }
+            checksum[rem.len()] ^= 0x80;
+            pad.zeroize();
+        }
+
+        // Tag = ENCIPHER(K, Checksum xor Offset xor L_$) xor HASH(K,A)
+        let mut tag = checksum;
+        xor_in_place(&mut tag, &offset);
+        xor_in_place(&mut tag, &self.l_dollar);
+        self.encipher(&mut tag);
+        xor_in_place(&mut tag, &self.hash(aad));
+
+        offset.zeroize();
+        checksum.zeroize();
+        tag
+    }
+}
+
+impl<C> BaseAeadCore for Ocb3<C> {
+    type NonceSize = typenum::U12;
+    type TagSize = typenum::U16;
+    type CiphertextOverhead = typenum::U0;
+}
+
+impl<C> BaseAeadInPlace for Ocb3<C>
+where
+    C: BlockCipher<BlockSize = typenum::U16> + BlockEncrypt + BlockDecrypt,
+{
+    fn encrypt_in_place_detached(
+        &self,
+        nonce: &aead::Nonce<Self>,
+        aad: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<aead::Tag<Self>, aead::Error> {
+        let tag = self.crypt_in_place(nonce, aad, buffer, true);
+        Ok(GenericArray::clone_from_slice(&tag))
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &aead::Nonce<Self>,
+        aad: &[u8],
+        buffer: &mut [u8],
+        tag: &aead::Tag<Self>,
+    ) -> Result<(), aead::Error> {
+        let expected_tag = self.crypt_in_place(nonce, aad, buffer, false);
+        if bool::from(expected_tag.ct_eq(tag.as_slice())) {
+            Ok(())
+        } else {
+            // The tag is over the plaintext, so decryption already happened. Don't leave
+            // unauthenticated plaintext behind.
+            buffer.zeroize();
+            Err(aead::Error)
+        }
+    }
+}
+
+impl<C> BaseNewAead for Ocb3<C>
+where
+    C: BlockCipher<BlockSize = typenum::U16> + BlockEncrypt + BlockDecrypt + NewBlockCipher,
+{
+    type KeySize = C::KeySize;
+
+    fn new(key: &aead::Key<Self>) -> Self {
+        let mut ocb = Ocb3 {
+            cipher: C::new(key),
+            l_star: [0u8; 16],
+            l_dollar: [0u8; 16],
+            l: [[0u8; 16]; NUM_L],
+        };
+
+        let mut l_star = [0u8; 16];
+        ocb.encipher(&mut l_star);
+        ocb.l_star = l_star;
+        ocb.l_dollar = double(&l_star);
+        ocb.l[0] = double(&ocb.l_dollar);
+        for i in 1..NUM_L {
+            ocb.l[i] = double(&ocb.l[i - 1]);
+        }
+        l_star.zeroize();
+
+        ocb
+    }
+}
+
+/// The implementation of AES-128-OCB3, with 128-bit tags and 96-bit nonces (RFC 7253)
+///
+/// OCB isn't one of the AEADs in RFC 9180, so its ID is from the private-use end of the ID space
+/// and isn't registered with IANA. Other HPKE implementations will only interoperate if they use
+/// the same ID for it.
+pub struct AesOcb128;
+
+impl Aead for AesOcb128 {
+    type AeadImpl = Ocb3<aes::Aes128>;
+
+    // Private use. Not in the IANA HPKE AEAD registry.
+    const AEAD_ID: u16 = 0xFF01;
+}
+
+/// The implementation of AES-256-OCB3, with 128-bit tags and 96-bit nonces (RFC 7253). See
+/// `AesOcb128` about its ID.
+pub struct AesOcb256;
+
+impl Aead for AesOcb256 {
+    type AeadImpl = Ocb3<aes::Aes256>;
+
+    // Private use. Not in the IANA HPKE AEAD registry.
+    const AEAD_ID: u16 = 0xFF02;
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod test {
+    use super::Ocb3;
+    use crate::Vec;
+
+    use aead::{AeadInPlace, NewAead};
+    use generic_array::GenericArray;
+    use hex_literal::hex;
+
+    // RFC 7253's OCB-ENCRYPT(K, N, A, P), which returns C || T
+    fn ocb_encrypt<C>(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8>
+    where
+        Ocb3<C>: NewAead + AeadInPlace,
+    {
+        let ocb = Ocb3::<C>::new(GenericArray::from_slice(key));
+        let mut buf = plaintext.to_vec();
+        let tag = ocb
+            .encrypt_in_place_detached(GenericArray::from_slice(nonce), aad, &mut buf)
+            .unwrap();
+        buf.extend_from_slice(&tag);
+        buf
+    }
+
+    /// Tests against the AES-128 sample results in RFC 7253 Appendix A, and checks that they
+    /// decrypt, and fail to decrypt when tampered with
+    #[test]
+    fn test_rfc7253_samples() {
+        let key = hex!("000102030405060708090A0B0C0D0E0F");
+        let data: Vec<u8> = (0u8..40).collect();
+        // (nonce, A length, P length, C || T)
+        let samples: [(&[u8], usize, usize, &[u8]); 4] = [
+            (
+                &hex!("BBAA99887766554433221100"),
+                0,
+                0,
+                &hex!("785407BFFFC8AD9EDCC5520AC9111EE6"),
+            ),
+            (
+                &hex!("BBAA99887766554433221101"),
+                8,
+                8,
+                &hex!("6820B3657B6F615A5725BDA0D3B4EB3A257C9AF1F8F03009"),
+            ),
+            (
+                &hex!("BBAA99887766554433221102"),
+                8,
+                0,
+                &hex!("81017F8203F081277152FADE694A0A00"),
+            ),
+            (
+                &hex!("BBAA99887766554433221103"),
+                0,
+                8,
+                &hex!("45DD69F8F5AAE72414054CD1F35D82760B2CD00D2F99BFA9"),
+            ),
+        ];
+
+        for (nonce, aad_len, pt_len, expected) in samples {
+            let (aad, plaintext) = (&data[..aad_len], &data[..pt_len]);
+            let ciphertext = ocb_encrypt::<aes::Aes128>(&key, nonce, aad, plaintext);
+            assert_eq!(ciphertext, expected);
+
+            let ocb = Ocb3::<aes::Aes128>::new(GenericArray::from_slice(&key));
+            let (ct, tag) = ciphertext.split_at(pt_len);
+            let mut buf = ct.to_vec();
+            ocb.decrypt_in_place_detached(
+                GenericArray::from_slice(nonce),
+                aad,
+                &mut buf,
+                GenericArray::from_slice(tag),
+            )
+            .unwrap();
+            assert_eq!(buf, plaintext);
+
+            let mut bad_tag = tag.to_vec();
+            bad_tag[0] ^= 1;
+            let mut buf = ct.to_vec();
+            assert!(ocb
+                .decrypt_in_place_detached(
+                    GenericArray::from_slice(nonce),
+                    aad,
+                    &mut buf,
+                    GenericArray::from_slice(&bad_tag),
+                )
+                .is_err());
+        }
+    }
+
+    // The iterated test in RFC 7253 Appendix A. It covers every message and AAD length from 0 to
+    // 127 bytes.
+    fn iterated_test<C>(key: &[u8]) -> Vec<u8>
+    where
+        Ocb3<C>: NewAead + AeadInPlace,
+    {
+        let num2str = |i: u64| {
+            let mut n = [0u8; 12];
+            n[4..].copy_from_slice(&i.to_be_bytes());
+            n
+        };
+
+        let mut c = Vec::new();
+        for i in 0..128u64 {
+            let s = [0u8; 128];
+            let s = &s[..i as usize];
+            c.extend(ocb_encrypt::<C>(key, &num2str(3 * i + 1), s, s));
+            c.extend(ocb_encrypt::<C>(key, &num2str(3 * i + 2), &[], s));
+            c.extend(ocb_encrypt::<C>(key, &num2str(3 * i + 3), s, &[]));
+        }
+        ocb_encrypt::<C>(key, &num2str(385), &c, &[])
+    }
+
+    /// Tests against the results of the iterated test in RFC 7253 Appendix A, for AES-128 and
+    /// AES-256 with 128-bit tags
+    #[test]
+    fn test_rfc7253_iterated() {
+        let mut key128 = [0u8; 16];
+        key128[15] = 128;
+        assert_eq!(
+            iterated_test::<aes::Aes128>(&key128),
+            hex!("67E944D23256C5E0B6C61FA22FDF1EA2")
+        );
+
+        let mut key256 = [0u8; 32];
+        key256[31] = 128;
+        assert_eq!(
+            iterated_test::<aes::Aes256>(&key256),
+            hex!("D90EB8E9C977C88B79DD793D7FFA161C")
+        );
+    }
+
+    /// Tests that the AES ciphers have no drop glue, so dropping an `Ocb3` zeroizes their round
+    /// keys
+    #[test]
+    fn test_cipher_zeroized_on_drop() {
+        assert!(!core::mem::needs_drop::<aes::Aes128>());
+        assert!(!core::mem::needs_drop::<aes::Aes256>());
+    }
+}
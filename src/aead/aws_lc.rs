@@ -0,0 +1,74 @@
+//! AES-GCM backed by aws-lc-rs. With the `aws-lc` feature, `AesGcm128` and `AesGcm256` use these
+//! in place of the RustCrypto `aes-gcm` crate.
+
+use std::sync::Arc;
+
+use aead::{
+    consts::{U0, U12, U16, U32},
+    AeadCore, AeadInPlace, Key, NewAead, Nonce, Tag,
+};
+use aws_lc_rs::aead::{Aad, LessSafeKey, UnboundKey, AES_128_GCM, AES_256_GCM};
+
+// Defines an AES-GCM type over aws-lc-rs with the given key size and algorithm. The key is behind
+// an Arc because aws-lc-rs keys aren't Clone, and the Aead trait needs them to be.
+macro_rules! impl_aws_lc_aes_gcm {
+    ($name:ident, $key_size:ty, $alg:expr) => {
+        #[doc(hidden)]
+        #[derive(Clone)]
+        pub struct $name(Arc<LessSafeKey>);
+
+        impl NewAead for $name {
+            type KeySize = $key_size;
+
+            fn new(key: &Key<Self>) -> Self {
+                // The key is the right length by construction, so this can't fail
+                let key = UnboundKey::new(&$alg, key.as_slice()).expect("AES-GCM key length");
+                $name(Arc::new(LessSafeKey::new(key)))
+            }
+        }
+
+        impl AeadCore for $name {
+            type NonceSize = U12;
+            type TagSize = U16;
+            type CiphertextOverhead = U0;
+        }
+
+        impl AeadInPlace for $name {
+            fn encrypt_in_place_detached(
+                &self,
+                nonce: &Nonce<Self>,
+                associated_data: &[u8],
+                buffer: &mut [u8],
+            ) -> Result<Tag<Self>, aead::Error> {
+                let nonce = aws_lc_rs::aead::Nonce::assume_unique_for_key((*nonce).into());
+                let tag = self
+                    .0
+                    .seal_in_place_separate_tag(nonce, Aad::from(associated_data), buffer)
+                    .map_err(|_| aead::Error)?;
+                Ok(Tag::<Self>::clone_from_slice(tag.as_ref()))
+            }
+
+            fn decrypt_in_place_detached(
+                &self,
+                nonce: &Nonce<Self>,
+                associated_data: &[u8],
+                buffer: &mut [u8],
+                tag: &Tag<Self>,
+            ) -> Result<(), aead::Error> {
+                let nonce = aws_lc_rs::aead::Nonce::assume_unique_for_key((*nonce).into());
+                self.0
+                    .open_in_place_separate_tag(
+                        nonce,
+                        Aad::from(associated_data),
+                        tag.as_slice(),
+                        buffer,
+                    )
+                    .map(|_| ())
+                    .map_err(|_| aead::Error)
+            }
+        }
+    };
+}
+
+impl_aws_lc_aes_gcm!(AwsLcAes128Gcm, U16, AES_128_GCM);
+impl_aws_lc_aes_gcm!(AwsLcAes256Gcm, U32, AES_256_GCM);
@@ -9,3 +9,33 @@ impl Aead for ChaCha20Poly1305 {
     // RFC 9180 §7.3: ChaCha20Poly1305
     const AEAD_ID: u16 = 0x0003;
 }
+
+/// The implementation of ChaCha12-Poly1305, i.e., ChaCha20-Poly1305 with 12 rounds instead of 20.
+/// This is faster, but it isn't in RFC 9180, and has less security margin. Only use it on links
+/// where both ends are yours.
+///
+/// Its ID is from the private-use end of the ID space, and isn't registered with IANA, so other
+/// HPKE implementations won't recognize it.
+#[cfg(feature = "reduced-round")]
+pub struct ChaCha12Poly1305;
+
+#[cfg(feature = "reduced-round")]
+impl Aead for ChaCha12Poly1305 {
+    type AeadImpl = chacha20poly1305::ChaCha12Poly1305;
+
+    // Private use. Not in the IANA HPKE AEAD registry.
+    const AEAD_ID: u16 = 0xFF03;
+}
+
+/// The implementation of ChaCha8-Poly1305, i.e., ChaCha20-Poly1305 with 8 rounds instead of 20.
+/// See `ChaCha12Poly1305` for the caveats, which apply more so here.
+#[cfg(feature = "reduced-round")]
+pub struct ChaCha8Poly1305;
+
+#[cfg(feature = "reduced-round")]
+impl Aead for ChaCha8Poly1305 {
+    type AeadImpl = chacha20poly1305::ChaCha8Poly1305;
+
+    // Private use. Not in the IANA HPKE AEAD registry.
+    const AEAD_ID: u16 = 0xFF04;
+}
@@ -0,0 +1,167 @@
+use crate::{
+    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS},
+    kdf::{Kdf as KdfTrait, LabeledExpand, SimpleHkdf},
+    kem::Kem as KemTrait,
+    setup::ExporterSecret,
+    util::FullSuiteId,
+};
+
+use std::io;
+
+// The reader's output is a counter-mode expansion of one export, with label the caller's label:
+//   stream_key = Export(concat("exp_stream", label), Nh)
+//   block_i = LabeledExpand(stream_key, "block", I2OSP(i, 8), Nh)
+//   output = block_0 || block_1 || ...
+// The exporter context prefix is prefix-free with the typed export and fork labels in aead.rs.
+const STREAM_EXPORT_LABEL: &[u8] = b"exp_stream";
+const STREAM_BLOCK_LABEL: &[u8] = b"block";
+
+/// An endless stream of pseudorandom bytes exported from an HPKE context, e.g., for seeding a
+/// DRBG. Make one with `AeadCtxS::exporter_reader` or `AeadCtxR::exporter_reader`. Both sides
+/// read the same stream for the same label. Unlike `export`, there's no limit on how much can be
+/// read. Reading `n` bytes at a time or all at once gives the same bytes.
+///
+/// The reader holds its own key, derived from the context's exporter secret and the label, and
+/// zeroizes it and any buffered output on drop. It doesn't borrow the context, which can keep
+/// being used alongside it.
+pub struct ExporterReader<Kdf: KdfTrait> {
+    /// The key that the blocks are expanded from
+    stream_key: ExporterSecret<Kdf>,
+    /// The full suite ID of the context this came from
+    suite_id: FullSuiteId,
+    /// The index of the next block to expand, or `None` once every block has been expanded
+    next_block: Option<u64>,
+    /// The current block
+    block: ExporterSecret<Kdf>,
+    /// How many bytes of `block` have been read
+    pos: usize,
+}
+
+impl<Kdf: KdfTrait> ExporterReader<Kdf> {
+    fn new<A: Aead, Kem: KemTrait>(ctx: &AeadCtx<A, Kdf, Kem>, label: &[u8]) -> Self {
+        let mut stream_key = <ExporterSecret<Kdf> as Default>::default();
+        ctx.export_multi(&[STREAM_EXPORT_LABEL, label], stream_key.0.as_mut_slice());
+
+        let block = <ExporterSecret<Kdf> as Default>::default();
+        let pos = block.0.len();
+        ExporterReader {
+            stream_key,
+            suite_id: ctx.suite_id,
+            next_block: Some(0),
+            block,
+            pos,
+        }
+    }
+
+    // Expands the next block into self.block. Returns false if there are no blocks left.
+    fn refill(&mut self) -> bool {
+        let i = match self.next_block {
+            Some(i) => i,
+            None => return false,
+        };
+        // The key is a full digest, and the block is one digest long, so this can't fail
+        SimpleHkdf::<Kdf>::from_prk(self.stream_key.0.as_slice())
+            .unwrap()
+            .labeled_expand(
+                &self.suite_id,
+                STREAM_BLOCK_LABEL,
+                &i.to_be_bytes(),
+                self.block.0.as_mut_slice(),
+            )
+            .unwrap();
+        self.next_block = i.checked_add(1);
+        self.pos = 0;
+        true
+    }
+}
+
+impl<Kdf: KdfTrait> io::Read for ExporterReader<Kdf> {
+    /// Fills `buf` entirely. This only fails after 2^64 blocks have been read, which doesn't
+    /// happen in practice.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.pos == self.block.0.len() && !self.refill() {
+                if written == 0 {
+                    return Err(io::Error::other("exporter stream exhausted"));
+                }
+                break;
+            }
+            let n = core::cmp::min(buf.len() - written, self.block.0.len() - self.pos);
+            buf[written..written + n].copy_from_slice(&self.block.0[self.pos..self.pos + n]);
+            self.pos += n;
+            written += n;
+        }
+        Ok(written)
+    }
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
+    /// Returns an endless reader of secret bytes derived from this context under `label`. See
+    /// `ExporterReader`. The receiver gets the same bytes from `AeadCtxR::exporter_reader` with
+    /// the same label. Like `export`, this does not depend on the sequence number.
+    pub fn exporter_reader(&self, label: &[u8]) -> ExporterReader<Kdf> {
+        ExporterReader::new(&self.0, label)
+    }
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
+    /// Returns an endless reader of secret bytes derived from this context under `label`. This
+    /// matches the sender's `AeadCtxS::exporter_reader` with the same label.
+    pub fn exporter_reader(&self, label: &[u8]) -> ExporterReader<Kdf> {
+        ExporterReader::new(&self.0, label)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{aead::ChaCha20Poly1305, kdf::HkdfSha256, test_util::gen_ctx_simple_pair};
+
+    use std::io::Read;
+
+    /// Tests that both sides read the same stream, however it's chunked, past the limit of a
+    /// single export, and that the stream depends on the label
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_exporter_reader() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+
+        // More than 255 * Nh, which is as much as export can give at once
+        let mut expected = vec![0u8; 10_000];
+        sender_ctx
+            .exporter_reader(b"drbg seed")
+            .read_exact(&mut expected)
+            .unwrap();
+
+        let mut reader = receiver_ctx.exporter_reader(b"drbg seed");
+        let mut chunked = vec![];
+        for chunk_len in [1usize, 31, 32, 33, 0, 1000].iter().cycle() {
+            if chunked.len() >= expected.len() {
+                break;
+            }
+            let mut chunk = vec![0u8; *chunk_len];
+            reader.read_exact(&mut chunk).unwrap();
+            chunked.extend_from_slice(&chunk);
+        }
+        assert_eq!(chunked[..expected.len()], expected[..]);
+
+        // Blocks don't repeat
+        assert_ne!(expected[..32], expected[32..64]);
+
+        let mut other_label = [0u8; 64];
+        sender_ctx
+            .exporter_reader(b"drbg seeD")
+            .read_exact(&mut other_label)
+            .unwrap();
+        assert_ne!(other_label[..], expected[..64]);
+
+        // The stream isn't the plain export under the same label
+        let mut exported = [0u8; 64];
+        sender_ctx.export(b"drbg seed", &mut exported).unwrap();
+        assert_ne!(exported[..], expected[..64]);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    Deserializable, HpkeError,
+};
+
+use core::marker::PhantomData;
+
+use ::aead::AeadCore as BaseAeadCore;
+use generic_array::typenum::Unsigned;
+use zeroize::Zeroize;
+
+/// Checks at compile time that a ciphertext of `M` bytes holds a plaintext of `N` bytes and a tag
+/// for `A`. Stable Rust can't write `[u8; N + TagSize]` in a signature, so the fixed-size APIs
+/// take both lengths, and evaluating `OK` turns a mismatch into a build error.
+struct FixedLens<A: Aead, const N: usize, const M: usize>(PhantomData<A>);
+
+impl<A: Aead, const N: usize, const M: usize> FixedLens<A, N, M> {
+    const OK: () = assert!(
+        M == N + <<A::AeadImpl as BaseAeadCore>::TagSize as Unsigned>::USIZE,
+        "fixed-size ciphertext length must be the plaintext length plus the AEAD tag length"
+    );
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
+    /// Seals a plaintext of the fixed size `N` into a ciphertext of the fixed size `M`, with the
+    /// tag appended, as `seal` outputs it. This doesn't allocate, and `M` must be `N` plus the
+    /// tag length, which is 16 for every AEAD in this crate. Otherwise, this fails to compile.
+    /// Both lengths are usually inferred, e.g., `let ct: [u8; 48] = ctx.seal_fixed(&pt, aad)?`
+    /// for a 32-byte `pt`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(ciphertext)` on success. Returns the errors `seal_in_place_detached` does. On
+    /// failure, no copy of the plaintext is left behind.
+    pub fn seal_fixed<const N: usize, const M: usize>(
+        &mut self,
+        plaintext: &[u8; N],
+        aad: &[u8],
+    ) -> Result<[u8; M], HpkeError> {
+        let () = FixedLens::<A, N, M>::OK;
+
+        let mut buf = [0u8; M];
+        let (msg, tag_out) = buf.split_at_mut(N);
+        msg.copy_from_slice(plaintext);
+        match self.seal_in_place_detached(msg, aad) {
+            Ok(tag) => {
+                tag_out.copy_from_slice(&tag.0);
+                Ok(buf)
+            }
+            Err(e) => {
+                buf.zeroize();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
+    /// Opens a ciphertext of the fixed size `M`, as `seal` or `seal_fixed` outputs it, into a
+    /// plaintext of the fixed size `N`. This doesn't allocate, and `M` must be `N` plus the tag
+    /// length, or this fails to compile. The lengths are given in the same order as `seal_fixed`'s,
+    /// plaintext first. The plaintext is an ordinary array. If it's sensitive, zeroize it when
+    /// you're done with it.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(plaintext)` on success. Returns the errors `open_in_place_detached` does.
+    pub fn open_fixed<const N: usize, const M: usize>(
+        &mut self,
+        ciphertext: &[u8; M],
+        aad: &[u8],
+    ) -> Result<[u8; N], HpkeError> {
+        let () = FixedLens::<A, N, M>::OK;
+
+        let (msg, tag) = ciphertext.split_at(N);
+        let tag = AeadTag::<A>::from_bytes(tag)?;
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(msg);
+        // This zeroes buf on failure
+        self.open_in_place_detached(&mut buf, aad, &tag)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, test_util::gen_ctx_simple_pair, HpkeError,
+    };
+
+    /// Tests that seal_fixed and open_fixed round-trip, interoperate with seal and open, and
+    /// reject tampered ciphertexts without advancing the receiver
+    #[test]
+    fn test_seal_open_fixed() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let msg = [0x42u8; 32];
+
+        let ciphertext: [u8; 48] = sender_ctx.seal_fixed(&msg, b"aad").unwrap();
+        assert_ne!(ciphertext[..32], msg[..]);
+        let plaintext: [u8; 32] = receiver_ctx.open_fixed(&ciphertext, b"aad").unwrap();
+        assert_eq!(plaintext, msg);
+
+        // A tampered ciphertext fails, and the next real one still opens
+        let ciphertext: [u8; 48] = sender_ctx.seal_fixed(&msg, b"aad").unwrap();
+        let mut tampered = ciphertext;
+        tampered[0] ^= 1;
+        assert_eq!(
+            receiver_ctx.open_fixed::<32, 48>(&tampered, b"aad"),
+            Err(HpkeError::OpenError)
+        );
+        assert_eq!(
+            receiver_ctx.open_fixed::<32, 48>(&ciphertext, b"aad"),
+            Ok(msg)
+        );
+
+        // The format is the same as seal and open
+        #[cfg(feature = "alloc")]
+        {
+            let ciphertext: [u8; 48] = sender_ctx.seal_fixed(&msg, b"aad").unwrap();
+            assert_eq!(receiver_ctx.open(&ciphertext, b"aad").unwrap(), msg);
+
+            let ciphertext = sender_ctx.seal(&msg, b"aad").unwrap();
+            let ciphertext: [u8; 48] = ciphertext.try_into().unwrap();
+            assert_eq!(receiver_ctx.open_fixed(&ciphertext, b"aad"), Ok(msg));
+        }
+
+        // Empty plaintexts work too
+        let ciphertext: [u8; 16] = sender_ctx.seal_fixed(&[], b"aad").unwrap();
+        assert_eq!(receiver_ctx.open_fixed(&ciphertext, b"aad"), Ok([]));
+    }
+}
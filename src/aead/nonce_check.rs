@@ -0,0 +1,123 @@
+//! A process-wide record of the (key, nonce) pairs that sender contexts have sealed with, for
+//! catching nonce reuse while developing. This is gated under the `nonce-reuse-check` feature.
+//!
+//! A single context can't reuse a nonce, since its sequence number only goes up. The way it
+//! happens is two sender contexts with the same key, e.g., a deterministic setup run twice with the
+//! same randomness, or a saved context that's restored and keeps sealing from an old sequence
+//! number. Each of those starts counting over, so nothing inside a context can notice. Recording
+//! every pair in one place can.
+
+use crate::{
+    aead::{Aead, AeadKey, AeadNonce},
+    util::FullSuiteId,
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex, PoisonError},
+};
+
+use sha2::{Digest, Sha256};
+
+/// Identifies a (key, base nonce) pair without storing either one
+pub(crate) type NonceCheckId = [u8; 32];
+
+// Maps each sender context's ID to the sequence numbers it has sealed with
+static SEALED: Mutex<BTreeMap<NonceCheckId, BTreeSet<u64>>> = Mutex::new(BTreeMap::new());
+
+/// Computes the ID of a context from its suite ID, key, and base nonce
+pub(crate) fn nonce_check_id<A: Aead>(
+    suite_id: &FullSuiteId,
+    key: &AeadKey<A>,
+    base_nonce: &AeadNonce<A>,
+) -> NonceCheckId {
+    let mut h = Sha256::new();
+    h.update(b"hpke nonce-reuse-check");
+    h.update(suite_id);
+    h.update(&key.0);
+    h.update(&base_nonce.0);
+    h.finalize().into()
+}
+
+/// Records that the context with the given ID is about to seal with sequence number `seq`
+///
+/// Panics
+/// ======
+/// Panics if any context with this ID has already sealed with `seq`
+pub(crate) fn record_seal(id: &NonceCheckId, seq: u64) {
+    // A panic below doesn't leave the map in a bad state, so don't let it break later checks
+    let fresh = SEALED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(*id)
+        .or_default()
+        .insert(seq);
+    assert!(
+        fresh,
+        "HPKE nonce reuse: sequence number {} was already sealed under this key",
+        seq
+    );
+}
+
+/// Forgets every sequence number the context with the given ID has sealed with. Tests that
+/// compare two contexts by sealing with both use this, since for equal contexts that's a reuse.
+#[cfg(any(test, feature = "hazmat"))]
+pub(crate) fn forget_seals(id: &NonceCheckId) {
+    SEALED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(id);
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::X25519HkdfSha256, setup_sender,
+        setup_sender_deterministic, Kem as KemTrait, OpModeS,
+    };
+
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that two contexts set up from the same randomness can't both seal the same
+    /// sequence number
+    #[test]
+    #[should_panic(expected = "HPKE nonce reuse: sequence number 0")]
+    fn test_nonce_reuse_panics() {
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk_recip) = Kem::gen_keypair(&mut csprng);
+        // Fresh randomness, so no other test shares these contexts
+        let mut ikm = [0u8; 32];
+        csprng.fill_bytes(&mut ikm);
+
+        let (_, mut ctx1) =
+            setup_sender_deterministic::<A, Kdf, Kem>(&OpModeS::Base, &pk_recip, b"", &ikm)
+                .unwrap();
+        let (_, mut ctx2) =
+            setup_sender_deterministic::<A, Kdf, Kem>(&OpModeS::Base, &pk_recip, b"", &ikm)
+                .unwrap();
+
+        // Sealing ahead in one is fine. The other one catching up isn't.
+        ctx1.seal(b"a", b"").unwrap();
+        ctx1.seal(b"b", b"").unwrap();
+        ctx2.seal(b"c", b"").unwrap_err();
+    }
+
+    /// Tests that independent contexts don't interfere
+    #[test]
+    fn test_nonce_distinct_contexts() {
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk_recip) = Kem::gen_keypair(&mut csprng);
+        for _ in 0..2 {
+            let (_, mut ctx) =
+                setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"", &mut csprng)
+                    .unwrap();
+            for _ in 0..3 {
+                ctx.seal(b"msg", b"").unwrap();
+            }
+        }
+    }
+}
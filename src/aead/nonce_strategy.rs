@@ -0,0 +1,292 @@
+#[cfg(feature = "alloc")]
+use crate::{
+    aead::{increment_seq, split_tag, Aead, AeadCtx, AeadCtxR, AeadCtxS, AeadNonce, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    trace, HpkeError, Serializable, Vec,
+};
+
+use rand_core::{CryptoRng, RngCore};
+
+#[cfg(feature = "alloc")]
+use aead::AeadInPlace as BaseAeadInPlace;
+#[cfg(feature = "alloc")]
+use zeroize::Zeroize;
+
+/// How a context picks the nonce for each message. Every nonce is the context's base nonce XORed
+/// with a mask that the strategy picks. RFC 9180 uses the sequence number as the mask, which is
+/// `SequenceNonces`, and is what `seal` and `open` always do.
+///
+/// With an explicit strategy, the mask is sent in front of each ciphertext, and the receiver reads
+/// it from there instead of computing it. That's **not** HPKE, and only peers using this crate's
+/// `seal_with_strategy` and `open_with_strategy` can read it. It's meant for internal links that
+/// lose or reorder messages, where the receiver can't keep its sequence number in step with the
+/// sender's.
+pub trait NonceStrategy {
+    /// Whether the mask is sent in front of each ciphertext. If not, the receiver computes it
+    /// with `nonce_mask`, just as the sender did.
+    const EXPLICIT: bool;
+
+    /// Fills `mask` with the mask for the message with sequence number `seq`. `mask` is as long as
+    /// the AEAD's nonce. The mask must never repeat for a given context.
+    fn nonce_mask(&mut self, seq: u64, mask: &mut [u8]);
+}
+
+/// The RFC 9180 nonce schedule, where the mask is the sequence number, big-endian. Nothing extra
+/// is sent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SequenceNonces;
+
+// RFC 9180 §5.2
+// def Context<ROLE>.ComputeNonce(seq):
+//   seq_bytes = I2OSP(seq, Nn)
+//   return xor(self.base_nonce, seq_bytes)
+
+impl NonceStrategy for SequenceNonces {
+    const EXPLICIT: bool = false;
+
+    fn nonce_mask(&mut self, seq: u64, mask: &mut [u8]) {
+        // Nonces are always at least 8 bytes, so the leading bytes of I2OSP(seq, Nn) are all zero
+        let (zeros, seq_bytes) = mask.split_at_mut(mask.len() - 8);
+        zeros.fill(0);
+        seq_bytes.copy_from_slice(&seq.to_be_bytes());
+    }
+}
+
+/// Uniformly random masks, drawn from the given RNG and sent in front of each ciphertext. This
+/// is an explicit strategy, so it isn't HPKE. See `NonceStrategy`.
+///
+/// With 96-bit nonces, a mask is expected to repeat after about 2^48 messages, and a repeat leaks
+/// the XOR of two plaintexts. Keep each context well under 2^32 messages, as NIST recommends for
+/// random GCM nonces.
+pub struct RandomNonces<R: CryptoRng + RngCore>(pub R);
+
+impl<R: CryptoRng + RngCore> NonceStrategy for RandomNonces<R> {
+    const EXPLICIT: bool = true;
+
+    fn nonce_mask(&mut self, _seq: u64, mask: &mut [u8]) {
+        self.0.fill_bytes(mask);
+    }
+}
+
+/// Returns the base nonce XORed with `mask`
+#[cfg(feature = "alloc")]
+fn masked_nonce<A: Aead>(base_nonce: &AeadNonce<A>, mask: &[u8]) -> AeadNonce<A> {
+    let mut nonce = AeadNonce::<A>(base_nonce.0.clone());
+    for (nonce_byte, mask_byte) in nonce.0.iter_mut().zip(mask.iter()) {
+        *nonce_byte ^= mask_byte;
+    }
+    nonce
+}
+
+#[cfg(feature = "alloc")]
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtx<A, Kdf, Kem> {
+    /// Advances the sequence number after a message is done
+    fn advance_seq(&mut self) {
+        match increment_seq(&self.seq) {
+            Some(new_seq) => self.seq = new_seq,
+            None => self.overflowed = true,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
+    /// Seals the given plaintext with the nonce that `strategy` picks, and returns the ciphertext.
+    /// If the strategy is explicit, the mask comes first, so the output is `mask || ct || tag`,
+    /// where the mask is as long as the AEAD's nonce. Otherwise, it's what `seal` outputs. Open it
+    /// with `AeadCtxR::open_with_strategy`, using the same kind of strategy.
+    ///
+    /// Every call uses up a sequence number, whatever the strategy, so the message limit still
+    /// applies. With `SequenceNonces`, this is exactly `seal`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the errors `seal` does.
+    ///
+    /// Panics
+    /// ======
+    /// With the `nonce-reuse-check` feature, and a strategy that isn't explicit, panics as
+    /// `seal_in_place_detached` does.
+    pub fn seal_with_strategy<N: NonceStrategy>(
+        &mut self,
+        strategy: &mut N,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, HpkeError> {
+        self.0.check_msg_len(plaintext.len())?;
+        if self.0.overflowed {
+            return Err(trace::limit_reached::<A, Kdf, Kem>("sender"));
+        }
+
+        #[cfg(feature = "nonce-reuse-check")]
+        if !N::EXPLICIT {
+            super::nonce_check::record_seal(&self.0.nonce_check_id, self.0.seq.0);
+        }
+
+        let mut mask = AeadNonce::<A>::default();
+        strategy.nonce_mask(self.0.seq.0, &mut mask.0);
+        let nonce = masked_nonce(&self.0.base_nonce, &mask.0);
+
+        let mask_len = if N::EXPLICIT { mask.0.len() } else { 0 };
+        let mut buf = Vec::with_capacity(mask_len + plaintext.len() + AeadTag::<A>::size());
+        buf.extend_from_slice(&mask.0[..mask_len]);
+        buf.extend_from_slice(plaintext);
+        let tag = self
+            .0
+            .encryptor
+            .encrypt_in_place_detached(&nonce.0, aad, &mut buf[mask_len..])
+            .map_err(|_| HpkeError::SealError)?;
+        buf.extend_from_slice(&tag);
+
+        self.0.advance_seq();
+        Ok(buf)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
+    /// Opens a ciphertext made by `AeadCtxS::seal_with_strategy`, and returns the plaintext.
+    /// With `SequenceNonces`, this is exactly `open`.
+    ///
+    /// DANGER
+    /// ======
+    /// If the strategy is explicit, ciphertexts open in any order, and this doesn't look at or
+    /// advance the sequence number. So nothing here stops a ciphertext from being opened twice.
+    /// Callers that care about replays have to catch them themselves, e.g., by remembering the
+    /// masks they've seen.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(plaintext)` on success. If the strategy is explicit and `ciphertext` is too
+    /// short to hold a mask and a tag, returns `Err(HpkeError::OpenError)`. Otherwise, returns
+    /// the errors `open` does.
+    pub fn open_with_strategy<N: NonceStrategy>(
+        &mut self,
+        strategy: &mut N,
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, HpkeError> {
+        let seq = self.0.seq.0;
+        let mut mask = AeadNonce::<A>::default();
+        let ciphertext = if N::EXPLICIT {
+            let mask_len = mask.0.len();
+            if ciphertext.len() < mask_len {
+                return Err(HpkeError::OpenError);
+            }
+            let (wire_mask, rest) = ciphertext.split_at(mask_len);
+            mask.0.copy_from_slice(wire_mask);
+            rest
+        } else {
+            if self.0.overflowed {
+                return Err(trace::limit_reached::<A, Kdf, Kem>("receiver"));
+            }
+            strategy.nonce_mask(seq, &mut mask.0);
+            ciphertext
+        };
+
+        let (ciphertext, tag) = split_tag::<A>(ciphertext)?;
+        self.0.check_msg_len(ciphertext.len())?;
+        let mut buf = ciphertext.to_vec();
+
+        let nonce = masked_nonce(&self.0.base_nonce, &mask.0);
+        if self
+            .0
+            .encryptor
+            .decrypt_in_place_detached(&nonce.0, aad, &mut buf, &tag.0)
+            .is_err()
+        {
+            buf.zeroize();
+            return Err(trace::open_error::<A, Kdf, Kem>(seq, HpkeError::OpenError));
+        }
+
+        if !N::EXPLICIT {
+            self.0.advance_seq();
+        }
+        Ok(buf)
+    }
+}
+
+#[cfg(all(test, feature = "alloc", feature = "x25519"))]
+mod test {
+    use super::{NonceStrategy, RandomNonces, SequenceNonces};
+    use crate::{
+        aead::{AesGcm128, ChaCha20Poly1305},
+        kdf::HkdfSha256,
+        kem::X25519HkdfSha256,
+        test_util::gen_ctx_simple_pair,
+        HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that `SequenceNonces` gives the RFC 9180 nonces, so it interoperates with plain
+    /// `seal` and `open`
+    #[test]
+    fn test_sequence_nonces() {
+        let mut mask = [0xffu8; 12];
+        SequenceNonces.nonce_mask(0x0102, &mut mask);
+        assert_eq!(mask, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2]);
+
+        let (mut sender_ctx, mut receiver_ctx) =
+            gen_ctx_simple_pair::<ChaCha20Poly1305, Kdf, Kem>();
+        let ct0 = sender_ctx
+            .seal_with_strategy(&mut SequenceNonces, b"zero", b"aad")
+            .unwrap();
+        let ct1 = sender_ctx.seal(b"one", b"aad").unwrap();
+        assert_eq!(receiver_ctx.open(&ct0, b"aad").unwrap(), b"zero");
+        assert_eq!(
+            receiver_ctx
+                .open_with_strategy(&mut SequenceNonces, &ct1, b"aad")
+                .unwrap(),
+            b"one"
+        );
+        assert_eq!(receiver_ctx.seq(), 2);
+    }
+
+    /// Tests that random nonces are carried on the wire, open in any order, and are still
+    /// authenticated
+    #[test]
+    fn test_random_nonces() {
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<AesGcm128, Kdf, Kem>();
+        let mut strategy = RandomNonces(StdRng::from_entropy());
+
+        let cts: [_; 3] = core::array::from_fn(|i| {
+            sender_ctx
+                .seal_with_strategy(&mut strategy, &[i as u8; 5], b"aad")
+                .unwrap()
+        });
+        assert_eq!(sender_ctx.seq(), 3);
+        // Mask, then ciphertext, then tag
+        assert_eq!(cts[0].len(), 12 + 5 + 16);
+        assert_ne!(cts[0][..12], cts[1][..12]);
+
+        for i in [2, 0, 1] {
+            assert_eq!(
+                receiver_ctx
+                    .open_with_strategy(&mut strategy, &cts[i], b"aad")
+                    .unwrap(),
+                [i as u8; 5]
+            );
+        }
+        assert_eq!(receiver_ctx.seq(), 0);
+
+        // A tampered mask doesn't open, and neither does something too short to hold one
+        let mut bad = cts[0].clone();
+        bad[0] ^= 1;
+        for ct in [&bad[..], &cts[0][..11]] {
+            assert_eq!(
+                receiver_ctx.open_with_strategy(&mut strategy, ct, b"aad"),
+                Err(HpkeError::OpenError)
+            );
+        }
+        // An explicit ciphertext isn't a plain one
+        assert_eq!(
+            receiver_ctx.open(&cts[0], b"aad"),
+            Err(HpkeError::OpenError)
+        );
+    }
+}
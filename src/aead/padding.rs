@@ -0,0 +1,154 @@
+use crate::HpkeError;
+
+#[cfg(feature = "alloc")]
+use crate::Vec;
+
+/// How `AeadCtxS::seal_padded` rounds up plaintext lengths, so ciphertext lengths say less about
+/// what's inside. Set one on a sender context with `set_padding_policy`. The receiver doesn't
+/// need to know it, since `AeadCtxR::open_padded` undoes any of them.
+///
+/// A padded plaintext is the plaintext, then a `0x80` byte, then zero or more `0x00` bytes, so
+/// every plaintext, including the empty one, is padded by at least one byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PaddingPolicy {
+    /// Padmé, from "Reducing Metadata Leakage from Encrypted Files and Communication with
+    /// PURBs" (Nikitin et al., PETS 2019). This rounds up to a length whose low bits are zero,
+    /// with at most 12% overhead, and leaks only `O(log log L)` bits of the length `L`. This is
+    /// the default.
+    #[default]
+    Padme,
+    /// Rounds up to a multiple of the given block size. A block size of 0 or 1 only adds the
+    /// `0x80` marker.
+    Block(usize),
+    /// Pads every message to exactly the given length, so all ciphertexts are the same size.
+    /// Plaintexts of this length or longer can't be sealed.
+    Fixed(usize),
+}
+
+impl PaddingPolicy {
+    /// Returns the padded length of a plaintext of length `len`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::MessageTooLarge)` if the policy is `Fixed(n)` and `len` is `n` or
+    /// more, or if the padded length doesn't fit in a `usize`.
+    pub fn padded_len(&self, len: usize) -> Result<usize, HpkeError> {
+        // Make room for the 0x80 marker
+        let min_len = len
+            .checked_add(1)
+            .ok_or(HpkeError::MessageTooLarge(usize::MAX - 1, len))?;
+
+        match *self {
+            PaddingPolicy::Padme => {
+                padme(min_len).ok_or(HpkeError::MessageTooLarge(usize::MAX - 1, len))
+            }
+            PaddingPolicy::Block(block_size) => {
+                let block_size = block_size.max(1);
+                min_len
+                    .checked_next_multiple_of(block_size)
+                    .ok_or(HpkeError::MessageTooLarge(usize::MAX - block_size, len))
+            }
+            PaddingPolicy::Fixed(n) if min_len <= n => Ok(n),
+            PaddingPolicy::Fixed(n) => Err(HpkeError::MessageTooLarge(n.saturating_sub(1), len)),
+        }
+    }
+}
+
+// The Padmé length of l: keep the top s = floor(log2(e)) + 1 bits of l, where e = floor(log2(l)),
+// and round the rest up. Lengths under 2 are left alone. Returns None if rounding up overflows.
+fn padme(l: usize) -> Option<usize> {
+    if l < 2 {
+        return Some(l);
+    }
+    let e = l.ilog2();
+    let s = e.ilog2() + 1;
+    let mask = (1usize << (e - s)) - 1;
+    l.checked_add(mask).map(|rounded| rounded & !mask)
+}
+
+/// Returns `plaintext`, padded per `policy`, in a buffer with room for `extra` more bytes
+#[cfg(feature = "alloc")]
+pub(crate) fn pad(
+    policy: &PaddingPolicy,
+    plaintext: &[u8],
+    extra: usize,
+) -> Result<Vec<u8>, HpkeError> {
+    let padded_len = policy.padded_len(plaintext.len())?;
+    let mut padded = Vec::with_capacity(padded_len.saturating_add(extra));
+    padded.extend_from_slice(plaintext);
+    padded.push(0x80);
+    padded.resize(padded_len, 0x00);
+    Ok(padded)
+}
+
+/// Returns the length of the plaintext inside `padded`. This works under every policy.
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::OpenError)` if `padded` isn't plaintext || 0x80 || 0x00*
+#[cfg(feature = "alloc")]
+pub(crate) fn unpadded_len(padded: &[u8]) -> Result<usize, HpkeError> {
+    // Look for the last nonzero byte. It must be the 0x80 marker.
+    match padded.iter().rposition(|&b| b != 0x00) {
+        Some(i) if padded[i] == 0x80 => Ok(i),
+        _ => Err(HpkeError::OpenError),
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod test {
+    use super::{padme, unpadded_len, PaddingPolicy};
+    use crate::HpkeError;
+
+    /// Tests Padmé against values from the paper's definition, and that it's monotone with at
+    /// most 12% overhead
+    #[test]
+    fn test_padme() {
+        for (l, expected) in [
+            (0, 0),
+            (1, 1),
+            (2, 2),
+            (3, 3),
+            (9, 10),
+            (100, 104),
+            (1000, 1024),
+            (1025, 1088),
+            (65536, 65536),
+            (65537, 67584),
+        ] {
+            assert_eq!(padme(l), Some(expected), "padme({})", l);
+        }
+
+        let mut prev = 0;
+        for l in 1..5000usize {
+            let p = padme(l).unwrap();
+            assert!(p >= l && p >= prev);
+            assert!((p - l) * 100 <= l * 12);
+            prev = p;
+        }
+    }
+
+    /// Tests padded lengths for each policy, and the unpadding check
+    #[test]
+    fn test_padding_policies() {
+        assert_eq!(PaddingPolicy::default(), PaddingPolicy::Padme);
+        assert_eq!(PaddingPolicy::Padme.padded_len(0), Ok(1));
+        assert_eq!(PaddingPolicy::Block(16).padded_len(0), Ok(16));
+        assert_eq!(PaddingPolicy::Block(16).padded_len(15), Ok(16));
+        assert_eq!(PaddingPolicy::Block(16).padded_len(16), Ok(32));
+        assert_eq!(PaddingPolicy::Block(0).padded_len(5), Ok(6));
+        assert_eq!(PaddingPolicy::Fixed(64).padded_len(63), Ok(64));
+        assert_eq!(
+            PaddingPolicy::Fixed(64).padded_len(64),
+            Err(HpkeError::MessageTooLarge(63, 64))
+        );
+        assert!(PaddingPolicy::Block(16).padded_len(usize::MAX).is_err());
+        assert!(PaddingPolicy::Padme.padded_len(usize::MAX - 2).is_err());
+
+        assert_eq!(unpadded_len(b"ab\x80\x00\x00"), Ok(2));
+        assert_eq!(unpadded_len(b"\x80"), Ok(0));
+        assert_eq!(unpadded_len(b"ab\x00"), Err(HpkeError::OpenError));
+        assert_eq!(unpadded_len(b"ab\x81\x00"), Err(HpkeError::OpenError));
+        assert_eq!(unpadded_len(b""), Err(HpkeError::OpenError));
+    }
+}
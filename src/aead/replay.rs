@@ -0,0 +1,145 @@
+use crate::HpkeError;
+
+/// The largest replay window `AeadCtxR::set_replay_window` accepts
+pub const MAX_REPLAY_WINDOW: usize = 1024;
+
+/// The replay window `AeadCtxR` starts with
+pub(crate) const DEFAULT_REPLAY_WINDOW: usize = 64;
+
+const NUM_WORDS: usize = MAX_REPLAY_WINDOW / 64;
+
+/// A sliding window of recently opened sequence numbers, as in IPsec (RFC 4303 §3.4.3). It
+/// remembers the highest sequence number opened, and which of the `depth` below it were opened.
+#[derive(Clone)]
+pub(crate) struct ReplayWindow {
+    /// 1 + the highest sequence number recorded, or 0 if none has been
+    top: u64,
+    /// Bit `i` is set iff sequence number `top - 1 - i` was recorded. Only the first `depth` bits
+    /// are meaningful.
+    bits: [u64; NUM_WORDS],
+    /// How far behind the highest sequence number an unrecorded one can be and still be accepted
+    depth: usize,
+}
+
+impl Default for ReplayWindow {
+    fn default() -> ReplayWindow {
+        ReplayWindow::new(DEFAULT_REPLAY_WINDOW)
+    }
+}
+
+impl ReplayWindow {
+    /// Makes an empty window. `depth` must be in `1..=MAX_REPLAY_WINDOW`.
+    pub(crate) fn new(depth: usize) -> ReplayWindow {
+        debug_assert!(depth > 0 && depth <= MAX_REPLAY_WINDOW);
+        ReplayWindow {
+            top: 0,
+            bits: [0u64; NUM_WORDS],
+            depth,
+        }
+    }
+
+    /// Returns `Ok(())` if `seq` has not been recorded and is within the window. Otherwise
+    /// returns `Err(HpkeError::ReplayedMessage)`.
+    pub(crate) fn check(&self, seq: u64) -> Result<(), HpkeError> {
+        // Anything above the highest sequence number is new. Otherwise, it must be in the window
+        // and not yet recorded.
+        match self.top.checked_sub(1 + seq) {
+            None => Ok(()),
+            Some(diff) if diff >= self.depth as u64 => Err(HpkeError::ReplayedMessage),
+            Some(diff) if self.bit(diff as usize) => Err(HpkeError::ReplayedMessage),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Records `seq` as opened. `seq` must have passed `check`, and must be less than `u64::MAX`.
+    pub(crate) fn record(&mut self, seq: u64) {
+        let marker = seq + 1;
+        if marker > self.top {
+            // Slide the window up so that seq is at the top
+            self.shift(marker - self.top);
+            self.top = marker;
+            self.set_bit(0);
+        } else {
+            self.set_bit((self.top - marker) as usize);
+        }
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set_bit(&mut self, i: usize) {
+        self.bits[i / 64] |= 1 << (i % 64);
+    }
+
+    /// Moves every bit `k` places higher, dropping the ones that fall off the end
+    fn shift(&mut self, k: u64) {
+        if k >= MAX_REPLAY_WINDOW as u64 {
+            self.bits = [0u64; NUM_WORDS];
+            return;
+        }
+        let (word_shift, bit_shift) = ((k / 64) as usize, (k % 64) as u32);
+        for i in (0..NUM_WORDS).rev() {
+            let lo = i.checked_sub(word_shift).map_or(0, |j| self.bits[j]);
+            let carry = match i.checked_sub(word_shift + 1) {
+                Some(j) if bit_shift > 0 => self.bits[j] >> (64 - bit_shift),
+                _ => 0,
+            };
+            self.bits[i] = (lo << bit_shift) | carry;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ReplayWindow, MAX_REPLAY_WINDOW};
+    use crate::HpkeError;
+
+    /// Tests that the window accepts each sequence number once, in any order within the depth,
+    /// and rejects ones that fell out of it
+    #[test]
+    fn test_replay_window() {
+        for depth in [1, 3, 64, 100, MAX_REPLAY_WINDOW] {
+            let mut window = ReplayWindow::new(depth);
+            let open = |window: &mut ReplayWindow, seq| {
+                window.check(seq)?;
+                window.record(seq);
+                Ok::<(), HpkeError>(())
+            };
+
+            // In order, with a gap
+            open(&mut window, 0).unwrap();
+            open(&mut window, 1).unwrap();
+            open(&mut window, 5).unwrap();
+            assert_eq!(open(&mut window, 1), Err(HpkeError::ReplayedMessage));
+            assert_eq!(open(&mut window, 5), Err(HpkeError::ReplayedMessage));
+
+            // Jump far ahead, then fill in behind, up to the depth
+            let top = 10_000u64;
+            open(&mut window, top).unwrap();
+            for back in 1..depth as u64 {
+                open(&mut window, top - back).unwrap();
+                assert_eq!(
+                    open(&mut window, top - back),
+                    Err(HpkeError::ReplayedMessage)
+                );
+            }
+            assert_eq!(
+                open(&mut window, top - depth as u64),
+                Err(HpkeError::ReplayedMessage)
+            );
+
+            // Sliding up keeps what was recorded, as long as it's still in the window. Everything
+            // from top - depth + 1 to top was recorded above.
+            open(&mut window, top + 70).unwrap();
+            for seq in (top + 71 - depth as u64)..=(top + 70) {
+                let recorded = seq <= top || seq == top + 70;
+                assert_eq!(
+                    window.check(seq).is_err(),
+                    recorded,
+                    "depth {depth} seq {seq}"
+                );
+            }
+        }
+    }
+}
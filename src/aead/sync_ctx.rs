@@ -0,0 +1,212 @@
+use crate::{
+    aead::{Aead, AeadCtx, AeadCtxR, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    HpkeError,
+};
+
+#[cfg(feature = "alloc")]
+use crate::{aead::split_tag, Vec};
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use zeroize::Zeroize;
+
+/// A receiver context that opens ciphertexts by explicit sequence number, through a shared
+/// reference. It is `Sync`, so many threads can open with it at once, e.g., behind an `Arc`.
+/// Make one with `AeadCtxR::into_sync`.
+///
+/// Replays are caught with a window of `W` slots. Sequence number `seq` goes in slot
+/// `seq % W`, and each slot remembers the highest sequence number opened in it. So:
+///
+/// * Every sequence number is opened at most once. Opening it again returns
+///   `Err(HpkeError::ReplayedMessage)`.
+/// * Ciphertexts can arrive in any order, as long as they're less than `W` apart. A sequence
+///   number that's `W` or more behind one already opened in its slot is also rejected with
+///   `Err(HpkeError::ReplayedMessage)`, since there's no longer a record of whether it was opened.
+///
+/// A slot is only updated after a ciphertext authenticates, so forgeries can't use up sequence
+/// numbers. None of this takes a lock.
+pub struct SyncAeadCtxR<A: Aead, Kdf: KdfTrait, Kem: KemTrait, const W: usize = 64> {
+    ctx: AeadCtx<A, Kdf, Kem>,
+    /// Slot `i` holds 1 + the highest sequence number `≡ i mod W` opened so far, or 0 if none
+    slots: [AtomicU64; W],
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
+    /// Converts this context into one that opens by explicit sequence number, through a shared
+    /// reference. See `SyncAeadCtxR`. The window size `W` is usually inferred, and defaults to 64.
+    /// The new context doesn't know which sequence numbers this one has opened, but it keeps this
+    /// context's size limit, if any. See `set_max_message_len`.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `W` is 0.
+    pub fn into_sync<const W: usize>(self) -> SyncAeadCtxR<A, Kdf, Kem, W> {
+        assert!(W > 0, "replay window must have at least one slot");
+        SyncAeadCtxR {
+            ctx: self.0,
+            slots: core::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait, const W: usize> SyncAeadCtxR<A, Kdf, Kem, W> {
+    /// Does a "detached open in place" of the ciphertext that was sealed with sequence number
+    /// `seq`, i.e., the `seq`-th ciphertext the sender sealed, counting from 0. This overwrites
+    /// `ciphertext` with the resulting plaintext, and takes the tag as a separate input.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If `seq` was already opened or is too old (see
+    /// `SyncAeadCtxR`), returns `Err(HpkeError::ReplayedMessage)`. If `seq` is `u64::MAX`,
+    /// returns `Err(HpkeError::MessageLimitReached)`. In both cases, `ciphertext` is zeroed or
+    /// unmodified. If the tag fails to validate, returns `Err(HpkeError::OpenError)`. If this
+    /// happens, `ciphertext` is zeroed.
+    pub fn open_in_place_detached_at(
+        &self,
+        seq: u64,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+    ) -> Result<(), HpkeError> {
+        // Slots store seq + 1, so that 0 can mean empty. The last sequence number has no room
+        // for that, but AeadCtxR can't go past it either.
+        let marker = seq.checked_add(1).ok_or(HpkeError::MessageLimitReached)?;
+        let slot = &self.slots[(seq % W as u64) as usize];
+
+        // Check for a replay before doing any work. This is checked again below, since another
+        // thread may open the same seq in the meantime.
+        if slot.load(Ordering::Acquire) >= marker {
+            return Err(HpkeError::ReplayedMessage);
+        }
+
+        self.ctx
+            .open_in_place_detached_at_seq(seq, ciphertext, aad, tag)?;
+
+        // The ciphertext is authentic. Record it, unless someone beat us to it.
+        if slot.fetch_max(marker, Ordering::AcqRel) >= marker {
+            ciphertext.zeroize();
+            return Err(HpkeError::ReplayedMessage);
+        }
+
+        Ok(())
+    }
+
+    /// Opens the ciphertext that was sealed with sequence number `seq`, and returns a plaintext.
+    /// See `open_in_place_detached_at`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the plaintext on success. If `seq` was already opened or is too old, returns
+    /// `Err(HpkeError::ReplayedMessage)`. If `seq` is `u64::MAX`, returns
+    /// `Err(HpkeError::MessageLimitReached)`. If the tag fails to validate, returns
+    /// `Err(HpkeError::OpenError)`.
+    #[cfg(feature = "alloc")]
+    pub fn open_at(&self, seq: u64, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        let (ciphertext, tag) = split_tag::<A>(ciphertext)?;
+        self.ctx.check_msg_len(ciphertext.len())?;
+        let mut buf = ciphertext.to_vec();
+
+        // Decrypt and return the decrypted buffer
+        self.open_in_place_detached_at(seq, &mut buf, aad, &tag)?;
+        Ok(buf)
+    }
+
+    /// Fills a given buffer with secret bytes derived from this encryption context. This is the
+    /// same as `AeadCtxR::export`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If the buffer length is more than 255x the digest size of the
+    /// underlying hash function, returns an `Err(HpkeError::KdfOutputTooLong)`.
+    pub fn export(&self, info: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
+        self.ctx.export(info, out_buf)
+    }
+}
+
+#[cfg(all(test, feature = "alloc", feature = "x25519"))]
+mod test {
+    use super::SyncAeadCtxR;
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::X25519HkdfSha256,
+        test_util::gen_ctx_simple_pair, HpkeError,
+    };
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that out-of-order opens work, and that replays, stale sequence numbers, and bad seqs
+    /// are rejected
+    #[test]
+    fn test_open_at() {
+        let (mut sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let receiver_ctx: SyncAeadCtxR<A, Kdf, Kem, 4> = receiver_ctx.into_sync();
+
+        let aad = b"datagram";
+        let ciphertexts: crate::Vec<_> = (0u8..10)
+            .map(|i| sender_ctx.seal(&[i], aad).unwrap())
+            .collect();
+
+        // Out of order is fine
+        assert_eq!(receiver_ctx.open_at(2, &ciphertexts[2], aad).unwrap(), [2]);
+        assert_eq!(receiver_ctx.open_at(0, &ciphertexts[0], aad).unwrap(), [0]);
+        assert_eq!(receiver_ctx.open_at(3, &ciphertexts[3], aad).unwrap(), [3]);
+
+        // Replays aren't
+        assert_eq!(
+            receiver_ctx.open_at(2, &ciphertexts[2], aad),
+            Err(HpkeError::ReplayedMessage)
+        );
+
+        // The wrong seq doesn't authenticate, and doesn't use up that seq
+        assert_eq!(
+            receiver_ctx.open_at(1, &ciphertexts[5], aad),
+            Err(HpkeError::OpenError)
+        );
+        assert_eq!(receiver_ctx.open_at(1, &ciphertexts[1], aad).unwrap(), [1]);
+
+        // 9 shares a slot with 5 and 1. Once 9 is opened, 5 is too old to open
+        assert_eq!(receiver_ctx.open_at(9, &ciphertexts[9], aad).unwrap(), [9]);
+        assert_eq!(
+            receiver_ctx.open_at(5, &ciphertexts[5], aad),
+            Err(HpkeError::ReplayedMessage)
+        );
+
+        assert_eq!(
+            receiver_ctx.open_at(u64::MAX, &ciphertexts[0], aad),
+            Err(HpkeError::MessageLimitReached)
+        );
+    }
+
+    /// Tests that one context can be shared across threads, and that every seq is opened exactly
+    /// once even when all the threads race to open all of them
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_open_at_threads() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (mut sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let receiver_ctx: SyncAeadCtxR<A, Kdf, Kem> = receiver_ctx.into_sync();
+
+        let ciphertexts: std::vec::Vec<_> = (0u8..32)
+            .map(|i| sender_ctx.seal(&[i], b"").unwrap())
+            .collect();
+        let num_opened = AtomicUsize::new(0);
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for (seq, ciphertext) in ciphertexts.iter().enumerate() {
+                        if receiver_ctx.open_at(seq as u64, ciphertext, b"").is_ok() {
+                            num_opened.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(num_opened.load(Ordering::Relaxed), ciphertexts.len());
+    }
+}
@@ -0,0 +1,166 @@
+//! This module defines `arbitrary::Arbitrary` for keys, encapsulated keys, `Envelope`, and the
+//! op-mode types. This is gated under the `arbitrary` feature.
+//!
+//! Keys and encapsulated keys can't be built from arbitrary bytes, since most byte strings aren't
+//! valid encodings. Instead, they're derived with `Kem::try_derive_keypair` from arbitrary keying
+//! material, so every value is valid. To fuzz the parsers themselves, feed raw bytes to the
+//! functions in `hpke::fuzz`.
+
+use crate::{
+    dhkex,
+    envelope::Envelope,
+    kem::{self, Kem as KemTrait},
+    op_mode::{OpModeR, OpModeS, PskBundle},
+    Deserializable, Serializable,
+};
+
+use arbitrary::{Arbitrary, Error, Result, Unstructured};
+use generic_array::{typenum::Unsigned, GenericArray};
+use zeroize::Zeroize;
+
+// Derives a keypair from arbitrary keying material the size of a private key
+fn arbitrary_keypair<Kem: KemTrait>(
+    u: &mut Unstructured<'_>,
+) -> Result<(Kem::PrivateKey, Kem::PublicKey)> {
+    let mut ikm: GenericArray<u8, <Kem::PrivateKey as Serializable>::OutputSize> =
+        GenericArray::default();
+    u.fill_buffer(&mut ikm)?;
+    let res = Kem::try_derive_keypair(&ikm).map_err(|_| Error::IncorrectFormat);
+    ikm.zeroize();
+    res
+}
+
+// Implements Arbitrary for the private key, public key, and encapped key types of the given KEM.
+// An encapped key is an ephemeral public key, so it's made the same way as a public key.
+macro_rules! impl_arbitrary_kem {
+    ($kem:ty, $sk:ty, $pk:ty, $encapped_key:ty) => {
+        impl<'a> Arbitrary<'a> for $sk {
+            fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+                arbitrary_keypair::<$kem>(u).map(|(sk, _)| sk)
+            }
+
+            fn size_hint(_: usize) -> (usize, Option<usize>) {
+                let n = <<$sk as Serializable>::OutputSize as Unsigned>::to_usize();
+                (n, Some(n))
+            }
+        }
+
+        impl<'a> Arbitrary<'a> for $pk {
+            fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+                arbitrary_keypair::<$kem>(u).map(|(_, pk)| pk)
+            }
+
+            fn size_hint(depth: usize) -> (usize, Option<usize>) {
+                <$sk as Arbitrary>::size_hint(depth)
+            }
+        }
+
+        impl<'a> Arbitrary<'a> for $encapped_key {
+            fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+                let pk = <$pk as Arbitrary>::arbitrary(u)?;
+                <$encapped_key>::from_bytes(&pk.to_bytes()).map_err(|_| Error::IncorrectFormat)
+            }
+
+            fn size_hint(depth: usize) -> (usize, Option<usize>) {
+                <$sk as Arbitrary>::size_hint(depth)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "x25519")]
+impl_arbitrary_kem!(
+    kem::X25519HkdfSha256,
+    dhkex::x25519::PrivateKey,
+    dhkex::x25519::PublicKey,
+    kem::DhEncappedKey<dhkex::X25519>
+);
+
+#[cfg(feature = "p256")]
+impl_arbitrary_kem!(
+    kem::DhP256HkdfSha256,
+    dhkex::ecdh_nistp::PrivateKey,
+    dhkex::ecdh_nistp::PublicKey,
+    kem::DhEncappedKey<dhkex::DhP256>
+);
+
+#[cfg(feature = "k256")]
+impl_arbitrary_kem!(
+    kem::DhK256HkdfSha256,
+    dhkex::ecdh_k256::PrivateKey,
+    dhkex::ecdh_k256::PublicKey,
+    kem::DhEncappedKey<dhkex::DhK256>
+);
+
+impl<'a> Arbitrary<'a> for PskBundle<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(PskBundle {
+            psk: u.arbitrary()?,
+            psk_id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a, Kem> Arbitrary<'a> for OpModeR<'a, Kem>
+where
+    Kem: KemTrait,
+    Kem::PublicKey: Arbitrary<'a>,
+{
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0u8..=3)? {
+            0 => OpModeR::Base,
+            1 => OpModeR::Psk(u.arbitrary()?),
+            2 => OpModeR::Auth(u.arbitrary()?),
+            _ => OpModeR::AuthPsk(u.arbitrary()?, u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a, Kem> Arbitrary<'a> for OpModeS<'a, Kem>
+where
+    Kem: KemTrait,
+    Kem::PrivateKey: Arbitrary<'a>,
+{
+    // The sender's identity keypair is always a matching pair
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        fn keypair<'a, Kem>(u: &mut Unstructured<'a>) -> Result<(Kem::PrivateKey, Kem::PublicKey)>
+        where
+            Kem: KemTrait,
+            Kem::PrivateKey: Arbitrary<'a>,
+        {
+            let sk: Kem::PrivateKey = u.arbitrary()?;
+            let pk = Kem::sk_to_pk(&sk);
+            Ok((sk, pk))
+        }
+
+        Ok(match u.int_in_range(0u8..=3)? {
+            0 => OpModeS::Base,
+            1 => OpModeS::Psk(u.arbitrary()?),
+            2 => OpModeS::Auth(keypair::<Kem>(u)?),
+            _ => OpModeS::AuthPsk(keypair::<Kem>(u)?, u.arbitrary()?),
+        })
+    }
+}
+
+// Envelopes keep the invariant that the encapped key, PSK ID, and AAD header fit in a u16 length
+// prefix
+impl<'a> Arbitrary<'a> for Envelope {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let envelope = Envelope {
+            kem_id: u.arbitrary()?,
+            kdf_id: u.arbitrary()?,
+            aead_id: u.arbitrary()?,
+            encapped_key: u.arbitrary()?,
+            psk_id: u.arbitrary()?,
+            aad_header: u.arbitrary()?,
+            ciphertext: u.arbitrary()?,
+        };
+        if envelope.encapped_key.len() > u16::MAX as usize
+            || envelope.psk_id.as_ref().map_or(0, |id| id.len()) > u16::MAX as usize
+            || envelope.aad_header.as_ref().map_or(0, |h| h.len()) > u16::MAX as usize
+        {
+            return Err(Error::IncorrectFormat);
+        }
+        Ok(envelope)
+    }
+}
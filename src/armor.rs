@@ -0,0 +1,426 @@
+//! PEM-like ASCII armor, so envelopes and keys can travel through email, ticketing systems, and
+//! anything else that mangles binary. This is gated under the `text-encoding` feature.
+//!
+//! Armored data is
+//!
+//! ```text
+//! -----BEGIN <label>-----
+//! (standard, padded base64 of the data, in lines of 64 characters)
+//! -----END <label>-----
+//! ```
+//!
+//! where the label says what the data is: `HPKE MESSAGE` for an
+//! [`Envelope`](crate::envelope::Envelope), `HPKE PUBLIC KEY` for a public key, and
+//! `HPKE ENCRYPTED PRIVATE KEY` or `AGE ENCRYPTED FILE` for a key from the `keystore` module. An
+//! armored public key is `kem_id (2) || pk`, with the KEM ID big-endian, so it can't be mistaken
+//! for a key of another KEM. This is the same armor that age uses.
+//!
+//! There are three ways to dearmor. `dearmor` takes what people paste: it skips whitespace around
+//! the armor and anywhere in the body, and accepts CRLF line endings. `dearmor_strict` takes
+//! exactly what `armor` writes, so every piece of data has one armored form. `Dearmorer` is
+//! `dearmor` for input that arrives in chunks, e.g., from a socket, and decodes as it goes.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{armor, kem::X25519HkdfSha256, Kem, Serializable};
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (_, pk) = X25519HkdfSha256::gen_keypair(&mut csprng);
+//!
+//! let armored = armor::armor_public_key::<X25519HkdfSha256>(&pk);
+//! assert!(armored.starts_with("-----BEGIN HPKE PUBLIC KEY-----\n"));
+//! let pk2 = armor::dearmor_public_key::<X25519HkdfSha256>(&armored).unwrap();
+//! assert_eq!(pk.to_bytes(), pk2.to_bytes());
+//! # }
+//! ```
+
+use crate::{kem::Kem as KemTrait, Deserializable, HpkeError, Serializable, Vec};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::string::String;
+
+use base64ct::{Base64, Encoding};
+use byteorder::{BigEndian, ByteOrder};
+
+/// The label of an armored [`Envelope`](crate::envelope::Envelope)
+pub const MESSAGE_LABEL: &str = "HPKE MESSAGE";
+
+/// The label of an armored public key, as written by `armor_public_key`
+pub const PUBLIC_KEY_LABEL: &str = "HPKE PUBLIC KEY";
+
+/// The label of an armored password-encrypted private key, as written by the `keystore` module
+pub const ENCRYPTED_PRIVATE_KEY_LABEL: &str = "HPKE ENCRYPTED PRIVATE KEY";
+
+/// The label of an armored age file, as written by `age --armor` and `keystore::export_encrypted`
+pub const AGE_ENCRYPTED_FILE_LABEL: &str = "AGE ENCRYPTED FILE";
+
+// The number of base64 characters on each line of the body
+const LINE_LEN: usize = 64;
+
+// The longest BEGIN or END line, plus surrounding whitespace, that a Dearmorer will buffer. Labels
+// here are much shorter. This bounds the memory that a Dearmorer uses on garbage input.
+const MAX_ARMOR_LINE_LEN: usize = 256;
+
+/// Armors `data` under `label`, in the format described in the module docs. The output ends in a
+/// newline.
+pub fn armor(label: &str, data: &[u8]) -> String {
+    let encoded = Base64::encode_string(data);
+    let mut out = format!("-----BEGIN {}-----\n", label);
+    // Base64 is ASCII, so every chunk is a valid str
+    for line in encoded.as_bytes().chunks(LINE_LEN) {
+        out.push_str(core::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+/// Dearmors `armored`, which must be under `label`. Whitespace around the armor and anywhere in
+/// the body is ignored, and lines may end in CRLF.
+///
+/// Return Value
+/// ============
+/// Returns the data on success. Returns `Err(HpkeError::ValidationError)` if the BEGIN or END line
+/// is missing or has a different label, if the body isn't base64, or if anything but whitespace
+/// follows the END line.
+pub fn dearmor(label: &str, armored: &str) -> Result<Vec<u8>, HpkeError> {
+    let mut dearmorer = Dearmorer::new(label);
+    let mut out = Vec::new();
+    dearmorer.update(armored.as_bytes(), &mut out)?;
+    dearmorer.finish()?;
+    Ok(out)
+}
+
+/// Dearmors `armored`, which must be exactly what `armor(label, data)` writes for some `data`,
+/// except that the final newline may be missing
+///
+/// Return Value
+/// ============
+/// Returns the data on success. Returns `Err(HpkeError::ValidationError)` otherwise.
+pub fn dearmor_strict(label: &str, armored: &str) -> Result<Vec<u8>, HpkeError> {
+    let data = dearmor(label, armored)?;
+    let canonical = armor(label, &data);
+    if armored == canonical || armored == &canonical[..canonical.len() - 1] {
+        Ok(data)
+    } else {
+        Err(HpkeError::ValidationError)
+    }
+}
+
+/// Armors a public key of `Kem` as `kem_id || pk`, under `PUBLIC_KEY_LABEL`
+pub fn armor_public_key<Kem: KemTrait>(pk: &Kem::PublicKey) -> String {
+    let mut data = Vec::with_capacity(2 + Kem::PublicKey::size());
+    let mut kem_id = [0u8; 2];
+    BigEndian::write_u16(&mut kem_id, Kem::KEM_ID);
+    data.extend_from_slice(&kem_id);
+    data.extend_from_slice(&pk.to_bytes());
+    armor(PUBLIC_KEY_LABEL, &data)
+}
+
+/// Dearmors a public key that `armor_public_key` armored, with `dearmor`
+///
+/// Return Value
+/// ============
+/// Returns the public key on success. Returns `Err(HpkeError::ValidationError)` if the armor is
+/// malformed or the key isn't for `Kem`. Otherwise, returns whatever error `from_bytes` returns
+/// on the key.
+pub fn dearmor_public_key<Kem: KemTrait>(armored: &str) -> Result<Kem::PublicKey, HpkeError> {
+    let data = dearmor(PUBLIC_KEY_LABEL, armored)?;
+    if data.len() < 2 || BigEndian::read_u16(&data[..2]) != Kem::KEM_ID {
+        return Err(HpkeError::ValidationError);
+    }
+    Kem::PublicKey::from_bytes(&data[2..])
+}
+
+// Where a Dearmorer is in its input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // Before the end of the BEGIN line
+    Begin,
+    // In the body
+    Body,
+    // In the END line, which the body ended at a '-'
+    End,
+    // After the END line. Only whitespace may follow.
+    Done,
+}
+
+/// An incremental `dearmor`, for armored input that arrives in pieces. Feed it the input with
+/// `update`, which appends the data decoded so far to `out`, then call `finish`.
+///
+/// ```
+/// use hpke::armor::{armor, Dearmorer};
+///
+/// let armored = armor("EXAMPLE", &[7u8; 1000]);
+/// let mut dearmorer = Dearmorer::new("EXAMPLE");
+/// let mut out = Vec::new();
+/// for chunk in armored.as_bytes().chunks(10) {
+///     dearmorer.update(chunk, &mut out).unwrap();
+/// }
+/// dearmorer.finish().unwrap();
+/// assert_eq!(out, [7u8; 1000]);
+/// ```
+///
+/// Decoded data is appended as soon as it's read, so a caller that can't tolerate data from
+/// input that turns out to be malformed must wait for `finish` to succeed before using it.
+#[derive(Clone, Debug)]
+pub struct Dearmorer {
+    begin_line: String,
+    end_line: String,
+    state: State,
+    // The BEGIN or END line read so far
+    line: Vec<u8>,
+    // The base64 characters of the current, incomplete 4-character quantum
+    quantum: [u8; 4],
+    quantum_len: usize,
+    // Whether a quantum ended in padding. Nothing but the END line may follow one that did.
+    padded: bool,
+}
+
+impl Dearmorer {
+    /// Makes a dearmorer that expects armor under `label`
+    pub fn new(label: &str) -> Dearmorer {
+        Dearmorer {
+            begin_line: format!("-----BEGIN {}-----", label),
+            end_line: format!("-----END {}-----", label),
+            state: State::Begin,
+            line: Vec::new(),
+            quantum: [0u8; 4],
+            quantum_len: 0,
+            padded: false,
+        }
+    }
+
+    /// Reads the next piece of the armored input, and appends whatever data it completes to `out`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` as soon as the input is known to be malformed,
+    /// as described in `dearmor`. The dearmorer is unusable after that.
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), HpkeError> {
+        for &b in input {
+            match self.state {
+                State::Begin => {
+                    if b == b'\n' {
+                        // Blank lines before the BEGIN line are fine
+                        if !take_line(&mut self.line, &self.begin_line)? {
+                            continue;
+                        }
+                        self.state = State::Body;
+                    } else {
+                        self.push_line(b)?;
+                    }
+                }
+                State::Body => match b {
+                    b'-' => {
+                        if self.quantum_len != 0 {
+                            return Err(HpkeError::ValidationError);
+                        }
+                        self.state = State::End;
+                        self.push_line(b)?;
+                    }
+                    _ if b.is_ascii_whitespace() => (),
+                    _ => self.push_quantum(b, out)?,
+                },
+                State::End => {
+                    if b == b'\n' {
+                        if !take_line(&mut self.line, &self.end_line)? {
+                            return Err(HpkeError::ValidationError);
+                        }
+                        self.state = State::Done;
+                    } else {
+                        self.push_line(b)?;
+                    }
+                }
+                State::Done => {
+                    if !b.is_ascii_whitespace() {
+                        return Err(HpkeError::ValidationError);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the input ended with a complete END line
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input was cut short.
+    pub fn finish(mut self) -> Result<(), HpkeError> {
+        match self.state {
+            State::Done => Ok(()),
+            // The final newline is optional
+            State::End if take_line(&mut self.line, &self.end_line)? => Ok(()),
+            _ => Err(HpkeError::ValidationError),
+        }
+    }
+
+    // Appends b to the BEGIN or END line being read, if it isn't too long
+    fn push_line(&mut self, b: u8) -> Result<(), HpkeError> {
+        if self.line.len() >= MAX_ARMOR_LINE_LEN {
+            return Err(HpkeError::ValidationError);
+        }
+        self.line.push(b);
+        Ok(())
+    }
+
+    // Adds a base64 character to the current quantum, and decodes the quantum into out if that
+    // completes it
+    fn push_quantum(&mut self, b: u8, out: &mut Vec<u8>) -> Result<(), HpkeError> {
+        if self.padded {
+            return Err(HpkeError::ValidationError);
+        }
+        self.quantum[self.quantum_len] = b;
+        self.quantum_len += 1;
+        if self.quantum_len == 4 {
+            let mut buf = [0u8; 3];
+            let decoded =
+                Base64::decode(self.quantum, &mut buf).map_err(|_| HpkeError::ValidationError)?;
+            out.extend_from_slice(decoded);
+            self.padded = self.quantum[3] == b'=';
+            self.quantum_len = 0;
+        }
+        Ok(())
+    }
+}
+
+// Clears the BEGIN or END line read so far and compares it, trimmed, to expected. Returns false if
+// the line was blank, true if it matched, and an error otherwise.
+fn take_line(line: &mut Vec<u8>, expected: &str) -> Result<bool, HpkeError> {
+    let line = core::mem::take(line);
+    let start = line
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    let end = line
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |i| i + 1);
+    let trimmed = &line[start..end];
+    if trimmed.is_empty() {
+        Ok(false)
+    } else if trimmed == expected.as_bytes() {
+        Ok(true)
+    } else {
+        Err(HpkeError::ValidationError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{armor, dearmor, dearmor_strict, Dearmorer};
+    use crate::{HpkeError, Vec};
+
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    /// Tests that armor round-trips through all three dearmors, for lengths around the line and
+    /// quantum boundaries, with the input split at every point
+    #[test]
+    fn test_armor_roundtrip() {
+        let mut csprng = StdRng::from_entropy();
+        for len in [0, 1, 2, 3, 47, 48, 49, 95, 96, 97, 300] {
+            let mut data = vec![0u8; len];
+            csprng.fill_bytes(&mut data);
+
+            let armored = armor("TEST", &data);
+            assert!(armored
+                .lines()
+                .all(|l| l.len() <= 64 || l.starts_with("-----")));
+            assert_eq!(dearmor("TEST", &armored).unwrap(), data);
+            assert_eq!(dearmor_strict("TEST", &armored).unwrap(), data);
+            assert_eq!(dearmor_strict("TEST", armored.trim_end()).unwrap(), data);
+
+            for split in 0..armored.len() {
+                let (a, b) = armored.as_bytes().split_at(split);
+                let mut dearmorer = Dearmorer::new("TEST");
+                let mut out = Vec::new();
+                dearmorer.update(a, &mut out).unwrap();
+                dearmorer.update(b, &mut out).unwrap();
+                dearmorer.finish().unwrap();
+                assert_eq!(out, data);
+            }
+        }
+    }
+
+    /// Tests that dearmor tolerates what pasting does to armor, and dearmor_strict doesn't
+    #[test]
+    fn test_dearmor_lenient() {
+        let data = [0xa5u8; 100];
+        let armored = armor("TEST", &data);
+        let body = armored
+            .lines()
+            .filter(|l| !l.starts_with("-----"))
+            .collect::<Vec<_>>()
+            .concat();
+
+        for mangled in [
+            format!("\n\n  {}  \n", armored),
+            armored.replace('\n', "\r\n"),
+            format!("-----BEGIN TEST-----\n{}\n-----END TEST-----", body),
+            format!(
+                "-----BEGIN TEST-----\n{}\n-----END TEST-----",
+                body.replace('A', "A \t")
+            ),
+        ] {
+            assert_eq!(dearmor("TEST", &mangled).unwrap(), data);
+            assert_eq!(
+                dearmor_strict("TEST", &mangled),
+                Err(HpkeError::ValidationError)
+            );
+        }
+    }
+
+    /// Tests that malformed armor is rejected
+    #[test]
+    fn test_dearmor_errors() {
+        let armored = armor("TEST", b"some data that spans a quantum or two");
+        let bad_body = armored.replacen("c29t", "c2!t", 1);
+        let extra_padding = armor("TEST", b"ab").replace("YWI=", "YWI=YWI=");
+        let truncated_body = armor("TEST", b"abcdef").replace("YWJjZGVm", "YWJjZGV");
+        for bad in [
+            "",
+            armored.trim_end_matches("-----END TEST-----\n"),
+            &armored.replace("BEGIN TEST", "BEGIN OTHER"),
+            &armored.replace("END TEST", "END OTHER"),
+            &format!("{}garbage\n", armored),
+            &format!("garbage\n{}", armored),
+            &bad_body,
+            &extra_padding,
+            &truncated_body,
+        ] {
+            assert_eq!(dearmor("TEST", bad), Err(HpkeError::ValidationError));
+        }
+
+        // Overlong lines are rejected without buffering them
+        let mut dearmorer = Dearmorer::new("TEST");
+        assert_eq!(
+            dearmorer.update(&[b'x'; 1000], &mut Vec::new()),
+            Err(HpkeError::ValidationError)
+        );
+    }
+
+    /// Tests that public keys round-trip, and don't dearmor as keys of another KEM
+    #[cfg(all(feature = "x25519", feature = "p256"))]
+    #[test]
+    fn test_armor_public_key() {
+        use super::{armor_public_key, dearmor_public_key};
+        use crate::kem::{DhP256HkdfSha256, Kem as KemTrait, X25519HkdfSha256};
+
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let armored = armor_public_key::<X25519HkdfSha256>(&pk);
+        assert_eq!(
+            dearmor_public_key::<X25519HkdfSha256>(&armored).unwrap(),
+            pk
+        );
+        assert_eq!(
+            dearmor_public_key::<DhP256HkdfSha256>(&armored).err(),
+            Some(HpkeError::ValidationError)
+        );
+    }
+}
@@ -1,4 +1,17 @@
-use crate::{kdf::Kdf as KdfTrait, util::KemSuiteId, Deserializable, Serializable};
+//! The Diffie-Hellman groups that DHKEMs are built from. Pair one with a KDF in
+//! [`crate::kem::DhKem`] to get a KEM.
+//!
+//! Groups that this crate doesn't ship, e.g., an in-house curve, plug in the same way: implement
+//! [`DhKeyExchange`] for them, outside this crate, and use them in a `DhKem` under a KEM ID of
+//! their own. See the trait docs for what an implementation must uphold.
+
+use crate::{kdf::Kdf as KdfTrait, util::KemSuiteId, Deserializable, HpkeError, Serializable};
+
+use subtle::ConstantTimeEq;
+use zeroize::ZeroizeOnDrop;
+
+#[cfg(any(feature = "p256", feature = "k256"))]
+use crate::KeyValidationError;
 
 #[cfg(feature = "serde_impls")]
 use serde::{Deserialize as SerdeDeserialize, Serialize as SerdeSerialize};
@@ -7,14 +20,40 @@ use serde::{Deserialize as SerdeDeserialize, Serialize as SerdeSerialize};
 // Table 2.
 pub(crate) const MAX_PUBKEY_SIZE: usize = 133;
 
-#[doc(hidden)]
-/// Internal error type used to represent `DhKeyExchange::dh()` failing
+/// The error `DhKeyExchange::dh()` returns when the DH result must be rejected. `DhKem` turns it
+/// into `HpkeError::EncapError` or `HpkeError::DecapError`, so it carries no detail.
 #[derive(Debug)]
 pub struct DhError;
 
 /// This trait captures the requirements of a Diffie-Hellman key exchange mechanism. It must have a
 /// way to generate keypairs, perform the Diffie-Hellman operation, and serialize/deserialize
-/// pubkeys. This is built into a KEM in `kem/dhkem.rs`.
+/// pubkeys. This is built into a KEM by [`crate::kem::DhKem`].
+///
+/// The groups in this module implement it, and so can groups from other crates. An
+/// implementation only needs the public API: the key types implement [`Serializable`],
+/// [`Deserializable`], [`ConstantTimeEq`], and [`ZeroizeOnDrop`] as bounded below, and are
+/// `Send + Sync` whatever features are enabled, so the `parallel` feature can share them across
+/// threads. `derive_keypair_with_counter` is written with the labeled KDF helpers in
+/// [`crate::low_level`]. Then `DhKem<MyGroup, HkdfSha256, 0xFF01>` is a KEM like any other, usable
+/// with `setup_sender`, envelopes, and the rest. A group that isn't in RFC 9180 needs a KEM ID
+/// that no registered KEM uses, as described on `DhKem`.
+///
+/// `DhKem` trusts the implementation to uphold the following, and checks none of it:
+///
+/// * `Deserializable::from_bytes` on public keys rejects every encoding that isn't a valid
+///   public key, e.g., points that aren't on the curve or are in a small subgroup, unless `dh`
+///   catches them instead. On private keys it rejects out-of-range scalars, including zero.
+/// * `dh` returns `Err(DhError)` whenever RFC 9180 §7.1.4 says to abort, e.g., on an all-zero
+///   shared secret.
+/// * Everything that touches a private key or a DH result runs in constant time, and the
+///   `ConstantTimeEq` impls compare in constant time.
+/// * Private keys and DH results are zeroized when dropped.
+///
+/// Serialized public keys and DH results can be at most 133 bytes long, the size of P-521's
+/// public keys. `DhKem` builds its KEM context in fixed-size buffers, so using a larger group in
+/// one fails to compile.
+///
+/// The `custom_group` test in this module's source is a template for such an implementation.
 pub trait DhKeyExchange {
     // Public and private keys need to implement serde::{Serialize, Deserialize} if the serde_impls
     // feature is set. So double up all the definitions: one with serde and one without.
@@ -25,12 +64,14 @@ pub trait DhKeyExchange {
     type PublicKey: Clone
         + Serializable
         + Deserializable
+        + Send
+        + Sync
         + SerdeSerialize
         + for<'a> SerdeDeserialize<'a>;
     /// The key exchange's public key type. If you want to generate a keypair, see
     /// `Kem::gen_keypair` or `Kem::derive_keypair`
     #[cfg(not(feature = "serde_impls"))]
-    type PublicKey: Clone + Serializable + Deserializable;
+    type PublicKey: Clone + Serializable + Deserializable + Send + Sync;
 
     /// The key exchange's private key type. If you want to generate a keypair, see
     /// `Kem::gen_keypair` or `Kem::derive_keypair`
@@ -38,33 +79,114 @@ pub trait DhKeyExchange {
     type PrivateKey: Clone
         + Serializable
         + Deserializable
+        + ConstantTimeEq
+        + ZeroizeOnDrop
+        + Send
+        + Sync
         + SerdeSerialize
         + for<'a> SerdeDeserialize<'a>;
 
     /// The key exchange's private key type. If you want to generate a keypair, see
     /// `Kem::gen_keypair` or `Kem::derive_keypair`
     #[cfg(not(feature = "serde_impls"))]
-    type PrivateKey: Clone + Serializable + Deserializable;
+    type PrivateKey: Clone
+        + Serializable
+        + Deserializable
+        + ConstantTimeEq
+        + ZeroizeOnDrop
+        + Send
+        + Sync;
 
-    /// The result of a DH operation
-    #[doc(hidden)]
-    type KexResult: Serializable;
+    /// The result of a DH operation. Its serialization is the `dh` output of RFC 9180 §4.1, which
+    /// goes into the KEM's shared secret, so it's `Ndh` bytes long.
+    type KexResult: Serializable + ConstantTimeEq + ZeroizeOnDrop + Send + Sync;
 
     /// Computes the public key of a given private key
-    #[doc(hidden)]
     fn sk_to_pk(sk: &Self::PrivateKey) -> Self::PublicKey;
 
     /// Does the Diffie-Hellman operation
-    #[doc(hidden)]
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(DhError)` if the result must be rejected, e.g., if it's the identity or all
+    /// zeros. `DhKem` reports this as `HpkeError::EncapError` or `HpkeError::DecapError`.
     fn dh(sk: &Self::PrivateKey, pk: &Self::PublicKey) -> Result<Self::KexResult, DhError>;
 
+    /// A public key together with whatever precomputation makes repeated `dh` calls against it,
+    /// and repeated `sk_to_pk` calls, faster. Backends that have nothing to precompute just use
+    /// the public key.
+    type PublicKeyTable: Clone;
+
+    /// Does the precomputation for the given public key
+    fn precompute(pk: &Self::PublicKey) -> Self::PublicKeyTable;
+
+    /// Same as `sk_to_pk`, but may use the fixed-base tables in `table`. Without tables, this is
+    /// just `sk_to_pk(sk)`.
+    fn sk_to_pk_with_table(sk: &Self::PrivateKey, table: &Self::PublicKeyTable) -> Self::PublicKey;
+
+    /// Same as `dh(sk, pk)`, where `pk` is the public key that `table` was computed from. The
+    /// result must be the same as `dh`'s.
+    fn dh_with_table(
+        sk: &Self::PrivateKey,
+        table: &Self::PublicKeyTable,
+    ) -> Result<Self::KexResult, DhError>;
+
     /// Computes a keypair given key material `ikm` of sufficient entropy. See
-    /// [`crate::kem::Kem::derive_keypair`] for discussion of entropy.
-    #[doc(hidden)]
+    /// [`crate::kem::Kem::derive_keypair`] for discussion of entropy. Also returns the counter of
+    /// the candidate that was accepted, if this group's DeriveKeyPair does rejection sampling.
+    ///
+    /// This is the DeriveKeyPair of RFC 9180 §7.1.3, and every labeled KDF call in it must use
+    /// `suite_id`, so that keys derived under different KEMs are independent. Groups that aren't
+    /// in RFC 9180 should follow the same shape: `LabeledExtract` the IKM into `dkp_prk`, then
+    /// `LabeledExpand` it into a scalar, with a counter if not every string is a valid scalar.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::KeyDerivation)` if every candidate was rejected.
+    fn derive_keypair_with_counter<Kdf: KdfTrait>(
+        suite_id: &KemSuiteId,
+        ikm: &[u8],
+    ) -> Result<(Self::PrivateKey, Self::PublicKey, Option<u8>), HpkeError>;
+
+    /// Same as `derive_keypair_with_counter`, without the counter
     fn derive_keypair<Kdf: KdfTrait>(
         suite_id: &KemSuiteId,
         ikm: &[u8],
-    ) -> (Self::PrivateKey, Self::PublicKey);
+    ) -> Result<(Self::PrivateKey, Self::PublicKey), HpkeError> {
+        let (sk, pk, _) = Self::derive_keypair_with_counter::<Kdf>(suite_id, ikm)?;
+        Ok((sk, pk))
+    }
+}
+
+/// Checks everything about an uncompressed SEC1 point encoding that doesn't need curve
+/// arithmetic: the length, the tag byte, and that both coordinates are less than the field prime
+/// `modulus`, which is given big-endian. The caller still has to check that the point is on the
+/// curve.
+#[cfg(any(feature = "p256", feature = "k256"))]
+pub(crate) fn check_uncompressed_point_encoding(
+    encoded: &[u8],
+    modulus: &[u8; 32],
+) -> Result<(), KeyValidationError> {
+    if encoded.len() != 65 {
+        return Err(KeyValidationError::IncorrectLength(65, encoded.len()));
+    }
+
+    // SEC1 §2.3.3: 0x00 is the point at infinity, 0x02 and 0x03 are compressed points, and 0x04 is
+    // an uncompressed point. RFC 9180 only permits the last one.
+    match encoded[0] {
+        0x04 => (),
+        0x00 => return Err(KeyValidationError::PointAtInfinity),
+        tag => return Err(KeyValidationError::UnsupportedPointEncoding(tag)),
+    }
+
+    // Big-endian byte strings of equal length compare the same way as the integers they encode.
+    // None of this is secret, so it needn't be constant-time.
+    let (x, y) = encoded[1..].split_at(32);
+    if x >= &modulus[..] || y >= &modulus[..] {
+        return Err(KeyValidationError::NonCanonicalEncoding);
+    }
+
+    Ok(())
 }
 
 #[cfg(feature = "p256")]
@@ -81,3 +203,218 @@ pub use ecdh_k256::DhK256;
 pub(crate) mod x25519;
 #[cfg(feature = "x25519-dalek")]
 pub use x25519::X25519;
+
+// A template for implementing DhKeyExchange outside this crate. It only uses public items, at the
+// paths a downstream crate would use, with `crate` in place of `hpke`. To keep the test honest,
+// the group is X25519 again, built straight on x25519-dalek, so it has to interoperate with the
+// built-in X25519HkdfSha256. A new group would take a private-use KEM ID instead of 0x0020.
+#[cfg(all(test, feature = "x25519", feature = "alloc"))]
+mod custom_group {
+    use crate::{
+        dhkex::{DhError, DhKeyExchange},
+        kdf::{HkdfSha256, Kdf as KdfTrait},
+        kem::{DhKem, Kem as KemTrait, X25519HkdfSha256},
+        low_level::{enforce_equal_len, labeled_extract, KemSuiteId, LabeledExpand},
+        Deserializable, HpkeError, Serializable,
+    };
+
+    use generic_array::{typenum, GenericArray};
+    use rand::{rngs::StdRng, SeedableRng};
+    use subtle::{Choice, ConstantTimeEq};
+    use zeroize::ZeroizeOnDrop;
+
+    /// The group. It's only ever used as a type parameter.
+    pub struct MyX25519;
+
+    /// A public key
+    #[derive(Clone)]
+    pub struct PublicKey(x25519_dalek::PublicKey);
+
+    /// A private key. The dalek type zeroizes itself on drop.
+    #[derive(Clone)]
+    pub struct PrivateKey(x25519_dalek::StaticSecret);
+
+    /// A DH result. The dalek type zeroizes itself on drop.
+    pub struct KexResult(x25519_dalek::SharedSecret);
+
+    impl ZeroizeOnDrop for PrivateKey {}
+    impl ZeroizeOnDrop for KexResult {}
+
+    impl ConstantTimeEq for PrivateKey {
+        fn ct_eq(&self, other: &Self) -> Choice {
+            self.0.as_bytes().ct_eq(other.0.as_bytes())
+        }
+    }
+
+    impl ConstantTimeEq for KexResult {
+        fn ct_eq(&self, other: &Self) -> Choice {
+            self.0.as_bytes().ct_eq(other.0.as_bytes())
+        }
+    }
+
+    impl Serializable for PublicKey {
+        type OutputSize = typenum::U32;
+
+        fn to_bytes(&self) -> GenericArray<u8, typenum::U32> {
+            GenericArray::clone_from_slice(self.0.as_bytes())
+        }
+    }
+
+    impl Deserializable for PublicKey {
+        fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+            enforce_equal_len(32, encoded.len())?;
+            let mut arr = [0u8; 32];
+            arr.copy_from_slice(encoded);
+            // Every 32-byte string is an X25519 public key. Low-order points are caught by `dh`.
+            Ok(PublicKey(x25519_dalek::PublicKey::from(arr)))
+        }
+    }
+
+    impl Serializable for PrivateKey {
+        type OutputSize = typenum::U32;
+
+        fn to_bytes(&self) -> GenericArray<u8, typenum::U32> {
+            GenericArray::clone_from_slice(&self.0.to_bytes())
+        }
+    }
+
+    impl Deserializable for PrivateKey {
+        fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+            enforce_equal_len(32, encoded.len())?;
+            let mut arr = [0u8; 32];
+            arr.copy_from_slice(encoded);
+            // Clamping makes every 32-byte string a nonzero scalar
+            Ok(PrivateKey(x25519_dalek::StaticSecret::from(arr)))
+        }
+    }
+
+    impl Serializable for KexResult {
+        type OutputSize = typenum::U32;
+
+        fn to_bytes(&self) -> GenericArray<u8, typenum::U32> {
+            GenericArray::clone_from_slice(self.0.as_bytes())
+        }
+    }
+
+    // With the serde_impls feature, keys also have to be serde types. Going through the byte
+    // encoding is enough.
+    #[cfg(feature = "serde_impls")]
+    macro_rules! impl_serde_via_bytes {
+        ($t:ty) => {
+            impl serde::Serialize for $t {
+                fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                    self.to_bytes().serialize(s)
+                }
+            }
+
+            impl<'de> serde::Deserialize<'de> for $t {
+                fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                    let bytes = GenericArray::<u8, typenum::U32>::deserialize(d)?;
+                    Self::from_bytes(&bytes).map_err(serde::de::Error::custom)
+                }
+            }
+        };
+    }
+    #[cfg(feature = "serde_impls")]
+    impl_serde_via_bytes!(PublicKey);
+    #[cfg(feature = "serde_impls")]
+    impl_serde_via_bytes!(PrivateKey);
+
+    impl DhKeyExchange for MyX25519 {
+        type PublicKey = PublicKey;
+        type PrivateKey = PrivateKey;
+        type KexResult = KexResult;
+        // Nothing to precompute
+        type PublicKeyTable = PublicKey;
+
+        fn sk_to_pk(sk: &PrivateKey) -> PublicKey {
+            PublicKey(x25519_dalek::PublicKey::from(&sk.0))
+        }
+
+        fn dh(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, DhError> {
+            let res = sk.0.diffie_hellman(&pk.0);
+            // RFC 9180 §7.1.4: abort on the all-zero shared secret
+            if res.as_bytes().ct_eq(&[0u8; 32]).into() {
+                Err(DhError)
+            } else {
+                Ok(KexResult(res))
+            }
+        }
+
+        fn precompute(pk: &PublicKey) -> PublicKey {
+            pk.clone()
+        }
+
+        fn sk_to_pk_with_table(sk: &PrivateKey, _: &PublicKey) -> PublicKey {
+            Self::sk_to_pk(sk)
+        }
+
+        fn dh_with_table(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, DhError> {
+            Self::dh(sk, pk)
+        }
+
+        // RFC 9180 §7.1.3
+        fn derive_keypair_with_counter<Kdf: KdfTrait>(
+            suite_id: &KemSuiteId,
+            ikm: &[u8],
+        ) -> Result<(PrivateKey, PublicKey, Option<u8>), HpkeError> {
+            let (_, dkp_prk) = labeled_extract::<Kdf>(&[], suite_id, b"dkp_prk", ikm);
+            let mut buf = [0u8; 32];
+            dkp_prk
+                .labeled_expand(suite_id, b"sk", &[], &mut buf)
+                .map_err(|_| HpkeError::KeyDerivation)?;
+            let sk = PrivateKey(x25519_dalek::StaticSecret::from(buf));
+            let pk = Self::sk_to_pk(&sk);
+            Ok((sk, pk, None))
+        }
+    }
+
+    /// The KEM. RFC 9180 gives X25519 with HKDF-SHA256 the ID 0x0020.
+    type MyKem = DhKem<MyX25519, HkdfSha256, 0x0020>;
+
+    /// Tests that the custom group derives the same keys as the built-in one, that the two
+    /// interoperate, and that the whole API works on top of it
+    #[test]
+    fn test_custom_group() {
+        let ikm = [7u8; 32];
+        let (my_sk, my_pk) = MyKem::derive_keypair(&ikm);
+        let (sk, pk) = X25519HkdfSha256::derive_keypair(&ikm);
+        assert_eq!(my_sk.to_bytes(), sk.to_bytes());
+        assert_eq!(my_pk.to_bytes(), pk.to_bytes());
+
+        // Encapsulate with one, decapsulate with the other
+        let mut csprng = StdRng::from_entropy();
+        let (my_ss, my_enc) = MyKem::encap(&my_pk, None, &mut csprng).unwrap();
+        let enc = <X25519HkdfSha256 as KemTrait>::EncappedKey::from_bytes(&my_enc.to_bytes());
+        let ss = X25519HkdfSha256::decap(&sk, None, &enc.unwrap()).unwrap();
+        assert_eq!(my_ss.0, ss.0);
+
+        // Full HPKE over the custom KEM
+        let ct = crate::single_shot_seal::<crate::aead::ChaCha20Poly1305, HkdfSha256, MyKem, _>(
+            &crate::OpModeS::Base,
+            &my_pk,
+            b"info",
+            b"hello",
+            b"aad",
+            &mut csprng,
+        )
+        .unwrap();
+        let pt = crate::single_shot_open::<crate::aead::ChaCha20Poly1305, HkdfSha256, MyKem>(
+            &crate::OpModeR::Base,
+            &my_sk,
+            &ct.0,
+            b"info",
+            &ct.1,
+            b"aad",
+        )
+        .unwrap();
+        assert_eq!(pt, b"hello");
+
+        // The all-zero point is rejected by `dh`, which DhKem reports as an encap error
+        let zero = PublicKey::from_bytes(&[0u8; 32]).unwrap();
+        assert!(matches!(
+            MyKem::encap(&zero, None, &mut csprng),
+            Err(HpkeError::EncapError)
+        ));
+    }
+}
@@ -1,8 +1,8 @@
 use crate::{
-    dhkex::{DhError, DhKeyExchange},
+    dhkex::{check_uncompressed_point_encoding, DhError, DhKeyExchange},
     kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
     util::{enforce_equal_len, KemSuiteId},
-    Deserializable, HpkeError, Serializable,
+    Deserializable, HpkeError, KeyValidationError, Serializable,
 };
 
 use generic_array::{
@@ -10,16 +10,29 @@ use generic_array::{
     GenericArray,
 };
 use k256::elliptic_curve::{ecdh::diffie_hellman, sec1::ToEncodedPoint};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::ZeroizeOnDrop;
+
+#[cfg(all(feature = "bech32", not(feature = "std")))]
+use alloc::string as alloc_string;
+#[cfg(all(feature = "bech32", feature = "std"))]
+use std::string as alloc_string;
+
+// The secp256k1 field prime, big-endian. This is what public key coordinates must be less than.
+const FIELD_MODULUS: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xfc, 0x2f,
+];
 
 /// An ECDH-K256 public key. This is never the point at infinity.
 #[derive(Clone)]
-pub struct PublicKey(k256::PublicKey);
+pub struct PublicKey(pub(crate) k256::PublicKey);
 
 // This is only ever constructed via its Deserializable::from_bytes, which checks for the 0 value.
 // Also, the underlying type is zeroize-on-drop.
 /// An ECDH-K256 private key. This is a scalar in the range `[1,p)` where `p` is the group order.
 #[derive(Clone)]
-pub struct PrivateKey(k256::SecretKey);
+pub struct PrivateKey(pub(crate) k256::SecretKey);
 
 impl PrivateKey {
     pub fn public(&self) -> PublicKey {
@@ -27,10 +40,111 @@ impl PrivateKey {
     }
 }
 
+#[cfg(feature = "pkcs8")]
+impl PrivateKey {
+    /// Parses a DER-encoded PKCS#8 private key
+    pub(crate) fn from_pkcs8_der(der: &[u8]) -> Result<PrivateKey, HpkeError> {
+        use k256::pkcs8::DecodePrivateKey;
+        k256::SecretKey::from_pkcs8_der(der)
+            .map(PrivateKey)
+            .map_err(|_| HpkeError::ValidationError)
+    }
+
+    /// Encodes this private key as DER-encoded PKCS#8
+    pub(crate) fn to_pkcs8_der(&self) -> zeroize::Zeroizing<crate::Vec<u8>> {
+        use k256::pkcs8::EncodePrivateKey;
+        // This only fails if the DER writer runs out of room, which it can't, since it allocates
+        let doc = self.0.to_pkcs8_der().expect("PKCS#8 encoding failed");
+        zeroize::Zeroizing::new(doc.as_ref().to_vec())
+    }
+}
+
+// The bech32m human-readable parts of K-256 public and private keys. These differ, so one can't
+// be pasted where the other is expected.
+#[cfg(feature = "bech32")]
+const BECH32_PUBLIC_HRP: &str = "hpkepub";
+#[cfg(feature = "bech32")]
+const BECH32_PRIVATE_HRP: &str = "hpkesec";
+
+#[cfg(feature = "bech32")]
+impl PublicKey {
+    /// Encodes this public key as bech32m with the human-readable part `hpkepub`
+    pub fn to_bech32(&self) -> alloc_string::String {
+        bech32_encode(BECH32_PUBLIC_HRP, &self.to_bytes())
+    }
+
+    /// Parses a public key from bech32m with the human-readable part `hpkepub`, in either case
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the checksum is wrong, the human-readable part
+    /// isn't `hpkepub`, or the string is bech32 rather than bech32m. Otherwise, returns whatever
+    /// error `from_bytes` returns on the decoded bytes.
+    pub fn from_bech32(encoded: &str) -> Result<PublicKey, HpkeError> {
+        let bytes = bech32_decode(BECH32_PUBLIC_HRP, encoded)?;
+        PublicKey::from_bytes(&bytes)
+    }
+}
+
+#[cfg(feature = "bech32")]
+impl PrivateKey {
+    /// Encodes this private key as bech32m with the human-readable part `hpkesec`
+    pub fn to_bech32(&self) -> zeroize::Zeroizing<alloc_string::String> {
+        zeroize::Zeroizing::new(bech32_encode(BECH32_PRIVATE_HRP, &self.to_bytes()))
+    }
+
+    /// Parses a private key from bech32m with the human-readable part `hpkesec`, in either case
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the checksum is wrong, the human-readable part
+    /// isn't `hpkesec`, or the string is bech32 rather than bech32m. Otherwise, returns whatever
+    /// error `from_bytes` returns on the decoded bytes.
+    pub fn from_bech32(encoded: &str) -> Result<PrivateKey, HpkeError> {
+        let bytes = zeroize::Zeroizing::new(bech32_decode(BECH32_PRIVATE_HRP, encoded)?);
+        PrivateKey::from_bytes(&bytes)
+    }
+}
+
+// Encodes bytes as bech32m under the given human-readable part
+#[cfg(feature = "bech32")]
+fn bech32_encode(hrp: &str, bytes: &[u8]) -> alloc_string::String {
+    use bech32::ToBase32;
+    bech32::encode(hrp, bytes.to_base32(), bech32::Variant::Bech32m).expect("HRPs are valid bech32")
+}
+
+// Decodes bech32m, checking the checksum and that the human-readable part is the expected one
+#[cfg(feature = "bech32")]
+fn bech32_decode(hrp: &str, encoded: &str) -> Result<crate::Vec<u8>, HpkeError> {
+    use bech32::FromBase32;
+    let (found_hrp, data, variant) =
+        bech32::decode(encoded).map_err(|_| HpkeError::ValidationError)?;
+    if found_hrp != hrp || variant != bech32::Variant::Bech32m {
+        return Err(HpkeError::ValidationError);
+    }
+    crate::Vec::<u8>::from_base32(&data).map_err(|_| HpkeError::ValidationError)
+}
+
 // The underlying type is zeroize-on-drop
 /// A bare DH computation result
 pub struct KexResult(k256::ecdh::SharedSecret);
 
+// The underlying types are zeroize-on-drop
+impl ZeroizeOnDrop for PrivateKey {}
+impl ZeroizeOnDrop for KexResult {}
+
+impl ConstantTimeEq for PrivateKey {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl ConstantTimeEq for KexResult {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.as_bytes().ct_eq(other.0.as_bytes())
+    }
+}
+
 // Everything is serialized and deserialized in uncompressed form
 impl Serializable for PublicKey {
     // RFC 9180 §7.1: Npk of DHKEM(K-256, HKDF-SHA256) is 65
@@ -55,7 +169,7 @@ impl Deserializable for PublicKey {
         // PublicKey::from_sec1_bytes() will error if it receives the point at infinity. This is
         // because its submethod, PublicKey::from_encoded_point(), does this check explicitly.
         let parsed =
-            k256::PublicKey::from_sec1_bytes(encoded).map_err(|_| HpkeError::ValidationError)?;
+            k256::PublicKey::from_sec1_bytes(encoded).map_err(|_| invalid_pubkey(encoded))?;
         Ok(PublicKey(parsed))
     }
 }
@@ -78,12 +192,86 @@ impl Deserializable for PrivateKey {
         // Invariant: PrivateKey is in [1,p). This is preserved here.
         // SecretKey::from_be_bytes() directly checks that the value isn't zero. And its submethod,
         // ScalarCore::from_be_bytes() checks that the value doesn't exceed the modulus.
-        let sk = k256::SecretKey::from_be_bytes(encoded).map_err(|_| HpkeError::ValidationError)?;
+        let sk = k256::SecretKey::from_be_bytes(encoded).map_err(|_| invalid_privkey(encoded))?;
 
         Ok(PrivateKey(sk))
     }
 }
 
+impl PublicKey {
+    /// Fully validates an encoded public key: checks that it's an uncompressed point whose
+    /// coordinates are reduced, that it lies on the curve, and that it isn't the point at infinity.
+    /// The curve has cofactor 1, so every point on it is in the prime-order subgroup.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if and only if `from_bytes` would accept `encoded`. Otherwise, returns the
+    /// first problem found.
+    pub fn validate_bytes(encoded: &[u8]) -> Result<(), KeyValidationError> {
+        check_uncompressed_point_encoding(encoded, &FIELD_MODULUS)?;
+
+        // The encoding is well-formed, so the only thing that can go wrong now is the curve
+        // equation. from_sec1_bytes() checks that, and also rejects the point at infinity, which
+        // has no affine encoding, so we've already ruled it out.
+        k256::PublicKey::from_sec1_bytes(encoded)
+            .map(|_| ())
+            .map_err(|_| KeyValidationError::NotOnCurve)
+    }
+
+    /// Re-runs `validate_bytes` on this key's encoding. This always succeeds for keys constructed
+    /// by this crate, and exists for symmetry with key types that admit weak values.
+    pub fn validate(&self) -> Result<(), KeyValidationError> {
+        Self::validate_bytes(&self.to_bytes())
+    }
+}
+
+impl PrivateKey {
+    /// Validates an encoded private key: checks that it's the right length, and that the scalar
+    /// it encodes is in the range `[1,n)` where `n` is the group order.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if and only if `from_bytes` would accept `encoded`. Otherwise, returns
+    /// `KeyValidationError::IncorrectLength`, `KeyValidationError::ZeroScalar`, or
+    /// `KeyValidationError::ScalarOutOfRange`.
+    pub fn validate_bytes(encoded: &[u8]) -> Result<(), KeyValidationError> {
+        if encoded.len() != 32 {
+            return Err(KeyValidationError::IncorrectLength(32, encoded.len()));
+        }
+
+        // from_be_bytes() does the range check in constant time. If it fails, the only thing left
+        // to find out is which end of the range was violated.
+        if k256::SecretKey::from_be_bytes(encoded).is_ok() {
+            Ok(())
+        } else if bool::from(encoded.ct_eq(&[0u8; 32])) {
+            Err(KeyValidationError::ZeroScalar)
+        } else {
+            Err(KeyValidationError::ScalarOutOfRange)
+        }
+    }
+
+    /// Re-runs `validate_bytes` on this key's encoding. This always succeeds for keys constructed
+    /// by this crate, and exists for symmetry with key types that admit weak values.
+    pub fn validate(&self) -> Result<(), KeyValidationError> {
+        Self::validate_bytes(&self.to_bytes())
+    }
+}
+
+// Only called once parsing has already failed, so the happy path doesn't pay for diagnostics. If
+// the validator somehow disagrees with the parser, fall back to the generic error.
+fn invalid_pubkey(encoded: &[u8]) -> HpkeError {
+    PublicKey::validate_bytes(encoded)
+        .err()
+        .map_or(HpkeError::ValidationError, HpkeError::from)
+}
+
+// Same as above, but for private keys
+fn invalid_privkey(encoded: &[u8]) -> HpkeError {
+    PrivateKey::validate_bytes(encoded)
+        .err()
+        .map_or(HpkeError::ValidationError, HpkeError::from)
+}
+
 // DH results are serialized in the same way as public keys
 impl Serializable for KexResult {
     // RFC 9180 §4.1
@@ -108,6 +296,8 @@ impl DhKeyExchange for DhK256 {
     type PrivateKey = PrivateKey;
     #[doc(hidden)]
     type KexResult = KexResult;
+    #[doc(hidden)]
+    type PublicKeyTable = PublicKey;
 
     /// Converts an K256 private key to a public key
     #[doc(hidden)]
@@ -136,6 +326,23 @@ impl DhKeyExchange for DhK256 {
         Ok(KexResult(dh_res))
     }
 
+    // k256 already multiplies with a windowed GLV method whose per-point lookup tables are only a
+    // small fraction of the cost, and it doesn't expose them. So the table is just the pubkey.
+    #[doc(hidden)]
+    fn precompute(pk: &PublicKey) -> PublicKey {
+        pk.clone()
+    }
+
+    #[doc(hidden)]
+    fn sk_to_pk_with_table(sk: &PrivateKey, _: &PublicKey) -> PublicKey {
+        Self::sk_to_pk(sk)
+    }
+
+    #[doc(hidden)]
+    fn dh_with_table(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, DhError> {
+        Self::dh(sk, pk)
+    }
+
     // RFC 9180 §7.1.3:
     // def DeriveKeyPair(ikm):
     //   dkp_prk = LabeledExtract("", "dkp_prk", ikm)
@@ -156,7 +363,10 @@ impl DhKeyExchange for DhK256 {
     /// ID. The keying material SHOULD have as many bits of entropy as the bit length of a secret
     /// key, i.e., 256.
     #[doc(hidden)]
-    fn derive_keypair<Kdf: KdfTrait>(suite_id: &KemSuiteId, ikm: &[u8]) -> (PrivateKey, PublicKey) {
+    fn derive_keypair_with_counter<Kdf: KdfTrait>(
+        suite_id: &KemSuiteId,
+        ikm: &[u8],
+    ) -> Result<(PrivateKey, PublicKey, Option<u8>), HpkeError> {
         // Write the label into a byte buffer and extract from the IKM
         let (_, hkdf_ctx) = labeled_extract::<Kdf>(&[], suite_id, b"dkp_prk", ikm);
 
@@ -175,13 +385,13 @@ impl DhKeyExchange for DhK256 {
             // keypair. Recall the invariant of PrivateKey: it is a value in the range [1,p).
             if let Ok(sk) = PrivateKey::from_bytes(&buf) {
                 let pk = Self::sk_to_pk(&sk);
-                return (sk, pk);
+                return Ok((sk, pk, Some(counter)));
             }
         }
 
         // The code should never ever get here. The likelihood that we get 256 bad samples
         // in a row for k256 is 2^-8192.
-        panic!("DeriveKeyPair failed all attempts");
+        Err(HpkeError::KeyDerivation)
     }
 }
 
@@ -194,12 +404,12 @@ mod tests {
             DhKeyExchange,
         },
         kdf::HkdfSha256,
-        kem::{dhk256_hkdfsha256::EncappedKey, DhK256HkdfSha256},
-        op_mode::OpMode,
+        kem::{DhEncappedKey, DhK256HkdfSha256},
         test_util::dhkex_gen_keypair,
         Deserializable, OpModeR, Serializable,
     };
 
+    use crate::{HpkeError, KeyValidationError};
     use rand::{rngs::StdRng, SeedableRng};
 
     // We need this in our serialize-deserialize tests
@@ -262,6 +472,55 @@ mod tests {
         assert!(new_pk == pk, "public key doesn't serialize correctly");
     }
 
+    /// Tests that bech32m encodings round-trip, and that bad checksums, the wrong HRP, and plain
+    /// bech32 are rejected
+    #[cfg(feature = "bech32")]
+    #[test]
+    fn test_bech32() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk, pk) = dhkex_gen_keypair::<DhK256, _>(&mut csprng);
+
+        let pk_str = pk.to_bech32();
+        let sk_str = sk.to_bech32();
+        assert!(pk_str.starts_with("hpkepub1"));
+        assert!(sk_str.starts_with("hpkesec1"));
+        assert_eq!(PublicKey::from_bech32(&pk_str).unwrap(), pk);
+        assert!(PrivateKey::from_bech32(&sk_str).unwrap() == sk);
+        assert_eq!(PublicKey::from_bech32(&pk_str.to_uppercase()).unwrap(), pk);
+
+        // Public and private keys can't be mixed up
+        assert!(matches!(
+            PublicKey::from_bech32(&sk_str),
+            Err(HpkeError::ValidationError)
+        ));
+        assert!(matches!(
+            PrivateKey::from_bech32(&pk_str),
+            Err(HpkeError::ValidationError)
+        ));
+
+        // A typo breaks the checksum
+        let mut typo = pk_str.clone().into_bytes();
+        let i = typo.len() - 10;
+        typo[i] = if typo[i] == b'q' { b'p' } else { b'q' };
+        assert!(matches!(
+            PublicKey::from_bech32(core::str::from_utf8(&typo).unwrap()),
+            Err(HpkeError::ValidationError)
+        ));
+
+        // Plain bech32 isn't accepted
+        use bech32::ToBase32;
+        let plain = bech32::encode(
+            "hpkepub",
+            pk.to_bytes().to_base32(),
+            bech32::Variant::Bech32,
+        )
+        .unwrap();
+        assert!(matches!(
+            PublicKey::from_bech32(&plain),
+            Err(HpkeError::ValidationError)
+        ));
+    }
+
     use hex_literal::hex;
     const ENCAP: [u8; 65] = hex!("041c606ea5ec589cd99872ab6bf34330dca8f67ccec9f84f4524ee3416af3bb8dcecfe6f2039a05f555066d1136e608dff880c392d3de2709cc0cee0e194e8195c");
     const CIHPHERTEXT: [u8; 50]  = hex!("683b4aa1f72a27429b338ae670273ba492c727dadf49228dfe1ec8b46997527fa72ffd4d636ed6548f7dee07e62e02d84267");
@@ -279,7 +538,7 @@ mod tests {
 
         let sk = PrivateKey::from_bytes(&TEST_SK).expect("Invalid Secret Key");
 
-        let encap_key = EncappedKey::from_bytes(&ENCAP).expect("Invalid encapped key");
+        let encap_key = DhEncappedKey::<DhK256>::from_bytes(&ENCAP).expect("Invalid encapped key");
         let mut dec_context =
             crate::setup_receiver::<Aead, Kdf, Kem>(&OpModeR::Base, &sk, &encap_key, INFO)
                 .expect("failed to set up receiver");
@@ -290,4 +549,78 @@ mod tests {
 
         assert_eq!(plaintext, MSG);
     }
+
+    /// Tests that validate_bytes accepts honest keys and gives the right diagnostic for each kind
+    /// of bad key
+    #[test]
+    fn test_key_validation() {
+        type Kex = DhK256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, pk) = dhkex_gen_keypair::<Kex, _>(&mut csprng);
+        assert_eq!(sk.validate(), Ok(()));
+        assert_eq!(pk.validate(), Ok(()));
+
+        // Public keys
+        let pk_bytes = pk.to_bytes();
+        assert_eq!(
+            PublicKey::validate_bytes(&pk_bytes[..33]),
+            Err(KeyValidationError::IncorrectLength(65, 33))
+        );
+        let mut bad = pk_bytes;
+        bad[0] = 0x00;
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::PointAtInfinity)
+        );
+        bad[0] = 0x02;
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::UnsupportedPointEncoding(0x02))
+        );
+        let mut bad = pk_bytes;
+        bad[1..33].copy_from_slice(&super::FIELD_MODULUS);
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::NonCanonicalEncoding)
+        );
+        let mut bad = pk_bytes;
+        bad[64] ^= 1;
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::NotOnCurve)
+        );
+
+        // Private keys
+        assert_eq!(
+            PrivateKey::validate_bytes(&[0u8; 32]),
+            Err(KeyValidationError::ZeroScalar)
+        );
+        assert_eq!(
+            PrivateKey::validate_bytes(&[0xffu8; 32]),
+            Err(KeyValidationError::ScalarOutOfRange)
+        );
+        assert_eq!(
+            PrivateKey::validate_bytes(&[1u8; 31]),
+            Err(KeyValidationError::IncorrectLength(32, 31))
+        );
+
+        // from_bytes should report the same diagnostics
+        assert_eq!(
+            PublicKey::from_bytes(&bad).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::NotOnCurve))
+        );
+        assert_eq!(
+            PublicKey::from_bytes(&pk_bytes[..64]).err(),
+            Some(HpkeError::IncorrectInputLength(65, 64))
+        );
+        assert_eq!(
+            PrivateKey::from_bytes(&[0u8; 32]).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::ZeroScalar))
+        );
+        assert_eq!(
+            PrivateKey::from_bytes(&[0xffu8; 32]).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::ScalarOutOfRange))
+        );
+    }
 }
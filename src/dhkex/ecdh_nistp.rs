@@ -1,15 +1,31 @@
 use crate::{
-    dhkex::{DhError, DhKeyExchange},
+    dhkex::{check_uncompressed_point_encoding, DhError, DhKeyExchange},
     kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
     util::{enforce_equal_len, KemSuiteId},
-    Deserializable, HpkeError, Serializable,
+    Deserializable, HpkeError, KeyValidationError, Serializable,
 };
 
 use generic_array::{
     typenum::{Unsigned, U32, U65},
     GenericArray,
 };
-use p256::elliptic_curve::{ecdh::diffie_hellman, sec1::ToEncodedPoint};
+use p256::{
+    elliptic_curve::{
+        ecdh::{diffie_hellman, SharedSecret},
+        ff::PrimeField,
+        sec1::ToEncodedPoint,
+        AffineXCoordinate,
+    },
+    NistP256, ProjectivePoint, Scalar,
+};
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+// The P-256 field prime, big-endian. This is what public key coordinates must be less than.
+const FIELD_MODULUS: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
 
 /// An ECDH-P256 public key. This is never the point at infinity.
 #[derive(Clone)]
@@ -21,10 +37,45 @@ pub struct PublicKey(p256::PublicKey);
 #[derive(Clone)]
 pub struct PrivateKey(p256::SecretKey);
 
+#[cfg(feature = "pkcs8")]
+impl PrivateKey {
+    /// Parses a DER-encoded PKCS#8 private key
+    pub(crate) fn from_pkcs8_der(der: &[u8]) -> Result<PrivateKey, HpkeError> {
+        use p256::pkcs8::DecodePrivateKey;
+        p256::SecretKey::from_pkcs8_der(der)
+            .map(PrivateKey)
+            .map_err(|_| HpkeError::ValidationError)
+    }
+
+    /// Encodes this private key as DER-encoded PKCS#8
+    pub(crate) fn to_pkcs8_der(&self) -> zeroize::Zeroizing<crate::Vec<u8>> {
+        use p256::pkcs8::EncodePrivateKey;
+        // This only fails if the DER writer runs out of room, which it can't, since it allocates
+        let doc = self.0.to_pkcs8_der().expect("PKCS#8 encoding failed");
+        zeroize::Zeroizing::new(doc.as_ref().to_vec())
+    }
+}
+
 // The underlying type is zeroize-on-drop
 /// A bare DH computation result
 pub struct KexResult(p256::ecdh::SharedSecret);
 
+// The underlying types are zeroize-on-drop
+impl ZeroizeOnDrop for PrivateKey {}
+impl ZeroizeOnDrop for KexResult {}
+
+impl ConstantTimeEq for PrivateKey {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl ConstantTimeEq for KexResult {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.as_bytes().ct_eq(other.0.as_bytes())
+    }
+}
+
 // Everything is serialized and deserialized in uncompressed form
 impl Serializable for PublicKey {
     // RFC 9180 §7.1: Npk of DHKEM(P-256, HKDF-SHA256) is 65
@@ -49,7 +100,7 @@ impl Deserializable for PublicKey {
         // PublicKey::from_sec1_bytes() will error if it receives the point at infinity. This is
         // because its submethod, PublicKey::from_encoded_point(), does this check explicitly.
         let parsed =
-            p256::PublicKey::from_sec1_bytes(encoded).map_err(|_| HpkeError::ValidationError)?;
+            p256::PublicKey::from_sec1_bytes(encoded).map_err(|_| invalid_pubkey(encoded))?;
         Ok(PublicKey(parsed))
     }
 }
@@ -72,12 +123,86 @@ impl Deserializable for PrivateKey {
         // Invariant: PrivateKey is in [1,p). This is preserved here.
         // SecretKey::from_be_bytes() directly checks that the value isn't zero. And its submethod,
         // ScalarCore::from_be_bytes() checks that the value doesn't exceed the modulus.
-        let sk = p256::SecretKey::from_be_bytes(encoded).map_err(|_| HpkeError::ValidationError)?;
+        let sk = p256::SecretKey::from_be_bytes(encoded).map_err(|_| invalid_privkey(encoded))?;
 
         Ok(PrivateKey(sk))
     }
 }
 
+impl PublicKey {
+    /// Fully validates an encoded public key: checks that it's an uncompressed point whose
+    /// coordinates are reduced, that it lies on the curve, and that it isn't the point at infinity.
+    /// The curve has cofactor 1, so every point on it is in the prime-order subgroup.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if and only if `from_bytes` would accept `encoded`. Otherwise, returns the
+    /// first problem found.
+    pub fn validate_bytes(encoded: &[u8]) -> Result<(), KeyValidationError> {
+        check_uncompressed_point_encoding(encoded, &FIELD_MODULUS)?;
+
+        // The encoding is well-formed, so the only thing that can go wrong now is the curve
+        // equation. from_sec1_bytes() checks that, and also rejects the point at infinity, which
+        // has no affine encoding, so we've already ruled it out.
+        p256::PublicKey::from_sec1_bytes(encoded)
+            .map(|_| ())
+            .map_err(|_| KeyValidationError::NotOnCurve)
+    }
+
+    /// Re-runs `validate_bytes` on this key's encoding. This always succeeds for keys constructed
+    /// by this crate, and exists for symmetry with key types that admit weak values.
+    pub fn validate(&self) -> Result<(), KeyValidationError> {
+        Self::validate_bytes(&self.to_bytes())
+    }
+}
+
+impl PrivateKey {
+    /// Validates an encoded private key: checks that it's the right length, and that the scalar
+    /// it encodes is in the range `[1,n)` where `n` is the group order.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if and only if `from_bytes` would accept `encoded`. Otherwise, returns
+    /// `KeyValidationError::IncorrectLength`, `KeyValidationError::ZeroScalar`, or
+    /// `KeyValidationError::ScalarOutOfRange`.
+    pub fn validate_bytes(encoded: &[u8]) -> Result<(), KeyValidationError> {
+        if encoded.len() != 32 {
+            return Err(KeyValidationError::IncorrectLength(32, encoded.len()));
+        }
+
+        // from_be_bytes() does the range check in constant time. If it fails, the only thing left
+        // to find out is which end of the range was violated.
+        if p256::SecretKey::from_be_bytes(encoded).is_ok() {
+            Ok(())
+        } else if bool::from(encoded.ct_eq(&[0u8; 32])) {
+            Err(KeyValidationError::ZeroScalar)
+        } else {
+            Err(KeyValidationError::ScalarOutOfRange)
+        }
+    }
+
+    /// Re-runs `validate_bytes` on this key's encoding. This always succeeds for keys constructed
+    /// by this crate, and exists for symmetry with key types that admit weak values.
+    pub fn validate(&self) -> Result<(), KeyValidationError> {
+        Self::validate_bytes(&self.to_bytes())
+    }
+}
+
+// Only called once parsing has already failed, so the happy path doesn't pay for diagnostics. If
+// the validator somehow disagrees with the parser, fall back to the generic error.
+fn invalid_pubkey(encoded: &[u8]) -> HpkeError {
+    PublicKey::validate_bytes(encoded)
+        .err()
+        .map_or(HpkeError::ValidationError, HpkeError::from)
+}
+
+// Same as above, but for private keys
+fn invalid_privkey(encoded: &[u8]) -> HpkeError {
+    PrivateKey::validate_bytes(encoded)
+        .err()
+        .map_or(HpkeError::ValidationError, HpkeError::from)
+}
+
 // DH results are serialized in the same way as public keys
 impl Serializable for KexResult {
     // RFC 9180 §4.1
@@ -92,6 +217,55 @@ impl Serializable for KexResult {
     }
 }
 
+/// The multiples 0·P, 1·P, ..., 15·P of a point P. This lets us compute k·P with a 4-bit fixed
+/// window, which does a quarter of the point additions of the bit-at-a-time ladder that p256 uses.
+#[derive(Clone)]
+struct WindowTable([ProjectivePoint; 16]);
+
+impl WindowTable {
+    fn new(p: ProjectivePoint) -> WindowTable {
+        let mut table = [ProjectivePoint::IDENTITY; 16];
+        let mut acc = ProjectivePoint::IDENTITY;
+        for multiple in table.iter_mut().skip(1) {
+            acc += p;
+            *multiple = acc;
+        }
+        WindowTable(table)
+    }
+
+    /// Computes k·P in constant time
+    fn mul(&self, k: &Scalar) -> ProjectivePoint {
+        let mut k_bytes = k.to_repr();
+        let mut acc = ProjectivePoint::IDENTITY;
+
+        // Go through k a nibble at a time, most significant first
+        for byte in k_bytes.iter() {
+            for nibble in [byte >> 4, byte & 0x0f] {
+                acc = acc.double().double().double().double();
+
+                // Look up nibble·P without branching on or indexing by the nibble. The addition
+                // formulas are complete, so adding the identity is fine.
+                let mut term = ProjectivePoint::IDENTITY;
+                for (i, multiple) in self.0.iter().enumerate() {
+                    term.conditional_assign(multiple, (i as u8).ct_eq(&nibble));
+                }
+                acc += term;
+            }
+        }
+
+        k_bytes.zeroize();
+        acc
+    }
+}
+
+/// A P-256 public key along with window tables for it and for the generator
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct PublicKeyTable {
+    generator: WindowTable,
+    pk: WindowTable,
+}
+
 /// Represents ECDH functionality over NIST curve P-256
 pub struct DhP256 {}
 
@@ -102,6 +276,8 @@ impl DhKeyExchange for DhP256 {
     type PrivateKey = PrivateKey;
     #[doc(hidden)]
     type KexResult = KexResult;
+    #[doc(hidden)]
+    type PublicKeyTable = PublicKeyTable;
 
     /// Converts an P256 private key to a public key
     #[doc(hidden)]
@@ -130,6 +306,34 @@ impl DhKeyExchange for DhP256 {
         Ok(KexResult(dh_res))
     }
 
+    /// Builds window tables for the generator and the given pubkey. This costs about 30 point
+    /// additions, which is made back after a single `dh_with_table` and `sk_to_pk_with_table`.
+    #[doc(hidden)]
+    fn precompute(pk: &PublicKey) -> PublicKeyTable {
+        PublicKeyTable {
+            generator: WindowTable::new(ProjectivePoint::GENERATOR),
+            pk: WindowTable::new(pk.0.to_projective()),
+        }
+    }
+
+    /// Same as `sk_to_pk`, but uses the generator table
+    #[doc(hidden)]
+    fn sk_to_pk_with_table(sk: &PrivateKey, table: &PublicKeyTable) -> PublicKey {
+        let point = table.generator.mul(&sk.0.to_nonzero_scalar());
+        // The same argument as in sk_to_pk shows this isn't the point at infinity
+        let pk = p256::PublicKey::from_affine(point.to_affine())
+            .expect("sk·G cannot be the point at infinity");
+        PublicKey(pk)
+    }
+
+    /// Same as `dh`, but uses the pubkey table. This is likewise infallible.
+    #[doc(hidden)]
+    fn dh_with_table(sk: &PrivateKey, table: &PublicKeyTable) -> Result<KexResult, DhError> {
+        // The same argument as in dh shows this isn't the point at infinity
+        let point = table.pk.mul(&sk.0.to_nonzero_scalar()).to_affine();
+        Ok(KexResult(SharedSecret::<NistP256>::from(point.x())))
+    }
+
     // RFC 9180 §7.1.3:
     // def DeriveKeyPair(ikm):
     //   dkp_prk = LabeledExtract("", "dkp_prk", ikm)
@@ -150,7 +354,10 @@ impl DhKeyExchange for DhP256 {
     /// ID. The keying material SHOULD have as many bits of entropy as the bit length of a secret
     /// key, i.e., 256.
     #[doc(hidden)]
-    fn derive_keypair<Kdf: KdfTrait>(suite_id: &KemSuiteId, ikm: &[u8]) -> (PrivateKey, PublicKey) {
+    fn derive_keypair_with_counter<Kdf: KdfTrait>(
+        suite_id: &KemSuiteId,
+        ikm: &[u8],
+    ) -> Result<(PrivateKey, PublicKey, Option<u8>), HpkeError> {
         // Write the label into a byte buffer and extract from the IKM
         let (_, hkdf_ctx) = labeled_extract::<Kdf>(&[], suite_id, b"dkp_prk", ikm);
 
@@ -169,13 +376,13 @@ impl DhKeyExchange for DhP256 {
             // keypair. Recall the invariant of PrivateKey: it is a value in the range [1,p).
             if let Ok(sk) = PrivateKey::from_bytes(&buf) {
                 let pk = Self::sk_to_pk(&sk);
-                return (sk, pk);
+                return Ok((sk, pk, Some(counter)));
             }
         }
 
         // The code should never ever get here. The likelihood that we get 256 bad samples
         // in a row for p256 is 2^-8192.
-        panic!("DeriveKeyPair failed all attempts");
+        Err(HpkeError::KeyDerivation)
     }
 }
 
@@ -190,6 +397,7 @@ mod tests {
         Deserializable, Serializable,
     };
 
+    use crate::{HpkeError, KeyValidationError};
     use rand::{rngs::StdRng, SeedableRng};
 
     // We need this in our serialize-deserialize tests
@@ -244,6 +452,38 @@ mod tests {
             derived_dh.to_bytes().as_slice(),
             dh_res_xcoord_bytes.as_slice()
         );
+
+        // The windowed version should get the same answer
+        let table = <Kex as DhKeyExchange>::precompute(&pk_sender);
+        let derived_dh = <Kex as DhKeyExchange>::dh_with_table(&sk_recip, &table).unwrap();
+        assert_eq!(
+            derived_dh.to_bytes().as_slice(),
+            dh_res_xcoord_bytes.as_slice()
+        );
+    }
+
+    /// Tests that the windowed `sk_to_pk` and `dh` agree with the plain ones on random keys
+    #[test]
+    fn test_table_ops() {
+        type Kex = DhP256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk) = dhkex_gen_keypair::<Kex, _>(&mut csprng);
+        let table = <Kex as DhKeyExchange>::precompute(&pk);
+
+        for _ in 0..16 {
+            let (sk, _) = dhkex_gen_keypair::<Kex, _>(&mut csprng);
+            assert_eq!(
+                <Kex as DhKeyExchange>::sk_to_pk_with_table(&sk, &table),
+                <Kex as DhKeyExchange>::sk_to_pk(&sk)
+            );
+            assert_eq!(
+                <Kex as DhKeyExchange>::dh_with_table(&sk, &table)
+                    .unwrap()
+                    .to_bytes(),
+                <Kex as DhKeyExchange>::dh(&sk, &pk).unwrap().to_bytes()
+            );
+        }
     }
 
     // Test vector comes from RFC 5903 §8.1
@@ -323,4 +563,78 @@ mod tests {
         assert!(new_sk == sk, "private key doesn't serialize correctly");
         assert!(new_pk == pk, "public key doesn't serialize correctly");
     }
+
+    /// Tests that validate_bytes accepts honest keys and gives the right diagnostic for each kind
+    /// of bad key
+    #[test]
+    fn test_key_validation() {
+        type Kex = DhP256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, pk) = dhkex_gen_keypair::<Kex, _>(&mut csprng);
+        assert_eq!(sk.validate(), Ok(()));
+        assert_eq!(pk.validate(), Ok(()));
+
+        // Public keys
+        let pk_bytes = pk.to_bytes();
+        assert_eq!(
+            PublicKey::validate_bytes(&pk_bytes[..33]),
+            Err(KeyValidationError::IncorrectLength(65, 33))
+        );
+        let mut bad = pk_bytes;
+        bad[0] = 0x00;
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::PointAtInfinity)
+        );
+        bad[0] = 0x02;
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::UnsupportedPointEncoding(0x02))
+        );
+        let mut bad = pk_bytes;
+        bad[1..33].copy_from_slice(&super::FIELD_MODULUS);
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::NonCanonicalEncoding)
+        );
+        let mut bad = pk_bytes;
+        bad[64] ^= 1;
+        assert_eq!(
+            PublicKey::validate_bytes(&bad),
+            Err(KeyValidationError::NotOnCurve)
+        );
+
+        // Private keys
+        assert_eq!(
+            PrivateKey::validate_bytes(&[0u8; 32]),
+            Err(KeyValidationError::ZeroScalar)
+        );
+        assert_eq!(
+            PrivateKey::validate_bytes(&[0xffu8; 32]),
+            Err(KeyValidationError::ScalarOutOfRange)
+        );
+        assert_eq!(
+            PrivateKey::validate_bytes(&[1u8; 31]),
+            Err(KeyValidationError::IncorrectLength(32, 31))
+        );
+
+        // from_bytes should report the same diagnostics
+        assert_eq!(
+            PublicKey::from_bytes(&bad).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::NotOnCurve))
+        );
+        assert_eq!(
+            PublicKey::from_bytes(&pk_bytes[..64]).err(),
+            Some(HpkeError::IncorrectInputLength(65, 64))
+        );
+        assert_eq!(
+            PrivateKey::from_bytes(&[0u8; 32]).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::ZeroScalar))
+        );
+        assert_eq!(
+            PrivateKey::from_bytes(&[0xffu8; 32]).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::ScalarOutOfRange))
+        );
+    }
 }
@@ -2,14 +2,17 @@ use crate::{
     dhkex::{DhError, DhKeyExchange},
     kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
     util::{enforce_equal_len, KemSuiteId},
-    Deserializable, HpkeError, Serializable,
+    Deserializable, HpkeError, KeyValidationError, Serializable,
 };
 
+use curve25519_dalek::edwards::CompressedEdwardsY;
 use generic_array::{
     typenum::{self, Unsigned},
     GenericArray,
 };
-use subtle::ConstantTimeEq;
+use sha2::{Digest, Sha512};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
 // We wrap the types in order to abstract away the dalek dep
 
@@ -26,6 +29,22 @@ pub struct PrivateKey(x25519_dalek::StaticSecret);
 /// A bare DH computation result
 pub struct KexResult(x25519_dalek::SharedSecret);
 
+// The underlying types are zeroize-on-drop
+impl ZeroizeOnDrop for PrivateKey {}
+impl ZeroizeOnDrop for KexResult {}
+
+impl ConstantTimeEq for PrivateKey {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.as_bytes().ct_eq(other.0.as_bytes())
+    }
+}
+
+impl ConstantTimeEq for KexResult {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.as_bytes().ct_eq(other.0.as_bytes())
+    }
+}
+
 // Oh I love an excuse to break out type-level integers
 impl Serializable for PublicKey {
     // RFC 9180 §7.1 Table 2: Npk of DHKEM(X25519, HKDF-SHA256) is 32
@@ -83,6 +102,159 @@ impl Deserializable for PrivateKey {
     }
 }
 
+// The u-coordinates of the points of small order on Curve25519 and its twist, with the top bit
+// cleared. This is the list from libsodium's crypto_scalarmult_curve25519 blocklist. The last
+// three are p-1, p, and p+1. p-1 is the canonical encoding of -1, and p and p+1 are non-canonical
+// aliases of 0 and 1.
+const SMALL_ORDER_POINTS: [[u8; 32]; 7] = [
+    // 0
+    [0; 32],
+    // 1
+    [
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ],
+    // A point of order 8
+    [
+        0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4,
+        0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16, 0x5f, 0x49,
+        0xb8, 0x00,
+    ],
+    // Another point of order 8
+    [
+        0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83, 0xef,
+        0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd, 0xd0, 0x9f,
+        0x11, 0x57,
+    ],
+    // p-1, which is -1, a point of order 2
+    [
+        0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+    // p, which is the same as 0
+    [
+        0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+    // p+1, which is the same as 1
+    [
+        0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+];
+
+impl PublicKey {
+    /// Validates an encoded public key. `from_bytes` accepts any 32 bytes, since X25519 is
+    /// defined on all of them, but a third-party key can still be useless or malicious. This checks
+    /// that the key isn't one of the small-order points (which make every DH result all-zero, and
+    /// thus make encap fail), and that it's canonically encoded, i.e., the top bit is clear and the
+    /// u-coordinate is less than p = 2^255 - 19.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if the key is good. Otherwise returns
+    /// `KeyValidationError::IncorrectLength`, `KeyValidationError::SmallOrder`, or
+    /// `KeyValidationError::NonCanonicalEncoding`, checked in that order.
+    pub fn validate_bytes(encoded: &[u8]) -> Result<(), KeyValidationError> {
+        if encoded.len() != 32 {
+            return Err(KeyValidationError::IncorrectLength(32, encoded.len()));
+        }
+
+        // X25519 ignores the top bit, so ignore it when comparing against the blocklist. Public
+        // keys aren't secret, so none of this needs to be constant-time.
+        let mut masked = [0u8; 32];
+        masked.copy_from_slice(encoded);
+        masked[31] &= 0x7f;
+        if SMALL_ORDER_POINTS.contains(&masked) {
+            return Err(KeyValidationError::SmallOrder);
+        }
+
+        // Canonical encodings have the top bit clear and are less than p. With the top bit clear,
+        // u >= p iff the top 31 bytes are all max (0x7f then 0xff..) and the bottom byte is >= 0xed
+        let top_bit_set = encoded[31] & 0x80 != 0;
+        let at_least_p =
+            masked[31] == 0x7f && masked[1..31].iter().all(|&b| b == 0xff) && masked[0] >= 0xed;
+        if top_bit_set || at_least_p {
+            return Err(KeyValidationError::NonCanonicalEncoding);
+        }
+
+        Ok(())
+    }
+
+    /// Runs `validate_bytes` on this key's encoding
+    pub fn validate(&self) -> Result<(), KeyValidationError> {
+        Self::validate_bytes(self.0.as_bytes())
+    }
+
+    /// Converts an Ed25519 public key to the X25519 public key of the same point, i.e., the
+    /// Montgomery u-coordinate `(1 + y) / (1 - y)` of the Edwards point (RFC 7748 §4.1). This is
+    /// what libsodium's `crypto_sign_ed25519_pk_to_curve25519` does. Together with
+    /// `PrivateKey::from_ed25519`, this lets an Ed25519 identity receive HPKE messages without a
+    /// second keypair.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidKey(KeyValidationError::NotOnCurve))` if `ed_pk` doesn't
+    /// decode to a point, and `Err(HpkeError::InvalidKey(KeyValidationError::SmallOrder))` if
+    /// the point has small order.
+    pub fn from_ed25519(ed_pk: &[u8; 32]) -> Result<PublicKey, HpkeError> {
+        let point = CompressedEdwardsY(*ed_pk)
+            .decompress()
+            .ok_or(HpkeError::InvalidKey(KeyValidationError::NotOnCurve))?;
+        if point.is_small_order() {
+            return Err(HpkeError::InvalidKey(KeyValidationError::SmallOrder));
+        }
+        Ok(PublicKey(x25519_dalek::PublicKey::from(
+            point.to_montgomery().to_bytes(),
+        )))
+    }
+}
+
+impl PrivateKey {
+    /// Validates an encoded private key. Every 32-byte string is a valid X25519 private key, since
+    /// clamping maps it to a nonzero scalar (see `from_bytes`), so this only checks the length.
+    pub fn validate_bytes(encoded: &[u8]) -> Result<(), KeyValidationError> {
+        if encoded.len() != 32 {
+            return Err(KeyValidationError::IncorrectLength(32, encoded.len()));
+        }
+        Ok(())
+    }
+
+    /// Always succeeds. See `validate_bytes`.
+    pub fn validate(&self) -> Result<(), KeyValidationError> {
+        Ok(())
+    }
+
+    /// Converts an Ed25519 private key, given as its 32-byte seed, to the X25519 private key with
+    /// the same scalar, i.e., the first half of SHA-512(seed), clamped (RFC 8032 §5.1.5). This is
+    /// what libsodium's `crypto_sign_ed25519_sk_to_curve25519` does. The public key of the result
+    /// is `PublicKey::from_ed25519` of the Ed25519 public key.
+    ///
+    /// libsodium and some other libraries store Ed25519 private keys as 64 bytes, `seed || pk`.
+    /// The seed is the first 32.
+    pub fn from_ed25519(ed_seed: &[u8; 32]) -> PrivateKey {
+        let mut h = Zeroizing::new([0u8; 64]);
+        h.copy_from_slice(&Sha512::digest(ed_seed));
+
+        // Clamp, so that to_bytes() gives the same bytes as libsodium
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&h[..32]);
+        scalar[0] &= 248;
+        scalar[31] &= 127;
+        scalar[31] |= 64;
+
+        // x25519-dalek only takes the scalar by value. The copy passed in becomes the secret,
+        // which zeroizes itself on drop, and the local one is zeroized here.
+        let sk = x25519_dalek::StaticSecret::from(scalar);
+        scalar.zeroize();
+        PrivateKey(sk)
+    }
+}
+
 impl Serializable for KexResult {
     // RFC 9180 §4.1: For X25519 and X448, the size Ndh is equal to 32 and 56, respectively
     type OutputSize = typenum::U32;
@@ -104,6 +276,8 @@ impl DhKeyExchange for X25519 {
     type PrivateKey = PrivateKey;
     #[doc(hidden)]
     type KexResult = KexResult;
+    #[doc(hidden)]
+    type PublicKeyTable = PublicKey;
 
     /// Converts an X25519 private key to a public key
     #[doc(hidden)]
@@ -126,6 +300,23 @@ impl DhKeyExchange for X25519 {
         }
     }
 
+    // x25519-dalek already does sk_to_pk with a precomputed basepoint table, and there's no table
+    // that speeds up the Montgomery ladder for a variable base. So the table is just the pubkey.
+    #[doc(hidden)]
+    fn precompute(pk: &PublicKey) -> PublicKey {
+        pk.clone()
+    }
+
+    #[doc(hidden)]
+    fn sk_to_pk_with_table(sk: &PrivateKey, _: &PublicKey) -> PublicKey {
+        Self::sk_to_pk(sk)
+    }
+
+    #[doc(hidden)]
+    fn dh_with_table(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, DhError> {
+        Self::dh(sk, pk)
+    }
+
     // RFC 9180 §7.1.3
     // def DeriveKeyPair(ikm):
     //   dkp_prk = LabeledExtract("", "dkp_prk", ikm)
@@ -136,7 +327,10 @@ impl DhKeyExchange for X25519 {
     /// ID. The keying material SHOULD have as many bits of entropy as the bit length of a secret
     /// key, i.e., 256.
     #[doc(hidden)]
-    fn derive_keypair<Kdf: KdfTrait>(suite_id: &KemSuiteId, ikm: &[u8]) -> (PrivateKey, PublicKey) {
+    fn derive_keypair_with_counter<Kdf: KdfTrait>(
+        suite_id: &KemSuiteId,
+        ikm: &[u8],
+    ) -> Result<(PrivateKey, PublicKey, Option<u8>), HpkeError> {
         // Write the label into a byte buffer and extract from the IKM
         let (_, hkdf_ctx) = labeled_extract::<Kdf>(&[], suite_id, b"dkp_prk", ikm);
         // The buffer we hold the candidate scalar bytes in. This is the size of a private key.
@@ -148,12 +342,14 @@ impl DhKeyExchange for X25519 {
         let sk = x25519_dalek::StaticSecret::from(buf);
         let pk = x25519_dalek::PublicKey::from(&sk);
 
-        (PrivateKey(sk), PublicKey(pk))
+        // Every 32-byte string is a valid X25519 private key, so there's no counter
+        Ok((PrivateKey(sk), PublicKey(pk), None))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::KeyValidationError;
     use crate::{
         dhkex::{
             x25519::{PrivateKey, PublicKey, X25519},
@@ -193,8 +389,8 @@ mod tests {
 
         // Fill a buffer with randomness
         let orig_bytes = {
-            let mut buf = vec![0u8; <Kex as DhKeyExchange>::PublicKey::size()];
-            csprng.fill_bytes(buf.as_mut_slice());
+            let mut buf = [0u8; 32];
+            csprng.fill_bytes(&mut buf);
             buf
         };
 
@@ -226,4 +422,84 @@ mod tests {
         assert!(new_sk == sk, "private key doesn't serialize correctly");
         assert!(new_pk == pk, "public key doesn't serialize correctly");
     }
+
+    /// Tests that validate_bytes accepts honest keys, and catches every blocklisted point and
+    /// non-canonical encoding. Also checks that the blocklist is right, i.e., that every point on
+    /// it makes DH fail.
+    #[test]
+    fn test_pubkey_validation() {
+        type Kex = X25519;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, pk) = dhkex_gen_keypair::<Kex, _>(&mut csprng);
+        assert_eq!(pk.validate(), Ok(()));
+
+        for point in super::SMALL_ORDER_POINTS.iter() {
+            assert_eq!(
+                PublicKey::validate_bytes(point),
+                Err(KeyValidationError::SmallOrder)
+            );
+            let bad_pk = PublicKey::from_bytes(point).unwrap();
+            assert!(Kex::dh(&sk, &bad_pk).is_err());
+        }
+
+        // An honest key with the top bit set is non-canonical
+        let mut high_bit = pk.to_bytes();
+        high_bit[31] |= 0x80;
+        assert_eq!(
+            PublicKey::validate_bytes(&high_bit),
+            Err(KeyValidationError::NonCanonicalEncoding)
+        );
+
+        // p+2 is non-canonical but not small-order
+        let mut p_plus_2 = [0xffu8; 32];
+        p_plus_2[0] = 0xef;
+        p_plus_2[31] = 0x7f;
+        assert_eq!(
+            PublicKey::validate_bytes(&p_plus_2),
+            Err(KeyValidationError::NonCanonicalEncoding)
+        );
+
+        assert_eq!(
+            PublicKey::validate_bytes(&[1u8; 31]),
+            Err(KeyValidationError::IncorrectLength(32, 31))
+        );
+    }
+
+    /// Tests the Ed25519 conversions against libsodium's output on the RFC 8032 §7.1 TEST 1
+    /// keypair, and that they give a working X25519 keypair
+    #[test]
+    fn test_from_ed25519() {
+        use crate::HpkeError;
+        use hex_literal::hex;
+
+        let ed_seed = hex!("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let ed_pk = hex!("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+
+        let sk = PrivateKey::from_ed25519(&ed_seed);
+        let pk = PublicKey::from_ed25519(&ed_pk).unwrap();
+        assert_eq!(
+            sk.to_bytes().as_slice(),
+            hex!("307c83864f2833cb427a2ef1c00a013cfdff2768d980c0a3a520f006904de94f")
+        );
+        assert_eq!(
+            pk.to_bytes().as_slice(),
+            hex!("d85e07ec22b0ad881537c2f44d662d1a143cf830c57aca4305d85c7a90f6b62e")
+        );
+        assert!(X25519::sk_to_pk(&sk) == pk);
+
+        // y = 2 isn't the y-coordinate of any point, and y = 1 is the identity
+        let mut not_on_curve = [0u8; 32];
+        not_on_curve[0] = 2;
+        assert_eq!(
+            PublicKey::from_ed25519(&not_on_curve).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::NotOnCurve))
+        );
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert_eq!(
+            PublicKey::from_ed25519(&identity).err(),
+            Some(HpkeError::InvalidKey(KeyValidationError::SmallOrder))
+        );
+    }
 }
@@ -0,0 +1,874 @@
+//! A dyn-safe facade over the ciphersuites compiled into this crate. Everything here takes and
+//! returns bytes, so a suite can be picked at runtime, e.g., from a config file, and stored as a
+//! `Box<dyn HpkeSuite>`.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::dyn_suite::{self, DynOpModeR, DynOpModeS};
+//!
+//! let mut csprng = StdRng::from_entropy();
+//!
+//! // DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20Poly1305
+//! let suite = dyn_suite::by_id(0x0020, 0x0001, 0x0003).expect("suite not compiled in");
+//! let (sk_recip, pk_recip) = suite.gen_keypair(&mut csprng);
+//!
+//! let (encapped_key, ciphertext) = suite
+//!     .seal(&DynOpModeS::Base, &pk_recip, b"info", b"hello", b"aad", &mut csprng)
+//!     .unwrap();
+//! let plaintext = suite
+//!     .open(&DynOpModeR::Base, &sk_recip, &encapped_key, b"info", &ciphertext, b"aad")
+//!     .unwrap();
+//! assert_eq!(plaintext, b"hello");
+//! # }
+//! ```
+
+use crate::{
+    aad::AadBuilder,
+    aead::{
+        Aead, AeadCtxR, AeadCtxS, AesGcm128, AesGcm256, AesOcb128, AesOcb256, ChaCha20Poly1305,
+        ExportOnlyAead,
+    },
+    kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
+    kem::Kem as KemTrait,
+    setup_receiver, setup_sender, single_shot_open, single_shot_seal, Deserializable, HpkeError,
+    OpModeR, OpModeS, PskBundle, Serializable,
+};
+
+use crate::{Box, Vec};
+
+use core::marker::PhantomData;
+
+use rand_core::CryptoRngCore;
+use zeroize::{Zeroize, Zeroizing};
+
+/// The sender's operation mode, with keys as bytes. See `OpModeS`.
+#[derive(Clone, Copy)]
+pub enum DynOpModeS<'a> {
+    /// No extra information included
+    Base,
+    /// A preshared key known to the sender and receiver
+    Psk(PskBundle<'a>),
+    /// The sender's identity private key. The public key is computed from it.
+    Auth(&'a [u8]),
+    /// Both of the above
+    AuthPsk(&'a [u8], PskBundle<'a>),
+}
+
+/// The receiver's operation mode, with keys as bytes. See `OpModeR`.
+#[derive(Clone, Copy)]
+pub enum DynOpModeR<'a> {
+    /// No extra information included
+    Base,
+    /// A preshared key known to the sender and receiver
+    Psk(PskBundle<'a>),
+    /// The sender's identity public key
+    Auth(&'a [u8]),
+    /// Both of the above
+    AuthPsk(&'a [u8], PskBundle<'a>),
+}
+
+impl<'a> DynOpModeS<'a> {
+    /// Parses the keys in this mode for the KEM `Kem`
+    fn to_typed<Kem: KemTrait>(self) -> Result<OpModeS<'a, Kem>, HpkeError> {
+        let keypair = |sk_bytes: &[u8]| -> Result<_, HpkeError> {
+            let sk = Kem::PrivateKey::from_bytes(sk_bytes)?;
+            let pk = Kem::sk_to_pk(&sk);
+            Ok((sk, pk))
+        };
+        Ok(match self {
+            DynOpModeS::Base => OpModeS::Base,
+            DynOpModeS::Psk(bundle) => OpModeS::Psk(bundle),
+            DynOpModeS::Auth(sk) => OpModeS::Auth(keypair(sk)?),
+            DynOpModeS::AuthPsk(sk, bundle) => OpModeS::AuthPsk(keypair(sk)?, bundle),
+        })
+    }
+}
+
+impl<'a> DynOpModeR<'a> {
+    /// Parses the keys in this mode for the KEM `Kem`
+    fn to_typed<Kem: KemTrait>(self) -> Result<OpModeR<'a, Kem>, HpkeError> {
+        Ok(match self {
+            DynOpModeR::Base => OpModeR::Base,
+            DynOpModeR::Psk(bundle) => OpModeR::Psk(bundle),
+            DynOpModeR::Auth(pk) => OpModeR::Auth(Kem::PublicKey::from_bytes(pk)?),
+            DynOpModeR::AuthPsk(pk, bundle) => {
+                OpModeR::AuthPsk(Kem::PublicKey::from_bytes(pk)?, bundle)
+            }
+        })
+    }
+}
+
+/// A sender's encryption context, behind a trait object. See `AeadCtxS`.
+pub trait DynAeadCtxS: Send + Sync {
+    /// Seals the plaintext, returning the ciphertext with the tag appended. See `AeadCtxS::seal`.
+    fn seal(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError>;
+
+    /// Fills `out_buf` with secret bytes derived from this context. See `AeadCtxS::export`.
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError>;
+}
+
+/// A receiver's decryption context, behind a trait object. See `AeadCtxR`.
+pub trait DynAeadCtxR: Send + Sync {
+    /// Opens a ciphertext with the tag appended. See `AeadCtxR::open`.
+    fn open(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError>;
+
+    /// Fills `out_buf` with secret bytes derived from this context. See `AeadCtxR::export`.
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError>;
+}
+
+impl<A, Kdf, Kem> DynAeadCtxS for AeadCtxS<A, Kdf, Kem>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    AeadCtxS<A, Kdf, Kem>: Send + Sync,
+{
+    fn seal(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        AeadCtxS::seal(self, plaintext, aad)
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
+        AeadCtxS::export(self, exporter_ctx, out_buf)
+    }
+}
+
+impl<A, Kdf, Kem> DynAeadCtxR for AeadCtxR<A, Kdf, Kem>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    AeadCtxR<A, Kdf, Kem>: Send + Sync,
+{
+    fn open(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        AeadCtxR::open(self, ciphertext, aad)
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
+        AeadCtxR::export(self, exporter_ctx, out_buf)
+    }
+}
+
+/// An HPKE ciphersuite behind a trait object. Keys, encapsulated keys, and ciphertexts are all
+/// bytes, in the same encodings as `Serializable::to_bytes`. Ciphertexts have the tag appended.
+/// Get one from `by_id` or `all`, or make one with `Suite`.
+pub trait HpkeSuite: Send + Sync {
+    /// The KEM's algorithm identifier
+    fn kem_id(&self) -> u16;
+
+    /// The KDF's algorithm identifier
+    fn kdf_id(&self) -> u16;
+
+    /// The AEAD's algorithm identifier
+    fn aead_id(&self) -> u16;
+
+    /// Generates a random keypair. Returns `(private_key, public_key)`. The private key is
+    /// zeroized when dropped. See `Kem::gen_keypair`.
+    fn gen_keypair(&self, csprng: &mut dyn CryptoRngCore) -> (Zeroizing<Vec<u8>>, Vec<u8>);
+
+    /// Deterministically derives a keypair from `ikm`. Returns `(private_key, public_key)`. The
+    /// private key is zeroized when dropped. See `Kem::derive_keypair`.
+    fn derive_keypair(&self, ikm: &[u8]) -> (Zeroizing<Vec<u8>>, Vec<u8>);
+
+    /// Initiates an encryption context. Returns the encapsulated key and the context. See
+    /// `setup_sender`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns an error if a key doesn't parse, or if encapsulation fails.
+    #[allow(clippy::type_complexity)]
+    fn setup_sender(
+        &self,
+        mode: &DynOpModeS,
+        pk_recip: &[u8],
+        info: &[u8],
+        csprng: &mut dyn CryptoRngCore,
+    ) -> Result<(Vec<u8>, Box<dyn DynAeadCtxS>), HpkeError>;
+
+    /// Initiates a decryption context. See `setup_receiver`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns an error if a key or the encapsulated key doesn't parse, or if decapsulation fails.
+    fn setup_receiver(
+        &self,
+        mode: &DynOpModeR,
+        sk_recip: &[u8],
+        encapped_key: &[u8],
+        info: &[u8],
+    ) -> Result<Box<dyn DynAeadCtxR>, HpkeError>;
+
+    /// Encrypts a single message. Returns the encapsulated key and the ciphertext. See
+    /// `single_shot_seal`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns an error if a key doesn't parse, or if encapsulation or sealing fails.
+    #[allow(clippy::too_many_arguments)]
+    fn seal(
+        &self,
+        mode: &DynOpModeS,
+        pk_recip: &[u8],
+        info: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+        csprng: &mut dyn CryptoRngCore,
+    ) -> Result<(Vec<u8>, Vec<u8>), HpkeError>;
+
+    /// Decrypts a single message. See `single_shot_open`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns an error if a key or the encapsulated key doesn't parse, or if decapsulation or
+    /// opening fails.
+    fn open(
+        &self,
+        mode: &DynOpModeR,
+        sk_recip: &[u8],
+        encapped_key: &[u8],
+        info: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, HpkeError>;
+}
+
+// Serializes a private key into a Vec that's zeroized on drop, and zeroizes the intermediate copy
+fn sk_to_vec<Kem: KemTrait>(sk: &Kem::PrivateKey) -> Zeroizing<Vec<u8>> {
+    let mut sk_bytes = sk.to_bytes();
+    let out = Zeroizing::new(sk_bytes.to_vec());
+    sk_bytes.zeroize();
+    out
+}
+
+/// The `HpkeSuite` for the ciphersuite `(A, Kdf, Kem)`
+pub struct Suite<A, Kdf, Kem>(PhantomData<fn() -> (A, Kdf, Kem)>);
+
+impl<A, Kdf, Kem> Suite<A, Kdf, Kem> {
+    /// Makes the suite
+    pub fn new() -> Self {
+        Suite(PhantomData)
+    }
+}
+
+impl<A, Kdf, Kem> Default for Suite<A, Kdf, Kem> {
+    fn default() -> Self {
+        Suite::new()
+    }
+}
+
+impl<A, Kdf, Kem> HpkeSuite for Suite<A, Kdf, Kem>
+where
+    A: Aead + 'static,
+    Kdf: KdfTrait + 'static,
+    Kem: KemTrait + 'static,
+    AeadCtxS<A, Kdf, Kem>: Send + Sync,
+    AeadCtxR<A, Kdf, Kem>: Send + Sync,
+{
+    fn kem_id(&self) -> u16 {
+        Kem::KEM_ID
+    }
+
+    fn kdf_id(&self) -> u16 {
+        Kdf::KDF_ID
+    }
+
+    fn aead_id(&self) -> u16 {
+        A::AEAD_ID
+    }
+
+    fn gen_keypair(&self, csprng: &mut dyn CryptoRngCore) -> (Zeroizing<Vec<u8>>, Vec<u8>) {
+        let (sk, pk) = Kem::gen_keypair_from_rng(csprng);
+        (sk_to_vec::<Kem>(&sk), pk.to_bytes().to_vec())
+    }
+
+    fn derive_keypair(&self, ikm: &[u8]) -> (Zeroizing<Vec<u8>>, Vec<u8>) {
+        let (sk, pk) = Kem::derive_keypair(ikm);
+        (sk_to_vec::<Kem>(&sk), pk.to_bytes().to_vec())
+    }
+
+    fn setup_sender(
+        &self,
+        mode: &DynOpModeS,
+        pk_recip: &[u8],
+        info: &[u8],
+        csprng: &mut dyn CryptoRngCore,
+    ) -> Result<(Vec<u8>, Box<dyn DynAeadCtxS>), HpkeError> {
+        let mode = mode.to_typed::<Kem>()?;
+        let pk_recip = Kem::PublicKey::from_bytes(pk_recip)?;
+        let (encapped_key, ctx) = setup_sender::<A, Kdf, Kem, _>(&mode, &pk_recip, info, csprng)?;
+        Ok((encapped_key.to_bytes().to_vec(), Box::new(ctx)))
+    }
+
+    fn setup_receiver(
+        &self,
+        mode: &DynOpModeR,
+        sk_recip: &[u8],
+        encapped_key: &[u8],
+        info: &[u8],
+    ) -> Result<Box<dyn DynAeadCtxR>, HpkeError> {
+        let mode = mode.to_typed::<Kem>()?;
+        let sk_recip = Kem::PrivateKey::from_bytes(sk_recip)?;
+        let encapped_key = Kem::EncappedKey::from_bytes(encapped_key)?;
+        let ctx = setup_receiver::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, info)?;
+        Ok(Box::new(ctx))
+    }
+
+    fn seal(
+        &self,
+        mode: &DynOpModeS,
+        pk_recip: &[u8],
+        info: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+        csprng: &mut dyn CryptoRngCore,
+    ) -> Result<(Vec<u8>, Vec<u8>), HpkeError> {
+        let mode = mode.to_typed::<Kem>()?;
+        let pk_recip = Kem::PublicKey::from_bytes(pk_recip)?;
+        let (encapped_key, ciphertext) =
+            single_shot_seal::<A, Kdf, Kem, _>(&mode, &pk_recip, info, plaintext, aad, csprng)?;
+        Ok((encapped_key.to_bytes().to_vec(), ciphertext))
+    }
+
+    fn open(
+        &self,
+        mode: &DynOpModeR,
+        sk_recip: &[u8],
+        encapped_key: &[u8],
+        info: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, HpkeError> {
+        let mode = mode.to_typed::<Kem>()?;
+        let sk_recip = Kem::PrivateKey::from_bytes(sk_recip)?;
+        let encapped_key = Kem::EncappedKey::from_bytes(encapped_key)?;
+        single_shot_open::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, info, ciphertext, aad)
+    }
+}
+
+// Calls $f!(A, Kdf, Kem) for every ciphersuite compiled into the crate, as a sequence of
+// statements
+macro_rules! for_each_suite {
+    ($f:ident) => {
+        #[cfg(feature = "x25519")]
+        for_each_suite!(@kem $f, crate::kem::X25519HkdfSha256);
+        #[cfg(feature = "p256")]
+        for_each_suite!(@kem $f, crate::kem::DhP256HkdfSha256);
+        #[cfg(feature = "k256")]
+        for_each_suite!(@kem $f, crate::kem::DhK256HkdfSha256);
+    };
+    (@kem $f:ident, $kem:ty) => {
+        for_each_suite!(@kdf $f, $kem, HkdfSha256);
+        for_each_suite!(@kdf $f, $kem, HkdfSha384);
+        for_each_suite!(@kdf $f, $kem, HkdfSha512);
+    };
+    (@kdf $f:ident, $kem:ty, $kdf:ty) => {
+        $f!(AesGcm128, $kdf, $kem);
+        $f!(AesGcm256, $kdf, $kem);
+        $f!(ChaCha20Poly1305, $kdf, $kem);
+        $f!(AesOcb128, $kdf, $kem);
+        $f!(AesOcb256, $kdf, $kem);
+        #[cfg(feature = "reduced-round")]
+        $f!(crate::aead::ChaCha12Poly1305, $kdf, $kem);
+        #[cfg(feature = "reduced-round")]
+        $f!(crate::aead::ChaCha8Poly1305, $kdf, $kem);
+        $f!(ExportOnlyAead, $kdf, $kem);
+    };
+}
+
+// Calls $f!($aead, Kdf, Kem) for every KEM and KDF compiled into the crate, as a sequence of
+// statements
+macro_rules! for_each_kem_kdf {
+    ($f:ident, $aead:ty) => {
+        #[cfg(feature = "x25519")]
+        for_each_kem_kdf!(@kem $f, $aead, crate::kem::X25519HkdfSha256);
+        #[cfg(feature = "p256")]
+        for_each_kem_kdf!(@kem $f, $aead, crate::kem::DhP256HkdfSha256);
+        #[cfg(feature = "k256")]
+        for_each_kem_kdf!(@kem $f, $aead, crate::kem::DhK256HkdfSha256);
+    };
+    (@kem $f:ident, $aead:ty, $kem:ty) => {
+        $f!($aead, HkdfSha256, $kem);
+        $f!($aead, HkdfSha384, $kem);
+        $f!($aead, HkdfSha512, $kem);
+    };
+}
+
+/// Returns the suite with the given algorithm identifiers, if it's compiled in. The export-only
+/// AEAD has ID `0xFFFF`. Its contexts can only `export`.
+pub fn by_id(kem_id: u16, kdf_id: u16, aead_id: u16) -> Option<Box<dyn HpkeSuite>> {
+    macro_rules! check {
+        ($aead:ty, $kdf:ty, $kem:ty) => {
+            if (kem_id, kdf_id, aead_id)
+                == (
+                    <$kem as KemTrait>::KEM_ID,
+                    <$kdf as KdfTrait>::KDF_ID,
+                    <$aead as Aead>::AEAD_ID,
+                )
+            {
+                return Some(Box::new(Suite::<$aead, $kdf, $kem>::new()));
+            }
+        };
+    }
+    for_each_suite!(check);
+
+    None
+}
+
+/// Returns every suite compiled into the crate
+pub fn all() -> Vec<Box<dyn HpkeSuite>> {
+    let mut suites: Vec<Box<dyn HpkeSuite>> = Vec::new();
+    macro_rules! push {
+        ($aead:ty, $kdf:ty, $kem:ty) => {
+            suites.push(Box::new(Suite::<$aead, $kdf, $kem>::new()));
+        };
+    }
+    for_each_suite!(push);
+
+    suites
+}
+
+/// A set of suites to look up by algorithm identifiers. Unlike `by_id`, this can hold suites whose
+/// AEAD comes from outside this crate, alongside the built-in ones, so that they can be picked or
+/// negotiated the same way.
+///
+/// To add an AEAD of your own, implement `aead::Aead` for a marker type, with `AeadImpl` being a
+/// type that implements the `aead` crate's `AeadCore`, `AeadInPlace`, `NewAead`, and `Clone`,
+/// and pick an `AEAD_ID` that no other AEAD here uses. Then pass it to `register_aead`.
+///
+/// ```
+/// # #[cfg(feature = "x25519")]
+/// # {
+/// use hpke::{aead::Aead, dyn_suite::SuiteRegistry};
+///
+/// // Stands in for a proprietary AEAD
+/// struct MyAead;
+/// impl Aead for MyAead {
+///     type AeadImpl = chacha20poly1305::XChaCha20Poly1305;
+///     const AEAD_ID: u16 = 0xFE00;
+/// }
+///
+/// let mut registry = SuiteRegistry::new();
+/// registry.register_aead::<MyAead>().unwrap();
+///
+/// // The peer prefers MyAead, and falls back to ChaCha20Poly1305
+/// let suite = registry
+///     .negotiate(&[(0x0020, 0x0001, 0xFE00), (0x0020, 0x0001, 0x0003)])
+///     .unwrap();
+/// assert_eq!(suite.aead_id(), 0xFE00);
+/// # }
+/// ```
+pub struct SuiteRegistry {
+    suites: Vec<Box<dyn HpkeSuite>>,
+}
+
+impl SuiteRegistry {
+    /// Makes a registry with every suite compiled into the crate, i.e., the ones `all` returns
+    pub fn new() -> Self {
+        SuiteRegistry { suites: all() }
+    }
+
+    /// Makes a registry with no suites in it
+    pub fn empty() -> Self {
+        SuiteRegistry { suites: Vec::new() }
+    }
+
+    /// Adds a suite to the registry
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If a suite with the same algorithm identifiers is already
+    /// registered, returns `Err(HpkeError::ValidationError)`, and the registry is unchanged.
+    pub fn register(&mut self, suite: Box<dyn HpkeSuite>) -> Result<(), HpkeError> {
+        if self
+            .get(suite.kem_id(), suite.kdf_id(), suite.aead_id())
+            .is_some()
+        {
+            return Err(HpkeError::ValidationError);
+        }
+        self.suites.push(suite);
+        Ok(())
+    }
+
+    /// Adds a suite for the AEAD `A` with every KEM and KDF compiled into the crate
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If a suite with `A::AEAD_ID` is already registered, returns
+    /// `Err(HpkeError::ValidationError)`, and the registry is unchanged.
+    pub fn register_aead<A>(&mut self) -> Result<(), HpkeError>
+    where
+        A: Aead + 'static,
+        A::AeadImpl: Send + Sync,
+    {
+        if self.suites.iter().any(|s| s.aead_id() == A::AEAD_ID) {
+            return Err(HpkeError::ValidationError);
+        }
+
+        macro_rules! push {
+            ($aead:ty, $kdf:ty, $kem:ty) => {
+                self.suites
+                    .push(Box::new(Suite::<$aead, $kdf, $kem>::new()));
+            };
+        }
+        for_each_kem_kdf!(push, A);
+
+        Ok(())
+    }
+
+    /// Returns the suite with the given algorithm identifiers, if it's registered
+    pub fn get(&self, kem_id: u16, kdf_id: u16, aead_id: u16) -> Option<&dyn HpkeSuite> {
+        self.suites
+            .iter()
+            .find(|s| (s.kem_id(), s.kdf_id(), s.aead_id()) == (kem_id, kdf_id, aead_id))
+            .map(|s| s.as_ref())
+    }
+
+    /// Picks a suite from the `(kem_id, kdf_id, aead_id)` triples a peer offered, in the peer's
+    /// order of preference. Returns the first one that's registered, or `None` if there is none.
+    pub fn negotiate(&self, offered: &[(u16, u16, u16)]) -> Option<&dyn HpkeSuite> {
+        offered
+            .iter()
+            .find_map(|&(kem_id, kdf_id, aead_id)| self.get(kem_id, kdf_id, aead_id))
+    }
+
+    /// Returns the `(kem_id, kdf_id, aead_id)` triples of every registered suite, in the order
+    /// they were registered
+    pub fn ids(&self) -> impl Iterator<Item = (u16, u16, u16)> + '_ {
+        self.suites
+            .iter()
+            .map(|s| (s.kem_id(), s.kdf_id(), s.aead_id()))
+    }
+}
+
+impl Default for SuiteRegistry {
+    fn default() -> Self {
+        SuiteRegistry::new()
+    }
+}
+
+// The version of the negotiation transcript encoding. This goes first, so a later encoding can't
+// be mistaken for this one.
+const NEGOTIATION_INFO_VERSION: &[u8] = b"hpke suite negotiation v1";
+
+// Encodes a list of suites as the concatenation of I2OSP(kem_id, 2) || I2OSP(kdf_id, 2) ||
+// I2OSP(aead_id, 2) for each one
+fn encode_suite_ids(ids: &[(u16, u16, u16)]) -> Vec<u8> {
+    ids.iter()
+        .flat_map(|&(kem_id, kdf_id, aead_id)| {
+            let mut buf = [0u8; 6];
+            buf[..2].copy_from_slice(&kem_id.to_be_bytes());
+            buf[2..4].copy_from_slice(&kdf_id.to_be_bytes());
+            buf[4..].copy_from_slice(&aead_id.to_be_bytes());
+            buf
+        })
+        .collect()
+}
+
+/// Returns the `info` to set up with after a suite negotiation. This binds the application's
+/// `info` to both parties' suite lists and to the suite that was picked, so that if anyone
+/// tampered with the negotiation, the two sides derive different keys, and the first `open`
+/// fails.
+///
+/// `offered` is the list of `(kem_id, kdf_id, aead_id)` triples the offering party sent, in its
+/// order of preference, as given to `SuiteRegistry::negotiate`. `supported` is the list of the
+/// party that picked, e.g., `SuiteRegistry::ids`. `selected` is the suite it picked. Both sides
+/// must use their own copy of any list they sent, not one that came back over the wire. The
+/// sender calls this directly. The receiver should call `verify_negotiation_info` instead.
+///
+/// The result is an `aad::AadBuilder` encoding of a version string, `info`, and the three lists.
+pub fn negotiation_info(
+    info: &[u8],
+    offered: &[(u16, u16, u16)],
+    supported: &[(u16, u16, u16)],
+    selected: (u16, u16, u16),
+) -> Vec<u8> {
+    AadBuilder::new()
+        .field("version", NEGOTIATION_INFO_VERSION)
+        .field("info", info)
+        .field("offered", encode_suite_ids(offered))
+        .field("supported", encode_suite_ids(supported))
+        .field("selected", encode_suite_ids(&[selected]))
+        .build()
+}
+
+/// The receiver's side of `negotiation_info`. This checks that `selected` is what negotiation
+/// should have picked, i.e., the first suite in `offered` that's also in `supported`, and then
+/// returns the same `info` that `negotiation_info` does.
+///
+/// The check catches a peer that claims to support a strong suite but picks a weaker one. The
+/// binding catches a peer that saw different lists than this side did. Since stripping suites from
+/// either list in transit changes one side's view of it, the key schedule breaks, even if the
+/// selection looks right.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(info)` on success. If `selected` isn't the first suite in `offered` that's also in
+/// `supported`, or no suite is in both, returns `Err(HpkeError::ValidationError)`.
+pub fn verify_negotiation_info(
+    info: &[u8],
+    offered: &[(u16, u16, u16)],
+    supported: &[(u16, u16, u16)],
+    selected: (u16, u16, u16),
+) -> Result<Vec<u8>, HpkeError> {
+    let expected = offered.iter().find(|ids| supported.contains(ids));
+    if expected != Some(&selected) {
+        return Err(HpkeError::ValidationError);
+    }
+    Ok(negotiation_info(info, offered, supported, selected))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{all, by_id, DynOpModeR, DynOpModeS};
+    use crate::{HpkeError, PskBundle};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that every compiled-in suite can be found by ID, and round-trips a message in every
+    /// mode, both single-shot and through contexts
+    #[test]
+    fn test_dyn_suites() {
+        let mut csprng = StdRng::from_entropy();
+        let psk = PskBundle {
+            psk: &[0x11; 32],
+            psk_id: b"preshared key",
+        };
+
+        for suite in all() {
+            let found = by_id(suite.kem_id(), suite.kdf_id(), suite.aead_id()).unwrap();
+            assert_eq!(found.aead_id(), suite.aead_id());
+
+            let (sk_recip, pk_recip) = suite.gen_keypair(&mut csprng);
+            let (sk_sender, pk_sender) = suite.derive_keypair(b"the sender's identity keying");
+
+            let modes = [
+                (DynOpModeS::Base, DynOpModeR::Base),
+                (DynOpModeS::Psk(psk), DynOpModeR::Psk(psk)),
+                (DynOpModeS::Auth(&sk_sender), DynOpModeR::Auth(&pk_sender)),
+                (
+                    DynOpModeS::AuthPsk(&sk_sender, psk),
+                    DynOpModeR::AuthPsk(&pk_sender, psk),
+                ),
+            ];
+            for (mode_s, mode_r) in modes.iter() {
+                let (encapped_key, mut ctx_s) = suite
+                    .setup_sender(mode_s, &pk_recip, b"info", &mut csprng)
+                    .unwrap();
+                let mut ctx_r = suite
+                    .setup_receiver(mode_r, &sk_recip, &encapped_key, b"info")
+                    .unwrap();
+
+                let (mut secret_s, mut secret_r) = ([0u8; 32], [0u8; 32]);
+                ctx_s.export(b"exp", &mut secret_s).unwrap();
+                ctx_r.export(b"exp", &mut secret_r).unwrap();
+                assert_eq!(secret_s, secret_r);
+
+                // The export-only AEAD can't seal or open
+                if suite.aead_id() == 0xFFFF {
+                    continue;
+                }
+
+                let ciphertext = ctx_s.seal(b"msg", b"aad").unwrap();
+                assert_eq!(ctx_r.open(&ciphertext, b"aad").unwrap(), b"msg");
+
+                let (encapped_key, ciphertext) = suite
+                    .seal(mode_s, &pk_recip, b"info", b"msg", b"aad", &mut csprng)
+                    .unwrap();
+                let plaintext = suite
+                    .open(
+                        mode_r,
+                        &sk_recip,
+                        &encapped_key,
+                        b"info",
+                        &ciphertext,
+                        b"aad",
+                    )
+                    .unwrap();
+                assert_eq!(plaintext, b"msg");
+            }
+
+            // Bad key encodings are errors, not panics
+            assert!(matches!(
+                suite.setup_sender(&DynOpModeS::Base, &[0u8; 3], b"", &mut csprng),
+                Err(HpkeError::IncorrectInputLength(..))
+            ));
+        }
+
+        assert!(by_id(0x1234, 0x0001, 0x0001).is_none());
+    }
+
+    /// Tests that an AEAD from outside the crate can be registered, negotiated, and used, and
+    /// that it can't shadow a registered one
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_registry() {
+        use super::SuiteRegistry;
+        use crate::aead::Aead;
+
+        struct CustomAead;
+        impl Aead for CustomAead {
+            type AeadImpl = chacha20poly1305::XChaCha20Poly1305;
+            const AEAD_ID: u16 = 0xFE00;
+        }
+
+        struct ShadowingAead;
+        impl Aead for ShadowingAead {
+            type AeadImpl = chacha20poly1305::XChaCha20Poly1305;
+            const AEAD_ID: u16 = 0x0003;
+        }
+
+        let mut registry = SuiteRegistry::new();
+        let num_builtin = registry.ids().count();
+        assert_eq!(num_builtin, all().len());
+
+        // Nothing is offered that's registered yet, so it falls through to ChaCha20Poly1305
+        let offered = [(0x0020, 0x0001, 0xFE00), (0x0020, 0x0001, 0x0003)];
+        assert_eq!(registry.negotiate(&offered).unwrap().aead_id(), 0x0003);
+        assert!(registry.negotiate(&offered[..1]).is_none());
+
+        registry.register_aead::<CustomAead>().unwrap();
+        assert!(registry.ids().count() > num_builtin);
+        let suite = registry.negotiate(&offered).unwrap();
+        assert_eq!(suite.aead_id(), 0xFE00);
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = suite.gen_keypair(&mut csprng);
+        let (encapped_key, ciphertext) = suite
+            .seal(
+                &DynOpModeS::Base,
+                &pk_recip,
+                b"info",
+                b"msg",
+                b"aad",
+                &mut csprng,
+            )
+            .unwrap();
+        let plaintext = suite
+            .open(
+                &DynOpModeR::Base,
+                &sk_recip,
+                &encapped_key,
+                b"info",
+                &ciphertext,
+                b"aad",
+            )
+            .unwrap();
+        assert_eq!(plaintext, b"msg");
+
+        // Registering an ID twice fails and leaves the registry as it was
+        let num_registered = registry.ids().count();
+        assert_eq!(
+            registry.register_aead::<CustomAead>(),
+            Err(HpkeError::ValidationError)
+        );
+        assert_eq!(
+            registry.register_aead::<ShadowingAead>(),
+            Err(HpkeError::ValidationError)
+        );
+        assert_eq!(
+            registry.register(by_id(0x0020, 0x0001, 0x0003).unwrap()),
+            Err(HpkeError::ValidationError)
+        );
+        assert_eq!(registry.ids().count(), num_registered);
+
+        // An empty registry has nothing until something is registered
+        let mut registry = SuiteRegistry::empty();
+        assert!(registry.get(0x0020, 0x0001, 0x0003).is_none());
+        registry
+            .register(by_id(0x0020, 0x0001, 0x0003).unwrap())
+            .unwrap();
+        assert!(registry.get(0x0020, 0x0001, 0x0003).is_some());
+    }
+
+    /// Tests that the receiver accepts an honest negotiation and derives the sender's keys, and
+    /// that a downgrade breaks either the selection check or the key schedule
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_negotiation_info() {
+        use super::{negotiation_info, verify_negotiation_info, SuiteRegistry};
+        use crate::{
+            aead::{AesGcm128, ChaCha20Poly1305},
+            kdf::HkdfSha256,
+            kem::{Kem as KemTrait, X25519HkdfSha256},
+            setup_receiver, setup_sender, OpModeR, OpModeS,
+        };
+
+        type Kem = X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+
+        let strong = (0x0020, 0x0001, 0x0003);
+        let weak = (0x0020, 0x0001, 0x0001);
+        // The receiver offers its suites, and the sender picks the first one it supports
+        let offered = [strong, weak];
+        let supported = [weak, strong];
+        let selected = strong;
+        let registry = SuiteRegistry::new();
+        let picked = registry.negotiate(&offered).unwrap();
+        assert_eq!(
+            (picked.kem_id(), picked.kdf_id(), picked.aead_id()),
+            selected
+        );
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+        // An honest run
+        let info_s = negotiation_info(b"app", &offered, &supported, selected);
+        let info_r = verify_negotiation_info(b"app", &offered, &supported, selected).unwrap();
+        assert_eq!(info_s, info_r);
+        let (encapped_key, mut ctx_s) = setup_sender::<ChaCha20Poly1305, Kdf, Kem, _>(
+            &OpModeS::Base,
+            &pk_recip,
+            &info_s,
+            &mut csprng,
+        )
+        .unwrap();
+        let mut ctx_r = setup_receiver::<ChaCha20Poly1305, Kdf, Kem>(
+            &OpModeR::Base,
+            &sk_recip,
+            &encapped_key,
+            &info_r,
+        )
+        .unwrap();
+        let ciphertext = ctx_s.seal(b"msg", b"").unwrap();
+        assert_eq!(ctx_r.open(&ciphertext, b"").unwrap(), b"msg");
+
+        // The sender picks the weak suite even though it supports the strong one
+        assert_eq!(
+            verify_negotiation_info(b"app", &offered, &supported, weak),
+            Err(HpkeError::ValidationError)
+        );
+        // Nothing in common
+        assert_eq!(
+            verify_negotiation_info(b"app", &[strong], &[weak], weak),
+            Err(HpkeError::ValidationError)
+        );
+
+        // An attacker strips the strong suite from the offer before the sender sees it. The
+        // sender honestly picks the weak one, and the attacker also strips the strong suite from
+        // the sender's list on the way back, so the receiver's check passes. The receiver still
+        // binds the lists it actually saw, which differ from the sender's, so opening fails.
+        let info_s = negotiation_info(b"app", &[weak], &supported, weak);
+        let info_r = verify_negotiation_info(b"app", &offered, &[weak], weak).unwrap();
+        assert_ne!(info_s, info_r);
+        let (encapped_key, mut ctx_s) =
+            setup_sender::<AesGcm128, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, &info_s, &mut csprng)
+                .unwrap();
+        let mut ctx_r = setup_receiver::<AesGcm128, Kdf, Kem>(
+            &OpModeR::Base,
+            &sk_recip,
+            &encapped_key,
+            &info_r,
+        )
+        .unwrap();
+        let ciphertext = ctx_s.seal(b"msg", b"").unwrap();
+        assert_eq!(ctx_r.open(&ciphertext, b""), Err(HpkeError::OpenError));
+
+        // The encoding is unambiguous about where one list stops and the next starts
+        assert_ne!(
+            negotiation_info(b"app", &[strong, weak], &[], strong),
+            negotiation_info(b"app", &[strong], &[weak], strong)
+        );
+    }
+}
@@ -0,0 +1,1042 @@
+//! A self-describing container for a single-shot HPKE ciphertext: the ciphersuite, the
+//! encapsulated key, an optional PSK ID hint, and the ciphertext, with a canonical binary encoding.
+//! For envelope encryption of data keys, `wrap_key` and `unwrap_key` fix the mode and info string,
+//! and `rotate_recipient` moves wrapped keys to a recipient's new key without touching the data.
+//! Keys wrapped with `wrap_key_delegable` can also be re-targeted to a new recipient by a gateway
+//! that never sees them. See `delegate` and `rewrap_key`. With the `compression` feature,
+//! `seal_to_envelope_compressed` DEFLATEs large payloads before sealing them. Read its caveats
+//! first. Old envelopes stay readable as suites change: `detect_suite` says which suite one is
+//! under, and `reencrypt` moves it to a new key or suite. While recipients are split across
+//! KEMs, `MultiKemSealer` seals one payload to all of them. Envelopes sealed with
+//! `seal_to_envelope_with_validity` carry a not-before and not-after time, which
+//! `open_envelope_with_policy` enforces. Metadata that routers and indexers need to read, but
+//! that must not be tampered with, goes in the AAD header of `seal_to_envelope_with_header`. With
+//! the `hazmat` feature, `seal_to_envelope_convergent` seals deterministically, for deduplicating
+//! storage. Read its caveats first.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     envelope::{open_envelope, seal_to_envelope, Envelope},
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     Kem, OpModeR, OpModeS,
+//! };
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_recip, pk_recip) = K::gen_keypair(&mut csprng);
+//!
+//! let envelope = seal_to_envelope::<A, Kdf, K, _>(
+//!     &OpModeS::Base,
+//!     &pk_recip,
+//!     b"info",
+//!     b"hello",
+//!     b"aad",
+//!     &mut csprng,
+//! )
+//! .unwrap();
+//! let wire = envelope.to_bytes();
+//!
+//! let envelope = Envelope::from_bytes(&wire).unwrap();
+//! let plaintext =
+//!     open_envelope::<A, Kdf, K>(&OpModeR::Base, &sk_recip, &envelope, b"info", b"aad").unwrap();
+//! assert_eq!(plaintext, b"hello");
+//! # }
+//! ```
+
+use crate::{
+    aead::Aead,
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpMode, OpModeR, OpModeS},
+    single_shot::{single_shot_open, single_shot_seal},
+    Deserializable, HpkeError, Serializable, Vec,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+#[cfg(all(feature = "text-encoding", not(feature = "std")))]
+use alloc::string as alloc_string;
+#[cfg(all(feature = "text-encoding", feature = "std"))]
+use std::string as alloc_string;
+
+#[cfg(feature = "compression")]
+mod compression;
+#[cfg(feature = "compression")]
+pub use compression::{
+    open_envelope_compressed, seal_to_envelope_compressed, DEFAULT_COMPRESSION_LEVEL,
+};
+#[cfg(feature = "hazmat")]
+mod convergent;
+#[cfg(feature = "hazmat")]
+pub use convergent::{seal_to_envelope_convergent, MIN_CONVERGENT_DIGEST_LEN};
+mod delegation;
+pub use delegation::{
+    delegate, rewrap_key, unwrap_key_delegable, wrap_key_delegable, DelegationToken,
+    DELEGABLE_KEY_WRAP_INFO,
+};
+mod migration;
+pub use migration::{
+    detect_suite, negotiate_version, reencrypt, EnvelopeHeader, SUPPORTED_ENVELOPE_VERSIONS,
+};
+mod validity;
+#[cfg(feature = "std")]
+pub use validity::SystemClock;
+pub use validity::{
+    envelope_validity, open_envelope_with_policy, seal_to_envelope_with_validity, Clock, Validity,
+    ValidityPolicy,
+};
+mod multi_kem;
+pub use multi_kem::{
+    open_multi_kem, KemStanza, MultiKemEnvelope, MultiKemSealer, MULTI_KEM_ENVELOPE_VERSION,
+    MULTI_KEM_INFO,
+};
+
+/// The version byte that `Envelope::to_bytes` writes. `Envelope::from_bytes` accepts the versions
+/// in `SUPPORTED_ENVELOPE_VERSIONS`.
+pub const ENVELOPE_VERSION: u8 = 1;
+
+// Bit 0 of the flags byte says whether a PSK ID hint follows, and bit 1 whether an AAD header
+// does. The rest must be 0.
+const FLAG_PSK_ID: u8 = 0x01;
+const FLAG_AAD_HEADER: u8 = 0x02;
+
+// The prefix of the AAD that envelopes with an AAD header are sealed under. See `Envelope`.
+const AAD_HEADER_PREFIX: &[u8] = b"HPKE aad header v1\x00";
+
+/// An encapsulated key and ciphertext, along with the IDs of the ciphersuite that made them and,
+/// if the sender used a PSK mode, the PSK ID. It may also carry an AAD header: sender metadata
+/// that's readable without opening the envelope. Make one with `seal_to_envelope` or
+/// `seal_to_envelope_with_header`, and open it with `open_envelope`.
+///
+/// The binary encoding, `to_bytes`, is
+///
+/// ```text
+/// version (1) || kem_id (2) || kdf_id (2) || aead_id (2) || flags (1)
+///     || enc_len (2) || enc
+///     || [psk_id_len (2) || psk_id]            if flags & 0x01
+///     || [aad_header_len (2) || aad_header]    if flags & 0x02
+///     || ciphertext                            (the rest)
+/// ```
+///
+/// with all integers big-endian. Nothing in the header is authenticated by the envelope itself.
+/// Instead, `open_envelope` checks the suite IDs against its type parameters, and the PSK ID hint
+/// against its mode, whose PSK ID is bound into the HPKE key schedule, so tampering with either
+/// makes it fail. If there's an AAD header, the ciphertext is sealed under the AAD
+///
+/// ```text
+/// "HPKE aad header v1\0" || aad_header_len (2) || aad_header || aad
+/// ```
+///
+/// in place of the caller's `aad`, so changing, adding, or removing the AAD header also makes
+/// `open_envelope` fail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub(crate) kem_id: u16,
+    pub(crate) kdf_id: u16,
+    pub(crate) aead_id: u16,
+    pub(crate) encapped_key: Vec<u8>,
+    pub(crate) psk_id: Option<Vec<u8>>,
+    pub(crate) aad_header: Option<Vec<u8>>,
+    pub(crate) ciphertext: Vec<u8>,
+}
+
+impl Envelope {
+    /// The KEM's algorithm identifier
+    pub fn kem_id(&self) -> u16 {
+        self.kem_id
+    }
+
+    /// The KDF's algorithm identifier
+    pub fn kdf_id(&self) -> u16 {
+        self.kdf_id
+    }
+
+    /// The AEAD's algorithm identifier
+    pub fn aead_id(&self) -> u16 {
+        self.aead_id
+    }
+
+    /// The encapsulated key, as bytes
+    pub fn encapped_key(&self) -> &[u8] {
+        &self.encapped_key
+    }
+
+    /// The ID of the PSK the sender used, if it used a PSK mode. Receivers can use this to look
+    /// up the PSK before calling `open_envelope`, which fails unless it's the PSK ID of the mode
+    /// it's given.
+    pub fn psk_id(&self) -> Option<&[u8]> {
+        self.psk_id.as_deref()
+    }
+
+    /// The AAD header, if any. This can be read before opening, but isn't authenticated until
+    /// `open_envelope` succeeds.
+    pub fn aad_header(&self) -> Option<&[u8]> {
+        self.aad_header.as_deref()
+    }
+
+    /// The ciphertext, with the tag appended
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+
+    /// Encodes this envelope in the binary format described in `Envelope`, with the version
+    /// `ENVELOPE_VERSION`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_versioned(ENVELOPE_VERSION)
+            .expect("ENVELOPE_VERSION is always supported")
+    }
+
+    /// Encodes this envelope in the binary format described in `Envelope`, with the given format
+    /// version. Use this with the version `negotiate_version` picked for a peer.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if `version` isn't in
+    /// `SUPPORTED_ENVELOPE_VERSIONS`.
+    pub fn to_bytes_versioned(&self, version: u8) -> Result<Vec<u8>, HpkeError> {
+        // Every supported version has the same layout, so far
+        if !SUPPORTED_ENVELOPE_VERSIONS.contains(&version) {
+            return Err(HpkeError::ValidationError);
+        }
+
+        let psk_id_len = self.psk_id.as_ref().map_or(0, |id| 2 + id.len());
+        let aad_header_len = self.aad_header.as_ref().map_or(0, |h| 2 + h.len());
+        let mut out = Vec::with_capacity(
+            10 + self.encapped_key.len() + psk_id_len + aad_header_len + self.ciphertext.len(),
+        );
+
+        let mut header = [0u8; 8];
+        header[0] = version;
+        BigEndian::write_u16(&mut header[1..3], self.kem_id);
+        BigEndian::write_u16(&mut header[3..5], self.kdf_id);
+        BigEndian::write_u16(&mut header[5..7], self.aead_id);
+        if self.psk_id.is_some() {
+            header[7] |= FLAG_PSK_ID;
+        }
+        if self.aad_header.is_some() {
+            header[7] |= FLAG_AAD_HEADER;
+        }
+        out.extend_from_slice(&header);
+
+        // The constructors make sure all of these fit in a u16
+        write_with_len(&mut out, &self.encapped_key);
+        if let Some(psk_id) = &self.psk_id {
+            write_with_len(&mut out, psk_id);
+        }
+        if let Some(aad_header) = &self.aad_header {
+            write_with_len(&mut out, aad_header);
+        }
+        out.extend_from_slice(&self.ciphertext);
+
+        Ok(out)
+    }
+
+    /// Decodes an envelope from the binary format described in `Envelope`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the version isn't in
+    /// `SUPPORTED_ENVELOPE_VERSIONS`, an unknown flag is set, or the input is truncated.
+    pub fn from_bytes(encoded: &[u8]) -> Result<Envelope, HpkeError> {
+        let header = detect_suite(encoded)?;
+        if encoded.len() < 8 {
+            return Err(HpkeError::ValidationError);
+        }
+        let flags = encoded[7];
+        if flags & !(FLAG_PSK_ID | FLAG_AAD_HEADER) != 0 {
+            return Err(HpkeError::ValidationError);
+        }
+
+        let rest = &encoded[8..];
+        let (encapped_key, rest) = read_with_len(rest)?;
+        let (psk_id, rest) = if flags & FLAG_PSK_ID != 0 {
+            let (psk_id, rest) = read_with_len(rest)?;
+            (Some(psk_id.to_vec()), rest)
+        } else {
+            (None, rest)
+        };
+        let (aad_header, rest) = if flags & FLAG_AAD_HEADER != 0 {
+            let (aad_header, rest) = read_with_len(rest)?;
+            (Some(aad_header.to_vec()), rest)
+        } else {
+            (None, rest)
+        };
+
+        Ok(Envelope {
+            kem_id: header.kem_id,
+            kdf_id: header.kdf_id,
+            aead_id: header.aead_id,
+            encapped_key: encapped_key.to_vec(),
+            psk_id,
+            aad_header,
+            ciphertext: rest.to_vec(),
+        })
+    }
+
+    /// Encodes this envelope as lowercase hex
+    #[cfg(feature = "text-encoding")]
+    pub fn to_hex(&self) -> alloc_string::String {
+        hex::encode(self.to_bytes())
+    }
+
+    /// Decodes an envelope from hex, in either case
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input isn't hex, or doesn't decode to a
+    /// valid envelope.
+    #[cfg(feature = "text-encoding")]
+    pub fn from_hex(encoded: &str) -> Result<Envelope, HpkeError> {
+        let bytes = hex::decode(encoded).map_err(|_| HpkeError::ValidationError)?;
+        Envelope::from_bytes(&bytes)
+    }
+
+    /// Encodes this envelope as standard, padded base64 (RFC 4648 §4)
+    #[cfg(feature = "text-encoding")]
+    pub fn to_base64(&self) -> alloc_string::String {
+        use base64ct::Encoding;
+        base64ct::Base64::encode_string(&self.to_bytes())
+    }
+
+    /// Decodes an envelope from standard, padded base64 (RFC 4648 §4)
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input isn't base64, or doesn't decode to
+    /// a valid envelope.
+    #[cfg(feature = "text-encoding")]
+    pub fn from_base64(encoded: &str) -> Result<Envelope, HpkeError> {
+        use base64ct::Encoding;
+        let bytes =
+            base64ct::Base64::decode_vec(encoded).map_err(|_| HpkeError::ValidationError)?;
+        Envelope::from_bytes(&bytes)
+    }
+
+    /// Encodes this envelope as ASCII armor under `armor::MESSAGE_LABEL`, i.e., between
+    /// `-----BEGIN HPKE MESSAGE-----` and `-----END HPKE MESSAGE-----` lines. See
+    /// [`crate::armor`].
+    #[cfg(feature = "text-encoding")]
+    pub fn to_armored(&self) -> alloc_string::String {
+        crate::armor::armor(crate::armor::MESSAGE_LABEL, &self.to_bytes())
+    }
+
+    /// Decodes an envelope from ASCII armor, ignoring whitespace as `armor::dearmor` does
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the armor is malformed, or doesn't hold a
+    /// valid envelope.
+    #[cfg(feature = "text-encoding")]
+    pub fn from_armored(armored: &str) -> Result<Envelope, HpkeError> {
+        let bytes = crate::armor::dearmor(crate::armor::MESSAGE_LABEL, armored)?;
+        Envelope::from_bytes(&bytes)
+    }
+
+    /// Same as `from_armored`, but only accepts exactly what `to_armored` writes, as
+    /// `armor::dearmor_strict` does
+    #[cfg(feature = "text-encoding")]
+    pub fn from_armored_strict(armored: &str) -> Result<Envelope, HpkeError> {
+        let bytes = crate::armor::dearmor_strict(crate::armor::MESSAGE_LABEL, armored)?;
+        Envelope::from_bytes(&bytes)
+    }
+}
+
+// Appends u16(bytes.len()) || bytes. Callers make sure the length fits.
+pub(crate) fn write_with_len(out: &mut Vec<u8>, bytes: &[u8]) {
+    let mut len = [0u8; 2];
+    BigEndian::write_u16(&mut len, bytes.len() as u16);
+    out.extend_from_slice(&len);
+    out.extend_from_slice(bytes);
+}
+
+// Reads u16(len) || bytes off the front of buf, and returns bytes and what's left
+pub(crate) fn read_with_len(buf: &[u8]) -> Result<(&[u8], &[u8]), HpkeError> {
+    if buf.len() < 2 {
+        return Err(HpkeError::ValidationError);
+    }
+    let len = BigEndian::read_u16(&buf[..2]) as usize;
+    let rest = &buf[2..];
+    if rest.len() < len {
+        return Err(HpkeError::ValidationError);
+    }
+    Ok(rest.split_at(len))
+}
+
+// Returns the AAD that a message with the given AAD header is sealed under, as described in
+// `Envelope`
+fn fold_aad_header(aad_header: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut folded = Vec::with_capacity(AAD_HEADER_PREFIX.len() + 2 + aad_header.len() + aad.len());
+    folded.extend_from_slice(AAD_HEADER_PREFIX);
+    write_with_len(&mut folded, aad_header);
+    folded.extend_from_slice(aad);
+    folded
+}
+
+/// Does a `single_shot_seal` and packs the result into an `Envelope`. If `mode` is a PSK mode,
+/// the PSK ID is included as a hint for the receiver.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Returns `Err(HpkeError::ValidationError)` if the PSK ID is
+/// longer than 65535 bytes. Otherwise, returns the errors `single_shot_seal` does.
+pub fn seal_to_envelope<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    seal_to_envelope_inner::<A, Kdf, Kem, R>(mode, pk_recip, info, plaintext, aad, None, csprng)
+}
+
+/// Like `seal_to_envelope`, but also puts `aad_header` in the envelope, in the clear. Anyone can
+/// read it with `Envelope::aad_header`, e.g., to route the envelope or index it, without the
+/// recipient's key. It's authenticated along with `aad`: `open_envelope` folds it into the AAD
+/// automatically, so it fails if the header was changed or stripped. The receiver passes the
+/// same `aad` as the sender, not the header.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Returns `Err(HpkeError::ValidationError)` if the PSK ID or
+/// `aad_header` is longer than 65535 bytes. Otherwise, returns the errors `single_shot_seal` does.
+pub fn seal_to_envelope_with_header<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    aad_header: &[u8],
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    seal_to_envelope_inner::<A, Kdf, Kem, R>(
+        mode,
+        pk_recip,
+        info,
+        plaintext,
+        aad,
+        Some(aad_header),
+        csprng,
+    )
+}
+
+// Returns the PSK ID hint for an envelope sealed in `mode`, or an error if it's too long to encode
+fn psk_id_hint<Kem: KemTrait>(mode: &OpModeS<Kem>) -> Result<Option<Vec<u8>>, HpkeError> {
+    match mode {
+        OpModeS::Psk(..) | OpModeS::AuthPsk(..) => {
+            let psk_id = mode.get_psk_id();
+            if psk_id.len() > u16::MAX as usize {
+                return Err(HpkeError::ValidationError);
+            }
+            Ok(Some(psk_id.to_vec()))
+        }
+        OpModeS::Base | OpModeS::Auth(..) => Ok(None),
+    }
+}
+
+// Seals an envelope, with an AAD header if one is given
+fn seal_to_envelope_inner<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    aad_header: Option<&[u8]>,
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let psk_id = psk_id_hint(mode)?;
+    if aad_header.map_or(0, <[u8]>::len) > u16::MAX as usize {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let (encapped_key, ciphertext) = match aad_header {
+        Some(aad_header) => single_shot_seal::<A, Kdf, Kem, R>(
+            mode,
+            pk_recip,
+            info,
+            plaintext,
+            &fold_aad_header(aad_header, aad),
+            csprng,
+        )?,
+        None => single_shot_seal::<A, Kdf, Kem, R>(mode, pk_recip, info, plaintext, aad, csprng)?,
+    };
+
+    Ok(Envelope {
+        kem_id: Kem::KEM_ID,
+        kdf_id: Kdf::KDF_ID,
+        aead_id: A::AEAD_ID,
+        encapped_key: encapped_key.to_bytes().to_vec(),
+        psk_id,
+        aad_header: aad_header.map(<[u8]>::to_vec),
+        ciphertext,
+    })
+}
+
+/// Opens an `Envelope` made by `seal_to_envelope` or `seal_to_envelope_with_header`. The PSK comes
+/// from `mode`, and the envelope's PSK ID hint has to be the PSK ID of `mode`, or absent if `mode`
+/// isn't a PSK mode. The AAD header, if any, is folded into `aad` as described in `Envelope`.
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. Returns `Err(HpkeError::ValidationError)` if the envelope's
+/// suite IDs aren't `(Kem, Kdf, A)`, or its PSK ID hint doesn't match `mode`. If the encapsulated
+/// key doesn't parse, returns the error from `Deserializable::from_bytes`. Otherwise, returns the
+/// errors `single_shot_open` does.
+pub fn open_envelope<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    info: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    open_envelope_with_ciphertext::<A, Kdf, Kem>(
+        mode,
+        sk_recip,
+        envelope,
+        &envelope.ciphertext,
+        info,
+        aad,
+    )
+}
+
+// Same as open_envelope, but opens `ciphertext` in place of the envelope's own. This is for
+// formats that put their own header at the front of the envelope's ciphertext.
+fn open_envelope_with_ciphertext<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    ciphertext: &[u8],
+    info: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if (envelope.kem_id, envelope.kdf_id, envelope.aead_id)
+        != (Kem::KEM_ID, Kdf::KDF_ID, A::AEAD_ID)
+    {
+        return Err(HpkeError::ValidationError);
+    }
+    // The hint isn't covered by the ciphertext, so check it against the PSK ID that is
+    let expected_psk_id = match mode {
+        OpModeR::Psk(..) | OpModeR::AuthPsk(..) => Some(mode.get_psk_id()),
+        OpModeR::Base | OpModeR::Auth(..) => None,
+    };
+    if envelope.psk_id.as_deref() != expected_psk_id {
+        return Err(HpkeError::ValidationError);
+    }
+    let encapped_key = Kem::EncappedKey::from_bytes(&envelope.encapped_key)?;
+
+    match &envelope.aad_header {
+        Some(aad_header) => single_shot_open::<A, Kdf, Kem>(
+            mode,
+            sk_recip,
+            &encapped_key,
+            info,
+            ciphertext,
+            &fold_aad_header(aad_header, aad),
+        ),
+        None => {
+            single_shot_open::<A, Kdf, Kem>(mode, sk_recip, &encapped_key, info, ciphertext, aad)
+        }
+    }
+}
+
+/// The info string that `wrap_key` and `unwrap_key` use. This keeps wrapped keys from being
+/// opened as ordinary messages, and vice versa.
+pub const KEY_WRAP_INFO: &[u8] = b"HPKE key wrap v1";
+
+/// Wraps the data encryption key `dek` to `pk_recip`, the holder of the key encryption key. This
+/// is `seal_to_envelope` in base mode, with the info string fixed to `KEY_WRAP_INFO`, and with
+/// `key_context` as the AAD. `key_context` should say what the key is for, e.g., the ID of the
+/// object it encrypts, so a wrapped key can't be swapped for another one. It isn't included in
+/// the envelope. The unwrapper must supply the same value.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Returns `Err(HpkeError::ValidationError)` if `dek` is empty.
+/// Otherwise, returns the errors `single_shot_seal` does.
+pub fn wrap_key<A, Kdf, Kem, R>(
+    pk_recip: &Kem::PublicKey,
+    dek: &[u8],
+    key_context: &[u8],
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    if dek.is_empty() {
+        return Err(HpkeError::ValidationError);
+    }
+    seal_to_envelope::<A, Kdf, Kem, R>(
+        &OpModeS::Base,
+        pk_recip,
+        KEY_WRAP_INFO,
+        dek,
+        key_context,
+        csprng,
+    )
+}
+
+/// Unwraps a data encryption key that `wrap_key` wrapped to the public key of `sk_recip`.
+/// `key_context` must be the value that was given to `wrap_key`.
+///
+/// Return Value
+/// ============
+/// Returns the data encryption key on success. It is zeroized on drop. Returns
+/// `Err(HpkeError::ValidationError)` if the envelope's suite IDs aren't `(Kem, Kdf, A)`, or if
+/// the envelope has a PSK ID, since `wrap_key` never makes one. Returns
+/// `Err(HpkeError::OpenError)` if the key was wrapped to someone else, under a different
+/// `key_context`, or was modified. Otherwise, returns the errors `open_envelope` does.
+pub fn unwrap_key<A, Kdf, Kem>(
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    key_context: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if envelope.psk_id.is_some() {
+        return Err(HpkeError::ValidationError);
+    }
+    open_envelope::<A, Kdf, Kem>(
+        &OpModeR::Base,
+        sk_recip,
+        envelope,
+        KEY_WRAP_INFO,
+        key_context,
+    )
+    .map(Zeroizing::new)
+}
+
+/// Re-wraps data encryption keys that `wrap_key` wrapped to the public key of `old_sk`, so that
+/// they're wrapped to `new_pk_recip` instead. Each item of `wrapped_keys` is an envelope and the
+/// `key_context` it was wrapped under. Only the wrapped keys are touched. The data they encrypt
+/// stays as it is, so rotating a recipient's key costs one decapsulation and one encapsulation per
+/// object, however large the objects are. When a key is wrapped to several recipients, only the
+/// envelopes for the recipient being rotated need to go through here.
+///
+/// This is lazy. Nothing happens until the returned iterator is advanced, and it yields one
+/// result per item of `wrapped_keys`, in the same order, so a large dataset can be streamed.
+///
+/// Return Value
+/// ============
+/// Each result is the new envelope, or the error `unwrap_key` or `wrap_key` returned for that
+/// item. A bad item does not affect the rest.
+pub fn rotate_recipient<'a, A, Kdf, Kem, R, I>(
+    old_sk: &'a Kem::PrivateKey,
+    new_pk_recip: &'a Kem::PublicKey,
+    wrapped_keys: I,
+    csprng: &'a mut R,
+) -> impl Iterator<Item = Result<Envelope, HpkeError>> + 'a
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+    I: IntoIterator<Item = (&'a Envelope, &'a [u8])>,
+    I::IntoIter: 'a,
+{
+    wrapped_keys
+        .into_iter()
+        .map(move |(envelope, key_context)| {
+            let dek = unwrap_key::<A, Kdf, Kem>(old_sk, envelope, key_context)?;
+            wrap_key::<A, Kdf, Kem, R>(new_pk_recip, &dek, key_context, csprng)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "x25519")]
+    use super::seal_to_envelope_with_header;
+    use super::{open_envelope, seal_to_envelope, unwrap_key, wrap_key, Envelope};
+    use crate::{
+        aead::{AesGcm128, ChaCha20Poly1305},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS, PskBundle},
+        HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    macro_rules! test_envelope_roundtrip {
+        ($test_name:ident, $kem:ty) => {
+            /// Tests that envelopes survive encoding and decoding, carry the PSK ID hint, and
+            /// don't open under the wrong suite or with a tampered hint
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let psk = PskBundle {
+                    psk: b"a preshared key of sufficient length",
+                    psk_id: b"psk #7",
+                };
+
+                let modes = [
+                    (OpModeS::<Kem>::Base, OpModeR::<Kem>::Base, None),
+                    (OpModeS::Psk(psk), OpModeR::Psk(psk), Some(&b"psk #7"[..])),
+                ];
+                for (mode_s, mode_r, psk_id) in modes.iter() {
+                    let envelope = seal_to_envelope::<A, Kdf, Kem, _>(
+                        mode_s,
+                        &pk_recip,
+                        b"info",
+                        b"msg",
+                        b"aad",
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    assert_eq!(envelope.psk_id(), *psk_id);
+
+                    let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+                    assert_eq!(decoded, envelope);
+                    #[cfg(feature = "text-encoding")]
+                    {
+                        assert_eq!(Envelope::from_hex(&envelope.to_hex()).unwrap(), envelope);
+                        assert_eq!(
+                            Envelope::from_base64(&envelope.to_base64()).unwrap(),
+                            envelope
+                        );
+                        let armored = envelope.to_armored();
+                        assert!(armored.starts_with("-----BEGIN HPKE MESSAGE-----\n"));
+                        assert_eq!(Envelope::from_armored(&armored).unwrap(), envelope);
+                        assert_eq!(Envelope::from_armored_strict(&armored).unwrap(), envelope);
+                    }
+
+                    let plaintext =
+                        open_envelope::<A, Kdf, Kem>(mode_r, &sk_recip, &decoded, b"info", b"aad")
+                            .unwrap();
+                    assert_eq!(plaintext, b"msg");
+
+                    // A different AEAD than the one in the envelope is rejected up front
+                    assert_eq!(
+                        open_envelope::<AesGcm128, Kdf, Kem>(
+                            mode_r, &sk_recip, &decoded, b"info", b"aad",
+                        ),
+                        Err(HpkeError::ValidationError)
+                    );
+
+                    // The hint can't be stripped, changed, or injected
+                    for hint in [None, Some(&b"psk #8"[..]), Some(&b"psk #7"[..])] {
+                        if hint == *psk_id {
+                            continue;
+                        }
+                        let mut tampered = decoded.clone();
+                        tampered.psk_id = hint.map(<[u8]>::to_vec);
+                        assert_eq!(
+                            open_envelope::<A, Kdf, Kem>(
+                                mode_r, &sk_recip, &tampered, b"info", b"aad",
+                            ),
+                            Err(HpkeError::ValidationError)
+                        );
+                    }
+                }
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_envelope_roundtrip!(test_envelope_roundtrip_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_envelope_roundtrip!(
+        test_envelope_roundtrip_nistp256,
+        crate::kem::DhP256HkdfSha256
+    );
+
+    macro_rules! test_key_wrap {
+        ($test_name:ident, $kem:ty) => {
+            /// Tests that wrapped keys unwrap under the same key context, and only under it
+            #[test]
+            fn $test_name() {
+                type A = AesGcm128;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (other_sk, _) = Kem::gen_keypair(&mut csprng);
+                let dek = [0x42u8; 32];
+
+                let envelope =
+                    wrap_key::<A, Kdf, Kem, _>(&pk_recip, &dek, b"object 17", &mut csprng).unwrap();
+                assert_eq!(envelope.psk_id(), None);
+                let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+                let unwrapped =
+                    unwrap_key::<A, Kdf, Kem>(&sk_recip, &decoded, b"object 17").unwrap();
+                assert_eq!(unwrapped.as_slice(), dek);
+
+                // The wrong context or recipient, or opening it as an ordinary message, fails
+                assert_eq!(
+                    unwrap_key::<A, Kdf, Kem>(&sk_recip, &decoded, b"object 18").err(),
+                    Some(HpkeError::OpenError)
+                );
+                assert_eq!(
+                    unwrap_key::<A, Kdf, Kem>(&other_sk, &decoded, b"object 17").err(),
+                    Some(HpkeError::OpenError)
+                );
+                assert_eq!(
+                    open_envelope::<A, Kdf, Kem>(
+                        &OpModeR::Base,
+                        &sk_recip,
+                        &decoded,
+                        b"",
+                        b"object 17"
+                    ),
+                    Err(HpkeError::OpenError)
+                );
+
+                assert_eq!(
+                    wrap_key::<A, Kdf, Kem, _>(&pk_recip, b"", b"object 17", &mut csprng).err(),
+                    Some(HpkeError::ValidationError)
+                );
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_key_wrap!(test_key_wrap_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_key_wrap!(test_key_wrap_nistp256, crate::kem::DhP256HkdfSha256);
+
+    /// Tests that rotation re-wraps every key to the new recipient, under its own key context,
+    /// and reports bad items without stopping
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_rotate_recipient() {
+        use super::rotate_recipient;
+        use crate::Vec;
+
+        type A = AesGcm128;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (old_sk, old_pk) = Kem::gen_keypair(&mut csprng);
+        let (new_sk, new_pk) = Kem::gen_keypair(&mut csprng);
+
+        let contexts: [&[u8]; 3] = [b"object 1", b"object 2", b"object 3"];
+        let wrapped: Vec<Envelope> = contexts
+            .iter()
+            .enumerate()
+            .map(|(i, ctx)| {
+                wrap_key::<A, Kdf, Kem, _>(&old_pk, &[i as u8 + 1; 32], ctx, &mut csprng).unwrap()
+            })
+            .collect();
+
+        // The second item is paired with the wrong context, so it fails to unwrap
+        let items = [
+            (&wrapped[0], contexts[0]),
+            (&wrapped[1], contexts[2]),
+            (&wrapped[2], contexts[2]),
+        ];
+        let rotated: Vec<_> =
+            rotate_recipient::<A, Kdf, Kem, _, _>(&old_sk, &new_pk, items, &mut csprng).collect();
+        assert_eq!(rotated.len(), 3);
+        assert_eq!(rotated[1], Err(HpkeError::OpenError));
+
+        for i in [0, 2] {
+            let envelope = rotated[i].as_ref().unwrap();
+            let dek = unwrap_key::<A, Kdf, Kem>(&new_sk, envelope, contexts[i]).unwrap();
+            assert_eq!(dek.as_slice(), [i as u8 + 1; 32]);
+            assert_eq!(
+                unwrap_key::<A, Kdf, Kem>(&old_sk, envelope, contexts[i]).err(),
+                Some(HpkeError::OpenError)
+            );
+        }
+    }
+
+    /// Tests that the AAD header is readable before opening, survives encoding, and is
+    /// authenticated: changing, stripping, or adding one makes the envelope fail to open
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_envelope_aad_header() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let open = |envelope: &Envelope, aad: &[u8]| {
+            open_envelope::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, envelope, b"info", aad)
+        };
+
+        let envelope = seal_to_envelope_with_header::<A, Kdf, Kem, _>(
+            &OpModeS::Base,
+            &pk_recip,
+            b"info",
+            b"msg",
+            b"aad",
+            b"route=eu-west",
+            &mut csprng,
+        )
+        .unwrap();
+        assert_eq!(envelope.aad_header(), Some(&b"route=eu-west"[..]));
+        let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(open(&decoded, b"aad").unwrap(), b"msg");
+        assert_eq!(open(&decoded, b"aae"), Err(HpkeError::OpenError));
+
+        // An empty header is still a header
+        let empty = seal_to_envelope_with_header::<A, Kdf, Kem, _>(
+            &OpModeS::Base,
+            &pk_recip,
+            b"info",
+            b"msg",
+            b"aad",
+            b"",
+            &mut csprng,
+        )
+        .unwrap();
+        assert_eq!(empty.aad_header(), Some(&b""[..]));
+        assert_eq!(open(&empty, b"aad").unwrap(), b"msg");
+
+        let mut changed = envelope.clone();
+        changed.aad_header = Some(b"route=us-east".to_vec());
+        let mut stripped = envelope.clone();
+        stripped.aad_header = None;
+        let mut emptied = envelope.clone();
+        emptied.aad_header = Some(vec![]);
+        for bad in [changed, stripped, emptied] {
+            assert_eq!(open(&bad, b"aad"), Err(HpkeError::OpenError));
+        }
+
+        // Moving bytes between the header and the AAD is caught too
+        let mut moved = envelope.clone();
+        moved.aad_header = Some(b"route=eu-wes".to_vec());
+        assert_eq!(open(&moved, b"taad"), Err(HpkeError::OpenError));
+
+        let mut added = seal_to_envelope::<A, Kdf, Kem, _>(
+            &OpModeS::Base,
+            &pk_recip,
+            b"info",
+            b"msg",
+            b"aad",
+            &mut csprng,
+        )
+        .unwrap();
+        added.aad_header = Some(b"route=eu-west".to_vec());
+        assert_eq!(open(&added, b"aad"), Err(HpkeError::OpenError));
+
+        assert_eq!(
+            seal_to_envelope_with_header::<A, Kdf, Kem, _>(
+                &OpModeS::Base,
+                &pk_recip,
+                b"info",
+                b"msg",
+                b"aad",
+                &vec![0u8; 65536],
+                &mut csprng,
+            ),
+            Err(HpkeError::ValidationError)
+        );
+    }
+
+    /// Tests that malformed encodings are rejected
+    #[test]
+    fn test_envelope_malformed() {
+        // version 1, suite (0x20, 1, 3), PSK ID flag, 2-byte enc, 1-byte PSK ID, 3-byte ciphertext
+        let good = b"\x01\x00\x20\x00\x01\x00\x03\x01\x00\x02ee\x00\x01pccc";
+        let envelope = Envelope::from_bytes(good).unwrap();
+        assert_eq!(envelope.encapped_key(), b"ee");
+        assert_eq!(envelope.psk_id(), Some(&b"p"[..]));
+        assert_eq!(envelope.ciphertext(), b"ccc");
+        assert_eq!(envelope.to_bytes(), good);
+
+        // Every truncation that cuts into a length-prefixed field fails
+        for len in 0..15 {
+            assert_eq!(
+                Envelope::from_bytes(&good[..len]),
+                Err(HpkeError::ValidationError)
+            );
+        }
+
+        // Unknown versions and flags fail
+        let mut bad = *good;
+        bad[0] = 2;
+        assert_eq!(Envelope::from_bytes(&bad), Err(HpkeError::ValidationError));
+        let mut bad = *good;
+        bad[7] = 0x05;
+        assert_eq!(Envelope::from_bytes(&bad), Err(HpkeError::ValidationError));
+
+        // The AAD header follows the PSK ID
+        let with_header = b"\x01\x00\x20\x00\x01\x00\x03\x03\x00\x02ee\x00\x01p\x00\x02hhccc";
+        let envelope = Envelope::from_bytes(with_header).unwrap();
+        assert_eq!(envelope.psk_id(), Some(&b"p"[..]));
+        assert_eq!(envelope.aad_header(), Some(&b"hh"[..]));
+        assert_eq!(envelope.ciphertext(), b"ccc");
+        assert_eq!(envelope.to_bytes(), with_header);
+        for len in 0..19 {
+            assert_eq!(
+                Envelope::from_bytes(&with_header[..len]),
+                Err(HpkeError::ValidationError)
+            );
+        }
+
+        #[cfg(feature = "text-encoding")]
+        {
+            assert_eq!(Envelope::from_hex("0g"), Err(HpkeError::ValidationError));
+            assert_eq!(
+                Envelope::from_base64("not base64!"),
+                Err(HpkeError::ValidationError)
+            );
+            let armored = crate::armor::armor(crate::armor::MESSAGE_LABEL, good);
+            assert!(Envelope::from_armored(&armored).is_ok());
+            let crlf = armored.replace('\n', "\r\n");
+            assert!(Envelope::from_armored(&crlf).is_ok());
+            assert_eq!(
+                Envelope::from_armored_strict(&crlf),
+                Err(HpkeError::ValidationError)
+            );
+            let other_label = crate::armor::armor(crate::armor::PUBLIC_KEY_LABEL, good);
+            assert_eq!(
+                Envelope::from_armored(&other_label),
+                Err(HpkeError::ValidationError)
+            );
+        }
+    }
+}
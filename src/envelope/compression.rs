@@ -0,0 +1,257 @@
+use super::{open_envelope, seal_to_envelope, Envelope};
+use crate::{
+    aead::Aead,
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    HpkeError, Vec,
+};
+
+use miniz_oxide::inflate::{DecompressError, TINFLStatus};
+use rand_core::{CryptoRng, RngCore};
+
+/// The DEFLATE level that callers should use absent a reason to pick another. Levels go from 0
+/// (store only) to 10 (smallest output, slowest).
+pub const DEFAULT_COMPRESSION_LEVEL: u8 = 6;
+
+/// Prepended to the AAD of compressed envelopes, so a compressed envelope doesn't open with
+/// `open_envelope`, and an ordinary one doesn't open with `open_envelope_compressed`
+const COMPRESSED_AAD_PREFIX: &[u8] = b"HPKE compressed v1\x00";
+
+// The first byte of the sealed plaintext says how the rest is encoded. It's inside the
+// ciphertext, so it's authenticated.
+const METHOD_STORED: u8 = 0x00;
+const METHOD_DEFLATE: u8 = 0x01;
+
+// Returns COMPRESSED_AAD_PREFIX || aad
+fn compressed_aad(aad: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(COMPRESSED_AAD_PREFIX.len() + aad.len());
+    out.extend_from_slice(COMPRESSED_AAD_PREFIX);
+    out.extend_from_slice(aad);
+    out
+}
+
+/// Compresses `plaintext` with raw DEFLATE at the given level, then does `seal_to_envelope`.
+/// If compressing doesn't make the plaintext smaller, it's sealed uncompressed. Either way, the
+/// envelope only opens with `open_envelope_compressed`.
+///
+/// DANGER
+/// ======
+/// Compression makes the ciphertext length depend on what the plaintext contains, not just how
+/// long it is. If an attacker can influence part of a plaintext that also holds a secret, e.g.,
+/// a JSON document with a session token and a user-supplied field, then they can learn the
+/// secret a few bytes at a time by watching how the length changes as they vary their part. This
+/// is how the CRIME and BREACH attacks on TLS and HTTP worked. Only compress payloads that are
+/// entirely public, entirely from one party, or where the length is hidden some other way.
+/// Padding doesn't reliably fix this.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Otherwise, returns the errors `seal_to_envelope` does.
+pub fn seal_to_envelope_compressed<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    level: u8,
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let deflated = miniz_oxide::deflate::compress_to_vec(plaintext, level);
+    let (method, body) = if deflated.len() < plaintext.len() {
+        (METHOD_DEFLATE, deflated.as_slice())
+    } else {
+        (METHOD_STORED, plaintext)
+    };
+
+    let mut encoded = Vec::with_capacity(1 + body.len());
+    encoded.push(method);
+    encoded.extend_from_slice(body);
+
+    seal_to_envelope::<A, Kdf, Kem, R>(mode, pk_recip, info, &encoded, &compressed_aad(aad), csprng)
+}
+
+/// Opens an `Envelope` made by `seal_to_envelope_compressed` and decompresses the plaintext,
+/// giving up once it's longer than `max_len` bytes. Set `max_len` to the largest plaintext the
+/// application expects. A few hundred bytes of DEFLATE can decompress to gigabytes.
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. Returns `Err(HpkeError::MessageTooLarge(max_len, n))` if
+/// the plaintext is longer than `max_len`, where `n` is its length if it was stored, and
+/// `max_len + 1` if decompression was cut off. Returns `Err(HpkeError::OpenError)` if the
+/// envelope wasn't made by `seal_to_envelope_compressed`, or if the sender's compressed data is
+/// malformed. Otherwise, returns the errors `open_envelope` does.
+pub fn open_envelope_compressed<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    info: &[u8],
+    aad: &[u8],
+    max_len: usize,
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut encoded =
+        open_envelope::<A, Kdf, Kem>(mode, sk_recip, envelope, info, &compressed_aad(aad))?;
+    if encoded.is_empty() {
+        return Err(HpkeError::OpenError);
+    }
+
+    match encoded[0] {
+        METHOD_STORED => {
+            let len = encoded.len() - 1;
+            if len > max_len {
+                return Err(HpkeError::MessageTooLarge(max_len, len));
+            }
+            encoded.remove(0);
+            Ok(encoded)
+        }
+        METHOD_DEFLATE => {
+            miniz_oxide::inflate::decompress_to_vec_with_limit(&encoded[1..], max_len).map_err(
+                |DecompressError { status, .. }| match status {
+                    TINFLStatus::HasMoreOutput => {
+                        HpkeError::MessageTooLarge(max_len, max_len.saturating_add(1))
+                    }
+                    _ => HpkeError::OpenError,
+                },
+            )
+        }
+        _ => Err(HpkeError::OpenError),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{open_envelope_compressed, seal_to_envelope_compressed, DEFAULT_COMPRESSION_LEVEL};
+    use crate::{
+        aead::ChaCha20Poly1305,
+        envelope::{open_envelope, seal_to_envelope, Envelope},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS},
+        HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    macro_rules! test_compressed_envelope {
+        ($test_name:ident, $kem:ty) => {
+            /// Tests that compressed envelopes round-trip and shrink repetitive payloads, that the
+            /// size limit holds, and that compressed and ordinary envelopes don't open as each
+            /// other
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let json = br#"{"items":[{"id":1,"tag":"x"},{"id":1,"tag":"x"}]}"#.repeat(200);
+
+                // Repetitive JSON compresses. A short message is stored as is.
+                for (msg, level) in [(&json[..], DEFAULT_COMPRESSION_LEVEL), (b"hi", 10)] {
+                    let envelope = seal_to_envelope_compressed::<A, Kdf, Kem, _>(
+                        &OpModeS::Base,
+                        &pk_recip,
+                        b"info",
+                        msg,
+                        b"aad",
+                        level,
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+                    let plaintext = open_envelope_compressed::<A, Kdf, Kem>(
+                        &OpModeR::Base,
+                        &sk_recip,
+                        &decoded,
+                        b"info",
+                        b"aad",
+                        msg.len(),
+                    )
+                    .unwrap();
+                    assert_eq!(plaintext, msg);
+
+                    // One byte under the limit fails, without decompressing everything
+                    assert!(matches!(
+                        open_envelope_compressed::<A, Kdf, Kem>(
+                            &OpModeR::Base,
+                            &sk_recip,
+                            &decoded,
+                            b"info",
+                            b"aad",
+                            msg.len() - 1,
+                        ),
+                        Err(HpkeError::MessageTooLarge(..))
+                    ));
+                    // A compressed envelope isn't an ordinary one
+                    assert_eq!(
+                        open_envelope::<A, Kdf, Kem>(
+                            &OpModeR::Base,
+                            &sk_recip,
+                            &decoded,
+                            b"info",
+                            b"aad"
+                        ),
+                        Err(HpkeError::OpenError)
+                    );
+                }
+                let compressed = seal_to_envelope_compressed::<A, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    b"info",
+                    &json,
+                    b"aad",
+                    DEFAULT_COMPRESSION_LEVEL,
+                    &mut csprng,
+                )
+                .unwrap();
+                assert!(compressed.ciphertext().len() < json.len() / 10);
+
+                // Nor the other way around
+                let plain = seal_to_envelope::<A, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    b"info",
+                    &json,
+                    b"aad",
+                    &mut csprng,
+                )
+                .unwrap();
+                assert_eq!(
+                    open_envelope_compressed::<A, Kdf, Kem>(
+                        &OpModeR::Base,
+                        &sk_recip,
+                        &plain,
+                        b"info",
+                        b"aad",
+                        json.len()
+                    ),
+                    Err(HpkeError::OpenError)
+                );
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_compressed_envelope!(
+        test_compressed_envelope_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_compressed_envelope!(
+        test_compressed_envelope_nistp256,
+        crate::kem::DhP256HkdfSha256
+    );
+}
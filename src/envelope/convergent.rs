@@ -0,0 +1,208 @@
+//! Convergent encryption: sealing the same plaintext to the same recipient gives a byte-identical
+//! envelope, so deduplicating storage can tell that two envelopes hold the same content without
+//! opening them. This is gated under the `hazmat` feature.
+
+use super::{psk_id_hint, Envelope};
+use crate::{
+    aead::Aead,
+    kdf::{labeled_extract_multi, Kdf as KdfTrait, LabeledExpand},
+    kem::Kem as KemTrait,
+    op_mode::OpModeS,
+    setup::setup_sender_deterministic,
+    util::full_suite_id,
+    HpkeError, Serializable,
+};
+
+use zeroize::Zeroizing;
+
+/// The shortest `content_digest` that `seal_to_envelope_convergent` accepts
+pub const MIN_CONVERGENT_DIGEST_LEN: usize = 32;
+
+// Twice the longest private key of any KEM here, so DeriveKeyPair always gets plenty
+const IKM_LEN: usize = 64;
+
+// Derives the ephemeral IKM as
+//   prk = LabeledExtract(content_digest, "convergent_ikm",
+//                        concat(pkRm, I2OSP(len(info), 8), info, I2OSP(len(aad), 8), aad, pt))
+//   ikm_eph = LabeledExpand(prk, "ikm_eph", "", 64)
+// under the full suite ID
+fn convergent_ikm<A, Kdf, Kem>(
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    content_digest: &[u8],
+) -> Zeroizing<[u8; IKM_LEN]>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let suite_id = full_suite_id::<A, Kdf, Kem>();
+    let (_, prk) = labeled_extract_multi::<Kdf>(
+        content_digest,
+        &suite_id,
+        b"convergent_ikm",
+        &[
+            &pk_recip.to_bytes(),
+            &(info.len() as u64).to_be_bytes(),
+            info,
+            &(aad.len() as u64).to_be_bytes(),
+            aad,
+            plaintext,
+        ],
+    );
+
+    let mut ikm = Zeroizing::new([0u8; IKM_LEN]);
+    prk.labeled_expand(&suite_id, b"ikm_eph", b"", ikm.as_mut_slice())
+        .expect("ikm is way too big");
+    ikm
+}
+
+/// Does `seal_to_envelope`, but with the ephemeral key derived from `content_digest` instead of
+/// sampled from an RNG, so the same mode, recipient, info string, plaintext, AAD, and digest
+/// always give a byte-identical envelope. The envelope opens with `open_envelope` as usual.
+///
+/// `content_digest` is what keys the convergence, e.g., HMAC-SHA256 of the plaintext under a
+/// secret shared by everyone whose uploads should deduplicate against each other. The plaintext,
+/// the recipient's public key, the info string, and the AAD are also hashed into the ephemeral
+/// key, so different plaintexts never share a key and nonce, even if they're given the same
+/// digest. A wrong digest only stops deduplication.
+///
+/// DANGER
+/// ======
+/// Anyone who knows `content_digest` and the other inputs can recompute the ephemeral private key,
+/// and from it the shared secret, so they can open the envelope without the recipient's key. Treat
+/// the digest as a decryption key: never store it next to the envelope, never use it as a
+/// content address, and never make it a public hash of the plaintext. With a keyed digest, the
+/// envelope is only as secret as the key and the plaintext. With an unkeyed one, it's only as
+/// secret as the plaintext, so anyone who can guess the plaintext, e.g., a form letter with a
+/// few blanks, can confirm the guess by resealing it and comparing, and then open it. Use this
+/// only where that's acceptable.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Returns `Err(HpkeError::ValidationError)` if `content_digest`
+/// is shorter than `MIN_CONVERGENT_DIGEST_LEN` bytes, or the PSK ID is longer than 65535 bytes.
+/// Returns `Err(HpkeError::KeyDerivation)` if the ephemeral keypair can't be derived, and
+/// otherwise the errors `single_shot_seal` does.
+pub fn seal_to_envelope_convergent<A, Kdf, Kem>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    content_digest: &[u8],
+) -> Result<Envelope, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if content_digest.len() < MIN_CONVERGENT_DIGEST_LEN {
+        return Err(HpkeError::ValidationError);
+    }
+    let psk_id = psk_id_hint(mode)?;
+
+    let ikm_eph = convergent_ikm::<A, Kdf, Kem>(pk_recip, info, plaintext, aad, content_digest);
+    let (encapped_key, mut ctx) =
+        setup_sender_deterministic::<A, Kdf, Kem>(mode, pk_recip, info, ikm_eph.as_slice())?;
+    let ciphertext = ctx.seal(plaintext, aad)?;
+    // The same key and nonce only ever seal the same plaintext and AAD here, which is safe, so
+    // don't let the next identical seal trip the check
+    #[cfg(feature = "nonce-reuse-check")]
+    ctx.forget_sealed_nonces();
+
+    Ok(Envelope {
+        kem_id: Kem::KEM_ID,
+        kdf_id: Kdf::KDF_ID,
+        aead_id: A::AEAD_ID,
+        encapped_key: encapped_key.to_bytes().to_vec(),
+        psk_id,
+        aad_header: None,
+        ciphertext,
+    })
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::seal_to_envelope_convergent;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        envelope::{open_envelope, seal_to_envelope},
+        kdf::HkdfSha256,
+        kem::X25519HkdfSha256,
+        HpkeError, Kem as KemTrait, OpModeR, OpModeS, PskBundle,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that convergent envelopes are identical exactly when their inputs are, and open
+    /// like any other envelope
+    #[test]
+    fn test_convergent_envelope() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let (_, pk_other) = Kem::gen_keypair(&mut csprng);
+        let digest = [0x5au8; 32];
+        let psk = PskBundle {
+            psk: &[0x42u8; 32],
+            psk_id: b"dedup psk",
+        };
+
+        let seal = |mode: &OpModeS<Kem>, pk_recip, pt: &[u8], aad: &[u8], digest: &[u8]| {
+            seal_to_envelope_convergent::<A, Kdf, Kem>(mode, pk_recip, b"info", pt, aad, digest)
+        };
+        let envelope = seal(&OpModeS::Base, &pk_recip, b"chunk", b"aad", &digest).unwrap();
+        assert_eq!(
+            envelope.to_bytes(),
+            seal(&OpModeS::Base, &pk_recip, b"chunk", b"aad", &digest)
+                .unwrap()
+                .to_bytes()
+        );
+        for other in [
+            seal(&OpModeS::Base, &pk_other, b"chunk", b"aad", &digest),
+            seal(&OpModeS::Base, &pk_recip, b"chunK", b"aad", &digest),
+            seal(&OpModeS::Base, &pk_recip, b"chunk", b"aaD", &digest),
+            seal(&OpModeS::Base, &pk_recip, b"chunk", b"aad", &[0x5b; 32]),
+            seal(&OpModeS::Psk(psk), &pk_recip, b"chunk", b"aad", &digest),
+        ] {
+            assert_ne!(other.unwrap().to_bytes(), envelope.to_bytes());
+        }
+        // The plaintext picks the ephemeral key, whatever the digest
+        let other = seal(&OpModeS::Base, &pk_recip, b"chunK", b"aad", &digest).unwrap();
+        assert_ne!(other.encapped_key(), envelope.encapped_key());
+
+        // It's an ordinary envelope, the same size as a randomized one
+        let plaintext =
+            open_envelope::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &envelope, b"info", b"aad")
+                .unwrap();
+        assert_eq!(plaintext, b"chunk");
+        let random = seal_to_envelope::<A, Kdf, Kem, _>(
+            &OpModeS::Base,
+            &pk_recip,
+            b"info",
+            b"chunk",
+            b"aad",
+            &mut csprng,
+        )
+        .unwrap();
+        assert_eq!(random.to_bytes().len(), envelope.to_bytes().len());
+
+        let envelope = seal(&OpModeS::Psk(psk), &pk_recip, b"chunk", b"aad", &digest).unwrap();
+        assert_eq!(envelope.psk_id(), Some(&b"dedup psk"[..]));
+        let plaintext =
+            open_envelope::<A, Kdf, Kem>(&OpModeR::Psk(psk), &sk_recip, &envelope, b"info", b"aad")
+                .unwrap();
+        assert_eq!(plaintext, b"chunk");
+
+        assert_eq!(
+            seal(&OpModeS::Base, &pk_recip, b"chunk", b"aad", &digest[..31]),
+            Err(HpkeError::ValidationError)
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! Re-targeting of wrapped keys from one recipient to another by a gateway that never learns the
+//! wrapped key, built on the HPKE exporter.
+//!
+//! A delegable wrap is an `Envelope` under the export-only AEAD, whose ciphertext is
+//!
+//! ```text
+//! (dek || check) XOR Export(key_context, len(dek) + 16)
+//! ```
+//!
+//! where `check` commits to `dek` and `key_context`, so a wrong unwrap is detected. To hand the key
+//! to someone else, the current recipient makes a `DelegationToken` with `delegate`. The token is
+//! a fresh encapsulation to the new recipient, and the XOR of the old and new masks. A gateway
+//! holding the wrap and the token runs `rewrap_key`, which swaps in the new encapsulated key and
+//! XORs in the mask difference. The result is exactly a delegable wrap to the new recipient. The
+//! gateway only ever sees masked values, so it learns nothing about the key, and so nothing about
+//! the data it protects.
+//!
+//! A token is good for one wrapped key only, since the masks are tied to its encapsulated key.
+//! Like any proxy re-encryption scheme, a gateway that colludes with the new recipient learns
+//! nothing the new recipient couldn't already learn.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     envelope::{delegate, rewrap_key, unwrap_key_delegable, wrap_key_delegable},
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     Kem,
+//! };
+//!
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_alice, pk_alice) = K::gen_keypair(&mut csprng);
+//! let (sk_bob, pk_bob) = K::gen_keypair(&mut csprng);
+//!
+//! let dek = [7u8; 32];
+//! let wrapped =
+//!     wrap_key_delegable::<Kdf, K, _>(&pk_alice, &dek, b"object 17", &mut csprng).unwrap();
+//!
+//! // Alice lets Bob read object 17. The gateway does the re-wrapping.
+//! let token =
+//!     delegate::<Kdf, K, _>(&sk_alice, &pk_bob, &wrapped, b"object 17", &mut csprng).unwrap();
+//! let rewrapped = rewrap_key(&wrapped, &token).unwrap();
+//!
+//! let unwrapped = unwrap_key_delegable::<Kdf, K>(&sk_bob, &rewrapped, b"object 17").unwrap();
+//! assert_eq!(unwrapped.as_slice(), &dek);
+//! # }
+//! ```
+
+use super::Envelope;
+use crate::{
+    aead::{Aead, ExportOnlyAead},
+    kdf::{labeled_extract_multi, Kdf as KdfTrait},
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    setup::{setup_receiver, setup_sender},
+    util::full_suite_id,
+    Deserializable, HpkeError, Serializable, Vec,
+};
+
+use rand_core::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+/// The info string that the delegable key wrap functions use. This keeps their contexts apart
+/// from `wrap_key`'s and from ordinary messages.
+pub const DELEGABLE_KEY_WRAP_INFO: &[u8] = b"HPKE delegable key wrap v1";
+
+// The length of the commitment to the key that follows it under the mask
+const CHECK_LEN: usize = 16;
+
+/// Permission for a gateway to re-target one delegable wrapped key to a new recipient. Make one
+/// with `delegate`, and use it with `rewrap_key`. A token reveals nothing about the wrapped key
+/// by itself, or together with the wrap.
+///
+/// The binary encoding, `to_bytes`, is
+///
+/// ```text
+/// enc_len (2) || enc || mask_delta (the rest)
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegationToken {
+    encapped_key: Vec<u8>,
+    mask_delta: Vec<u8>,
+}
+
+impl DelegationToken {
+    /// Encodes this token in the format described in `DelegationToken`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(2 + self.encapped_key.len() + self.mask_delta.len());
+        super::write_with_len(&mut out, &self.encapped_key);
+        out.extend_from_slice(&self.mask_delta);
+        out
+    }
+
+    /// Decodes a token in the format described in `DelegationToken`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input is truncated, or there's no mask
+    /// after the encapsulated key.
+    pub fn from_bytes(encoded: &[u8]) -> Result<DelegationToken, HpkeError> {
+        let (encapped_key, mask_delta) = super::read_with_len(encoded)?;
+        if mask_delta.is_empty() {
+            return Err(HpkeError::ValidationError);
+        }
+        Ok(DelegationToken {
+            encapped_key: encapped_key.to_vec(),
+            mask_delta: mask_delta.to_vec(),
+        })
+    }
+}
+
+// Computes the commitment to dek and key_context, which is the first CHECK_LEN bytes of
+//   LabeledExtract("", "dek_check", concat(I2OSP(len(key_context), 8), key_context, dek))
+fn key_check<Kdf, Kem>(dek: &[u8], key_context: &[u8]) -> [u8; CHECK_LEN]
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let suite_id = full_suite_id::<ExportOnlyAead, Kdf, Kem>();
+    let (prk, _) = labeled_extract_multi::<Kdf>(
+        &[],
+        &suite_id,
+        b"dek_check",
+        &[&(key_context.len() as u64).to_be_bytes(), key_context, dek],
+    );
+    let mut check = [0u8; CHECK_LEN];
+    check.copy_from_slice(&prk[..CHECK_LEN]);
+    check
+}
+
+// Encapsulates to pk_recip and exports a mask of the given length. Returns the encapped key and
+// the mask.
+fn new_mask<Kdf, Kem, R>(
+    pk_recip: &Kem::PublicKey,
+    key_context: &[u8],
+    len: usize,
+    csprng: &mut R,
+) -> Result<(Kem::EncappedKey, Zeroizing<Vec<u8>>), HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let (encapped_key, ctx) = setup_sender::<ExportOnlyAead, Kdf, Kem, R>(
+        &OpModeS::Base,
+        pk_recip,
+        DELEGABLE_KEY_WRAP_INFO,
+        csprng,
+    )?;
+    let mut mask = Zeroizing::new(vec![0u8; len]);
+    ctx.export(key_context, &mut mask)?;
+    Ok((encapped_key, mask))
+}
+
+// Checks that the envelope is a delegable wrap for (Kdf, Kem), decapsulates it, and exports its
+// mask
+fn recover_mask<Kdf, Kem>(
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    key_context: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if (envelope.kem_id, envelope.kdf_id, envelope.aead_id)
+        != (Kem::KEM_ID, Kdf::KDF_ID, ExportOnlyAead::AEAD_ID)
+        || envelope.psk_id.is_some()
+        || envelope.aad_header.is_some()
+        || envelope.ciphertext.len() <= CHECK_LEN
+    {
+        return Err(HpkeError::ValidationError);
+    }
+    let encapped_key = Kem::EncappedKey::from_bytes(&envelope.encapped_key)?;
+    let ctx = setup_receiver::<ExportOnlyAead, Kdf, Kem>(
+        &OpModeR::Base,
+        sk_recip,
+        &encapped_key,
+        DELEGABLE_KEY_WRAP_INFO,
+    )?;
+    let mut mask = Zeroizing::new(vec![0u8; envelope.ciphertext.len()]);
+    ctx.export(key_context, &mut mask)?;
+    Ok(mask)
+}
+
+// Removes the mask from the envelope's ciphertext and checks the commitment. Returns the key.
+fn unmask<Kdf, Kem>(
+    mask: &[u8],
+    envelope: &Envelope,
+    key_context: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut unmasked: Zeroizing<Vec<u8>> = Zeroizing::new(
+        mask.iter()
+            .zip(envelope.ciphertext.iter())
+            .map(|(m, c)| m ^ c)
+            .collect(),
+    );
+
+    let dek_len = unmasked.len() - CHECK_LEN;
+    let check = key_check::<Kdf, Kem>(&unmasked[..dek_len], key_context);
+    if !bool::from(check.ct_eq(&unmasked[dek_len..])) {
+        return Err(HpkeError::OpenError);
+    }
+    unmasked.truncate(dek_len);
+    Ok(unmasked)
+}
+
+/// Wraps the data encryption key `dek` to `pk_recip` so that it can later be re-targeted with
+/// `delegate` and `rewrap_key`. `key_context` works as in `wrap_key`: it should say what the key
+/// is for, it isn't included in the envelope, and the unwrapper must supply the same value.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Returns `Err(HpkeError::ValidationError)` if `dek` is empty,
+/// and `Err(HpkeError::KdfOutputTooLong)` if it's much longer than a key. Otherwise, returns the
+/// errors `setup_sender` does.
+pub fn wrap_key_delegable<Kdf, Kem, R>(
+    pk_recip: &Kem::PublicKey,
+    dek: &[u8],
+    key_context: &[u8],
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    if dek.is_empty() {
+        return Err(HpkeError::ValidationError);
+    }
+    let (encapped_key, mut masked) =
+        new_mask::<Kdf, Kem, R>(pk_recip, key_context, dek.len() + CHECK_LEN, csprng)?;
+
+    // masked = (dek || check) XOR mask
+    let check = key_check::<Kdf, Kem>(dek, key_context);
+    masked
+        .iter_mut()
+        .zip(dek.iter().chain(check.iter()))
+        .for_each(|(m, b)| *m ^= b);
+
+    Ok(Envelope {
+        kem_id: Kem::KEM_ID,
+        kdf_id: Kdf::KDF_ID,
+        aead_id: ExportOnlyAead::AEAD_ID,
+        encapped_key: encapped_key.to_bytes().to_vec(),
+        psk_id: None,
+        aad_header: None,
+        ciphertext: masked.to_vec(),
+    })
+}
+
+/// Unwraps a data encryption key that `wrap_key_delegable` wrapped, or `rewrap_key` re-targeted,
+/// to the public key of `sk_recip`. `key_context` must be the value that was given to
+/// `wrap_key_delegable`.
+///
+/// Return Value
+/// ============
+/// Returns the data encryption key on success. It is zeroized on drop. Returns
+/// `Err(HpkeError::ValidationError)` if the envelope isn't a delegable wrap for `(Kem, Kdf)`.
+/// Returns `Err(HpkeError::OpenError)` if the key was wrapped to someone else, under a different
+/// `key_context`, or was modified, including by a `rewrap_key` with someone else's token.
+pub fn unwrap_key_delegable<Kdf, Kem>(
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    key_context: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mask = recover_mask::<Kdf, Kem>(sk_recip, envelope, key_context)?;
+    unmask::<Kdf, Kem>(&mask, envelope, key_context)
+}
+
+/// Makes a token that lets a gateway re-target `envelope`, a delegable wrap to the public key of
+/// `sk_from`, to `pk_to`. The caller must hold `sk_from`. The key itself is never exposed to the
+/// gateway. `key_context` must be the value that was given to `wrap_key_delegable`.
+///
+/// Return Value
+/// ============
+/// Returns the token on success. Returns `Err(HpkeError::OpenError)` if `sk_from` can't unwrap
+/// `envelope` under `key_context`, since the token would be useless. Otherwise, returns the errors
+/// `unwrap_key_delegable` does.
+pub fn delegate<Kdf, Kem, R>(
+    sk_from: &Kem::PrivateKey,
+    pk_to: &Kem::PublicKey,
+    envelope: &Envelope,
+    key_context: &[u8],
+    csprng: &mut R,
+) -> Result<DelegationToken, HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    // Make sure the wrap is ours and intact before vouching for it
+    let old_mask = recover_mask::<Kdf, Kem>(sk_from, envelope, key_context)?;
+    unmask::<Kdf, Kem>(&old_mask, envelope, key_context)?;
+
+    let (encapped_key, new_mask) =
+        new_mask::<Kdf, Kem, R>(pk_to, key_context, old_mask.len(), csprng)?;
+    let mask_delta = old_mask
+        .iter()
+        .zip(new_mask.iter())
+        .map(|(a, b)| a ^ b)
+        .collect();
+
+    Ok(DelegationToken {
+        encapped_key: encapped_key.to_bytes().to_vec(),
+        mask_delta,
+    })
+}
+
+/// Re-targets a delegable wrapped key with a token from `delegate`. This takes no keys, and
+/// learns nothing about the wrapped key. The result is a delegable wrap to the token's recipient,
+/// and can be delegated again.
+///
+/// Return Value
+/// ============
+/// Returns the re-targeted envelope on success. Returns `Err(HpkeError::ValidationError)` if the
+/// envelope isn't a delegable wrap, or the token's mask is the wrong length for it. A token made
+/// for a different envelope of the same length isn't detected here, but makes the result fail to
+/// unwrap.
+pub fn rewrap_key(envelope: &Envelope, token: &DelegationToken) -> Result<Envelope, HpkeError> {
+    if envelope.aead_id != ExportOnlyAead::AEAD_ID
+        || envelope.psk_id.is_some()
+        || envelope.aad_header.is_some()
+        || envelope.ciphertext.len() != token.mask_delta.len()
+    {
+        return Err(HpkeError::ValidationError);
+    }
+
+    // (dek || check) XOR old_mask XOR (old_mask XOR new_mask) = (dek || check) XOR new_mask
+    let ciphertext = envelope
+        .ciphertext
+        .iter()
+        .zip(token.mask_delta.iter())
+        .map(|(c, d)| c ^ d)
+        .collect();
+
+    Ok(Envelope {
+        kem_id: envelope.kem_id,
+        kdf_id: envelope.kdf_id,
+        aead_id: envelope.aead_id,
+        encapped_key: token.encapped_key.clone(),
+        psk_id: None,
+        aad_header: None,
+        ciphertext,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{delegate, rewrap_key, unwrap_key_delegable, wrap_key_delegable, DelegationToken};
+    use crate::{kdf::HkdfSha256, kem::Kem as KemTrait, HpkeError};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    macro_rules! test_delegation {
+        ($test_name:ident, $kem:ty) => {
+            /// Tests that a delegable wrap unwraps for its recipient, that a re-wrapped one
+            /// unwraps for the new recipient and can be delegated again, and that wrong keys,
+            /// contexts, and tokens are caught
+            #[test]
+            fn $test_name() {
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_a, pk_a) = Kem::gen_keypair(&mut csprng);
+                let (sk_b, pk_b) = Kem::gen_keypair(&mut csprng);
+                let (sk_c, pk_c) = Kem::gen_keypair(&mut csprng);
+                let dek = [0x42u8; 32];
+                let ctx = b"object 17";
+
+                let wrapped =
+                    wrap_key_delegable::<Kdf, Kem, _>(&pk_a, &dek, ctx, &mut csprng).unwrap();
+                assert_eq!(
+                    unwrap_key_delegable::<Kdf, Kem>(&sk_a, &wrapped, ctx)
+                        .unwrap()
+                        .as_slice(),
+                    &dek
+                );
+                // The key isn't sitting in the ciphertext in the clear
+                assert!(!wrapped.ciphertext().windows(32).any(|w| w == dek));
+
+                // A to B, then B to C
+                let token =
+                    delegate::<Kdf, Kem, _>(&sk_a, &pk_b, &wrapped, ctx, &mut csprng).unwrap();
+                let token = DelegationToken::from_bytes(&token.to_bytes()).unwrap();
+                let to_b = rewrap_key(&wrapped, &token).unwrap();
+                assert_eq!(
+                    unwrap_key_delegable::<Kdf, Kem>(&sk_b, &to_b, ctx)
+                        .unwrap()
+                        .as_slice(),
+                    &dek
+                );
+                let to_c = rewrap_key(
+                    &to_b,
+                    &delegate::<Kdf, Kem, _>(&sk_b, &pk_c, &to_b, ctx, &mut csprng).unwrap(),
+                )
+                .unwrap();
+                assert_eq!(
+                    unwrap_key_delegable::<Kdf, Kem>(&sk_c, &to_c, ctx)
+                        .unwrap()
+                        .as_slice(),
+                    &dek
+                );
+
+                // Wrong recipient and wrong context
+                assert_eq!(
+                    unwrap_key_delegable::<Kdf, Kem>(&sk_a, &to_b, ctx).err(),
+                    Some(HpkeError::OpenError)
+                );
+                assert_eq!(
+                    unwrap_key_delegable::<Kdf, Kem>(&sk_b, &to_b, b"object 18").err(),
+                    Some(HpkeError::OpenError)
+                );
+                // Only the recipient can delegate
+                assert_eq!(
+                    delegate::<Kdf, Kem, _>(&sk_b, &pk_c, &wrapped, ctx, &mut csprng).err(),
+                    Some(HpkeError::OpenError)
+                );
+
+                // A token for a different wrap of the same length gives garbage, which is caught
+                let other =
+                    wrap_key_delegable::<Kdf, Kem, _>(&pk_a, &dek, ctx, &mut csprng).unwrap();
+                let misapplied = rewrap_key(&other, &token).unwrap();
+                assert_eq!(
+                    unwrap_key_delegable::<Kdf, Kem>(&sk_b, &misapplied, ctx).err(),
+                    Some(HpkeError::OpenError)
+                );
+
+                // A token for a key of a different length is rejected outright
+                let short =
+                    wrap_key_delegable::<Kdf, Kem, _>(&pk_a, &dek[..16], ctx, &mut csprng).unwrap();
+                assert_eq!(
+                    rewrap_key(&short, &token).err(),
+                    Some(HpkeError::ValidationError)
+                );
+                assert_eq!(
+                    wrap_key_delegable::<Kdf, Kem, _>(&pk_a, b"", ctx, &mut csprng).err(),
+                    Some(HpkeError::ValidationError)
+                );
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_delegation!(test_delegation_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_delegation!(test_delegation_nistp256, crate::kem::DhP256HkdfSha256);
+}
@@ -0,0 +1,307 @@
+//! Reading envelopes across format versions and ciphersuites, and moving old envelopes to a new
+//! recipient key or suite.
+//!
+//! Every envelope starts with a version byte and the suite IDs, so a reader can tell what it's
+//! holding before parsing the rest. `detect_suite` reads just that header. Paired with
+//! `dyn_suite::by_id`, or a match on the IDs, it lets one reader open envelopes from every suite
+//! that was ever in use, while new ones are sealed under the current suite. To retire an old key
+//! or suite entirely, `reencrypt` opens an envelope and seals its contents again.
+//!
+//! ```
+//! # #[cfg(all(feature = "x25519", feature = "p256"))]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::{AesGcm256, ChaCha20Poly1305},
+//!     envelope::{detect_suite, open_envelope, reencrypt, seal_to_envelope, Envelope},
+//!     kdf::{HkdfSha256, HkdfSha384},
+//!     kem::{DhP256HkdfSha256, X25519HkdfSha256},
+//!     Kem, OpModeR, OpModeS,
+//! };
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (old_sk, old_pk) = X25519HkdfSha256::gen_keypair(&mut csprng);
+//! let (new_sk, new_pk) = DhP256HkdfSha256::gen_keypair(&mut csprng);
+//!
+//! let wire = seal_to_envelope::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256, _>(
+//!     &OpModeS::Base,
+//!     &old_pk,
+//!     b"info",
+//!     b"hello",
+//!     b"aad",
+//!     &mut csprng,
+//! )
+//! .unwrap()
+//! .to_bytes();
+//! assert_eq!(detect_suite(&wire).unwrap().kem_id, 0x0020);
+//!
+//! // Move the envelope to the new key and suite
+//! let migrated = reencrypt::<
+//!     ChaCha20Poly1305,
+//!     HkdfSha256,
+//!     X25519HkdfSha256,
+//!     AesGcm256,
+//!     HkdfSha384,
+//!     DhP256HkdfSha256,
+//!     _,
+//! >(
+//!     &Envelope::from_bytes(&wire).unwrap(),
+//!     &OpModeR::Base,
+//!     &old_sk,
+//!     &OpModeS::Base,
+//!     &new_pk,
+//!     b"info",
+//!     b"aad",
+//!     &mut csprng,
+//! )
+//! .unwrap();
+//! let plaintext = open_envelope::<AesGcm256, HkdfSha384, DhP256HkdfSha256>(
+//!     &OpModeR::Base,
+//!     &new_sk,
+//!     &migrated,
+//!     b"info",
+//!     b"aad",
+//! )
+//! .unwrap();
+//! assert_eq!(plaintext, b"hello");
+//! # }
+//! ```
+
+use super::{open_envelope, seal_to_envelope_inner, Envelope, ENVELOPE_VERSION};
+use crate::{
+    aead::Aead,
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    HpkeError,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+/// The envelope format versions that `Envelope::from_bytes` and `detect_suite` accept, and that
+/// `Envelope::to_bytes_versioned` writes, oldest first. `Envelope::to_bytes` always writes the
+/// last one, `ENVELOPE_VERSION`.
+pub const SUPPORTED_ENVELOPE_VERSIONS: &[u8] = &[ENVELOPE_VERSION];
+
+/// Picks the envelope format version to send to a peer that can read the versions in `offered`.
+/// Returns the newest version that both sides support, or `None` if there is none. Encode
+/// envelopes for that peer with `Envelope::to_bytes_versioned` and the returned version.
+pub fn negotiate_version(offered: &[u8]) -> Option<u8> {
+    SUPPORTED_ENVELOPE_VERSIONS
+        .iter()
+        .rev()
+        .find(|v| offered.contains(v))
+        .copied()
+}
+
+/// The unauthenticated header at the front of an encoded `Envelope`. See `detect_suite`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnvelopeHeader {
+    /// The envelope format version
+    pub version: u8,
+    /// The KEM's algorithm identifier
+    pub kem_id: u16,
+    /// The KDF's algorithm identifier
+    pub kdf_id: u16,
+    /// The AEAD's algorithm identifier
+    pub aead_id: u16,
+}
+
+/// Reads the format version and suite IDs off the front of an encoded `Envelope`, without parsing
+/// or copying the rest. This is for deciding which key and suite to open it with. Nothing here is
+/// authenticated until the envelope opens.
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if `encoded` is shorter than the header, or its
+/// version isn't in `SUPPORTED_ENVELOPE_VERSIONS`.
+pub fn detect_suite(encoded: &[u8]) -> Result<EnvelopeHeader, HpkeError> {
+    if encoded.len() < 7 || !SUPPORTED_ENVELOPE_VERSIONS.contains(&encoded[0]) {
+        return Err(HpkeError::ValidationError);
+    }
+
+    Ok(EnvelopeHeader {
+        version: encoded[0],
+        kem_id: BigEndian::read_u16(&encoded[1..3]),
+        kdf_id: BigEndian::read_u16(&encoded[3..5]),
+        aead_id: BigEndian::read_u16(&encoded[5..7]),
+    })
+}
+
+/// Opens `envelope`, which was sealed under the suite `(OldA, OldKdf, OldKem)` to the public key
+/// of `old_sk`, and seals the plaintext again under `(A, Kdf, Kem)` to `new_pk_recip`. The info
+/// string, AAD, and AAD header, if any, carry over unchanged. The plaintext only lives in a
+/// buffer that's zeroized on drop.
+///
+/// The new envelope is sealed by whoever runs this. In particular, if `old_mode` is an Auth mode,
+/// the original sender's authentication doesn't carry over. The new envelope is only
+/// authenticated as coming from the key in `new_mode`, if any.
+///
+/// Return Value
+/// ============
+/// Returns the new envelope on success. If opening fails, returns the errors `open_envelope`
+/// does, and nothing is sealed. Otherwise, returns the errors `seal_to_envelope` does.
+#[allow(clippy::too_many_arguments)]
+pub fn reencrypt<OldA, OldKdf, OldKem, A, Kdf, Kem, R>(
+    envelope: &Envelope,
+    old_mode: &OpModeR<OldKem>,
+    old_sk: &OldKem::PrivateKey,
+    new_mode: &OpModeS<Kem>,
+    new_pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    OldA: Aead,
+    OldKdf: KdfTrait,
+    OldKem: KemTrait,
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let plaintext = Zeroizing::new(open_envelope::<OldA, OldKdf, OldKem>(
+        old_mode, old_sk, envelope, info, aad,
+    )?);
+    seal_to_envelope_inner::<A, Kdf, Kem, R>(
+        new_mode,
+        new_pk_recip,
+        info,
+        &plaintext,
+        aad,
+        envelope.aad_header(),
+        csprng,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect_suite, negotiate_version, EnvelopeHeader};
+    use crate::{
+        envelope::{Envelope, ENVELOPE_VERSION},
+        HpkeError,
+    };
+
+    /// Tests that the newest common version is picked, and that headers are read without the
+    /// rest of the envelope
+    #[test]
+    fn test_version_and_header() {
+        assert_eq!(
+            negotiate_version(&[7, ENVELOPE_VERSION]),
+            Some(ENVELOPE_VERSION)
+        );
+        assert_eq!(negotiate_version(&[7]), None);
+        assert_eq!(negotiate_version(&[]), None);
+
+        // Whatever version is negotiated can be written
+        let good = b"\x01\x00\x20\x00\x01\x00\x03\x00\x00\x02eeccc";
+        let envelope = Envelope::from_bytes(good).unwrap();
+        let version = negotiate_version(&[7, ENVELOPE_VERSION]).unwrap();
+        assert_eq!(envelope.to_bytes_versioned(version).unwrap(), good);
+        assert_eq!(
+            envelope.to_bytes_versioned(7),
+            Err(HpkeError::ValidationError)
+        );
+
+        // A header alone is enough, even though it isn't a whole envelope
+        let header = b"\x01\x00\x20\x00\x01\x00\x03";
+        assert_eq!(
+            detect_suite(header),
+            Ok(EnvelopeHeader {
+                version: 1,
+                kem_id: 0x0020,
+                kdf_id: 0x0001,
+                aead_id: 0x0003,
+            })
+        );
+        assert_eq!(detect_suite(&header[..6]), Err(HpkeError::ValidationError));
+        assert_eq!(
+            detect_suite(b"\x07\x00\x20\x00\x01\x00\x03"),
+            Err(HpkeError::ValidationError)
+        );
+    }
+
+    /// Tests that envelopes move to a new key and suite, and that nothing is resealed if the
+    /// old key is wrong
+    #[cfg(all(feature = "x25519", feature = "p256"))]
+    #[test]
+    fn test_reencrypt() {
+        use super::reencrypt;
+        use crate::{
+            aead::{AesGcm256, ChaCha20Poly1305},
+            envelope::{open_envelope, seal_to_envelope},
+            kdf::{HkdfSha256, HkdfSha384},
+            kem::{DhP256HkdfSha256, Kem as KemTrait, X25519HkdfSha256},
+            op_mode::{OpModeR, OpModeS, PskBundle},
+        };
+
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut csprng = StdRng::from_entropy();
+        let (old_sk, old_pk) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let (wrong_sk, _) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let (new_sk, new_pk) = DhP256HkdfSha256::gen_keypair(&mut csprng);
+        let psk = PskBundle {
+            psk: b"a preshared key of sufficient length",
+            psk_id: b"psk #7",
+        };
+
+        let old = seal_to_envelope::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256, _>(
+            &OpModeS::Psk(psk),
+            &old_pk,
+            b"info",
+            b"msg",
+            b"aad",
+            &mut csprng,
+        )
+        .unwrap();
+
+        let migrate = |sk, csprng: &mut StdRng| {
+            reencrypt::<
+                ChaCha20Poly1305,
+                HkdfSha256,
+                X25519HkdfSha256,
+                AesGcm256,
+                HkdfSha384,
+                DhP256HkdfSha256,
+                _,
+            >(
+                &old,
+                &OpModeR::Psk(psk),
+                sk,
+                &OpModeS::Base,
+                &new_pk,
+                b"info",
+                b"aad",
+                csprng,
+            )
+        };
+
+        let new = migrate(&old_sk, &mut csprng).unwrap();
+        let header = detect_suite(&new.to_bytes()).unwrap();
+        assert_eq!(
+            (header.kem_id, header.kdf_id, header.aead_id),
+            (0x0010, 0x0002, 0x0002)
+        );
+        assert_eq!(new.psk_id(), None);
+        assert_eq!(
+            open_envelope::<AesGcm256, HkdfSha384, DhP256HkdfSha256>(
+                &OpModeR::Base,
+                &new_sk,
+                &new,
+                b"info",
+                b"aad",
+            )
+            .unwrap(),
+            b"msg"
+        );
+
+        assert_eq!(
+            migrate(&wrong_sk, &mut csprng).err(),
+            Some(HpkeError::OpenError)
+        );
+    }
+}
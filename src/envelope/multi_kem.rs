@@ -0,0 +1,457 @@
+//! Envelopes addressed to recipients under different KEMs at once, for migrations where some
+//! recipients only have a key under the old KEM and some only have one under the new.
+//!
+//! The payload is sealed once with the AEAD `A`, under a fresh content key and an all-zero nonce.
+//! Each recipient gets a stanza, which is the content key wrapped to their public key with a
+//! base-mode single-shot seal under their own KEM. Every stanza uses the same KDF and AEAD, and
+//! its info string is `MULTI_KEM_INFO`. Its AAD binds it to the suite and the sealed payload:
+//!
+//! ```text
+//! kdf_id (2) || aead_id (2) || Hash(ciphertext)
+//! ```
+//!
+//! where `Hash` is the KDF's hash. So a stanza can't be moved onto another payload, though, as
+//! in `group`, any recipient who learns the content key could make their own envelope.
+//!
+//! A receiver picks the stanzas under the KEM of the key it holds, by KEM ID, and only tries
+//! those. `kem_ids` lists which KEMs an envelope was sealed to, for picking a key up front.
+//!
+//! ```
+//! # #[cfg(all(feature = "k256", feature = "x25519"))]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     envelope::{open_multi_kem, MultiKemEnvelope, MultiKemSealer},
+//!     kdf::HkdfSha256,
+//!     kem::{DhK256HkdfSha256, X25519HkdfSha256},
+//!     Kem,
+//! };
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_old, pk_old) = DhK256HkdfSha256::gen_keypair(&mut csprng);
+//! let (sk_new, pk_new) = X25519HkdfSha256::gen_keypair(&mut csprng);
+//!
+//! let mut sealer = MultiKemSealer::<A, Kdf>::new(b"hello", b"aad", &mut csprng).unwrap();
+//! sealer
+//!     .add_recipient::<DhK256HkdfSha256, _>(&pk_old, &mut csprng)
+//!     .unwrap();
+//! sealer
+//!     .add_recipient::<X25519HkdfSha256, _>(&pk_new, &mut csprng)
+//!     .unwrap();
+//! let wire = sealer.finish().to_bytes();
+//!
+//! let envelope = MultiKemEnvelope::from_bytes(&wire).unwrap();
+//! assert_eq!(envelope.kem_ids().collect::<Vec<_>>(), [0x0030, 0x0020]);
+//! let from_old = open_multi_kem::<A, Kdf, DhK256HkdfSha256>(&sk_old, &envelope, b"aad").unwrap();
+//! let from_new = open_multi_kem::<A, Kdf, X25519HkdfSha256>(&sk_new, &envelope, b"aad").unwrap();
+//! assert_eq!(from_old, b"hello");
+//! assert_eq!(from_new, b"hello");
+//! # }
+//! ```
+
+use super::{read_with_len, write_with_len};
+use crate::{
+    aead::Aead,
+    group::{content_key_len, content_tag_len, open_content, seal_content},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    single_shot::{single_shot_open, single_shot_seal},
+    Deserializable, HpkeError, Serializable, Vec,
+};
+
+use core::marker::PhantomData;
+
+use byteorder::{BigEndian, ByteOrder};
+use digest::Digest;
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+/// The version byte that `MultiKemEnvelope::to_bytes` writes, and the only one `from_bytes`
+/// accepts. It's distinct from every `Envelope` version, so neither parses as the other.
+pub const MULTI_KEM_ENVELOPE_VERSION: u8 = 0x81;
+
+/// The info string of every stanza's single-shot seal
+pub const MULTI_KEM_INFO: &[u8] = b"HPKE multi-KEM v1";
+
+/// One recipient's wrapped content key in a `MultiKemEnvelope`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KemStanza {
+    /// The ID of the KEM this stanza was sealed under
+    pub kem_id: u16,
+    /// The encapsulated key, serialized
+    pub encapped_key: Vec<u8>,
+    /// The content key, sealed to the recipient
+    pub wrapped_key: Vec<u8>,
+}
+
+/// A payload sealed once for recipients under several KEMs, with one `KemStanza` per recipient.
+/// Make one with `MultiKemSealer`, and open it with `open_multi_kem`.
+///
+/// The binary encoding, `to_bytes`, is
+///
+/// ```text
+/// version (1) || kdf_id (2) || aead_id (2) || num_stanzas (2)
+///     || num_stanzas * (kem_id (2) || enc_len (2) || enc || wrapped_len (2) || wrapped_key)
+///     || ciphertext                 (the rest)
+/// ```
+///
+/// with all integers big-endian.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiKemEnvelope {
+    kdf_id: u16,
+    aead_id: u16,
+    stanzas: Vec<KemStanza>,
+    ciphertext: Vec<u8>,
+}
+
+impl MultiKemEnvelope {
+    /// Returns the KDF's algorithm identifier
+    pub fn kdf_id(&self) -> u16 {
+        self.kdf_id
+    }
+
+    /// Returns the AEAD's algorithm identifier
+    pub fn aead_id(&self) -> u16 {
+        self.aead_id
+    }
+
+    /// Returns the stanzas, in the order their recipients were added
+    pub fn stanzas(&self) -> &[KemStanza] {
+        &self.stanzas
+    }
+
+    /// Returns the KEM ID of each stanza, in order. A KEM appears once per recipient under it.
+    pub fn kem_ids(&self) -> impl Iterator<Item = u16> + '_ {
+        self.stanzas.iter().map(|s| s.kem_id)
+    }
+
+    /// Returns the sealed payload, with the tag appended
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+
+    /// Encodes this envelope in the binary format described in `MultiKemEnvelope`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let stanzas_len: usize = self
+            .stanzas
+            .iter()
+            .map(|s| 6 + s.encapped_key.len() + s.wrapped_key.len())
+            .sum();
+        let mut out = Vec::with_capacity(7 + stanzas_len + self.ciphertext.len());
+
+        let mut header = [0u8; 7];
+        header[0] = MULTI_KEM_ENVELOPE_VERSION;
+        BigEndian::write_u16(&mut header[1..3], self.kdf_id);
+        BigEndian::write_u16(&mut header[3..5], self.aead_id);
+        // MultiKemSealer makes sure all of these fit in a u16
+        BigEndian::write_u16(&mut header[5..7], self.stanzas.len() as u16);
+        out.extend_from_slice(&header);
+
+        for stanza in &self.stanzas {
+            let mut kem_id = [0u8; 2];
+            BigEndian::write_u16(&mut kem_id, stanza.kem_id);
+            out.extend_from_slice(&kem_id);
+            write_with_len(&mut out, &stanza.encapped_key);
+            write_with_len(&mut out, &stanza.wrapped_key);
+        }
+        out.extend_from_slice(&self.ciphertext);
+
+        out
+    }
+
+    /// Decodes an envelope from the binary format described in `MultiKemEnvelope`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the version isn't
+    /// `MULTI_KEM_ENVELOPE_VERSION`, or the input is truncated.
+    pub fn from_bytes(encoded: &[u8]) -> Result<MultiKemEnvelope, HpkeError> {
+        if encoded.len() < 7 || encoded[0] != MULTI_KEM_ENVELOPE_VERSION {
+            return Err(HpkeError::ValidationError);
+        }
+        let kdf_id = BigEndian::read_u16(&encoded[1..3]);
+        let aead_id = BigEndian::read_u16(&encoded[3..5]);
+        let num_stanzas = BigEndian::read_u16(&encoded[5..7]);
+
+        let mut rest = &encoded[7..];
+        let mut stanzas = Vec::new();
+        for _ in 0..num_stanzas {
+            if rest.len() < 2 {
+                return Err(HpkeError::ValidationError);
+            }
+            let kem_id = BigEndian::read_u16(&rest[..2]);
+            let (encapped_key, after_enc) = read_with_len(&rest[2..])?;
+            let (wrapped_key, after_wrapped) = read_with_len(after_enc)?;
+            stanzas.push(KemStanza {
+                kem_id,
+                encapped_key: encapped_key.to_vec(),
+                wrapped_key: wrapped_key.to_vec(),
+            });
+            rest = after_wrapped;
+        }
+
+        Ok(MultiKemEnvelope {
+            kdf_id,
+            aead_id,
+            stanzas,
+            ciphertext: rest.to_vec(),
+        })
+    }
+}
+
+// kdf_id || aead_id || Hash(ciphertext)
+fn stanza_aad<A: Aead, Kdf: KdfTrait>(ciphertext: &[u8]) -> Vec<u8> {
+    let mut ids = [0u8; 4];
+    BigEndian::write_u16(&mut ids[..2], Kdf::KDF_ID);
+    BigEndian::write_u16(&mut ids[2..], A::AEAD_ID);
+    [&ids[..], &Kdf::HashImpl::digest(ciphertext)].concat()
+}
+
+/// Builds a `MultiKemEnvelope`. `new` seals the payload, each `add_recipient` adds a stanza under
+/// that recipient's KEM, and `finish` returns the envelope.
+pub struct MultiKemSealer<A: Aead, Kdf: KdfTrait> {
+    content_key: Zeroizing<Vec<u8>>,
+    stanza_aad: Vec<u8>,
+    envelope: MultiKemEnvelope,
+    suite: PhantomData<(A, Kdf)>,
+}
+
+impl<A: Aead, Kdf: KdfTrait> MultiKemSealer<A, Kdf> {
+    /// Seals `plaintext` with `A` under a fresh content key. The envelope has no recipients yet.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the sealer on success. If `A` is `ExportOnlyAead`, which can't seal the payload,
+    /// returns `Err(HpkeError::ValidationError)`. If an error happened during encryption, returns
+    /// `Err(HpkeError::SealError)`.
+    pub fn new<R: CryptoRng + RngCore + ?Sized>(
+        plaintext: &[u8],
+        aad: &[u8],
+        csprng: &mut R,
+    ) -> Result<Self, HpkeError> {
+        let mut content_key = Zeroizing::new(vec![0u8; content_key_len::<A>()?]);
+        csprng.fill_bytes(&mut content_key);
+
+        let ciphertext = seal_content::<A>(&content_key, plaintext, aad)?;
+
+        Ok(MultiKemSealer {
+            content_key,
+            stanza_aad: stanza_aad::<A, Kdf>(&ciphertext),
+            envelope: MultiKemEnvelope {
+                kdf_id: Kdf::KDF_ID,
+                aead_id: A::AEAD_ID,
+                stanzas: Vec::new(),
+                ciphertext,
+            },
+            suite: PhantomData,
+        })
+    }
+
+    /// Adds a stanza that wraps the content key to `pk_recip`, under the KEM `Kem`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If the envelope already has 65535 stanzas, returns
+    /// `Err(HpkeError::ValidationError)`. Otherwise, returns the errors `single_shot_seal` does.
+    pub fn add_recipient<Kem: KemTrait, R: CryptoRng + RngCore + ?Sized>(
+        &mut self,
+        pk_recip: &Kem::PublicKey,
+        csprng: &mut R,
+    ) -> Result<(), HpkeError> {
+        if self.envelope.stanzas.len() >= u16::MAX as usize {
+            return Err(HpkeError::ValidationError);
+        }
+
+        let (encapped_key, wrapped_key) = single_shot_seal::<A, Kdf, Kem, R>(
+            &OpModeS::Base,
+            pk_recip,
+            MULTI_KEM_INFO,
+            &self.content_key,
+            &self.stanza_aad,
+            csprng,
+        )?;
+        self.envelope.stanzas.push(KemStanza {
+            kem_id: Kem::KEM_ID,
+            encapped_key: encapped_key.to_bytes().to_vec(),
+            wrapped_key,
+        });
+        Ok(())
+    }
+
+    /// Returns the envelope, and forgets the content key
+    pub fn finish(self) -> MultiKemEnvelope {
+        self.envelope
+    }
+}
+
+/// Opens a `MultiKemEnvelope` with `sk_recip`, a private key under the KEM `Kem`. Only the
+/// stanzas under `Kem` are tried, in order, until one unwraps the content key.
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. Returns `Err(HpkeError::ValidationError)` if the envelope's
+/// KDF and AEAD aren't `Kdf` and `A`, if `A` is `ExportOnlyAead`, or if no stanza is under `Kem`.
+/// Returns `Err(HpkeError::OpenError)` if no stanza under `Kem` unwraps with `sk_recip`, or the
+/// payload doesn't open.
+pub fn open_multi_kem<A, Kdf, Kem>(
+    sk_recip: &Kem::PrivateKey,
+    envelope: &MultiKemEnvelope,
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if (envelope.kdf_id, envelope.aead_id) != (Kdf::KDF_ID, A::AEAD_ID) {
+        return Err(HpkeError::ValidationError);
+    }
+    let key_len = content_key_len::<A>()?;
+    if envelope.ciphertext.len() < content_tag_len::<A>() {
+        return Err(HpkeError::OpenError);
+    }
+
+    let mut candidates = envelope
+        .stanzas
+        .iter()
+        .filter(|s| s.kem_id == Kem::KEM_ID)
+        .peekable();
+    if candidates.peek().is_none() {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let stanza_aad = stanza_aad::<A, Kdf>(&envelope.ciphertext);
+    let content_key = candidates
+        .find_map(|stanza| {
+            let encapped_key = Kem::EncappedKey::from_bytes(&stanza.encapped_key).ok()?;
+            single_shot_open::<A, Kdf, Kem>(
+                &OpModeR::Base,
+                sk_recip,
+                &encapped_key,
+                MULTI_KEM_INFO,
+                &stanza.wrapped_key,
+                &stanza_aad,
+            )
+            .ok()
+            .map(Zeroizing::new)
+        })
+        .filter(|key| key.len() == key_len)
+        .ok_or(HpkeError::OpenError)?;
+
+    open_content::<A>(&content_key, &envelope.ciphertext, aad)
+}
+
+#[cfg(all(test, feature = "x25519", feature = "p256"))]
+mod test {
+    use super::{open_multi_kem, MultiKemEnvelope, MultiKemSealer};
+    use crate::{
+        aead::{AesGcm128, ExportOnlyAead},
+        kdf::{HkdfSha256, HkdfSha384},
+        kem::{DhP256HkdfSha256, Kem as KemTrait, X25519HkdfSha256},
+        HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = AesGcm128;
+    type Kdf = HkdfSha256;
+
+    /// Tests that recipients under each KEM open the envelope after an encoding roundtrip, and
+    /// that other keys, suites, and payloads don't
+    #[test]
+    fn test_multi_kem_roundtrip() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_p256, pk_p256) = DhP256HkdfSha256::gen_keypair(&mut csprng);
+        let (sk_x1, pk_x1) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let (sk_x2, pk_x2) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let (sk_outsider, _) = X25519HkdfSha256::gen_keypair(&mut csprng);
+
+        let seal = |plaintext: &[u8], csprng: &mut StdRng| {
+            let mut sealer = MultiKemSealer::<A, Kdf>::new(plaintext, b"aad", csprng).unwrap();
+            sealer
+                .add_recipient::<DhP256HkdfSha256, _>(&pk_p256, csprng)
+                .unwrap();
+            sealer
+                .add_recipient::<X25519HkdfSha256, _>(&pk_x1, csprng)
+                .unwrap();
+            sealer
+                .add_recipient::<X25519HkdfSha256, _>(&pk_x2, csprng)
+                .unwrap();
+            sealer.finish()
+        };
+
+        let envelope = seal(b"migrating", &mut csprng);
+        let envelope = MultiKemEnvelope::from_bytes(&envelope.to_bytes()).unwrap();
+        assert_eq!(
+            envelope.kem_ids().collect::<crate::Vec<_>>(),
+            [0x0010, 0x0020, 0x0020]
+        );
+
+        let p256 = open_multi_kem::<A, Kdf, DhP256HkdfSha256>(&sk_p256, &envelope, b"aad");
+        assert_eq!(p256.unwrap(), b"migrating");
+        for sk in [&sk_x1, &sk_x2] {
+            let x25519 = open_multi_kem::<A, Kdf, X25519HkdfSha256>(sk, &envelope, b"aad");
+            assert_eq!(x25519.unwrap(), b"migrating");
+        }
+
+        assert_eq!(
+            open_multi_kem::<A, Kdf, X25519HkdfSha256>(&sk_outsider, &envelope, b"aad"),
+            Err(HpkeError::OpenError)
+        );
+        assert_eq!(
+            open_multi_kem::<A, Kdf, X25519HkdfSha256>(&sk_x1, &envelope, b"other"),
+            Err(HpkeError::OpenError)
+        );
+        assert_eq!(
+            open_multi_kem::<A, HkdfSha384, X25519HkdfSha256>(&sk_x1, &envelope, b"aad"),
+            Err(HpkeError::ValidationError)
+        );
+
+        // A stanza from another envelope doesn't unwrap here
+        let other = seal(b"something else", &mut csprng);
+        let mut spliced = envelope.clone();
+        spliced.stanzas[1] = other.stanzas[1].clone();
+        assert_eq!(
+            open_multi_kem::<A, Kdf, X25519HkdfSha256>(&sk_x1, &spliced, b"aad"),
+            Err(HpkeError::OpenError)
+        );
+
+        // No stanza under the KEM at all
+        spliced.stanzas.truncate(1);
+        assert_eq!(
+            open_multi_kem::<A, Kdf, X25519HkdfSha256>(&sk_x2, &spliced, b"aad"),
+            Err(HpkeError::ValidationError)
+        );
+    }
+
+    /// Tests that truncated and mislabeled encodings are rejected, and the export-only AEAD
+    /// can't seal a payload
+    #[test]
+    fn test_multi_kem_encoding_errors() {
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let mut sealer = MultiKemSealer::<A, Kdf>::new(b"", b"", &mut csprng).unwrap();
+        sealer
+            .add_recipient::<X25519HkdfSha256, _>(&pk, &mut csprng)
+            .unwrap();
+        let wire = sealer.finish().to_bytes();
+
+        // Cutting into the stanza is an error, but the ciphertext is just "the rest"
+        assert!(MultiKemEnvelope::from_bytes(&wire[..20]).is_err());
+        let mut bad_version = wire.clone();
+        bad_version[0] = crate::envelope::ENVELOPE_VERSION;
+        assert_eq!(
+            MultiKemEnvelope::from_bytes(&bad_version),
+            Err(HpkeError::ValidationError)
+        );
+        assert!(matches!(
+            MultiKemSealer::<ExportOnlyAead, Kdf>::new(b"", b"", &mut csprng),
+            Err(HpkeError::ValidationError)
+        ));
+    }
+}
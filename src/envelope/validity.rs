@@ -0,0 +1,376 @@
+use super::{open_envelope_with_ciphertext, seal_to_envelope, Envelope};
+use crate::{
+    aead::Aead,
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    HpkeError, Vec,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+/// Prepended to the AAD of time-bound envelopes, so a time-bound envelope doesn't open with
+/// `open_envelope`, and an ordinary one doesn't open with `open_envelope_with_policy`
+const VALIDITY_AAD_PREFIX: &[u8] = b"HPKE validity v1\x00";
+
+// The header at the front of a time-bound envelope's ciphertext:
+// version (1) || not_before (8) || not_after (8)
+const VALIDITY_VERSION: u8 = 1;
+const VALIDITY_HEADER_LEN: usize = 17;
+
+/// The times between which a time-bound envelope opens, as Unix times in seconds. Both ends are
+/// inclusive. The default is unbounded on both ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Validity {
+    /// The envelope doesn't open before this time
+    pub not_before: u64,
+    /// The envelope doesn't open after this time
+    pub not_after: u64,
+}
+
+impl Default for Validity {
+    fn default() -> Validity {
+        Validity {
+            not_before: 0,
+            not_after: u64::MAX,
+        }
+    }
+}
+
+impl Validity {
+    /// Valid from `not_before` through `not_after`
+    pub fn between(not_before: u64, not_after: u64) -> Validity {
+        Validity {
+            not_before,
+            not_after,
+        }
+    }
+
+    /// Valid at any time up to and including `not_after`
+    pub fn until(not_after: u64) -> Validity {
+        Validity {
+            not_after,
+            ..Validity::default()
+        }
+    }
+
+    fn to_header(self) -> [u8; VALIDITY_HEADER_LEN] {
+        let mut header = [0u8; VALIDITY_HEADER_LEN];
+        header[0] = VALIDITY_VERSION;
+        BigEndian::write_u64(&mut header[1..9], self.not_before);
+        BigEndian::write_u64(&mut header[9..17], self.not_after);
+        header
+    }
+
+    fn from_header(header: &[u8]) -> Result<Validity, HpkeError> {
+        if header.len() < VALIDITY_HEADER_LEN || header[0] != VALIDITY_VERSION {
+            return Err(HpkeError::ValidationError);
+        }
+        Ok(Validity {
+            not_before: BigEndian::read_u64(&header[1..9]),
+            not_after: BigEndian::read_u64(&header[9..17]),
+        })
+    }
+}
+
+/// A source of the current Unix time, in seconds. Closures that return it are clocks, so tests can
+/// pass `|| 1_700_000_000`.
+pub trait Clock {
+    /// Returns the current Unix time, in seconds
+    fn now(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// The system's wall clock. Times before the Unix epoch read as 0.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+/// How `open_envelope_with_policy` decides whether a time-bound envelope is still good: the clock
+/// to read, and how many seconds of clock skew between sender and receiver to tolerate at either
+/// end of the window
+#[derive(Clone, Copy, Debug)]
+pub struct ValidityPolicy<C: Clock> {
+    clock: C,
+    leeway: u64,
+}
+
+impl<C: Clock> ValidityPolicy<C> {
+    /// Makes a policy that reads `clock`, with no leeway
+    pub fn new(clock: C) -> ValidityPolicy<C> {
+        ValidityPolicy { clock, leeway: 0 }
+    }
+
+    /// Tolerates `leeway` seconds of clock skew at either end of the window
+    pub fn with_leeway(mut self, leeway: u64) -> ValidityPolicy<C> {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Returns whether `validity` covers the current time, give or take the leeway
+    pub fn allows(&self, validity: &Validity) -> bool {
+        let now = self.clock.now();
+        now.saturating_add(self.leeway) >= validity.not_before
+            && now <= validity.not_after.saturating_add(self.leeway)
+    }
+}
+
+// Returns VALIDITY_AAD_PREFIX || header || aad
+fn validity_aad(header: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(VALIDITY_AAD_PREFIX.len() + header.len() + aad.len());
+    out.extend_from_slice(VALIDITY_AAD_PREFIX);
+    out.extend_from_slice(header);
+    out.extend_from_slice(aad);
+    out
+}
+
+/// Does `seal_to_envelope`, with `validity` bound into the AAD. The validity window goes at the
+/// front of the envelope's ciphertext, in the clear, so the envelope only opens with
+/// `open_envelope_with_policy`, which checks the window against the receiver's clock. The
+/// ciphertext is
+///
+/// ```text
+/// version (1) || not_before (8) || not_after (8) || Seal(..., aad = prefix || header || aad, pt)
+/// ```
+///
+/// with the times big-endian, where `header` is the 17 bytes before the sealed part.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Returns `Err(HpkeError::ValidationError)` if
+/// `validity.not_before` is after `validity.not_after`. Otherwise, returns the errors
+/// `seal_to_envelope` does.
+pub fn seal_to_envelope_with_validity<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    validity: Validity,
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    if validity.not_before > validity.not_after {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let header = validity.to_header();
+    let mut envelope = seal_to_envelope::<A, Kdf, Kem, R>(
+        mode,
+        pk_recip,
+        info,
+        plaintext,
+        &validity_aad(&header, aad),
+        csprng,
+    )?;
+
+    let mut ciphertext = Vec::with_capacity(VALIDITY_HEADER_LEN + envelope.ciphertext.len());
+    ciphertext.extend_from_slice(&header);
+    ciphertext.extend_from_slice(&envelope.ciphertext);
+    envelope.ciphertext = ciphertext;
+    Ok(envelope)
+}
+
+/// Reads the validity window off a time-bound envelope without opening it, e.g., to drop expired
+/// envelopes from a queue cheaply. The window isn't authenticated until the envelope opens.
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if the envelope is too short to hold a window, or
+/// its window has an unknown version.
+pub fn envelope_validity(envelope: &Envelope) -> Result<Validity, HpkeError> {
+    Validity::from_header(&envelope.ciphertext)
+}
+
+/// Opens an `Envelope` made by `seal_to_envelope_with_validity`, if `policy` says its validity
+/// window covers the current time. The window is checked after the envelope opens, so it's
+/// authentic, and the plaintext is never returned outside of it.
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. Returns `Err(HpkeError::OutsideValidityWindow)` if the
+/// envelope opened, but isn't valid yet or has expired. Returns `Err(HpkeError::ValidationError)`
+/// if it has no validity window, and `Err(HpkeError::OpenError)` if it wasn't made by
+/// `seal_to_envelope_with_validity` or its window was modified. Otherwise, returns the errors
+/// `open_envelope` does.
+pub fn open_envelope_with_policy<A, Kdf, Kem, C>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    info: &[u8],
+    aad: &[u8],
+    policy: &ValidityPolicy<C>,
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    C: Clock,
+{
+    let validity = envelope_validity(envelope)?;
+    let (header, ciphertext) = envelope.ciphertext.split_at(VALIDITY_HEADER_LEN);
+
+    // Outside the window, the plaintext is dropped, and shouldn't linger in freed memory
+    let mut plaintext = Zeroizing::new(open_envelope_with_ciphertext::<A, Kdf, Kem>(
+        mode,
+        sk_recip,
+        envelope,
+        ciphertext,
+        info,
+        &validity_aad(header, aad),
+    )?);
+
+    if policy.allows(&validity) {
+        Ok(core::mem::take(&mut *plaintext))
+    } else {
+        Err(HpkeError::OutsideValidityWindow)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "x25519")]
+    use super::{
+        envelope_validity, open_envelope_with_policy, seal_to_envelope_with_validity, Validity,
+        ValidityPolicy,
+    };
+    #[cfg(feature = "x25519")]
+    use crate::{
+        aead::ChaCha20Poly1305,
+        envelope::{open_envelope, seal_to_envelope},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        HpkeError, OpModeR, OpModeS,
+    };
+
+    #[cfg(feature = "x25519")]
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that time-bound envelopes open inside their window and only there, that leeway
+    /// widens the window, and that the window is authenticated
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_validity_window() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let validity = Validity::between(1000, 2000);
+        let envelope = seal_to_envelope_with_validity::<A, Kdf, Kem, _>(
+            &OpModeS::Base,
+            &pk_recip,
+            b"info",
+            b"token",
+            b"aad",
+            validity,
+            &mut csprng,
+        )
+        .unwrap();
+        assert_eq!(envelope_validity(&envelope).unwrap(), validity);
+
+        let open_at = |now: u64, leeway: u64| {
+            open_envelope_with_policy::<A, Kdf, Kem, _>(
+                &OpModeR::Base,
+                &sk_recip,
+                &envelope,
+                b"info",
+                b"aad",
+                &ValidityPolicy::new(move || now).with_leeway(leeway),
+            )
+        };
+        for now in [1000, 1500, 2000] {
+            assert_eq!(open_at(now, 0).unwrap(), b"token");
+        }
+        for now in [0, 999, 2001, u64::MAX] {
+            assert_eq!(open_at(now, 0), Err(HpkeError::OutsideValidityWindow));
+        }
+        assert_eq!(open_at(990, 10).unwrap(), b"token");
+        assert_eq!(open_at(2010, 10).unwrap(), b"token");
+        assert_eq!(open_at(2011, 10), Err(HpkeError::OutsideValidityWindow));
+
+        // Extending the window breaks the AAD
+        let mut extended = envelope.clone();
+        extended.ciphertext[9..17].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(
+            open_envelope_with_policy::<A, Kdf, Kem, _>(
+                &OpModeR::Base,
+                &sk_recip,
+                &extended,
+                b"info",
+                b"aad",
+                &ValidityPolicy::new(|| 3000),
+            ),
+            Err(HpkeError::OpenError)
+        );
+
+        // Time-bound and ordinary envelopes don't open as each other
+        assert_eq!(
+            open_envelope::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &envelope, b"info", b"aad"),
+            Err(HpkeError::OpenError)
+        );
+        let plain = seal_to_envelope::<A, Kdf, Kem, _>(
+            &OpModeS::Base,
+            &pk_recip,
+            b"info",
+            b"token",
+            b"aad",
+            &mut csprng,
+        )
+        .unwrap();
+        assert!(open_envelope_with_policy::<A, Kdf, Kem, _>(
+            &OpModeR::Base,
+            &sk_recip,
+            &plain,
+            b"info",
+            b"aad",
+            &ValidityPolicy::new(|| 1500),
+        )
+        .is_err());
+
+        // An empty window is rejected up front
+        assert_eq!(
+            seal_to_envelope_with_validity::<A, Kdf, Kem, _>(
+                &OpModeS::Base,
+                &pk_recip,
+                b"info",
+                b"token",
+                b"aad",
+                Validity::between(2, 1),
+                &mut csprng,
+            )
+            .err(),
+            Some(HpkeError::ValidationError)
+        );
+    }
+
+    /// Tests that the system clock reads something after this code was written
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_system_clock() {
+        use super::{Clock, SystemClock};
+        assert!(SystemClock.now() > 1_700_000_000);
+    }
+}
@@ -0,0 +1,217 @@
+//! Entry points for fuzzing the parsers and the open path. Each function takes raw fuzzer input
+//! and panics if and only if it finds a bug, so a cargo-fuzz target is just
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     hpke::fuzz::open_arbitrary::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256>(data)
+//! });
+//! ```
+//!
+//! The functions that need structured inputs, like keys and modes, read them from the input with
+//! the `Arbitrary` impls that the `arbitrary` feature adds.
+
+use crate::{
+    aead::Aead,
+    envelope::{open_envelope, Envelope},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    setup::setup_sender_deterministic,
+    single_shot::single_shot_open,
+    Deserializable, Serializable,
+};
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use generic_array::typenum::Unsigned;
+
+/// Parses `data` as an `Envelope` and, if that works, checks that it re-encodes to exactly
+/// `data`
+///
+/// Panics
+/// ======
+/// Panics if the envelope encoding isn't canonical
+pub fn envelope_roundtrip(data: &[u8]) {
+    if let Ok(envelope) = Envelope::from_bytes(data) {
+        assert_eq!(
+            envelope.to_bytes(),
+            data,
+            "envelope encoding isn't canonical"
+        );
+    }
+}
+
+/// Parses `data` as each of `Kem`'s public key, private key, and encapsulated key types and, for
+/// each that parses, checks that it re-encodes to exactly `data`
+///
+/// Panics
+/// ======
+/// Panics if any of these encodings isn't canonical
+pub fn deserialize_roundtrip<Kem: KemTrait>(data: &[u8]) {
+    fn check<T: Serializable + Deserializable>(data: &[u8]) {
+        if let Ok(t) = T::from_bytes(data) {
+            assert_eq!(
+                t.to_bytes().as_slice(),
+                data,
+                "key encoding isn't canonical"
+            );
+        }
+    }
+
+    check::<Kem::PublicKey>(data);
+    check::<Kem::PrivateKey>(data);
+    check::<Kem::EncappedKey>(data);
+}
+
+/// Reads a recipient private key, receiver mode, info string, and AAD from `data`, then tries to
+/// open the rest of `data`, both as an encoded `Envelope` and as a raw encapsulated key followed
+/// by a ciphertext. Failures are expected. Only panics are bugs.
+pub fn open_arbitrary<A, Kdf, Kem>(data: &[u8])
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    for<'a> Kem::PrivateKey: Arbitrary<'a>,
+    for<'a> Kem::PublicKey: Arbitrary<'a>,
+{
+    // Running out of input partway through is fine. There's just nothing to test.
+    let _ = try_open_arbitrary::<A, Kdf, Kem>(&mut Unstructured::new(data));
+}
+
+fn try_open_arbitrary<A, Kdf, Kem>(u: &mut Unstructured<'_>) -> Result<()>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    for<'a> Kem::PrivateKey: Arbitrary<'a>,
+    for<'a> Kem::PublicKey: Arbitrary<'a>,
+{
+    let sk_recip: Kem::PrivateKey = u.arbitrary()?;
+    let mode: OpModeR<Kem> = u.arbitrary()?;
+    let info: &[u8] = u.arbitrary()?;
+    let aad: &[u8] = u.arbitrary()?;
+    let rest = u.bytes(u.len())?;
+
+    if let Ok(envelope) = Envelope::from_bytes(rest) {
+        let _ = open_envelope::<A, Kdf, Kem>(&mode, &sk_recip, &envelope, info, aad);
+    }
+
+    let enc_len = <Kem::EncappedKey as Serializable>::OutputSize::to_usize();
+    if rest.len() >= enc_len {
+        let (enc, ciphertext) = rest.split_at(enc_len);
+        if let Ok(encapped_key) = Kem::EncappedKey::from_bytes(enc) {
+            let _ = single_shot_open::<A, Kdf, Kem>(
+                &mode,
+                &sk_recip,
+                &encapped_key,
+                info,
+                ciphertext,
+                aad,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a recipient keypair, sender mode, info string, AAD, ephemeral keying material, and
+/// plaintext from `data`, seals the plaintext, and checks that it opens to the same plaintext and
+/// that flipping a bit in the ciphertext makes it fail to open
+///
+/// Panics
+/// ======
+/// Panics if the round trip or the tamper check fails
+pub fn seal_open_roundtrip<A, Kdf, Kem>(data: &[u8])
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    for<'a> Kem::PrivateKey: Arbitrary<'a>,
+{
+    let _ = try_seal_open_roundtrip::<A, Kdf, Kem>(&mut Unstructured::new(data));
+}
+
+fn try_seal_open_roundtrip<A, Kdf, Kem>(u: &mut Unstructured<'_>) -> Result<()>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    for<'a> Kem::PrivateKey: Arbitrary<'a>,
+{
+    let sk_recip: Kem::PrivateKey = u.arbitrary()?;
+    let pk_recip = Kem::sk_to_pk(&sk_recip);
+    let mode_s: OpModeS<Kem> = u.arbitrary()?;
+    let info: &[u8] = u.arbitrary()?;
+    let aad: &[u8] = u.arbitrary()?;
+    let ikm_eph: &[u8] = u.arbitrary()?;
+    let plaintext = u.bytes(u.len())?;
+
+    // The receiver's view of the sender's mode
+    let mode_r = match &mode_s {
+        OpModeS::Base => OpModeR::Base,
+        OpModeS::Psk(bundle) => OpModeR::Psk(*bundle),
+        OpModeS::Auth((_, pk)) => OpModeR::Auth(pk.clone()),
+        OpModeS::AuthPsk((_, pk), bundle) => OpModeR::AuthPsk(pk.clone(), *bundle),
+    };
+
+    let (encapped_key, mut sender_ctx) =
+        setup_sender_deterministic::<A, Kdf, Kem>(&mode_s, &pk_recip, info, ikm_eph)
+            .expect("encapsulation to a valid public key failed");
+    let mut ciphertext = sender_ctx.seal(plaintext, aad).expect("seal failed");
+
+    let opened =
+        single_shot_open::<A, Kdf, Kem>(&mode_r, &sk_recip, &encapped_key, info, &ciphertext, aad)
+            .expect("open failed on an honestly sealed ciphertext");
+    assert_eq!(opened, plaintext, "round trip changed the plaintext");
+
+    ciphertext[0] ^= 1;
+    assert!(
+        single_shot_open::<A, Kdf, Kem>(&mode_r, &sk_recip, &encapped_key, info, &ciphertext, aad)
+            .is_err(),
+        "tampered ciphertext opened"
+    );
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "x25519", feature = "p256"))]
+mod test {
+    use super::{deserialize_roundtrip, envelope_roundtrip, open_arbitrary, seal_open_roundtrip};
+    use crate::{
+        aead::{AesGcm128, ChaCha20Poly1305},
+        envelope::Envelope,
+        kdf::HkdfSha256,
+        kem::{DhP256HkdfSha256, Kem as KemTrait, X25519HkdfSha256},
+        op_mode::{OpModeR, OpModeS},
+        Serializable,
+    };
+
+    use arbitrary::{Arbitrary, Unstructured};
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    /// Runs the fuzz entry points on random inputs, and checks that the Arbitrary impls make
+    /// values that are valid
+    #[test]
+    fn test_fuzz_entry_points() {
+        let mut csprng = StdRng::from_entropy();
+        let mut data = [0u8; 512];
+
+        for _ in 0..64 {
+            csprng.fill_bytes(&mut data);
+            envelope_roundtrip(&data);
+            deserialize_roundtrip::<X25519HkdfSha256>(&data[..32]);
+            deserialize_roundtrip::<DhP256HkdfSha256>(&data[..65]);
+            open_arbitrary::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256>(&data);
+            open_arbitrary::<AesGcm128, HkdfSha256, DhP256HkdfSha256>(&data);
+            seal_open_roundtrip::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256>(&data);
+            seal_open_roundtrip::<AesGcm128, HkdfSha256, DhP256HkdfSha256>(&data);
+
+            let mut u = Unstructured::new(&data);
+            let pk = <<DhP256HkdfSha256 as KemTrait>::PublicKey>::arbitrary(&mut u).unwrap();
+            deserialize_roundtrip::<DhP256HkdfSha256>(&pk.to_bytes());
+            let _ = OpModeR::<X25519HkdfSha256>::arbitrary(&mut u).unwrap();
+            let _ = OpModeS::<DhP256HkdfSha256>::arbitrary(&mut u).unwrap();
+            let envelope = Envelope::arbitrary(&mut u).unwrap();
+            envelope_roundtrip(&envelope.to_bytes());
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Messages to a small group, where the payload is sealed once and each recipient gets a short
+//! entry that lets them, and only them, read it.
+//!
+//! `seal_group` seals the payload under a fresh content key with the AEAD `A`. Then, for each
+//! recipient, it sets up an export-only HPKE context to their public key and stores
+//!
+//! ```text
+//! enc              the context's encapsulated key
+//! masked_key       content_key XOR Export("HPKE group key", len(content_key))
+//! tag              Export("HPKE group tag" || aead_id || len(pk_recip) || pk_recip
+//!                         || masked_key || Hash(ciphertext), 32)
+//! ```
+//!
+//! The tag is bound to the recipient's public key and to this exact ciphertext. `open_group`
+//! checks it before unmasking the content key, so a recipient knows the entry was made for their
+//! key and for this payload. Every recipient learns the content key, so a member could seal a
+//! different payload under it, but they can't make tags that another member's entry would
+//! accept. In an Auth mode, the tag also shows who sent it.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     group::{open_group, seal_group},
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     Kem, OpModeR, OpModeS,
+//! };
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let members: Vec<_> = (0..3).map(|_| K::gen_keypair(&mut csprng)).collect();
+//! let pks: Vec<_> = members.iter().map(|(_, pk)| pk.clone()).collect();
+//!
+//! let message =
+//!     seal_group::<A, Kdf, K, _>(&OpModeS::Base, &pks, b"room 5", b"hi all", b"", &mut csprng)
+//!         .unwrap();
+//! for (sk, _) in &members {
+//!     let plaintext =
+//!         open_group::<A, Kdf, K>(&OpModeR::Base, sk, &message, b"room 5", b"").unwrap();
+//!     assert_eq!(plaintext, b"hi all");
+//! }
+//! # }
+//! ```
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS, ExportOnlyAead},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    setup::{setup_receiver, setup_sender_multi},
+    HpkeError, Serializable, Vec,
+};
+
+use aead::{AeadCore, AeadInPlace, NewAead};
+use byteorder::{BigEndian, ByteOrder};
+use digest::Digest;
+use generic_array::{typenum::Unsigned, GenericArray};
+use rand_core::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+/// The length of a `GroupEntry`'s tag
+pub const GROUP_TAG_LEN: usize = 32;
+
+// The exporter contexts of the content key mask and the tag
+const GROUP_KEY_EXPORTER_CTX: &[u8] = b"HPKE group key";
+const GROUP_TAG_EXPORTER_CTX: &[u8] = b"HPKE group tag";
+
+/// One recipient's part of a `GroupMessage`
+pub struct GroupEntry<Kem: KemTrait> {
+    /// The encapsulated key of this recipient's context
+    pub encapped_key: Kem::EncappedKey,
+    /// The content key, masked with a value exported from this recipient's context
+    pub masked_key: Vec<u8>,
+    /// Binds this entry to the recipient's public key and the message's ciphertext
+    pub tag: [u8; GROUP_TAG_LEN],
+}
+
+/// A payload sealed once for a group of recipients, with one `GroupEntry` per recipient. Make one
+/// with `seal_group`, and open it with `open_group`.
+pub struct GroupMessage<Kem: KemTrait> {
+    /// The recipients' entries, in the order their public keys were given to `seal_group`
+    pub entries: Vec<GroupEntry<Kem>>,
+    /// The payload, sealed under the content key, with the tag appended
+    pub ciphertext: Vec<u8>,
+}
+
+// The payload here, and in envelope::multi_kem, is sealed once with the AEAD A under a fresh
+// content key. The helpers below are shared by both.
+
+// The content key length for the AEAD A. The export-only AEAD can't carry a payload.
+pub(crate) fn content_key_len<A: Aead>() -> Result<usize, HpkeError> {
+    if A::AEAD_ID == ExportOnlyAead::AEAD_ID {
+        return Err(HpkeError::ValidationError);
+    }
+    Ok(<<A::AeadImpl as NewAead>::KeySize as Unsigned>::to_usize())
+}
+
+// The tag length of the AEAD A, which seal_content appends to the payload
+pub(crate) fn content_tag_len<A: Aead>() -> usize {
+    <<A::AeadImpl as AeadCore>::TagSize as Unsigned>::to_usize()
+}
+
+// The content key is only ever used once, so its nonce is all zeros
+fn content_nonce<A: Aead>() -> GenericArray<u8, <A::AeadImpl as AeadCore>::NonceSize> {
+    GenericArray::default()
+}
+
+// Seals the payload under the content key, and appends the tag
+pub(crate) fn seal_content<A: Aead>(
+    content_key: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError> {
+    let cipher = A::AeadImpl::new(GenericArray::from_slice(content_key));
+    let mut ciphertext = Vec::with_capacity(plaintext.len() + content_tag_len::<A>());
+    ciphertext.extend_from_slice(plaintext);
+    let tag = cipher
+        .encrypt_in_place_detached(&content_nonce::<A>(), aad, &mut ciphertext)
+        .map_err(|_| HpkeError::SealError)?;
+    ciphertext.extend_from_slice(&tag);
+    Ok(ciphertext)
+}
+
+// Opens what seal_content sealed. Returns Err(HpkeError::OpenError) if the ciphertext is shorter
+// than a tag, or doesn't open.
+pub(crate) fn open_content<A: Aead>(
+    content_key: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError> {
+    let tag_len = content_tag_len::<A>();
+    if ciphertext.len() < tag_len {
+        return Err(HpkeError::OpenError);
+    }
+
+    let cipher = A::AeadImpl::new(GenericArray::from_slice(content_key));
+    let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - tag_len);
+    let mut plaintext = ciphertext.to_vec();
+    cipher
+        .decrypt_in_place_detached(
+            &content_nonce::<A>(),
+            aad,
+            &mut plaintext,
+            GenericArray::from_slice(tag),
+        )
+        .map(|_| plaintext)
+        .map_err(|_| HpkeError::OpenError)
+}
+
+// Computes an entry's tag from its context. `ciphertext_digest` is Hash(ciphertext).
+fn entry_tag<A: Aead>(
+    export: impl FnOnce(&[u8], &mut [u8]) -> Result<(), HpkeError>,
+    pk_recip_bytes: &[u8],
+    masked_key: &[u8],
+    ciphertext_digest: &[u8],
+) -> Result<[u8; GROUP_TAG_LEN], HpkeError> {
+    // "HPKE group tag" || aead_id || len(pk_recip) || pk_recip || masked_key || Hash(ciphertext)
+    let mut exporter_ctx = Vec::with_capacity(
+        GROUP_TAG_EXPORTER_CTX.len()
+            + 4
+            + pk_recip_bytes.len()
+            + masked_key.len()
+            + ciphertext_digest.len(),
+    );
+    let mut ids = [0u8; 4];
+    BigEndian::write_u16(&mut ids[..2], A::AEAD_ID);
+    BigEndian::write_u16(&mut ids[2..], pk_recip_bytes.len() as u16);
+    exporter_ctx.extend_from_slice(GROUP_TAG_EXPORTER_CTX);
+    exporter_ctx.extend_from_slice(&ids);
+    exporter_ctx.extend_from_slice(pk_recip_bytes);
+    exporter_ctx.extend_from_slice(masked_key);
+    exporter_ctx.extend_from_slice(ciphertext_digest);
+
+    let mut tag = [0u8; GROUP_TAG_LEN];
+    export(&exporter_ctx, &mut tag)?;
+    Ok(tag)
+}
+
+/// Seals `plaintext` once, and makes an entry for each of `pk_recips` that lets them open it. The
+/// payload is sealed with `A` under a fresh content key. The entries use export-only contexts,
+/// all under `mode` and `info`, so `mode`'s PSK and sender key apply to every recipient.
+///
+/// Return Value
+/// ============
+/// Returns the message on success. If `A` is `ExportOnlyAead`, which can't seal the payload,
+/// returns `Err(HpkeError::ValidationError)`. If an error happened during any key encapsulation,
+/// returns `Err(HpkeError::EncapError)`.
+pub fn seal_group<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recips: &[Kem::PublicKey],
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<GroupMessage<Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let key_len = content_key_len::<A>()?;
+    let mut content_key = Zeroizing::new(vec![0u8; key_len]);
+    csprng.fill_bytes(&mut content_key);
+
+    // Seal the payload once, under the content key
+    let ciphertext = seal_content::<A>(&content_key, plaintext, aad)?;
+    let ciphertext_digest = Kdf::HashImpl::digest(&ciphertext);
+
+    // Give every recipient the content key, masked and tagged under their own context
+    let senders = setup_sender_multi::<ExportOnlyAead, Kdf, Kem, R>(mode, pk_recips, info, csprng)?;
+    let entries = senders
+        .into_iter()
+        .zip(pk_recips)
+        .map(|((encapped_key, ctx), pk_recip)| {
+            let mut masked_key = vec![0u8; key_len];
+            ctx.export(GROUP_KEY_EXPORTER_CTX, &mut masked_key)?;
+            masked_key
+                .iter_mut()
+                .zip(content_key.iter())
+                .for_each(|(m, k)| *m ^= k);
+
+            let tag = entry_tag::<A>(
+                |c, out| AeadCtxS::export(&ctx, c, out),
+                &pk_recip.to_bytes(),
+                &masked_key,
+                &ciphertext_digest,
+            )?;
+            Ok(GroupEntry {
+                encapped_key,
+                masked_key,
+                tag,
+            })
+        })
+        .collect::<Result<Vec<_>, HpkeError>>()?;
+
+    Ok(GroupMessage {
+        entries,
+        ciphertext,
+    })
+}
+
+/// Finds the entry in `message` that was made for the public key of `sk_recip`, checks its tag,
+/// and opens the payload. `mode`, `info`, and `aad` must match what was given to `seal_group`.
+/// This tries each entry in turn, so it does up to one decapsulation per recipient.
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. If `A` is `ExportOnlyAead`, returns
+/// `Err(HpkeError::ValidationError)`. Returns `Err(HpkeError::OpenError)` if no entry has a valid
+/// tag for this recipient and this ciphertext, or if the payload doesn't open.
+pub fn open_group<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    message: &GroupMessage<Kem>,
+    info: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let key_len = content_key_len::<A>()?;
+    if message.ciphertext.len() < content_tag_len::<A>() {
+        return Err(HpkeError::OpenError);
+    }
+    let pk_recip_bytes = Kem::sk_to_pk(sk_recip).to_bytes();
+    let ciphertext_digest = Kdf::HashImpl::digest(&message.ciphertext);
+
+    for entry in &message.entries {
+        if entry.masked_key.len() != key_len {
+            continue;
+        }
+        // Entries for other recipients decapsulate to garbage, or fail to
+        let ctx = match setup_receiver::<ExportOnlyAead, Kdf, Kem>(
+            mode,
+            sk_recip,
+            &entry.encapped_key,
+            info,
+        ) {
+            Ok(ctx) => ctx,
+            Err(_) => continue,
+        };
+        let expected_tag = entry_tag::<A>(
+            |c, out| AeadCtxR::export(&ctx, c, out),
+            &pk_recip_bytes,
+            &entry.masked_key,
+            &ciphertext_digest,
+        )?;
+        if !bool::from(expected_tag.ct_eq(&entry.tag)) {
+            continue;
+        }
+
+        // The entry is ours. Unmask the content key and open the payload.
+        let mut content_key = Zeroizing::new(vec![0u8; key_len]);
+        ctx.export(GROUP_KEY_EXPORTER_CTX, &mut content_key)?;
+        content_key
+            .iter_mut()
+            .zip(entry.masked_key.iter())
+            .for_each(|(k, m)| *k ^= m);
+
+        return open_content::<A>(&content_key, &message.ciphertext, aad);
+    }
+
+    Err(HpkeError::OpenError)
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::{open_group, seal_group, GroupMessage};
+    use crate::{
+        aead::{AesGcm256, ExportOnlyAead},
+        kdf::HkdfSha384,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        op_mode::{OpModeR, OpModeS},
+        HpkeError, Vec,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = AesGcm256;
+    type Kdf = HkdfSha384;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that every member opens the message, outsiders and the wrong AAD don't, and entries
+    /// don't carry over to another message
+    #[test]
+    fn test_group_roundtrip() {
+        let mut csprng = StdRng::from_entropy();
+        let members: Vec<_> = (0..3).map(|_| Kem::gen_keypair(&mut csprng)).collect();
+        let pks: Vec<_> = members.iter().map(|(_, pk)| pk.clone()).collect();
+        let (outsider_sk, _) = Kem::gen_keypair(&mut csprng);
+
+        let seal = |plaintext: &[u8], csprng: &mut StdRng| {
+            seal_group::<A, Kdf, Kem, _>(&OpModeS::Base, &pks, b"info", plaintext, b"aad", csprng)
+                .unwrap()
+        };
+        let open = |sk, message: &GroupMessage<Kem>| {
+            open_group::<A, Kdf, Kem>(&OpModeR::Base, sk, message, b"info", b"aad")
+        };
+
+        let message = seal(b"hello group", &mut csprng);
+        assert_eq!(message.entries.len(), 3);
+        for (sk, _) in &members {
+            assert_eq!(open(sk, &message).unwrap(), b"hello group");
+        }
+        assert_eq!(open(&outsider_sk, &message), Err(HpkeError::OpenError));
+        assert_eq!(
+            open_group::<A, Kdf, Kem>(&OpModeR::Base, &members[0].0, &message, b"info", b"other"),
+            Err(HpkeError::OpenError)
+        );
+
+        // An entry from one message doesn't verify against another message's ciphertext
+        let mut other = seal(b"second message", &mut csprng);
+        other.entries = message.entries;
+        assert_eq!(open(&members[1].0, &other), Err(HpkeError::OpenError));
+
+        // The payload needs a real AEAD
+        assert!(matches!(
+            seal_group::<ExportOnlyAead, Kdf, Kem, _>(
+                &OpModeS::Base,
+                &pks,
+                b"info",
+                b"hello",
+                b"aad",
+                &mut csprng
+            ),
+            Err(HpkeError::ValidationError)
+        ));
+    }
+}
@@ -1,18 +1,17 @@
 use crate::{
-    aead::{Aead, AesGcm128, AesGcm256, ChaCha20Poly1305, ExportOnlyAead},
+    aead::{Aead, AesGcm128, AesGcm256, AesOcb128, AesOcb256, ChaCha20Poly1305, ExportOnlyAead},
     kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
     kem::{self, DhP256HkdfSha256, Kem as KemTrait, SharedSecret, X25519HkdfSha256},
     op_mode::{OpModeR, PskBundle},
     setup::setup_receiver,
+    test_vectors::write_test_vector,
     Deserializable, HpkeError, Serializable,
 };
 
 extern crate std;
 use std::{fs::File, string::String, vec::Vec};
 
-use hex;
 use serde::{de::Error as SError, Deserialize, Deserializer};
-use serde_json;
 
 // For known-answer tests we need to be able to encap with fixed randomness. This allows that.
 trait TestableKem: KemTrait {
@@ -34,26 +33,40 @@ impl TestableKem for X25519HkdfSha256 {
     // In DHKEM, ephemeral keys and private keys are both scalars
     type EphemeralKey = <X25519HkdfSha256 as KemTrait>::PrivateKey;
 
-    // Call the x25519 deterministic encap function we defined in dhkem.rs
+    // Call the x25519 deterministic encap function DhKem defines in dhkem.rs
     fn encap_with_eph(
         pk_recip: &Self::PublicKey,
         sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
         sk_eph: Self::EphemeralKey,
     ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
-        kem::x25519_hkdfsha256::encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
+        kem::X25519HkdfSha256::encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
+    }
+}
+#[cfg(feature = "k256")]
+impl TestableKem for kem::DhK256HkdfSha256 {
+    // In DHKEM, ephemeral keys and private keys are both scalars
+    type EphemeralKey = <kem::DhK256HkdfSha256 as KemTrait>::PrivateKey;
+
+    // Call the k256 deterministic encap function DhKem defines in dhkem.rs
+    fn encap_with_eph(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        sk_eph: Self::EphemeralKey,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        kem::DhK256HkdfSha256::encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
     }
 }
 impl TestableKem for DhP256HkdfSha256 {
     // In DHKEM, ephemeral keys and private keys are both scalars
     type EphemeralKey = <DhP256HkdfSha256 as KemTrait>::PrivateKey;
 
-    // Call the p256 deterministic encap function we defined in dhkem.rs
+    // Call the p256 deterministic encap function DhKem defines in dhkem.rs
     fn encap_with_eph(
         pk_recip: &Self::PublicKey,
         sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
         sk_eph: Self::EphemeralKey,
     ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
-        kem::dhp256_hkdfsha256::encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
+        kem::DhP256HkdfSha256::encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
     }
 }
 
@@ -82,7 +95,7 @@ fn bytes_from_hex_opt<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Err
 where
     D: Deserializer<'de>,
 {
-    bytes_from_hex(deserializer).map(|v| Some(v))
+    bytes_from_hex(deserializer).map(Some)
 }
 
 // Each individual test case looks like this
@@ -102,7 +115,7 @@ struct MainTestVector {
     #[serde(default, rename = "ikmS", deserialize_with = "bytes_from_hex_opt")]
     ikm_sender: Option<Vec<u8>>,
     #[serde(rename = "ikmE", deserialize_with = "bytes_from_hex")]
-    _ikm_eph: Vec<u8>,
+    ikm_eph: Vec<u8>,
 
     // Private keys
     #[serde(rename = "skRm", deserialize_with = "bytes_from_hex")]
@@ -144,6 +157,10 @@ struct MainTestVector {
 
     encryptions: Vec<EncryptionTestVector>,
     exports: Vec<ExporterTestVector>,
+
+    // The vector as it appeared in the file, for comparing against regenerated vectors
+    #[serde(skip)]
+    raw: serde_json::Value,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -235,10 +252,27 @@ fn test_case<A: Aead, Kdf: KdfTrait, Kem: TestableKem>(tv: MainTestVector) {
     // Now derive the encapped key with the deterministic encap function, using all the inputs
     // above
     let (shared_secret, encapped_key) = {
-        let sender_keypair_ref = sender_keypair.as_ref().map(|&(ref sk, ref pk)| (sk, pk));
+        let sender_keypair_ref = sender_keypair.as_ref().map(|(sk, pk)| (sk, pk));
         Kem::encap_with_eph(&pk_recip, sender_keypair_ref, sk_eph).expect("encap failed")
     };
 
+    // Make sure the IKM-seeded encap agrees with the above
+    {
+        let sender_keypair_ref = sender_keypair.as_ref().map(|(sk, pk)| (sk, pk));
+        let (ikm_shared_secret, ikm_encapped_key) =
+            Kem::encap_with_ikm(&pk_recip, sender_keypair_ref, &tv.ikm_eph)
+                .expect("encap_with_ikm failed");
+        assert_eq!(
+            ikm_shared_secret.0, shared_secret.0,
+            "encap_with_ikm shared secret doesn't match"
+        );
+        assert_serializable_eq!(
+            ikm_encapped_key,
+            encapped_key,
+            "encap_with_ikm encapped key doesn't match"
+        );
+    }
+
     // Assert that the derived shared secret key is identical to the one provided
     assert_eq!(
         shared_secret.0.as_slice(),
@@ -261,8 +295,8 @@ fn test_case<A: Aead, Kdf: KdfTrait, Kem: TestableKem>(tv: MainTestVector) {
     let mode = make_op_mode_r(
         tv.mode,
         sender_keypair.map(|(_, pk)| pk),
-        tv.psk.as_ref().map(Vec::as_slice),
-        tv.psk_id.as_ref().map(Vec::as_slice),
+        tv.psk.as_deref(),
+        tv.psk_id.as_deref(),
     );
     let mut aead_ctx = setup_receiver::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, &tv.info)
         .expect("setup_receiver failed");
@@ -295,34 +329,34 @@ fn test_case<A: Aead, Kdf: KdfTrait, Kem: TestableKem>(tv: MainTestVector) {
     }
 }
 
-// This macro takes in all the supported AEADs, KDFs, and KEMs, and dispatches the given test
-// vector to the test case with the appropriate types
+// This macro takes in a test function and all the supported AEADs, KDFs, and KEMs, and dispatches
+// the given test vector to the test function with the appropriate types
 macro_rules! dispatch_testcase {
     // Step 1: Roll up the AEAD, KDF, and KEM types into tuples. We'll unroll them later
-    ($tv:ident, ($( $aead_ty:ty ),*), ($( $kdf_ty:ty ),*), ($( $kem_ty:ty ),*)) => {
-        dispatch_testcase!(@tup1 $tv, ($( $aead_ty ),*), ($( $kdf_ty ),*), ($( $kem_ty ),*))
+    ($test_fn:ident, $tv:ident, ($( $aead_ty:ty ),*), ($( $kdf_ty:ty ),*), ($( $kem_ty:ty ),*)) => {
+        dispatch_testcase!(@tup1 $test_fn, $tv, ($( $aead_ty ),*), ($( $kdf_ty ),*), ($( $kem_ty ),*))
     };
     // Step 2: Expand with respect to every AEAD
-    (@tup1 $tv:ident, ($( $aead_ty:ty ),*), $kdf_tup:tt, $kem_tup:tt) => {
+    (@tup1 $test_fn:ident, $tv:ident, ($( $aead_ty:ty ),*), $kdf_tup:tt, $kem_tup:tt) => {
         $(
-            dispatch_testcase!(@tup2 $tv, $aead_ty, $kdf_tup, $kem_tup);
+            dispatch_testcase!(@tup2 $test_fn, $tv, $aead_ty, $kdf_tup, $kem_tup);
         )*
     };
     // Step 3: Expand with respect to every KDF
-    (@tup2 $tv:ident, $aead_ty:ty, ($( $kdf_ty:ty ),*), $kem_tup:tt) => {
+    (@tup2 $test_fn:ident, $tv:ident, $aead_ty:ty, ($( $kdf_ty:ty ),*), $kem_tup:tt) => {
         $(
-            dispatch_testcase!(@tup3 $tv, $aead_ty, $kdf_ty, $kem_tup);
+            dispatch_testcase!(@tup3 $test_fn, $tv, $aead_ty, $kdf_ty, $kem_tup);
         )*
     };
     // Step 4: Expand with respect to every KEM
-    (@tup3 $tv:ident, $aead_ty:ty, $kdf_ty:ty, ($( $kem_ty:ty ),*)) => {
+    (@tup3 $test_fn:ident, $tv:ident, $aead_ty:ty, $kdf_ty:ty, ($( $kem_ty:ty ),*)) => {
         $(
-            dispatch_testcase!(@base $tv, $aead_ty, $kdf_ty, $kem_ty);
+            dispatch_testcase!(@base $test_fn, $tv, $aead_ty, $kdf_ty, $kem_ty);
         )*
     };
     // Step 5: Now that we're only dealing with 1 type of each kind, do the dispatch. If the test
     // vector matches the IDs of these types, run the test case.
-    (@base $tv:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {
+    (@base $test_fn:ident, $tv:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {
         if let (<$aead_ty>::AEAD_ID, <$kdf_ty>::KDF_ID, <$kem_ty>::KEM_ID) =
             ($tv.aead_id, $tv.kdf_id, $tv.kem_id)
         {
@@ -334,7 +368,7 @@ macro_rules! dispatch_testcase {
             );
 
             let tv = $tv.clone();
-            test_case::<$aead_ty, $kdf_ty, $kem_ty>(tv);
+            $test_fn::<$aead_ty, $kdf_ty, $kem_ty>(tv);
 
             // This is so that code that comes after a dispatch_testcase! invocation will know that
             // the test vector matched no known ciphersuites
@@ -356,6 +390,7 @@ fn kat_test() {
 
         // This unrolls into 24 `if let` statements
         dispatch_testcase!(
+            test_case,
             tv,
             (AesGcm128, AesGcm256, ChaCha20Poly1305, ExportOnlyAead),
             (HkdfSha256, HkdfSha384, HkdfSha512),
@@ -370,3 +405,74 @@ fn kat_test() {
         );
     }
 }
+
+// Regenerates the given vector from its keying material and checks that the generator's JSON is
+// identical to it, field for field
+fn regenerate_case<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(tv: MainTestVector) {
+    let mut json = String::new();
+    write_test_vector::<A, Kdf, Kem>(
+        &mut json,
+        tv.mode,
+        &tv.ikm_recip,
+        tv.ikm_sender.as_deref().unwrap_or(&tv.ikm_recip),
+        &tv.ikm_eph,
+        &tv.info,
+    )
+    .unwrap();
+    let generated: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(generated, tv.raw, "generated vector doesn't match");
+}
+
+/// Tests that the generator reproduces every RFC 9180 vector from its keying material
+#[test]
+fn kat_regenerate_test() {
+    let file = File::open("test-vectors-5f503c5.json").unwrap();
+    let raw_tvs: Vec<serde_json::Value> = serde_json::from_reader(file).unwrap();
+
+    for raw in raw_tvs.into_iter() {
+        let mut tv: MainTestVector = serde_json::from_value(raw.clone()).unwrap();
+        if tv.kem_id != DhP256HkdfSha256::KEM_ID && tv.kem_id != X25519HkdfSha256::KEM_ID {
+            continue;
+        }
+        tv.raw = raw;
+
+        dispatch_testcase!(
+            regenerate_case,
+            tv,
+            (AesGcm128, AesGcm256, ChaCha20Poly1305, ExportOnlyAead),
+            (HkdfSha256, HkdfSha384, HkdfSha512),
+            (X25519HkdfSha256, DhP256HkdfSha256)
+        );
+    }
+}
+
+/// Tests that freshly generated vectors, including for K-256, pass the same checks the RFC
+/// vectors do
+#[test]
+fn kat_generated_test() {
+    use crate::test_vectors::generate_test_vectors;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    macro_rules! check_generated {
+        ($aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {
+            let mut csprng = StdRng::from_entropy();
+            let json = generate_test_vectors::<$aead_ty, $kdf_ty, $kem_ty, _>(&mut csprng).unwrap();
+            let tvs: Vec<MainTestVector> = serde_json::from_str(&json).unwrap();
+            assert_eq!(tvs.len(), 4);
+            for tv in tvs {
+                test_case::<$aead_ty, $kdf_ty, $kem_ty>(tv);
+            }
+        };
+    }
+
+    check_generated!(ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256);
+    check_generated!(AesGcm256, HkdfSha384, DhP256HkdfSha256);
+    check_generated!(ExportOnlyAead, HkdfSha512, X25519HkdfSha256);
+    check_generated!(AesOcb128, HkdfSha256, X25519HkdfSha256);
+    check_generated!(AesOcb256, HkdfSha512, DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    {
+        check_generated!(AesGcm128, HkdfSha256, kem::DhK256HkdfSha256);
+        check_generated!(ChaCha20Poly1305, HkdfSha256, kem::DhK256HkdfSha256);
+    }
+}
@@ -1,16 +1,26 @@
 //! Traits and structs for key derivation functions
 
+use core::marker::PhantomData;
+
 use byteorder::{BigEndian, ByteOrder};
 use digest::{core_api::BlockSizeUser, Digest, OutputSizeUser};
 use generic_array::GenericArray;
 use hmac::SimpleHmac;
+#[cfg(not(feature = "aws-lc"))]
 use sha2::{Sha256, Sha384, Sha512};
 
+#[cfg(feature = "aws-lc")]
+mod aws_lc;
+pub mod labeled;
+
 const VERSION_LABEL: &[u8] = b"HPKE-v1";
 
 // This is the maximum value of Nh. It is achieved by HKDF-SHA512 in RFC 9180 §7.2.
 pub(crate) const MAX_DIGEST_SIZE: usize = 64;
 
+// The most pieces labeled_expand_multi can build its info string out of
+const MAX_INFO_PARTS: usize = 4;
+
 // Pretty much all the KDF functionality is covered by the hkdf crate
 
 /// Represents key derivation functionality
@@ -39,7 +49,10 @@ pub struct HkdfSha256 {}
 
 impl KdfTrait for HkdfSha256 {
     #[doc(hidden)]
+    #[cfg(not(feature = "aws-lc"))]
     type HashImpl = Sha256;
+    #[cfg(feature = "aws-lc")]
+    type HashImpl = aws_lc::AwsLcSha256;
 
     // RFC 9180 §7.2: HKDF-SHA256
     const KDF_ID: u16 = 0x0001;
@@ -50,7 +63,10 @@ pub struct HkdfSha384 {}
 
 impl KdfTrait for HkdfSha384 {
     #[doc(hidden)]
+    #[cfg(not(feature = "aws-lc"))]
     type HashImpl = Sha384;
+    #[cfg(feature = "aws-lc")]
+    type HashImpl = aws_lc::AwsLcSha384;
 
     // RFC 9180 §7.2: HKDF-SHA384
     const KDF_ID: u16 = 0x0002;
@@ -61,12 +77,35 @@ pub struct HkdfSha512 {}
 
 impl KdfTrait for HkdfSha512 {
     #[doc(hidden)]
+    #[cfg(not(feature = "aws-lc"))]
     type HashImpl = Sha512;
+    #[cfg(feature = "aws-lc")]
+    type HashImpl = aws_lc::AwsLcSha512;
 
     // RFC 9180 §7.2: HKDF-SHA512
     const KDF_ID: u16 = 0x0003;
 }
 
+/// The KDF `K` under the algorithm identifier `KDF_ID` instead of its own. This is for private
+/// deployments that assign their own code points. The ID goes into the suite ID of every key
+/// schedule, so contexts made under an overridden ID can't talk to ones made under `K`'s ID.
+/// DHKEMs built on it with [`crate::kem::DhKem`] use the ID in their labeled extractions too.
+///
+/// ```
+/// use hpke::kdf::{CustomKdfId, HkdfSha256, Kdf};
+///
+/// type PrivateSha256 = CustomKdfId<HkdfSha256, 0xFF11>;
+/// assert_eq!(PrivateSha256::KDF_ID, 0xFF11);
+/// ```
+pub struct CustomKdfId<K, const KDF_ID: u16>(PhantomData<fn() -> K>);
+
+impl<K: KdfTrait, const KDF_ID: u16> KdfTrait for CustomKdfId<K, KDF_ID> {
+    #[doc(hidden)]
+    type HashImpl = K::HashImpl;
+
+    const KDF_ID: u16 = KDF_ID;
+}
+
 // RFC 9180 §4.1
 // def ExtractAndExpand(dh, kem_context):
 //   eae_prk = LabeledExtract("", "eae_prk", dh)
@@ -76,7 +115,6 @@ impl KdfTrait for HkdfSha512 {
 
 /// Uses the given IKM to extract a secret, and then uses that secret, plus the given suite ID and
 /// info string, to expand to the output buffer
-#[doc(hidden)]
 pub fn extract_and_expand<Kdf: KdfTrait>(
     ikm: &[u8],
     suite_id: &[u8],
@@ -95,26 +133,39 @@ pub fn extract_and_expand<Kdf: KdfTrait>(
 //   return Extract(salt, labeled_ikm)
 
 /// Returns the HKDF context derived from `(salt=salt, ikm="HPKE-v1"||suite_id||label||ikm)`
-#[doc(hidden)]
 pub fn labeled_extract<Kdf: KdfTrait>(
     salt: &[u8],
     suite_id: &[u8],
     label: &[u8],
     ikm: &[u8],
+) -> (DigestArray<Kdf>, SimpleHkdf<Kdf>) {
+    labeled_extract_multi::<Kdf>(salt, suite_id, label, &[ikm])
+}
+
+/// Same as `labeled_extract`, where `ikm` is the concatenation of `ikm_parts`. This lets callers
+/// build the IKM out of pieces without allocating.
+pub(crate) fn labeled_extract_multi<Kdf: KdfTrait>(
+    salt: &[u8],
+    suite_id: &[u8],
+    label: &[u8],
+    ikm_parts: &[&[u8]],
 ) -> (DigestArray<Kdf>, SimpleHkdf<Kdf>) {
     // Call HKDF-Extract with the IKM being the concatenation of all of the above
     let mut extract_ctx = SimpleHkdfExtract::<Kdf>::new(Some(salt));
     extract_ctx.input_ikm(VERSION_LABEL);
     extract_ctx.input_ikm(suite_id);
     extract_ctx.input_ikm(label);
-    extract_ctx.input_ikm(ikm);
+    for part in ikm_parts {
+        extract_ctx.input_ikm(part);
+    }
     extract_ctx.finalize()
 }
 
 // This trait only exists so I can implement it for hkdf::Hkdf
 /// Describes the `labeled_expand` key derivation function
-#[doc(hidden)]
 pub trait LabeledExpand {
+    /// Fills `out` with `LabeledExpand(prk, label, info, len(out))` from RFC 9180 §4, where
+    /// `prk` is `self`. Returns an error if `out` is longer than 255 hashes.
     fn labeled_expand(
         &self,
         suite_id: &[u8],
@@ -122,6 +173,16 @@ pub trait LabeledExpand {
         info: &[u8],
         out: &mut [u8],
     ) -> Result<(), hkdf::InvalidLength>;
+
+    /// Same as `labeled_expand`, where `info` is the concatenation of `info_parts`. There can be
+    /// at most 4 parts.
+    fn labeled_expand_multi(
+        &self,
+        suite_id: &[u8],
+        label: &[u8],
+        info_parts: &[&[u8]],
+        out: &mut [u8],
+    ) -> Result<(), hkdf::InvalidLength>;
 }
 
 impl<D> LabeledExpand for hkdf::Hkdf<D, SimpleHmac<D>>
@@ -139,16 +200,30 @@ where
         label: &[u8],
         info: &[u8],
         out: &mut [u8],
+    ) -> Result<(), hkdf::InvalidLength> {
+        self.labeled_expand_multi(suite_id, label, &[info], out)
+    }
+
+    fn labeled_expand_multi(
+        &self,
+        suite_id: &[u8],
+        label: &[u8],
+        info_parts: &[&[u8]],
+        out: &mut [u8],
     ) -> Result<(), hkdf::InvalidLength> {
         // We need to write the length as a u16, so that's the de-facto upper bound on length
         assert!(out.len() <= u16::MAX as usize);
+        assert!(info_parts.len() <= MAX_INFO_PARTS);
 
         // Encode the output length in the info string
         let mut len_buf = [0u8; 2];
         BigEndian::write_u16(&mut len_buf, out.len() as u16);
 
-        // Call HKDF-Expand() with the info string set to the concatenation of all of the above
-        let labeled_info = [&len_buf, VERSION_LABEL, suite_id, label, info];
+        // Call HKDF-Expand() with the info string set to the concatenation of all of the above.
+        // Unused slots are left empty, which doesn't change the concatenation.
+        let mut labeled_info: [&[u8]; 4 + MAX_INFO_PARTS] = [&[]; 4 + MAX_INFO_PARTS];
+        labeled_info[..4].copy_from_slice(&[&len_buf, VERSION_LABEL, suite_id, label]);
+        labeled_info[4..4 + info_parts.len()].copy_from_slice(info_parts);
         self.expand_multi_info(&labeled_info, out)
     }
 }
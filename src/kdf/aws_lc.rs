@@ -0,0 +1,82 @@
+//! SHA-2 backed by aws-lc-rs. With the `aws-lc` feature, `HkdfSha256`, `HkdfSha384`, and
+//! `HkdfSha512` hash with these in place of the RustCrypto `sha2` crate. HMAC and HKDF are still
+//! built on top of the hash by the `hmac` and `hkdf` crates.
+
+use aws_lc_rs::digest::{Algorithm, Context, SHA256, SHA384, SHA512};
+use digest::{
+    consts::{U128, U32, U48, U64},
+    core_api::BlockSizeUser,
+    FixedOutput, HashMarker, Output, OutputSizeUser, Update,
+};
+
+// Defines a hash type over aws-lc-rs with the given algorithm, output size, and block size
+macro_rules! impl_aws_lc_sha2 {
+    ($name:ident, $alg:expr, $output_size:ty, $block_size:ty) => {
+        #[doc(hidden)]
+        #[derive(Clone)]
+        pub struct $name(Context);
+
+        impl $name {
+            fn algorithm() -> &'static Algorithm {
+                &$alg
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name(Context::new(Self::algorithm()))
+            }
+        }
+
+        impl HashMarker for $name {}
+
+        impl OutputSizeUser for $name {
+            type OutputSize = $output_size;
+        }
+
+        impl BlockSizeUser for $name {
+            type BlockSize = $block_size;
+        }
+
+        impl Update for $name {
+            fn update(&mut self, data: &[u8]) {
+                self.0.update(data);
+            }
+        }
+
+        impl FixedOutput for $name {
+            fn finalize_into(self, out: &mut Output<Self>) {
+                out.copy_from_slice(self.0.finish().as_ref());
+            }
+        }
+    };
+}
+
+impl_aws_lc_sha2!(AwsLcSha256, SHA256, U32, U64);
+impl_aws_lc_sha2!(AwsLcSha384, SHA384, U48, U128);
+impl_aws_lc_sha2!(AwsLcSha512, SHA512, U64, U128);
+
+#[cfg(test)]
+mod test {
+    use super::{AwsLcSha256, AwsLcSha384, AwsLcSha512};
+
+    use digest::Digest;
+    use hex_literal::hex;
+
+    /// Tests the aws-lc-rs hashes against the sha2 crate, including over several updates
+    #[test]
+    fn test_aws_lc_sha2_matches_sha2() {
+        let msg = b"The quick brown fox jumps over the lazy dog";
+        assert_eq!(
+            AwsLcSha256::digest(msg)[..],
+            hex!("d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592")
+        );
+        assert_eq!(AwsLcSha384::digest(msg), sha2::Sha384::digest(msg));
+        assert_eq!(AwsLcSha512::digest(msg), sha2::Sha512::digest(msg));
+
+        let mut h = AwsLcSha256::new();
+        h.update(&msg[..10]);
+        h.update(&msg[10..]);
+        assert_eq!(h.finalize(), sha2::Sha256::digest(msg));
+    }
+}
@@ -0,0 +1,279 @@
+//! The labeled key derivation functions from RFC 9180 §4, for protocols that build on HPKE's key
+//! schedule, e.g., MLS and ECH
+
+use crate::{
+    aead::Aead,
+    kdf::{labeled_extract as raw_labeled_extract, DigestArray, Kdf as KdfTrait, LabeledExpand},
+    kem::Kem as KemTrait,
+    util::{full_suite_id, kem_suite_id},
+    HpkeError,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use digest::OutputSizeUser;
+use generic_array::typenum::Unsigned;
+use zeroize::Zeroize;
+
+/// The maximum length of a `SuiteId`, in bytes
+pub const MAX_SUITE_ID_LEN: usize = 64;
+
+/// The `suite_id` that the labeled KDFs bind their outputs to. HPKE uses two of these: the KEM
+/// one, `"KEM" || kem_id`, and the full one, `"HPKE" || kem_id || kdf_id || aead_id`.
+/// Protocols that reuse the labeled KDFs for their own purposes can also make custom ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SuiteId {
+    buf: [u8; MAX_SUITE_ID_LEN],
+    len: usize,
+}
+
+impl SuiteId {
+    // RFC 9180 §4.1
+    // suite_id = concat("KEM", I2OSP(kem_id, 2))
+
+    /// Returns the `suite_id` that the given KEM uses in `ExtractAndExpand` and `DeriveKeyPair`
+    pub fn kem<Kem: KemTrait>() -> SuiteId {
+        SuiteId::from_bytes_unchecked(&kem_suite_id::<Kem>())
+    }
+
+    /// Returns the KEM `suite_id` for the KEM with the given ID
+    pub fn from_kem_id(kem_id: u16) -> SuiteId {
+        let mut suite_id = *b"KEMXX";
+        BigEndian::write_u16(&mut suite_id[3..5], kem_id);
+        SuiteId::from_bytes_unchecked(&suite_id)
+    }
+
+    // RFC 9180 §5.1
+    // suite_id = concat(
+    //   "HPKE",
+    //   I2OSP(kem_id, 2),
+    //   I2OSP(kdf_id, 2),
+    //   I2OSP(aead_id, 2)
+    // )
+
+    /// Returns the `suite_id` that the given ciphersuite uses in its key schedule and secret
+    /// exporter
+    pub fn hpke<A: Aead, Kdf: KdfTrait, Kem: KemTrait>() -> SuiteId {
+        SuiteId::from_bytes_unchecked(&full_suite_id::<A, Kdf, Kem>())
+    }
+
+    /// Returns the full `suite_id` for the ciphersuite with the given IDs
+    pub fn from_ids(kem_id: u16, kdf_id: u16, aead_id: u16) -> SuiteId {
+        let mut suite_id = *b"HPKEXXYYZZ";
+        BigEndian::write_u16(&mut suite_id[4..6], kem_id);
+        BigEndian::write_u16(&mut suite_id[6..8], kdf_id);
+        BigEndian::write_u16(&mut suite_id[8..10], aead_id);
+        SuiteId::from_bytes_unchecked(&suite_id)
+    }
+
+    /// Makes a custom `suite_id` out of the given bytes. This is for protocols that define their
+    /// own. It can't collide with HPKE's own labeled KDF calls unless it starts with `"KEM"` or
+    /// `"HPKE"`, so those prefixes are rejected.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if `bytes` is longer than `MAX_SUITE_ID_LEN`,
+    /// or starts with `"KEM"` or `"HPKE"`.
+    pub fn custom(bytes: &[u8]) -> Result<SuiteId, HpkeError> {
+        if bytes.len() > MAX_SUITE_ID_LEN || bytes.starts_with(b"KEM") || bytes.starts_with(b"HPKE")
+        {
+            return Err(HpkeError::ValidationError);
+        }
+        Ok(SuiteId::from_bytes_unchecked(bytes))
+    }
+
+    /// Returns the bytes of this `suite_id`
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    // Callers make sure bytes.len() <= MAX_SUITE_ID_LEN
+    fn from_bytes_unchecked(bytes: &[u8]) -> SuiteId {
+        let mut buf = [0u8; MAX_SUITE_ID_LEN];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        SuiteId {
+            buf,
+            len: bytes.len(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for SuiteId {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// A pseudorandom key, i.e., the output of `labeled_extract` and the input to `labeled_expand`.
+/// This is zeroized on drop.
+pub struct Prk<Kdf: KdfTrait> {
+    bytes: DigestArray<Kdf>,
+}
+
+impl<Kdf: KdfTrait> Prk<Kdf> {
+    /// Makes a PRK out of the given bytes, e.g., one carried over from an earlier step of a
+    /// protocol's key schedule
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::IncorrectInputLength)` if `prk` isn't `Nh` bytes long, where `Nh`
+    /// is the output size of the KDF's hash function.
+    pub fn from_bytes(prk: &[u8]) -> Result<Prk<Kdf>, HpkeError> {
+        let nh = <Kdf::HashImpl as OutputSizeUser>::OutputSize::to_usize();
+        if prk.len() != nh {
+            return Err(HpkeError::IncorrectInputLength(nh, prk.len()));
+        }
+        let mut bytes = DigestArray::<Kdf>::default();
+        bytes.copy_from_slice(prk);
+        Ok(Prk { bytes })
+    }
+
+    /// Returns the bytes of this PRK
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // RFC 9180 §4
+    // def LabeledExpand(prk, label, info, L):
+    //   labeled_info = concat(I2OSP(L, 2), "HPKE-v1", suite_id,
+    //                         label, info)
+    //   return Expand(prk, labeled_info, L)
+
+    /// Runs `LabeledExpand(self, label, info, out.len())` with the given `suite_id`, and writes
+    /// the result to `out`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::KdfOutputTooLong)` if `out` is longer than the KDF can output,
+    /// i.e., longer than `255 * Nh` bytes.
+    pub fn labeled_expand(
+        &self,
+        suite_id: &SuiteId,
+        label: &[u8],
+        info: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), HpkeError> {
+        // LabeledExpand encodes the length in 2 bytes. HKDF's own bound of 255 * Nh is lower for
+        // every hash here, but check anyway rather than let labeled_expand panic.
+        if out.len() > u16::MAX as usize {
+            return Err(HpkeError::KdfOutputTooLong);
+        }
+        let hkdf_ctx = crate::kdf::SimpleHkdf::<Kdf>::from_prk(&self.bytes)
+            .map_err(|_| HpkeError::KdfOutputTooLong)?;
+        hkdf_ctx
+            .labeled_expand(suite_id.as_bytes(), label, info, out)
+            .map_err(|_| HpkeError::KdfOutputTooLong)
+    }
+}
+
+impl<Kdf: KdfTrait> Clone for Prk<Kdf> {
+    fn clone(&self) -> Prk<Kdf> {
+        Prk {
+            bytes: self.bytes.clone(),
+        }
+    }
+}
+
+impl<Kdf: KdfTrait> Drop for Prk<Kdf> {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+// RFC 9180 §4
+// def LabeledExtract(salt, label, ikm):
+//   labeled_ikm = concat("HPKE-v1", suite_id, label, ikm)
+//   return Extract(salt, labeled_ikm)
+
+/// Runs `LabeledExtract(salt, label, ikm)` with the given `suite_id`
+pub fn labeled_extract<Kdf: KdfTrait>(
+    salt: &[u8],
+    suite_id: &SuiteId,
+    label: &[u8],
+    ikm: &[u8],
+) -> Prk<Kdf> {
+    let (bytes, _) = raw_labeled_extract::<Kdf>(salt, suite_id.as_bytes(), label, ikm);
+    Prk { bytes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdf::{extract_and_expand, HkdfSha256, HkdfSha384, HkdfSha512};
+
+    macro_rules! test_labeled_roundtrip {
+        ($test_name:ident, $kdf_ty:ty) => {
+            /// Tests that the public labeled KDFs agree with the ones the crate uses internally,
+            /// and that a PRK survives a trip through its bytes
+            #[test]
+            fn $test_name() {
+                type Kdf = $kdf_ty;
+
+                let suite_id = SuiteId::from_kem_id(0x0020);
+                let ikm = b"input keying material";
+                let info = b"kem context";
+
+                let mut expected = [0u8; 32];
+                extract_and_expand::<Kdf>(ikm, suite_id.as_bytes(), info, &mut expected).unwrap();
+
+                let prk = labeled_extract::<Kdf>(&[], &suite_id, b"eae_prk", ikm);
+                let mut out = [0u8; 32];
+                prk.labeled_expand(&suite_id, b"shared_secret", info, &mut out)
+                    .unwrap();
+                assert_eq!(out, expected);
+
+                let prk = Prk::<Kdf>::from_bytes(prk.as_bytes()).unwrap();
+                let mut out = [0u8; 32];
+                prk.labeled_expand(&suite_id, b"shared_secret", info, &mut out)
+                    .unwrap();
+                assert_eq!(out, expected);
+
+                // HKDF can't output more than 255 * Nh bytes
+                let mut too_long = [0u8; 255 * 64 + 1];
+                assert_eq!(
+                    prk.labeled_expand(&suite_id, b"label", info, &mut too_long),
+                    Err(HpkeError::KdfOutputTooLong)
+                );
+                assert!(matches!(
+                    Prk::<Kdf>::from_bytes(&[0u8; 3]),
+                    Err(HpkeError::IncorrectInputLength(_, 3))
+                ));
+            }
+        };
+    }
+
+    test_labeled_roundtrip!(test_labeled_roundtrip_sha256, HkdfSha256);
+    test_labeled_roundtrip!(test_labeled_roundtrip_sha384, HkdfSha384);
+    test_labeled_roundtrip!(test_labeled_roundtrip_sha512, HkdfSha512);
+
+    /// Tests the encodings of the suite IDs
+    #[test]
+    fn test_suite_ids() {
+        assert_eq!(SuiteId::from_kem_id(0x0010).as_bytes(), b"KEM\x00\x10");
+        assert_eq!(
+            SuiteId::from_ids(0x0020, 0x0001, 0x0003).as_bytes(),
+            b"HPKE\x00\x20\x00\x01\x00\x03"
+        );
+
+        #[cfg(feature = "x25519")]
+        {
+            use crate::{aead::ChaCha20Poly1305, kem::X25519HkdfSha256};
+
+            assert_eq!(
+                SuiteId::kem::<X25519HkdfSha256>(),
+                SuiteId::from_kem_id(0x0020)
+            );
+            assert_eq!(
+                SuiteId::hpke::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256>(),
+                SuiteId::from_ids(0x0020, 0x0001, 0x0003)
+            );
+        }
+
+        assert_eq!(
+            SuiteId::custom(b"MLS 1.0 ").unwrap().as_bytes(),
+            b"MLS 1.0 "
+        );
+        assert!(SuiteId::custom(b"KEM\x00\x20").is_err());
+        assert!(SuiteId::custom(b"HPKE").is_err());
+        assert!(SuiteId::custom(&[0u8; MAX_SUITE_ID_LEN + 1]).is_err());
+    }
+}
@@ -1,17 +1,57 @@
 //! Traits and structs for key encapsulation mechanisms
 
-use crate::{Deserializable, HpkeError, Serializable};
+use crate::{
+    aead::{AeadCtx, ExportOnlyAead},
+    kdf::{labeled_extract, HkdfSha256, Kdf as KdfTrait, LabeledExpand},
+    op_mode::{OpModeR, OpModeS},
+    setup::derive_enc_ctx,
+    util::kem_suite_id,
+    Deserializable, HpkeError, KeyValidationError, Serializable,
+};
+
+#[cfg(feature = "alloc")]
+use crate::Vec;
 
 use generic_array::{ArrayLength, GenericArray};
-use rand_core::{CryptoRng, RngCore};
-use zeroize::Zeroize;
+use rand_core::{CryptoRng, CryptoRngCore, RngCore};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+mod combined;
+pub use combined::{
+    AddLength, CombinedEncappedKey, CombinedKem, CombinedPrivateKey, CombinedPublicKey,
+};
 
 mod dhkem;
 pub use dhkem::*;
 
+mod fingerprint;
+pub use fingerprint::Fingerprint;
+
+mod keypair;
+pub use keypair::Keypair;
+
+mod recipient_handle;
+pub use recipient_handle::RecipientHandle;
+
 #[cfg(feature = "serde_impls")]
 use serde::{Deserialize as SerdeDeserialize, Serialize as SerdeSerialize};
 
+/// A record of how `Kem::derive_keypair_verbose` derived a keypair
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeriveTrace {
+    /// The algorithm identifier of the KEM that derived the keypair
+    pub kem_id: u16,
+    /// The value of the `counter` byte in DeriveKeyPair (RFC 9180 §7.1.3) whose candidate was
+    /// accepted. This is `None` for KEMs that don't do rejection sampling, like X25519.
+    pub counter: Option<u8>,
+}
+
+// The fixed inputs to Kem::self_test. The ephemeral keying material is a repeated byte, which is
+// fine since nothing is ever sealed under the resulting shared secret.
+const SELF_TEST_IKM_BYTE: u8 = 0x5a;
+const SELF_TEST_INFO: &[u8] = b"HPKE self-test";
+
 /// Represents authenticated encryption functionality
 pub trait Kem: Sized {
     /// The key exchange's public key type. If you want to generate a keypair, see
@@ -33,13 +73,15 @@ pub trait Kem: Sized {
     type PrivateKey: Clone
         + Serializable
         + Deserializable
+        + ConstantTimeEq
+        + ZeroizeOnDrop
         + SerdeSerialize
         + for<'a> SerdeDeserialize<'a>;
 
     /// The key exchange's private key type. If you want to generate a keypair, see
     /// `Kem::gen_keypair` or `Kem::derive_keypair`
     #[cfg(not(feature = "serde_impls"))]
-    type PrivateKey: Clone + Serializable + Deserializable;
+    type PrivateKey: Clone + Serializable + Deserializable + ConstantTimeEq + ZeroizeOnDrop;
 
     /// The encapsulated key for this KEM. This is used by the recipient to derive the shared
     /// secret.
@@ -55,70 +97,459 @@ pub trait Kem: Sized {
     type EncappedKey: Clone + Serializable + Deserializable;
 
     /// The size of a shared secret in this KEM
-    #[doc(hidden)]
     type NSecret: ArrayLength<u8>;
 
+    /// A recipient public key with precomputed tables that make encapsulating to it faster. See
+    /// `RecipientHandle`.
+    #[doc(hidden)]
+    type PublicKeyTable: Clone;
+
     /// The algorithm identifier for a KEM implementation
     const KEM_ID: u16;
 
-    /// Deterministically derives a keypair from the given input keying material
+    /// Deterministically derives a keypair from the given input keying material. This is
+    /// `DeriveKeyPair()` from RFC 9180 §7.1.3.
     ///
     /// Requirements
     /// ============
     /// This keying material SHOULD have as many bits of entropy as the bit length of a secret key,
     /// i.e., `8 * Self::PrivateKey::size()`. For X25519 and P-256, this is 256 bits of
     /// entropy.
-    fn derive_keypair(ikm: &[u8]) -> (Self::PrivateKey, Self::PublicKey);
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns the keypair. If DeriveKeyPair rejected every candidate private key,
+    /// returns `Err(HpkeError::KeyDerivation)`. For the DHKEMs here, this happens with
+    /// probability at most 2^-8192.
+    fn try_derive_keypair(ikm: &[u8]) -> Result<(Self::PrivateKey, Self::PublicKey), HpkeError>;
+
+    /// Same as `try_derive_keypair`, but panics on failure
+    ///
+    /// Requirements
+    /// ============
+    /// This keying material SHOULD have as many bits of entropy as the bit length of a secret key,
+    /// i.e., `8 * Self::PrivateKey::size()`. For X25519 and P-256, this is 256 bits of
+    /// entropy.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `try_derive_keypair` fails. Builds that must not panic should use
+    /// `try_derive_keypair` instead.
+    fn derive_keypair(ikm: &[u8]) -> (Self::PrivateKey, Self::PublicKey) {
+        Self::try_derive_keypair(ikm).expect("DeriveKeyPair failed all attempts")
+    }
+
+    /// Same as `try_derive_keypair`, but also returns a `DeriveTrace` recording how the key was
+    /// derived, e.g., for audit logs. KEMs whose DeriveKeyPair does rejection sampling override
+    /// this to report the counter. The default reports no counter.
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns the keypair and its trace. If DeriveKeyPair rejected every candidate,
+    /// returns `Err(HpkeError::KeyDerivation)`.
+    fn derive_keypair_verbose(
+        ikm: &[u8],
+    ) -> Result<(Self::PrivateKey, Self::PublicKey, DeriveTrace), HpkeError> {
+        let (sk, pk) = Self::try_derive_keypair(ikm)?;
+        let trace = DeriveTrace {
+            kem_id: Self::KEM_ID,
+            counter: None,
+        };
+        Ok((sk, pk, trace))
+    }
+
+    // Each level of the path is an extract keyed with the level above. The hash is fixed, so the
+    // tree doesn't depend on which KDF the KEM uses internally.
+    //
+    // node_0     = LabeledExtract("", "hd_master", master_ikm)
+    // node_{i+1} = LabeledExtract(node_i, "hd_child", path[i])
+    // ikm        = LabeledExpand(node_n, "hd_ikm", "", Nsk)
+    // return DeriveKeyPair(ikm)
+    //
+    // with HKDF-SHA256, and suite_id = concat("KEM", I2OSP(kem_id, 2))
+
+    /// Deterministically derives the keypair at `path` in a tree of keypairs rooted at
+    /// `master_ikm`, e.g., one per device, with `path = [b"devices", device_id]`. Anyone with
+    /// `master_ikm` derives the same keypair for the same path, so the keys needn't be stored.
+    /// Every path component is a separate level, so `[b"ab"]` and `[b"a", b"b"]` give unrelated
+    /// keypairs, and so do different KEMs.
+    ///
+    /// This isn't BIP-32. There is no public derivation, so child public keys can only be derived
+    /// with `master_ikm`, and a leaked child key reveals nothing about its parent or siblings.
+    ///
+    /// Requirements
+    /// ============
+    /// `master_ikm` SHOULD have at least as much entropy as `derive_keypair` asks for.
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns the keypair. Otherwise, returns the errors `try_derive_keypair` does.
+    fn derive_child_keypair(
+        master_ikm: &[u8],
+        path: &[&[u8]],
+    ) -> Result<(Self::PrivateKey, Self::PublicKey), HpkeError> {
+        let suite_id = kem_suite_id::<Self>();
+        let (mut node, mut hkdf_ctx) =
+            labeled_extract::<HkdfSha256>(&[], &suite_id, b"hd_master", master_ikm);
+        for component in path {
+            let (child, child_ctx) =
+                labeled_extract::<HkdfSha256>(&node, &suite_id, b"hd_child", component);
+            node.zeroize();
+            node = child;
+            hkdf_ctx = child_ctx;
+        }
+        node.zeroize();
+
+        let mut ikm: GenericArray<u8, <Self::PrivateKey as Serializable>::OutputSize> =
+            GenericArray::default();
+        // Private keys are far shorter than 255 hashes, so this can't fail
+        hkdf_ctx
+            .labeled_expand(&suite_id, b"hd_ikm", b"", &mut ikm)
+            .unwrap();
+        let res = Self::try_derive_keypair(&ikm);
+        ikm.zeroize();
+        res
+    }
+
+    /// Computes the public key corresponding to the given private key
+    fn sk_to_pk(sk: &Self::PrivateKey) -> Self::PublicKey;
 
     /// Generates a random keypair using the given RNG
-    fn gen_keypair<R: CryptoRng + RngCore>(csprng: &mut R) -> (Self::PrivateKey, Self::PublicKey) {
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns the keypair. Returns `Err(HpkeError::KeyDerivation)` if
+    /// `try_derive_keypair` fails on the sampled keying material.
+    fn try_gen_keypair<R: CryptoRng + RngCore + ?Sized>(
+        csprng: &mut R,
+    ) -> Result<(Self::PrivateKey, Self::PublicKey), HpkeError> {
         // Make some keying material that's the size of a private key
         let mut ikm: GenericArray<u8, <Self::PrivateKey as Serializable>::OutputSize> =
             GenericArray::default();
         // Fill it with randomness
         csprng.fill_bytes(&mut ikm);
         // Run derive_keypair using the KEM's KDF
-        Self::derive_keypair(&ikm)
+        let res = Self::try_derive_keypair(&ikm);
+        ikm.zeroize();
+        res
+    }
+
+    /// Same as `try_gen_keypair`, but panics on failure
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `try_gen_keypair` fails. Builds that must not panic should use
+    /// `try_gen_keypair` instead.
+    fn gen_keypair<R: CryptoRng + RngCore + ?Sized>(
+        csprng: &mut R,
+    ) -> (Self::PrivateKey, Self::PublicKey) {
+        Self::try_gen_keypair(csprng).expect("DeriveKeyPair failed all attempts")
+    }
+
+    /// Generates a random keypair using the given RNG trait object. This is the same as
+    /// `gen_keypair`, but isn't generic over the RNG, so it's one function per KEM no matter how
+    /// many RNG types the caller has. This is useful for callers whose randomness comes from an
+    /// HSM or other external source behind a `dyn` interface.
+    ///
+    /// Every API in this crate that needs randomness takes it from a caller-provided RNG like
+    /// this. None of them draw from the OS, and this crate does not depend on `getrandom`.
+    fn gen_keypair_from_rng(csprng: &mut dyn CryptoRngCore) -> (Self::PrivateKey, Self::PublicKey) {
+        Self::gen_keypair(csprng)
     }
 
     /// Derives a shared secret given the encapsulated key and the recipients secret key. If
-    /// `pk_sender_id` is given, the sender's identity will be tied to the shared secret.
+    /// `pk_sender_id` is given, the sender's identity will be tied to the shared secret. This is
+    /// `Decap()` from RFC 9180 §4.1, or `AuthDecap()` if `pk_sender_id` is given.
+    ///
+    /// This is the raw KEM. Most users want `setup_receiver`, which runs this and then the HPKE
+    /// key schedule. Use this directly if you're feeding the shared secret into your own key
+    /// schedule.
     ///
     /// Return Value
     /// ============
     /// Returns a shared secret on success. If an error happened during key exchange, returns
     /// `Err(HpkeError::DecapError)`.
-    #[doc(hidden)]
     fn decap(
         sk_recip: &Self::PrivateKey,
         pk_sender_id: Option<&Self::PublicKey>,
         encapped_key: &Self::EncappedKey,
     ) -> Result<SharedSecret<Self>, HpkeError>;
 
+    /// Runs `decap` on every one of `encapped_keys` with the same recipient secret key and sender
+    /// identity. KEMs can override this to share work across the batch. The default just loops.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns one result per encapped key, in the same order. Each is what `decap` would have
+    /// returned for that key.
+    #[cfg(feature = "alloc")]
+    #[doc(hidden)]
+    fn decap_batch(
+        sk_recip: &Self::PrivateKey,
+        pk_sender_id: Option<&Self::PublicKey>,
+        encapped_keys: &[Self::EncappedKey],
+    ) -> Vec<Result<SharedSecret<Self>, HpkeError>> {
+        encapped_keys
+            .iter()
+            .map(|ek| Self::decap(sk_recip, pk_sender_id, ek))
+            .collect()
+    }
+
     /// Derives a shared secret and an ephemeral pubkey that the owner of the reciepint's pubkey
-    /// can use to derive the same shared secret. If `sk_sender_id` is given, the sender's identity
-    /// will be tied to the shared secret. All this does is generate an ephemeral keypair and pass
-    /// to `encap_with_eph`.
+    /// can use to derive the same shared secret. If `sender_id_keypair` is given, the sender's
+    /// identity will be tied to the shared secret. This is `Encap()` from RFC 9180 §4.1, or
+    /// `AuthEncap()` if `sender_id_keypair` is given.
+    ///
+    /// This is the raw KEM. Most users want `setup_sender`, which runs this and then the HPKE key
+    /// schedule. Use this directly if you're feeding the shared secret into your own key
+    /// schedule. The recipient gets the same shared secret by calling `decap` on the encapped
+    /// key.
     ///
     /// Return Value
     /// ============
     /// Returns a shared secret and encapped key on success. If an error happened during key
     /// exchange, returns `Err(HpkeError::EncapError)`.
+    fn encap<R: CryptoRng + RngCore + ?Sized>(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError>;
+
+    /// Runs `encap` once for each of `pk_recips`, with the same sender identity. Every
+    /// encapsulation gets its own ephemeral key. KEMs can override this to share work across the
+    /// batch. The default just loops.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns one shared secret and encapped key per recipient, in the same order as
+    /// `pk_recips`. If an error happened during any key exchange, returns
+    /// `Err(HpkeError::EncapError)`.
+    #[cfg(feature = "alloc")]
     #[doc(hidden)]
-    fn encap<R: CryptoRng + RngCore>(
+    fn encap_batch<R: CryptoRng + RngCore + ?Sized>(
+        pk_recips: &[Self::PublicKey],
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        csprng: &mut R,
+    ) -> Result<Vec<(SharedSecret<Self>, Self::EncappedKey)>, HpkeError> {
+        pk_recips
+            .iter()
+            .map(|pk| Self::encap(pk, sender_id_keypair, csprng))
+            .collect()
+    }
+
+    /// Does the precomputation for encapsulating to `pk_recip`
+    #[doc(hidden)]
+    fn precompute_pk(pk_recip: &Self::PublicKey) -> Self::PublicKeyTable;
+
+    /// Same as `encap`, where `table` was computed from `pk_recip`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret and encapped key on success. If an error happened during key
+    /// exchange, returns `Err(HpkeError::EncapError)`.
+    #[doc(hidden)]
+    fn encap_with_table<R: CryptoRng + RngCore + ?Sized>(
         pk_recip: &Self::PublicKey,
+        table: &Self::PublicKeyTable,
         sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
         csprng: &mut R,
     ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError>;
+
+    /// Like `encap`, but derives the ephemeral keypair deterministically from `ikm_eph` via
+    /// `derive_keypair`, rather than sampling it from an RNG.
+    ///
+    /// DANGER
+    /// ======
+    /// This exists so that tests and CI can produce fixed ciphertexts. Reusing `ikm_eph` across
+    /// encapsulations reuses the ephemeral key, which destroys the security of every message
+    /// sealed under it. Never use this with IKM that isn't fresh, uniformly random, and secret.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret and encapped key on success. If an error happened during key
+    /// exchange, returns `Err(HpkeError::EncapError)`.
+    #[doc(hidden)]
+    fn encap_with_ikm(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        ikm_eph: &[u8],
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError>;
+
+    /// Like `encap`, but uses the given ephemeral keypair rather than sampling one. The encapped
+    /// key is `pk_eph`. This only makes sense for KEMs whose encapsulated key is an ephemeral
+    /// public key, so by default it's unsupported and always fails.
+    ///
+    /// DANGER
+    /// ======
+    /// See `setup_sender_with_ephemeral`. Every encapsulation with the same ephemeral key to the
+    /// same recipient gives the same shared secret.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret and encapped key on success. If `pk_eph` isn't the public key of
+    /// `sk_eph`, the KEM doesn't support this, or an error happened during key exchange, returns
+    /// `Err(HpkeError::EncapError)`.
+    #[doc(hidden)]
+    fn encap_with_ephemeral(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        sk_eph: &Self::PrivateKey,
+        pk_eph: &Self::PublicKey,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        let _ = (pk_recip, sender_id_keypair, sk_eph, pk_eph);
+        Err(HpkeError::EncapError)
+    }
+
+    /// Generates a fresh encapsulated key that no one can decapsulate, for sending where a real
+    /// one would go, e.g., in a GREASE TLS Encrypted Client Hello extension. It's distributed
+    /// exactly like a real encapsulated key, since by default it's a real encapsulation to a
+    /// freshly sampled keypair whose private half is thrown away. KEMs whose encapsulated key is
+    /// just an ephemeral public key override this to skip the encapsulation.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if key generation or encapsulation fails, like `gen_keypair`
+    fn generate_grease_encapped_key<R: CryptoRng + RngCore + ?Sized>(
+        csprng: &mut R,
+    ) -> Self::EncappedKey {
+        let (_, pk) = Self::gen_keypair(csprng);
+        let (_, encapped_key) =
+            Self::encap(&pk, None, csprng).expect("encapsulating to a fresh keypair failed");
+        encapped_key
+    }
+
+    /// Computes the fingerprint of `pk`, a short identifier for naming it in logs and envelopes.
+    /// The fingerprint is bound to this KEM and to `Kdf`, so the same key bytes under a different
+    /// KEM or KDF have an unrelated fingerprint. It is
+    ///
+    /// ```text
+    /// suite_id = concat("KEM", I2OSP(kem_id, 2))
+    /// fingerprint = LabeledExtract("", "pk_fingerprint",
+    ///                              concat(I2OSP(kdf_id, 2), SerializePublicKey(pk)))
+    /// ```
+    ///
+    /// The DHKEM public key types also have this as a method, e.g., `pk.fingerprint::<Kdf>()`.
+    fn fingerprint<Kdf: KdfTrait>(pk: &Self::PublicKey) -> Fingerprint<Kdf> {
+        Fingerprint::of::<Self>(pk)
+    }
+
+    /// Checks that `sk` and `pk` are a working keypair, e.g., before putting keys that were
+    /// converted from a wallet or imported from an HSM into production. This checks that `pk` is
+    /// the public key of `sk`, then encapsulates to `pk`, decapsulates with `sk`, and checks that
+    /// both ends get the same shared secret. Finally, it runs both ends of the key schedule, with
+    /// HKDF-SHA256, and checks that they export the same secret.
+    ///
+    /// This is deterministic. The ephemeral key is derived from fixed keying material, so the
+    /// same keypair always runs the same computation.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if every check passes. If `pk` isn't the public key of `sk`, or the two
+    /// ends disagree, returns `Err(HpkeError::InvalidKey(KeyValidationError::KeypairMismatch))`.
+    /// If encapsulation or decapsulation fails, returns `Err(HpkeError::EncapError)` or
+    /// `Err(HpkeError::DecapError)`.
+    fn self_test(sk: &Self::PrivateKey, pk: &Self::PublicKey) -> Result<(), HpkeError> {
+        let mismatch = HpkeError::InvalidKey(KeyValidationError::KeypairMismatch);
+
+        // Public keys aren't secret, so this needn't be constant-time
+        if Self::sk_to_pk(sk).to_bytes() != pk.to_bytes() {
+            return Err(mismatch);
+        }
+
+        // Encap to pk and decap with sk
+        let mut ikm_eph: GenericArray<u8, <Self::PrivateKey as Serializable>::OutputSize> =
+            GenericArray::default();
+        ikm_eph.iter_mut().for_each(|b| *b = SELF_TEST_IKM_BYTE);
+        let (shared_secret_s, encapped_key) = Self::encap_with_ikm(pk, None, &ikm_eph)?;
+        let shared_secret_r = Self::decap(sk, None, &encapped_key)?;
+        if !bool::from(shared_secret_s.ct_eq(&shared_secret_r)) {
+            return Err(mismatch);
+        }
+
+        // Run the key schedule on both ends, and compare what they export
+        let ctx_s: AeadCtx<ExportOnlyAead, HkdfSha256, Self> =
+            derive_enc_ctx(&OpModeS::Base, shared_secret_s, &[SELF_TEST_INFO]);
+        let ctx_r: AeadCtx<ExportOnlyAead, HkdfSha256, Self> =
+            derive_enc_ctx(&OpModeR::Base, shared_secret_r, &[SELF_TEST_INFO]);
+        let (mut exported_s, mut exported_r) = ([0u8; 32], [0u8; 32]);
+        ctx_s.export(SELF_TEST_INFO, &mut exported_s)?;
+        ctx_r.export(SELF_TEST_INFO, &mut exported_r)?;
+        let exports_match = bool::from(exported_s.ct_eq(&exported_r));
+        exported_s.zeroize();
+        exported_r.zeroize();
+
+        if exports_match {
+            Ok(())
+        } else {
+            Err(mismatch)
+        }
+    }
+
+    /// Runs a pairwise consistency test, as FIPS 140-3 asks for at power-up: generates a fresh
+    /// keypair, encapsulates to it with fresh randomness, decapsulates, and checks that both ends
+    /// get the same shared secret. Unlike `self_test`, this doesn't need a keypair, and nothing
+    /// about it is fixed. Nothing derived here leaves the function.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if the two ends agree. Otherwise, returns
+    /// `Err(HpkeError::InvalidKey(KeyValidationError::KeypairMismatch))`. If key generation,
+    /// encapsulation, or decapsulation fails, returns their error.
+    fn pairwise_consistency_test<R: CryptoRng + RngCore + ?Sized>(
+        csprng: &mut R,
+    ) -> Result<(), HpkeError> {
+        let (sk, pk) = Self::try_gen_keypair(csprng)?;
+        let (shared_secret_s, encapped_key) = Self::encap(&pk, None, csprng)?;
+        let shared_secret_r = Self::decap(&sk, None, &encapped_key)?;
+        if bool::from(shared_secret_s.ct_eq(&shared_secret_r)) {
+            Ok(())
+        } else {
+            Err(HpkeError::InvalidKey(KeyValidationError::KeypairMismatch))
+        }
+    }
+}
+
+/// Runs `Kem::pairwise_consistency_test` on every KEM this build includes, in the order
+/// DHKEM(X25519, HKDF-SHA256), DHKEM(P-256, HKDF-SHA256), DHKEM(K-256, HKDF-SHA256), and stops at
+/// the first failure. Call this once at startup, before any key is used.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(())` if every test passes. Otherwise, returns the KEM ID of the first KEM that
+/// failed, along with its error.
+pub fn self_test_all<R: CryptoRng + RngCore + ?Sized>(
+    csprng: &mut R,
+) -> Result<(), (u16, HpkeError)> {
+    // Without any KEM features, there's nothing to test
+    let _ = &csprng;
+
+    #[cfg(feature = "x25519-dalek")]
+    X25519HkdfSha256::pairwise_consistency_test(csprng)
+        .map_err(|e| (X25519HkdfSha256::KEM_ID, e))?;
+    #[cfg(feature = "p256")]
+    DhP256HkdfSha256::pairwise_consistency_test(csprng)
+        .map_err(|e| (DhP256HkdfSha256::KEM_ID, e))?;
+    #[cfg(feature = "k256")]
+    DhK256HkdfSha256::pairwise_consistency_test(csprng)
+        .map_err(|e| (DhK256HkdfSha256::KEM_ID, e))?;
+
+    Ok(())
 }
 
 // Kem is used as a type parameter everywhere. To avoid confusion, alias it
 use Kem as KemTrait;
 
-/// A convenience type for `[u8; NSecret]` for any given KEM
-#[doc(hidden)]
+/// The shared secret output by `Kem::encap` and `Kem::decap`. This is `Kem::NSecret` bytes long.
+/// It is zeroized on drop, and its equality check is constant-time.
 pub struct SharedSecret<Kem: KemTrait>(pub GenericArray<u8, Kem::NSecret>);
 
+impl<Kem: KemTrait> SharedSecret<Kem> {
+    /// Returns the bytes of this shared secret
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl<Kem: KemTrait> Default for SharedSecret<Kem> {
     fn default() -> SharedSecret<Kem> {
         SharedSecret(GenericArray::<u8, Kem::NSecret>::default())
@@ -136,6 +567,13 @@ impl<Kem: KemTrait> Drop for SharedSecret<Kem> {
         self.zeroize();
     }
 }
+impl<Kem: KemTrait> ZeroizeOnDrop for SharedSecret<Kem> {}
+
+impl<Kem: KemTrait> ConstantTimeEq for SharedSecret<Kem> {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -163,6 +601,10 @@ mod tests {
 
                 // Ensure that the encapsulated secret is what decap() derives
                 assert_eq!(auth_shared_secret.0, decapped_auth_shared_secret.0);
+                assert_eq!(
+                    auth_shared_secret.as_bytes().len(),
+                    <<Kem as KemTrait>::NSecret as generic_array::typenum::Unsigned>::to_usize()
+                );
 
                 //
                 // Now do it with the auth, i.e., using the sender's identity keys
@@ -219,12 +661,159 @@ mod tests {
         };
     }
 
+    /// Tests that the constant-time equality on private keys and shared secrets agrees with
+    /// byte equality, and that the secret types are marked zeroize-on-drop
+    macro_rules! test_secret_ct_eq {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                use subtle::ConstantTimeEq;
+                use zeroize::ZeroizeOnDrop;
+
+                type Kem = $kem_ty;
+
+                // This only compiles if the types are ZeroizeOnDrop
+                fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+                assert_zeroize_on_drop::<<Kem as KemTrait>::PrivateKey>();
+                assert_zeroize_on_drop::<crate::kem::SharedSecret<Kem>>();
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk1, pk1) = Kem::gen_keypair(&mut csprng);
+                let (sk2, _) = Kem::gen_keypair(&mut csprng);
+                assert!(bool::from(sk1.ct_eq(&sk1.clone())));
+                assert!(!bool::from(sk1.ct_eq(&sk2)));
+
+                let (ss1, encapped_key) = Kem::encap(&pk1, None, &mut csprng).unwrap();
+                let ss2 = Kem::decap(&sk1, None, &encapped_key).unwrap();
+                let (ss3, _) = Kem::encap(&pk1, None, &mut csprng).unwrap();
+                assert!(bool::from(ss1.ct_eq(&ss2)));
+                assert!(!bool::from(ss1.ct_eq(&ss3)));
+            }
+        };
+    }
+
+    /// Tests that the RNG-taking functions accept a `&mut dyn CryptoRngCore`, and that
+    /// `gen_keypair_from_rng` draws the same keypair as `gen_keypair` from the same RNG state
+    macro_rules! test_dyn_rng {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                use rand_core::CryptoRngCore;
+
+                type Kem = $kem_ty;
+
+                let mut rng1 = StdRng::seed_from_u64(0xd1);
+                let mut rng2 = StdRng::seed_from_u64(0xd1);
+                let dyn_rng: &mut dyn CryptoRngCore = &mut rng1;
+                let (sk1, pk1) = Kem::gen_keypair_from_rng(dyn_rng);
+                let (sk2, pk2) = Kem::gen_keypair(&mut rng2);
+                assert_eq!(sk1.to_bytes(), sk2.to_bytes());
+                assert_eq!(pk1.to_bytes(), pk2.to_bytes());
+
+                // The generic functions take the trait object directly
+                let (ss, encapped_key) = Kem::encap(&pk1, None, dyn_rng).unwrap();
+                let decapped_ss = Kem::decap(&sk1, None, &encapped_key).unwrap();
+                assert_eq!(ss.0, decapped_ss.0);
+            }
+        };
+    }
+
+    /// Tests that `try_derive_keypair` and `derive_keypair_verbose` derive the same keypair as
+    /// `derive_keypair`, and that the latter reports a counter exactly when the KEM does
+    /// rejection sampling
+    macro_rules! test_derive_verbose {
+        ($test_name:ident, $kem_ty:ty, $has_counter:expr) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+
+                for i in 0u8..16 {
+                    let ikm = [i; 32];
+                    let (sk, pk) = Kem::derive_keypair(&ikm);
+                    let (sk_try, pk_try) = Kem::try_derive_keypair(&ikm).unwrap();
+                    assert_eq!(sk.to_bytes(), sk_try.to_bytes());
+                    assert_eq!(pk.to_bytes(), pk_try.to_bytes());
+                    let (sk_verbose, pk_verbose, trace) =
+                        Kem::derive_keypair_verbose(&ikm).unwrap();
+                    assert_eq!(sk.to_bytes(), sk_verbose.to_bytes());
+                    assert_eq!(pk.to_bytes(), pk_verbose.to_bytes());
+                    assert_eq!(trace.kem_id, <Kem as KemTrait>::KEM_ID);
+                    assert_eq!(trace.counter.is_some(), $has_counter);
+                }
+            }
+        };
+    }
+
+    /// Tests that `self_test` passes on a keypair, and fails on a public key from another keypair
+    macro_rules! test_self_test {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                use crate::{HpkeError, KeyValidationError};
+
+                type Kem = $kem_ty;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk1, pk1) = Kem::gen_keypair(&mut csprng);
+                let (sk2, pk2) = Kem::gen_keypair(&mut csprng);
+                Kem::self_test(&sk1, &pk1).unwrap();
+                Kem::self_test(&sk2, &pk2).unwrap();
+
+                let mismatch = Err(HpkeError::InvalidKey(KeyValidationError::KeypairMismatch));
+                assert_eq!(Kem::self_test(&sk1, &pk2), mismatch);
+                assert_eq!(Kem::self_test(&sk2, &pk1), mismatch);
+            }
+        };
+    }
+
+    /// Tests that every compiled KEM passes its pairwise consistency test
+    #[test]
+    fn test_self_test_all() {
+        super::self_test_all(&mut StdRng::from_entropy()).unwrap();
+    }
+
     #[cfg(feature = "x25519-dalek")]
     mod x25519_tests {
         use super::*;
 
         test_encap_correctness!(test_encap_correctness_x25519, crate::kem::X25519HkdfSha256);
         test_encapped_serialize!(test_encapped_serialize_x25519, crate::kem::X25519HkdfSha256);
+        test_secret_ct_eq!(test_secret_ct_eq_x25519, crate::kem::X25519HkdfSha256);
+        test_dyn_rng!(test_dyn_rng_x25519, crate::kem::X25519HkdfSha256);
+        test_derive_verbose!(
+            test_derive_verbose_x25519,
+            crate::kem::X25519HkdfSha256,
+            false
+        );
+        test_self_test!(test_self_test_x25519, crate::kem::X25519HkdfSha256);
+
+        /// Tests that child keypairs are deterministic, and that every path, path split, master
+        /// and master secret gives a different one
+        #[test]
+        fn test_derive_child_keypair() {
+            use crate::kem::X25519HkdfSha256;
+
+            let master = [0x42u8; 32];
+            let pk_at = |master: &[u8], path: &[&[u8]]| {
+                let (sk, pk) = X25519HkdfSha256::derive_child_keypair(master, path).unwrap();
+                assert_eq!(X25519HkdfSha256::sk_to_pk(&sk).to_bytes(), pk.to_bytes());
+                pk.to_bytes()
+            };
+
+            let device = pk_at(&master, &[b"devices", b"7"]);
+            assert_eq!(device, pk_at(&master, &[b"devices", b"7"]));
+            let others = [
+                pk_at(&master, &[b"devices", b"8"]),
+                pk_at(&master, &[b"devices7"]),
+                pk_at(&master, &[b"devices", b"7", b""]),
+                pk_at(&master, &[]),
+                pk_at(&[0x43u8; 32], &[b"devices", b"7"]),
+                X25519HkdfSha256::derive_keypair(&master).1.to_bytes(),
+            ];
+            for other in &others {
+                assert_ne!(&device, other);
+            }
+        }
     }
 
     #[cfg(feature = "p256")]
@@ -233,5 +822,55 @@ mod tests {
 
         test_encap_correctness!(test_encap_correctness_p256, crate::kem::DhP256HkdfSha256);
         test_encapped_serialize!(test_encapped_serialize_p256, crate::kem::DhP256HkdfSha256);
+        test_secret_ct_eq!(test_secret_ct_eq_p256, crate::kem::DhP256HkdfSha256);
+        test_dyn_rng!(test_dyn_rng_p256, crate::kem::DhP256HkdfSha256);
+        test_derive_verbose!(test_derive_verbose_p256, crate::kem::DhP256HkdfSha256, true);
+        test_self_test!(test_self_test_p256, crate::kem::DhP256HkdfSha256);
+    }
+
+    // DHKEM(X25519, HKDF-SHA512) isn't in RFC 9180, so this is built from the generic DhKem
+    #[cfg(feature = "x25519-dalek")]
+    mod custom_dhkem_tests {
+        use super::*;
+        use crate::{
+            dhkex::X25519,
+            kdf::{HkdfSha256, HkdfSha512},
+            kem::{DhKem, X25519HkdfSha256},
+        };
+
+        use generic_array::typenum::Unsigned;
+
+        type X25519HkdfSha512 = DhKem<X25519, HkdfSha512, 0xFF20>;
+
+        test_encap_correctness!(test_encap_correctness_custom, X25519HkdfSha512);
+        test_encapped_serialize!(test_encapped_serialize_custom, X25519HkdfSha512);
+        test_self_test!(test_self_test_custom, X25519HkdfSha512);
+
+        /// Tests that the KEM ID and KDF of a custom DHKEM go into its key derivations
+        #[test]
+        fn test_custom_dhkem_domain_separation() {
+            type SameKdf = DhKem<X25519, HkdfSha256, 0xFF21>;
+
+            let ikm = [7u8; 32];
+            let (_, pk_std) = X25519HkdfSha256::derive_keypair(&ikm);
+            let (_, pk_custom) = X25519HkdfSha512::derive_keypair(&ikm);
+            let (_, pk_same_kdf) = SameKdf::derive_keypair(&ikm);
+            assert_ne!(pk_std.to_bytes(), pk_custom.to_bytes());
+            assert_ne!(pk_std.to_bytes(), pk_same_kdf.to_bytes());
+            assert_eq!(X25519HkdfSha512::KEM_ID, 0xFF20);
+            assert_eq!(
+                <X25519HkdfSha512 as KemTrait>::NSecret::USIZE,
+                64,
+                "Nsecret is the KDF's hash length"
+            );
+        }
+    }
+
+    #[cfg(feature = "k256")]
+    mod k256_tests {
+        use super::*;
+
+        test_derive_verbose!(test_derive_verbose_k256, crate::kem::DhK256HkdfSha256, true);
+        test_self_test!(test_self_test_k256, crate::kem::DhK256HkdfSha256);
     }
 }
@@ -0,0 +1,415 @@
+use crate::{
+    kdf::{labeled_extract, labeled_extract_multi, Kdf as KdfTrait, LabeledExpand},
+    kem::{Kem as KemTrait, SharedSecret},
+    util::kem_suite_id,
+    Deserializable, HpkeError, Serializable,
+};
+
+use core::{marker::PhantomData, ops::Add};
+
+use digest::OutputSizeUser;
+use generic_array::{typenum::Sum, ArrayLength, GenericArray};
+use rand_core::{CryptoRng, RngCore};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// The bound that two byte lengths can be added at the type level. This is implemented for every
+/// pair of lengths, and only exists to keep the bounds on `CombinedKem` readable.
+#[doc(hidden)]
+pub trait AddLength<Rhs>: ArrayLength<u8> {
+    type Output: ArrayLength<u8>;
+}
+
+impl<L, R> AddLength<R> for L
+where
+    L: ArrayLength<u8> + Add<R>,
+    Sum<L, R>: ArrayLength<u8>,
+{
+    type Output = Sum<L, R>;
+}
+
+// The serialized sizes of each component, and of their concatenation
+type PkSize<K> = <<K as KemTrait>::PublicKey as Serializable>::OutputSize;
+type SkSize<K> = <<K as KemTrait>::PrivateKey as Serializable>::OutputSize;
+type EncSize<K> = <<K as KemTrait>::EncappedKey as Serializable>::OutputSize;
+type SumSize<L, R> = <L as AddLength<R>>::Output;
+
+/// A KEM that runs the KEMs `K1` and `K2` side by side, and combines their shared secrets with
+/// `Kdf`, so the result is secure as long as either one is. This is a PQ/T hybrid when one
+/// component is post-quantum and the other is traditional, e.g., X25519 + ML-KEM, but any two
+/// KEMs implementing `Kem` can be combined, e.g., K-256 + X25519.
+///
+/// Public keys, private keys, and encapsulated keys are the concatenation of the components', `K1`
+/// first. The shared secret is
+///
+/// ```text
+/// suite_id = concat("KEM", I2OSP(KEM_ID, 2))
+/// hybrid_prk = LabeledExtract("", "hybrid_prk",
+///                             concat(ss1, ss2, enc1, enc2, pkR1, pkR2))
+/// shared_secret = LabeledExpand(hybrid_prk, "shared_secret", "", Nh)
+/// ```
+///
+/// which binds both encapsulated keys and both recipient public keys, as the hybrid KEM drafts
+/// recommend, so it's sound even when a component doesn't bind them itself. Decapsulation
+/// recomputes `pkR1` and `pkR2` from the private key, which costs a little extra.
+///
+/// `KEM_ID` is the identifier this KEM uses in HPKE's `suite_id`s. Pick the one your hybrid is
+/// registered under, or one from the private-use range.
+///
+/// The auth modes work if both components support them. Each component gets its part of the
+/// sender's identity keypair.
+pub struct CombinedKem<K1, K2, Kdf, const KEM_ID: u16>(PhantomData<fn() -> (K1, K2, Kdf)>);
+
+/// The public key of a `CombinedKem`. This is the pair of the components' public keys.
+pub struct CombinedPublicKey<K1: KemTrait, K2: KemTrait>(pub K1::PublicKey, pub K2::PublicKey);
+
+/// The private key of a `CombinedKem`. This is the pair of the components' private keys.
+pub struct CombinedPrivateKey<K1: KemTrait, K2: KemTrait>(pub K1::PrivateKey, pub K2::PrivateKey);
+
+/// The encapsulated key of a `CombinedKem`. This is the pair of the components' encapsulated
+/// keys.
+pub struct CombinedEncappedKey<K1: KemTrait, K2: KemTrait>(
+    pub K1::EncappedKey,
+    pub K2::EncappedKey,
+);
+
+// Implements Clone, Serializable, and Deserializable for a pair of component values, where
+// $field is the name of the associated type of Kem
+macro_rules! impl_pair_encoding {
+    ($pair:ident, $field:ident, $size:ident) => {
+        impl<K1: KemTrait, K2: KemTrait> Clone for $pair<K1, K2> {
+            fn clone(&self) -> Self {
+                $pair(self.0.clone(), self.1.clone())
+            }
+        }
+
+        impl<K1: KemTrait, K2: KemTrait> Serializable for $pair<K1, K2>
+        where
+            $size<K1>: AddLength<$size<K2>>,
+        {
+            type OutputSize = SumSize<$size<K1>, $size<K2>>;
+
+            fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
+                let mut out = GenericArray::default();
+                let (first, second) = out.split_at_mut(<K1::$field as Serializable>::size());
+                first.copy_from_slice(&self.0.to_bytes());
+                second.copy_from_slice(&self.1.to_bytes());
+                out
+            }
+        }
+
+        impl<K1: KemTrait, K2: KemTrait> Deserializable for $pair<K1, K2>
+        where
+            $size<K1>: AddLength<$size<K2>>,
+        {
+            fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+                if encoded.len() != Self::size() {
+                    return Err(HpkeError::IncorrectInputLength(Self::size(), encoded.len()));
+                }
+                let (first, second) = encoded.split_at(<K1::$field as Serializable>::size());
+                Ok($pair(
+                    K1::$field::from_bytes(first)?,
+                    K2::$field::from_bytes(second)?,
+                ))
+            }
+        }
+    };
+}
+
+impl_pair_encoding!(CombinedPublicKey, PublicKey, PkSize);
+impl_pair_encoding!(CombinedPrivateKey, PrivateKey, SkSize);
+impl_pair_encoding!(CombinedEncappedKey, EncappedKey, EncSize);
+
+impl<K1: KemTrait, K2: KemTrait> ConstantTimeEq for CombinedPrivateKey<K1, K2> {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0) & self.1.ct_eq(&other.1)
+    }
+}
+
+// Both components zeroize themselves on drop
+impl<K1: KemTrait, K2: KemTrait> ZeroizeOnDrop for CombinedPrivateKey<K1, K2> {}
+
+impl<K1, K2, Kdf, const KEM_ID: u16> CombinedKem<K1, K2, Kdf, KEM_ID>
+where
+    K1: KemTrait,
+    K2: KemTrait,
+    Kdf: KdfTrait,
+    PkSize<K1>: AddLength<PkSize<K2>>,
+    SkSize<K1>: AddLength<SkSize<K2>>,
+    EncSize<K1>: AddLength<EncSize<K2>>,
+{
+    /// Splits `ikm` into keying material for each component, labeled with `label1` and `label2`
+    fn split_ikm(
+        ikm: &[u8],
+        label1: &[u8],
+        label2: &[u8],
+    ) -> (GenericArray<u8, SkSize<K1>>, GenericArray<u8, SkSize<K2>>) {
+        let suite_id = kem_suite_id::<Self>();
+        let (_, hkdf_ctx) = labeled_extract::<Kdf>(&[], &suite_id, b"dkp_prk", ikm);
+
+        let mut ikm1 = GenericArray::<u8, SkSize<K1>>::default();
+        let mut ikm2 = GenericArray::<u8, SkSize<K2>>::default();
+        hkdf_ctx
+            .labeled_expand(&suite_id, label1, b"", &mut ikm1)
+            .expect("component private key is too big for the KDF");
+        hkdf_ctx
+            .labeled_expand(&suite_id, label2, b"", &mut ikm2)
+            .expect("component private key is too big for the KDF");
+        (ikm1, ikm2)
+    }
+
+    // hybrid_prk = LabeledExtract("", "hybrid_prk",
+    //                             concat(ss1, ss2, enc1, enc2, pkR1, pkR2))
+    // shared_secret = LabeledExpand(hybrid_prk, "shared_secret", "", Nh)
+
+    /// Combines the components' shared secrets into this KEM's shared secret
+    fn combine(
+        ss1: &SharedSecret<K1>,
+        ss2: &SharedSecret<K2>,
+        encapped_key: &CombinedEncappedKey<K1, K2>,
+        pk_recip: &CombinedPublicKey<K1, K2>,
+    ) -> SharedSecret<Self> {
+        let suite_id = kem_suite_id::<Self>();
+        let (enc1, enc2) = (encapped_key.0.to_bytes(), encapped_key.1.to_bytes());
+        let (pk1, pk2) = (pk_recip.0.to_bytes(), pk_recip.1.to_bytes());
+        let (_, hkdf_ctx) = labeled_extract_multi::<Kdf>(
+            &[],
+            &suite_id,
+            b"hybrid_prk",
+            &[ss1.as_bytes(), ss2.as_bytes(), &enc1, &enc2, &pk1, &pk2],
+        );
+
+        let mut shared_secret = SharedSecret::<Self>::default();
+        hkdf_ctx
+            .labeled_expand(&suite_id, b"shared_secret", b"", &mut shared_secret.0)
+            .expect("shared secret is way too big");
+        shared_secret
+    }
+}
+
+impl<K1, K2, Kdf, const KEM_ID: u16> KemTrait for CombinedKem<K1, K2, Kdf, KEM_ID>
+where
+    K1: KemTrait,
+    K2: KemTrait,
+    Kdf: KdfTrait,
+    PkSize<K1>: AddLength<PkSize<K2>>,
+    SkSize<K1>: AddLength<SkSize<K2>>,
+    EncSize<K1>: AddLength<EncSize<K2>>,
+{
+    type PublicKey = CombinedPublicKey<K1, K2>;
+    type PrivateKey = CombinedPrivateKey<K1, K2>;
+    type EncappedKey = CombinedEncappedKey<K1, K2>;
+    type NSecret = <Kdf::HashImpl as OutputSizeUser>::OutputSize;
+    #[doc(hidden)]
+    type PublicKeyTable = (K1::PublicKeyTable, K2::PublicKeyTable);
+
+    const KEM_ID: u16 = KEM_ID;
+
+    // Each component derives its keypair from its own share of ikm, via
+    //   dkp_prk = LabeledExtract("", "dkp_prk", ikm)
+    //   ikm1 = LabeledExpand(dkp_prk, "ikm1", "", Nsk1)
+    //   ikm2 = LabeledExpand(dkp_prk, "ikm2", "", Nsk2)
+    fn try_derive_keypair(ikm: &[u8]) -> Result<(Self::PrivateKey, Self::PublicKey), HpkeError> {
+        let (mut ikm1, mut ikm2) = Self::split_ikm(ikm, b"ikm1", b"ikm2");
+        let res1 = K1::try_derive_keypair(&ikm1);
+        let res2 = K2::try_derive_keypair(&ikm2);
+        ikm1.zeroize();
+        ikm2.zeroize();
+        let ((sk1, pk1), (sk2, pk2)) = (res1?, res2?);
+
+        Ok((CombinedPrivateKey(sk1, sk2), CombinedPublicKey(pk1, pk2)))
+    }
+
+    fn sk_to_pk(sk: &Self::PrivateKey) -> Self::PublicKey {
+        CombinedPublicKey(K1::sk_to_pk(&sk.0), K2::sk_to_pk(&sk.1))
+    }
+
+    fn encap<R: CryptoRng + RngCore + ?Sized>(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        let (ss1, enc1) = K1::encap(
+            &pk_recip.0,
+            sender_id_keypair.map(|(sk, pk)| (&sk.0, &pk.0)),
+            csprng,
+        )?;
+        let (ss2, enc2) = K2::encap(
+            &pk_recip.1,
+            sender_id_keypair.map(|(sk, pk)| (&sk.1, &pk.1)),
+            csprng,
+        )?;
+
+        let encapped_key = CombinedEncappedKey(enc1, enc2);
+        let shared_secret = Self::combine(&ss1, &ss2, &encapped_key, pk_recip);
+        Ok((shared_secret, encapped_key))
+    }
+
+    fn decap(
+        sk_recip: &Self::PrivateKey,
+        pk_sender_id: Option<&Self::PublicKey>,
+        encapped_key: &Self::EncappedKey,
+    ) -> Result<SharedSecret<Self>, HpkeError> {
+        let ss1 = K1::decap(&sk_recip.0, pk_sender_id.map(|pk| &pk.0), &encapped_key.0)?;
+        let ss2 = K2::decap(&sk_recip.1, pk_sender_id.map(|pk| &pk.1), &encapped_key.1)?;
+
+        let pk_recip = Self::sk_to_pk(sk_recip);
+        Ok(Self::combine(&ss1, &ss2, encapped_key, &pk_recip))
+    }
+
+    #[doc(hidden)]
+    fn precompute_pk(pk_recip: &Self::PublicKey) -> Self::PublicKeyTable {
+        (
+            K1::precompute_pk(&pk_recip.0),
+            K2::precompute_pk(&pk_recip.1),
+        )
+    }
+
+    #[doc(hidden)]
+    fn encap_with_table<R: CryptoRng + RngCore + ?Sized>(
+        pk_recip: &Self::PublicKey,
+        table: &Self::PublicKeyTable,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        let (ss1, enc1) = K1::encap_with_table(
+            &pk_recip.0,
+            &table.0,
+            sender_id_keypair.map(|(sk, pk)| (&sk.0, &pk.0)),
+            csprng,
+        )?;
+        let (ss2, enc2) = K2::encap_with_table(
+            &pk_recip.1,
+            &table.1,
+            sender_id_keypair.map(|(sk, pk)| (&sk.1, &pk.1)),
+            csprng,
+        )?;
+
+        let encapped_key = CombinedEncappedKey(enc1, enc2);
+        let shared_secret = Self::combine(&ss1, &ss2, &encapped_key, pk_recip);
+        Ok((shared_secret, encapped_key))
+    }
+
+    #[doc(hidden)]
+    fn encap_with_ikm(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        ikm_eph: &[u8],
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        let (mut ikm1, mut ikm2) = Self::split_ikm(ikm_eph, b"eph_ikm1", b"eph_ikm2");
+        let res1 = K1::encap_with_ikm(
+            &pk_recip.0,
+            sender_id_keypair.map(|(sk, pk)| (&sk.0, &pk.0)),
+            &ikm1,
+        );
+        let res2 = K2::encap_with_ikm(
+            &pk_recip.1,
+            sender_id_keypair.map(|(sk, pk)| (&sk.1, &pk.1)),
+            &ikm2,
+        );
+        ikm1.zeroize();
+        ikm2.zeroize();
+        let ((ss1, enc1), (ss2, enc2)) = (res1?, res2?);
+
+        let encapped_key = CombinedEncappedKey(enc1, enc2);
+        let shared_secret = Self::combine(&ss1, &ss2, &encapped_key, pk_recip);
+        Ok((shared_secret, encapped_key))
+    }
+
+    #[doc(hidden)]
+    fn encap_with_ephemeral(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        sk_eph: &Self::PrivateKey,
+        pk_eph: &Self::PublicKey,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        let (ss1, enc1) = K1::encap_with_ephemeral(
+            &pk_recip.0,
+            sender_id_keypair.map(|(sk, pk)| (&sk.0, &pk.0)),
+            &sk_eph.0,
+            &pk_eph.0,
+        )?;
+        let (ss2, enc2) = K2::encap_with_ephemeral(
+            &pk_recip.1,
+            sender_id_keypair.map(|(sk, pk)| (&sk.1, &pk.1)),
+            &sk_eph.1,
+            &pk_eph.1,
+        )?;
+
+        let encapped_key = CombinedEncappedKey(enc1, enc2);
+        let shared_secret = Self::combine(&ss1, &ss2, &encapped_key, pk_recip);
+        Ok((shared_secret, encapped_key))
+    }
+
+    fn generate_grease_encapped_key<R: CryptoRng + RngCore + ?Sized>(
+        csprng: &mut R,
+    ) -> Self::EncappedKey {
+        CombinedEncappedKey(
+            K1::generate_grease_encapped_key(csprng),
+            K2::generate_grease_encapped_key(csprng),
+        )
+    }
+}
+
+#[cfg(all(test, feature = "x25519", feature = "p256"))]
+mod tests {
+    use super::CombinedKem;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS},
+        setup_receiver, setup_sender, Deserializable, Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that a combined KEM round-trips through encap/decap and HPKE, that its keys
+    /// serialize, and that breaking either half of the encapped key breaks the shared secret
+    #[test]
+    fn test_combined_kem() {
+        use crate::kem::{DhP256HkdfSha256, X25519HkdfSha256};
+
+        type Kem = CombinedKem<DhP256HkdfSha256, X25519HkdfSha256, HkdfSha256, 0xFF10>;
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        assert_eq!(<Kem as KemTrait>::PublicKey::size(), 65 + 32);
+
+        // derive_keypair is deterministic, and the keys survive a trip through their bytes
+        let (sk1, pk1) = Kem::derive_keypair(b"some ikm");
+        let (sk2, _) = Kem::derive_keypair(b"some ikm");
+        assert_eq!(sk1.to_bytes(), sk2.to_bytes());
+        let sk1 = <Kem as KemTrait>::PrivateKey::from_bytes(&sk1.to_bytes()).unwrap();
+        assert_eq!(Kem::sk_to_pk(&sk1).to_bytes(), pk1.to_bytes());
+
+        // Plain and auth encap/decap agree
+        let (sk_sender, pk_sender) = Kem::gen_keypair(&mut csprng);
+        for sender in [None, Some((&sk_sender, &pk_sender))] {
+            let (ss, encapped_key) = Kem::encap(&pk_recip, sender, &mut csprng).unwrap();
+            let decapped = Kem::decap(&sk_recip, sender.map(|(_, pk)| pk), &encapped_key).unwrap();
+            assert_eq!(ss.0, decapped.0);
+        }
+
+        // Swapping in half of another encapped key changes the shared secret
+        let (ss, encapped_key) = Kem::encap(&pk_recip, None, &mut csprng).unwrap();
+        let (_, other_encapped_key) = Kem::encap(&pk_recip, None, &mut csprng).unwrap();
+        let mut spliced = encapped_key.clone();
+        spliced.1 = other_encapped_key.1;
+        assert_ne!(ss.0, Kem::decap(&sk_recip, None, &spliced).unwrap().0);
+
+        // And it works with HPKE
+        let (encapped_key, mut sender_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+                .unwrap();
+        let encapped_key =
+            <Kem as KemTrait>::EncappedKey::from_bytes(&encapped_key.to_bytes()).unwrap();
+        let mut receiver_ctx =
+            setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"info")
+                .unwrap();
+        let ciphertext = sender_ctx.seal(b"msg", b"").unwrap();
+        assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), b"msg");
+    }
+}
@@ -1,4 +1,609 @@
-/// Defines DHKEM(G, K) given a Diffie-Hellman group G and KDF K
+use crate::{
+    dhkex::{DhError, DhKeyExchange, MAX_PUBKEY_SIZE},
+    kdf::{extract_and_expand, Kdf as KdfTrait},
+    kem::{DeriveTrace, Kem as KemTrait, SharedSecret},
+    util::kem_suite_id,
+    Deserializable, HpkeError, Serializable,
+};
+
+#[cfg(feature = "alloc")]
+use crate::Vec;
+
+use core::marker::PhantomData;
+
+use digest::OutputSizeUser;
+use generic_array::{typenum::Unsigned, GenericArray};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+/// DHKEM(G, K) from RFC 9180 §4.1, for a Diffie-Hellman group `Dh` and a KDF `Kdf`, with the
+/// algorithm identifier `KEM_ID`. `Dh` is one of the groups in [`crate::dhkex`], or any other
+/// implementation of [`DhKeyExchange`](crate::dhkex::DhKeyExchange). The KEMs in RFC 9180 are
+/// aliases of this, e.g., `X25519HkdfSha256` is `DhKem<X25519, HkdfSha256, 0x0020>`.
+///
+/// Other combinations aren't in the IANA registry, so they need an ID of their own, which is
+/// bound into every key derivation. Pick one that no registered KEM uses, and that both ends
+/// agree on, e.g., from `0xFF00` up:
+///
+/// ```
+/// # #[cfg(feature = "x25519")]
+/// # {
+/// use hpke::{dhkex::X25519, kdf::HkdfSha512, kem::DhKem};
+///
+/// /// DHKEM(X25519, HKDF-SHA512), under a private-use ID
+/// type X25519HkdfSha512 = DhKem<X25519, HkdfSha512, 0xFF20>;
+/// # }
+/// ```
+///
+/// Reusing the ID of a registered KEM for a different combination would make this crate's key
+/// derivations disagree with every other implementation of that ID.
+pub struct DhKem<Dh, Kdf, const KEM_ID: u16>(PhantomData<fn() -> (Dh, Kdf)>);
+
+// Convenience types
+type PublicKey<Dh> = <Dh as DhKeyExchange>::PublicKey;
+type PrivateKey<Dh> = <Dh as DhKeyExchange>::PrivateKey;
+type PublicKeyTable<Dh> = <Dh as DhKeyExchange>::PublicKeyTable;
+
+// RFC 9180 §4.1
+// The function parameters pkR and pkS are deserialized public keys, and enc is a serialized
+// public key. Since encapsulated keys are Diffie-Hellman public keys in this KEM algorithm, we use
+// SerializePublicKey() and DeserializePublicKey() to encode and decode them, respectively. Npk
+// equals Nenc.
+
+/// Holds the content of an encapsulated secret. This is what the receiver uses to derive the
+/// shared secret. This just wraps a pubkey, because that's all an encapsulated key is in a DHKEM.
+#[doc(hidden)]
+pub struct DhEncappedKey<Dh: DhKeyExchange>(pub(crate) Dh::PublicKey);
+
+impl<Dh: DhKeyExchange> Clone for DhEncappedKey<Dh> {
+    fn clone(&self) -> Self {
+        DhEncappedKey(self.0.clone())
+    }
+}
+
+// EncappedKeys need to be serializable, since they're gonna be sent over the wire. Underlyingly,
+// they're just DH pubkeys, so we just serialize them the same way
+impl<Dh: DhKeyExchange> Serializable for DhEncappedKey<Dh> {
+    type OutputSize = <Dh::PublicKey as Serializable>::OutputSize;
+
+    // Pass to underlying to_bytes() impl
+    fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
+        self.0.to_bytes()
+    }
+}
+
+impl<Dh: DhKeyExchange> Deserializable for DhEncappedKey<Dh> {
+    // Pass to underlying from_bytes() impl
+    fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        let pubkey = <Dh::PublicKey as Deserializable>::from_bytes(encoded)?;
+        Ok(DhEncappedKey(pubkey))
+    }
+}
+
+// RFC 9180 §4.1
+// def Encap(pkR):
+//   skE, pkE = GenerateKeyPair()
+//   dh = DH(skE, pkR)
+//   enc = SerializePublicKey(pkE)
+//
+//   pkRm = SerializePublicKey(pkR)
+//   kem_context = concat(enc, pkRm)
+//
+// def AuthEncap(pkR, skS):
+//   skE, pkE = GenerateKeyPair()
+//   dh = concat(DH(skE, pkR), DH(skS, pkR))
+//   enc = SerializePublicKey(pkE)
+//
+//   pkRm = SerializePublicKey(pkR)
+//   pkSm = SerializePublicKey(pk(skS))
+//   kem_context = concat(enc, pkRm, pkSm)
+//
+//   shared_secret = ExtractAndExpand(dh, kem_context)
+//   return shared_secret, enc
+
+// The reason we define encap_with_eph() rather than just encap() is because we need to use
+// deterministic ephemeral keys in the known-answer tests. So we define a function here, then use
+// it to impl kem::Kem and kat_tests::TestableKem.
+
+impl<Dh: DhKeyExchange, Kdf: KdfTrait, const KEM_ID: u16> DhKem<Dh, Kdf, KEM_ID> {
+    /// Checks at compile time that `Dh`'s public keys and DH results fit in the `MAX_PUBKEY_SIZE`
+    /// buffers that the KEM context and DH concatenations are built in. Evaluating this turns an
+    /// oversized group into a build error instead of a panic.
+    const SIZES_OK: () = assert!(
+        <<Dh::PublicKey as Serializable>::OutputSize as Unsigned>::USIZE <= MAX_PUBKEY_SIZE
+            && <<Dh::KexResult as Serializable>::OutputSize as Unsigned>::USIZE <= MAX_PUBKEY_SIZE,
+        "DH public keys and results must be at most MAX_PUBKEY_SIZE bytes"
+    );
+
+    /// Derives a shared secret that the owner of the recipient's pubkey can use to derive the
+    /// same shared secret. If `sk_sender_id` is given, the sender's identity will be tied to the
+    /// shared secret.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret and encapped key on success. If an error happened during key
+    /// exchange, returns `Err(HpkeError::EncapError)`.
+    pub(crate) fn encap_with_eph(
+        pk_recip: &PublicKey<Dh>,
+        sender_id_keypair: Option<(&PrivateKey<Dh>, &PublicKey<Dh>)>,
+        sk_eph: PrivateKey<Dh>,
+    ) -> Result<(SharedSecret<Self>, DhEncappedKey<Dh>), HpkeError> {
+        Self::encap_with_eph_using(
+            pk_recip,
+            sender_id_keypair,
+            sk_eph,
+            |sk| Dh::dh(sk, pk_recip),
+            Dh::sk_to_pk,
+        )
+    }
+
+    /// Same as `encap_with_eph`, but does the DH operations using tables precomputed from
+    /// `pk_recip`
+    fn encap_with_eph_and_table(
+        pk_recip: &PublicKey<Dh>,
+        table: &PublicKeyTable<Dh>,
+        sender_id_keypair: Option<(&PrivateKey<Dh>, &PublicKey<Dh>)>,
+        sk_eph: PrivateKey<Dh>,
+    ) -> Result<(SharedSecret<Self>, DhEncappedKey<Dh>), HpkeError> {
+        Self::encap_with_eph_using(
+            pk_recip,
+            sender_id_keypair,
+            sk_eph,
+            |sk| Dh::dh_with_table(sk, table),
+            |sk| Dh::sk_to_pk_with_table(sk, table),
+        )
+    }
+
+    /// The body of `encap_with_eph`. `dh_with_recip(sk)` computes `DH(sk, pkR)`, and
+    /// `sk_to_pk(sk)` computes `pk(sk)`.
+    fn encap_with_eph_using(
+        pk_recip: &PublicKey<Dh>,
+        sender_id_keypair: Option<(&PrivateKey<Dh>, &PublicKey<Dh>)>,
+        sk_eph: PrivateKey<Dh>,
+        dh_with_recip: impl Fn(&PrivateKey<Dh>) -> Result<Dh::KexResult, DhError>,
+        sk_to_pk: impl Fn(&PrivateKey<Dh>) -> PublicKey<Dh>,
+    ) -> Result<(SharedSecret<Self>, DhEncappedKey<Dh>), HpkeError> {
+        let () = Self::SIZES_OK;
+
+        // Put together the binding context used for all KDF operations
+        let suite_id = kem_suite_id::<Self>();
+
+        // Compute the shared secret from the ephemeral inputs
+        let kex_res_eph = dh_with_recip(&sk_eph).map_err(|_| HpkeError::EncapError)?;
+
+        // The encapped key is the ephemeral pubkey
+        let encapped_key = {
+            let pk_eph = sk_to_pk(&sk_eph);
+            DhEncappedKey(pk_eph)
+        };
+
+        // The shared secret is either gonna be kex_res_eph, or that along with another shared
+        // secret that's tied to the sender's identity.
+        let shared_secret = if let Some((sk_sender_id, pk_sender_id)) = sender_id_keypair {
+            // kem_context = encapped_key || pk_recip || pk_sender_id
+            // We concat without allocation by making a buffer of the maximum possible size, then
+            // taking the appropriately sized slice.
+            let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
+                MAX_PUBKEY_SIZE,
+                &encapped_key.to_bytes(),
+                &pk_recip.to_bytes(),
+                &pk_sender_id.to_bytes()
+            );
+            let kem_context = &kem_context_buf[..kem_context_size];
+
+            // We want to do an authed encap. Do a DH exchange between the sender identity secret
+            // key and the recipient's pubkey
+            let kex_res_identity =
+                dh_with_recip(sk_sender_id).map_err(|_| HpkeError::EncapError)?;
+
+            // concatted_secrets = kex_res_eph || kex_res_identity
+            // Same no-alloc concat trick as above
+            let (concatted_secrets_buf, concatted_secret_size) = concat_with_known_maxlen!(
+                MAX_PUBKEY_SIZE,
+                &kex_res_eph.to_bytes(),
+                &kex_res_identity.to_bytes()
+            );
+            let concatted_secrets = &concatted_secrets_buf[..concatted_secret_size];
+
+            // The "authed shared secret" is derived from the KEX of the ephemeral input with the
+            // recipient pubkey, and the KEX of the identity input with the recipient pubkey. The
+            // HKDF-Expand call only errors if the output values are 255x the digest size of the
+            // hash function. Since these values are fixed at compile time, we don't worry about
+            // it.
+            let mut buf = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(concatted_secrets, &suite_id, kem_context, &mut buf.0)
+                .expect("shared secret is way too big");
+            buf
+        } else {
+            // kem_context = encapped_key || pk_recip
+            // We concat without allocation by making a buffer of the maximum possible size, then
+            // taking the appropriately sized slice.
+            let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
+                MAX_PUBKEY_SIZE,
+                &encapped_key.to_bytes(),
+                &pk_recip.to_bytes()
+            );
+            let kem_context = &kem_context_buf[..kem_context_size];
+
+            // The "unauthed shared secret" is derived from just the KEX of the ephemeral input
+            // with the recipient pubkey. The HKDF-Expand call only errors if the output values
+            // are 255x the digest size of the hash function. Since these values are fixed at
+            // compile time, we don't worry about it.
+            let mut buf = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(&kex_res_eph.to_bytes(), &suite_id, kem_context, &mut buf.0)
+                .expect("shared secret is way too big");
+            buf
+        };
+
+        Ok((shared_secret, encapped_key))
+    }
+
+    /// Derives a shared secret given the encapsulated key, the recipient's secret key, and the
+    /// precomputed values for that secret key. If the precomputation includes a sender identity,
+    /// the sender's identity will be tied to the shared secret.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret on success. If an error happened during key exchange, returns
+    /// `Err(HpkeError::DecapError)`.
+    fn decap_with_precomputation(
+        sk_recip: &PrivateKey<Dh>,
+        precomp: &DecapPrecomputation<'_, Dh>,
+        encapped_key: &DhEncappedKey<Dh>,
+    ) -> Result<SharedSecret<Self>, HpkeError> {
+        let () = Self::SIZES_OK;
+
+        // Put together the binding context used for all KDF operations
+        let suite_id = kem_suite_id::<Self>();
+
+        // Compute the shared secret from the ephemeral inputs
+        let kex_res_eph = Dh::dh(sk_recip, &encapped_key.0).map_err(|_| HpkeError::DecapError)?;
+
+        let pk_recip = &precomp.pk_recip;
+
+        // The shared secret is either gonna be kex_res_eph, or that along with another shared
+        // secret that's tied to the sender's identity.
+        if let Some((pk_sender_id, kex_res_identity)) = &precomp.sender_id {
+            // kem_context = encapped_key || pk_recip || pk_sender_id We concat without allocation
+            // by making a buffer of the maximum possible size, then taking the appropriately sized
+            // slice.
+            let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
+                MAX_PUBKEY_SIZE,
+                &encapped_key.to_bytes(),
+                &pk_recip.to_bytes(),
+                &pk_sender_id.to_bytes()
+            );
+            let kem_context = &kem_context_buf[..kem_context_size];
+
+            // concatted_secrets = kex_res_eph || kex_res_identity
+            // Same no-alloc concat trick as above
+            let (concatted_secrets_buf, concatted_secret_size) = concat_with_known_maxlen!(
+                MAX_PUBKEY_SIZE,
+                &kex_res_eph.to_bytes(),
+                &kex_res_identity.to_bytes()
+            );
+            let concatted_secrets = &concatted_secrets_buf[..concatted_secret_size];
+
+            // The "authed shared secret" is derived from the KEX of the ephemeral input with the
+            // recipient pubkey, and the kex of the identity input with the recipient pubkey. The
+            // HKDF-Expand call only errors if the output values are 255x the digest size of the
+            // hash function. Since these values are fixed at compile time, we don't worry about
+            // it.
+            let mut shared_secret = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(
+                concatted_secrets,
+                &suite_id,
+                kem_context,
+                &mut shared_secret.0,
+            )
+            .expect("shared secret is way too big");
+            Ok(shared_secret)
+        } else {
+            // kem_context = encapped_key || pk_recip
+            // We concat without allocation by making a buffer of the maximum possible size, then
+            // taking the appropriately sized slice.
+            let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
+                MAX_PUBKEY_SIZE,
+                &encapped_key.to_bytes(),
+                &pk_recip.to_bytes()
+            );
+            let kem_context = &kem_context_buf[..kem_context_size];
+
+            // The "unauthed shared secret" is derived from just the KEX of the ephemeral input
+            // with the recipient pubkey. The HKDF-Expand call only errors if the output values
+            // are 255x the digest size of the hash function. Since these values are fixed at
+            // compile time, we don't worry about it.
+            let mut shared_secret = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(
+                &kex_res_eph.to_bytes(),
+                &suite_id,
+                kem_context,
+                &mut shared_secret.0,
+            )
+            .expect("shared secret is way too big");
+            Ok(shared_secret)
+        }
+    }
+}
+
+// RFC 9180 §4.1
+// def Decap(enc, skR):
+//   pkE = DeserializePublicKey(enc)
+//   dh = DH(skR, pkE)
+//
+//   pkRm = SerializePublicKey(pk(skR))
+//   kem_context = concat(enc, pkRm)
+//
+//   shared_secret = ExtractAndExpand(dh, kem_context)
+//   return shared_secret
+//
+// def AuthDecap(enc, skR, pkS):
+//   pkE = DeserializePublicKey(enc)
+//   dh = concat(DH(skR, pkE), DH(skR, pkS))
+//
+//   pkRm = SerializePublicKey(pk(skR))
+//   pkSm = SerializePublicKey(pkS)
+//   kem_context = concat(enc, pkRm, pkSm)
+//
+//   shared_secret = ExtractAndExpand(dh, kem_context)
+//   return shared_secret
+
+/// The parts of Decap and AuthDecap that don't depend on the encapsulated key
+struct DecapPrecomputation<'a, Dh: DhKeyExchange> {
+    /// pk(skR)
+    pk_recip: PublicKey<Dh>,
+    /// In AuthDecap, pkS and DH(skR, pkS)
+    sender_id: Option<(&'a PublicKey<Dh>, Dh::KexResult)>,
+}
+
+impl<'a, Dh: DhKeyExchange> DecapPrecomputation<'a, Dh> {
+    /// Computes everything in Decap/AuthDecap that only depends on the recipient's secret key
+    /// and, if given, the sender's identity pubkey
+    fn new(
+        sk_recip: &PrivateKey<Dh>,
+        pk_sender_id: Option<&'a PublicKey<Dh>>,
+    ) -> Result<Self, HpkeError> {
+        // Compute the recipient's pubkey from their privkey
+        let pk_recip = Dh::sk_to_pk(sk_recip);
+
+        // We want to do an authed decap. Do a DH exchange between the recipient's secret key and
+        // the sender's identity pubkey
+        let sender_id = match pk_sender_id {
+            Some(pk) => {
+                let kex_res_identity = Dh::dh(sk_recip, pk).map_err(|_| HpkeError::DecapError)?;
+                Some((pk, kex_res_identity))
+            }
+            None => None,
+        };
+
+        Ok(DecapPrecomputation {
+            pk_recip,
+            sender_id,
+        })
+    }
+}
+
+impl<Dh: DhKeyExchange, Kdf: KdfTrait, const KEM_ID: u16> KemTrait for DhKem<Dh, Kdf, KEM_ID> {
+    // RFC 9180 §4.1
+    // For the variants of DHKEM defined in this document, the size Nsecret of the KEM shared
+    // secret is equal to the output length of the hash function underlying the KDF.
+
+    /// The size of the shared secret at the end of the key exchange process
+    #[doc(hidden)]
+    type NSecret = <Kdf::HashImpl as OutputSizeUser>::OutputSize;
+
+    type PublicKey = PublicKey<Dh>;
+    type PrivateKey = PrivateKey<Dh>;
+    #[doc(hidden)]
+    type PublicKeyTable = PublicKeyTable<Dh>;
+    type EncappedKey = DhEncappedKey<Dh>;
+
+    const KEM_ID: u16 = KEM_ID;
+
+    /// Deterministically derives a keypair from the given input keying material
+    ///
+    /// Requirements
+    /// ============
+    /// This keying material SHOULD have as many bits of entropy as the bit length of a secret
+    /// key, i.e., `8 * Self::PrivateKey::size()`. For X25519 and P-256, this is 256 bits of
+    /// entropy.
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns the keypair. If DeriveKeyPair rejected every candidate private key,
+    /// returns `Err(HpkeError::KeyDerivation)`.
+    fn try_derive_keypair(ikm: &[u8]) -> Result<(Self::PrivateKey, Self::PublicKey), HpkeError> {
+        let suite_id = kem_suite_id::<Self>();
+        Dh::derive_keypair::<Kdf>(&suite_id, ikm)
+    }
+
+    // Same as above, but keeps the counter
+    fn derive_keypair_verbose(
+        ikm: &[u8],
+    ) -> Result<(Self::PrivateKey, Self::PublicKey, DeriveTrace), HpkeError> {
+        let suite_id = kem_suite_id::<Self>();
+        let (sk, pk, counter) = Dh::derive_keypair_with_counter::<Kdf>(&suite_id, ikm)?;
+        let trace = DeriveTrace {
+            kem_id: Self::KEM_ID,
+            counter,
+        };
+        Ok((sk, pk, trace))
+    }
+
+    // Pass to the underlying DH group
+    fn sk_to_pk(sk: &Self::PrivateKey) -> Self::PublicKey {
+        Dh::sk_to_pk(sk)
+    }
+
+    // Runs encap_with_eph using a random ephemeral key
+    fn encap<R: CryptoRng + RngCore + ?Sized>(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        // Generate a new ephemeral key
+        let (sk_eph, _) = Self::try_gen_keypair(csprng)?;
+        // Now pass to encap_with_eph()
+        Self::encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
+    }
+
+    // Samples all the ephemeral keys up front, on this thread, so the RNG is only ever used
+    // sequentially. The DH operations don't touch the RNG, so with the parallel feature they're
+    // spread across the rayon pool.
+    #[cfg(feature = "alloc")]
+    #[doc(hidden)]
+    fn encap_batch<R: CryptoRng + RngCore + ?Sized>(
+        pk_recips: &[Self::PublicKey],
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        csprng: &mut R,
+    ) -> Result<Vec<(SharedSecret<Self>, Self::EncappedKey)>, HpkeError> {
+        let sk_ephs = pk_recips
+            .iter()
+            .map(|_| Self::try_gen_keypair(csprng).map(|(sk, _)| sk))
+            .collect::<Result<Vec<Self::PrivateKey>, HpkeError>>()?;
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            pk_recips
+                .par_iter()
+                .zip(sk_ephs)
+                .map(|(pk, sk_eph)| Self::encap_with_eph(pk, sender_id_keypair, sk_eph))
+                .collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            pk_recips
+                .iter()
+                .zip(sk_ephs)
+                .map(|(pk, sk_eph)| Self::encap_with_eph(pk, sender_id_keypair, sk_eph))
+                .collect()
+        }
+    }
+
+    // Pass to the underlying DH group
+    #[doc(hidden)]
+    fn precompute_pk(pk_recip: &Self::PublicKey) -> Self::PublicKeyTable {
+        Dh::precompute(pk_recip)
+    }
+
+    // Runs encap_with_eph_and_table using a random ephemeral key
+    #[doc(hidden)]
+    fn encap_with_table<R: CryptoRng + RngCore + ?Sized>(
+        pk_recip: &Self::PublicKey,
+        table: &Self::PublicKeyTable,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        // Sample the ephemeral private key directly. gen_keypair() would also compute its pubkey
+        // without the table, which is the cost we're trying to avoid. The rejection loop almost
+        // never runs more than once.
+        let sk_eph = loop {
+            let mut buf =
+                GenericArray::<u8, <Self::PrivateKey as Serializable>::OutputSize>::default();
+            csprng.fill_bytes(&mut buf);
+            let res = Self::PrivateKey::from_bytes(&buf);
+            buf.zeroize();
+            if let Ok(sk) = res {
+                break sk;
+            }
+        };
+        // Now pass to encap_with_eph_and_table()
+        Self::encap_with_eph_and_table(pk_recip, table, sender_id_keypair, sk_eph)
+    }
+
+    // Runs encap_with_eph using an ephemeral key derived from the given IKM
+    fn encap_with_ikm(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        ikm_eph: &[u8],
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        // Derive the ephemeral key. This is the DeriveKeyPair(ikmE) step that the RFC 9180 test
+        // vectors use
+        let (sk_eph, _) = Self::try_derive_keypair(ikm_eph)?;
+        // Now pass to encap_with_eph()
+        Self::encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
+    }
+
+    // Runs encap_with_eph, then checks that the encapped key it computed is pk_eph
+    fn encap_with_ephemeral(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
+        sk_eph: &Self::PrivateKey,
+        pk_eph: &Self::PublicKey,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
+        let (shared_secret, encapped_key) =
+            Self::encap_with_eph(pk_recip, sender_id_keypair, sk_eph.clone())?;
+        // The encapped key is pk(sk_eph), which encap_with_eph computes anyway, so checking the
+        // keypair is free. Public keys aren't secret, so this needn't be constant-time.
+        if encapped_key.0.to_bytes() != pk_eph.to_bytes() {
+            return Err(HpkeError::EncapError);
+        }
+        Ok((shared_secret, encapped_key))
+    }
+
+    // The encapped key is just an ephemeral public key
+    fn generate_grease_encapped_key<R: CryptoRng + RngCore + ?Sized>(
+        csprng: &mut R,
+    ) -> Self::EncappedKey {
+        let (_, pk) = Self::gen_keypair(csprng);
+        DhEncappedKey(pk)
+    }
+
+    /// Derives a shared secret given the encapsulated key and the recipients secret key. If
+    /// `pk_sender_id` is given, the sender's identity will be tied to the shared secret.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret on success. If an error happened during key exchange, returns
+    /// `Err(HpkeError::DecapError)`.
+    #[doc(hidden)]
+    fn decap(
+        sk_recip: &Self::PrivateKey,
+        pk_sender_id: Option<&Self::PublicKey>,
+        encapped_key: &Self::EncappedKey,
+    ) -> Result<SharedSecret<Self>, HpkeError> {
+        let precomp = DecapPrecomputation::new(sk_recip, pk_sender_id)?;
+        Self::decap_with_precomputation(sk_recip, &precomp, encapped_key)
+    }
+
+    // The pubkey of sk_recip, and the identity DH if there is one, are the same for every encapped
+    // key. So compute them once, then do only the per-key work for each one. This roughly halves
+    // the cost of a Base mode decap, and takes it to a third for Auth mode.
+    #[cfg(feature = "alloc")]
+    #[doc(hidden)]
+    fn decap_batch(
+        sk_recip: &Self::PrivateKey,
+        pk_sender_id: Option<&Self::PublicKey>,
+        encapped_keys: &[Self::EncappedKey],
+    ) -> Vec<Result<SharedSecret<Self>, HpkeError>> {
+        let precomp = match DecapPrecomputation::new(sk_recip, pk_sender_id) {
+            Ok(p) => p,
+            // If the identity DH failed, every decap would fail the same way
+            Err(e) => return encapped_keys.iter().map(|_| Err(e)).collect(),
+        };
+
+        // With the parallel feature, spread the per-key work across the rayon pool. Results come
+        // back in the same order as the input either way.
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            encapped_keys
+                .par_iter()
+                .map(|ek| Self::decap_with_precomputation(sk_recip, &precomp, ek))
+                .collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            encapped_keys
+                .iter()
+                .map(|ek| Self::decap_with_precomputation(sk_recip, &precomp, ek))
+                .collect()
+        }
+    }
+}
+
+/// Names DHKEM(G, K) for a Diffie-Hellman group G and KDF K from RFC 9180
 macro_rules! impl_dhkem {
     (
         $mod_name:ident,
@@ -8,337 +613,14 @@ macro_rules! impl_dhkem {
         $kem_id:literal,
         $doc_str:expr
     ) => {
-
-        // Export everything from the crate we define
+        // Export everything from the module we define
         pub use $mod_name::*;
 
         pub(crate) mod $mod_name {
-            use crate::{
-                dhkex::{DhKeyExchange, MAX_PUBKEY_SIZE},
-                kdf::{extract_and_expand, Kdf as KdfTrait},
-                kem::{Kem as KemTrait, SharedSecret},
-                util::kem_suite_id,
-                Deserializable, HpkeError, Serializable,
-            };
-
-            use digest::OutputSizeUser;
-            use generic_array::GenericArray;
-            use rand_core::{CryptoRng, RngCore};
-
-            // Define convenience types
-            type PublicKey = <$dhkex as DhKeyExchange>::PublicKey;
-            type PrivateKey = <$dhkex as DhKeyExchange>::PrivateKey;
-
-            // RFC 9180 §4.1
-            // The function parameters pkR and pkS are deserialized public keys, and enc is a
-            // serialized public key. Since encapsulated keys are Diffie-Hellman public keys in
-            // this KEM algorithm, we use SerializePublicKey() and DeserializePublicKey() to
-            // encode and decode them, respectively. Npk equals Nenc.
-
-            /// Holds the content of an encapsulated secret. This is what the receiver uses to
-            /// derive the shared secret. This just wraps a pubkey, because that's all an
-            /// encapsulated key is in a DHKEM.
-            #[doc(hidden)]
-            #[derive(Clone)]
-            pub struct EncappedKey(pub(crate) <$dhkex as DhKeyExchange>::PublicKey);
-
-            // EncappedKeys need to be serializable, since they're gonna be sent over the wire.
-            // Underlyingly, they're just DH pubkeys, so we just serialize them the same way
-            impl Serializable for EncappedKey {
-                type OutputSize = <<$dhkex as DhKeyExchange>::PublicKey as Serializable>::OutputSize;
-
-                // Pass to underlying to_bytes() impl
-                fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
-                    self.0.to_bytes()
-                }
-            }
-
-            impl Deserializable for EncappedKey {
-                // Pass to underlying from_bytes() impl
-                fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
-                    let pubkey =
-                        <<$dhkex as DhKeyExchange>::PublicKey as Deserializable>::from_bytes(encoded)?;
-                    Ok(EncappedKey(pubkey))
-                }
-            }
+            use super::DhKem;
 
-            // Define the KEM struct
             #[doc = $doc_str]
-            pub struct $kem_name;
-
-            // RFC 9180 §4.1
-            // def Encap(pkR):
-            //   skE, pkE = GenerateKeyPair()
-            //   dh = DH(skE, pkR)
-            //   enc = SerializePublicKey(pkE)
-            //
-            //   pkRm = SerializePublicKey(pkR)
-            //   kem_context = concat(enc, pkRm)
-            //
-            // def AuthEncap(pkR, skS):
-            //   skE, pkE = GenerateKeyPair()
-            //   dh = concat(DH(skE, pkR), DH(skS, pkR))
-            //   enc = SerializePublicKey(pkE)
-            //
-            //   pkRm = SerializePublicKey(pkR)
-            //   pkSm = SerializePublicKey(pk(skS))
-            //   kem_context = concat(enc, pkRm, pkSm)
-            //
-            //   shared_secret = ExtractAndExpand(dh, kem_context)
-            //   return shared_secret, enc
-
-            // The reason we define encap_with_eph() rather than just encap() is because we need to
-            // use deterministic ephemeral keys in the known-answer tests. So we define a function
-            // here, then use it to impl kem::Kem and kat_tests::TestableKem.
-
-            /// Derives a shared secret that the owner of the recipient's pubkey can use to derive
-            /// the same shared secret. If `sk_sender_id` is given, the sender's identity will be
-            /// tied to the shared secret.
-            ///
-            /// Return Value
-            /// ============
-            /// Returns a shared secret and encapped key on success. If an error happened during
-            /// key exchange, returns `Err(HpkeError::EncapError)`.
-            #[doc(hidden)]
-            pub(crate) fn encap_with_eph(
-                pk_recip: &PublicKey,
-                sender_id_keypair: Option<(&PrivateKey, &PublicKey)>,
-                sk_eph: PrivateKey,
-            ) -> Result<(SharedSecret<$kem_name>, EncappedKey), HpkeError> {
-                // Put together the binding context used for all KDF operations
-                let suite_id = kem_suite_id::<$kem_name>();
-
-                // Compute the shared secret from the ephemeral inputs
-                let kex_res_eph = <$dhkex as DhKeyExchange>::dh(&sk_eph, pk_recip)
-                    .map_err(|_| HpkeError::EncapError)?;
-
-                // The encapped key is the ephemeral pubkey
-                let encapped_key = {
-                    let pk_eph = <$dhkex as DhKeyExchange>::sk_to_pk(&sk_eph);
-                    EncappedKey(pk_eph)
-                };
-
-                // The shared secret is either gonna be kex_res_eph, or that along with another
-                // shared secret that's tied to the sender's identity.
-                let shared_secret = if let Some((sk_sender_id, pk_sender_id)) = sender_id_keypair {
-                    // kem_context = encapped_key || pk_recip || pk_sender_id
-                    // We concat without allocation by making a buffer of the maximum possible
-                    // size, then taking the appropriately sized slice.
-                    let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-                        MAX_PUBKEY_SIZE,
-                        &encapped_key.to_bytes(),
-                        &pk_recip.to_bytes(),
-                        &pk_sender_id.to_bytes()
-                    );
-                    let kem_context = &kem_context_buf[..kem_context_size];
-
-                    // We want to do an authed encap. Do a DH exchange between the sender identity
-                    // secret key and the recipient's pubkey
-                    let kex_res_identity = <$dhkex as DhKeyExchange>::dh(sk_sender_id, pk_recip)
-                        .map_err(|_| HpkeError::EncapError)?;
-
-                    // concatted_secrets = kex_res_eph || kex_res_identity
-                    // Same no-alloc concat trick as above
-                    let (concatted_secrets_buf, concatted_secret_size) = concat_with_known_maxlen!(
-                        MAX_PUBKEY_SIZE,
-                        &kex_res_eph.to_bytes(),
-                        &kex_res_identity.to_bytes()
-                    );
-                    let concatted_secrets = &concatted_secrets_buf[..concatted_secret_size];
-
-                    // The "authed shared secret" is derived from the KEX of the ephemeral input
-                    // with the recipient pubkey, and the KEX of the identity input with the
-                    // recipient pubkey. The HKDF-Expand call only errors if the output values are
-                    // 255x the digest size of the hash function. Since these values are fixed at
-                    // compile time, we don't worry about it.
-                    let mut buf = <SharedSecret<$kem_name> as Default>::default();
-                    extract_and_expand::<$kdf>(concatted_secrets, &suite_id, kem_context, &mut buf.0)
-                        .expect("shared secret is way too big");
-                    buf
-                } else {
-                    // kem_context = encapped_key || pk_recip
-                    // We concat without allocation by making a buffer of the maximum possible
-                    // size, then taking the appropriately sized slice.
-                    let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-                        MAX_PUBKEY_SIZE,
-                        &encapped_key.to_bytes(),
-                        &pk_recip.to_bytes()
-                    );
-                    let kem_context = &kem_context_buf[..kem_context_size];
-
-                    // The "unauthed shared secret" is derived from just the KEX of the ephemeral
-                    // input with the recipient pubkey. The HKDF-Expand call only errors if the
-                    // output values are 255x the digest size of the hash function. Since these
-                    // values are fixed at compile time, we don't worry about it.
-                    let mut buf = <SharedSecret<$kem_name> as Default>::default();
-                    extract_and_expand::<$kdf>(
-                        &kex_res_eph.to_bytes(),
-                        &suite_id,
-                        kem_context,
-                        &mut buf.0,
-                    )
-                    .expect("shared secret is way too big");
-                    buf
-                };
-
-                Ok((shared_secret, encapped_key))
-            }
-
-            impl KemTrait for $kem_name {
-                // RFC 9180 §4.1
-                // For the variants of DHKEM defined in this document, the size Nsecret of the
-                // KEM shared secret is equal to the output length of the hash function underlying
-                // the KDF.
-
-                /// The size of the shared secret at the end of the key exchange process
-                #[doc(hidden)]
-                type NSecret = <<$kdf as KdfTrait>::HashImpl as OutputSizeUser>::OutputSize;
-
-                type PublicKey = PublicKey;
-                type PrivateKey = PrivateKey;
-                type EncappedKey = EncappedKey;
-
-                const KEM_ID: u16 = $kem_id;
-
-                /// Deterministically derives a keypair from the given input keying material
-                ///
-                /// Requirements
-                /// ============
-                /// This keying material SHOULD have as many bits of entropy as the bit length of a
-                /// secret key, i.e., `8 * Self::PrivateKey::size()`. For X25519 and P-256, this is
-                /// 256 bits of entropy.
-                fn derive_keypair(ikm: &[u8]) -> (Self::PrivateKey, Self::PublicKey) {
-                    let suite_id = kem_suite_id::<Self>();
-                    <$dhkex as DhKeyExchange>::derive_keypair::<$kdf>(&suite_id, ikm)
-                }
-
-                // Runs encap_with_eph using a random ephemeral key
-                fn encap<R: CryptoRng + RngCore>(
-                    pk_recip: &Self::PublicKey,
-                    sender_id_keypair: Option<(&Self::PrivateKey, &Self::PublicKey)>,
-                    csprng: &mut R,
-                ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError> {
-                    // Generate a new ephemeral key
-                    let (sk_eph, _) = Self::gen_keypair(csprng);
-                    // Now pass to encap_with_eph()
-                    encap_with_eph(pk_recip, sender_id_keypair, sk_eph)
-                }
-
-                // RFC 9180 §4.1
-                // def Decap(enc, skR):
-                //   pkE = DeserializePublicKey(enc)
-                //   dh = DH(skR, pkE)
-                //
-                //   pkRm = SerializePublicKey(pk(skR))
-                //   kem_context = concat(enc, pkRm)
-                //
-                //   shared_secret = ExtractAndExpand(dh, kem_context)
-                //   return shared_secret
-                //
-                // def AuthDecap(enc, skR, pkS):
-                //   pkE = DeserializePublicKey(enc)
-                //   dh = concat(DH(skR, pkE), DH(skR, pkS))
-                //
-                //   pkRm = SerializePublicKey(pk(skR))
-                //   pkSm = SerializePublicKey(pkS)
-                //   kem_context = concat(enc, pkRm, pkSm)
-                //
-                //   shared_secret = ExtractAndExpand(dh, kem_context)
-                //   return shared_secret
-
-                /// Derives a shared secret given the encapsulated key and the recipients secret key.
-                /// If `pk_sender_id` is given, the sender's identity will be tied to the shared
-                /// secret.
-                ///
-                /// Return Value
-                /// ============
-                /// Returns a shared secret on success. If an error happened during key exchange,
-                /// returns `Err(HpkeError::DecapError)`.
-                #[doc(hidden)]
-                fn decap(
-                    sk_recip: &Self::PrivateKey,
-                    pk_sender_id: Option<&Self::PublicKey>,
-                    encapped_key: &Self::EncappedKey,
-                ) -> Result<SharedSecret<Self>, HpkeError> {
-                    // Put together the binding context used for all KDF operations
-                    let suite_id = kem_suite_id::<Self>();
-
-                    // Compute the shared secret from the ephemeral inputs
-                    let kex_res_eph = <$dhkex as DhKeyExchange>::dh(sk_recip, &encapped_key.0)
-                        .map_err(|_| HpkeError::DecapError)?;
-
-                    // Compute the sender's pubkey from their privkey
-                    let pk_recip = <$dhkex as DhKeyExchange>::sk_to_pk(sk_recip);
-
-                    // The shared secret is either gonna be kex_res_eph, or that along with another
-                    // shared secret that's tied to the sender's identity.
-                    if let Some(pk_sender_id) = pk_sender_id {
-                        // kem_context = encapped_key || pk_recip || pk_sender_id We concat without
-                        // allocation by making a buffer of the maximum possible size, then taking the
-                        // appropriately sized slice.
-                        let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-                            MAX_PUBKEY_SIZE,
-                            &encapped_key.to_bytes(),
-                            &pk_recip.to_bytes(),
-                            &pk_sender_id.to_bytes()
-                        );
-                        let kem_context = &kem_context_buf[..kem_context_size];
-
-                        // We want to do an authed encap. Do a DH exchange between the sender identity
-                        // secret key and the recipient's pubkey
-                        let kex_res_identity = <$dhkex as DhKeyExchange>::dh(sk_recip, pk_sender_id)
-                            .map_err(|_| HpkeError::DecapError)?;
-
-                        // concatted_secrets = kex_res_eph || kex_res_identity
-                        // Same no-alloc concat trick as above
-                        let (concatted_secrets_buf, concatted_secret_size) = concat_with_known_maxlen!(
-                            MAX_PUBKEY_SIZE,
-                            &kex_res_eph.to_bytes(),
-                            &kex_res_identity.to_bytes()
-                        );
-                        let concatted_secrets = &concatted_secrets_buf[..concatted_secret_size];
-
-                        // The "authed shared secret" is derived from the KEX of the ephemeral input
-                        // with the recipient pubkey, and the kex of the identity input with the
-                        // recipient pubkey. The HKDF-Expand call only errors if the output values are
-                        // 255x the digest size of the hash function. Since these values are fixed at
-                        // compile time, we don't worry about it.
-                        let mut shared_secret = <SharedSecret<Self> as Default>::default();
-                        extract_and_expand::<$kdf>(
-                            concatted_secrets,
-                            &suite_id,
-                            kem_context,
-                            &mut shared_secret.0,
-                        )
-                        .expect("shared secret is way too big");
-                        Ok(shared_secret)
-                    } else {
-                        // kem_context = encapped_key || pk_recip || pk_sender_id
-                        // We concat without allocation by making a buffer of the maximum possible
-                        // size, then taking the appropriately sized slice.
-                        let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-                            MAX_PUBKEY_SIZE,
-                            &encapped_key.to_bytes(),
-                            &pk_recip.to_bytes()
-                        );
-                        let kem_context = &kem_context_buf[..kem_context_size];
-
-                        // The "unauthed shared secret" is derived from just the KEX of the ephemeral
-                        // input with the recipient pubkey. The HKDF-Expand call only errors if the
-                        // output values are 255x the digest size of the hash function. Since these
-                        // values are fixed at compile time, we don't worry about it.
-                        let mut shared_secret = <SharedSecret<Self> as Default>::default();
-                        extract_and_expand::<$kdf>(
-                            &kex_res_eph.to_bytes(),
-                            &suite_id,
-                            kem_context,
-                            &mut shared_secret.0,
-                        )
-                        .expect("shared secret is way too big");
-                        Ok(shared_secret)
-                    }
-                }
-            }
+            pub type $kem_name = DhKem<$dhkex, $kdf, $kem_id>;
         }
     };
 }
@@ -348,7 +630,7 @@ macro_rules! impl_dhkem {
 impl_dhkem!(
     x25519_hkdfsha256,
     X25519HkdfSha256,
-    crate::dhkex::x25519::X25519,
+    crate::dhkex::X25519,
     crate::kdf::HkdfSha256,
     0x0020,
     "Represents DHKEM(X25519, HKDF-SHA256)"
@@ -359,7 +641,7 @@ impl_dhkem!(
 impl_dhkem!(
     dhp256_hkdfsha256,
     DhP256HkdfSha256,
-    crate::dhkex::ecdh_nistp::DhP256,
+    crate::dhkex::DhP256,
     crate::kdf::HkdfSha256,
     0x0010,
     "Represents DHKEM(P-256, HKDF-SHA256)"
@@ -369,7 +651,7 @@ impl_dhkem!(
 impl_dhkem!(
     dhk256_hkdfsha256,
     DhK256HkdfSha256,
-    crate::dhkex::ecdh_k256::DhK256,
+    crate::dhkex::DhK256,
     crate::kdf::HkdfSha256,
     0x0030,
     "Represents DHKEM(K-256, HKDF-SHA256)"
@@ -0,0 +1,238 @@
+use crate::{
+    kdf::{labeled_extract_multi, Kdf as KdfTrait},
+    kem::Kem as KemTrait,
+    util::kem_suite_id,
+    Serializable,
+};
+
+use core::fmt;
+
+use digest::OutputSizeUser;
+use generic_array::GenericArray;
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::string::String;
+
+// The Bitcoin base58 alphabet. It leaves out 0, O, I, and l, which are easy to misread.
+#[cfg(feature = "alloc")]
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// A short, stable identifier of a public key, for naming recipients in logs, envelopes, and
+/// key listings. It is `Nh` bytes long, where `Nh` is the output size of `Kdf`. See
+/// `Kem::fingerprint` for how it's computed.
+///
+/// `Display` and `LowerHex` render it as lowercase hex. With the `alloc` feature, `to_hex` and
+/// `to_base58` render it as a `String`.
+pub struct Fingerprint<Kdf: KdfTrait>(
+    GenericArray<u8, <Kdf::HashImpl as OutputSizeUser>::OutputSize>,
+);
+
+impl<Kdf: KdfTrait> Fingerprint<Kdf> {
+    // RFC 9180 §4.1
+    // suite_id = concat("KEM", I2OSP(kem_id, 2))
+    //
+    // fingerprint = LabeledExtract("", "pk_fingerprint",
+    //                              concat(I2OSP(kdf_id, 2), SerializePublicKey(pk)))
+
+    /// Computes the fingerprint of `pk`. This is the implementation of `Kem::fingerprint`.
+    pub(crate) fn of<Kem: KemTrait>(pk: &Kem::PublicKey) -> Fingerprint<Kdf> {
+        let suite_id = kem_suite_id::<Kem>();
+        let (prk, _) = labeled_extract_multi::<Kdf>(
+            &[],
+            &suite_id,
+            b"pk_fingerprint",
+            &[&Kdf::KDF_ID.to_be_bytes(), &pk.to_bytes()],
+        );
+        Fingerprint(prk)
+    }
+
+    /// Returns the bytes of this fingerprint
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns this fingerprint in lowercase hex. This is the same as its `Display` output.
+    #[cfg(feature = "alloc")]
+    pub fn to_hex(&self) -> String {
+        use core::fmt::Write;
+
+        let mut out = String::with_capacity(2 * self.0.len());
+        let _ = write!(out, "{:x}", self);
+        out
+    }
+
+    /// Returns this fingerprint in base58, with the Bitcoin alphabet. This is shorter than hex,
+    /// and has no characters that are easy to confuse.
+    #[cfg(feature = "alloc")]
+    pub fn to_base58(&self) -> String {
+        // Repeatedly divide the big-endian number by 58. Leading zero bytes become leading '1's.
+        let mut digits = crate::Vec::with_capacity(2 * self.0.len());
+        for &byte in self.0.iter() {
+            let mut carry = byte as u32;
+            for digit in digits.iter_mut() {
+                carry += (*digit as u32) << 8;
+                *digit = (carry % 58) as u8;
+                carry /= 58;
+            }
+            while carry > 0 {
+                digits.push((carry % 58) as u8);
+                carry /= 58;
+            }
+        }
+
+        let leading_zeros = self.0.iter().take_while(|&&b| b == 0).count();
+        let mut out = String::with_capacity(leading_zeros + digits.len());
+        out.extend(core::iter::repeat('1').take(leading_zeros));
+        out.extend(
+            digits
+                .iter()
+                .rev()
+                .map(|&d| BASE58_ALPHABET[d as usize] as char),
+        );
+        out
+    }
+}
+
+// Fingerprints are public, so none of these need to be constant-time
+
+impl<Kdf: KdfTrait> Clone for Fingerprint<Kdf> {
+    fn clone(&self) -> Self {
+        Fingerprint(self.0.clone())
+    }
+}
+
+impl<Kdf: KdfTrait> PartialEq for Fingerprint<Kdf> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<Kdf: KdfTrait> Eq for Fingerprint<Kdf> {}
+
+impl<Kdf: KdfTrait> core::hash::Hash for Fingerprint<Kdf> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<Kdf: KdfTrait> fmt::LowerHex for Fingerprint<Kdf> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+impl<Kdf: KdfTrait> fmt::Display for Fingerprint<Kdf> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl<Kdf: KdfTrait> fmt::Debug for Fingerprint<Kdf> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({:x})", self)
+    }
+}
+
+// Gives each DHKEM's public key type a fingerprint method, so callers don't have to name the KEM
+macro_rules! impl_pk_fingerprint {
+    ($pk:ty, $kem:ty) => {
+        impl $pk {
+            /// Returns the fingerprint of this public key. See `Kem::fingerprint`.
+            pub fn fingerprint<Kdf: KdfTrait>(&self) -> Fingerprint<Kdf> {
+                <$kem as KemTrait>::fingerprint::<Kdf>(self)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "x25519-dalek")]
+impl_pk_fingerprint!(
+    crate::dhkex::x25519::PublicKey,
+    crate::kem::X25519HkdfSha256
+);
+#[cfg(feature = "p256")]
+impl_pk_fingerprint!(
+    crate::dhkex::ecdh_nistp::PublicKey,
+    crate::kem::DhP256HkdfSha256
+);
+#[cfg(feature = "k256")]
+impl_pk_fingerprint!(
+    crate::dhkex::ecdh_k256::PublicKey,
+    crate::kem::DhK256HkdfSha256
+);
+
+#[cfg(test)]
+mod test {
+    use crate::{kdf::HkdfSha256, kem::Kem as KemTrait};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    macro_rules! test_fingerprint {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                use crate::kdf::HkdfSha384;
+
+                type Kem = $kem_ty;
+
+                let mut csprng = StdRng::from_entropy();
+                let (_, pk1) = Kem::gen_keypair(&mut csprng);
+                let (_, pk2) = Kem::gen_keypair(&mut csprng);
+
+                // Deterministic, and the method on the key agrees with the one on the KEM
+                let fp1 = Kem::fingerprint::<HkdfSha256>(&pk1);
+                assert_eq!(fp1, pk1.fingerprint::<HkdfSha256>());
+                assert_eq!(fp1.as_bytes().len(), 32);
+                assert_ne!(fp1, Kem::fingerprint::<HkdfSha256>(&pk2));
+
+                // Bound to the KDF
+                let fp1_384 = Kem::fingerprint::<HkdfSha384>(&pk1);
+                assert_eq!(fp1_384.as_bytes().len(), 48);
+                assert_ne!(&fp1_384.as_bytes()[..32], fp1.as_bytes());
+
+                #[cfg(feature = "alloc")]
+                {
+                    assert_eq!(fp1.to_hex(), hex::encode(fp1.as_bytes()));
+                    let b58 = fp1.to_base58();
+                    assert!(b58.len() <= 44);
+                    assert!(!b58.contains(['0', 'O', 'I', 'l']));
+                }
+            }
+        };
+    }
+
+    /// Tests base58 encoding against known values, including leading zeros
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_base58() {
+        use super::Fingerprint;
+        use generic_array::GenericArray;
+
+        let mut bytes = GenericArray::default();
+        let fp = Fingerprint::<HkdfSha256>(bytes);
+        assert_eq!(fp.to_base58(), "1".repeat(32));
+
+        bytes[31] = 57;
+        assert_eq!(
+            Fingerprint::<HkdfSha256>(bytes).to_base58(),
+            "1".repeat(31) + "z"
+        );
+
+        bytes[30] = 1;
+        bytes[31] = 0;
+        // 256 = 4 * 58 + 24
+        assert_eq!(
+            Fingerprint::<HkdfSha256>(bytes).to_base58(),
+            "1".repeat(30) + "5R"
+        );
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_fingerprint!(test_fingerprint_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_fingerprint!(test_fingerprint_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_fingerprint!(test_fingerprint_k256, crate::kem::DhK256HkdfSha256);
+}
@@ -0,0 +1,206 @@
+use crate::{
+    kem::Kem as KemTrait,
+    op_mode::{OpModeS, PskBundle},
+    Deserializable, HpkeError, Serializable,
+};
+
+use rand_core::{CryptoRng, RngCore};
+
+/// A KEM private key together with its public key. The two are always consistent: every
+/// constructor either derives both halves together or computes the public key from the private
+/// key.
+///
+/// The `Debug` impl prints the public key and redacts the private key, so it's safe to log.
+pub struct Keypair<Kem: KemTrait> {
+    sk: Kem::PrivateKey,
+    pk: Kem::PublicKey,
+}
+
+// Kem itself needn't be Clone, so we can't derive this
+impl<Kem: KemTrait> Clone for Keypair<Kem> {
+    fn clone(&self) -> Self {
+        Keypair {
+            sk: self.sk.clone(),
+            pk: self.pk.clone(),
+        }
+    }
+}
+
+impl<Kem: KemTrait> Keypair<Kem> {
+    /// Generates a random keypair using the given RNG. This is the same as `Kem::gen_keypair`.
+    pub fn generate<R: CryptoRng + RngCore + ?Sized>(csprng: &mut R) -> Self {
+        let (sk, pk) = Kem::gen_keypair(csprng);
+        Keypair { sk, pk }
+    }
+
+    /// Deterministically derives a keypair from the given input keying material. This is the same
+    /// as `Kem::derive_keypair`.
+    ///
+    /// Requirements
+    /// ============
+    /// This keying material SHOULD have as many bits of entropy as the bit length of a secret key,
+    /// i.e., `8 * Kem::PrivateKey::size()`. For X25519 and P-256, this is 256 bits of entropy.
+    pub fn derive_from_ikm(ikm: &[u8]) -> Self {
+        let (sk, pk) = Kem::derive_keypair(ikm);
+        Keypair { sk, pk }
+    }
+
+    /// Makes a keypair out of the given private key by computing its public key
+    pub fn from_private_key(sk: Kem::PrivateKey) -> Self {
+        let pk = Kem::sk_to_pk(&sk);
+        Keypair { sk, pk }
+    }
+
+    /// Deserializes a private key and computes its public key
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the keypair on success. Returns whatever error `Kem::PrivateKey::from_bytes`
+    /// returns on failure.
+    pub fn from_private_key_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        Kem::PrivateKey::from_bytes(encoded).map(Self::from_private_key)
+    }
+
+    /// Returns the public half of this keypair
+    pub fn public(&self) -> &Kem::PublicKey {
+        &self.pk
+    }
+
+    /// Returns the private half of this keypair
+    pub fn private(&self) -> &Kem::PrivateKey {
+        &self.sk
+    }
+
+    /// Splits this keypair into its private and public key, in the order that `Kem::gen_keypair`
+    /// returns them
+    pub fn into_parts(self) -> (Kem::PrivateKey, Kem::PublicKey) {
+        (self.sk, self.pk)
+    }
+
+    /// Makes an `OpModeS::Auth` that authenticates the sender with this keypair
+    pub fn into_auth_mode<'a>(self) -> OpModeS<'a, Kem> {
+        OpModeS::Auth(self.into_parts())
+    }
+
+    /// Makes an `OpModeS::AuthPsk` that authenticates the sender with this keypair and the given
+    /// PSK bundle
+    pub fn into_auth_psk_mode(self, psk_bundle: PskBundle<'_>) -> OpModeS<'_, Kem> {
+        OpModeS::AuthPsk(self.into_parts(), psk_bundle)
+    }
+}
+
+// Never print the private key
+impl<Kem: KemTrait> core::fmt::Debug for Keypair<Kem> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Keypair")
+            .field(
+                "public",
+                &format_args!("{:02x?}", self.pk.to_bytes().as_slice()),
+            )
+            .field("private", &format_args!("[REDACTED]"))
+            .finish()
+    }
+}
+
+// Implements PKCS#8 import and export for the given NIST-style KEM. The private key types in
+// dhkex do the actual encoding.
+#[cfg(feature = "pkcs8")]
+macro_rules! impl_pkcs8 {
+    ($kem_ty:ty, $sk_ty:ty) => {
+        impl Keypair<$kem_ty> {
+            /// Parses a DER-encoded PKCS#8 private key, and computes its public key
+            ///
+            /// Return Value
+            /// ============
+            /// Returns the keypair on success. If the encoding is malformed or is for a different
+            /// curve, returns `Err(HpkeError::ValidationError)`.
+            pub fn from_pkcs8_der(der: &[u8]) -> Result<Self, HpkeError> {
+                <$sk_ty>::from_pkcs8_der(der).map(Self::from_private_key)
+            }
+
+            /// Encodes the private key as DER-encoded PKCS#8. The returned buffer is zeroed on
+            /// drop.
+            pub fn to_pkcs8_der(&self) -> zeroize::Zeroizing<crate::Vec<u8>> {
+                self.sk.to_pkcs8_der()
+            }
+        }
+    };
+}
+
+#[cfg(all(feature = "pkcs8", feature = "p256"))]
+impl_pkcs8!(
+    crate::kem::DhP256HkdfSha256,
+    crate::dhkex::ecdh_nistp::PrivateKey
+);
+
+#[cfg(all(feature = "pkcs8", feature = "k256"))]
+impl_pkcs8!(
+    crate::kem::DhK256HkdfSha256,
+    crate::dhkex::ecdh_k256::PrivateKey
+);
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::Keypair;
+    use crate::{kem::Kem as KemTrait, Serializable};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that all the constructors agree with the `Kem` functions and with each other, and that
+    /// `Debug` doesn't leak the private key
+    macro_rules! test_keypair_consistency {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+
+                let mut csprng = StdRng::from_entropy();
+
+                // derive_from_ikm should match derive_keypair
+                let ikm = [7u8; 32];
+                let kp = Keypair::<Kem>::derive_from_ikm(&ikm);
+                let (sk, pk) = Kem::derive_keypair(&ikm);
+                assert_eq!(kp.private().to_bytes(), sk.to_bytes());
+                assert_eq!(kp.public().to_bytes(), pk.to_bytes());
+
+                // Recomputing the pubkey from the privkey should give the same thing
+                let kp = Keypair::<Kem>::generate(&mut csprng);
+                let kp2 = Keypair::<Kem>::from_private_key_bytes(&kp.private().to_bytes()).unwrap();
+                assert_eq!(kp.public().to_bytes(), kp2.public().to_bytes());
+
+                // Debug shouldn't contain the private key bytes in any form
+                let debug_str = format!("{:?}", kp);
+                let sk_hex = format!("{:02x?}", kp.private().to_bytes().as_slice());
+                assert!(debug_str.contains("REDACTED"));
+                assert!(!debug_str.contains(&sk_hex[1..sk_hex.len() - 1]));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_keypair_consistency!(
+        test_keypair_consistency_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_keypair_consistency!(test_keypair_consistency_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_keypair_consistency!(test_keypair_consistency_k256, crate::kem::DhK256HkdfSha256);
+
+    /// Tests that a PKCS#8 round trip preserves the keypair
+    #[cfg(all(feature = "pkcs8", feature = "p256"))]
+    #[test]
+    fn test_pkcs8_roundtrip_p256() {
+        type Kem = crate::kem::DhP256HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let kp = Keypair::<Kem>::generate(&mut csprng);
+        let der = kp.to_pkcs8_der();
+        let kp2 = Keypair::<Kem>::from_pkcs8_der(&der).unwrap();
+        assert_eq!(kp.private().to_bytes(), kp2.private().to_bytes());
+        assert_eq!(kp.public().to_bytes(), kp2.public().to_bytes());
+
+        // Garbage shouldn't parse
+        assert!(Keypair::<Kem>::from_pkcs8_der(&der[1..]).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use crate::kem::Kem as KemTrait;
+
+/// A recipient public key together with precomputed tables that make encapsulating to it faster.
+/// If you're going to call `setup_sender` on the same recipient many times, make one of these and
+/// use `setup_sender_with_handle` instead.
+///
+/// How much this helps depends on the KEM. Every KEM saves a fixed-base scalar multiplication per
+/// call, since the ephemeral private key is sampled directly rather than via `Kem::gen_keypair`,
+/// which also computes a pubkey that `setup_sender` then throws away. For P-256, the ephemeral
+/// pubkey computation and the DH with the recipient key additionally switch from a bit-at-a-time
+/// ladder to a 4-bit window, which roughly halves the cost of `setup_sender`. X25519 and K-256
+/// already use windowed multiplication internally, so there's no table to precompute for them.
+pub struct RecipientHandle<Kem: KemTrait> {
+    pk: Kem::PublicKey,
+    table: Kem::PublicKeyTable,
+}
+
+// Kem itself needn't be Clone, so we can't derive this
+impl<Kem: KemTrait> Clone for RecipientHandle<Kem> {
+    fn clone(&self) -> Self {
+        RecipientHandle {
+            pk: self.pk.clone(),
+            table: self.table.clone(),
+        }
+    }
+}
+
+impl<Kem: KemTrait> RecipientHandle<Kem> {
+    /// Does the precomputation for the given recipient public key
+    pub fn new(pk_recip: &Kem::PublicKey) -> Self {
+        RecipientHandle {
+            pk: pk_recip.clone(),
+            table: Kem::precompute_pk(pk_recip),
+        }
+    }
+
+    /// Returns the recipient public key this handle was made from
+    pub fn public_key(&self) -> &Kem::PublicKey {
+        &self.pk
+    }
+
+    pub(crate) fn table(&self) -> &Kem::PublicKeyTable {
+        &self.table
+    }
+}
@@ -0,0 +1,484 @@
+//! The age v1 file format (age-encryption.org/v1), with a single scrypt recipient stanza. This is
+//! what `age --passphrase` writes, so files from here can be decrypted by age, and vice versa.
+//!
+//! A file is
+//!
+//! ```text
+//! age-encryption.org/v1
+//! -> scrypt <salt> <work factor>
+//! <wrapped file key>
+//! --- <header MAC>
+//! <payload nonce (16)> || <payload>
+//! ```
+//!
+//! where the base64 is standard and unpadded, and the header lines end in `\n`. The 16-byte file
+//! key is wrapped with ChaCha20Poly1305, under an all-zero nonce, with the key
+//! `scrypt(passphrase, "age-encryption.org/v1/scrypt" || salt, N = 2^work_factor, r = 8, p = 1)`.
+//! The header MAC is HMAC-SHA256 over everything up to and including `---`, keyed with
+//! `HKDF-SHA256(ikm = file_key, salt = "", info = "header")`. The payload is the plaintext
+//! encrypted with ChaCha20Poly1305 in 64 KiB chunks, under the key
+//! `HKDF-SHA256(ikm = file_key, salt = payload_nonce, info = "payload")`, where chunk `i` has the
+//! nonce `I2OSP(i, 11) || last`, with `last` being 1 for the final chunk and 0 otherwise.
+
+use super::PasswordKdf;
+use crate::{
+    aead::{Aead, ChaCha20Poly1305},
+    HpkeError, Vec,
+};
+
+use std::string::String;
+
+use aead::{AeadInPlace, NewAead, Nonce};
+use base64ct::{Base64Unpadded, Encoding};
+use byteorder::{BigEndian, ByteOrder};
+use generic_array::GenericArray;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::{CryptoRng, RngCore};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+/// The largest scrypt work factor accepted, when encrypting and when decrypting. scrypt with
+/// `r = 8` uses `2^(work_factor + 10)` bytes, so this is the most that fits the keystore's 1 GiB
+/// limit.
+pub(super) const MAX_WORK_FACTOR: u8 = 20;
+
+const VERSION_LINE: &[u8] = b"age-encryption.org/v1";
+const SCRYPT_TAG: &[u8] = b"scrypt";
+const SCRYPT_SALT_LABEL: &[u8] = b"age-encryption.org/v1/scrypt";
+const SALT_LEN: usize = 16;
+const FILE_KEY_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+const PAYLOAD_NONCE_LEN: usize = 16;
+const CHUNK_LEN: usize = 64 * 1024;
+// The number of base64 characters on each full line of a stanza body
+const COLUMNS: usize = 64;
+
+type A = ChaCha20Poly1305;
+type Cipher = <A as Aead>::AeadImpl;
+
+/// Encrypts `plaintext` to `passphrase` as an age file, with scrypt work factor `work_factor`
+///
+/// Return Value
+/// ============
+/// Returns the binary (unarmored) file on success. Returns `Err(HpkeError::ValidationError)` if
+/// `work_factor` is 0 or greater than `MAX_WORK_FACTOR`.
+pub(super) fn encrypt<R: CryptoRng + RngCore + ?Sized>(
+    plaintext: &[u8],
+    passphrase: &[u8],
+    work_factor: u8,
+    csprng: &mut R,
+) -> Result<Vec<u8>, HpkeError> {
+    let mut file_key = Zeroizing::new([0u8; FILE_KEY_LEN]);
+    csprng.fill_bytes(file_key.as_mut());
+    let mut salt = [0u8; SALT_LEN];
+    csprng.fill_bytes(&mut salt);
+
+    // Wrap the file key
+    let mut wrapped = [0u8; FILE_KEY_LEN + TAG_LEN];
+    wrapped[..FILE_KEY_LEN].copy_from_slice(file_key.as_ref());
+    let tag = wrapping_cipher(passphrase, &salt, work_factor)?
+        .encrypt_in_place_detached(&Default::default(), b"", &mut wrapped[..FILE_KEY_LEN])
+        .map_err(|_| HpkeError::SealError)?;
+    wrapped[FILE_KEY_LEN..].copy_from_slice(&tag);
+
+    // The wrapped key is 43 base64 characters, so the stanza body is a single short line
+    let mut header = String::new();
+    header.push_str("age-encryption.org/v1\n-> scrypt ");
+    header.push_str(&Base64Unpadded::encode_string(&salt));
+    header.push_str(&format!(" {}\n", work_factor));
+    header.push_str(&Base64Unpadded::encode_string(&wrapped));
+    header.push_str("\n---");
+    let mac = header_mac(file_key.as_ref(), header.as_bytes()).finalize();
+    header.push(' ');
+    header.push_str(&Base64Unpadded::encode_string(&mac.into_bytes()));
+    header.push('\n');
+
+    let mut nonce = [0u8; PAYLOAD_NONCE_LEN];
+    csprng.fill_bytes(&mut nonce);
+    let cipher = payload_cipher(file_key.as_ref(), &nonce);
+
+    let num_chunks = core::cmp::max(1, plaintext.len().div_ceil(CHUNK_LEN));
+    let mut out = Vec::with_capacity(
+        header.len() + PAYLOAD_NONCE_LEN + plaintext.len() + num_chunks * TAG_LEN,
+    );
+    out.extend_from_slice(header.as_bytes());
+    out.extend_from_slice(&nonce);
+    // An empty plaintext is still one (empty) chunk
+    let chunks = plaintext
+        .chunks(CHUNK_LEN)
+        .chain(plaintext.is_empty().then_some(&[][..]));
+    for (i, chunk) in chunks.enumerate() {
+        let start = out.len();
+        out.extend_from_slice(chunk);
+        let chunk_nonce = chunk_nonce(i as u64, i + 1 == num_chunks);
+        let tag = cipher
+            .encrypt_in_place_detached(&chunk_nonce, b"", &mut out[start..])
+            .map_err(|_| HpkeError::SealError)?;
+        out.extend_from_slice(&tag);
+    }
+
+    Ok(out)
+}
+
+/// Decrypts an age file, which must have exactly one recipient stanza, an scrypt one, with a work
+/// factor of at most `MAX_WORK_FACTOR`
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. Returns `Err(HpkeError::ValidationError)` if the file is
+/// malformed, has any other stanzas, or its work factor is out of range. The work factor is
+/// checked before any key derivation. Returns `Err(HpkeError::OpenError)` if the passphrase is
+/// wrong, or the file was modified.
+pub(super) fn decrypt(file: &[u8], passphrase: &[u8]) -> Result<Zeroizing<Vec<u8>>, HpkeError> {
+    let header = parse_header(file)?;
+
+    // The scrypt stanza has to be the only one
+    let (args, body) = match &header.stanzas[..] {
+        [stanza] => stanza,
+        _ => return Err(HpkeError::ValidationError),
+    };
+    let (salt, work_factor) = match &args[..] {
+        [tag, salt, work_factor] if *tag == SCRYPT_TAG => (
+            decode_exact::<SALT_LEN>(salt)?,
+            parse_work_factor(work_factor)?,
+        ),
+        _ => return Err(HpkeError::ValidationError),
+    };
+    if body.len() != FILE_KEY_LEN + TAG_LEN {
+        return Err(HpkeError::ValidationError);
+    }
+
+    // Unwrap the file key, then check the header with it
+    let mut file_key = Zeroizing::new([0u8; FILE_KEY_LEN]);
+    file_key.copy_from_slice(&body[..FILE_KEY_LEN]);
+    wrapping_cipher(passphrase, &salt, work_factor)?
+        .decrypt_in_place_detached(
+            &Default::default(),
+            b"",
+            file_key.as_mut(),
+            GenericArray::from_slice(&body[FILE_KEY_LEN..]),
+        )
+        .map_err(|_| HpkeError::OpenError)?;
+    header_mac(file_key.as_ref(), header.authenticated)
+        .verify_slice(&header.mac)
+        .map_err(|_| HpkeError::OpenError)?;
+
+    decrypt_payload(file_key.as_ref(), header.payload)
+}
+
+// A parsed header, and the payload after it
+struct Header<'a> {
+    stanzas: Vec<Stanza<'a>>,
+    // Everything the MAC covers, i.e., up to and including the `---`
+    authenticated: &'a [u8],
+    mac: [u8; 32],
+    payload: &'a [u8],
+}
+
+// A stanza's arguments, including its type, and its decoded body
+type Stanza<'a> = (Vec<&'a [u8]>, Vec<u8>);
+
+// Parses the header of `file`, without checking the MAC
+fn parse_header(file: &[u8]) -> Result<Header<'_>, HpkeError> {
+    let mut reader = LineReader { file, pos: 0 };
+    if reader.next_line()? != VERSION_LINE {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let mut stanzas = Vec::new();
+    loop {
+        let line_start = reader.pos;
+        let line = reader.next_line()?;
+        if let Some(encoded_mac) = line.strip_prefix(b"--- ") {
+            return Ok(Header {
+                stanzas,
+                authenticated: &file[..line_start + 3],
+                mac: decode_exact(encoded_mac)?,
+                payload: &file[reader.pos..],
+            });
+        }
+        stanzas.push(read_stanza(line, &mut reader)?);
+    }
+}
+
+// Decrypts the payload, i.e., the nonce and the chunks after it, under the file key
+fn decrypt_payload(file_key: &[u8], payload: &[u8]) -> Result<Zeroizing<Vec<u8>>, HpkeError> {
+    if payload.len() < PAYLOAD_NONCE_LEN {
+        return Err(HpkeError::ValidationError);
+    }
+    let (nonce, ciphertext) = payload.split_at(PAYLOAD_NONCE_LEN);
+    let cipher = payload_cipher(file_key, nonce);
+
+    // Every chunk but the last is full, and the last is only empty if it's the only one
+    let mut plaintext = Zeroizing::new(Vec::with_capacity(ciphertext.len()));
+    let mut chunks = ciphertext
+        .chunks(CHUNK_LEN + TAG_LEN)
+        .enumerate()
+        .peekable();
+    if chunks.peek().is_none() {
+        return Err(HpkeError::ValidationError);
+    }
+    while let Some((i, chunk)) = chunks.next() {
+        let last = chunks.peek().is_none();
+        if chunk.len() < TAG_LEN || (last && i > 0 && chunk.len() == TAG_LEN) {
+            return Err(HpkeError::ValidationError);
+        }
+        let (chunk, tag) = chunk.split_at(chunk.len() - TAG_LEN);
+        let start = plaintext.len();
+        plaintext.extend_from_slice(chunk);
+        cipher
+            .decrypt_in_place_detached(
+                &chunk_nonce(i as u64, last),
+                b"",
+                &mut plaintext[start..],
+                GenericArray::from_slice(tag),
+            )
+            .map_err(|_| HpkeError::OpenError)?;
+    }
+
+    Ok(plaintext)
+}
+
+// Splits the header into lines. Every header line ends in `\n`.
+struct LineReader<'a> {
+    file: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> LineReader<'a> {
+    // Returns the next line, without its `\n`, and moves past it
+    fn next_line(&mut self) -> Result<&'a [u8], HpkeError> {
+        let rest = &self.file[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(HpkeError::ValidationError)?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+}
+
+// Reads a stanza whose first line is `line`, and whose body starts at `reader`. Arguments are
+// nonempty and printable ASCII, and the body is base64 in full lines, ending in a short one,
+// which may be empty.
+fn read_stanza<'a>(line: &'a [u8], reader: &mut LineReader<'a>) -> Result<Stanza<'a>, HpkeError> {
+    let args: Vec<&[u8]> = line
+        .strip_prefix(b"-> ")
+        .ok_or(HpkeError::ValidationError)?
+        .split(|&b| b == b' ')
+        .collect();
+    if args
+        .iter()
+        .any(|arg| arg.is_empty() || !arg.iter().all(u8::is_ascii_graphic))
+    {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let mut body = Vec::new();
+    loop {
+        let line = reader.next_line()?;
+        if line.len() > COLUMNS {
+            return Err(HpkeError::ValidationError);
+        }
+        body.extend_from_slice(
+            &Base64Unpadded::decode_vec(
+                core::str::from_utf8(line).map_err(|_| HpkeError::ValidationError)?,
+            )
+            .map_err(|_| HpkeError::ValidationError)?,
+        );
+        if line.len() < COLUMNS {
+            return Ok((args, body));
+        }
+    }
+}
+
+// Decodes unpadded base64 that must be exactly N bytes
+fn decode_exact<const N: usize>(encoded: &[u8]) -> Result<[u8; N], HpkeError> {
+    let mut out = [0u8; N];
+    let decoded =
+        Base64Unpadded::decode(encoded, &mut out).map_err(|_| HpkeError::ValidationError)?;
+    if decoded.len() != N {
+        return Err(HpkeError::ValidationError);
+    }
+    Ok(out)
+}
+
+// Parses a work factor, which is decimal without a sign or leading zeros, and at most
+// MAX_WORK_FACTOR
+fn parse_work_factor(encoded: &[u8]) -> Result<u8, HpkeError> {
+    if encoded.is_empty() || encoded[0] == b'0' || !encoded.iter().all(u8::is_ascii_digit) {
+        return Err(HpkeError::ValidationError);
+    }
+    core::str::from_utf8(encoded)
+        .ok()
+        .and_then(|s| s.parse::<u8>().ok())
+        .filter(|&work_factor| work_factor <= MAX_WORK_FACTOR)
+        .ok_or(HpkeError::ValidationError)
+}
+
+// Derives the key that wraps the file key. Fails if the work factor is out of range.
+fn wrapping_cipher(
+    passphrase: &[u8],
+    salt: &[u8; SALT_LEN],
+    work_factor: u8,
+) -> Result<Cipher, HpkeError> {
+    if work_factor == 0 || work_factor > MAX_WORK_FACTOR {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let mut full_salt = [0u8; SCRYPT_SALT_LABEL.len() + SALT_LEN];
+    full_salt[..SCRYPT_SALT_LABEL.len()].copy_from_slice(SCRYPT_SALT_LABEL);
+    full_salt[SCRYPT_SALT_LABEL.len()..].copy_from_slice(salt);
+    let kdf = PasswordKdf::Scrypt {
+        log_n: work_factor.into(),
+        r: 8,
+        p: 1,
+    };
+    let key = kdf.derive_key(passphrase, &full_salt)?;
+    Ok(Cipher::new(GenericArray::from_slice(key.as_ref())))
+}
+
+// Returns the HMAC that authenticates the header, keyed by the file key
+fn header_mac(file_key: &[u8], header: &[u8]) -> Hmac<Sha256> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, file_key)
+        .expand(b"header", key.as_mut())
+        .unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_ref()).unwrap();
+    mac.update(header);
+    mac
+}
+
+// Derives the cipher that encrypts the payload
+fn payload_cipher(file_key: &[u8], nonce: &[u8]) -> Cipher {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(nonce), file_key)
+        .expand(b"payload", key.as_mut())
+        .unwrap();
+    Cipher::new(GenericArray::from_slice(key.as_ref()))
+}
+
+// The nonce of chunk i of the payload: an 11-byte big-endian counter, then 1 if it's the last chunk
+fn chunk_nonce(i: u64, last: bool) -> Nonce<Cipher> {
+    let mut nonce = Nonce::<Cipher>::default();
+    BigEndian::write_u64(&mut nonce[3..11], i);
+    nonce[11] = u8::from(last);
+    nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decrypt, encrypt, CHUNK_LEN};
+    use crate::{HpkeError, Vec};
+
+    use hex_literal::hex;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // The "scrypt" file from age's testkit (C2SP/CCTV), which encrypts "age" under "password"
+    fn testkit_file() -> Vec<u8> {
+        let header = b"age-encryption.org/v1
+-> scrypt rF0/NwblUHHTpgQgRpe5CQ 10
+gUjEymFKMVXQEKdMMHL24oYexjE3TIC0O0zGSqJ2aUY
+--- IOXiQYStkoT1mvZW2tFOqZdhRVvj58egABx/sWfZQbc
+";
+        let payload =
+            hex!("1b35c6e687dd00da3ac379ac9f742c21fd185a1b9e3ded739d14ac6a9a50124db866d8");
+        [&header[..], &payload[..]].concat()
+    }
+
+    /// Tests that a file made by age decrypts, and only under its passphrase
+    #[test]
+    fn test_age_testkit() {
+        let file = testkit_file();
+        assert_eq!(decrypt(&file, b"password").unwrap().as_slice(), b"age");
+        assert_eq!(
+            decrypt(&file, b"passw0rd").err(),
+            Some(HpkeError::OpenError)
+        );
+    }
+
+    /// Tests that files round-trip, including empty ones and ones of more than one chunk
+    #[test]
+    fn test_age_roundtrip() {
+        let mut csprng = StdRng::from_entropy();
+        for len in [0, 1, CHUNK_LEN - 1, CHUNK_LEN, CHUNK_LEN + 1, 2 * CHUNK_LEN] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let file = encrypt(&plaintext, b"passphrase", 4, &mut csprng).unwrap();
+            assert!(file.starts_with(b"age-encryption.org/v1\n-> scrypt "));
+            assert_eq!(
+                decrypt(&file, b"passphrase").unwrap().as_slice(),
+                plaintext.as_slice()
+            );
+
+            // Dropping a chunk, or any byte, is detected
+            let truncated = &file[..file.len() - 1];
+            assert!(decrypt(truncated, b"passphrase").is_err());
+            if len > CHUNK_LEN {
+                let dropped = &file[..file.len() - (len - CHUNK_LEN) - 16];
+                assert!(decrypt(dropped, b"passphrase").is_err());
+            }
+        }
+
+        for work_factor in [0, 21] {
+            assert_eq!(
+                encrypt(b"", b"passphrase", work_factor, &mut csprng).err(),
+                Some(HpkeError::ValidationError)
+            );
+        }
+    }
+
+    /// Tests that malformed headers are rejected, the same way age rejects them, and that the
+    /// header is authenticated
+    #[test]
+    fn test_age_malformed() {
+        let file = testkit_file();
+        let stanza =
+            "-> scrypt rF0/NwblUHHTpgQgRpe5CQ 10\ngUjEymFKMVXQEKdMMHL24oYexjE3TIC0O0zGSqJ2aUY\n";
+        let replace = |from: &str, to: &str| -> Vec<u8> {
+            let i = file
+                .windows(from.len())
+                .position(|w| w == from.as_bytes())
+                .unwrap();
+            [&file[..i], to.as_bytes(), &file[i + from.len()..]].concat()
+        };
+
+        for bad in [
+            replace("v1\n", "v2\n"),
+            // Work factors that are out of range, or not canonical decimal
+            replace(" 10\n", " 0\n"),
+            replace(" 10\n", " 21\n"),
+            replace(" 10\n", " 010\n"),
+            replace(" 10\n", " +10\n"),
+            replace(" 10\n", " 0xa\n"),
+            replace(" 10\n", " 10 10\n"),
+            replace(" 10\n", "\n"),
+            // A salt that isn't canonical base64, or isn't 16 bytes
+            replace("CQ 10", "CR 10"),
+            replace("CQ 10", "CQA 10"),
+            // A different stanza type, or another stanza
+            replace("scrypt", "SCRYPT"),
+            replace(stanza, &stanza.repeat(2)),
+            replace(stanza, &[stanza, "-> X25519 abc\n\n"].concat()),
+            // A body that isn't the length of a wrapped file key, or isn't canonical base64
+            replace("aUY\n", "aUYAAA\n"),
+            replace("aUY\n", "aUZ\n"),
+            // A MAC line that's malformed
+            replace("--- ", "---"),
+            replace("Qbc\n", "Qb\n"),
+            replace("Qbc\n", "Qbc \n"),
+        ] {
+            assert_eq!(
+                decrypt(&bad, b"password").err(),
+                Some(HpkeError::ValidationError)
+            );
+        }
+
+        // A well-formed header that was modified fails the MAC
+        let bad_mac = replace("--- IOXi", "--- IOXj");
+        assert_eq!(
+            decrypt(&bad_mac, b"password").err(),
+            Some(HpkeError::OpenError)
+        );
+    }
+}
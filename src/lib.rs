@@ -7,7 +7,7 @@
 //! public key they know. Here's an example of Alice and Bob, where Alice knows Bob's public key:
 //!
 //! ```
-//! # #[cfg(feature = "x25519")]
+//! # #[cfg(all(feature = "alloc", feature = "x25519"))]
 //! # {
 //! # use rand::{rngs::StdRng, SeedableRng};
 //! # use hpke::{
@@ -77,15 +77,15 @@
 extern crate std;
 
 #[cfg(feature = "std")]
-pub(crate) use std::vec::Vec;
+pub(crate) use std::{boxed::Box, vec::Vec};
 
-#[cfg(not(feature = "std"))]
+#[cfg(all(feature = "alloc", not(feature = "std")))]
 #[allow(unused_imports)]
 #[macro_use]
 extern crate alloc;
 
-#[cfg(not(feature = "std"))]
-pub(crate) use alloc::vec::Vec;
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+pub(crate) use alloc::{boxed::Box, vec::Vec};
 
 //-------- Testing stuff --------//
 
@@ -99,40 +99,140 @@ mod kat_tests;
 #[macro_use]
 extern crate serde_derive;
 
+// wycheproof_tests runs the externally provided Wycheproof vectors. It needs std for file IO.
+#[cfg(all(test, feature = "wycheproof"))]
+mod wycheproof_tests;
+
+// openssl_interop_tests round-trips messages with OpenSSL's HPKE. It loads libcrypto with dlopen.
+#[cfg(all(test, unix, feature = "openssl-interop"))]
+mod openssl_interop_tests;
+
+// proptests checks serialization and seal/open invariants over every compiled ciphersuite
+#[cfg(all(test, feature = "alloc"))]
+mod proptests;
+
 #[cfg(test)]
 mod test_util;
 
 //-------- Modules and exports--------//
 
-// Re-export our versions of generic_array and rand_core, since their traits and types are exposed
-// in this crate
+// Re-export our versions of generic_array, rand_core, subtle, and zeroize, since their traits and
+// types are exposed in this crate
 pub use generic_array;
 pub use rand_core;
+pub use subtle;
+pub use zeroize;
+
+// Re-export the RustCrypto aead crate that AeadKey::to_cipher and export_to_aead interoperate
+// with. It's renamed so it doesn't clash with our own aead module.
+pub use ::aead as rustcrypto_aead;
+
+// Re-export the rand_core 0.9 that RngCompat is implemented for
+#[cfg(feature = "rand_core_09")]
+pub use rand_core_09;
 
 #[macro_use]
 mod util;
 
+#[cfg(feature = "alloc")]
+pub mod aad;
 pub mod aead;
-mod dhkex;
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls;
+#[cfg(feature = "text-encoding")]
+pub mod armor;
+pub mod dhkex;
+#[cfg(feature = "alloc")]
+pub mod dyn_suite;
+#[cfg(feature = "alloc")]
+pub mod envelope;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+#[cfg(feature = "alloc")]
+pub mod group;
 pub mod kdf;
 pub mod kem;
+#[cfg(feature = "keystore")]
+pub mod keystore;
+pub mod low_level;
 mod op_mode;
+#[cfg(feature = "bech32")]
+pub mod qr;
+#[cfg(feature = "rand_core_09")]
+mod rand_compat;
+pub mod receipt;
+#[cfg(feature = "alloc")]
+pub mod record;
+#[cfg(all(feature = "sealed-sender", any(feature = "p256", feature = "k256")))]
+pub mod sealed_sender;
 mod setup;
+#[cfg(feature = "alloc")]
+pub mod shamir;
+#[cfg(all(feature = "simple", any(feature = "x25519", feature = "k256")))]
+pub mod simple;
 mod single_shot;
+#[cfg(all(feature = "ssh", any(feature = "x25519", feature = "p256")))]
+pub mod ssh;
+#[cfg(feature = "static-static")]
+pub mod static_static;
+#[cfg(feature = "alloc")]
+pub mod test_vectors;
+#[cfg(feature = "test-util")]
+pub mod testing;
+#[cfg(all(feature = "k256", feature = "alloc"))]
+pub mod threshold;
+#[cfg(feature = "alloc")]
+pub mod ticket;
+#[cfg(feature = "timing-tests")]
+pub mod timing;
+mod trace;
+#[cfg(all(feature = "x509", any(feature = "p256", feature = "k256")))]
+pub mod x509;
 
+#[cfg(feature = "minicbor")]
+mod minicbor_impls;
 #[cfg(feature = "serde_impls")]
 mod serde_impls;
+#[cfg(feature = "text-encoding")]
+mod text_encoding_impls;
 
 #[doc(inline)]
-pub use kem::Kem;
+pub use kem::{self_test_all, Kem, Keypair, RecipientHandle};
+#[cfg(feature = "low-entropy-psk")]
+#[doc(inline)]
+pub use op_mode::{LowEntropyPskParams, Psk};
 #[doc(inline)]
 pub use op_mode::{OpModeR, OpModeS, PskBundle};
+#[cfg(feature = "rand_core_09")]
 #[doc(inline)]
-pub use setup::{setup_receiver, setup_sender};
+pub use rand_compat::RngCompat;
+#[cfg(feature = "key-usage-hook")]
+#[doc(inline)]
+pub use setup::{clear_key_usage_hook, set_key_usage_hook, KeyUsage};
+#[doc(inline)]
+pub use setup::{
+    setup_receiver, setup_receiver_with_async_resolver, setup_receiver_with_encap_filter,
+    setup_receiver_with_resolver, setup_receiver_with_verifier, setup_sender,
+    setup_sender_deterministic, setup_sender_with_ephemeral, setup_sender_with_handle,
+    AsyncPskResolver, EncapFilter, PskResolver, ReceiverBuilder, SenderBuilder, SenderVerifier,
+};
+#[cfg(feature = "alloc")]
+#[doc(inline)]
+pub use setup::{setup_receiver_batch, setup_sender_multi};
+#[cfg(feature = "debug-internals")]
+#[doc(inline)]
+pub use setup::{setup_receiver_debug, setup_sender_debug, KeyScheduleValues};
+#[cfg(feature = "alloc")]
 #[doc(inline)]
 pub use single_shot::{
-    single_shot_open, single_shot_open_in_place_detached, single_shot_seal,
-    single_shot_seal_in_place_detached,
+    open_from_slice, seal_to_vec, single_shot_open, single_shot_open_with_limit,
+    single_shot_open_zeroizing, single_shot_seal, single_shot_seal_multi,
+    single_shot_seal_with_limit,
+};
+#[doc(inline)]
+pub use single_shot::{
+    single_shot_open_fixed, single_shot_open_in_place, single_shot_open_in_place_detached,
+    single_shot_seal_fixed, single_shot_seal_in_place_detached,
 };
 
 //-------- Top-level types --------//
@@ -141,11 +241,15 @@ use generic_array::{typenum::marker_traits::Unsigned, ArrayLength, GenericArray}
 
 /// Describes things that can go wrong in the HPKE protocol
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum HpkeError {
     /// The allowed number of message encryptions has been reached
     MessageLimitReached,
     /// An error occurred while opening a ciphertext
     OpenError,
+    /// A ciphertext's sequence number was already opened, or is too far behind the newest one to
+    /// tell
+    ReplayedMessage,
     /// An error occured while sealing a plaintext
     SealError,
     /// The KDF was asked to output too many bytes
@@ -159,6 +263,61 @@ pub enum HpkeError {
     /// An input isn't the right length. First value is the expected length, second is the given
     /// length.
     IncorrectInputLength(usize, usize),
+    /// A public or private key failed validation. The value says what was wrong with it. P-256
+    /// and K-256 key parsing returns this where earlier versions returned `ValidationError`.
+    InvalidKey(KeyValidationError),
+    /// A `PskResolver` has no PSK with the requested PSK ID
+    UnknownPskId,
+    /// A `SenderVerifier` rejected the sender's identity public key
+    UntrustedSender,
+    /// An `EncapFilter` has seen the encapsulated key before
+    ReplayedEncappedKey,
+    /// DeriveKeyPair rejected every candidate private key. For the KEMs here, this happens with
+    /// probability at most 2^-8192.
+    KeyDerivation,
+    /// A message is longer than the limit set on the context or passed to the single-shot
+    /// function. First value is the limit, second is the given length.
+    MessageTooLarge(usize, usize),
+    /// A time-bound envelope was opened before its not-before time or after its not-after time
+    OutsideValidityWindow,
+}
+
+/// The part of HPKE that an `HpkeError` came from. See `HpkeError::component`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HpkeComponent {
+    /// Key encapsulation, decapsulation, or key parsing
+    Kem,
+    /// Key derivation, i.e., the key schedule or the secret exporter
+    Kdf,
+    /// Authenticated encryption, i.e., sealing and opening
+    Aead,
+    /// Checks on caller-provided inputs that don't belong to any one primitive
+    Input,
+}
+
+impl HpkeError {
+    /// Returns the part of HPKE this error came from. This is meant to make interop failures
+    /// easier to narrow down, e.g., to tell a bad encapsulated key apart from a bad ciphertext.
+    pub fn component(&self) -> HpkeComponent {
+        match self {
+            HpkeError::EncapError
+            | HpkeError::DecapError
+            | HpkeError::InvalidKey(_)
+            | HpkeError::KeyDerivation => HpkeComponent::Kem,
+            HpkeError::KdfOutputTooLong => HpkeComponent::Kdf,
+            HpkeError::OpenError
+            | HpkeError::SealError
+            | HpkeError::MessageLimitReached
+            | HpkeError::ReplayedMessage => HpkeComponent::Aead,
+            HpkeError::ValidationError
+            | HpkeError::IncorrectInputLength(..)
+            | HpkeError::UnknownPskId
+            | HpkeError::UntrustedSender
+            | HpkeError::ReplayedEncappedKey
+            | HpkeError::MessageTooLarge(..)
+            | HpkeError::OutsideValidityWindow => HpkeComponent::Input,
+        }
+    }
 }
 
 impl core::fmt::Display for HpkeError {
@@ -166,6 +325,7 @@ impl core::fmt::Display for HpkeError {
         match self {
             HpkeError::MessageLimitReached => write!(f, "Message limit reached"),
             HpkeError::OpenError => write!(f, "Failed to open ciphertext"),
+            HpkeError::ReplayedMessage => write!(f, "Sequence number was replayed or is too old"),
             HpkeError::SealError => write!(f, "Failed to seal plaintext"),
             HpkeError::KdfOutputTooLong => write!(f, "Too many bytes requested from KDF"),
             HpkeError::ValidationError => write!(f, "Input value is invalid"),
@@ -176,6 +336,87 @@ impl core::fmt::Display for HpkeError {
                 "Incorrect input length. Expected {} bytes. Got {}.",
                 expected, given
             ),
+            HpkeError::InvalidKey(e) => write!(f, "Invalid key: {}", e),
+            HpkeError::UnknownPskId => write!(f, "No PSK with the given PSK ID"),
+            HpkeError::UntrustedSender => write!(f, "Sender identity key is not trusted"),
+            HpkeError::ReplayedEncappedKey => write!(f, "Encapsulated key was already seen"),
+            HpkeError::KeyDerivation => write!(f, "DeriveKeyPair failed all attempts"),
+            HpkeError::MessageTooLarge(limit, given) => write!(
+                f,
+                "Message too large. Limit is {} bytes. Got {}.",
+                limit, given
+            ),
+            HpkeError::OutsideValidityWindow => {
+                write!(f, "Message is not yet valid, or has expired")
+            }
+        }
+    }
+}
+
+/// Describes why a public or private key failed validation. This is what the `validate` and
+/// `validate_bytes` methods on key types return.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyValidationError {
+    /// The encoding isn't the right length. First value is the expected length, second is the
+    /// given length.
+    IncorrectLength(usize, usize),
+    /// The encoding's leading tag byte isn't the uncompressed point tag `0x04`. The value is the
+    /// tag that was given.
+    UnsupportedPointEncoding(u8),
+    /// The point is the point at infinity, i.e., the identity element
+    PointAtInfinity,
+    /// A coordinate isn't fully reduced modulo the field prime
+    NonCanonicalEncoding,
+    /// The point doesn't satisfy the curve equation
+    NotOnCurve,
+    /// The point has small order, i.e., it isn't in the prime-order subgroup
+    SmallOrder,
+    /// The scalar is zero
+    ZeroScalar,
+    /// The scalar is not less than the group order
+    ScalarOutOfRange,
+    /// The private key and public key aren't a working keypair. This is what `Kem::self_test`
+    /// returns when a check fails.
+    KeypairMismatch,
+}
+
+impl core::fmt::Display for KeyValidationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            KeyValidationError::IncorrectLength(expected, given) => write!(
+                f,
+                "Incorrect key length. Expected {} bytes. Got {}.",
+                expected, given
+            ),
+            KeyValidationError::UnsupportedPointEncoding(tag) => {
+                write!(f, "Unsupported point encoding tag 0x{:02x}", tag)
+            }
+            KeyValidationError::PointAtInfinity => write!(f, "Point is the point at infinity"),
+            KeyValidationError::NonCanonicalEncoding => {
+                write!(f, "Point coordinate is not canonically encoded")
+            }
+            KeyValidationError::NotOnCurve => write!(f, "Point is not on the curve"),
+            KeyValidationError::SmallOrder => write!(f, "Point has small order"),
+            KeyValidationError::ZeroScalar => write!(f, "Scalar is zero"),
+            KeyValidationError::ScalarOutOfRange => {
+                write!(f, "Scalar is not less than the group order")
+            }
+            KeyValidationError::KeypairMismatch => {
+                write!(f, "Private key and public key are not a keypair")
+            }
+        }
+    }
+}
+
+// Lets key validation results be used with ? in functions returning HpkeError. Length errors map
+// to IncorrectInputLength, since that's what from_bytes has always returned for them.
+impl From<KeyValidationError> for HpkeError {
+    fn from(e: KeyValidationError) -> HpkeError {
+        match e {
+            KeyValidationError::IncorrectLength(expected, given) => {
+                HpkeError::IncorrectInputLength(expected, given)
+            }
+            e => HpkeError::InvalidKey(e),
         }
     }
 }
@@ -194,9 +435,27 @@ pub trait Serializable {
 
 /// Implemented by types that can be deserialized from byte representation
 pub trait Deserializable: Serializable + Sized {
+    /// Decodes a value from its byte representation
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::IncorrectInputLength)` if `encoded` is the wrong length. For P-256
+    /// and K-256 keys, an encoding of the right length that isn't a valid key gives
+    /// `Err(HpkeError::InvalidKey(..))` saying why. Earlier versions of this crate returned
+    /// `Err(HpkeError::ValidationError)` there, so code that matched on that needs to match
+    /// `InvalidKey` too.
     fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError>;
 }
 
-// An Error type is just something that's Debug and Display
-#[cfg(feature = "std")]
-impl std::error::Error for HpkeError {}
+// An Error type is just something that's Debug and Display. core::error::Error is the same trait
+// as std::error::Error, so this works with and without std.
+impl core::error::Error for HpkeError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            HpkeError::InvalidKey(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl core::error::Error for KeyValidationError {}
@@ -0,0 +1,65 @@
+//! The building blocks of RFC 9180's key schedule, for libraries that wrap or implement their own
+//! KEMs, KDFs, or AEADs on top of this crate and need to bind their derivations the same way.
+//!
+//! Everything here follows semver like the rest of the public API. The suite ID layouts and the
+//! labeled KDF functions are fixed by RFC 9180, so they won't change at all.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     low_level::{full_suite_id, kem_suite_id, labeled_extract, LabeledExpand},
+//! };
+//!
+//! assert_eq!(&kem_suite_id::<X25519HkdfSha256>(), b"KEM\x00\x20");
+//! assert_eq!(
+//!     &full_suite_id::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256>(),
+//!     b"HPKE\x00\x20\x00\x01\x00\x03"
+//! );
+//!
+//! // A wrapper KEM deriving its own secret, bound to its suite like the built-in KEMs are
+//! let suite_id = kem_suite_id::<X25519HkdfSha256>();
+//! let (_, prk) = labeled_extract::<HkdfSha256>(b"", &suite_id, b"wrapper_prk", b"ikm");
+//! let mut okm = [0u8; 32];
+//! prk.labeled_expand(&suite_id, b"wrapper_key", b"", &mut okm)
+//!     .unwrap();
+//! # }
+//! ```
+
+#[doc(inline)]
+pub use crate::kdf::{extract_and_expand, labeled_extract, LabeledExpand};
+#[doc(inline)]
+pub use crate::util::{enforce_equal_len, full_suite_id, kem_suite_id, FullSuiteId, KemSuiteId};
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::{enforce_equal_len, full_suite_id, kem_suite_id};
+    use crate::{
+        aead::AesGcm256,
+        kdf::HkdfSha384,
+        kem::{DhKem, X25519HkdfSha256},
+        HpkeError,
+    };
+
+    /// Tests the suite ID layouts, including for a custom KEM ID, and the length check
+    #[test]
+    fn test_low_level_helpers() {
+        type Custom = DhKem<crate::dhkex::X25519, HkdfSha384, 0xFF42>;
+
+        assert_eq!(&kem_suite_id::<X25519HkdfSha256>(), b"KEM\x00\x20");
+        assert_eq!(&kem_suite_id::<Custom>(), b"KEM\xff\x42");
+        assert_eq!(
+            &full_suite_id::<AesGcm256, HkdfSha384, Custom>(),
+            b"HPKE\xff\x42\x00\x02\x00\x02"
+        );
+
+        assert_eq!(enforce_equal_len(32, 32), Ok(()));
+        assert_eq!(
+            enforce_equal_len(32, 31),
+            Err(HpkeError::IncorrectInputLength(32, 31))
+        );
+    }
+}
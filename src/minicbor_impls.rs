@@ -0,0 +1,298 @@
+//! This module defines minicbor::Encode and minicbor::Decode for all Serializable and
+//! Deserializable types defined in this crate, and for `Envelope`. This is gated under the
+//! `minicbor` feature.
+//!
+//! Keys, encapsulated keys, and tags are encoded as a CBOR byte string holding their
+//! `Serializable` encoding. A `Keypair` is encoded as its private key. An `Envelope` is encoded
+//! as the array
+//!
+//! ```text
+//! [version, kem_id, kdf_id, aead_id, enc: bytes, psk_id: bytes / null, ciphertext: bytes]
+//! ```
+//!
+//! with `aad_header: bytes` appended as an eighth element if the envelope has an AAD header.
+//!
+//! These encodings are stable. A change to any of them will come with a new envelope version.
+
+use crate::{
+    aead::{Aead, AeadTag},
+    dhkex,
+    kem::{self, Kem as KemTrait, Keypair},
+    Deserializable, Serializable,
+};
+
+use minicbor::{
+    decode::{Decoder, Error as DecodeError},
+    encode::{Encoder, Error as EncodeError, Write},
+    Decode, Encode,
+};
+
+#[cfg(feature = "alloc")]
+use crate::envelope::{Envelope, ENVELOPE_VERSION};
+
+// Reads a byte string and parses it as T
+fn decode_serializable<T: Deserializable>(d: &mut Decoder<'_>) -> Result<T, DecodeError> {
+    let bytes = d.bytes()?;
+    T::from_bytes(bytes).map_err(|_| DecodeError::message("invalid HPKE value encoding"))
+}
+
+// Implements minicbor::{Encode, Decode} for AeadTag<A: Aead>
+impl<A: Aead, C> Encode<C> for AeadTag<A> {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>, _: &mut C) -> Result<(), EncodeError<W::Error>> {
+        e.bytes(&self.to_bytes())?;
+        Ok(())
+    }
+}
+
+impl<'b, A: Aead, C> Decode<'b, C> for AeadTag<A> {
+    fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, DecodeError> {
+        decode_serializable(d)
+    }
+}
+
+// Implements minicbor::{Encode, Decode} over type t. This is identical to the above.
+macro_rules! impl_minicbor_noparam {
+    ($t:ty) => {
+        /// Implements `minicbor::Encode`
+        impl<C> Encode<C> for $t {
+            fn encode<W: Write>(
+                &self,
+                e: &mut Encoder<W>,
+                _: &mut C,
+            ) -> Result<(), EncodeError<W::Error>> {
+                e.bytes(&self.to_bytes())?;
+                Ok(())
+            }
+        }
+
+        /// Implements `minicbor::Decode`
+        impl<'b, C> Decode<'b, C> for $t {
+            fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, DecodeError> {
+                decode_serializable(d)
+            }
+        }
+    };
+}
+
+// Implement Encode/Decode for all PrivateKey, PublicKey, and EncappedKey types, as features
+// permit
+
+#[cfg(feature = "x25519")]
+impl_minicbor_noparam!(dhkex::x25519::PrivateKey);
+#[cfg(feature = "x25519")]
+impl_minicbor_noparam!(dhkex::x25519::PublicKey);
+#[cfg(feature = "x25519")]
+impl_minicbor_noparam!(kem::DhEncappedKey<dhkex::X25519>);
+
+#[cfg(feature = "p256")]
+impl_minicbor_noparam!(dhkex::ecdh_nistp::PrivateKey);
+#[cfg(feature = "p256")]
+impl_minicbor_noparam!(dhkex::ecdh_nistp::PublicKey);
+#[cfg(feature = "p256")]
+impl_minicbor_noparam!(kem::DhEncappedKey<dhkex::DhP256>);
+
+#[cfg(feature = "k256")]
+impl_minicbor_noparam!(dhkex::ecdh_k256::PrivateKey);
+#[cfg(feature = "k256")]
+impl_minicbor_noparam!(dhkex::ecdh_k256::PublicKey);
+#[cfg(feature = "k256")]
+impl_minicbor_noparam!(kem::DhEncappedKey<dhkex::DhK256>);
+
+// A Keypair is encoded as just its private key, same as with serde
+impl<Kem: KemTrait, C> Encode<C> for Keypair<Kem> {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>, _: &mut C) -> Result<(), EncodeError<W::Error>> {
+        e.bytes(&self.private().to_bytes())?;
+        Ok(())
+    }
+}
+
+impl<'b, Kem: KemTrait, C> Decode<'b, C> for Keypair<Kem> {
+    fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, DecodeError> {
+        decode_serializable::<Kem::PrivateKey>(d).map(Keypair::from_private_key)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<C> Encode<C> for Envelope {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>, _: &mut C) -> Result<(), EncodeError<W::Error>> {
+        e.array(if self.aad_header.is_some() { 8 } else { 7 })?
+            .u8(ENVELOPE_VERSION)?
+            .u16(self.kem_id)?
+            .u16(self.kdf_id)?
+            .u16(self.aead_id)?
+            .bytes(&self.encapped_key)?;
+        match &self.psk_id {
+            Some(psk_id) => e.bytes(psk_id)?,
+            None => e.null()?,
+        };
+        e.bytes(&self.ciphertext)?;
+        if let Some(aad_header) = &self.aad_header {
+            e.bytes(aad_header)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'b, C> Decode<'b, C> for Envelope {
+    fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, DecodeError> {
+        let len = d.array()?;
+        if len != Some(7) && len != Some(8) {
+            return Err(DecodeError::message(
+                "expected an envelope array of length 7 or 8",
+            ));
+        }
+        if d.u8()? != ENVELOPE_VERSION {
+            return Err(DecodeError::message("unknown envelope version"));
+        }
+        let kem_id = d.u16()?;
+        let kdf_id = d.u16()?;
+        let aead_id = d.u16()?;
+        let encapped_key = d.bytes()?;
+        let psk_id = match d.datatype()? {
+            minicbor::data::Type::Null => {
+                d.null()?;
+                None
+            }
+            _ => Some(d.bytes()?),
+        };
+        let ciphertext = d.bytes()?;
+        let aad_header = if len == Some(8) {
+            Some(d.bytes()?)
+        } else {
+            None
+        };
+
+        // Keep the binary encoding's invariant that these fit in a u16
+        if encapped_key.len() > u16::MAX as usize
+            || psk_id.map_or(0, <[u8]>::len) > u16::MAX as usize
+            || aad_header.map_or(0, <[u8]>::len) > u16::MAX as usize
+        {
+            return Err(DecodeError::message("envelope field too long"));
+        }
+
+        Ok(Envelope {
+            kem_id,
+            kdf_id,
+            aead_id,
+            encapped_key: encapped_key.to_vec(),
+            psk_id: psk_id.map(<[u8]>::to_vec),
+            aad_header: aad_header.map(<[u8]>::to_vec),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod test {
+    use crate::{
+        aead::AesGcm128,
+        envelope::{open_envelope, seal_to_envelope, seal_to_envelope_with_header, Envelope},
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, Keypair},
+        op_mode::{OpModeR, OpModeS, PskBundle},
+        Deserializable, Serializable,
+    };
+
+    use minicbor::{Decode, Encode};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // Checks that encoding and decoding the given data preserves its identity
+    fn assert_minicbor_roundtrip<T>(data: &T)
+    where
+        T: Serializable + Encode<()> + for<'a> Decode<'a, ()>,
+    {
+        let cbor = minicbor::to_vec(data).expect("couldn't encode data");
+        let reconstructed_data: T = minicbor::decode(&cbor).expect("couldn't decode data");
+        assert_eq!(data.to_bytes(), reconstructed_data.to_bytes());
+    }
+
+    /// Tests that minicbor's decode undoes whatever its encode does, for keys, encapped keys, and
+    /// envelopes
+    macro_rules! test_minicbor_roundtrip {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = AesGcm128;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                assert_minicbor_roundtrip(&sk_recip);
+                assert_minicbor_roundtrip(&pk_recip);
+
+                let keypair = Keypair::<Kem>::generate(&mut csprng);
+                let cbor = minicbor::to_vec(&keypair).unwrap();
+                let reconstructed_keypair: Keypair<Kem> = minicbor::decode(&cbor).unwrap();
+                assert_eq!(
+                    keypair.public().to_bytes(),
+                    reconstructed_keypair.public().to_bytes()
+                );
+
+                let psk = PskBundle {
+                    psk: b"a preshared key of sufficient length",
+                    psk_id: b"psk",
+                };
+                for (mode_s, mode_r) in [
+                    (OpModeS::<Kem>::Base, OpModeR::<Kem>::Base),
+                    (OpModeS::Psk(psk), OpModeR::Psk(psk)),
+                ] {
+                    let envelope = seal_to_envelope::<A, Kdf, Kem, _>(
+                        &mode_s,
+                        &pk_recip,
+                        b"info",
+                        b"msg",
+                        b"",
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    let encapped_key =
+                        <Kem as KemTrait>::EncappedKey::from_bytes(envelope.encapped_key())
+                            .unwrap();
+                    assert_minicbor_roundtrip(&encapped_key);
+
+                    let cbor = minicbor::to_vec(&envelope).unwrap();
+                    let decoded: Envelope = minicbor::decode(&cbor).unwrap();
+                    assert_eq!(decoded, envelope);
+                    let plaintext =
+                        open_envelope::<A, Kdf, Kem>(&mode_r, &sk_recip, &decoded, b"info", b"")
+                            .unwrap();
+                    assert_eq!(plaintext, b"msg");
+                }
+
+                // The AAD header is an eighth element
+                let envelope = seal_to_envelope_with_header::<A, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    b"info",
+                    b"msg",
+                    b"",
+                    b"header",
+                    &mut csprng,
+                )
+                .unwrap();
+                let cbor = minicbor::to_vec(&envelope).unwrap();
+                assert_eq!(cbor[0], 0x88);
+                let decoded: Envelope = minicbor::decode(&cbor).unwrap();
+                assert_eq!(decoded, envelope);
+
+                // Garbage doesn't decode
+                assert!(
+                    minicbor::decode::<<Kem as KemTrait>::PublicKey>(&[0x43, 1, 2, 3]).is_err()
+                );
+                assert!(minicbor::decode::<Envelope>(&[0x81, 0x02]).is_err());
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_minicbor_roundtrip!(test_minicbor_roundtrip_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_minicbor_roundtrip!(
+        test_minicbor_roundtrip_nistp256,
+        crate::kem::DhP256HkdfSha256
+    );
+    #[cfg(feature = "k256")]
+    test_minicbor_roundtrip!(test_minicbor_roundtrip_k256, crate::kem::DhK256HkdfSha256);
+}
@@ -1,5 +1,12 @@
 use crate::kem::Kem as KemTrait;
 
+use subtle::{Choice, ConstantTimeEq};
+
+#[cfg(feature = "low-entropy-psk")]
+mod low_entropy;
+#[cfg(feature = "low-entropy-psk")]
+pub use low_entropy::{LowEntropyPskParams, Psk};
+
 /// Contains preshared key bytes and an identifier. This is intended to go inside an `OpModeR` or
 /// `OpModeS` struct.
 ///
@@ -7,7 +14,8 @@ use crate::kem::Kem as KemTrait;
 /// ============
 /// `psk` MUST contain at least 32 bytes of entropy. Further, `psk.len()` SHOULD be at least as
 /// long as an extracted key from the KDF you use with `setup_sender`/`setup_receiver`, i.e., at
-/// least `Kdf::extracted_key_size()`.
+/// least `Kdf::extracted_key_size()`. To derive a PSK from a secret with less entropy than that,
+/// see `Psk::from_low_entropy`, under the `low-entropy-psk` feature.
 #[derive(Clone, Copy)]
 pub struct PskBundle<'a> {
     /// The preshared key
@@ -16,6 +24,14 @@ pub struct PskBundle<'a> {
     pub psk_id: &'a [u8],
 }
 
+// Compares the PSK and PSK ID in constant time. Since the bundle only borrows its contents, it
+// can't zeroize them. That's up to whoever owns the buffers.
+impl ConstantTimeEq for PskBundle<'_> {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.psk.ct_eq(other.psk) & self.psk_id.ct_eq(other.psk_id)
+    }
+}
+
 /// The operation mode of the HPKE session (receiver's view). This is how the sender authenticates
 /// their identity to the receiver. This authentication information can include a preshared key,
 /// the identity key of the sender, both, or neither. `Base` is the only mode that does not provide
@@ -0,0 +1,186 @@
+use super::PskBundle;
+use crate::{HpkeError, Vec};
+
+use zeroize::Zeroizing;
+
+// Prepended to the PSK ID to make the Argon2id salt, so these PSKs can't collide with Argon2id
+// hashes of the same secret made for something else
+const SALT_PREFIX: &[u8] = b"HPKE low-entropy PSK v1\x00";
+
+// The length of the stretched PSK. RFC 9180 §5.1.2 says a PSK SHOULD be at least Nh bytes long,
+// and 64 is Nh for the largest KDF here, HKDF-SHA512.
+const STRETCHED_PSK_LEN: usize = 64;
+
+/// The Argon2id cost parameters that `Psk::from_low_entropy` stretches a secret with. Sender and
+/// receiver must use the same ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LowEntropyPskParams {
+    /// Memory, in KiB
+    pub m_cost: u32,
+    /// Number of passes over the memory
+    pub t_cost: u32,
+    /// Number of lanes
+    pub p_cost: u32,
+}
+
+impl Default for LowEntropyPskParams {
+    /// Argon2id with 64 MiB of memory, 3 passes, and 4 lanes, the second recommended option of
+    /// RFC 9106 §4
+    fn default() -> LowEntropyPskParams {
+        LowEntropyPskParams {
+            m_cost: 64 * 1024,
+            t_cost: 3,
+            p_cost: 4,
+        }
+    }
+}
+
+/// An owned PSK and PSK ID. Borrow it as a `PskBundle` with `as_bundle` to put it in an `OpModeS`
+/// or `OpModeR`. The PSK is zeroized on drop.
+#[derive(Clone)]
+pub struct Psk {
+    psk: Zeroizing<[u8; STRETCHED_PSK_LEN]>,
+    psk_id: Vec<u8>,
+}
+
+impl Psk {
+    /// Stretches a low-entropy secret, like a short token or a passphrase, into a PSK with
+    /// Argon2id, so that every guess an attacker makes costs `params` worth of memory and time.
+    /// The salt is the PSK ID, with a domain-separating prefix, so guesses against one PSK ID
+    /// don't carry over to another. Sender and receiver must use the same `params` and `psk_id`.
+    ///
+    /// DANGER
+    /// ======
+    /// Stretching makes each guess expensive. It doesn't add entropy. RFC 9180 §9.5 says PSK mode
+    /// isn't secure against an attacker who can check guesses offline, which anyone holding a
+    /// ciphertext can, and that stays true here: a 6-digit code is still a million guesses. Pick
+    /// `params` as large as the slowest legitimate device can bear, and prefer high-entropy PSKs
+    /// where you can.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if `params` are out of the range Argon2id
+    /// accepts, e.g., if `m_cost` is less than `8 * p_cost`.
+    pub fn from_low_entropy(
+        secret: &[u8],
+        psk_id: &[u8],
+        params: LowEntropyPskParams,
+    ) -> Result<Psk, HpkeError> {
+        let mut salt = Vec::with_capacity(SALT_PREFIX.len() + psk_id.len());
+        salt.extend_from_slice(SALT_PREFIX);
+        salt.extend_from_slice(psk_id);
+
+        let argon2_params = argon2::Params::new(
+            params.m_cost,
+            params.t_cost,
+            params.p_cost,
+            Some(STRETCHED_PSK_LEN),
+        )
+        .map_err(|_| HpkeError::ValidationError)?;
+        let mut psk = Zeroizing::new([0u8; STRETCHED_PSK_LEN]);
+        argon2::Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            argon2_params,
+        )
+        .hash_password_into(secret, &salt, psk.as_mut())
+        .map_err(|_| HpkeError::ValidationError)?;
+
+        Ok(Psk {
+            psk,
+            psk_id: psk_id.to_vec(),
+        })
+    }
+
+    /// Returns the PSK ID
+    pub fn psk_id(&self) -> &[u8] {
+        &self.psk_id
+    }
+
+    /// Borrows this PSK as a `PskBundle`
+    pub fn as_bundle(&self) -> PskBundle<'_> {
+        PskBundle {
+            psk: self.psk.as_ref(),
+            psk_id: &self.psk_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LowEntropyPskParams, Psk};
+    use crate::HpkeError;
+
+    use subtle::ConstantTimeEq;
+
+    // Fast and weak, for tests only
+    const TEST_PARAMS: LowEntropyPskParams = LowEntropyPskParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    /// Tests that stretching is deterministic, depends on the secret, PSK ID, and params, and that
+    /// the resulting PSK works in PSK mode
+    #[test]
+    fn test_low_entropy_psk() {
+        let psk = Psk::from_low_entropy(b"123456", b"device-7", TEST_PARAMS).unwrap();
+        let same = Psk::from_low_entropy(b"123456", b"device-7", TEST_PARAMS).unwrap();
+        assert!(bool::from(psk.as_bundle().ct_eq(&same.as_bundle())));
+        assert_eq!(psk.psk_id(), b"device-7");
+
+        let other_params = LowEntropyPskParams {
+            t_cost: 2,
+            ..TEST_PARAMS
+        };
+        for other in [
+            Psk::from_low_entropy(b"123457", b"device-7", TEST_PARAMS).unwrap(),
+            Psk::from_low_entropy(b"123456", b"device-8", TEST_PARAMS).unwrap(),
+            Psk::from_low_entropy(b"123456", b"device-7", other_params).unwrap(),
+        ] {
+            assert_ne!(psk.as_bundle().psk, other.as_bundle().psk);
+        }
+
+        // Argon2id needs at least 8 KiB per lane
+        let bad_params = LowEntropyPskParams {
+            m_cost: 7,
+            ..TEST_PARAMS
+        };
+        assert_eq!(
+            Psk::from_low_entropy(b"123456", b"device-7", bad_params).err(),
+            Some(HpkeError::ValidationError)
+        );
+
+        #[cfg(feature = "x25519")]
+        {
+            use crate::{
+                aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait,
+                kem::X25519HkdfSha256, setup_receiver, setup_sender, OpModeR, OpModeS,
+            };
+            use rand::{rngs::StdRng, SeedableRng};
+
+            type A = ChaCha20Poly1305;
+            type Kdf = HkdfSha256;
+            type Kem = X25519HkdfSha256;
+
+            let mut csprng = StdRng::from_entropy();
+            let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+            let (encapped_key, mut sender_ctx) = setup_sender::<A, Kdf, Kem, _>(
+                &OpModeS::Psk(psk.as_bundle()),
+                &pk_recip,
+                b"info",
+                &mut csprng,
+            )
+            .unwrap();
+            let mut receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                &OpModeR::Psk(same.as_bundle()),
+                &sk_recip,
+                &encapped_key,
+                b"info",
+            )
+            .unwrap();
+            let ciphertext = sender_ctx.seal(b"msg", b"").unwrap();
+            assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), b"msg");
+        }
+    }
+}
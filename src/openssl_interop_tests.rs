@@ -0,0 +1,451 @@
+//! Round-trips messages between this crate and OpenSSL's HPKE implementation, in both directions,
+//! for the suites both support. This needs libcrypto from OpenSSL 3.2 or later, which is the first
+//! version with the `OSSL_HPKE_*` API. It's loaded at runtime, so nothing is linked at build time.
+//! Set the `OPENSSL_LIBCRYPTO` environment variable to its path, e.g.,
+//! `OPENSSL_LIBCRYPTO=/opt/openssl/lib/libcrypto.so.3 cargo test --features openssl-interop`, or
+//! leave it unset to use the one on the default library path.
+
+use crate::{
+    aead::{Aead, AesGcm128, AesGcm256, ChaCha20Poly1305},
+    kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
+    kem::Kem as KemTrait,
+    setup_receiver, setup_sender, Deserializable, OpModeR, OpModeS, PskBundle, Serializable,
+};
+
+extern crate std;
+use std::{
+    env,
+    ffi::CString,
+    os::raw::{c_char, c_int, c_uchar, c_void},
+    ptr,
+    vec::Vec,
+};
+
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+const OSSL_HPKE_ROLE_SENDER: c_int = 0;
+const OSSL_HPKE_ROLE_RECEIVER: c_int = 1;
+
+// From openssl/hpke.h
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OsslHpkeSuite {
+    kem_id: u16,
+    kdf_id: u16,
+    aead_id: u16,
+}
+
+// Opaque OpenSSL types
+type OsslHpkeCtxPtr = *mut c_void;
+type EvpPkeyPtr = *mut c_void;
+
+// RTLD_NOW has the same value on Linux and macOS
+const RTLD_NOW: c_int = 2;
+
+#[cfg_attr(target_os = "linux", link(name = "dl"))]
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+// Declares the table of libcrypto functions used here, and how to look them up
+macro_rules! libcrypto_fns {
+    ($($name:ident: fn($($arg:ty),*) -> $ret:ty;)*) => {
+        #[allow(non_snake_case)]
+        struct Libcrypto {
+            $($name: unsafe extern "C" fn($($arg),*) -> $ret,)*
+        }
+
+        impl Libcrypto {
+            // Returns None if any of the functions is missing
+            unsafe fn from_handle(handle: *mut c_void) -> Option<Libcrypto> {
+                Some(Libcrypto {
+                    $($name: {
+                        let name = concat!(stringify!($name), "\0");
+                        let sym = dlsym(handle, name.as_ptr() as *const c_char);
+                        if sym.is_null() {
+                            return None;
+                        }
+                        core::mem::transmute::<*mut c_void, unsafe extern "C" fn($($arg),*) -> $ret>(
+                            sym,
+                        )
+                    },)*
+                })
+            }
+        }
+    };
+}
+
+libcrypto_fns! {
+    OSSL_HPKE_CTX_new:
+        fn(c_int, OsslHpkeSuite, c_int, *mut c_void, *const c_char) -> OsslHpkeCtxPtr;
+    OSSL_HPKE_CTX_free: fn(OsslHpkeCtxPtr) -> ();
+    OSSL_HPKE_CTX_set1_psk: fn(OsslHpkeCtxPtr, *const c_char, *const c_uchar, usize) -> c_int;
+    OSSL_HPKE_CTX_set1_authpriv: fn(OsslHpkeCtxPtr, EvpPkeyPtr) -> c_int;
+    OSSL_HPKE_CTX_set1_authpub: fn(OsslHpkeCtxPtr, *const c_uchar, usize) -> c_int;
+    OSSL_HPKE_keygen: fn(
+        OsslHpkeSuite,
+        *mut c_uchar,
+        *mut usize,
+        *mut EvpPkeyPtr,
+        *const c_uchar,
+        usize,
+        *mut c_void,
+        *const c_char
+    ) -> c_int;
+    OSSL_HPKE_encap: fn(
+        OsslHpkeCtxPtr,
+        *mut c_uchar,
+        *mut usize,
+        *const c_uchar,
+        usize,
+        *const c_uchar,
+        usize
+    ) -> c_int;
+    OSSL_HPKE_decap:
+        fn(OsslHpkeCtxPtr, *const c_uchar, usize, EvpPkeyPtr, *const c_uchar, usize) -> c_int;
+    OSSL_HPKE_seal: fn(
+        OsslHpkeCtxPtr,
+        *mut c_uchar,
+        *mut usize,
+        *const c_uchar,
+        usize,
+        *const c_uchar,
+        usize
+    ) -> c_int;
+    OSSL_HPKE_open: fn(
+        OsslHpkeCtxPtr,
+        *mut c_uchar,
+        *mut usize,
+        *const c_uchar,
+        usize,
+        *const c_uchar,
+        usize
+    ) -> c_int;
+    OSSL_HPKE_export: fn(OsslHpkeCtxPtr, *mut c_uchar, usize, *const c_uchar, usize) -> c_int;
+    EVP_PKEY_free: fn(EvpPkeyPtr) -> ();
+}
+
+/// Loads libcrypto from `OPENSSL_LIBCRYPTO`, or from the default library path. If the variable is
+/// unset and there's no libcrypto with HPKE, returns `None` so the caller can skip. If it's set,
+/// failing to load is a hard failure, since an interop run that silently skips is worse than
+/// useless.
+fn load_libcrypto() -> Option<Libcrypto> {
+    let (candidates, explicit) = match env::var("OPENSSL_LIBCRYPTO") {
+        Ok(path) => (vec![path], true),
+        Err(_) => (
+            vec!["libcrypto.so.3".into(), "libcrypto.3.dylib".into()],
+            false,
+        ),
+    };
+
+    for path in candidates.iter() {
+        let c_path = CString::new(path.as_str()).unwrap();
+        let handle = unsafe { dlopen(c_path.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            continue;
+        }
+        // The handle is never closed, so the function pointers stay valid
+        match unsafe { Libcrypto::from_handle(handle) } {
+            Some(lib) => return Some(lib),
+            None if explicit => panic!("{} has no HPKE. It must be OpenSSL 3.2 or later.", path),
+            None => continue,
+        }
+    }
+
+    if explicit {
+        panic!("couldn't load {}", candidates[0]);
+    }
+    std::eprintln!("skipping OpenSSL interop: no libcrypto with HPKE found");
+    None
+}
+
+// Panics with the name of the OpenSSL function if it didn't return 1
+fn check(ret: c_int, func: &str) {
+    assert_eq!(ret, 1, "{} failed", func);
+}
+
+/// An OpenSSL private key, freed on drop
+struct PKey<'a>(&'a Libcrypto, EvpPkeyPtr);
+
+impl Drop for PKey<'_> {
+    fn drop(&mut self) {
+        unsafe { (self.0.EVP_PKEY_free)(self.1) }
+    }
+}
+
+/// Runs OpenSSL's DeriveKeyPair on `ikm`, and returns the serialized public key and the private
+/// key
+fn ossl_keygen<'a>(lib: &'a Libcrypto, suite: OsslHpkeSuite, ikm: &[u8]) -> (Vec<u8>, PKey<'a>) {
+    let mut pk = vec![0u8; 256];
+    let mut pk_len = pk.len();
+    let mut sk = ptr::null_mut();
+    check(
+        unsafe {
+            (lib.OSSL_HPKE_keygen)(
+                suite,
+                pk.as_mut_ptr(),
+                &mut pk_len,
+                &mut sk,
+                ikm.as_ptr(),
+                ikm.len(),
+                ptr::null_mut(),
+                ptr::null(),
+            )
+        },
+        "OSSL_HPKE_keygen",
+    );
+    pk.truncate(pk_len);
+    (pk, PKey(lib, sk))
+}
+
+/// An OpenSSL HPKE context, freed on drop
+struct OsslCtx<'a>(&'a Libcrypto, OsslHpkeCtxPtr);
+
+impl<'a> OsslCtx<'a> {
+    fn new(lib: &'a Libcrypto, mode: u8, suite: OsslHpkeSuite, role: c_int) -> OsslCtx<'a> {
+        let ctx = unsafe {
+            (lib.OSSL_HPKE_CTX_new)(mode as c_int, suite, role, ptr::null_mut(), ptr::null())
+        };
+        assert!(!ctx.is_null(), "OSSL_HPKE_CTX_new failed");
+        OsslCtx(lib, ctx)
+    }
+
+    // OpenSSL takes the PSK ID as a C string
+    fn set_psk(&self, psk: &PskBundle) {
+        let psk_id = CString::new(psk.psk_id).unwrap();
+        check(
+            unsafe {
+                (self.0.OSSL_HPKE_CTX_set1_psk)(
+                    self.1,
+                    psk_id.as_ptr(),
+                    psk.psk.as_ptr(),
+                    psk.psk.len(),
+                )
+            },
+            "OSSL_HPKE_CTX_set1_psk",
+        );
+    }
+
+    fn seal(&self, pt: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut ct = vec![0u8; pt.len() + 64];
+        let mut ct_len = ct.len();
+        check(
+            unsafe {
+                (self.0.OSSL_HPKE_seal)(
+                    self.1,
+                    ct.as_mut_ptr(),
+                    &mut ct_len,
+                    aad.as_ptr(),
+                    aad.len(),
+                    pt.as_ptr(),
+                    pt.len(),
+                )
+            },
+            "OSSL_HPKE_seal",
+        );
+        ct.truncate(ct_len);
+        ct
+    }
+
+    fn open(&self, ct: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut pt = vec![0u8; ct.len()];
+        let mut pt_len = pt.len();
+        check(
+            unsafe {
+                (self.0.OSSL_HPKE_open)(
+                    self.1,
+                    pt.as_mut_ptr(),
+                    &mut pt_len,
+                    aad.as_ptr(),
+                    aad.len(),
+                    ct.as_ptr(),
+                    ct.len(),
+                )
+            },
+            "OSSL_HPKE_open",
+        );
+        pt.truncate(pt_len);
+        pt
+    }
+
+    fn export(&self, label: &[u8], out: &mut [u8]) {
+        check(
+            unsafe {
+                (self.0.OSSL_HPKE_export)(
+                    self.1,
+                    out.as_mut_ptr(),
+                    out.len(),
+                    label.as_ptr(),
+                    label.len(),
+                )
+            },
+            "OSSL_HPKE_export",
+        );
+    }
+}
+
+impl Drop for OsslCtx<'_> {
+    fn drop(&mut self) {
+        unsafe { (self.0.OSSL_HPKE_CTX_free)(self.1) }
+    }
+}
+
+/// Checks that this crate and OpenSSL derive the same keypairs, and that messages and exports
+/// round-trip between them in both directions, in every mode
+fn interop_suite<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(lib: &Libcrypto) {
+    let suite = OsslHpkeSuite {
+        kem_id: Kem::KEM_ID,
+        kdf_id: Kdf::KDF_ID,
+        aead_id: A::AEAD_ID,
+    };
+    let mut csprng = StdRng::from_entropy();
+    let mut ikm_recip = [0u8; 32];
+    let mut ikm_sender = [0u8; 32];
+    let mut psk = [0u8; 32];
+    csprng.fill_bytes(&mut ikm_recip);
+    csprng.fill_bytes(&mut ikm_sender);
+    csprng.fill_bytes(&mut psk);
+    let psk = PskBundle {
+        psk: &psk,
+        psk_id: b"interop psk",
+    };
+    let (info, aad) = (b"interop info", b"interop aad");
+    // Two messages, so the sequence numbers have to agree too. OpenSSL won't seal an empty one.
+    let msgs: [&[u8]; 2] = [b"first message", b"second message"];
+
+    // DeriveKeyPair is deterministic, so both sides get the same keys from the same IKM
+    let (sk_recip, pk_recip) = Kem::derive_keypair(&ikm_recip);
+    let (sk_sender, pk_sender) = Kem::derive_keypair(&ikm_sender);
+    let (ossl_pk_recip, ossl_sk_recip) = ossl_keygen(lib, suite, &ikm_recip);
+    let (ossl_pk_sender, ossl_sk_sender) = ossl_keygen(lib, suite, &ikm_sender);
+    assert_eq!(pk_recip.to_bytes().as_slice(), &ossl_pk_recip[..]);
+    assert_eq!(pk_sender.to_bytes().as_slice(), &ossl_pk_sender[..]);
+
+    // Modes are numbered as in RFC 9180 §5 Table 1: Base, Psk, Auth, AuthPsk
+    for mode_id in 0u8..4 {
+        let (uses_psk, uses_auth) = (mode_id & 1 == 1, mode_id & 2 == 2);
+        let mode_s = match mode_id {
+            0 => OpModeS::Base,
+            1 => OpModeS::Psk(psk),
+            2 => OpModeS::Auth((sk_sender.clone(), pk_sender.clone())),
+            _ => OpModeS::AuthPsk((sk_sender.clone(), pk_sender.clone()), psk),
+        };
+        let mode_r = match mode_id {
+            0 => OpModeR::Base,
+            1 => OpModeR::Psk(psk),
+            2 => OpModeR::Auth(pk_sender.clone()),
+            _ => OpModeR::AuthPsk(pk_sender.clone(), psk),
+        };
+
+        // OpenSSL seals, and this crate opens
+        let ossl_ctx = OsslCtx::new(lib, mode_id, suite, OSSL_HPKE_ROLE_SENDER);
+        if uses_psk {
+            ossl_ctx.set_psk(&psk);
+        }
+        if uses_auth {
+            check(
+                unsafe { (lib.OSSL_HPKE_CTX_set1_authpriv)(ossl_ctx.1, ossl_sk_sender.1) },
+                "OSSL_HPKE_CTX_set1_authpriv",
+            );
+        }
+        let mut enc = vec![0u8; 256];
+        let mut enc_len = enc.len();
+        check(
+            unsafe {
+                (lib.OSSL_HPKE_encap)(
+                    ossl_ctx.1,
+                    enc.as_mut_ptr(),
+                    &mut enc_len,
+                    ossl_pk_recip.as_ptr(),
+                    ossl_pk_recip.len(),
+                    info.as_ptr(),
+                    info.len(),
+                )
+            },
+            "OSSL_HPKE_encap",
+        );
+        enc.truncate(enc_len);
+
+        let encapped_key = Kem::EncappedKey::from_bytes(&enc).unwrap();
+        let mut ctx_r =
+            setup_receiver::<A, Kdf, Kem>(&mode_r, &sk_recip, &encapped_key, info).unwrap();
+        for msg in msgs.iter() {
+            let ciphertext = ossl_ctx.seal(msg, aad);
+            assert_eq!(ctx_r.open(&ciphertext, aad).unwrap(), *msg);
+        }
+        let (mut exported, mut ossl_exported) = ([0u8; 48], [0u8; 48]);
+        ctx_r.export(b"interop export", &mut exported).unwrap();
+        ossl_ctx.export(b"interop export", &mut ossl_exported);
+        assert_eq!(exported, ossl_exported);
+
+        // This crate seals, and OpenSSL opens
+        let (encapped_key, mut ctx_s) =
+            setup_sender::<A, Kdf, Kem, _>(&mode_s, &pk_recip, info, &mut csprng).unwrap();
+        let ossl_ctx = OsslCtx::new(lib, mode_id, suite, OSSL_HPKE_ROLE_RECEIVER);
+        if uses_psk {
+            ossl_ctx.set_psk(&psk);
+        }
+        if uses_auth {
+            check(
+                unsafe {
+                    (lib.OSSL_HPKE_CTX_set1_authpub)(
+                        ossl_ctx.1,
+                        ossl_pk_sender.as_ptr(),
+                        ossl_pk_sender.len(),
+                    )
+                },
+                "OSSL_HPKE_CTX_set1_authpub",
+            );
+        }
+        let enc = encapped_key.to_bytes();
+        check(
+            unsafe {
+                (lib.OSSL_HPKE_decap)(
+                    ossl_ctx.1,
+                    enc.as_ptr(),
+                    enc.len(),
+                    ossl_sk_recip.1,
+                    info.as_ptr(),
+                    info.len(),
+                )
+            },
+            "OSSL_HPKE_decap",
+        );
+        for msg in msgs.iter() {
+            let ciphertext = ctx_s.seal(msg, aad).unwrap();
+            assert_eq!(ossl_ctx.open(&ciphertext, aad), *msg);
+        }
+        ctx_s.export(b"interop export", &mut exported).unwrap();
+        ossl_ctx.export(b"interop export", &mut ossl_exported);
+        assert_eq!(exported, ossl_exported);
+    }
+}
+
+macro_rules! test_interop {
+    ($test_name:ident, $kem:ty) => {
+        #[test]
+        fn $test_name() {
+            let lib = match load_libcrypto() {
+                Some(lib) => lib,
+                None => return,
+            };
+
+            macro_rules! for_kdf {
+                ($kdf:ty) => {
+                    interop_suite::<AesGcm128, $kdf, $kem>(&lib);
+                    interop_suite::<AesGcm256, $kdf, $kem>(&lib);
+                    interop_suite::<ChaCha20Poly1305, $kdf, $kem>(&lib);
+                };
+            }
+            for_kdf!(HkdfSha256);
+            for_kdf!(HkdfSha384);
+            for_kdf!(HkdfSha512);
+        }
+    };
+}
+
+#[cfg(feature = "x25519")]
+test_interop!(test_openssl_interop_x25519, crate::kem::X25519HkdfSha256);
+#[cfg(feature = "p256")]
+test_interop!(test_openssl_interop_p256, crate::kem::DhP256HkdfSha256);
@@ -0,0 +1,294 @@
+//! Property tests that run over every compiled (KEM, KDF, AEAD) combination. A new KEM, KDF, or
+//! AEAD gets these for free by adding it to the lists at the bottom of this file.
+//!
+//! The properties are
+//! * Round trip: keys and encapsulated keys survive serialization, and `open` undoes `seal` in
+//!   every mode
+//! * Non-identity: derived public keys are never the all-zero encoding, and the all-zero encoding
+//!   can't be encapsulated to
+//! * Tamper detection: flipping any bit of the ciphertext or encapsulated key, or changing the
+//!   AAD, makes `open` fail
+
+use crate::{
+    aead::Aead,
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS, PskBundle},
+    setup::{setup_receiver, setup_sender_deterministic},
+    Deserializable, Serializable, Vec,
+};
+
+use proptest::{prelude::*, sample::Index};
+
+// Keying material for deriving keys. Every KEM here has 32-byte private keys.
+fn ikm() -> impl Strategy<Value = [u8; 32]> {
+    any::<[u8; 32]>()
+}
+
+// Short byte strings for info strings, AADs, and PSK IDs
+fn short_bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), 0..64)
+}
+
+// Messages, including the empty one
+fn message() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), 0..512)
+}
+
+// The inputs to one HPKE exchange. `mode` selects Base, Psk, Auth, or AuthPsk.
+#[derive(Debug)]
+struct Exchange {
+    ikm_recip: [u8; 32],
+    ikm_sender: [u8; 32],
+    ikm_eph: [u8; 32],
+    mode: u8,
+    psk: [u8; 32],
+    psk_id: Vec<u8>,
+    info: Vec<u8>,
+    aad: Vec<u8>,
+    msg: Vec<u8>,
+}
+
+fn exchange() -> impl Strategy<Value = Exchange> {
+    (
+        (ikm(), ikm(), ikm()),
+        (0u8..4, any::<[u8; 32]>(), short_bytes()),
+        (short_bytes(), short_bytes(), message()),
+    )
+        .prop_map(
+            |((ikm_recip, ikm_sender, ikm_eph), (mode, psk, psk_id), (info, aad, msg))| Exchange {
+                ikm_recip,
+                ikm_sender,
+                ikm_eph,
+                mode,
+                psk,
+                psk_id,
+                info,
+                aad,
+                msg,
+            },
+        )
+}
+
+// Runs an exchange and returns the recipient's private key, the receiver's mode, the encapsulated
+// key, and the ciphertext
+fn seal_exchange<'a, A, Kdf, Kem>(
+    ex: &'a Exchange,
+) -> (Kem::PrivateKey, OpModeR<'a, Kem>, Kem::EncappedKey, Vec<u8>)
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let (sk_recip, pk_recip) = Kem::derive_keypair(&ex.ikm_recip);
+    let (sk_sender, pk_sender) = Kem::derive_keypair(&ex.ikm_sender);
+    let psk = PskBundle {
+        psk: &ex.psk,
+        psk_id: &ex.psk_id,
+    };
+    let (mode_s, mode_r) = match ex.mode {
+        0 => (OpModeS::Base, OpModeR::Base),
+        1 => (OpModeS::Psk(psk), OpModeR::Psk(psk)),
+        2 => (
+            OpModeS::Auth((sk_sender, pk_sender.clone())),
+            OpModeR::Auth(pk_sender),
+        ),
+        _ => (
+            OpModeS::AuthPsk((sk_sender, pk_sender.clone()), psk),
+            OpModeR::AuthPsk(pk_sender, psk),
+        ),
+    };
+
+    let (encapped_key, mut sender_ctx) =
+        setup_sender_deterministic::<A, Kdf, Kem>(&mode_s, &pk_recip, &ex.info, &ex.ikm_eph)
+            .unwrap();
+    let ciphertext = sender_ctx.seal(&ex.msg, &ex.aad).unwrap();
+
+    (sk_recip, mode_r, encapped_key, ciphertext)
+}
+
+// Opens the ciphertext of an exchange, with the given AAD
+fn open_exchange<A, Kdf, Kem>(
+    ex: &Exchange,
+    sk_recip: &Kem::PrivateKey,
+    mode_r: &OpModeR<Kem>,
+    encapped_key: &Kem::EncappedKey,
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Option<Vec<u8>>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut receiver_ctx =
+        setup_receiver::<A, Kdf, Kem>(mode_r, sk_recip, encapped_key, &ex.info).ok()?;
+    receiver_ctx.open(ciphertext, aad).ok()
+}
+
+fn prop_roundtrip<A, Kdf, Kem>(ex: &Exchange) -> Result<(), TestCaseError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let (sk_recip, mode_r, encapped_key, ciphertext) = seal_exchange::<A, Kdf, Kem>(ex);
+
+    let pk_recip = Kem::sk_to_pk(&sk_recip);
+    let sk_bytes = sk_recip.to_bytes();
+    let pk_bytes = pk_recip.to_bytes();
+    let enc_bytes = encapped_key.to_bytes();
+    prop_assert_eq!(Kem::PrivateKey::from_bytes(&sk_bytes)?.to_bytes(), sk_bytes);
+    prop_assert_eq!(Kem::PublicKey::from_bytes(&pk_bytes)?.to_bytes(), pk_bytes);
+    prop_assert_eq!(
+        Kem::EncappedKey::from_bytes(&enc_bytes)?.to_bytes(),
+        enc_bytes
+    );
+
+    let plaintext =
+        open_exchange::<A, Kdf, Kem>(ex, &sk_recip, &mode_r, &encapped_key, &ciphertext, &ex.aad);
+    prop_assert_eq!(plaintext.as_ref(), Some(&ex.msg));
+    Ok(())
+}
+
+fn prop_non_identity<Kem: KemTrait>(
+    ikm_recip: &[u8; 32],
+    ikm_eph: &[u8; 32],
+) -> Result<(), TestCaseError> {
+    let (_, pk) = Kem::derive_keypair(ikm_recip);
+    prop_assert!(pk.to_bytes().iter().any(|&b| b != 0));
+
+    // The all-zero encoding is either unparseable or can't be encapsulated to
+    let mut zeros = pk.to_bytes();
+    zeros.fill(0);
+    if let Ok(zero_pk) = Kem::PublicKey::from_bytes(&zeros) {
+        prop_assert!(Kem::encap_with_ikm(&zero_pk, None, ikm_eph).is_err());
+    }
+    Ok(())
+}
+
+fn prop_tamper<A, Kdf, Kem>(ex: &Exchange, idx: Index, bit: u8) -> Result<(), TestCaseError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let (sk_recip, mode_r, encapped_key, ciphertext) = seal_exchange::<A, Kdf, Kem>(ex);
+
+    // A flipped bit anywhere in the ciphertext
+    let mut bad_ciphertext = ciphertext.clone();
+    bad_ciphertext[idx.index(ciphertext.len())] ^= 1 << bit;
+    prop_assert!(open_exchange::<A, Kdf, Kem>(
+        ex,
+        &sk_recip,
+        &mode_r,
+        &encapped_key,
+        &bad_ciphertext,
+        &ex.aad
+    )
+    .is_none());
+
+    // A different AAD
+    let mut bad_aad = ex.aad.clone();
+    bad_aad.push(0);
+    prop_assert!(open_exchange::<A, Kdf, Kem>(
+        ex,
+        &sk_recip,
+        &mode_r,
+        &encapped_key,
+        &ciphertext,
+        &bad_aad
+    )
+    .is_none());
+
+    // A flipped bit anywhere in the encapsulated key. If it still parses, it makes a different
+    // shared secret, since the encapsulated key is part of the KEM context.
+    let mut bad_enc = encapped_key.to_bytes();
+    let enc_idx = idx.index(bad_enc.len());
+    bad_enc[enc_idx] ^= 1 << bit;
+    if let Ok(bad_encapped_key) = Kem::EncappedKey::from_bytes(&bad_enc) {
+        prop_assert!(open_exchange::<A, Kdf, Kem>(
+            ex,
+            &sk_recip,
+            &mode_r,
+            &bad_encapped_key,
+            &ciphertext,
+            &ex.aad
+        )
+        .is_none());
+    }
+    Ok(())
+}
+
+// Each suite runs every property this many times. There are up to 27 suites, so this is kept low
+// enough that a debug-build `cargo test` stays quick.
+const CASES: u32 = 16;
+
+// Instantiates the round-trip and tamper properties for one (KEM, KDF, AEAD) combination
+macro_rules! suite_props {
+    ($mod_name:ident, $aead:ty, $kdf:ty, $kem:ty) => {
+        mod $mod_name {
+            use super::*;
+
+            type A = $aead;
+            type Kdf = $kdf;
+            type Kem = $kem;
+
+            proptest! {
+                #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+                #[test]
+                fn roundtrip(ex in exchange()) {
+                    prop_roundtrip::<A, Kdf, Kem>(&ex)?;
+                }
+
+                #[test]
+                fn tamper(ex in exchange(), idx in any::<Index>(), bit in 0u8..8) {
+                    prop_tamper::<A, Kdf, Kem>(&ex, idx, bit)?;
+                }
+            }
+        }
+    };
+}
+
+// Instantiates the non-identity property for the given KEM, and suite_props for every KDF and
+// AEAD with it
+macro_rules! kem_props {
+    ($mod_name:ident, $kem:ty) => {
+        mod $mod_name {
+            use super::*;
+            use crate::{
+                aead::{AesGcm128, AesGcm256, AesOcb128, AesOcb256, ChaCha20Poly1305},
+                kdf::{HkdfSha256, HkdfSha384, HkdfSha512},
+            };
+
+            proptest! {
+                #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+                #[test]
+                fn non_identity(ikm_recip in ikm(), ikm_eph in ikm()) {
+                    prop_non_identity::<$kem>(&ikm_recip, &ikm_eph)?;
+                }
+            }
+
+            suite_props!(sha256_aes128, AesGcm128, HkdfSha256, $kem);
+            suite_props!(sha256_aes256, AesGcm256, HkdfSha256, $kem);
+            suite_props!(sha256_chacha, ChaCha20Poly1305, HkdfSha256, $kem);
+            suite_props!(sha384_aes128, AesGcm128, HkdfSha384, $kem);
+            suite_props!(sha384_aes256, AesGcm256, HkdfSha384, $kem);
+            suite_props!(sha384_chacha, ChaCha20Poly1305, HkdfSha384, $kem);
+            suite_props!(sha512_aes128, AesGcm128, HkdfSha512, $kem);
+            suite_props!(sha512_aes256, AesGcm256, HkdfSha512, $kem);
+            suite_props!(sha512_chacha, ChaCha20Poly1305, HkdfSha512, $kem);
+            suite_props!(sha256_ocb128, AesOcb128, HkdfSha256, $kem);
+            suite_props!(sha256_ocb256, AesOcb256, HkdfSha256, $kem);
+        }
+    };
+}
+
+#[cfg(feature = "x25519")]
+kem_props!(x25519, crate::kem::X25519HkdfSha256);
+#[cfg(feature = "p256")]
+kem_props!(p256, crate::kem::DhP256HkdfSha256);
+#[cfg(feature = "k256")]
+kem_props!(k256, crate::kem::DhK256HkdfSha256);
@@ -0,0 +1,265 @@
+//! A compact encoding of recipient public keys for QR codes, so devices can be provisioned by
+//! scanning one. This is gated under the `bech32` feature.
+//!
+//! A recipient code carries the public key together with the full ciphersuite it's to be used
+//! under, and a bech32m checksum that catches misreads. It's written in uppercase, since QR codes
+//! store uppercase letters and digits in alphanumeric mode, which is denser than byte mode. Any
+//! KEM's public keys can be encoded. Parsing accepts either case.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::X25519HkdfSha256, qr, Kem};
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (_, pk) = K::gen_keypair(&mut csprng);
+//!
+//! // Put this in the QR code
+//! let code = qr::render_recipient::<A, Kdf, K>(&pk);
+//! assert!(code.starts_with("HPKEQR1"));
+//!
+//! // And this on the device that scans it
+//! let suite = qr::recipient_suite(&code).unwrap();
+//! assert_eq!(suite.kem_id, 0x0020);
+//! let pk = qr::parse_recipient::<A, Kdf, K>(&code).unwrap();
+//! # }
+//! ```
+//!
+//! The format is
+//!
+//! ```text
+//! code = Bech32m("hpkeqr", version (1) || kem_id (2) || kdf_id (2) || aead_id (2) || pk)
+//! ```
+//!
+//! in uppercase, with all integers big-endian. An X25519 recipient is 76 characters long. A P-256
+//! or K-256 one is 129, since their public keys are serialized uncompressed.
+
+use crate::{
+    aead::Aead, kdf::Kdf as KdfTrait, kem::Kem as KemTrait, Deserializable, HpkeError,
+    Serializable, Vec,
+};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::string::String;
+
+use bech32::{FromBase32, ToBase32, Variant};
+use byteorder::{BigEndian, ByteOrder};
+
+/// The bech32m human-readable part of recipient codes. Codes are written in uppercase.
+pub const QR_RECIPIENT_HRP: &str = "hpkeqr";
+
+/// The version byte that `render_recipient` writes, and the only one the parsers accept
+pub const QR_RECIPIENT_VERSION: u8 = 1;
+
+// version || kem_id || kdf_id || aead_id
+const HEADER_LEN: usize = 7;
+
+/// The ciphersuite that a recipient code says to use. See `recipient_suite`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QrSuite {
+    /// The KEM's algorithm identifier
+    pub kem_id: u16,
+    /// The KDF's algorithm identifier
+    pub kdf_id: u16,
+    /// The AEAD's algorithm identifier
+    pub aead_id: u16,
+}
+
+/// Encodes `pk` as an uppercase recipient code for the suite `(A, Kdf, Kem)`, in the format
+/// described in the module docs
+pub fn render_recipient<A, Kdf, Kem>(pk: &Kem::PublicKey) -> String
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut payload = Vec::with_capacity(HEADER_LEN + Kem::PublicKey::size());
+    let mut header = [0u8; HEADER_LEN];
+    header[0] = QR_RECIPIENT_VERSION;
+    BigEndian::write_u16(&mut header[1..3], Kem::KEM_ID);
+    BigEndian::write_u16(&mut header[3..5], Kdf::KDF_ID);
+    BigEndian::write_u16(&mut header[5..7], A::AEAD_ID);
+    payload.extend_from_slice(&header);
+    payload.extend_from_slice(&pk.to_bytes());
+
+    bech32::encode(QR_RECIPIENT_HRP, payload.to_base32(), Variant::Bech32m)
+        .expect("HRP is valid bech32")
+        .to_uppercase()
+}
+
+/// Reads the ciphersuite off a recipient code, for deciding which suite to parse it under
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if the code is malformed, its checksum is wrong, or
+/// its version isn't `QR_RECIPIENT_VERSION`.
+pub fn recipient_suite(code: &str) -> Result<QrSuite, HpkeError> {
+    decode(code).map(|(suite, _)| suite)
+}
+
+/// Parses a recipient code for the suite `(A, Kdf, Kem)`, in either case
+///
+/// Return Value
+/// ============
+/// Returns the public key on success. Returns `Err(HpkeError::ValidationError)` if the code is
+/// malformed, its checksum is wrong, its version isn't `QR_RECIPIENT_VERSION`, or it's for a
+/// different suite. Otherwise, returns whatever error `from_bytes` returns on the key.
+pub fn parse_recipient<A, Kdf, Kem>(code: &str) -> Result<Kem::PublicKey, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let (suite, pk_bytes) = decode(code)?;
+    let expected = QrSuite {
+        kem_id: Kem::KEM_ID,
+        kdf_id: Kdf::KDF_ID,
+        aead_id: A::AEAD_ID,
+    };
+    if suite != expected {
+        return Err(HpkeError::ValidationError);
+    }
+    Kem::PublicKey::from_bytes(&pk_bytes)
+}
+
+// Decodes a recipient code into its suite and the public key bytes
+fn decode(code: &str) -> Result<(QrSuite, Vec<u8>), HpkeError> {
+    let (hrp, data, variant) = bech32::decode(code).map_err(|_| HpkeError::ValidationError)?;
+    if hrp != QR_RECIPIENT_HRP || variant != Variant::Bech32m {
+        return Err(HpkeError::ValidationError);
+    }
+    let payload = Vec::<u8>::from_base32(&data).map_err(|_| HpkeError::ValidationError)?;
+    if payload.len() < HEADER_LEN || payload[0] != QR_RECIPIENT_VERSION {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let suite = QrSuite {
+        kem_id: BigEndian::read_u16(&payload[1..3]),
+        kdf_id: BigEndian::read_u16(&payload[3..5]),
+        aead_id: BigEndian::read_u16(&payload[5..7]),
+    };
+    Ok((suite, payload[HEADER_LEN..].to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_recipient, recipient_suite, render_recipient, QrSuite};
+    use crate::{
+        aead::{AesGcm128, ChaCha20Poly1305},
+        kdf::{HkdfSha256, HkdfSha384},
+        kem::Kem as KemTrait,
+        HpkeError, Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // The characters a QR code can store in alphanumeric mode
+    const QR_ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+    macro_rules! test_qr_roundtrip {
+        ($test_name:ident, $kem:ty, $len:expr) => {
+            /// Tests that recipient codes round-trip in either case, fit QR alphanumeric mode,
+            /// and don't parse under another suite
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (_, pk) = Kem::gen_keypair(&mut csprng);
+
+                let code = render_recipient::<A, Kdf, Kem>(&pk);
+                assert_eq!(code.len(), $len);
+                assert!(code.bytes().all(|b| QR_ALPHANUMERIC.contains(&b)));
+                assert_eq!(
+                    recipient_suite(&code).unwrap(),
+                    QrSuite {
+                        kem_id: Kem::KEM_ID,
+                        kdf_id: 0x0001,
+                        aead_id: 0x0003,
+                    }
+                );
+
+                for code in [code.clone(), code.to_lowercase()] {
+                    let parsed = parse_recipient::<A, Kdf, Kem>(&code).unwrap();
+                    assert_eq!(parsed.to_bytes(), pk.to_bytes());
+                }
+
+                assert_eq!(
+                    parse_recipient::<AesGcm128, Kdf, Kem>(&code).err(),
+                    Some(HpkeError::ValidationError)
+                );
+                assert_eq!(
+                    parse_recipient::<A, HkdfSha384, Kem>(&code).err(),
+                    Some(HpkeError::ValidationError)
+                );
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_qr_roundtrip!(test_qr_roundtrip_x25519, crate::kem::X25519HkdfSha256, 76);
+    #[cfg(feature = "p256")]
+    test_qr_roundtrip!(test_qr_roundtrip_p256, crate::kem::DhP256HkdfSha256, 129);
+    #[cfg(feature = "k256")]
+    test_qr_roundtrip!(test_qr_roundtrip_k256, crate::kem::DhK256HkdfSha256, 129);
+
+    /// Tests that misread, mixed-case, and foreign codes are rejected
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_qr_errors() {
+        use bech32::{ToBase32, Variant};
+
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk) = Kem::gen_keypair(&mut csprng);
+        let code = render_recipient::<A, Kdf, Kem>(&pk);
+
+        // A single misread character breaks the checksum
+        let mut misread = code.clone().into_bytes();
+        let last = misread.len() - 1;
+        misread[last] = if misread[last] == b'Q' { b'P' } else { b'Q' };
+        let misread = super::String::from_utf8(misread).unwrap();
+
+        let mut payload = [1u8, 0x00, 0x20, 0x00, 0x01, 0x00, 0x03].to_vec();
+        payload.extend_from_slice(&pk.to_bytes());
+        let encode = |hrp: &str, payload: &[u8], variant| {
+            bech32::encode(hrp, payload.to_base32(), variant).unwrap()
+        };
+        let mut wrong_version = payload.clone();
+        wrong_version[0] = 2;
+
+        for bad in [
+            misread,
+            format!("{}{}", &code[..10], code[10..].to_lowercase()),
+            encode("hpkeqr", &payload, Variant::Bech32),
+            encode("hpke", &payload, Variant::Bech32m),
+            encode("hpkeqr", &wrong_version, Variant::Bech32m),
+            encode("hpkeqr", &payload[..5], Variant::Bech32m),
+        ] {
+            assert_eq!(recipient_suite(&bad), Err(HpkeError::ValidationError));
+            assert_eq!(
+                parse_recipient::<A, Kdf, Kem>(&bad).err(),
+                Some(HpkeError::ValidationError)
+            );
+        }
+
+        // The right suite with the wrong key length fails in from_bytes
+        assert_eq!(
+            parse_recipient::<A, Kdf, Kem>(&encode("hpkeqr", &payload[..20], Variant::Bech32m))
+                .err(),
+            Some(HpkeError::IncorrectInputLength(32, 13))
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Lets RNGs written against `rand_core` 0.9 be used with this crate, whose APIs take `rand_core`
+//! 0.6 RNGs
+
+use rand_core_09::{CryptoRng as CryptoRng09, RngCore as RngCore09};
+
+/// Wraps a `rand_core` 0.9 RNG so it can be passed anywhere this crate takes an RNG, e.g.,
+/// `Kem::gen_keypair(&mut RngCompat(&mut rng))`. This works with both owned RNGs and `&mut`
+/// references to them, since `rand_core` 0.9 implements its traits for the latter.
+#[derive(Clone, Debug)]
+pub struct RngCompat<R>(pub R);
+
+impl<R: RngCore09> rand_core::RngCore for RngCompat<R> {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    // rand_core 0.9 RNGs are infallible, so this never fails
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// A rand_core 0.9 CryptoRng has the same meaning as a rand_core 0.6 one
+impl<R: CryptoRng09> rand_core::CryptoRng for RngCompat<R> {}
+
+#[cfg(all(test, feature = "x25519"))]
+mod tests {
+    use super::RngCompat;
+    use crate::{kem::X25519HkdfSha256, Kem, Serializable};
+
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    /// A rand_core 0.9 RNG that draws from a rand 0.8 StdRng
+    struct Rng09(StdRng);
+
+    impl rand_core_09::RngCore for Rng09 {
+        fn next_u32(&mut self) -> u32 {
+            self.0.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0.next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.0.fill_bytes(dest)
+        }
+    }
+
+    impl rand_core_09::CryptoRng for Rng09 {}
+
+    /// Tests that a wrapped rand_core 0.9 RNG can be used to generate keys and encapsulate, and
+    /// that it produces the same output as the RNG it draws from
+    #[test]
+    fn test_rng_compat() {
+        type Kem = X25519HkdfSha256;
+
+        let mut rng09 = Rng09(StdRng::seed_from_u64(0x09));
+        let mut rng06 = StdRng::seed_from_u64(0x09);
+
+        // Wrap a reference, so rng09 can be used again afterwards
+        let (sk1, pk1) = Kem::gen_keypair(&mut RngCompat(&mut rng09));
+        let (sk2, pk2) = Kem::gen_keypair(&mut rng06);
+        assert_eq!(sk1.to_bytes(), sk2.to_bytes());
+        assert_eq!(pk1.to_bytes(), pk2.to_bytes());
+
+        // Wrap an owned RNG
+        let (ss, encapped_key) = Kem::encap(&pk1, None, &mut RngCompat(rng09)).unwrap();
+        let decapped_ss = Kem::decap(&sk1, None, &encapped_key).unwrap();
+        assert_eq!(ss.0, decapped_ss.0);
+    }
+}
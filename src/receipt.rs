@@ -0,0 +1,239 @@
+//! Receipts: per-message tags, exported from an HPKE context, that let a receiver show it opened a
+//! message without revealing the plaintext.
+//!
+//! When sealing, the sender computes `sender_receipt_tag` for the message and keeps it, e.g., in
+//! an audit log. After opening, the receiver computes `receiver_receipt_tag` for the same message
+//! and hands it back. If the two match, as checked with `ReceiptTag::verify`, the receiver held
+//! the context and the plaintext of that exact message, along with its sequence number and AAD.
+//! The tag is a PRF output under a key only the two sides have, so it says nothing about the
+//! plaintext to anyone else.
+//!
+//! A receipt proves this to the sender, or to an auditor that trusts the sender's log. It isn't
+//! proof to an outsider, since the sender can compute every receipt on its own.
+//!
+//! ```
+//! # #[cfg(all(feature = "x25519", feature = "alloc"))]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     receipt::{receiver_receipt_tag, sender_receipt_tag},
+//!     Kem, OpModeR, OpModeS,
+//! };
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_recip, pk_recip) = K::gen_keypair(&mut csprng);
+//! let (encapped_key, mut sender_ctx) =
+//!     hpke::setup_sender::<A, Kdf, K, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+//!         .unwrap();
+//! let mut receiver_ctx =
+//!     hpke::setup_receiver::<A, Kdf, K>(&OpModeR::Base, &sk_recip, &encapped_key, b"info")
+//!         .unwrap();
+//!
+//! // The sender notes the sequence number before sealing
+//! let seq = sender_ctx.seq();
+//! let ciphertext = sender_ctx.seal(b"token", b"aad").unwrap();
+//! let expected = sender_receipt_tag(&sender_ctx, seq, b"aad", b"token");
+//!
+//! let seq = receiver_ctx.seq();
+//! let plaintext = receiver_ctx.open(&ciphertext, b"aad").unwrap();
+//! let receipt = receiver_receipt_tag(&receiver_ctx, seq, b"aad", &plaintext);
+//!
+//! expected.verify(&receipt).unwrap();
+//! # }
+//! ```
+//!
+//! The tag of the message with sequence number `seq`, where `LabeledExpand` is under the full
+//! suite ID of the context, is
+//!
+//! ```text
+//! receipt_key = Export("HPKE receipt", Nh)
+//! tag         = LabeledExpand(receipt_key, "rcpt",
+//!                             I2OSP(seq, 8) || I2OSP(len(aad), 8) || aad || pt, 32)
+//! ```
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS},
+    kdf::{Kdf as KdfTrait, LabeledExpand, SimpleHkdf},
+    kem::Kem as KemTrait,
+    setup::ExporterSecret,
+    util::{enforce_equal_len, full_suite_id},
+    Deserializable, HpkeError, Serializable,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use generic_array::{typenum, GenericArray};
+use subtle::{Choice, ConstantTimeEq};
+
+/// The length of a `ReceiptTag`, in bytes
+pub const RECEIPT_TAG_LEN: usize = 32;
+
+// The exporter context that the receipt key is exported under
+const RECEIPT_EXPORTER_CTX: &[u8] = b"HPKE receipt";
+
+/// A receipt for one message. See the module docs.
+#[derive(Clone, Debug)]
+pub struct ReceiptTag([u8; RECEIPT_TAG_LEN]);
+
+impl ReceiptTag {
+    /// Checks that this receipt equals `other`, in constant time
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if the receipts are equal, and `Err(HpkeError::ValidationError)`
+    /// otherwise.
+    pub fn verify(&self, other: &ReceiptTag) -> Result<(), HpkeError> {
+        if bool::from(self.ct_eq(other)) {
+            Ok(())
+        } else {
+            Err(HpkeError::ValidationError)
+        }
+    }
+}
+
+impl ConstantTimeEq for ReceiptTag {
+    fn ct_eq(&self, other: &ReceiptTag) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl Serializable for ReceiptTag {
+    type OutputSize = typenum::U32;
+
+    fn to_bytes(&self) -> GenericArray<u8, typenum::U32> {
+        GenericArray::clone_from_slice(&self.0)
+    }
+}
+
+impl Deserializable for ReceiptTag {
+    fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        enforce_equal_len(RECEIPT_TAG_LEN, encoded.len())?;
+        let mut arr = [0u8; RECEIPT_TAG_LEN];
+        arr.copy_from_slice(encoded);
+        Ok(ReceiptTag(arr))
+    }
+}
+
+/// Computes the receipt for the message that `ctx` sealed with sequence number `seq`, i.e., what
+/// `ctx.seq()` returned right before the seal, under `aad`
+pub fn sender_receipt_tag<A, Kdf, Kem>(
+    ctx: &AeadCtxS<A, Kdf, Kem>,
+    seq: u64,
+    aad: &[u8],
+    plaintext: &[u8],
+) -> ReceiptTag
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    receipt_tag::<A, Kdf, Kem>(|c, out| ctx.export(c, out), seq, aad, plaintext)
+}
+
+/// Computes the receipt for the message that `ctx` opened with sequence number `seq`, i.e., what
+/// `ctx.seq()` returned right before the open, under `aad`. This equals the sender's
+/// `sender_receipt_tag` for the same message.
+pub fn receiver_receipt_tag<A, Kdf, Kem>(
+    ctx: &AeadCtxR<A, Kdf, Kem>,
+    seq: u64,
+    aad: &[u8],
+    plaintext: &[u8],
+) -> ReceiptTag
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    receipt_tag::<A, Kdf, Kem>(|c, out| ctx.export(c, out), seq, aad, plaintext)
+}
+
+// Computes the receipt as described in the module docs. `export` fills its second argument with
+// the export of the HPKE context under the exporter context in its first.
+fn receipt_tag<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(
+    export: impl FnOnce(&[u8], &mut [u8]) -> Result<(), HpkeError>,
+    seq: u64,
+    aad: &[u8],
+    plaintext: &[u8],
+) -> ReceiptTag {
+    let mut receipt_key = <ExporterSecret<Kdf> as Default>::default();
+    export(RECEIPT_EXPORTER_CTX, receipt_key.0.as_mut_slice())
+        .expect("receipt key is short enough to export");
+
+    let mut prefix = [0u8; 16];
+    BigEndian::write_u64(&mut prefix[..8], seq);
+    BigEndian::write_u64(&mut prefix[8..], aad.len() as u64);
+
+    // The key is a full digest, and the tag is at most a digest long, so neither of these can
+    // fail
+    let mut tag = [0u8; RECEIPT_TAG_LEN];
+    SimpleHkdf::<Kdf>::from_prk(receipt_key.0.as_slice())
+        .unwrap()
+        .labeled_expand_multi(
+            &full_suite_id::<A, Kdf, Kem>(),
+            b"rcpt",
+            &[&prefix, aad, plaintext],
+            &mut tag,
+        )
+        .unwrap();
+    ReceiptTag(tag)
+}
+
+#[cfg(all(test, feature = "x25519", feature = "alloc"))]
+mod test {
+    use super::{receiver_receipt_tag, sender_receipt_tag, ReceiptTag};
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, test_util::gen_ctx_simple_pair, Deserializable,
+        HpkeError, Serializable,
+    };
+
+    /// Tests that both sides compute the same receipt, and that it's bound to the sequence
+    /// number, AAD, plaintext, and context
+    #[test]
+    fn test_receipt_tags() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let mut receipts = [None, None];
+        for (seq, msg) in [b"first", b"other"].iter().enumerate() {
+            let seq = seq as u64;
+            assert_eq!(sender_ctx.seq(), seq);
+            let ct = sender_ctx.seal(*msg, b"aad").unwrap();
+            let pt = receiver_ctx.open(&ct, b"aad").unwrap();
+
+            let expected = sender_receipt_tag(&sender_ctx, seq, b"aad", *msg);
+            let receipt = receiver_receipt_tag(&receiver_ctx, seq, b"aad", &pt);
+            expected.verify(&receipt).unwrap();
+
+            let decoded = ReceiptTag::from_bytes(&receipt.to_bytes()).unwrap();
+            expected.verify(&decoded).unwrap();
+            receipts[seq as usize] = Some(receipt);
+        }
+
+        let [first, _] = receipts;
+        let first = first.unwrap();
+        let (other_sender_ctx, _) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        for wrong in [
+            sender_receipt_tag(&sender_ctx, 1, b"aad", b"first"),
+            sender_receipt_tag(&sender_ctx, 0, b"aae", b"first"),
+            sender_receipt_tag(&sender_ctx, 0, b"aad", b"firsT"),
+            // Moving bytes between the AAD and plaintext changes the receipt
+            sender_receipt_tag(&sender_ctx, 0, b"aadf", b"irst"),
+            sender_receipt_tag(&other_sender_ctx, 0, b"aad", b"first"),
+        ] {
+            assert_eq!(first.verify(&wrong), Err(HpkeError::ValidationError));
+        }
+
+        assert_eq!(
+            ReceiptTag::from_bytes(&[0u8; 31]).err(),
+            Some(HpkeError::IncorrectInputLength(32, 31))
+        );
+    }
+}
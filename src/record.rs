@@ -0,0 +1,535 @@
+//! A DTLS-style record layer keyed from an HPKE context, for datagram protocols. Records carry
+//! their epoch and sequence number in the clear, so they can be lost, reordered, or duplicated in
+//! transit, and the receiver still opens each one at most once.
+//!
+//! Each direction of traffic has its own secret, exported from the HPKE context. Both sides of the
+//! context can export either, so the receiver of the HPKE context can also write records back to
+//! the sender. Every epoch has its own key and IV, and moving to the next epoch ratchets the
+//! secret forward and forgets the old one, as in TLS 1.3's key update. A writer moves on with
+//! `update_epoch`. A reader follows by itself once a record from the next epoch opens, and keeps
+//! the previous epoch's keys around for stragglers.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::{AesGcm128, ChaCha20Poly1305},
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     record::{Direction, RecordReader, RecordWriter},
+//!     Kem, OpModeR, OpModeS,
+//! };
+//!
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_recip, pk_recip) = K::gen_keypair(&mut csprng);
+//! let (encapped_key, sender_ctx) = hpke::setup_sender::<ChaCha20Poly1305, HkdfSha256, K, _>(
+//!     &OpModeS::Base,
+//!     &pk_recip,
+//!     b"dtls-ish",
+//!     &mut csprng,
+//! )
+//! .unwrap();
+//! let receiver_ctx = hpke::setup_receiver::<ChaCha20Poly1305, HkdfSha256, K>(
+//!     &OpModeR::Base,
+//!     &sk_recip,
+//!     &encapped_key,
+//!     b"dtls-ish",
+//! )
+//! .unwrap();
+//!
+//! let mut writer =
+//!     RecordWriter::<AesGcm128, _>::from_sender_ctx(&sender_ctx, Direction::SenderToReceiver);
+//! let mut reader =
+//!     RecordReader::<AesGcm128, _>::from_receiver_ctx(&receiver_ctx, Direction::SenderToReceiver);
+//!
+//! let first = writer.seal(b"first", b"").unwrap();
+//! writer.update_epoch().unwrap();
+//! let second = writer.seal(b"second", b"").unwrap();
+//!
+//! // Records open out of order, across the epoch change
+//! assert_eq!(reader.open(&second, b"").unwrap(), b"second");
+//! assert_eq!(reader.open(&first, b"").unwrap(), b"first");
+//! assert_eq!(reader.epoch(), 1);
+//! # }
+//! ```
+//!
+//! The key schedule, where `label` is `"HPKE record s2r"` or `"HPKE record r2s"` depending on the
+//! direction, and `LabeledExpand` is under the suite ID of the record AEAD, the context's KDF, and
+//! the context's KEM, is
+//!
+//! ```text
+//! secret_0     = Export(label, Nh)
+//! key_e        = LabeledExpand(secret_e, "rec key", "", Nk)
+//! iv_e         = LabeledExpand(secret_e, "rec iv", "", Nn)
+//! secret_{e+1} = LabeledExpand(secret_e, "rec upd", "", Nh)
+//! ```
+//!
+//! and a record with epoch `e` and sequence number `seq` is
+//!
+//! ```text
+//! header = I2OSP(e, 2) || I2OSP(seq, 6)
+//! record = header || Seal(key_e, xor(iv_e, I2OSP(seq, Nn)), header || aad, pt)
+//! ```
+
+use crate::{
+    aead::{split_tag, Aead, AeadCtxR, AeadCtxS, AeadKey, AeadNonce, AeadTag, ReplayWindow},
+    kdf::{Kdf as KdfTrait, LabeledExpand, SimpleHkdf},
+    kem::Kem as KemTrait,
+    setup::ExporterSecret,
+    trace,
+    util::{full_suite_id, FullSuiteId},
+    HpkeError, Serializable, Vec,
+};
+
+use core::marker::PhantomData;
+
+use aead::{AeadInPlace, NewAead};
+use byteorder::{BigEndian, ByteOrder};
+use zeroize::Zeroize;
+
+/// The length of the header at the front of every record: a 2-byte epoch, then a 6-byte sequence
+/// number
+pub const RECORD_HEADER_LEN: usize = 8;
+
+/// The highest sequence number a record can have in one epoch. After that, the writer has to
+/// move to the next epoch.
+pub const MAX_RECORD_SEQ: u64 = (1 << 48) - 1;
+
+/// Which way records flow. Each direction has its own keys, so the two sides of an HPKE context
+/// can each write records to the other without ever sharing a nonce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From the side that ran `setup_sender` to the side that ran `setup_receiver`
+    SenderToReceiver,
+    /// From the side that ran `setup_receiver` to the side that ran `setup_sender`
+    ReceiverToSender,
+}
+
+impl Direction {
+    /// The exporter context that this direction's first secret is exported under
+    fn exporter_ctx(self) -> &'static [u8] {
+        match self {
+            Direction::SenderToReceiver => b"HPKE record s2r",
+            Direction::ReceiverToSender => b"HPKE record r2s",
+        }
+    }
+}
+
+/// One epoch's AEAD instance and IV
+struct EpochKeys<A: Aead> {
+    epoch: u16,
+    aead: A::AeadImpl,
+    iv: AeadNonce<A>,
+}
+
+impl<A: Aead> EpochKeys<A> {
+    /// Returns the nonce for the record with sequence number `seq`
+    fn nonce(&self, seq: u64) -> AeadNonce<A> {
+        let mut nonce = AeadNonce::<A>(self.iv.0.clone());
+        let nonce_size = nonce.0.len();
+        for (nonce_byte, seq_byte) in nonce.0[nonce_size - 8..]
+            .iter_mut()
+            .zip(seq.to_be_bytes().iter())
+        {
+            *nonce_byte ^= seq_byte;
+        }
+        nonce
+    }
+}
+
+/// The traffic secret of one direction at one epoch
+struct Schedule<A: Aead, Kdf: KdfTrait> {
+    suite_id: FullSuiteId,
+    secret: ExporterSecret<Kdf>,
+    epoch: u16,
+    // trace::open_error for the context's suite, which is the only place the KEM still matters
+    open_error: fn(u64, HpkeError) -> HpkeError,
+    aead: PhantomData<A>,
+}
+
+impl<A: Aead, Kdf: KdfTrait> Schedule<A, Kdf> {
+    /// Starts the schedule at epoch 0. `export` fills its second argument with the export of the
+    /// HPKE context under the exporter context in its first.
+    fn new<Kem: KemTrait>(
+        direction: Direction,
+        export: impl FnOnce(&[u8], &mut [u8]) -> Result<(), HpkeError>,
+    ) -> Self {
+        let mut secret = <ExporterSecret<Kdf> as Default>::default();
+        export(direction.exporter_ctx(), secret.0.as_mut_slice())
+            .expect("traffic secret is short enough to export");
+        Schedule {
+            suite_id: full_suite_id::<A, Kdf, Kem>(),
+            secret,
+            epoch: 0,
+            open_error: trace::open_error::<A, Kdf, Kem>,
+            aead: PhantomData,
+        }
+    }
+
+    fn expand(&self, label: &[u8], out: &mut [u8]) {
+        // The secret is a full digest, and every output is at most a digest long, so neither of
+        // these can fail
+        SimpleHkdf::<Kdf>::from_prk(self.secret.0.as_slice())
+            .unwrap()
+            .labeled_expand(&self.suite_id, label, b"", out)
+            .unwrap();
+    }
+
+    /// Derives this epoch's key and IV
+    fn keys(&self) -> EpochKeys<A> {
+        let mut key = AeadKey::<A>::default();
+        let mut iv = AeadNonce::<A>::default();
+        self.expand(b"rec key", key.0.as_mut_slice());
+        self.expand(b"rec iv", iv.0.as_mut_slice());
+        EpochKeys {
+            epoch: self.epoch,
+            aead: <A::AeadImpl as NewAead>::new(&key.0),
+            iv,
+        }
+    }
+
+    /// Returns the schedule at the next epoch, or `Err(HpkeError::MessageLimitReached)` if this is
+    /// the last one
+    fn next(&self) -> Result<Self, HpkeError> {
+        let epoch = self
+            .epoch
+            .checked_add(1)
+            .ok_or(HpkeError::MessageLimitReached)?;
+        let mut secret = <ExporterSecret<Kdf> as Default>::default();
+        self.expand(b"rec upd", secret.0.as_mut_slice());
+        Ok(Schedule {
+            suite_id: self.suite_id,
+            secret,
+            epoch,
+            open_error: self.open_error,
+            aead: PhantomData,
+        })
+    }
+}
+
+fn record_header(epoch: u16, seq: u64) -> [u8; RECORD_HEADER_LEN] {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    BigEndian::write_u16(&mut header[..2], epoch);
+    BigEndian::write_u48(&mut header[2..], seq);
+    header
+}
+
+fn record_aad(header: &[u8], aad: &[u8]) -> Vec<u8> {
+    [header, aad].concat()
+}
+
+/// Seals records in one direction, with the AEAD `A`
+///
+/// DANGER
+/// ======
+/// A writer's keys and nonces depend only on the HPKE context and the `Direction`, so two writers
+/// made from the same context, or its peer, with the same `Direction` seal with the same key and
+/// nonce sequence. Sealing with both reuses nonces, which breaks the confidentiality and integrity
+/// of every record in that direction. Make at most one writer per context and direction, and
+/// only on the side that sends in that direction.
+pub struct RecordWriter<A: Aead, Kdf: KdfTrait> {
+    schedule: Schedule<A, Kdf>,
+    keys: EpochKeys<A>,
+    seq: u64,
+}
+
+impl<A: Aead, Kdf: KdfTrait> RecordWriter<A, Kdf> {
+    /// Makes a writer at epoch 0 from a sender's context. The context's own AEAD doesn't matter,
+    /// so it can be `ExportOnlyAead`.
+    ///
+    /// DANGER
+    /// ======
+    /// Never make a second writer for `direction` from this context or its peer. See
+    /// `RecordWriter`.
+    pub fn from_sender_ctx<CtxA: Aead, Kem: KemTrait>(
+        ctx: &AeadCtxS<CtxA, Kdf, Kem>,
+        direction: Direction,
+    ) -> Self {
+        Self::new(Schedule::new::<Kem>(direction, |c, out| ctx.export(c, out)))
+    }
+
+    /// Makes a writer at epoch 0 from a receiver's context. See `from_sender_ctx`, including its
+    /// DANGER section.
+    pub fn from_receiver_ctx<CtxA: Aead, Kem: KemTrait>(
+        ctx: &AeadCtxR<CtxA, Kdf, Kem>,
+        direction: Direction,
+    ) -> Self {
+        Self::new(Schedule::new::<Kem>(direction, |c, out| ctx.export(c, out)))
+    }
+
+    fn new(schedule: Schedule<A, Kdf>) -> Self {
+        RecordWriter {
+            keys: schedule.keys(),
+            schedule,
+            seq: 0,
+        }
+    }
+
+    /// Seals `plaintext` into a record, and returns the record. `aad` isn't sent, and the reader
+    /// has to supply the same one.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the record on success. If this epoch has used up every sequence number up to
+    /// `MAX_RECORD_SEQ`, returns `Err(HpkeError::MessageLimitReached)`, and `update_epoch` makes
+    /// room for more. If an error happened during encryption, returns
+    /// `Err(HpkeError::SealError)`.
+    pub fn seal(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        if self.seq > MAX_RECORD_SEQ {
+            return Err(HpkeError::MessageLimitReached);
+        }
+
+        let header = record_header(self.keys.epoch, self.seq);
+        let mut buf =
+            Vec::with_capacity(RECORD_HEADER_LEN + plaintext.len() + AeadTag::<A>::size());
+        buf.extend_from_slice(&header);
+        buf.extend_from_slice(plaintext);
+
+        let nonce = self.keys.nonce(self.seq);
+        let tag = self
+            .keys
+            .aead
+            .encrypt_in_place_detached(
+                &nonce.0,
+                &record_aad(&header, aad),
+                &mut buf[RECORD_HEADER_LEN..],
+            )
+            .map_err(|_| HpkeError::SealError)?;
+        buf.extend_from_slice(&tag);
+
+        self.seq += 1;
+        Ok(buf)
+    }
+
+    /// Moves to the next epoch, and forgets this epoch's secret and keys. Sequence numbers start
+    /// over at 0.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If this is already the last epoch, `u16::MAX`, returns
+    /// `Err(HpkeError::MessageLimitReached)` and changes nothing.
+    pub fn update_epoch(&mut self) -> Result<(), HpkeError> {
+        self.schedule = self.schedule.next()?;
+        self.keys = self.schedule.keys();
+        self.seq = 0;
+        Ok(())
+    }
+
+    /// Returns the epoch the next record will be sealed in
+    pub fn epoch(&self) -> u16 {
+        self.keys.epoch
+    }
+
+    /// Returns the sequence number the next record will be sealed with
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+/// Opens records in one direction, with the AEAD `A`. It opens records from its current epoch and
+/// the one before, each at most once, in any order. Replays are caught with a window of the last
+/// 64 sequence numbers of each epoch, as in `AeadCtxR::open_at`.
+pub struct RecordReader<A: Aead, Kdf: KdfTrait> {
+    schedule: Schedule<A, Kdf>,
+    current: (EpochKeys<A>, ReplayWindow),
+    previous: Option<(EpochKeys<A>, ReplayWindow)>,
+}
+
+impl<A: Aead, Kdf: KdfTrait> RecordReader<A, Kdf> {
+    /// Makes a reader at epoch 0 from a sender's context. The context's own AEAD doesn't matter,
+    /// so it can be `ExportOnlyAead`.
+    pub fn from_sender_ctx<CtxA: Aead, Kem: KemTrait>(
+        ctx: &AeadCtxS<CtxA, Kdf, Kem>,
+        direction: Direction,
+    ) -> Self {
+        Self::new(Schedule::new::<Kem>(direction, |c, out| ctx.export(c, out)))
+    }
+
+    /// Makes a reader at epoch 0 from a receiver's context. See `from_sender_ctx`.
+    pub fn from_receiver_ctx<CtxA: Aead, Kem: KemTrait>(
+        ctx: &AeadCtxR<CtxA, Kdf, Kem>,
+        direction: Direction,
+    ) -> Self {
+        Self::new(Schedule::new::<Kem>(direction, |c, out| ctx.export(c, out)))
+    }
+
+    fn new(schedule: Schedule<A, Kdf>) -> Self {
+        RecordReader {
+            current: (schedule.keys(), ReplayWindow::default()),
+            schedule,
+            previous: None,
+        }
+    }
+
+    /// Opens a record made by `RecordWriter::seal`, and returns the plaintext. If the record is
+    /// from the epoch after the current one, and it opens, the reader moves to that epoch, and
+    /// forgets the epoch before the current one.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the plaintext on success. If the record is from an epoch before the previous one,
+    /// or its sequence number was already opened or is too far behind the newest one in its
+    /// epoch, returns `Err(HpkeError::ReplayedMessage)`. If the record is too short, is from an
+    /// epoch more than one ahead of the current one, or doesn't authenticate, returns
+    /// `Err(HpkeError::OpenError)`. In every error case, the reader is unchanged.
+    pub fn open(&mut self, record: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        let tag_len = AeadTag::<A>::size();
+        if record.len() < RECORD_HEADER_LEN + tag_len {
+            return Err(HpkeError::OpenError);
+        }
+        let (header, rest) = record.split_at(RECORD_HEADER_LEN);
+        let epoch = BigEndian::read_u16(&header[..2]);
+        let seq = BigEndian::read_u48(&header[2..]);
+
+        let open_error = self.schedule.open_error;
+        let current_epoch = self.current.0.epoch;
+        if epoch == current_epoch {
+            let (keys, window) = &mut self.current;
+            return open_record(keys, window, header, seq, rest, aad)
+                .map_err(|e| open_error(seq, e));
+        }
+        if let Some((keys, window)) = self.previous.as_mut().filter(|(k, _)| k.epoch == epoch) {
+            return open_record(keys, window, header, seq, rest, aad)
+                .map_err(|e| open_error(seq, e));
+        }
+        if epoch < current_epoch {
+            return Err(HpkeError::ReplayedMessage);
+        }
+        if Some(epoch) != current_epoch.checked_add(1) {
+            return Err(HpkeError::OpenError);
+        }
+
+        // The record is from the next epoch. Only move there if it opens.
+        let schedule = self.schedule.next()?;
+        let mut next = (schedule.keys(), ReplayWindow::default());
+        let plaintext = open_record(&next.0, &mut next.1, header, seq, rest, aad)
+            .map_err(|e| open_error(seq, e))?;
+        self.schedule = schedule;
+        self.previous = Some(core::mem::replace(&mut self.current, next));
+        Ok(plaintext)
+    }
+
+    /// Returns the newest epoch this reader has opened a record from, or 0 if none
+    pub fn epoch(&self) -> u16 {
+        self.current.0.epoch
+    }
+}
+
+/// Checks `seq` against `window`, opens the rest of the record, and records `seq` if it opened
+fn open_record<A: Aead>(
+    keys: &EpochKeys<A>,
+    window: &mut ReplayWindow,
+    header: &[u8],
+    seq: u64,
+    ciphertext_and_tag: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError> {
+    window.check(seq)?;
+
+    let (ciphertext, tag) = split_tag::<A>(ciphertext_and_tag)?;
+    let mut buf = ciphertext.to_vec();
+    let nonce = keys.nonce(seq);
+    if keys
+        .aead
+        .decrypt_in_place_detached(
+            &nonce.0,
+            &record_aad(header, aad),
+            &mut buf,
+            &tag.to_bytes(),
+        )
+        .is_err()
+    {
+        buf.zeroize();
+        return Err(HpkeError::OpenError);
+    }
+
+    window.record(seq);
+    Ok(buf)
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::{Direction, RecordReader, RecordWriter, MAX_RECORD_SEQ};
+    use crate::{
+        aead::{AesGcm256, ChaCha20Poly1305, ExportOnlyAead},
+        kdf::HkdfSha384,
+        kem::X25519HkdfSha256,
+        test_util::gen_ctx_simple_pair,
+        HpkeError,
+    };
+
+    type Kdf = HkdfSha384;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that records open in any order, at most once, across epoch changes, and that the
+    /// two directions have different keys
+    #[test]
+    fn test_record_layer() {
+        let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<ExportOnlyAead, Kdf, Kem>();
+        let mut writer = RecordWriter::<AesGcm256, _>::from_receiver_ctx(
+            &receiver_ctx,
+            Direction::ReceiverToSender,
+        );
+        let mut reader =
+            RecordReader::<AesGcm256, _>::from_sender_ctx(&sender_ctx, Direction::ReceiverToSender);
+        let mut wrong_direction =
+            RecordReader::<AesGcm256, _>::from_sender_ctx(&sender_ctx, Direction::SenderToReceiver);
+
+        let e0: [_; 3] = core::array::from_fn(|i| writer.seal(&[i as u8], b"aad").unwrap());
+        assert_eq!(&e0[1][..8], &[0, 0, 0, 0, 0, 0, 0, 1]);
+        writer.update_epoch().unwrap();
+        assert_eq!((writer.epoch(), writer.seq()), (1, 0));
+        let e1 = writer.seal(b"e1", b"aad").unwrap();
+        writer.update_epoch().unwrap();
+        let e2 = writer.seal(b"e2", b"aad").unwrap();
+
+        assert_eq!(
+            wrong_direction.open(&e0[0], b"aad"),
+            Err(HpkeError::OpenError)
+        );
+        // Epoch 2 is too far ahead of epoch 0
+        assert_eq!(reader.open(&e2, b"aad"), Err(HpkeError::OpenError));
+        assert_eq!(reader.open(&e0[2], b"aad").unwrap(), [2]);
+        assert_eq!(reader.open(&e0[0], b"aad").unwrap(), [0]);
+        assert_eq!(reader.open(&e0[0], b"aad"), Err(HpkeError::ReplayedMessage));
+        assert_eq!(reader.open(&e0[1], b"wrong"), Err(HpkeError::OpenError));
+
+        // A forged record from the next epoch doesn't move the reader there
+        let mut forged = e1.clone();
+        forged[9] ^= 1;
+        assert_eq!(reader.open(&forged, b"aad"), Err(HpkeError::OpenError));
+        assert_eq!(reader.epoch(), 0);
+
+        assert_eq!(reader.open(&e1, b"aad").unwrap(), b"e1");
+        assert_eq!(reader.epoch(), 1);
+        // Epoch 0 is now the previous one, so its stragglers still open
+        assert_eq!(reader.open(&e0[1], b"aad").unwrap(), [1]);
+        assert_eq!(reader.open(&e2, b"aad").unwrap(), b"e2");
+        // And now it's forgotten
+        assert_eq!(reader.open(&e0[1], b"aad"), Err(HpkeError::ReplayedMessage));
+        assert_eq!(reader.open(&e0[0][..23], b"aad"), Err(HpkeError::OpenError));
+    }
+
+    /// Tests the per-epoch and epoch limits
+    #[test]
+    fn test_record_limits() {
+        let (sender_ctx, _) = gen_ctx_simple_pair::<ChaCha20Poly1305, Kdf, Kem>();
+        let mut writer = RecordWriter::<ChaCha20Poly1305, _>::from_sender_ctx(
+            &sender_ctx,
+            Direction::SenderToReceiver,
+        );
+
+        writer.seq = MAX_RECORD_SEQ;
+        let record = writer.seal(b"last", b"").unwrap();
+        assert_eq!(&record[2..8], &[0xff; 6]);
+        assert_eq!(writer.seal(b"", b""), Err(HpkeError::MessageLimitReached));
+        writer.update_epoch().unwrap();
+        assert!(writer.seal(b"", b"").is_ok());
+
+        writer.schedule.epoch = u16::MAX;
+        assert_eq!(writer.update_epoch(), Err(HpkeError::MessageLimitReached));
+    }
+}
@@ -0,0 +1,477 @@
+//! Sealed sender: sender authentication with the sender's identity hidden inside the ciphertext,
+//! in the style of Signal. This is gated under the `sealed-sender` feature.
+//!
+//! Auth mode authenticates the sender, but the receiver has to know who the sender is before it
+//! can open anything, so the sender's identity travels in the clear next to the message. Here the
+//! message is sealed in Base mode instead, and the sender's signing key and a signature go inside
+//! the ciphertext. Only the recipient learns who sent it. The signature covers a value exported
+//! from the HPKE context, so it's bound to this one encapsulation and can't be lifted into a
+//! message to someone else, along with the AAD and plaintext.
+//!
+//! `open_sealed_sender` checks the signature, but can't know whether the sender's key is one the
+//! recipient trusts. It returns the key, and the caller must check it, e.g., against a contact
+//! list, before acting on the message.
+//!
+//! ```
+//! # #[cfg(all(feature = "p256", feature = "x25519"))]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     sealed_sender::{open_sealed_sender, seal_sealed_sender, EcdsaP256, SenderIdentity},
+//!     Kem,
+//! };
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_recip, pk_recip) = K::gen_keypair(&mut csprng);
+//! let signing_key = p256::ecdsa::SigningKey::random(&mut csprng);
+//!
+//! let envelope = seal_sealed_sender::<EcdsaP256, A, Kdf, K, _>(
+//!     &signing_key,
+//!     &pk_recip,
+//!     b"info",
+//!     b"hello",
+//!     b"aad",
+//!     &mut csprng,
+//! )
+//! .unwrap();
+//!
+//! let (sender, plaintext) =
+//!     open_sealed_sender::<EcdsaP256, A, Kdf, K>(&sk_recip, &envelope, b"info", b"aad").unwrap();
+//! assert_eq!(plaintext, b"hello");
+//! // The caller decides whether to trust the sender
+//! assert_eq!(sender, EcdsaP256::verifying_key(&signing_key));
+//! # }
+//! ```
+//!
+//! The envelope is an ordinary Base mode `Envelope` with no PSK ID or AAD header. With `Nh` the
+//! digest size of the KDF, and all lengths big-endian, its plaintext and AAD are
+//!
+//! ```text
+//! transcript = Export("HPKE sealed sender transcript", Nh)
+//! signed     = "HPKE sealed sender v1\0" || transcript || I2OSP(len(aad), 8) || aad || plaintext
+//! inner      = I2OSP(len(vk), 2) || vk || I2OSP(len(sig), 2) || sig || plaintext
+//! ciphertext = Seal("HPKE sealed sender v1\0" || aad, inner)
+//! ```
+//!
+//! where `vk` is the encoded verifying key and `sig` is its owner's signature of `signed`. The
+//! prefix on the AAD keeps `open_envelope` from mistaking these for ordinary envelopes.
+
+use crate::{
+    aead::Aead,
+    envelope::{read_with_len, write_with_len, Envelope},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, OpModeS},
+    setup::ExporterSecret,
+    setup_receiver, setup_sender, Deserializable, HpkeError, Serializable, Vec,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+// The exporter context that the signed transcript is exported under
+const TRANSCRIPT_EXPORTER_CTX: &[u8] = b"HPKE sealed sender transcript";
+// Prefixes the signed message and the AAD
+const SEALED_SENDER_PREFIX: &[u8] = b"HPKE sealed sender v1\x00";
+
+/// A signature scheme for sender identities. Implemented for ECDSA over P-256 and K-256, as
+/// `EcdsaP256` and `EcdsaK256`. Implement it for other schemes to use them as identities.
+pub trait SenderIdentity {
+    /// The sender's private signing key
+    type SigningKey;
+    /// The public key that a recipient learns the sender by
+    type VerifyingKey;
+
+    /// Returns the public key of `sk`
+    fn verifying_key(sk: &Self::SigningKey) -> Self::VerifyingKey;
+
+    /// Encodes a public key. The encoding must be at most 65535 bytes long.
+    fn verifying_key_to_bytes(vk: &Self::VerifyingKey) -> Vec<u8>;
+
+    /// Decodes a public key that `verifying_key_to_bytes` encoded. Returns
+    /// `Err(HpkeError::ValidationError)` if it doesn't decode.
+    fn verifying_key_from_bytes(encoded: &[u8]) -> Result<Self::VerifyingKey, HpkeError>;
+
+    /// Signs `msg`. The signature must be at most 65535 bytes long.
+    fn sign(sk: &Self::SigningKey, msg: &[u8]) -> Vec<u8>;
+
+    /// Checks that `sig` is a signature of `msg` under `vk`. Returns
+    /// `Err(HpkeError::OpenError)` if it isn't.
+    fn verify(vk: &Self::VerifyingKey, msg: &[u8], sig: &[u8]) -> Result<(), HpkeError>;
+}
+
+/// ECDSA over P-256 with SHA-256. Verifying keys are SEC1 compressed points, and signatures are
+/// the 64-byte `r || s`. Signing is deterministic, per RFC 6979.
+#[cfg(feature = "p256")]
+pub struct EcdsaP256;
+
+#[cfg(feature = "p256")]
+impl SenderIdentity for EcdsaP256 {
+    type SigningKey = p256::ecdsa::SigningKey;
+    type VerifyingKey = p256::ecdsa::VerifyingKey;
+
+    fn verifying_key(sk: &Self::SigningKey) -> Self::VerifyingKey {
+        sk.verifying_key()
+    }
+
+    fn verifying_key_to_bytes(vk: &Self::VerifyingKey) -> Vec<u8> {
+        vk.to_encoded_point(true).as_bytes().to_vec()
+    }
+
+    fn verifying_key_from_bytes(encoded: &[u8]) -> Result<Self::VerifyingKey, HpkeError> {
+        p256::ecdsa::VerifyingKey::from_sec1_bytes(encoded).map_err(|_| HpkeError::ValidationError)
+    }
+
+    fn sign(sk: &Self::SigningKey, msg: &[u8]) -> Vec<u8> {
+        use p256::ecdsa::{signature::Signer, Signature};
+        let sig: Signature = sk.sign(msg);
+        sig.as_ref().to_vec()
+    }
+
+    fn verify(vk: &Self::VerifyingKey, msg: &[u8], sig: &[u8]) -> Result<(), HpkeError> {
+        use p256::ecdsa::{signature::Verifier, Signature};
+        let sig = Signature::try_from(sig).map_err(|_| HpkeError::OpenError)?;
+        vk.verify(msg, &sig).map_err(|_| HpkeError::OpenError)
+    }
+}
+
+/// ECDSA over K-256 with SHA-256. Verifying keys are SEC1 compressed points, and signatures are
+/// the 64-byte `r || s`. Signing is deterministic, per RFC 6979.
+#[cfg(feature = "k256")]
+pub struct EcdsaK256;
+
+#[cfg(feature = "k256")]
+impl SenderIdentity for EcdsaK256 {
+    type SigningKey = k256::ecdsa::SigningKey;
+    type VerifyingKey = k256::ecdsa::VerifyingKey;
+
+    fn verifying_key(sk: &Self::SigningKey) -> Self::VerifyingKey {
+        sk.verifying_key()
+    }
+
+    fn verifying_key_to_bytes(vk: &Self::VerifyingKey) -> Vec<u8> {
+        vk.to_bytes().to_vec()
+    }
+
+    fn verifying_key_from_bytes(encoded: &[u8]) -> Result<Self::VerifyingKey, HpkeError> {
+        k256::ecdsa::VerifyingKey::from_sec1_bytes(encoded).map_err(|_| HpkeError::ValidationError)
+    }
+
+    fn sign(sk: &Self::SigningKey, msg: &[u8]) -> Vec<u8> {
+        use k256::ecdsa::{signature::Signer, Signature};
+        let sig: Signature = sk.sign(msg);
+        sig.as_ref().to_vec()
+    }
+
+    fn verify(vk: &Self::VerifyingKey, msg: &[u8], sig: &[u8]) -> Result<(), HpkeError> {
+        use k256::ecdsa::{signature::Verifier, Signature};
+        let sig = Signature::try_from(sig).map_err(|_| HpkeError::OpenError)?;
+        vk.verify(msg, &sig).map_err(|_| HpkeError::OpenError)
+    }
+}
+
+// Returns the message that the sender signs, as described in the module docs
+fn signed_message(transcript: &[u8], aad: &[u8], plaintext: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut msg = Zeroizing::new(Vec::with_capacity(
+        SEALED_SENDER_PREFIX.len() + transcript.len() + 8 + aad.len() + plaintext.len(),
+    ));
+    let mut aad_len = [0u8; 8];
+    BigEndian::write_u64(&mut aad_len, aad.len() as u64);
+    msg.extend_from_slice(SEALED_SENDER_PREFIX);
+    msg.extend_from_slice(transcript);
+    msg.extend_from_slice(&aad_len);
+    msg.extend_from_slice(aad);
+    msg.extend_from_slice(plaintext);
+    msg
+}
+
+// Returns the AAD that the inner payload is sealed under
+fn prefixed_aad(aad: &[u8]) -> Vec<u8> {
+    let mut prefixed = Vec::with_capacity(SEALED_SENDER_PREFIX.len() + aad.len());
+    prefixed.extend_from_slice(SEALED_SENDER_PREFIX);
+    prefixed.extend_from_slice(aad);
+    prefixed
+}
+
+/// Seals `plaintext` to `pk_recip` in Base mode, with the sender's identity, the public key of
+/// `sender_sk`, and a signature hidden inside the ciphertext. See the module docs.
+///
+/// Return Value
+/// ============
+/// Returns the envelope on success. Returns `Err(HpkeError::ValidationError)` if the encoded
+/// verifying key or the signature is longer than 65535 bytes. Otherwise, returns the errors
+/// `setup_sender` and `seal` do.
+pub fn seal_sealed_sender<S, A, Kdf, Kem, R>(
+    sender_sk: &S::SigningKey,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<Envelope, HpkeError>
+where
+    S: SenderIdentity,
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let (encapped_key, mut ctx) =
+        setup_sender::<A, Kdf, Kem, R>(&OpModeS::Base, pk_recip, info, csprng)?;
+
+    let mut transcript = <ExporterSecret<Kdf> as Default>::default();
+    ctx.export(TRANSCRIPT_EXPORTER_CTX, transcript.0.as_mut_slice())?;
+    let vk = S::verifying_key_to_bytes(&S::verifying_key(sender_sk));
+    let sig = S::sign(
+        sender_sk,
+        &signed_message(transcript.0.as_slice(), aad, plaintext),
+    );
+    if vk.len() > u16::MAX as usize || sig.len() > u16::MAX as usize {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let mut inner = Zeroizing::new(Vec::with_capacity(
+        4 + vk.len() + sig.len() + plaintext.len(),
+    ));
+    write_with_len(&mut inner, &vk);
+    write_with_len(&mut inner, &sig);
+    inner.extend_from_slice(plaintext);
+    let ciphertext = ctx.seal(&inner, &prefixed_aad(aad))?;
+
+    Ok(Envelope {
+        kem_id: Kem::KEM_ID,
+        kdf_id: Kdf::KDF_ID,
+        aead_id: A::AEAD_ID,
+        encapped_key: encapped_key.to_bytes().to_vec(),
+        psk_id: None,
+        aad_header: None,
+        ciphertext,
+    })
+}
+
+/// Opens an envelope that `seal_sealed_sender` made, and checks the sender's signature.
+///
+/// The returned key is only who signed the message. It's up to the caller to decide whether
+/// that's a sender it trusts.
+///
+/// Return Value
+/// ============
+/// Returns the sender's verifying key and the plaintext on success. Returns
+/// `Err(HpkeError::ValidationError)` if the envelope's suite IDs aren't `(Kem, Kdf, A)`, it has a
+/// PSK ID or AAD header, or the inner payload is malformed. Returns `Err(HpkeError::OpenError)`
+/// if the ciphertext doesn't open, or the signature doesn't verify. Otherwise, returns the errors
+/// `setup_receiver` does.
+pub fn open_sealed_sender<S, A, Kdf, Kem>(
+    sk_recip: &Kem::PrivateKey,
+    envelope: &Envelope,
+    info: &[u8],
+    aad: &[u8],
+) -> Result<(S::VerifyingKey, Vec<u8>), HpkeError>
+where
+    S: SenderIdentity,
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if (envelope.kem_id(), envelope.kdf_id(), envelope.aead_id())
+        != (Kem::KEM_ID, Kdf::KDF_ID, A::AEAD_ID)
+        || envelope.psk_id().is_some()
+        || envelope.aad_header().is_some()
+    {
+        return Err(HpkeError::ValidationError);
+    }
+    let encapped_key = Kem::EncappedKey::from_bytes(envelope.encapped_key())?;
+    let mut ctx = setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, sk_recip, &encapped_key, info)?;
+    let inner = Zeroizing::new(ctx.open(envelope.ciphertext(), &prefixed_aad(aad))?);
+
+    let (vk_bytes, rest) = read_with_len(&inner)?;
+    let (sig, plaintext) = read_with_len(rest)?;
+    let vk = S::verifying_key_from_bytes(vk_bytes)?;
+
+    let mut transcript = <ExporterSecret<Kdf> as Default>::default();
+    ctx.export(TRANSCRIPT_EXPORTER_CTX, transcript.0.as_mut_slice())?;
+    S::verify(
+        &vk,
+        &signed_message(transcript.0.as_slice(), aad, plaintext),
+        sig,
+    )?;
+
+    Ok((vk, plaintext.to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{open_sealed_sender, seal_sealed_sender, SenderIdentity};
+    use crate::{
+        aead::ChaCha20Poly1305,
+        envelope::{open_envelope, Envelope},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        HpkeError, OpModeR,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    macro_rules! test_sealed_sender {
+        ($test_name:ident, $ident:ty, $signing_key:ty, $kem:ty) => {
+            /// Tests that sealed sender messages open to the right sender and plaintext, that
+            /// neither the signature nor the ciphertext can be swapped or modified, and that they
+            /// don't open as ordinary envelopes
+            #[test]
+            fn $test_name() {
+                type S = $ident;
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let alice = <$signing_key>::random(&mut csprng);
+                let mallory = <$signing_key>::random(&mut csprng);
+                let seal = |signing_key, plaintext: &[u8], csprng: &mut StdRng| {
+                    seal_sealed_sender::<S, A, Kdf, Kem, _>(
+                        signing_key,
+                        &pk_recip,
+                        b"info",
+                        plaintext,
+                        b"aad",
+                        csprng,
+                    )
+                    .unwrap()
+                };
+                let open = |envelope: &Envelope, aad: &[u8]| {
+                    open_sealed_sender::<S, A, Kdf, Kem>(&sk_recip, envelope, b"info", aad)
+                };
+
+                let envelope = seal(&alice, b"from alice", &mut csprng);
+                let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+                let (sender, plaintext) = open(&decoded, b"aad").unwrap();
+                assert_eq!(plaintext, b"from alice");
+                assert_eq!(
+                    S::verifying_key_to_bytes(&sender),
+                    S::verifying_key_to_bytes(&S::verifying_key(&alice))
+                );
+                assert_eq!(open(&envelope, b"aae").err(), Some(HpkeError::OpenError));
+
+                // Another sender is told apart
+                let (sender, _) =
+                    open(&seal(&mallory, b"from alice", &mut csprng), b"aad").unwrap();
+                assert_ne!(
+                    S::verifying_key_to_bytes(&sender),
+                    S::verifying_key_to_bytes(&S::verifying_key(&alice))
+                );
+
+                // Nothing about the sender is in the clear
+                let vk_bytes = S::verifying_key_to_bytes(&S::verifying_key(&alice));
+                assert!(!envelope
+                    .to_bytes()
+                    .windows(vk_bytes.len())
+                    .any(|w| w == &vk_bytes[..]));
+
+                // It isn't an ordinary envelope
+                assert_eq!(
+                    open_envelope::<A, Kdf, Kem>(
+                        &OpModeR::Base,
+                        &sk_recip,
+                        &envelope,
+                        b"info",
+                        b"aad"
+                    ),
+                    Err(HpkeError::OpenError)
+                );
+
+                let mut tampered = envelope.clone();
+                tampered.ciphertext[40] ^= 1;
+                assert_eq!(open(&tampered, b"aad").err(), Some(HpkeError::OpenError));
+                let mut with_header = envelope.clone();
+                with_header.aad_header = Some(b"h".to_vec());
+                assert_eq!(
+                    open(&with_header, b"aad").err(),
+                    Some(HpkeError::ValidationError)
+                );
+            }
+        };
+    }
+
+    #[cfg(all(feature = "p256", feature = "x25519"))]
+    test_sealed_sender!(
+        test_sealed_sender_p256,
+        super::EcdsaP256,
+        p256::ecdsa::SigningKey,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "k256")]
+    test_sealed_sender!(
+        test_sealed_sender_k256,
+        super::EcdsaK256,
+        k256::ecdsa::SigningKey,
+        crate::kem::DhK256HkdfSha256
+    );
+
+    /// Tests that a signature only counts for the message it was made in, by having the
+    /// recipient, who holds the context, re-seal Alice's signature around another plaintext
+    #[cfg(all(feature = "p256", feature = "x25519"))]
+    #[test]
+    fn test_sealed_sender_signature_binding() {
+        use super::{prefixed_aad, read_with_len, write_with_len, EcdsaP256};
+        use crate::{
+            kem::X25519HkdfSha256, setup_receiver, setup_sender, Deserializable, OpModeS,
+            Serializable, Vec,
+        };
+
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = X25519HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let alice = p256::ecdsa::SigningKey::random(&mut csprng);
+
+        let envelope = seal_sealed_sender::<EcdsaP256, A, Kdf, Kem, _>(
+            &alice,
+            &pk_recip,
+            b"info",
+            b"pay bob 10",
+            b"aad",
+            &mut csprng,
+        )
+        .unwrap();
+        let encapped_key =
+            <Kem as KemTrait>::EncappedKey::from_bytes(envelope.encapped_key()).unwrap();
+        let mut ctx =
+            setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"info")
+                .unwrap();
+        let inner = ctx
+            .open(envelope.ciphertext(), &prefixed_aad(b"aad"))
+            .unwrap();
+        let (vk, rest) = read_with_len(&inner).unwrap();
+        let (sig, _) = read_with_len(rest).unwrap();
+
+        // Splice Alice's key and signature onto a new plaintext in a fresh context
+        let (encapped_key, mut forged_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+                .unwrap();
+        let mut forged_inner = Vec::new();
+        write_with_len(&mut forged_inner, vk);
+        write_with_len(&mut forged_inner, sig);
+        forged_inner.extend_from_slice(b"pay mallory 1000");
+        let mut forged = envelope.clone();
+        forged.encapped_key = encapped_key.to_bytes().to_vec();
+        forged.ciphertext = forged_ctx
+            .seal(&forged_inner, &prefixed_aad(b"aad"))
+            .unwrap();
+
+        assert_eq!(
+            open_sealed_sender::<EcdsaP256, A, Kdf, Kem>(&sk_recip, &forged, b"info", b"aad").err(),
+            Some(HpkeError::OpenError)
+        );
+    }
+}
@@ -3,7 +3,9 @@
 
 use crate::{
     aead::{Aead, AeadTag},
-    dhkex, kem, Deserializable, Serializable,
+    dhkex::{self, DhKeyExchange},
+    kem::{self, Kem as KemTrait, Keypair},
+    Deserializable, Serializable,
 };
 
 use digest::generic_array::GenericArray;
@@ -73,36 +75,120 @@ macro_rules! impl_serde_noparam {
     };
 }
 
-// Implement Serialize/Deserialize for all PrivateKey, PublicKey, and EncappedKey types, as
-// features permit
+// Implement Serialize/Deserialize for all PrivateKey and PublicKey types, as features permit
 
 #[cfg(feature = "x25519")]
 impl_serde_noparam!(dhkex::x25519::PrivateKey);
 #[cfg(feature = "x25519")]
 impl_serde_noparam!(dhkex::x25519::PublicKey);
-#[cfg(feature = "x25519")]
-impl_serde_noparam!(kem::x25519_hkdfsha256::EncappedKey);
 
 #[cfg(feature = "p256")]
 impl_serde_noparam!(dhkex::ecdh_nistp::PrivateKey);
 #[cfg(feature = "p256")]
 impl_serde_noparam!(dhkex::ecdh_nistp::PublicKey);
-#[cfg(feature = "p256")]
-impl_serde_noparam!(kem::dhp256_hkdfsha256::EncappedKey);
 
 #[cfg(feature = "k256")]
 impl_serde_noparam!(dhkex::ecdh_k256::PrivateKey);
 #[cfg(feature = "k256")]
 impl_serde_noparam!(dhkex::ecdh_k256::PublicKey);
-#[cfg(feature = "k256")]
-impl_serde_noparam!(kem::dhk256_hkdfsha256::EncappedKey);
+
+// Implement Serialize/Deserialize for DHKEM encapsulated keys, in any group. Kem requires these,
+// so they have to be generic like DhKem is.
+impl<Dh: DhKeyExchange> SerdeSerialize for kem::DhEncappedKey<Dh> {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // Convert to a GenericArray and serialize that
+        let bytes = self.to_bytes();
+        bytes.serialize(serializer)
+    }
+}
+
+impl<'de, Dh: DhKeyExchange> SerdeDeserialize<'de> for kem::DhEncappedKey<Dh> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // Use the GenericArray deserializer to get the appropriate number of bytes
+        let bytes = GenericArray::<u8, <Self as crate::Serializable>::OutputSize>::deserialize(
+            deserializer,
+        )?;
+        // Try to build this object from the given bytes. If it doesn't work, wrap and
+        // return the resulting HpkeError
+        Self::from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
+
+// Implements serde::{Serialize, Deserialize} for the combined KEM's pair types. These are the
+// same as above, but generic over the component KEMs.
+macro_rules! impl_serde_pair {
+    ($t:ident) => {
+        /// Implements `serde::Serialize`
+        impl<K1: KemTrait, K2: KemTrait> SerdeSerialize for kem::$t<K1, K2>
+        where
+            kem::$t<K1, K2>: Serializable,
+        {
+            #[inline]
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                self.to_bytes().serialize(serializer)
+            }
+        }
+
+        /// Implements `serde::Deserialize`
+        impl<'de, K1: KemTrait, K2: KemTrait> SerdeDeserialize<'de> for kem::$t<K1, K2>
+        where
+            kem::$t<K1, K2>: Deserializable,
+        {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let bytes =
+                    GenericArray::<u8, <Self as crate::Serializable>::OutputSize>::deserialize(
+                        deserializer,
+                    )?;
+                Self::from_bytes(&bytes).map_err(D::Error::custom)
+            }
+        }
+    };
+}
+
+impl_serde_pair!(CombinedPublicKey);
+impl_serde_pair!(CombinedPrivateKey);
+impl_serde_pair!(CombinedEncappedKey);
+
+// A Keypair is serialized as just its private key. The public key is recomputed on
+// deserialization, so a serialized keypair can never be inconsistent.
+impl<Kem: KemTrait> SerdeSerialize for Keypair<Kem> {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.private().serialize(serializer)
+    }
+}
+
+impl<'de, Kem: KemTrait> SerdeDeserialize<'de> for Keypair<Kem> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Kem::PrivateKey::deserialize(deserializer).map(Keypair::from_private_key)
+    }
+}
 
 #[cfg(test)]
 mod test {
     use crate::{
         aead::AesGcm128,
         kdf::HkdfSha256,
-        kem::Kem as KemTrait,
+        kem::{Kem as KemTrait, Keypair},
         setup_sender,
         test_util::{gen_rand_buf, new_op_mode_pair, OpModeKind},
         Serializable,
@@ -146,8 +232,7 @@ mod test {
                 // this gives us a pubkey, secret key, and encapped key to test serde on
                 let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
                 let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
-                let (sender_mode, _) =
-                    new_op_mode_pair::<Kdf, Kem>(OpModeKind::Base, &psk, &psk_id);
+                let (sender_mode, _) = new_op_mode_pair::<Kem>(OpModeKind::Base, &psk, &psk_id);
                 let (encapped_key, mut aead_ctx) =
                     setup_sender::<A, Kdf, Kem, _>(&sender_mode, &pk_recip, &info[..], &mut csprng)
                         .unwrap();
@@ -160,6 +245,20 @@ mod test {
                 assert_serde_roundtrip(&pk_recip);
                 assert_serde_roundtrip(&encapped_key);
                 assert_serde_roundtrip(&aead_tag);
+
+                // Keypairs aren't Serializable, so check them by hand
+                let keypair = Keypair::<Kem>::generate(&mut csprng);
+                let json = serde_json::to_vec(&keypair).expect("couldn't serialize keypair");
+                let reconstructed_keypair: Keypair<Kem> =
+                    serde_json::from_slice(&json).expect("couldn't deserialize keypair");
+                assert_eq!(
+                    keypair.private().to_bytes(),
+                    reconstructed_keypair.private().to_bytes()
+                );
+                assert_eq!(
+                    keypair.public().to_bytes(),
+                    reconstructed_keypair.public().to_bytes()
+                );
             }
         };
     }
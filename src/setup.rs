@@ -1,14 +1,45 @@
 use crate::{
     aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS},
-    kdf::{labeled_extract, DigestArray, Kdf as KdfTrait, LabeledExpand, MAX_DIGEST_SIZE},
-    kem::{Kem as KemTrait, SharedSecret},
+    kdf::{
+        labeled_extract, labeled_extract_multi, DigestArray, Kdf as KdfTrait, LabeledExpand,
+        MAX_DIGEST_SIZE,
+    },
+    kem::{Kem as KemTrait, RecipientHandle, SharedSecret},
     op_mode::{OpMode, OpModeR, OpModeS},
+    trace,
     util::full_suite_id,
     HpkeError,
 };
 
+#[cfg(feature = "alloc")]
+use crate::Vec;
+
+mod builder;
+pub use builder::{ReceiverBuilder, SenderBuilder};
+
+#[cfg(feature = "debug-internals")]
+mod debug;
+#[cfg(feature = "debug-internals")]
+pub use debug::{setup_receiver_debug, setup_sender_debug, KeyScheduleValues};
+
+mod encap_filter;
+pub use encap_filter::{setup_receiver_with_encap_filter, EncapFilter};
+
+mod resolver;
+pub use resolver::{
+    setup_receiver_with_async_resolver, setup_receiver_with_resolver, AsyncPskResolver, PskResolver,
+};
+
+pub(crate) mod usage_hook;
+#[cfg(feature = "key-usage-hook")]
+pub use usage_hook::{clear_key_usage_hook, set_key_usage_hook, KeyUsage};
+
+mod verifier;
+pub use verifier::{setup_receiver_with_verifier, SenderVerifier};
+
 use rand_core::{CryptoRng, RngCore};
-use zeroize::Zeroize;
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 /// Secret generated in `derive_enc_ctx` and stored in `AeadCtx`
 pub(crate) struct ExporterSecret<K: KdfTrait>(pub(crate) DigestArray<K>);
@@ -32,6 +63,13 @@ impl<K: KdfTrait> Drop for ExporterSecret<K> {
         self.0.zeroize();
     }
 }
+impl<K: KdfTrait> ZeroizeOnDrop for ExporterSecret<K> {}
+
+impl<K: KdfTrait> ConstantTimeEq for ExporterSecret<K> {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
 
 // RFC 9180 §5.1
 // def KeySchedule<ROLE>(mode, shared_secret, info, psk, psk_id):
@@ -52,17 +90,47 @@ impl<K: KdfTrait> Drop for ExporterSecret<K> {
 //   return Context<ROLE>(key, base_nonce, 0, exporter_secret)
 
 // This is the KeySchedule function. It runs a KDF over all the parameters, inputs, and secrets,
-// and spits out a key-nonce pair to be used for symmetric encryption.
-fn derive_enc_ctx<A, Kdf, Kem, O>(
+// and spits out a key-nonce pair to be used for symmetric encryption. The info string is the
+// concatenation of `info_parts`.
+pub(crate) fn derive_enc_ctx<A, Kdf, Kem, O>(
     mode: &O,
     shared_secret: SharedSecret<Kem>,
-    info: &[u8],
+    info_parts: &[&[u8]],
+) -> AeadCtx<A, Kdf, Kem>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    O: OpMode<Kem>,
+{
+    derive_enc_ctx_observed(mode, shared_secret, info_parts, |_| ())
+}
+
+/// The intermediate values of one run of KeySchedule, named as in RFC 9180 §5.1. Only the test
+/// vector generator and the `debug-internals` setup functions read these.
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+pub(crate) struct KeyScheduleTrace<'a> {
+    pub(crate) key_schedule_context: &'a [u8],
+    pub(crate) secret: &'a [u8],
+    pub(crate) key: &'a [u8],
+    pub(crate) base_nonce: &'a [u8],
+    pub(crate) exporter_secret: &'a [u8],
+}
+
+// Same as derive_enc_ctx, but shows the intermediate values to `observe` before returning. This
+// is for generating test vectors.
+pub(crate) fn derive_enc_ctx_observed<A, Kdf, Kem, O, F>(
+    mode: &O,
+    shared_secret: SharedSecret<Kem>,
+    info_parts: &[&[u8]],
+    observe: F,
 ) -> AeadCtx<A, Kdf, Kem>
 where
     A: Aead,
     Kdf: KdfTrait,
     Kem: KemTrait,
     O: OpMode<Kem>,
+    F: FnOnce(KeyScheduleTrace<'_>),
 {
     // Put together the binding context used for all KDF operations
     let suite_id = full_suite_id::<A, Kdf, Kem>();
@@ -77,7 +145,7 @@ where
     let (sched_context_buf, sched_context_size) = {
         let (psk_id_hash, _) =
             labeled_extract::<Kdf>(&[], &suite_id, b"psk_id_hash", mode.get_psk_id());
-        let (info_hash, _) = labeled_extract::<Kdf>(&[], &suite_id, b"info_hash", info);
+        let (info_hash, _) = labeled_extract_multi::<Kdf>(&[], &suite_id, b"info_hash", info_parts);
 
         // Yes it's overkill to bound the first input by MAX_DIGEST_SIZE, since it's only 1 byte.
         // But whatever, this is pretty clean.
@@ -97,7 +165,7 @@ where
     //   exporter_secret = LabeledExpand(secret, "exp", key_schedule_context, Nh)
     // Instead of `secret` we derive an HKDF context which we run .expand() on to derive the
     // key-nonce pair.
-    let (_, secret_ctx) =
+    let (secret, secret_ctx) =
         labeled_extract::<Kdf>(&shared_secret.0, &suite_id, b"secret", mode.get_psk_bytes());
 
     // Empty fixed-size buffers
@@ -128,6 +196,14 @@ where
         )
         .expect("exporter secret len is way too big");
 
+    observe(KeyScheduleTrace {
+        key_schedule_context: sched_context,
+        secret: &secret,
+        key: &key.0,
+        base_nonce: &base_nonce.0,
+        exporter_secret: &exporter_secret.0,
+    });
+
     AeadCtx::new(&key, base_nonce, exporter_secret)
 }
 
@@ -154,14 +230,183 @@ where
     A: Aead,
     Kdf: KdfTrait,
     Kem: KemTrait,
-    R: CryptoRng + RngCore,
+    R: CryptoRng + RngCore + ?Sized,
 {
+    let _span = trace::enter_setup::<A, Kdf, Kem>("sender", mode.mode_id());
+
     // If the identity key is set, use it
     let sender_id_keypair = mode.get_sender_id_keypair();
     // Do the encapsulation
-    let (shared_secret, encapped_key) = Kem::encap(pk_recip, sender_id_keypair, csprng)?;
+    let (shared_secret, encapped_key) =
+        Kem::encap(pk_recip, sender_id_keypair, csprng).map_err(trace::setup_error)?;
+    // Use everything to derive an encryption context
+    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, &[info]);
+    usage_hook::record::<A, Kdf, Kem>(pk_recip, mode.mode_id());
+
+    Ok((encapped_key, enc_ctx.into()))
+}
+
+/// Initiates an encryption context to the recipient public key in `handle`. This is the same as
+/// `setup_sender(mode, handle.public_key(), info, csprng)`, but it uses the handle's precomputed
+/// tables, so it's faster for some KEMs. See `RecipientHandle` for details.
+///
+/// Return Value
+/// ============
+/// On success, returns an encapsulated public key (intended to be sent to the recipient), and an
+/// encryption context. If an error happened during key encapsulation, returns
+/// `Err(HpkeError::EncapError)`. This is the only possible error.
+pub fn setup_sender_with_handle<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    handle: &RecipientHandle<Kem>,
+    info: &[u8],
+    csprng: &mut R,
+) -> Result<(Kem::EncappedKey, AeadCtxS<A, Kdf, Kem>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let _span = trace::enter_setup::<A, Kdf, Kem>("sender", mode.mode_id());
+
+    // If the identity key is set, use it
+    let sender_id_keypair = mode.get_sender_id_keypair();
+    // Do the encapsulation using the precomputed tables
+    let (shared_secret, encapped_key) = Kem::encap_with_table(
+        handle.public_key(),
+        handle.table(),
+        sender_id_keypair,
+        csprng,
+    )
+    .map_err(trace::setup_error)?;
+    // Use everything to derive an encryption context
+    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, &[info]);
+    usage_hook::record::<A, Kdf, Kem>(handle.public_key(), mode.mode_id());
+
+    Ok((encapped_key, enc_ctx.into()))
+}
+
+/// Initiates an encryption context to each of the given recipient public keys, all with the same
+/// mode and info string. Every recipient gets an independent encapsulation with its own ephemeral
+/// key, exactly as if `setup_sender` were called once per recipient. With the `parallel` feature,
+/// the encapsulations are spread across the rayon thread pool. `csprng` is only ever used from
+/// the calling thread.
+///
+/// Return Value
+/// ============
+/// On success, returns one encapsulated key and encryption context per recipient, in the same
+/// order as `pk_recips`. If an error happened during any key encapsulation, returns
+/// `Err(HpkeError::EncapError)`. This is the only possible error.
+#[cfg(feature = "alloc")]
+#[allow(clippy::type_complexity)]
+pub fn setup_sender_multi<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recips: &[Kem::PublicKey],
+    info: &[u8],
+    csprng: &mut R,
+) -> Result<Vec<(Kem::EncappedKey, AeadCtxS<A, Kdf, Kem>)>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    // If the identity key is set, use it
+    let sender_id_keypair = mode.get_sender_id_keypair();
+    // Do all the encapsulations
+    let encaps = Kem::encap_batch(pk_recips, sender_id_keypair, csprng)?;
+
+    // Use everything to derive the encryption contexts
+    Ok(encaps
+        .into_iter()
+        .zip(pk_recips)
+        .map(|((shared_secret, encapped_key), pk_recip)| {
+            let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, &[info]);
+            usage_hook::record::<A, Kdf, Kem>(pk_recip, mode.mode_id());
+            (encapped_key, enc_ctx.into())
+        })
+        .collect())
+}
+
+/// Initiates an encryption context to the given recipient public key, deriving the ephemeral
+/// keypair deterministically from `ikm_eph` instead of sampling it from an RNG. The same inputs
+/// always produce the same encapsulated key and encryption context.
+///
+/// DANGER
+/// ======
+/// This is for producing fixed ciphertexts in tests and CI. It is NOT safe for real use. Calling
+/// this twice with the same `ikm_eph` reuses the ephemeral key, and if the other inputs also
+/// match, the same key and nonce sequence, which breaks the confidentiality and integrity of
+/// everything sealed under it. Use `setup_sender` instead.
+///
+/// Return Value
+/// ============
+/// On success, returns an encapsulated public key (intended to be sent to the recipient), and an
+/// encryption context. If an error happened during key encapsulation, returns
+/// `Err(HpkeError::EncapError)`. This is the only possible error.
+pub fn setup_sender_deterministic<A, Kdf, Kem>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    ikm_eph: &[u8],
+) -> Result<(Kem::EncappedKey, AeadCtxS<A, Kdf, Kem>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // If the identity key is set, use it
+    let sender_id_keypair = mode.get_sender_id_keypair();
+    // Do the encapsulation with the derived ephemeral key
+    let (shared_secret, encapped_key) = Kem::encap_with_ikm(pk_recip, sender_id_keypair, ikm_eph)?;
+    // Use everything to derive an encryption context
+    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, &[info]);
+    usage_hook::record::<A, Kdf, Kem>(pk_recip, mode.mode_id());
+
+    Ok((encapped_key, enc_ctx.into()))
+}
+
+/// Initiates an encryption context to the given recipient public key, using the given ephemeral
+/// keypair instead of sampling one. The encapsulated key is `pk_eph`. This is for protocols that
+/// must reuse an encapsulation, like TLS ECH across a HelloRetryRequest, where the ephemeral
+/// keypair from the first `setup_sender` has to be kept and used again.
+///
+/// DANGER
+/// ======
+/// With the same ephemeral keypair, recipient, mode, and info string, this always produces the
+/// same encryption context, so its sequence numbers start over at 0, and sealing different
+/// messages with both contexts reuses nonces. That breaks the confidentiality and integrity of
+/// everything sealed under them. Only reuse an ephemeral keypair where the protocol says to, and
+/// make sure the info string, or something else in the key schedule, differs between uses, or
+/// that only one of the resulting contexts is ever sealed with. Never use a long-term key as the
+/// ephemeral key.
+///
+/// Return Value
+/// ============
+/// On success, returns the encapsulated key, which is `pk_eph`, and an encryption context. If
+/// `pk_eph` isn't the public key of `sk_eph`, `Kem` doesn't support caller-chosen ephemeral keys,
+/// or an error happened during key encapsulation, returns `Err(HpkeError::EncapError)`. Every
+/// DHKEM, and every `CombinedKem` of them, supports them.
+pub fn setup_sender_with_ephemeral<A, Kdf, Kem>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    sk_eph: &Kem::PrivateKey,
+    pk_eph: &Kem::PublicKey,
+) -> Result<(Kem::EncappedKey, AeadCtxS<A, Kdf, Kem>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // If the identity key is set, use it
+    let sender_id_keypair = mode.get_sender_id_keypair();
+    // Do the encapsulation with the given ephemeral key
+    let (shared_secret, encapped_key) =
+        Kem::encap_with_ephemeral(pk_recip, sender_id_keypair, sk_eph, pk_eph)?;
     // Use everything to derive an encryption context
-    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info);
+    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, &[info]);
+    usage_hook::record::<A, Kdf, Kem>(pk_recip, mode.mode_id());
 
     Ok((encapped_key, enc_ctx.into()))
 }
@@ -190,21 +435,71 @@ where
     Kdf: KdfTrait,
     Kem: KemTrait,
 {
+    let _span = trace::enter_setup::<A, Kdf, Kem>("receiver", mode.mode_id());
+
     // If the identity key is set, use it
     let pk_sender_id: Option<&Kem::PublicKey> = mode.get_pk_sender_id();
     // Do the decapsulation
-    let shared_secret = Kem::decap(sk_recip, pk_sender_id, encapped_key)?;
+    let shared_secret =
+        Kem::decap(sk_recip, pk_sender_id, encapped_key).map_err(trace::setup_error)?;
 
     // Use everything to derive an encryption context
-    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info);
+    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, &[info]);
     Ok(enc_ctx.into())
 }
 
+/// Runs `setup_receiver` on many encapsulated keys that were all encapsulated to `sk_recip`'s
+/// public key, under the same mode and info string. This is faster than calling `setup_receiver`
+/// in a loop, since the work that only depends on `sk_recip` and the mode is done once for the
+/// whole batch. With the `parallel` feature, the decapsulations are spread across the rayon
+/// thread pool.
+///
+/// Return Value
+/// ============
+/// Returns one result per encapsulated key, in the same order as `encapped_keys`. Each result is
+/// what `setup_receiver` would have returned for that key, i.e., a decryption context or
+/// `Err(HpkeError::DecapError)`. A bad key does not affect the rest of the batch.
+#[cfg(feature = "alloc")]
+pub fn setup_receiver_batch<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_keys: &[Kem::EncappedKey],
+    info: &[u8],
+) -> Vec<Result<AeadCtxR<A, Kdf, Kem>, HpkeError>>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // If the identity key is set, use it
+    let pk_sender_id: Option<&Kem::PublicKey> = mode.get_pk_sender_id();
+    // Do all the decapsulations
+    let shared_secrets = Kem::decap_batch(sk_recip, pk_sender_id, encapped_keys);
+
+    // Use everything to derive the encryption contexts
+    shared_secrets
+        .into_iter()
+        .map(|res| res.map(|ss| derive_enc_ctx::<_, _, Kem, _>(mode, ss, &[info]).into()))
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
-    use super::{setup_receiver, setup_sender};
+    use super::{
+        setup_receiver, setup_sender, setup_sender_deterministic, setup_sender_with_ephemeral,
+        setup_sender_with_handle,
+    };
+    #[cfg(feature = "alloc")]
+    use super::{setup_receiver_batch, ReceiverBuilder, SenderBuilder};
     use crate::test_util::{aead_ctx_eq, gen_rand_buf, new_op_mode_pair, OpModeKind};
-    use crate::{aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait};
+    #[cfg(feature = "alloc")]
+    use crate::Vec;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, RecipientHandle},
+        Deserializable, HpkeError, Serializable,
+    };
 
     use rand::{rngs::StdRng, SeedableRng};
 
@@ -235,7 +530,7 @@ mod test {
                     // Generate a mutually agreeing op mode pair
                     let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
                     let (sender_mode, receiver_mode) =
-                        new_op_mode_pair::<Kdf, Kem>(*op_mode_kind, &psk, &psk_id);
+                        new_op_mode_pair::<Kem>(*op_mode_kind, &psk, &psk_id);
 
                     // Construct the sender's encryption context, and get an encapped key
                     let (encapped_key, mut aead_ctx1) = setup_sender::<A, Kdf, Kem, _>(
@@ -257,6 +552,24 @@ mod test {
 
                     // Ensure that the two derived contexts are equivalent
                     assert!(aead_ctx_eq(&mut aead_ctx1, &mut aead_ctx2));
+
+                    // Do the same thing, but encapsulate using a precomputed recipient handle
+                    let handle = RecipientHandle::<Kem>::new(&pk_recip);
+                    let (encapped_key, mut aead_ctx1) = setup_sender_with_handle::<A, Kdf, Kem, _>(
+                        &sender_mode,
+                        &handle,
+                        &info[..],
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    let mut aead_ctx2 = setup_receiver::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        &sk_recip,
+                        &encapped_key,
+                        &info[..],
+                    )
+                    .unwrap();
+                    assert!(aead_ctx_eq(&mut aead_ctx1, &mut aead_ctx2));
                 }
             }
         };
@@ -281,7 +594,7 @@ mod test {
                 // Generate a mutually agreeing op mode pair
                 let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
                 let (sender_mode, receiver_mode) =
-                    new_op_mode_pair::<Kdf, Kem>(OpModeKind::Base, &psk, &psk_id);
+                    new_op_mode_pair::<Kem>(OpModeKind::Base, &psk, &psk_id);
 
                 // Construct the sender's encryption context normally
                 let (encapped_key, sender_ctx) =
@@ -337,6 +650,313 @@ mod test {
         };
     }
 
+    /// Tests that `setup_sender_deterministic` is reproducible, that it depends on the IKM, and
+    /// that its output is openable by an ordinary `setup_receiver`
+    #[cfg(feature = "alloc")]
+    macro_rules! test_setup_deterministic {
+        ($test_name:ident, $aead:ty, $kdf:ty, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead;
+                type Kdf = $kdf;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+
+                let info = b"the same thing over and over";
+                let ikm_eph = [0x42u8; 32];
+
+                // Generate the receiver's long-term keypair
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+                // Generate a mutually agreeing op mode pair
+                let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                let (sender_mode, receiver_mode) =
+                    new_op_mode_pair::<Kem>(OpModeKind::AuthPsk, &psk, &psk_id);
+
+                // Set up twice with the same IKM. Everything should match
+                let (encapped_key1, mut sender_ctx1) = setup_sender_deterministic::<A, Kdf, Kem>(
+                    &sender_mode,
+                    &pk_recip,
+                    &info[..],
+                    &ikm_eph,
+                )
+                .unwrap();
+                let (encapped_key2, mut sender_ctx2) = setup_sender_deterministic::<A, Kdf, Kem>(
+                    &sender_mode,
+                    &pk_recip,
+                    &info[..],
+                    &ikm_eph,
+                )
+                .unwrap();
+                assert_eq!(encapped_key1.to_bytes(), encapped_key2.to_bytes());
+                let msg = b"fixed ciphertexts in CI";
+                let ciphertext = sender_ctx1.seal(msg, b"").unwrap();
+                // That's a nonce reuse, on purpose
+                #[cfg(feature = "nonce-reuse-check")]
+                sender_ctx2.forget_sealed_nonces();
+                assert_eq!(ciphertext, sender_ctx2.seal(msg, b"").unwrap());
+
+                // A different IKM should give a different encapped key
+                let (encapped_key3, _) = setup_sender_deterministic::<A, Kdf, Kem>(
+                    &sender_mode,
+                    &pk_recip,
+                    &info[..],
+                    &[0x43u8; 32],
+                )
+                .unwrap();
+                assert_ne!(encapped_key1.to_bytes(), encapped_key3.to_bytes());
+
+                // The receiver doesn't need to know how the ephemeral key was made
+                let mut receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key1,
+                    &info[..],
+                )
+                .unwrap();
+                assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), msg);
+                assert!(aead_ctx_eq(&mut sender_ctx1, &mut receiver_ctx));
+            }
+        };
+    }
+
+    /// Tests that `setup_sender_with_ephemeral` sends the given ephemeral public key, agrees with
+    /// `setup_receiver`, rejects mismatched keypairs, and that GREASE encapped keys don't
+    /// decapsulate to a working context
+    macro_rules! test_setup_with_ephemeral {
+        ($test_name:ident, $aead:ty, $kdf:ty, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead;
+                type Kdf = $kdf;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (sk_eph, pk_eph) = Kem::gen_keypair(&mut csprng);
+
+                let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                let (sender_mode, receiver_mode) =
+                    new_op_mode_pair::<Kem>(OpModeKind::AuthPsk, &psk, &psk_id);
+
+                // Two setups with the same ephemeral key, e.g., before and after a
+                // HelloRetryRequest, send the same encapped key. Different info strings keep the
+                // contexts apart.
+                let (encapped_key1, mut sender_ctx1) = setup_sender_with_ephemeral::<A, Kdf, Kem>(
+                    &sender_mode,
+                    &pk_recip,
+                    b"first",
+                    &sk_eph,
+                    &pk_eph,
+                )
+                .unwrap();
+                let (encapped_key2, mut sender_ctx2) = setup_sender_with_ephemeral::<A, Kdf, Kem>(
+                    &sender_mode,
+                    &pk_recip,
+                    b"second",
+                    &sk_eph,
+                    &pk_eph,
+                )
+                .unwrap();
+                assert_eq!(encapped_key1.to_bytes(), pk_eph.to_bytes());
+                assert_eq!(encapped_key2.to_bytes(), pk_eph.to_bytes());
+
+                for (info, sender_ctx) in [
+                    (&b"first"[..], &mut sender_ctx1),
+                    (&b"second"[..], &mut sender_ctx2),
+                ] {
+                    let mut receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        &sk_recip,
+                        &encapped_key1,
+                        info,
+                    )
+                    .unwrap();
+                    assert!(aead_ctx_eq(sender_ctx, &mut receiver_ctx));
+                }
+
+                // The public key has to match the private key
+                let (_, other_pk) = Kem::gen_keypair(&mut csprng);
+                assert!(matches!(
+                    setup_sender_with_ephemeral::<A, Kdf, Kem>(
+                        &sender_mode,
+                        &pk_recip,
+                        b"first",
+                        &sk_eph,
+                        &other_pk,
+                    ),
+                    Err(HpkeError::EncapError)
+                ));
+
+                // A GREASE encapped key is fresh every time, parses like a real one, and gives
+                // the receiver a context that doesn't match any sender's
+                let grease = Kem::generate_grease_encapped_key(&mut csprng);
+                assert_ne!(
+                    grease.to_bytes(),
+                    Kem::generate_grease_encapped_key(&mut csprng).to_bytes()
+                );
+                let reparsed =
+                    <Kem as KemTrait>::EncappedKey::from_bytes(&grease.to_bytes()).unwrap();
+                let mut grease_ctx =
+                    setup_receiver::<A, Kdf, Kem>(&receiver_mode, &sk_recip, &reparsed, b"first")
+                        .unwrap();
+                assert!(!aead_ctx_eq(&mut sender_ctx1, &mut grease_ctx));
+            }
+        };
+    }
+
+    /// Tests that a domain set with the builders must match on both sides, and that it's the same
+    /// as prefixing the encoded domain to the info string
+    #[cfg(feature = "alloc")]
+    macro_rules! test_setup_domain {
+        ($test_name:ident, $aead:ty, $kdf:ty, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead;
+                type Kdf = $kdf;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+
+                let info = b"shared keys, separate apps";
+                let domain = b"com.example.chat";
+
+                // Generate the receiver's long-term keypair
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+                // Generate a mutually agreeing op mode pair
+                let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                let (sender_mode, receiver_mode) =
+                    new_op_mode_pair::<Kem>(OpModeKind::AuthPsk, &psk, &psk_id);
+
+                let (encapped_key, sender_ctx) = SenderBuilder::new(&sender_mode, &pk_recip)
+                    .info(info)
+                    .domain(domain)
+                    .setup::<A, Kdf, _>(&mut csprng)
+                    .unwrap();
+                let receiver = ReceiverBuilder::new(&receiver_mode, &sk_recip, &encapped_key);
+
+                // The same domain agrees
+                let mut receiver_ctx = receiver.info(info).domain(domain).setup().unwrap();
+                assert!(aead_ctx_eq(&mut sender_ctx.clone(), &mut receiver_ctx));
+
+                // A different domain, or none at all, doesn't
+                let receiver = ReceiverBuilder::new(&receiver_mode, &sk_recip, &encapped_key);
+                let mut receiver_ctx = receiver
+                    .info(info)
+                    .domain(b"com.example.mail")
+                    .setup()
+                    .unwrap();
+                assert!(!aead_ctx_eq(&mut sender_ctx.clone(), &mut receiver_ctx));
+                let mut receiver_ctx =
+                    setup_receiver::<A, Kdf, Kem>(&receiver_mode, &sk_recip, &encapped_key, info)
+                        .unwrap();
+                assert!(!aead_ctx_eq(&mut sender_ctx.clone(), &mut receiver_ctx));
+
+                // Any other implementation can get the same context using the encoded info string
+                let mut encoded_info = Vec::new();
+                encoded_info.extend_from_slice(&[0, 11]);
+                encoded_info.extend_from_slice(b"HPKE-domain");
+                encoded_info.extend_from_slice(&(domain.len() as u16).to_be_bytes());
+                encoded_info.extend_from_slice(domain);
+                encoded_info.extend_from_slice(info);
+                let mut receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    &encoded_info,
+                )
+                .unwrap();
+                assert!(aead_ctx_eq(&mut sender_ctx.clone(), &mut receiver_ctx));
+
+                // Without a domain, the builders are the same as the plain functions
+                let (encapped_key, mut sender_ctx) = SenderBuilder::new(&sender_mode, &pk_recip)
+                    .info(info)
+                    .setup::<A, Kdf, _>(&mut csprng)
+                    .unwrap();
+                let mut receiver_ctx =
+                    setup_receiver::<A, Kdf, Kem>(&receiver_mode, &sk_recip, &encapped_key, info)
+                        .unwrap();
+                assert!(aead_ctx_eq(&mut sender_ctx, &mut receiver_ctx));
+
+                // Domains longer than 65535 bytes are rejected
+                let long_domain = vec![0u8; 65536];
+                let res = SenderBuilder::new(&sender_mode, &pk_recip)
+                    .domain(&long_domain)
+                    .setup::<A, Kdf, _>(&mut csprng);
+                assert!(matches!(res, Err(crate::HpkeError::ValidationError)));
+            }
+        };
+    }
+
+    /// Tests that `setup_receiver_batch` gives the same contexts as `setup_receiver`, in order, and
+    /// that a bad encapped key only spoils its own slot
+    #[cfg(feature = "alloc")]
+    macro_rules! test_setup_receiver_batch {
+        ($test_name:ident, $aead:ty, $kdf:ty, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead;
+                type Kdf = $kdf;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+
+                let info = b"one of many";
+
+                // Generate the receiver's long-term keypair
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+                for op_mode_kind in &[OpModeKind::Base, OpModeKind::AuthPsk] {
+                    // Generate a mutually agreeing op mode pair
+                    let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                    let (sender_mode, receiver_mode) =
+                        new_op_mode_pair::<Kem>(*op_mode_kind, &psk, &psk_id);
+
+                    // Make a bunch of senders
+                    let (mut encapped_keys, mut sender_ctxs): (Vec<_>, Vec<_>) = (0..5)
+                        .map(|_| {
+                            setup_sender::<A, Kdf, Kem, _>(
+                                &sender_mode,
+                                &pk_recip,
+                                &info[..],
+                                &mut csprng,
+                            )
+                            .unwrap()
+                        })
+                        .unzip();
+
+                    // Replace one of the encapped keys with one for a different recipient
+                    let (_, other_pk) = Kem::gen_keypair(&mut csprng);
+                    encapped_keys[2] = setup_sender::<A, Kdf, Kem, _>(
+                        &sender_mode,
+                        &other_pk,
+                        &info[..],
+                        &mut csprng,
+                    )
+                    .unwrap()
+                    .0;
+
+                    let receiver_ctxs = setup_receiver_batch::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        &sk_recip,
+                        &encapped_keys,
+                        &info[..],
+                    );
+                    assert_eq!(receiver_ctxs.len(), encapped_keys.len());
+
+                    for (i, (sender_ctx, receiver_ctx)) in
+                        sender_ctxs.iter_mut().zip(receiver_ctxs).enumerate()
+                    {
+                        let mut receiver_ctx = receiver_ctx.unwrap();
+                        assert_eq!(aead_ctx_eq(sender_ctx, &mut receiver_ctx), i != 2);
+                    }
+                }
+            }
+        };
+    }
+
     #[cfg(feature = "x25519-dalek")]
     mod x25519_tests {
         use super::*;
@@ -353,6 +973,111 @@ mod test {
             HkdfSha256,
             crate::kem::x25519_hkdfsha256::X25519HkdfSha256
         );
+        #[cfg(feature = "alloc")]
+        test_setup_domain!(
+            test_setup_domain_x25519,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::x25519_hkdfsha256::X25519HkdfSha256
+        );
+        #[cfg(feature = "alloc")]
+        test_setup_deterministic!(
+            test_setup_deterministic_x25519,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::x25519_hkdfsha256::X25519HkdfSha256
+        );
+        test_setup_with_ephemeral!(
+            test_setup_with_ephemeral_x25519,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::x25519_hkdfsha256::X25519HkdfSha256
+        );
+        #[cfg(feature = "alloc")]
+        test_setup_receiver_batch!(
+            test_setup_receiver_batch_x25519,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::x25519_hkdfsha256::X25519HkdfSha256
+        );
+    }
+
+    // A private suite with its own code points for every algorithm
+    #[cfg(feature = "x25519-dalek")]
+    mod custom_id_tests {
+        use super::*;
+        use crate::{
+            aead::CustomAeadId,
+            dhkex::X25519,
+            kdf::CustomKdfId,
+            kem::{DhKem, X25519HkdfSha256},
+            OpModeR, OpModeS,
+        };
+
+        type CustomA = CustomAeadId<ChaCha20Poly1305, 0xFF10>;
+        type CustomKdf = CustomKdfId<HkdfSha256, 0xFF11>;
+        type CustomKem = DhKem<X25519, CustomKdf, 0xFF20>;
+
+        test_setup_correctness!(
+            test_setup_correctness_custom_ids,
+            CustomA,
+            CustomKdf,
+            CustomKem
+        );
+        test_setup_soundness!(
+            test_setup_soundness_custom_ids,
+            CustomA,
+            CustomKdf,
+            CustomKem
+        );
+
+        /// Tests that overridden IDs change the key schedule, and that it stays deterministic
+        #[test]
+        fn test_custom_ids_key_schedule() {
+            let ikm_recip = [1u8; 32];
+            let ikm_eph = [2u8; 32];
+
+            // Sets up a deterministic context pair and returns an export from the receiver
+            fn export<A, Kdf, Kem>(ikm_recip: &[u8], ikm_eph: &[u8]) -> [u8; 32]
+            where
+                A: crate::aead::Aead,
+                Kdf: crate::kdf::Kdf,
+                Kem: KemTrait,
+            {
+                let (sk_recip, pk_recip) = Kem::derive_keypair(ikm_recip);
+                let (encapped_key, _) = setup_sender_deterministic::<A, Kdf, Kem>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    b"info",
+                    ikm_eph,
+                )
+                .unwrap();
+                let ctx = setup_receiver::<A, Kdf, Kem>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    b"info",
+                )
+                .unwrap();
+                let mut out = [0u8; 32];
+                ctx.export(b"test", &mut out).unwrap();
+                out
+            }
+
+            let custom = export::<CustomA, CustomKdf, CustomKem>(&ikm_recip, &ikm_eph);
+            assert_eq!(
+                custom,
+                export::<CustomA, CustomKdf, CustomKem>(&ikm_recip, &ikm_eph)
+            );
+
+            let standard =
+                export::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256>(&ikm_recip, &ikm_eph);
+            assert_ne!(custom, standard);
+            // Overriding any one ID is enough to change it
+            let aead_only = export::<CustomA, HkdfSha256, X25519HkdfSha256>(&ikm_recip, &ikm_eph);
+            assert_ne!(aead_only, standard);
+            assert_ne!(aead_only, custom);
+        }
     }
 
     #[cfg(feature = "p256")]
@@ -371,5 +1096,57 @@ mod test {
             HkdfSha256,
             crate::kem::dhp256_hkdfsha256::DhP256HkdfSha256
         );
+        #[cfg(feature = "alloc")]
+        test_setup_domain!(
+            test_setup_domain_p256,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::dhp256_hkdfsha256::DhP256HkdfSha256
+        );
+        #[cfg(feature = "alloc")]
+        test_setup_deterministic!(
+            test_setup_deterministic_p256,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::dhp256_hkdfsha256::DhP256HkdfSha256
+        );
+        test_setup_with_ephemeral!(
+            test_setup_with_ephemeral_p256,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::dhp256_hkdfsha256::DhP256HkdfSha256
+        );
+        #[cfg(feature = "alloc")]
+        test_setup_receiver_batch!(
+            test_setup_receiver_batch_p256,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::dhp256_hkdfsha256::DhP256HkdfSha256
+        );
+    }
+
+    #[cfg(feature = "k256")]
+    mod k256_tests {
+        use super::*;
+
+        test_setup_correctness!(
+            test_setup_correctness_k256,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::dhk256_hkdfsha256::DhK256HkdfSha256
+        );
+        test_setup_with_ephemeral!(
+            test_setup_with_ephemeral_k256,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::dhk256_hkdfsha256::DhK256HkdfSha256
+        );
+        #[cfg(feature = "alloc")]
+        test_setup_receiver_batch!(
+            test_setup_receiver_batch_k256,
+            ChaCha20Poly1305,
+            HkdfSha256,
+            crate::kem::dhk256_hkdfsha256::DhK256HkdfSha256
+        );
     }
 }
@@ -0,0 +1,223 @@
+use crate::{
+    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS},
+    kdf::Kdf as KdfTrait,
+    kem::{Kem as KemTrait, SharedSecret},
+    op_mode::{OpMode, OpModeR, OpModeS},
+    HpkeError,
+};
+
+use super::derive_enc_ctx;
+
+use rand_core::{CryptoRng, RngCore};
+
+// A domain is bound into the key schedule by prefixing it to the info string:
+//   info' = concat(I2OSP(len("HPKE-domain"), 2), "HPKE-domain", I2OSP(len(domain), 2), domain,
+//                  info)
+// Both the label and the domain are length-prefixed, as in labeled HKDF, so this is injective:
+// two setups that both set a domain get the same info string only if they have the same domain
+// and info. It doesn't separate them from setups without a domain, though. A plain info string
+// that happens to be an encoded (domain, info) gives the same context. Since it's just an info
+// string, any RFC 9180 implementation can interoperate by passing info' as its info.
+const DOMAIN_LABEL: &[u8] = b"HPKE-domain";
+const DOMAIN_LABEL_LEN: [u8; 2] = (DOMAIN_LABEL.len() as u16).to_be_bytes();
+
+/// Checks that the domain's length fits in 2 bytes
+fn check_domain(domain: Option<&[u8]>) -> Result<(), HpkeError> {
+    match domain {
+        Some(d) if d.len() > u16::MAX as usize => Err(HpkeError::ValidationError),
+        _ => Ok(()),
+    }
+}
+
+/// Runs the key schedule with the domain, if any, prefixed to the info string. The domain must
+/// have passed `check_domain`.
+fn derive_enc_ctx_with_domain<A, Kdf, Kem, O>(
+    mode: &O,
+    shared_secret: SharedSecret<Kem>,
+    domain: Option<&[u8]>,
+    info: &[u8],
+) -> AeadCtx<A, Kdf, Kem>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    O: OpMode<Kem>,
+{
+    match domain {
+        Some(domain) => {
+            let len = (domain.len() as u16).to_be_bytes();
+            derive_enc_ctx(
+                mode,
+                shared_secret,
+                &[&DOMAIN_LABEL_LEN, DOMAIN_LABEL, &len, domain, info],
+            )
+        }
+        None => derive_enc_ctx(mode, shared_secret, &[info]),
+    }
+}
+
+/// Sets up an encryption context, with optional parameters. This is `setup_sender` with the
+/// option of an application domain, via `domain`.
+///
+/// Example
+/// =======
+/// ```
+/// # #[cfg(feature = "x25519")]
+/// # {
+/// # use rand::{rngs::StdRng, SeedableRng};
+/// # use hpke::{aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::X25519HkdfSha256, Kem, OpModeS};
+/// # let mut csprng = StdRng::from_entropy();
+/// # let (_, pk_recip) = X25519HkdfSha256::gen_keypair(&mut csprng);
+/// let mode = OpModeS::<X25519HkdfSha256>::Base;
+/// let (encapped_key, ctx) = hpke::SenderBuilder::new(&mode, &pk_recip)
+///     .info(b"session 12")
+///     .domain(b"com.example.chat")
+///     .setup::<ChaCha20Poly1305, HkdfSha256, _>(&mut csprng)
+///     .unwrap();
+/// # }
+/// ```
+pub struct SenderBuilder<'a, Kem: KemTrait> {
+    mode: &'a OpModeS<'a, Kem>,
+    pk_recip: &'a Kem::PublicKey,
+    info: &'a [u8],
+    domain: Option<&'a [u8]>,
+}
+
+impl<'a, Kem: KemTrait> SenderBuilder<'a, Kem> {
+    /// Starts setting up an encryption context to `pk_recip` in the given mode. The info string
+    /// is empty and there is no domain until they're set.
+    pub fn new(mode: &'a OpModeS<'a, Kem>, pk_recip: &'a Kem::PublicKey) -> Self {
+        SenderBuilder {
+            mode,
+            pk_recip,
+            info: &[],
+            domain: None,
+        }
+    }
+
+    /// Sets the info string. This is the same as `setup_sender`'s `info`.
+    pub fn info(mut self, info: &'a [u8]) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Sets the application domain. This is bound into the key schedule alongside the info
+    /// string, so a context set up under one domain never agrees with one set up under another,
+    /// even with the same keys and info. Use a name that's unique to your application, like a
+    /// reverse DNS name. The domain can be at most 65535 bytes long.
+    ///
+    /// This is the same as using `I2OSP(11, 2) || "HPKE-domain" || I2OSP(len(domain), 2) || domain
+    /// || info` as the info string, so other HPKE implementations can interoperate with it. It also
+    /// means a setup without a domain, whose info string is that encoding, agrees with this one.
+    pub fn domain(mut self, domain: &'a [u8]) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    /// Does the setup
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns an encapsulated public key (intended to be sent to the recipient),
+    /// and an encryption context. If the domain is longer than 65535 bytes, returns
+    /// `Err(HpkeError::ValidationError)`. If an error happened during key encapsulation, returns
+    /// `Err(HpkeError::EncapError)`.
+    pub fn setup<A, Kdf, R>(
+        self,
+        csprng: &mut R,
+    ) -> Result<(Kem::EncappedKey, AeadCtxS<A, Kdf, Kem>), HpkeError>
+    where
+        A: Aead,
+        Kdf: KdfTrait,
+        R: CryptoRng + RngCore + ?Sized,
+    {
+        // Check the domain before doing any work
+        check_domain(self.domain)?;
+
+        // If the identity key is set, use it
+        let sender_id_keypair = self.mode.get_sender_id_keypair();
+        // Do the encapsulation
+        let (shared_secret, encapped_key) = Kem::encap(self.pk_recip, sender_id_keypair, csprng)?;
+        // Use everything to derive an encryption context
+        let enc_ctx = derive_enc_ctx_with_domain::<_, _, Kem, _>(
+            self.mode,
+            shared_secret,
+            self.domain,
+            self.info,
+        );
+        super::usage_hook::record::<A, Kdf, Kem>(self.pk_recip, self.mode.mode_id());
+
+        Ok((encapped_key, enc_ctx.into()))
+    }
+}
+
+/// Sets up a decryption context, with optional parameters. This is `setup_receiver` with the
+/// option of an application domain, via `domain`. See `SenderBuilder`.
+pub struct ReceiverBuilder<'a, Kem: KemTrait> {
+    mode: &'a OpModeR<'a, Kem>,
+    sk_recip: &'a Kem::PrivateKey,
+    encapped_key: &'a Kem::EncappedKey,
+    info: &'a [u8],
+    domain: Option<&'a [u8]>,
+}
+
+impl<'a, Kem: KemTrait> ReceiverBuilder<'a, Kem> {
+    /// Starts setting up a decryption context for `encapped_key`, which was encapsulated to
+    /// `sk_recip`'s public key. The info string is empty and there is no domain until they're
+    /// set.
+    pub fn new(
+        mode: &'a OpModeR<'a, Kem>,
+        sk_recip: &'a Kem::PrivateKey,
+        encapped_key: &'a Kem::EncappedKey,
+    ) -> Self {
+        ReceiverBuilder {
+            mode,
+            sk_recip,
+            encapped_key,
+            info: &[],
+            domain: None,
+        }
+    }
+
+    /// Sets the info string. This is the same as `setup_receiver`'s `info`.
+    pub fn info(mut self, info: &'a [u8]) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Sets the application domain. This must match the sender's. See `SenderBuilder::domain`.
+    pub fn domain(mut self, domain: &'a [u8]) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    /// Does the setup
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns a decryption context. If the domain is longer than 65535 bytes,
+    /// returns `Err(HpkeError::ValidationError)`. If an error happened during key decapsulation,
+    /// returns `Err(HpkeError::DecapError)`.
+    pub fn setup<A, Kdf>(self) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+    where
+        A: Aead,
+        Kdf: KdfTrait,
+    {
+        // Check the domain before doing any work
+        check_domain(self.domain)?;
+
+        // If the identity key is set, use it
+        let pk_sender_id = self.mode.get_pk_sender_id();
+        // Do the decapsulation
+        let shared_secret = Kem::decap(self.sk_recip, pk_sender_id, self.encapped_key)?;
+        // Use everything to derive an encryption context
+        let enc_ctx = derive_enc_ctx_with_domain::<_, _, Kem, _>(
+            self.mode,
+            shared_secret,
+            self.domain,
+            self.info,
+        );
+
+        Ok(enc_ctx.into())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpMode, OpModeR, OpModeS},
+    HpkeError, Vec,
+};
+
+use super::{derive_enc_ctx_observed, KeyScheduleTrace};
+
+use core::fmt;
+
+use rand_core::{CryptoRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// The secret intermediate values of one HPKE setup, named as in RFC 9180 §5.1. Comparing these
+/// with another implementation's shows which step of the setup they disagree on.
+///
+/// DANGER
+/// ======
+/// These are the secrets that protect the session. Anyone who sees them can decrypt everything in
+/// it. Don't log them outside of debugging, and never enable the `debug-internals` feature in
+/// production builds.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct KeyScheduleValues {
+    /// The KEM's shared secret
+    pub shared_secret: Vec<u8>,
+    /// `mode || psk_id_hash || info_hash`
+    pub key_schedule_context: Vec<u8>,
+    /// `LabeledExtract(shared_secret, "secret", psk)`
+    pub secret: Vec<u8>,
+    /// The AEAD key
+    pub key: Vec<u8>,
+    /// The AEAD base nonce
+    pub base_nonce: Vec<u8>,
+    /// The exporter secret
+    pub exporter_secret: Vec<u8>,
+}
+
+impl KeyScheduleValues {
+    fn new(shared_secret: Vec<u8>, trace: KeyScheduleTrace<'_>) -> Self {
+        KeyScheduleValues {
+            shared_secret,
+            key_schedule_context: trace.key_schedule_context.to_vec(),
+            secret: trace.secret.to_vec(),
+            key: trace.key.to_vec(),
+            base_nonce: trace.base_nonce.to_vec(),
+            exporter_secret: trace.exporter_secret.to_vec(),
+        }
+    }
+}
+
+// Prints every value in hex, the way the RFC 9180 test vectors write them
+impl fmt::Debug for KeyScheduleValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Hex<'a>(&'a [u8]);
+        impl fmt::Debug for Hex<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+        }
+
+        f.debug_struct("KeyScheduleValues")
+            .field("shared_secret", &Hex(&self.shared_secret))
+            .field("key_schedule_context", &Hex(&self.key_schedule_context))
+            .field("secret", &Hex(&self.secret))
+            .field("key", &Hex(&self.key))
+            .field("base_nonce", &Hex(&self.base_nonce))
+            .field("exporter_secret", &Hex(&self.exporter_secret))
+            .finish()
+    }
+}
+
+/// Same as `setup_sender`, but also returns the intermediate values of the key schedule
+///
+/// DANGER
+/// ======
+/// The returned values are secret. See `KeyScheduleValues`.
+///
+/// Return Value
+/// ============
+/// On success, returns an encapsulated public key, an encryption context, and the key schedule's
+/// intermediate values. If an error happened during key encapsulation, returns
+/// `Err(HpkeError::EncapError)`. This is the only possible error.
+#[allow(clippy::type_complexity)]
+pub fn setup_sender_debug<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    csprng: &mut R,
+) -> Result<(Kem::EncappedKey, AeadCtxS<A, Kdf, Kem>, KeyScheduleValues), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let sender_id_keypair = mode.get_sender_id_keypair();
+    let (shared_secret, encapped_key) = Kem::encap(pk_recip, sender_id_keypair, csprng)?;
+    let shared_secret_bytes = shared_secret.0.to_vec();
+
+    let mut values = None;
+    let enc_ctx = derive_enc_ctx_observed::<_, _, Kem, _, _>(mode, shared_secret, &[info], |t| {
+        values = Some(KeyScheduleValues::new(shared_secret_bytes, t))
+    });
+    super::usage_hook::record::<A, Kdf, Kem>(pk_recip, mode.mode_id());
+
+    Ok((
+        encapped_key,
+        enc_ctx.into(),
+        values.expect("key schedule was not observed"),
+    ))
+}
+
+/// Same as `setup_receiver`, but also returns the intermediate values of the key schedule
+///
+/// DANGER
+/// ======
+/// The returned values are secret. See `KeyScheduleValues`.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context and the key schedule's intermediate values. If an
+/// error happened during key decapsulation, returns `Err(HpkeError::DecapError)`. This is the
+/// only possible error.
+pub fn setup_receiver_debug<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+) -> Result<(AeadCtxR<A, Kdf, Kem>, KeyScheduleValues), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let pk_sender_id = mode.get_pk_sender_id();
+    let shared_secret = Kem::decap(sk_recip, pk_sender_id, encapped_key)?;
+    let shared_secret_bytes = shared_secret.0.to_vec();
+
+    let mut values = None;
+    let enc_ctx = derive_enc_ctx_observed::<_, _, Kem, _, _>(mode, shared_secret, &[info], |t| {
+        values = Some(KeyScheduleValues::new(shared_secret_bytes, t))
+    });
+
+    Ok((
+        enc_ctx.into(),
+        values.expect("key schedule was not observed"),
+    ))
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::{setup_receiver_debug, setup_sender_debug};
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        op_mode::{OpModeR, OpModeS, PskBundle},
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that both sides see the same intermediate values, that they have the right sizes,
+    /// and that the contexts still work
+    #[test]
+    fn test_setup_debug() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let psk = PskBundle {
+            psk: b"a preshared key of sufficient length",
+            psk_id: b"psk",
+        };
+
+        let (encapped_key, mut sender_ctx, sender_values) = setup_sender_debug::<A, Kdf, Kem, _>(
+            &OpModeS::Psk(psk),
+            &pk_recip,
+            b"info",
+            &mut csprng,
+        )
+        .unwrap();
+        let (mut receiver_ctx, receiver_values) = setup_receiver_debug::<A, Kdf, Kem>(
+            &OpModeR::Psk(psk),
+            &sk_recip,
+            &encapped_key,
+            b"info",
+        )
+        .unwrap();
+        assert_eq!(sender_values, receiver_values);
+
+        assert_eq!(sender_values.shared_secret.len(), 32);
+        assert_eq!(sender_values.key_schedule_context.len(), 1 + 32 + 32);
+        assert_eq!(sender_values.key_schedule_context[0], 1);
+        assert_eq!(sender_values.secret.len(), 32);
+        assert_eq!(sender_values.key.len(), 32);
+        assert_eq!(sender_values.base_nonce.len(), 12);
+        assert_eq!(sender_values.exporter_secret.len(), 32);
+
+        let ciphertext = sender_ctx.seal(b"msg", b"").unwrap();
+        assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), b"msg");
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    aead::{Aead, AeadCtxR},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::OpModeR,
+    HpkeError,
+};
+
+use super::setup_receiver;
+
+#[cfg(feature = "alloc")]
+use crate::{Serializable, Vec};
+#[cfg(feature = "alloc")]
+use alloc_collections::BTreeSet;
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections as alloc_collections;
+#[cfg(feature = "std")]
+use std::collections as alloc_collections;
+
+/// Remembers encapsulated keys a receiver has seen, so that replayed envelopes can be rejected.
+/// This can be exact, or probabilistic, e.g., a bloom filter that the caller sizes and ages out.
+/// A false positive only rejects a fresh message; it never lets a replay through. See
+/// `setup_receiver_with_encap_filter`.
+///
+/// This is implemented for closures `FnMut(&Kem::EncappedKey) -> bool`, and, with the `alloc`
+/// feature, for `BTreeSet<Vec<u8>>` holding serialized encapsulated keys.
+pub trait EncapFilter<Kem: KemTrait> {
+    /// Records `encapped_key` as seen. Returns `true` if it hadn't been seen before, and `false`
+    /// if it had.
+    fn check_and_insert(&mut self, encapped_key: &Kem::EncappedKey) -> bool;
+}
+
+impl<Kem, F> EncapFilter<Kem> for F
+where
+    Kem: KemTrait,
+    F: FnMut(&Kem::EncappedKey) -> bool,
+{
+    fn check_and_insert(&mut self, encapped_key: &Kem::EncappedKey) -> bool {
+        self(encapped_key)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<Kem: KemTrait> EncapFilter<Kem> for BTreeSet<Vec<u8>> {
+    fn check_and_insert(&mut self, encapped_key: &Kem::EncappedKey) -> bool {
+        self.insert(encapped_key.to_bytes().to_vec())
+    }
+}
+
+/// Initiates a decryption context, after asking `filter` whether `encapped_key` has been seen
+/// before. The check happens before decapsulation, so a replay costs no DH operations.
+///
+/// The filter records `encapped_key` before anything is authenticated. Anyone who sees an
+/// envelope in transit can deliver a copy first, and the genuine one is then rejected as a
+/// replay. Callers who can't accept that should instead check their filter themselves after the
+/// first successful `open`.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. If `filter` has seen `encapped_key` before, returns
+/// `Err(HpkeError::ReplayedEncappedKey)`. Otherwise, returns the errors `setup_receiver` does.
+pub fn setup_receiver_with_encap_filter<A, Kdf, Kem, F>(
+    mode: &OpModeR<Kem>,
+    filter: &mut F,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    F: EncapFilter<Kem> + ?Sized,
+{
+    if !filter.check_and_insert(encapped_key) {
+        return Err(HpkeError::ReplayedEncappedKey);
+    }
+
+    setup_receiver(mode, sk_recip, encapped_key, info)
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::setup_receiver_with_encap_filter;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        op_mode::{OpModeR, OpModeS},
+        setup_sender, HpkeError, Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that a fresh encapsulated key is accepted and a repeated one is rejected, both with
+    /// a closure and with a set
+    #[test]
+    fn test_setup_receiver_with_encap_filter() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+        let (encapped_key, mut sender_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+                .unwrap();
+        let mut msg = *b"msg";
+        let tag = sender_ctx.seal_in_place_detached(&mut msg, b"").unwrap();
+
+        // A closure that only remembers the last key it saw
+        let mut last = None;
+        let mut closure = |enc: &<Kem as KemTrait>::EncappedKey| {
+            let bytes = enc.to_bytes();
+            let fresh = last.as_ref() != Some(&bytes);
+            last = Some(bytes);
+            fresh
+        };
+
+        let mut receiver_ctx = setup_receiver_with_encap_filter::<A, Kdf, Kem, _>(
+            &OpModeR::Base,
+            &mut closure,
+            &sk_recip,
+            &encapped_key,
+            b"info",
+        )
+        .unwrap();
+        receiver_ctx
+            .open_in_place_detached(&mut msg, b"", &tag)
+            .unwrap();
+        assert_eq!(&msg, b"msg");
+        assert!(matches!(
+            setup_receiver_with_encap_filter::<A, Kdf, Kem, _>(
+                &OpModeR::Base,
+                &mut closure,
+                &sk_recip,
+                &encapped_key,
+                b"info",
+            ),
+            Err(HpkeError::ReplayedEncappedKey)
+        ));
+
+        #[cfg(feature = "alloc")]
+        {
+            let mut seen = super::BTreeSet::new();
+            let (other_encapped_key, _) =
+                setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+                    .unwrap();
+
+            for (enc, fresh) in [
+                (&encapped_key, true),
+                (&other_encapped_key, true),
+                (&encapped_key, false),
+            ] {
+                let res = setup_receiver_with_encap_filter::<A, Kdf, Kem, _>(
+                    &OpModeR::Base,
+                    &mut seen,
+                    &sk_recip,
+                    enc,
+                    b"info",
+                );
+                assert_eq!(res.is_ok(), fresh);
+            }
+            assert_eq!(seen.len(), 2);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{
+    aead::{Aead, AeadCtxR},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeR, PskBundle},
+    HpkeError,
+};
+
+use super::setup_receiver;
+
+#[cfg(feature = "alloc")]
+use crate::Vec;
+#[cfg(feature = "alloc")]
+use alloc_collections::BTreeMap;
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections as alloc_collections;
+#[cfg(feature = "std")]
+use std::collections as alloc_collections;
+
+/// Looks up a receiver's PSK by its PSK ID. This lets a receiver that holds many PSKs, e.g., one
+/// per tenant, pick the right one from the PSK ID that came with a message, such as
+/// `Envelope::psk_id`. See `setup_receiver_with_resolver`.
+///
+/// This is implemented for closures `Fn(&[u8]) -> Option<P>` where `P: AsRef<[u8]>`, and, with
+/// the `alloc` feature, for `BTreeMap<Vec<u8>, Vec<u8>>` mapping PSK IDs to PSKs. Lookups that
+/// are async can use `AsyncPskResolver` instead.
+pub trait PskResolver {
+    /// The PSK type this returns. This can borrow from the resolver, or own its bytes, e.g., in
+    /// a `Zeroizing` buffer.
+    type Psk<'a>: AsRef<[u8]>
+    where
+        Self: 'a;
+
+    /// Returns the PSK with the given ID, or `None` if there isn't one
+    fn resolve(&self, psk_id: &[u8]) -> Option<Self::Psk<'_>>;
+}
+
+impl<F, P> PskResolver for F
+where
+    F: Fn(&[u8]) -> Option<P>,
+    P: AsRef<[u8]>,
+{
+    type Psk<'a>
+        = P
+    where
+        Self: 'a;
+
+    fn resolve(&self, psk_id: &[u8]) -> Option<P> {
+        self(psk_id)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl PskResolver for BTreeMap<Vec<u8>, Vec<u8>> {
+    type Psk<'a> = &'a [u8];
+
+    fn resolve(&self, psk_id: &[u8]) -> Option<&[u8]> {
+        self.get(psk_id).map(Vec::as_slice)
+    }
+}
+
+/// The async version of `PskResolver`, for PSKs that live in a database or remote key store. See
+/// `setup_receiver_with_async_resolver`.
+#[allow(async_fn_in_trait)]
+pub trait AsyncPskResolver {
+    /// The PSK type this returns
+    type Psk: AsRef<[u8]>;
+
+    /// Returns the PSK with the given ID, or `None` if there isn't one
+    async fn resolve(&self, psk_id: &[u8]) -> Option<Self::Psk>;
+}
+
+/// Initiates a decryption context in the Psk mode, or the AuthPsk mode if `pk_sender_id` is
+/// given, with the PSK that `resolver` returns for `psk_id`. This is `setup_receiver` for
+/// receivers that don't know which PSK a message uses until they see its PSK ID.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. If `resolver` has no PSK for `psk_id`, returns
+/// `Err(HpkeError::UnknownPskId)`. Otherwise, returns the errors `setup_receiver` does.
+pub fn setup_receiver_with_resolver<A, Kdf, Kem, P>(
+    resolver: &P,
+    psk_id: &[u8],
+    pk_sender_id: Option<Kem::PublicKey>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    P: PskResolver + ?Sized,
+{
+    let psk = resolver.resolve(psk_id).ok_or(HpkeError::UnknownPskId)?;
+    setup_receiver_with_psk(
+        psk.as_ref(),
+        psk_id,
+        pk_sender_id,
+        sk_recip,
+        encapped_key,
+        info,
+    )
+}
+
+/// Same as `setup_receiver_with_resolver`, but awaits the PSK lookup
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. If `resolver` has no PSK for `psk_id`, returns
+/// `Err(HpkeError::UnknownPskId)`. Otherwise, returns the errors `setup_receiver` does.
+pub async fn setup_receiver_with_async_resolver<A, Kdf, Kem, P>(
+    resolver: &P,
+    psk_id: &[u8],
+    pk_sender_id: Option<Kem::PublicKey>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    P: AsyncPskResolver + ?Sized,
+{
+    let psk = resolver
+        .resolve(psk_id)
+        .await
+        .ok_or(HpkeError::UnknownPskId)?;
+    setup_receiver_with_psk(
+        psk.as_ref(),
+        psk_id,
+        pk_sender_id,
+        sk_recip,
+        encapped_key,
+        info,
+    )
+}
+
+/// Runs `setup_receiver` in the Psk or AuthPsk mode with the given PSK
+fn setup_receiver_with_psk<A, Kdf, Kem>(
+    psk: &[u8],
+    psk_id: &[u8],
+    pk_sender_id: Option<Kem::PublicKey>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let bundle = PskBundle { psk, psk_id };
+    let mode = match pk_sender_id {
+        Some(pk) => OpModeR::AuthPsk(pk, bundle),
+        None => OpModeR::Psk(bundle),
+    };
+    setup_receiver(&mode, sk_recip, encapped_key, info)
+}
+
+#[cfg(all(test, feature = "alloc", feature = "x25519"))]
+mod test {
+    use super::{
+        setup_receiver_with_async_resolver, setup_receiver_with_resolver, AsyncPskResolver,
+    };
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        op_mode::{OpModeS, PskBundle},
+        setup_sender, HpkeError,
+    };
+
+    #[cfg(not(feature = "std"))]
+    use alloc::{sync::Arc, task::Wake};
+    use core::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+    #[cfg(feature = "std")]
+    use std::{sync::Arc, task::Wake};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    // A waker that does nothing, since block_on never waits
+    struct NoopWake;
+
+    impl Wake for NoopWake {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Polls a future that never waits to completion
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        let waker = Waker::from(Arc::new(NoopWake));
+        match fut.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(out) => out,
+            Poll::Pending => panic!("future was pending"),
+        }
+    }
+
+    /// Tests that the resolver picks each tenant's PSK by ID, for sync and async resolvers, with
+    /// and without sender auth, and that unknown IDs are reported as such
+    #[test]
+    fn test_setup_receiver_with_resolver() {
+        use super::BTreeMap;
+        use crate::Vec;
+
+        struct AsyncDirectory(BTreeMap<Vec<u8>, Vec<u8>>);
+        impl AsyncPskResolver for AsyncDirectory {
+            type Psk = Vec<u8>;
+            async fn resolve(&self, psk_id: &[u8]) -> Option<Vec<u8>> {
+                self.0.get(psk_id).cloned()
+            }
+        }
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let (sk_sender, pk_sender) = Kem::gen_keypair(&mut csprng);
+
+        let mut directory = BTreeMap::new();
+        for tenant in 0u8..3 {
+            directory.insert(
+                [b't', tenant].to_vec(),
+                [tenant; 32]
+                    .iter()
+                    .chain(b"psk")
+                    .copied()
+                    .collect::<Vec<u8>>(),
+            );
+        }
+
+        for (psk_id, psk) in directory.iter() {
+            let bundle = PskBundle { psk, psk_id };
+            let sender_modes = [
+                OpModeS::Psk(bundle),
+                OpModeS::AuthPsk((sk_sender.clone(), pk_sender.clone()), bundle),
+            ];
+            for (mode, pk_sender_id) in sender_modes.iter().zip([None, Some(&pk_sender)]) {
+                let (encapped_key, mut sender_ctx) =
+                    setup_sender::<A, Kdf, Kem, _>(mode, &pk_recip, b"info", &mut csprng).unwrap();
+                let ciphertext = sender_ctx.seal(b"msg", b"").unwrap();
+
+                let mut receiver_ctx = setup_receiver_with_resolver::<A, Kdf, Kem, _>(
+                    &directory,
+                    psk_id,
+                    pk_sender_id.cloned(),
+                    &sk_recip,
+                    &encapped_key,
+                    b"info",
+                )
+                .unwrap();
+                assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), b"msg");
+
+                let mut receiver_ctx =
+                    block_on(setup_receiver_with_async_resolver::<A, Kdf, Kem, _>(
+                        &AsyncDirectory(directory.clone()),
+                        psk_id,
+                        pk_sender_id.cloned(),
+                        &sk_recip,
+                        &encapped_key,
+                        b"info",
+                    ))
+                    .unwrap();
+                assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), b"msg");
+            }
+        }
+
+        // Closures work too, and unknown PSK IDs are errors
+        let (encapped_key, _) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"", &mut csprng).unwrap();
+        let resolver = |_: &[u8]| None::<[u8; 32]>;
+        assert!(matches!(
+            setup_receiver_with_resolver::<A, Kdf, Kem, _>(
+                &resolver,
+                b"nobody",
+                None,
+                &sk_recip,
+                &encapped_key,
+                b"",
+            ),
+            Err(HpkeError::UnknownPskId)
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+//! A process-wide hook that's told about every successful sender setup, for key transparency and
+//! monitoring. This is gated under the `key-usage-hook` feature. Without it, `record` compiles to
+//! nothing, so call sites don't need `cfg`s.
+
+// The suite types are only read when there's something to record
+#![cfg_attr(
+    not(feature = "key-usage-hook"),
+    allow(clippy::extra_unused_type_parameters)
+)]
+
+use crate::{aead::Aead, kdf::Kdf as KdfTrait, kem::Kem as KemTrait};
+
+#[cfg(feature = "key-usage-hook")]
+use std::{
+    boxed::Box,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        PoisonError, RwLock,
+    },
+};
+
+/// What a key usage hook is told about one sender setup. See `set_key_usage_hook`.
+#[cfg(feature = "key-usage-hook")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyUsage<'a> {
+    /// The recipient public key's fingerprint under the setup's KDF, as `Kem::fingerprint`
+    /// computes it
+    pub recipient_fingerprint: &'a [u8],
+    /// The KEM's algorithm identifier
+    pub kem_id: u16,
+    /// The KDF's algorithm identifier
+    pub kdf_id: u16,
+    /// The AEAD's algorithm identifier
+    pub aead_id: u16,
+    /// The mode identifier from RFC 9180 §5 Table 1: 0 for Base, 1 for Psk, 2 for Auth, and 3 for
+    /// AuthPsk. With the `static-static` feature, this can also be
+    /// `static_static::STATIC_STATIC_MODE_ID`.
+    pub mode_id: u8,
+    /// The number of sender setups in this process before this one. This goes up by one with
+    /// every sender setup, whether or not a hook is set, so gaps in what a hook sees are setups
+    /// that happened while it wasn't set. There are no timestamps. Add them in the hook if
+    /// they're wanted.
+    pub counter: u64,
+}
+
+#[cfg(feature = "key-usage-hook")]
+type Hook = Box<dyn Fn(&KeyUsage<'_>) + Send + Sync>;
+
+#[cfg(feature = "key-usage-hook")]
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+#[cfg(feature = "key-usage-hook")]
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the hook that's called after every successful sender setup in this process, replacing
+/// the one before, if any. Every function here that makes an `AeadCtxS` calls it once per
+/// recipient, including the single-shot, multi-recipient, and envelope functions, since they're
+/// built on those.
+///
+/// The hook runs on the thread that did the setup, before the setup returns, so it should be
+/// quick, e.g., push onto a channel. It's only given public values. It must not set or clear the
+/// hook itself, since that deadlocks.
+#[cfg(feature = "key-usage-hook")]
+pub fn set_key_usage_hook<F>(hook: F)
+where
+    F: Fn(&KeyUsage<'_>) + Send + Sync + 'static,
+{
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(hook));
+}
+
+/// Removes the key usage hook, if any. See `set_key_usage_hook`.
+#[cfg(feature = "key-usage-hook")]
+pub fn clear_key_usage_hook() {
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Tells the key usage hook, if one is set, that a sender setup to `pk_recip` in the mode with ID
+/// `mode_id` succeeded
+#[inline(always)]
+pub(crate) fn record<A, Kdf, Kem>(pk_recip: &Kem::PublicKey, mode_id: u8)
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    #[cfg(feature = "key-usage-hook")]
+    {
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+        // A panicking hook doesn't leave anything in a bad state, so don't let it break later
+        // setups
+        let hook = HOOK.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(hook) = hook.as_ref() {
+            let fingerprint = Kem::fingerprint::<Kdf>(pk_recip);
+            hook(&KeyUsage {
+                recipient_fingerprint: fingerprint.as_bytes(),
+                kem_id: Kem::KEM_ID,
+                kdf_id: Kdf::KDF_ID,
+                aead_id: A::AEAD_ID,
+                mode_id,
+                counter,
+            });
+        }
+    }
+
+    #[cfg(not(feature = "key-usage-hook"))]
+    let _ = (pk_recip, mode_id);
+}
+
+#[cfg(all(test, feature = "key-usage-hook"))]
+mod test {
+    use super::{clear_key_usage_hook, set_key_usage_hook};
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait, setup_sender,
+        single_shot_seal, OpModeS, Vec,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::{Arc, Mutex};
+
+    /// Tests that the hook sees each sender setup with the right fingerprint, suite, and mode, and
+    /// that the counter goes up. Other tests set up senders concurrently, so only events for this
+    /// test's recipient are looked at.
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_key_usage_hook() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let fingerprint = Kem::fingerprint::<Kdf>(&pk_recip).as_bytes().to_vec();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_hook = seen.clone();
+        let watched = fingerprint.clone();
+        set_key_usage_hook(move |usage| {
+            if usage.recipient_fingerprint == &watched[..] {
+                seen_by_hook.lock().unwrap().push((
+                    usage.kem_id,
+                    usage.kdf_id,
+                    usage.aead_id,
+                    usage.mode_id,
+                    usage.counter,
+                ));
+            }
+        });
+
+        setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng).unwrap();
+        let (sk_sender, pk_sender) = Kem::gen_keypair(&mut csprng);
+        single_shot_seal::<A, Kdf, Kem, _>(
+            &OpModeS::Auth((sk_sender, pk_sender)),
+            &pk_recip,
+            b"info",
+            b"msg",
+            b"aad",
+            &mut csprng,
+        )
+        .unwrap();
+        clear_key_usage_hook();
+        setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng).unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        let (kem_id, kdf_id, aead_id, mode_id, first) = seen[0];
+        assert_eq!(
+            (kem_id, kdf_id, aead_id, mode_id),
+            (0x0020, 0x0001, 0x0003, 0x00)
+        );
+        let (_, _, _, mode_id, second) = seen[1];
+        assert_eq!(mode_id, 0x02);
+        assert!(second > first);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    aead::{Aead, AeadCtxR},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::OpModeR,
+    HpkeError,
+};
+
+use super::setup_receiver;
+
+/// Decides whether a sender's identity public key is trusted, e.g., by checking it against a set
+/// of pinned keys or a revocation list. See `setup_receiver_with_verifier`.
+///
+/// This is implemented for closures `Fn(&Kem::PublicKey) -> bool`.
+pub trait SenderVerifier<Kem: KemTrait> {
+    /// Returns whether `pk_sender_id` is trusted
+    fn verify(&self, pk_sender_id: &Kem::PublicKey) -> bool;
+}
+
+impl<Kem, F> SenderVerifier<Kem> for F
+where
+    Kem: KemTrait,
+    F: Fn(&Kem::PublicKey) -> bool,
+{
+    fn verify(&self, pk_sender_id: &Kem::PublicKey) -> bool {
+        self(pk_sender_id)
+    }
+}
+
+/// Initiates a decryption context in the Auth or AuthPsk mode, after asking `verifier` whether
+/// the sender's identity key in `mode` is trusted. The check happens before decapsulation, so an
+/// untrusted sender costs no DH operations.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. If `mode` is Base or Psk, there's no sender key to
+/// check, so this returns `Err(HpkeError::ValidationError)`. If `verifier` rejects the sender's
+/// key, returns `Err(HpkeError::UntrustedSender)`. Otherwise, returns the errors
+/// `setup_receiver` does.
+pub fn setup_receiver_with_verifier<A, Kdf, Kem, V>(
+    mode: &OpModeR<Kem>,
+    verifier: &V,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    V: SenderVerifier<Kem> + ?Sized,
+{
+    let pk_sender_id = mode.get_pk_sender_id().ok_or(HpkeError::ValidationError)?;
+    if !verifier.verify(pk_sender_id) {
+        return Err(HpkeError::UntrustedSender);
+    }
+
+    setup_receiver(mode, sk_recip, encapped_key, info)
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::setup_receiver_with_verifier;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        op_mode::{OpModeR, OpModeS},
+        setup_sender, HpkeError, Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that pinned senders are accepted, others are rejected, and non-auth modes are
+    /// refused
+    #[test]
+    fn test_setup_receiver_with_verifier() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let (sk_pinned, pk_pinned) = Kem::gen_keypair(&mut csprng);
+        let (sk_other, pk_other) = Kem::gen_keypair(&mut csprng);
+
+        let pinned = pk_pinned.to_bytes();
+        let verifier = |pk: &<Kem as KemTrait>::PublicKey| pk.to_bytes() == pinned;
+
+        for (sk_sender, pk_sender, trusted) in
+            [(sk_pinned, pk_pinned, true), (sk_other, pk_other, false)]
+        {
+            let sender_mode = OpModeS::Auth((sk_sender, pk_sender.clone()));
+            let (encapped_key, mut sender_ctx) =
+                setup_sender::<A, Kdf, Kem, _>(&sender_mode, &pk_recip, b"info", &mut csprng)
+                    .unwrap();
+            let mut msg = *b"msg";
+            let tag = sender_ctx.seal_in_place_detached(&mut msg, b"").unwrap();
+
+            let res = setup_receiver_with_verifier::<A, Kdf, Kem, _>(
+                &OpModeR::Auth(pk_sender),
+                &verifier,
+                &sk_recip,
+                &encapped_key,
+                b"info",
+            );
+            if trusted {
+                res.unwrap()
+                    .open_in_place_detached(&mut msg, b"", &tag)
+                    .unwrap();
+                assert_eq!(&msg, b"msg");
+            } else {
+                assert!(matches!(res, Err(HpkeError::UntrustedSender)));
+            }
+        }
+
+        let (encapped_key, _) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"", &mut csprng).unwrap();
+        assert!(matches!(
+            setup_receiver_with_verifier::<A, Kdf, Kem, _>(
+                &OpModeR::Base,
+                &verifier,
+                &sk_recip,
+                &encapped_key,
+                b"",
+            ),
+            Err(HpkeError::ValidationError)
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+//! Shamir secret sharing of private keys, for splitting a recipient's key among several holders so
+//! that any `t` of them can recover it, and fewer learn nothing about it.
+//!
+//! Sharing is done byte by byte over GF(2^8), on the serialized private key, so it works for every
+//! KEM. The field arithmetic is constant-time, and every intermediate value is zeroized.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     kem::X25519HkdfSha256,
+//!     shamir::{recover_private_key, split_private_key},
+//!     Kem, Serializable,
+//! };
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk, _) = X25519HkdfSha256::gen_keypair(&mut csprng);
+//!
+//! // 2-of-3
+//! let shares = split_private_key::<X25519HkdfSha256, _>(&sk, 2, 3, &mut csprng).unwrap();
+//! let recovered = recover_private_key::<X25519HkdfSha256>(&shares[1..]).unwrap();
+//! assert_eq!(recovered.to_bytes(), sk.to_bytes());
+//! # }
+//! ```
+
+use crate::{kem::Kem as KemTrait, Deserializable, HpkeError, Serializable, Vec};
+
+use byteorder::{BigEndian, ByteOrder};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// One share of a private key. Shares are secret. Any `threshold` of them recover the key.
+///
+/// The encoding, `to_bytes`, is
+///
+/// ```text
+/// kem_id (2) || threshold (1) || index (1) || share (the rest)
+/// ```
+///
+/// where `share` is as long as the KEM's serialized private key.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct KeyShare {
+    kem_id: u16,
+    threshold: u8,
+    index: u8,
+    share: Vec<u8>,
+}
+
+impl KeyShare {
+    /// The algorithm identifier of the KEM whose private key this is a share of
+    pub fn kem_id(&self) -> u16 {
+        self.kem_id
+    }
+
+    /// The number of shares needed to recover the private key
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// The index of this share. This is in the range `[1, n]`, and is different for every share
+    /// of the same key.
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Encodes this share in the format described in `KeyShare`
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut out = Zeroizing::new(Vec::with_capacity(4 + self.share.len()));
+        let mut kem_id = [0u8; 2];
+        BigEndian::write_u16(&mut kem_id, self.kem_id);
+        out.extend_from_slice(&kem_id);
+        out.push(self.threshold);
+        out.push(self.index);
+        out.extend_from_slice(&self.share);
+        out
+    }
+
+    /// Decodes a share in the format described in `KeyShare`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input is too short, or the threshold or
+    /// index is 0.
+    pub fn from_bytes(encoded: &[u8]) -> Result<KeyShare, HpkeError> {
+        if encoded.len() < 5 || encoded[2] == 0 || encoded[3] == 0 {
+            return Err(HpkeError::ValidationError);
+        }
+        Ok(KeyShare {
+            kem_id: BigEndian::read_u16(&encoded[..2]),
+            threshold: encoded[2],
+            index: encoded[3],
+            share: encoded[4..].to_vec(),
+        })
+    }
+}
+
+/// Splits `sk` into `n` shares, any `t` of which recover it with `recover_private_key`
+///
+/// Return Value
+/// ============
+/// Returns the shares, with indices `1` through `n`, on success. Returns
+/// `Err(HpkeError::ValidationError)` unless `1 <= t <= n <= 255`. With `t = 1`, every share is
+/// a copy of the key.
+pub fn split_private_key<Kem, R>(
+    sk: &Kem::PrivateKey,
+    t: u8,
+    n: u8,
+    csprng: &mut R,
+) -> Result<Vec<KeyShare>, HpkeError>
+where
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    if t == 0 || t > n {
+        return Err(HpkeError::ValidationError);
+    }
+
+    // Each byte of the key is the constant term of its own random polynomial of degree t-1.
+    // coeffs[i] holds the coefficients of x^(i+1) for every byte.
+    let secret = Zeroizing::new(sk.to_bytes().to_vec());
+    let mut coeffs: Zeroizing<Vec<Vec<u8>>> = Zeroizing::new(Vec::with_capacity(t as usize - 1));
+    for _ in 1..t {
+        let mut c = vec![0u8; secret.len()];
+        csprng.fill_bytes(&mut c);
+        coeffs.push(c);
+    }
+
+    let shares = (1..=n)
+        .map(|x| {
+            // Horner's rule, highest coefficient first
+            let mut share = vec![0u8; secret.len()];
+            for c in coeffs.iter().rev() {
+                for (s, &ci) in share.iter_mut().zip(c.iter()) {
+                    *s = gf_mul(*s, x) ^ ci;
+                }
+            }
+            for (s, &b) in share.iter_mut().zip(secret.iter()) {
+                *s = gf_mul(*s, x) ^ b;
+            }
+            KeyShare {
+                kem_id: Kem::KEM_ID,
+                threshold: t,
+                index: x,
+                share,
+            }
+        })
+        .collect();
+
+    coeffs.iter_mut().for_each(|c| c.zeroize());
+    Ok(shares)
+}
+
+/// Recovers a private key from shares made by `split_private_key`. If more than the threshold
+/// number of shares are given, the first ones are used.
+///
+/// Return Value
+/// ============
+/// Returns the private key on success. Returns `Err(HpkeError::ValidationError)` if there are
+/// fewer shares than the threshold, the shares aren't all for `Kem` with the same threshold and
+/// length, or two of them have the same index. If the shares are from different keys, the result
+/// is garbage, and this returns whatever error `from_bytes` returns on it, or a wrong key.
+pub fn recover_private_key<Kem: KemTrait>(
+    shares: &[KeyShare],
+) -> Result<Kem::PrivateKey, HpkeError> {
+    let first = shares.first().ok_or(HpkeError::ValidationError)?;
+    let t = first.threshold as usize;
+    if shares.len() < t
+        || shares.iter().any(|s| {
+            s.kem_id != Kem::KEM_ID
+                || s.threshold != first.threshold
+                || s.share.len() != first.share.len()
+        })
+    {
+        return Err(HpkeError::ValidationError);
+    }
+    let shares = &shares[..t];
+    for (i, s) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|other| other.index == s.index) {
+            return Err(HpkeError::ValidationError);
+        }
+    }
+
+    // Lagrange interpolation at x = 0. In GF(2^8), subtraction is XOR, so the basis polynomial of
+    // share i at 0 is the product over j != i of x_j / (x_j ^ x_i).
+    let mut secret = Zeroizing::new(vec![0u8; first.share.len()]);
+    for (i, si) in shares.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, sj) in shares.iter().enumerate() {
+            if i != j {
+                basis = gf_mul(basis, gf_mul(sj.index, gf_inv(sj.index ^ si.index)));
+            }
+        }
+        for (b, &y) in secret.iter_mut().zip(si.share.iter()) {
+            *b ^= gf_mul(basis, y);
+        }
+    }
+
+    Kem::PrivateKey::from_bytes(&secret)
+}
+
+// Multiplies in GF(2^8) with the AES polynomial x^8 + x^4 + x^3 + x + 1. This is constant-time.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        // Add a if the low bit of b is set
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        // Multiply a by x, reducing if the high bit was set
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+// Inverts in GF(2^8) as a^254. The inverse of 0 comes out as 0, but callers never invert 0.
+fn gf_inv(a: u8) -> u8 {
+    // a^254 = a^(2+4+8+16+32+64+128)
+    let mut result = 1u8;
+    let mut power = a;
+    for _ in 1..8 {
+        power = gf_mul(power, power);
+        result = gf_mul(result, power);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{gf_inv, gf_mul, recover_private_key, split_private_key, KeyShare};
+    use crate::{kem::Kem as KemTrait, HpkeError, Serializable};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests the field arithmetic against the example in FIPS 197 §4.2, and that every nonzero
+    /// element has an inverse
+    #[test]
+    fn test_gf256() {
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    macro_rules! test_shamir_roundtrip {
+        ($test_name:ident, $kem:ty) => {
+            /// Tests that every t-subset of the shares recovers the key, that fewer than t don't,
+            /// and that shares survive encoding
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk, _) = Kem::gen_keypair(&mut csprng);
+                let sk_bytes = sk.to_bytes();
+
+                let shares = split_private_key::<Kem, _>(&sk, 3, 5, &mut csprng).unwrap();
+                assert_eq!(shares.len(), 5);
+                for a in 0..5 {
+                    for b in (a + 1)..5 {
+                        for c in (b + 1)..5 {
+                            let subset = [
+                                shares[c].clone(),
+                                shares[a].clone(),
+                                KeyShare::from_bytes(&shares[b].to_bytes()).unwrap(),
+                            ];
+                            let recovered = recover_private_key::<Kem>(&subset).unwrap();
+                            assert_eq!(recovered.to_bytes(), sk_bytes);
+                        }
+                    }
+                }
+
+                assert!(matches!(
+                    recover_private_key::<Kem>(&shares[..2]),
+                    Err(HpkeError::ValidationError)
+                ));
+                let duplicated = [shares[0].clone(), shares[1].clone(), shares[0].clone()];
+                assert!(matches!(
+                    recover_private_key::<Kem>(&duplicated),
+                    Err(HpkeError::ValidationError)
+                ));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_shamir_roundtrip!(test_shamir_roundtrip_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_shamir_roundtrip!(test_shamir_roundtrip_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_shamir_roundtrip!(test_shamir_roundtrip_k256, crate::kem::DhK256HkdfSha256);
+
+    /// Tests that bad thresholds are rejected
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_shamir_bad_params() {
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, _) = Kem::gen_keypair(&mut csprng);
+        assert!(split_private_key::<Kem, _>(&sk, 0, 3, &mut csprng).is_err());
+        assert!(split_private_key::<Kem, _>(&sk, 4, 3, &mut csprng).is_err());
+
+        // With t = 1, every share is the key
+        let shares = split_private_key::<Kem, _>(&sk, 1, 2, &mut csprng).unwrap();
+        assert_eq!(shares[1].share.as_slice(), sk.to_bytes().as_slice());
+
+        assert!(KeyShare::from_bytes(b"\x00\x20\x00\x01k").is_err());
+        assert!(KeyShare::from_bytes(b"\x00\x20\x01").is_err());
+    }
+}
@@ -0,0 +1,316 @@
+//! A high-level API for quick tooling, in the style of [age](https://age-encryption.org). Keys are
+//! bech32 strings, and ciphertexts are base64 strings, so they can be passed around on command
+//! lines and in config files without any codec plumbing.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::simple;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//!
+//! // The identity is secret. The recipient can be handed out.
+//! let identity = simple::generate_identity(&mut csprng);
+//! let recipient = simple::to_recipient(&identity).unwrap();
+//! assert!(recipient.starts_with("hpke1"));
+//!
+//! let blob = simple::encrypt(&recipient, b"hello", &mut csprng).unwrap();
+//! assert_eq!(simple::decrypt(&identity, &blob).unwrap(), b"hello");
+//! # }
+//! ```
+//!
+//! The formats are
+//!
+//! ```text
+//! recipient = Bech32("hpke", concat(I2OSP(kem_id, 2), SerializePublicKey(pk)))
+//! identity  = Bech32("HPKE-SECRET-KEY-", concat(I2OSP(kem_id, 2), SerializePrivateKey(sk)))
+//! blob      = Base64(envelope)
+//! ```
+//!
+//! where identities are written in uppercase, and `envelope` is an `envelope::Envelope` sealed in
+//! base mode with HKDF-SHA256, ChaCha20Poly1305, and the KEM of the recipient, with the info
+//! string `"hpke simple v1"` and an empty AAD. Any of DHKEM(X25519), DHKEM(P-256), and
+//! DHKEM(K-256) that is compiled in can be used. New identities use X25519 if it's compiled in,
+//! and K-256 otherwise.
+
+use crate::{
+    aead::ChaCha20Poly1305,
+    envelope::{open_envelope, seal_to_envelope, Envelope},
+    kdf::HkdfSha256,
+    kem::Kem as KemTrait,
+    Deserializable, HpkeError, OpModeR, OpModeS, Serializable, Vec,
+};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::string::String;
+
+use bech32::{FromBase32, ToBase32, Variant};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::{Zeroize, Zeroizing};
+
+/// The bech32 human-readable part of recipient strings
+pub const RECIPIENT_HRP: &str = "hpke";
+
+/// The bech32 human-readable part of identity strings. Identities are written in uppercase.
+pub const IDENTITY_HRP: &str = "hpke-secret-key-";
+
+/// The KEM that `generate_identity` uses
+#[cfg(feature = "x25519")]
+pub type DefaultKem = crate::kem::X25519HkdfSha256;
+/// The KEM that `generate_identity` uses
+#[cfg(all(feature = "k256", not(feature = "x25519")))]
+pub type DefaultKem = crate::kem::DhK256HkdfSha256;
+
+// The rest of the ciphersuite, and the info string every blob is sealed under
+type A = ChaCha20Poly1305;
+type Kdf = HkdfSha256;
+const INFO: &[u8] = b"hpke simple v1";
+
+// Calls $f::<Kem, $g>($args) for the KEM with the given ID, if it's compiled in. Otherwise,
+// returns Err(HpkeError::ValidationError) from the enclosing function.
+macro_rules! dispatch_kem {
+    ($kem_id:expr, $f:ident $(::<$($g:ty),+>)? ($($arg:expr),*)) => {{
+        let kem_id: u16 = $kem_id;
+        #[cfg(feature = "x25519")]
+        if kem_id == <crate::kem::X25519HkdfSha256 as KemTrait>::KEM_ID {
+            return $f::<crate::kem::X25519HkdfSha256 $($(, $g)+)?>($($arg),*);
+        }
+        #[cfg(feature = "p256")]
+        if kem_id == <crate::kem::DhP256HkdfSha256 as KemTrait>::KEM_ID {
+            return $f::<crate::kem::DhP256HkdfSha256 $($(, $g)+)?>($($arg),*);
+        }
+        #[cfg(feature = "k256")]
+        if kem_id == <crate::kem::DhK256HkdfSha256 as KemTrait>::KEM_ID {
+            return $f::<crate::kem::DhK256HkdfSha256 $($(, $g)+)?>($($arg),*);
+        }
+        Err(HpkeError::ValidationError)
+    }};
+}
+
+/// Generates a new identity for `DefaultKem`
+pub fn generate_identity<R>(csprng: &mut R) -> Zeroizing<String>
+where
+    R: CryptoRng + RngCore + ?Sized,
+{
+    gen_identity::<DefaultKem, R>(csprng)
+}
+
+/// Generates a new identity for the KEM with the given algorithm identifier, e.g., `0x0030` for
+/// DHKEM(K-256, HKDF-SHA256)
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if that KEM isn't usable here, or isn't compiled in.
+pub fn generate_identity_with_kem<R>(
+    kem_id: u16,
+    csprng: &mut R,
+) -> Result<Zeroizing<String>, HpkeError>
+where
+    R: CryptoRng + RngCore + ?Sized,
+{
+    fn gen<Kem: KemTrait, R: CryptoRng + RngCore + ?Sized>(
+        csprng: &mut R,
+    ) -> Result<Zeroizing<String>, HpkeError> {
+        Ok(gen_identity::<Kem, R>(csprng))
+    }
+
+    dispatch_kem!(kem_id, gen::<R>(csprng))
+}
+
+/// Returns the recipient string of the given identity, for handing out to senders
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if `identity` isn't a valid identity string, or is
+/// for a KEM that isn't compiled in. Returns a key parsing error if the private key is invalid.
+pub fn to_recipient(identity: &str) -> Result<String, HpkeError> {
+    fn recipient<Kem: KemTrait>(sk_bytes: &[u8]) -> Result<String, HpkeError> {
+        let sk = Kem::PrivateKey::from_bytes(sk_bytes)?;
+        let pk = Kem::sk_to_pk(&sk);
+        Ok(encode(RECIPIENT_HRP, Kem::KEM_ID, &pk.to_bytes(), false))
+    }
+
+    let (kem_id, sk_bytes) = decode_identity(identity)?;
+    dispatch_kem!(kem_id, recipient(&sk_bytes))
+}
+
+/// Encrypts `plaintext` to the recipient string `recipient`, and returns the result as base64
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if `recipient` isn't a valid recipient string, or is
+/// for a KEM that isn't compiled in. Returns a key parsing error if the public key is invalid,
+/// and `Err(HpkeError::EncapError)` if encapsulation fails.
+pub fn encrypt<R>(recipient: &str, plaintext: &[u8], csprng: &mut R) -> Result<String, HpkeError>
+where
+    R: CryptoRng + RngCore + ?Sized,
+{
+    fn seal<Kem: KemTrait, R: CryptoRng + RngCore + ?Sized>(
+        pk_bytes: &[u8],
+        plaintext: &[u8],
+        csprng: &mut R,
+    ) -> Result<String, HpkeError> {
+        let pk = Kem::PublicKey::from_bytes(pk_bytes)?;
+        let envelope =
+            seal_to_envelope::<A, Kdf, Kem, R>(&OpModeS::Base, &pk, INFO, plaintext, b"", csprng)?;
+        Ok(envelope.to_base64())
+    }
+
+    let (hrp, kem_id, pk_bytes) = decode(recipient)?;
+    if hrp != RECIPIENT_HRP {
+        return Err(HpkeError::ValidationError);
+    }
+    dispatch_kem!(kem_id, seal::<R>(&pk_bytes, plaintext, csprng))
+}
+
+/// Decrypts the base64 `blob` that `encrypt` made, with the identity string `identity`
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if `identity` isn't a valid identity string, or if
+/// `blob` isn't a valid envelope for the identity's ciphersuite. Returns
+/// `Err(HpkeError::OpenError)` if the blob wasn't encrypted to this identity, or was modified.
+pub fn decrypt(identity: &str, blob: &str) -> Result<Vec<u8>, HpkeError> {
+    fn open<Kem: KemTrait>(sk_bytes: &[u8], envelope: &Envelope) -> Result<Vec<u8>, HpkeError> {
+        let sk = Kem::PrivateKey::from_bytes(sk_bytes)?;
+        open_envelope::<A, Kdf, Kem>(&OpModeR::Base, &sk, envelope, INFO, b"")
+    }
+
+    let (kem_id, sk_bytes) = decode_identity(identity)?;
+    let envelope = Envelope::from_base64(blob)?;
+    dispatch_kem!(kem_id, open(&sk_bytes, &envelope))
+}
+
+// Generates a keypair and encodes its private key as an identity
+fn gen_identity<Kem, R>(csprng: &mut R) -> Zeroizing<String>
+where
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let (sk, _) = Kem::gen_keypair(csprng);
+    let mut sk_bytes = sk.to_bytes();
+    let identity = encode(IDENTITY_HRP, Kem::KEM_ID, &sk_bytes, true);
+    sk_bytes.zeroize();
+    Zeroizing::new(identity)
+}
+
+// Parses an identity string into its KEM ID and private key bytes
+fn decode_identity(identity: &str) -> Result<(u16, Zeroizing<Vec<u8>>), HpkeError> {
+    let (hrp, kem_id, sk_bytes) = decode(identity)?;
+    let sk_bytes = Zeroizing::new(sk_bytes);
+    if hrp != IDENTITY_HRP {
+        return Err(HpkeError::ValidationError);
+    }
+    Ok((kem_id, sk_bytes))
+}
+
+// Encodes Bech32(hrp, concat(I2OSP(kem_id, 2), key)), in uppercase if asked
+fn encode(hrp: &str, kem_id: u16, key: &[u8], uppercase: bool) -> String {
+    let mut payload = Vec::with_capacity(2 + key.len());
+    payload.extend_from_slice(&kem_id.to_be_bytes());
+    payload.extend_from_slice(key);
+
+    let encoded =
+        bech32::encode(hrp, payload.to_base32(), Variant::Bech32).expect("HRPs are valid bech32");
+    payload.zeroize();
+
+    if uppercase {
+        encoded.to_uppercase()
+    } else {
+        encoded
+    }
+}
+
+// Decodes a bech32 string into its lowercase HRP, KEM ID, and key bytes
+fn decode(encoded: &str) -> Result<(String, u16, Vec<u8>), HpkeError> {
+    let (hrp, data, variant) = bech32::decode(encoded).map_err(|_| HpkeError::ValidationError)?;
+    if variant != Variant::Bech32 {
+        return Err(HpkeError::ValidationError);
+    }
+    let mut payload = Vec::<u8>::from_base32(&data).map_err(|_| HpkeError::ValidationError)?;
+    if payload.len() < 2 {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let kem_id = u16::from_be_bytes([payload[0], payload[1]]);
+    let key = payload[2..].to_vec();
+    payload.zeroize();
+    Ok((hrp, kem_id, key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decrypt, encrypt, generate_identity, generate_identity_with_kem, to_recipient};
+    use crate::HpkeError;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that blobs decrypt with the right identity, for every KEM compiled in
+    #[test]
+    fn test_encrypt_decrypt() {
+        let mut csprng = StdRng::from_entropy();
+
+        let mut kem_ids = crate::Vec::new();
+        #[cfg(feature = "x25519")]
+        kem_ids.push(0x0020);
+        #[cfg(feature = "p256")]
+        kem_ids.push(0x0010);
+        #[cfg(feature = "k256")]
+        kem_ids.push(0x0030);
+
+        for kem_id in kem_ids {
+            let identity = generate_identity_with_kem(kem_id, &mut csprng).unwrap();
+            assert!(identity.starts_with("HPKE-SECRET-KEY-1"));
+            let recipient = to_recipient(&identity).unwrap();
+            assert!(recipient.starts_with("hpke1"));
+
+            let blob = encrypt(&recipient, b"hello", &mut csprng).unwrap();
+            assert_eq!(decrypt(&identity, &blob).unwrap(), b"hello");
+
+            // Identities are case-insensitive, like all bech32
+            assert_eq!(decrypt(&identity.to_lowercase(), &blob).unwrap(), b"hello");
+
+            // A different identity of the same KEM can't decrypt
+            let other = generate_identity_with_kem(kem_id, &mut csprng).unwrap();
+            assert_eq!(decrypt(&other, &blob), Err(HpkeError::OpenError));
+        }
+    }
+
+    /// Tests that malformed strings are rejected, and that recipients and identities can't be
+    /// mixed up
+    #[test]
+    fn test_bad_strings() {
+        let mut csprng = StdRng::from_entropy();
+        let identity = generate_identity(&mut csprng);
+        let recipient = to_recipient(&identity).unwrap();
+        let blob = encrypt(&recipient, b"hello", &mut csprng).unwrap();
+
+        assert_eq!(
+            encrypt(&identity, b"hello", &mut csprng),
+            Err(HpkeError::ValidationError)
+        );
+        assert_eq!(decrypt(&recipient, &blob), Err(HpkeError::ValidationError));
+        assert_eq!(to_recipient(&recipient), Err(HpkeError::ValidationError));
+
+        // A typo breaks the checksum
+        let mut typo = recipient.clone().into_bytes();
+        let last = typo.len() - 1;
+        typo[last] = if typo[last] == b'q' { b'p' } else { b'q' };
+        assert_eq!(
+            encrypt(core::str::from_utf8(&typo).unwrap(), b"hello", &mut csprng),
+            Err(HpkeError::ValidationError)
+        );
+
+        assert_eq!(
+            generate_identity_with_kem(0x1234, &mut csprng).err(),
+            Some(HpkeError::ValidationError)
+        );
+        assert_eq!(
+            decrypt(&identity, "not base64!"),
+            Err(HpkeError::ValidationError)
+        );
+    }
+}
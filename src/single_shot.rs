@@ -4,9 +4,14 @@ use crate::{
     kem::Kem as KemTrait,
     op_mode::{OpModeR, OpModeS},
     setup::{setup_receiver, setup_sender},
-    HpkeError, Vec,
+    HpkeError,
 };
 
+#[cfg(feature = "alloc")]
+use crate::{setup::setup_sender_multi, Deserializable, Serializable, Vec};
+#[cfg(feature = "alloc")]
+use zeroize::Zeroizing;
+
 use rand_core::{CryptoRng, RngCore};
 
 // RFC 9180 §6.1
@@ -36,7 +41,7 @@ where
     A: Aead,
     Kdf: KdfTrait,
     Kem: KemTrait,
-    R: CryptoRng + RngCore,
+    R: CryptoRng + RngCore + ?Sized,
 {
     // Encap a key
     let (encapped_key, mut aead_ctx) =
@@ -56,6 +61,7 @@ where
 /// Returns `Ok((encapped_key, ciphertext))` on success. If an error happened during key
 /// encapsulation, returns `Err(HpkeError::EncapError)`. If an error happened during encryption,
 /// returns `Err(HpkeError::SealError)`.
+#[cfg(feature = "alloc")]
 pub fn single_shot_seal<A, Kdf, Kem, R>(
     mode: &OpModeS<Kem>,
     pk_recip: &Kem::PublicKey,
@@ -68,7 +74,7 @@ where
     A: Aead,
     Kdf: KdfTrait,
     Kem: KemTrait,
-    R: CryptoRng + RngCore,
+    R: CryptoRng + RngCore + ?Sized,
 {
     // Encap a key
     let (encapped_key, mut aead_ctx) =
@@ -79,6 +85,44 @@ where
     Ok((encapped_key, ciphertext))
 }
 
+/// Does a `setup_sender_multi` and `AeadCtxS::seal` in one shot. That is, it encrypts the same
+/// plaintext to every one of the given recipients, each under its own key encapsulation. This is
+/// meant for wrapping a small payload, like a content key, to many recipients. See
+/// `setup::setup_sender_multi` and `AeadCtxS::seal` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(vec)` on success, where `vec` holds one `(encapped_key, ciphertext)` pair per
+/// recipient, in the same order as `pk_recips`. If an error happened during any key
+/// encapsulation, returns `Err(HpkeError::EncapError)`. If an error happened during any
+/// encryption, returns `Err(HpkeError::SealError)`.
+#[cfg(feature = "alloc")]
+pub fn single_shot_seal_multi<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recips: &[Kem::PublicKey],
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<Vec<(Kem::EncappedKey, Vec<u8>)>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    // Encap a key for every recipient
+    let senders = setup_sender_multi::<A, Kdf, Kem, R>(mode, pk_recips, info, csprng)?;
+    // Encrypt to each of them
+    senders
+        .into_iter()
+        .map(|(encapped_key, mut aead_ctx)| {
+            let ciphertext = aead_ctx.seal(plaintext, aad)?;
+            Ok((encapped_key, ciphertext))
+        })
+        .collect()
+}
+
 // RFC 9180 §6.1
 // def OpenAuthPSK(enc, skR, info, aad, ct, psk, psk_id, pkS):
 //   ctx = SetupAuthPSKR(enc, skR, info, psk, psk_id, pkS)
@@ -92,7 +136,7 @@ where
 /// ============
 /// Returns `Ok()` on success. If an error happened during key decapsulation, returns
 /// `Err(HpkeError::DecapError)`. If an error happened during decryption, returns
-/// `Err(HpkeError::OpenError)`. In this case, the contents of `ciphertext` is undefined.
+/// `Err(HpkeError::OpenError)`. In this case, `ciphertext` is zeroed.
 pub fn single_shot_open_in_place_detached<A, Kdf, Kem>(
     mode: &OpModeR<Kem>,
     sk_recip: &Kem::PrivateKey,
@@ -113,6 +157,85 @@ where
     aead_ctx.open_in_place_detached(ciphertext, aad, tag)
 }
 
+/// Does a `setup_receiver` and `AeadCtxR::open_in_place` in one shot. That is, it does a key
+/// decapsulation for the specified recipient and decrypts the ciphertext-and-tag in `buf` in place,
+/// without allocating. See `setup::setup_reciever` and `AeadCtxR::open_in_place` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns the plaintext, a prefix of `buf`, on success. If an error happened during key
+/// decapsulation, returns `Err(HpkeError::DecapError)`. If an error happened during decryption,
+/// returns `Err(HpkeError::OpenError)`. In this case, the ciphertext part of `buf` is zeroed.
+pub fn single_shot_open_in_place<'a, A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+    buf: &'a mut [u8],
+    aad: &[u8],
+) -> Result<&'a mut [u8], HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // Decap the key
+    let mut aead_ctx = setup_receiver::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info)?;
+    // Decrypt
+    aead_ctx.open_in_place(buf, aad)
+}
+
+/// Does a `setup_sender` and `AeadCtxS::seal_fixed` in one shot. This doesn't allocate, and `M`
+/// must be `N` plus the tag length, or this fails to compile. See `AeadCtxS::seal_fixed`.
+///
+/// Return Value
+/// ============
+/// Returns `Ok((encapped_key, ciphertext))` on success. Returns the errors `single_shot_seal`
+/// does.
+pub fn single_shot_seal_fixed<A, Kdf, Kem, R, const N: usize, const M: usize>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8; N],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<(Kem::EncappedKey, [u8; M]), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let (encapped_key, mut aead_ctx) =
+        setup_sender::<A, Kdf, Kem, R>(mode, pk_recip, info, csprng)?;
+    let ciphertext = aead_ctx.seal_fixed(plaintext, aad)?;
+    Ok((encapped_key, ciphertext))
+}
+
+/// Does a `setup_receiver` and `AeadCtxR::open_fixed` in one shot. This doesn't allocate, and `M`
+/// must be `N` plus the tag length, or this fails to compile. See `AeadCtxR::open_fixed`.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(plaintext)` on success. Returns the errors `single_shot_open_in_place_detached`
+/// does.
+pub fn single_shot_open_fixed<A, Kdf, Kem, const N: usize, const M: usize>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+    ciphertext: &[u8; M],
+    aad: &[u8],
+) -> Result<[u8; N], HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut aead_ctx = setup_receiver::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info)?;
+    aead_ctx.open_fixed(ciphertext, aad)
+}
+
 /// Does a `setup_receiver` and `AeadCtxR::open` in one shot. That is, it does a key decapsulation
 /// for the specified recipient and decrypts the provided ciphertext. See `setup::setup_reciever`
 /// and `AeadCtxR::open` for more detail.
@@ -122,6 +245,7 @@ where
 /// Returns `Ok(plaintext)` on success. If an error happened during key decapsulation, returns
 /// `Err(HpkeError::DecapError)`. If an error happened during decryption, returns
 /// `Err(HpkeError::OpenError)`.
+#[cfg(feature = "alloc")]
 pub fn single_shot_open<A, Kdf, Kem>(
     mode: &OpModeR<Kem>,
     sk_recip: &Kem::PrivateKey,
@@ -141,9 +265,176 @@ where
     aead_ctx.open(ciphertext, aad)
 }
 
+/// Does a `setup_receiver` and `AeadCtxR::open_zeroizing` in one shot. This is the same as
+/// `single_shot_open`, except the plaintext is zeroized when it's dropped.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(plaintext)` on success. Returns the errors `single_shot_open` does.
+#[cfg(feature = "alloc")]
+pub fn single_shot_open_zeroizing<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut aead_ctx = setup_receiver::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info)?;
+    aead_ctx.open_zeroizing(ciphertext, aad)
+}
+
+/// Does a `single_shot_seal`, and returns the encapsulated key and ciphertext concatenated into one
+/// buffer, `enc || ciphertext`. This is the encoding that MLS and many other protocols put on the
+/// wire. The encapsulated key has a fixed length for each KEM, so `open_from_slice` can split it
+/// back off without a length prefix.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(enc || ciphertext)` on success. Otherwise, returns the errors `single_shot_seal`
+/// does.
+#[cfg(feature = "alloc")]
+pub fn seal_to_vec<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let enc_len = Kem::EncappedKey::size();
+    let mut out = Vec::with_capacity(enc_len + plaintext.len() + AeadTag::<A>::size());
+
+    // Encap a key
+    let (encapped_key, mut aead_ctx) =
+        setup_sender::<A, Kdf, Kem, R>(mode, pk_recip, info, csprng)?;
+    out.extend_from_slice(&encapped_key.to_bytes());
+    // Encrypt the plaintext in place, right after the encapped key, and append the tag
+    out.extend_from_slice(plaintext);
+    let tag = aead_ctx.seal_in_place_detached(&mut out[enc_len..], aad)?;
+    out.extend_from_slice(&tag.to_bytes());
+
+    Ok(out)
+}
+
+/// Opens `enc || ciphertext`, as `seal_to_vec` outputs it. This splits the encapsulated key off
+/// the front, then does a `single_shot_open`.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(plaintext)` on success. If `enc_and_ciphertext` is shorter than an encapsulated
+/// key, returns `Err(HpkeError::IncorrectInputLength)`. If the encapsulated key doesn't parse,
+/// returns the error from `Deserializable::from_bytes`. Otherwise, returns the errors
+/// `single_shot_open` does.
+#[cfg(feature = "alloc")]
+pub fn open_from_slice<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    info: &[u8],
+    enc_and_ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let enc_len = Kem::EncappedKey::size();
+    if enc_and_ciphertext.len() < enc_len {
+        return Err(HpkeError::IncorrectInputLength(
+            enc_len,
+            enc_and_ciphertext.len(),
+        ));
+    }
+    let (enc, ciphertext) = enc_and_ciphertext.split_at(enc_len);
+    let encapped_key = Kem::EncappedKey::from_bytes(enc)?;
+
+    single_shot_open::<A, Kdf, Kem>(mode, sk_recip, &encapped_key, info, ciphertext, aad)
+}
+
+/// Same as `single_shot_seal`, but rejects plaintexts longer than `max_len` bytes. See
+/// `AeadCtxS::set_max_message_len`.
+///
+/// Return Value
+/// ============
+/// Returns `Ok((encapped_key, ciphertext))` on success. If `plaintext` is longer than `max_len`,
+/// returns `Err(HpkeError::MessageTooLarge)` before doing any encapsulation. Otherwise, returns the
+/// errors `single_shot_seal` does.
+#[cfg(feature = "alloc")]
+pub fn single_shot_seal_with_limit<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    max_len: usize,
+    csprng: &mut R,
+) -> Result<(Kem::EncappedKey, Vec<u8>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    if plaintext.len() > max_len {
+        return Err(HpkeError::MessageTooLarge(max_len, plaintext.len()));
+    }
+    single_shot_seal::<A, Kdf, Kem, R>(mode, pk_recip, info, plaintext, aad, csprng)
+}
+
+/// Same as `single_shot_open`, but rejects ciphertexts whose plaintext would be longer than
+/// `max_len` bytes, i.e., ciphertexts longer than `max_len` plus the tag length. Use this when
+/// ciphertexts come from an untrusted source. See `AeadCtxR::set_max_message_len`.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(plaintext)` on success. If the ciphertext is too long, returns
+/// `Err(HpkeError::MessageTooLarge)` before doing any decapsulation or allocation. Otherwise,
+/// returns the errors `single_shot_open` does.
+#[cfg(feature = "alloc")]
+pub fn single_shot_open_with_limit<A, Kdf, Kem>(
+    mode: &OpModeR<Kem>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+    max_len: usize,
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // Ciphertexts too short to hold a tag are left for open() to reject
+    let msg_len = ciphertext.len().saturating_sub(AeadTag::<A>::size());
+    if msg_len > max_len {
+        return Err(HpkeError::MessageTooLarge(max_len, msg_len));
+    }
+    single_shot_open::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info, ciphertext, aad)
+}
+
 #[cfg(test)]
 mod test {
-    use super::{single_shot_open, single_shot_seal};
+    #[cfg(feature = "alloc")]
+    use super::{
+        open_from_slice, seal_to_vec, single_shot_open, single_shot_open_in_place,
+        single_shot_open_in_place_detached, single_shot_open_with_limit,
+        single_shot_open_zeroizing, single_shot_seal, single_shot_seal_in_place_detached,
+        single_shot_seal_multi, single_shot_seal_with_limit,
+    };
+    use super::{single_shot_open_fixed, single_shot_seal_fixed};
     use crate::{
         aead::ChaCha20Poly1305,
         kdf::HkdfSha256,
@@ -151,9 +442,12 @@ mod test {
         op_mode::{OpModeR, OpModeS, PskBundle},
         test_util::gen_rand_buf,
     };
+    #[cfg(feature = "alloc")]
+    use crate::{Deserializable, HpkeError, Serializable, Vec};
 
     use rand::{rngs::StdRng, SeedableRng};
 
+    #[cfg(feature = "alloc")]
     macro_rules! test_single_shot_correctness {
         ($test_name:ident, $aead:ty, $kdf:ty, $kem:ty) => {
             /// Tests that `single_shot_open` can open a `single_shot_seal` ciphertext. This
@@ -216,23 +510,292 @@ mod test {
                 )
                 .expect("single_shot_open() failed");
                 assert_eq!(&decrypted, &msg);
+
+                // The zeroizing variant gives the same plaintext
+                let decrypted = single_shot_open_zeroizing::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &ciphertext,
+                    aad,
+                )
+                .expect("single_shot_open_zeroizing() failed");
+                assert_eq!(&decrypted[..], &msg[..]);
+
+                // Open a copy of the combined ciphertext in place
+                let mut combined = ciphertext.clone();
+                let plaintext = single_shot_open_in_place::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &mut combined,
+                    aad,
+                )
+                .expect("single_shot_open_in_place() failed");
+                assert_eq!(plaintext, &msg[..]);
+
+                // Now do the same thing in place, on a caller-owned buffer
+                let mut buf = *msg;
+                let (encapped_key, tag) = single_shot_seal_in_place_detached::<A, Kdf, Kem, _>(
+                    &sender_mode,
+                    &pk_recip,
+                    info,
+                    &mut buf,
+                    aad,
+                    &mut csprng,
+                )
+                .expect("single_shot_seal_in_place_detached() failed");
+                assert!(&buf[..] != &msg[..]);
+
+                single_shot_open_in_place_detached::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &mut buf,
+                    aad,
+                    &tag,
+                )
+                .expect("single_shot_open_in_place_detached() failed");
+                assert_eq!(&buf, msg);
+
+                // Now encrypt to a handful of recipients at once. Each should be able to open
+                // their own ciphertext, and only their own.
+                let recips: Vec<_> = (0..4).map(|_| Kem::gen_keypair(&mut csprng)).collect();
+                let pk_recips: Vec<_> = recips.iter().map(|(_, pk)| pk.clone()).collect();
+                let sealed = single_shot_seal_multi::<A, Kdf, Kem, _>(
+                    &sender_mode,
+                    &pk_recips,
+                    info,
+                    msg,
+                    aad,
+                    &mut csprng,
+                )
+                .expect("single_shot_seal_multi() failed");
+                assert_eq!(sealed.len(), recips.len());
+                // Every recipient must get a fresh ephemeral key
+                assert!(sealed[0].0.to_bytes() != sealed[1].0.to_bytes());
+
+                for (i, ((sk, _), (encapped_key, ciphertext))) in
+                    recips.iter().zip(sealed.iter()).enumerate()
+                {
+                    let decrypted = single_shot_open::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        sk,
+                        encapped_key,
+                        info,
+                        ciphertext,
+                        aad,
+                    )
+                    .expect("single_shot_open() of a multi ciphertext failed");
+                    assert_eq!(&decrypted, &msg);
+
+                    // The next recipient over shouldn't be able to open this one
+                    let (other_sk, _) = &recips[(i + 1) % recips.len()];
+                    assert!(single_shot_open::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        other_sk,
+                        encapped_key,
+                        info,
+                        ciphertext,
+                        aad,
+                    )
+                    .is_err());
+                }
+
+                // Size limits are checked before any KEM work
+                assert_eq!(
+                    single_shot_seal_with_limit::<A, Kdf, Kem, _>(
+                        &sender_mode,
+                        &pk_recip,
+                        info,
+                        msg,
+                        aad,
+                        msg.len() - 1,
+                        &mut csprng,
+                    )
+                    .map(|_| ()),
+                    Err(HpkeError::MessageTooLarge(msg.len() - 1, msg.len()))
+                );
+                let (encapped_key, ciphertext) = single_shot_seal_with_limit::<A, Kdf, Kem, _>(
+                    &sender_mode,
+                    &pk_recip,
+                    info,
+                    msg,
+                    aad,
+                    msg.len(),
+                    &mut csprng,
+                )
+                .expect("single_shot_seal_with_limit() failed");
+                assert_eq!(
+                    single_shot_open_with_limit::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        &sk_recip,
+                        &encapped_key,
+                        info,
+                        &ciphertext,
+                        aad,
+                        msg.len() - 1,
+                    ),
+                    Err(HpkeError::MessageTooLarge(msg.len() - 1, msg.len()))
+                );
+                let decrypted = single_shot_open_with_limit::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &ciphertext,
+                    aad,
+                    msg.len(),
+                )
+                .expect("single_shot_open_with_limit() failed");
+                assert_eq!(&decrypted, &msg);
+
+                // The combined encoding is enc || ciphertext, and opens from one slice
+                let combined = seal_to_vec::<A, Kdf, Kem, _>(
+                    &sender_mode,
+                    &pk_recip,
+                    info,
+                    msg,
+                    aad,
+                    &mut csprng,
+                )
+                .expect("seal_to_vec() failed");
+                let enc_len = <Kem as KemTrait>::EncappedKey::size();
+                let (enc, ciphertext) = combined.split_at(enc_len);
+                let decrypted = single_shot_open::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &Deserializable::from_bytes(enc).unwrap(),
+                    info,
+                    ciphertext,
+                    aad,
+                )
+                .expect("single_shot_open() of seal_to_vec() output failed");
+                assert_eq!(&decrypted, &msg);
+                let decrypted =
+                    open_from_slice::<A, Kdf, Kem>(&receiver_mode, &sk_recip, info, &combined, aad)
+                        .expect("open_from_slice() failed");
+                assert_eq!(&decrypted, &msg);
+
+                // Truncations are caught, whether they cut into enc or the tag
+                assert_eq!(
+                    open_from_slice::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        &sk_recip,
+                        info,
+                        &combined[..enc_len - 1],
+                        aad,
+                    ),
+                    Err(HpkeError::IncorrectInputLength(enc_len, enc_len - 1))
+                );
+                assert!(open_from_slice::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    info,
+                    &combined[..combined.len() - 1],
+                    aad,
+                )
+                .is_err());
             }
         };
     }
 
-    #[cfg(feature = "x25519-dalek")]
+    macro_rules! test_single_shot_fixed {
+        ($test_name:ident, $aead:ty, $kdf:ty, $kem:ty) => {
+            /// Tests that `single_shot_open_fixed` opens a `single_shot_seal_fixed` ciphertext,
+            /// where the ciphertext is the message plus the tag. Neither needs an allocator.
+            #[test]
+            fn $test_name() {
+                type A = $aead;
+                type Kdf = $kdf;
+                type Kem = $kem;
+
+                let msg = b"Good night, a-ding ding ding ding ding";
+                let aad = b"Five four three two one";
+                let info = b"fixed-size info";
+
+                let mut csprng = StdRng::from_entropy();
+                let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                let psk_bundle = PskBundle {
+                    psk: &psk,
+                    psk_id: &psk_id,
+                };
+                let (sk_sender_id, pk_sender_id) = Kem::gen_keypair(&mut csprng);
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let sender_mode =
+                    OpModeS::<Kem>::AuthPsk((sk_sender_id, pk_sender_id.clone()), psk_bundle);
+                let receiver_mode = OpModeR::<Kem>::AuthPsk(pk_sender_id, psk_bundle);
+
+                let (encapped_key, ciphertext): (_, [u8; 38 + 16]) =
+                    single_shot_seal_fixed::<A, Kdf, Kem, _, 38, 54>(
+                        &sender_mode,
+                        &pk_recip,
+                        info,
+                        msg,
+                        aad,
+                        &mut csprng,
+                    )
+                    .expect("single_shot_seal_fixed() failed");
+                assert!(ciphertext[..38] != msg[..]);
+                let plaintext: [u8; 38] = single_shot_open_fixed::<A, Kdf, Kem, 38, 54>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &ciphertext,
+                    aad,
+                )
+                .expect("single_shot_open_fixed() failed");
+                assert_eq!(&plaintext, msg);
+            }
+        };
+    }
+
+    #[cfg(all(feature = "alloc", feature = "x25519-dalek"))]
     test_single_shot_correctness!(
         test_single_shot_correctness_x25519,
         ChaCha20Poly1305,
         HkdfSha256,
         crate::kem::x25519_hkdfsha256::X25519HkdfSha256
     );
+    #[cfg(feature = "x25519-dalek")]
+    test_single_shot_fixed!(
+        test_single_shot_fixed_x25519,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::x25519_hkdfsha256::X25519HkdfSha256
+    );
 
-    #[cfg(feature = "p256")]
+    #[cfg(all(feature = "alloc", feature = "p256"))]
     test_single_shot_correctness!(
         test_single_shot_correctness_p256,
         ChaCha20Poly1305,
         HkdfSha256,
         crate::kem::dhp256_hkdfsha256::DhP256HkdfSha256
     );
+    #[cfg(feature = "p256")]
+    test_single_shot_fixed!(
+        test_single_shot_fixed_p256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::dhp256_hkdfsha256::DhP256HkdfSha256
+    );
+
+    #[cfg(all(feature = "alloc", feature = "k256"))]
+    test_single_shot_correctness!(
+        test_single_shot_correctness_k256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::dhk256_hkdfsha256::DhK256HkdfSha256
+    );
+    #[cfg(feature = "k256")]
+    test_single_shot_fixed!(
+        test_single_shot_fixed_k256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::dhk256_hkdfsha256::DhK256HkdfSha256
+    );
 }
@@ -0,0 +1,240 @@
+//! A non-standard mode where the sender uses its static key in place of an ephemeral one, for
+//! deployments where both parties' static keys are known and fresh randomness isn't allowed,
+//! e.g., deterministic encryption for deduplication. This is NOT part of RFC 9180, and no other
+//! HPKE implementation speaks it. It's gated under the `static-static` feature.
+//!
+//! The shared secret is DHKEM's `Encap()` with the sender's static keypair as the ephemeral one,
+//! so it's `ExtractAndExpand(DH(skS, pkR), pkSm || pkRm)`, and nothing is sent in place of an
+//! encapsulated key. The key schedule runs with the mode ID `STATIC_STATIC_MODE_ID`, which isn't
+//! one of RFC 9180's, so no standard HPKE context ever equals one of these. The caller-supplied
+//! salt goes where the PSK would, and the PSK ID is empty.
+//!
+//! DANGER
+//! ======
+//! The context depends only on the two keys, the salt, and the info string, so setting up twice
+//! with the same ones gives the same key and nonce sequence. Sealing the same plaintext under
+//! both gives the same ciphertext, which is the point. Sealing different plaintexts under both
+//! reuses nonces, which breaks the confidentiality and integrity of everything sealed with either
+//! context. The salt, or the info string, MUST be different for every distinct plaintext, e.g., a
+//! hash of it. There's also no forward secrecy: anyone who later learns either static private key
+//! can open everything ever sealed between the two.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     static_static::{setup_receiver_static_static, setup_sender_static_static},
+//!     Kem,
+//! };
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_sender, pk_sender) = K::gen_keypair(&mut csprng);
+//! let (sk_recip, pk_recip) = K::gen_keypair(&mut csprng);
+//!
+//! // The salt must be unique to the plaintext
+//! let msg = b"chunk contents";
+//! let salt = b"hash of chunk contents";
+//!
+//! let mut sender_ctx =
+//!     setup_sender_static_static::<A, Kdf, K>(&sk_sender, &pk_sender, &pk_recip, salt, b"info")
+//!         .unwrap();
+//! let ciphertext = sender_ctx.seal(msg, b"").unwrap();
+//!
+//! let mut receiver_ctx =
+//!     setup_receiver_static_static::<A, Kdf, K>(&sk_recip, &pk_sender, salt, b"info").unwrap();
+//! assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), msg);
+//! # }
+//! ```
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS},
+    dhkex::DhKeyExchange,
+    kdf::Kdf as KdfTrait,
+    kem::{DhEncappedKey, DhKem, Kem as KemTrait},
+    op_mode::OpMode,
+    setup::{derive_enc_ctx, usage_hook},
+    trace, HpkeError,
+};
+
+/// The mode ID that the key schedule runs with in this mode. RFC 9180 only uses `0x00` through
+/// `0x03`, so this is in none of its modes' ranges.
+pub const STATIC_STATIC_MODE_ID: u8 = 0xFF;
+
+/// A KEM whose sender can use a static keypair in place of an ephemeral one. This is implemented
+/// for every DHKEM, since their encapsulated keys are public keys.
+pub trait StaticStaticKem: KemTrait {
+    /// Returns the encapsulated key that encapsulating with the ephemeral public key `pk` gives
+    #[doc(hidden)]
+    fn pk_to_encapped_key(pk: &Self::PublicKey) -> Self::EncappedKey;
+}
+
+impl<Dh: DhKeyExchange, Kdf: KdfTrait, const KEM_ID: u16> StaticStaticKem
+    for DhKem<Dh, Kdf, KEM_ID>
+{
+    fn pk_to_encapped_key(pk: &Self::PublicKey) -> Self::EncappedKey {
+        DhEncappedKey(pk.clone())
+    }
+}
+
+// The key schedule inputs of this mode. The salt takes the place of the PSK.
+struct StaticStaticMode<'a> {
+    salt: &'a [u8],
+}
+
+impl<Kem: KemTrait> OpMode<Kem> for StaticStaticMode<'_> {
+    fn mode_id(&self) -> u8 {
+        STATIC_STATIC_MODE_ID
+    }
+
+    fn get_psk_bytes(&self) -> &[u8] {
+        self.salt
+    }
+
+    fn get_psk_id(&self) -> &[u8] {
+        &[]
+    }
+}
+
+/// Initiates an encryption context from the sender's static keypair to the recipient's static
+/// public key, with no ephemeral key. Nothing needs to be sent to the recipient besides the
+/// ciphertexts. The recipient opens them with `setup_receiver_static_static`. See the module
+/// documentation for how the context is derived.
+///
+/// DANGER
+/// ======
+/// The same keys, salt, and info string always give the same context. Never seal two different
+/// plaintexts under contexts set up with the same ones. See the module documentation.
+///
+/// Return Value
+/// ============
+/// On success, returns an encryption context. If `pk_sender` isn't the public key of
+/// `sk_sender`, or an error happened during the key exchange, returns
+/// `Err(HpkeError::EncapError)`.
+pub fn setup_sender_static_static<A, Kdf, Kem>(
+    sk_sender: &Kem::PrivateKey,
+    pk_sender: &Kem::PublicKey,
+    pk_recip: &Kem::PublicKey,
+    salt: &[u8],
+    info: &[u8],
+) -> Result<AeadCtxS<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: StaticStaticKem,
+{
+    let _span = trace::enter_setup::<A, Kdf, Kem>("sender", STATIC_STATIC_MODE_ID);
+
+    // Encapsulate with the static keypair as the ephemeral one. The encapsulated key is
+    // pk_sender, which the recipient already has, so it's dropped.
+    let (shared_secret, _) = Kem::encap_with_ephemeral(pk_recip, None, sk_sender, pk_sender)
+        .map_err(trace::setup_error)?;
+    let enc_ctx =
+        derive_enc_ctx::<_, _, Kem, _>(&StaticStaticMode { salt }, shared_secret, &[info]);
+    usage_hook::record::<A, Kdf, Kem>(pk_recip, STATIC_STATIC_MODE_ID);
+
+    Ok(enc_ctx.into())
+}
+
+/// Initiates a decryption context for ciphertexts sealed by `setup_sender_static_static` from
+/// `pk_sender` to `sk_recip`'s public key, with the same salt and info string. Opening succeeds
+/// only if the sender had the private key of `pk_sender`, so this authenticates the sender like
+/// Auth mode does, with the same caveat: the recipient could have made the ciphertext too.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. If an error happened during the key exchange,
+/// returns `Err(HpkeError::DecapError)`.
+pub fn setup_receiver_static_static<A, Kdf, Kem>(
+    sk_recip: &Kem::PrivateKey,
+    pk_sender: &Kem::PublicKey,
+    salt: &[u8],
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: StaticStaticKem,
+{
+    let _span = trace::enter_setup::<A, Kdf, Kem>("receiver", STATIC_STATIC_MODE_ID);
+
+    // Decapsulate as if pk_sender were the encapsulated key
+    let encapped_key = Kem::pk_to_encapped_key(pk_sender);
+    let shared_secret = Kem::decap(sk_recip, None, &encapped_key).map_err(trace::setup_error)?;
+    let enc_ctx =
+        derive_enc_ctx::<_, _, Kem, _>(&StaticStaticMode { salt }, shared_secret, &[info]);
+
+    Ok(enc_ctx.into())
+}
+
+#[cfg(all(test, feature = "x25519"))]
+mod test {
+    use super::{setup_receiver_static_static, setup_sender_static_static, StaticStaticKem};
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::X25519HkdfSha256, setup_receiver, HpkeError,
+        Kem as KemTrait, OpModeR,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Tests that static-static contexts round-trip, are deterministic in the keys, salt, and
+    /// info, and don't open under the wrong inputs or a standard Base mode context
+    #[test]
+    fn test_static_static() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_sender, pk_sender) = Kem::gen_keypair(&mut csprng);
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let (_, pk_other) = Kem::gen_keypair(&mut csprng);
+        let msg = b"dedup me";
+
+        let seal = |salt: &[u8], info: &[u8]| {
+            let mut ctx = setup_sender_static_static::<A, Kdf, Kem>(
+                &sk_sender, &pk_sender, &pk_recip, salt, info,
+            )
+            .unwrap();
+            // Sealing the same plaintext twice under the same context is the point here
+            #[cfg(feature = "nonce-reuse-check")]
+            ctx.forget_sealed_nonces();
+            ctx.seal(msg, b"").unwrap()
+        };
+        let ciphertext = seal(b"salt", b"info");
+        assert_eq!(seal(b"salt", b"info"), ciphertext);
+        assert_ne!(seal(b"other salt", b"info"), ciphertext);
+        assert_ne!(seal(b"salt", b"other info"), ciphertext);
+
+        let open = |pk_sender, salt: &[u8], info: &[u8]| {
+            let mut ctx =
+                setup_receiver_static_static::<A, Kdf, Kem>(&sk_recip, pk_sender, salt, info)
+                    .unwrap();
+            ctx.open(&ciphertext, b"")
+        };
+        assert_eq!(open(&pk_sender, b"salt", b"info").unwrap(), msg);
+        assert_eq!(open(&pk_other, b"salt", b"info"), Err(HpkeError::OpenError));
+        assert_eq!(open(&pk_sender, b"", b"info"), Err(HpkeError::OpenError));
+        assert_eq!(open(&pk_sender, b"salt", b""), Err(HpkeError::OpenError));
+
+        // The same KEM shared secret under a standard mode gives a different context
+        let encapped_key = Kem::pk_to_encapped_key(&pk_sender);
+        let mut base_ctx =
+            setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"info")
+                .unwrap();
+        assert_eq!(base_ctx.open(&ciphertext, b""), Err(HpkeError::OpenError));
+
+        // The sender's keypair has to match
+        assert!(matches!(
+            setup_sender_static_static::<A, Kdf, Kem>(&sk_sender, &pk_other, &pk_recip, b"", b""),
+            Err(HpkeError::EncapError)
+        ));
+    }
+}
@@ -29,7 +29,7 @@ pub(crate) fn dhkex_gen_keypair<Kex: DhKeyExchange, R: CryptoRng + RngCore>(
     // Fill it with randomness
     csprng.fill_bytes(&mut ikm);
     // Run derive_keypair with a nonsense ciphersuite. We use SHA-512 to satisfy any security level
-    Kex::derive_keypair::<crate::kdf::HkdfSha512>(b"31337", &ikm)
+    Kex::derive_keypair::<crate::kdf::HkdfSha512>(b"31337", &ikm).unwrap()
 }
 
 /// Creates a pair of `AeadCtx`s without doing a key exchange
@@ -73,7 +73,7 @@ pub(crate) enum OpModeKind {
 }
 
 /// Makes an agreeing pair of `OpMode`s of the specified variant
-pub(crate) fn new_op_mode_pair<'a, Kdf: KdfTrait, Kem: KemTrait>(
+pub(crate) fn new_op_mode_pair<'a, Kem: KemTrait>(
     kind: OpModeKind,
     psk: &'a [u8],
     psk_id: &'a [u8],
@@ -112,36 +112,38 @@ pub(crate) fn aead_ctx_eq<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(
     sender: &mut AeadCtxS<A, Kdf, Kem>,
     receiver: &mut AeadCtxR<A, Kdf, Kem>,
 ) -> bool {
+    // Comparing contexts means sealing with both, which is a nonce reuse when they're equal. Let
+    // the nonce reuse check know it's deliberate.
+    #[cfg(feature = "nonce-reuse-check")]
+    sender.forget_sealed_nonces();
+
     let mut csprng = StdRng::from_entropy();
 
-    // Some random input data
-    let msg = {
-        let len = csprng.gen::<u8>();
-        let mut buf = vec![0u8; len as usize];
-        csprng.fill_bytes(&mut buf);
-        buf
-    };
-    let aad = {
-        let len = csprng.gen::<u8>();
-        let mut buf = vec![0u8; len as usize];
-        csprng.fill_bytes(&mut buf);
-        buf
-    };
+    // Some random input data, in stack buffers so this works without an allocator
+    let mut msg_buf = [0u8; 255];
+    let msg_len = csprng.gen::<u8>() as usize;
+    csprng.fill_bytes(&mut msg_buf[..msg_len]);
+    let msg = &msg_buf[..msg_len];
+    let mut aad_buf = [0u8; 255];
+    let aad_len = csprng.gen::<u8>() as usize;
+    csprng.fill_bytes(&mut aad_buf[..aad_len]);
+    let aad = &aad_buf[..aad_len];
 
     // Do 1000 iterations of encryption-decryption. The underlying sequence number increments
     // each time.
     for i in 0..1000 {
-        let mut plaintext = msg.clone();
+        let mut buf = msg_buf;
+        let plaintext = &mut buf[..msg_len];
         // Encrypt the plaintext
         let tag = sender
-            .seal_in_place_detached(&mut plaintext[..], &aad)
+            .seal_in_place_detached(plaintext, aad)
             .unwrap_or_else(|_| panic!("seal() #{} failed", i));
         // Rename for clarity
-        let mut ciphertext = plaintext;
+        let ciphertext = plaintext;
 
         // Now to decrypt on the other side
         if receiver
-            .open_in_place_detached(&mut ciphertext[..], &aad, &tag)
+            .open_in_place_detached(ciphertext, aad, &tag)
             .is_err()
         {
             // An error occurred in decryption. These encryption contexts are not identical.
@@ -0,0 +1,247 @@
+//! Generation of known-answer test vectors in the JSON format of the RFC 9180 test vectors, for
+//! publishing vectors for ciphersuites the RFC doesn't cover, like DHKEM(K-256, HKDF-SHA256).
+//! Vectors in BoringSSL's format can be read and checked against this crate with
+//! `parse_boringssl_test_vectors` and `check_test_vector`.
+
+use crate::{
+    aead::{Aead, AeadCtxS},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    op_mode::{OpModeS, PskBundle},
+    setup::derive_enc_ctx_observed,
+    HpkeError, Serializable, Vec,
+};
+
+use core::fmt::Write;
+
+use generic_array::GenericArray;
+use rand_core::{CryptoRng, RngCore};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::string::String;
+
+mod boringssl;
+pub use boringssl::{
+    check_test_vector, parse_boringssl_test_vectors, EncryptionVector, ExportVector, TestVector,
+};
+
+// The fixed inputs the RFC 9180 vectors use, so generated vectors are easy to compare with them
+const INFO: &[u8] = b"Ode on a Grecian Urn";
+const PSK: &[u8] = b"\x02\x47\xfd\x33\xb9\x13\x76\x0f\xa1\xfa\x51\xe1\x89\x2d\x9f\x30\x7f\xbe\x65\xeb\x17\x1e\x81\x32\xc2\xaf\x18\x55\x5a\x73\x8b\x82";
+const PSK_ID: &[u8] = b"Ennyn Durin aran Moria";
+const PLAINTEXT: &[u8] = b"Beauty is truth, truth beauty";
+const NUM_ENCRYPTIONS: u32 = 257;
+const EXPORTER_CONTEXTS: [&[u8]; 3] = [b"", b"\x00", b"TestContext"];
+const EXPORT_LEN: usize = 32;
+
+// ExportOnlyAead's ID. Suites with it have no encryptions.
+const EXPORT_ONLY_AEAD_ID: u16 = 0xFFFF;
+
+/// Generates one test vector for each of the four modes of the ciphersuite `(A, Kdf, Kem)`, with
+/// fresh keying material from `csprng`, and returns them as a JSON array in the format of the RFC
+/// 9180 test vectors. The info string, PSK, PSK ID, plaintext, AADs, and exporter contexts are
+/// the same ones the RFC uses.
+///
+/// `skEm` and `pkEm` are the keypair `Kem::derive_keypair(ikmE)`. For DHKEMs, this is the
+/// ephemeral keypair. `enc` is always the encapsulated key.
+///
+/// Return Value
+/// ============
+/// Returns the JSON on success. If key derivation or encapsulation fails, returns that error.
+pub fn generate_test_vectors<A, Kdf, Kem, R>(csprng: &mut R) -> Result<String, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let mut json = String::from("[");
+    for mode_id in 0u8..4 {
+        if mode_id > 0 {
+            json.push(',');
+        }
+        let ikm_recip = random_ikm::<Kem, R>(csprng);
+        let ikm_sender = random_ikm::<Kem, R>(csprng);
+        let ikm_eph = random_ikm::<Kem, R>(csprng);
+        write_test_vector::<A, Kdf, Kem>(
+            &mut json,
+            mode_id,
+            &ikm_recip,
+            &ikm_sender,
+            &ikm_eph,
+            INFO,
+        )?;
+    }
+    json.push(']');
+
+    Ok(json)
+}
+
+// Makes keying material the size of a private key, like Kem::gen_keypair does
+fn random_ikm<Kem, R>(
+    csprng: &mut R,
+) -> GenericArray<u8, <Kem::PrivateKey as Serializable>::OutputSize>
+where
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let mut ikm = GenericArray::default();
+    csprng.fill_bytes(&mut ikm);
+    ikm
+}
+
+// Appends the JSON object for one test vector to `json`. `ikm_sender` is ignored in the non-auth
+// modes.
+pub(crate) fn write_test_vector<A, Kdf, Kem>(
+    json: &mut String,
+    mode_id: u8,
+    ikm_recip: &[u8],
+    ikm_sender: &[u8],
+    ikm_eph: &[u8],
+    info: &[u8],
+) -> Result<(), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let is_auth = mode_id & 2 != 0;
+    let is_psk = mode_id & 1 != 0;
+
+    let (sk_recip, pk_recip) = Kem::try_derive_keypair(ikm_recip)?;
+    let (sk_sender, pk_sender) = Kem::try_derive_keypair(ikm_sender)?;
+    let (sk_eph, pk_eph) = Kem::try_derive_keypair(ikm_eph)?;
+
+    let psk = PskBundle {
+        psk: PSK,
+        psk_id: PSK_ID,
+    };
+    let mode = match (is_auth, is_psk) {
+        (false, false) => OpModeS::Base,
+        (false, true) => OpModeS::Psk(psk),
+        (true, false) => OpModeS::Auth((sk_sender.clone(), pk_sender.clone())),
+        (true, true) => OpModeS::AuthPsk((sk_sender.clone(), pk_sender.clone()), psk),
+    };
+
+    // RFC 9180 §5.1: SetupS(), with the intermediate values of the key schedule written down
+    let sender_id_keypair = is_auth.then_some((&sk_sender, &pk_sender));
+    let (shared_secret, encapped_key) = Kem::encap_with_ikm(&pk_recip, sender_id_keypair, ikm_eph)?;
+    let shared_secret_bytes = shared_secret.0.to_vec();
+
+    let mut schedule = String::new();
+    let mut base_nonce = Vec::new();
+    let mut ctx: AeadCtxS<A, Kdf, Kem> =
+        derive_enc_ctx_observed(&mode, shared_secret, &[info], |trace| {
+            write_field(
+                &mut schedule,
+                "key_schedule_context",
+                trace.key_schedule_context,
+            );
+            write_field(&mut schedule, "secret", trace.secret);
+            write_field(&mut schedule, "key", trace.key);
+            // The export-only AEAD has Nn = 0, though it keeps a dummy nonce internally
+            let base_nonce_bytes = if A::AEAD_ID == EXPORT_ONLY_AEAD_ID {
+                &[][..]
+            } else {
+                trace.base_nonce
+            };
+            write_field(&mut schedule, "base_nonce", base_nonce_bytes);
+            write_field(&mut schedule, "exporter_secret", trace.exporter_secret);
+            base_nonce.extend_from_slice(trace.base_nonce);
+        })
+        .into();
+
+    // The fields go in the same order as in the RFC vectors
+    json.push('{');
+    let _ = write!(
+        json,
+        "\"mode\":{},\"kem_id\":{},\"kdf_id\":{},\"aead_id\":{},",
+        mode_id,
+        Kem::KEM_ID,
+        Kdf::KDF_ID,
+        A::AEAD_ID
+    );
+    write_field(json, "info", info);
+    write_field(json, "ikmR", ikm_recip);
+    if is_auth {
+        write_field(json, "ikmS", ikm_sender);
+    }
+    write_field(json, "ikmE", ikm_eph);
+    write_field(json, "skRm", &sk_recip.to_bytes());
+    if is_auth {
+        write_field(json, "skSm", &sk_sender.to_bytes());
+    }
+    write_field(json, "skEm", &sk_eph.to_bytes());
+    if is_psk {
+        write_field(json, "psk", PSK);
+        write_field(json, "psk_id", PSK_ID);
+    }
+    write_field(json, "pkRm", &pk_recip.to_bytes());
+    if is_auth {
+        write_field(json, "pkSm", &pk_sender.to_bytes());
+    }
+    write_field(json, "pkEm", &pk_eph.to_bytes());
+    write_field(json, "enc", &encapped_key.to_bytes());
+    write_field(json, "shared_secret", &shared_secret_bytes);
+    json.push_str(&schedule);
+
+    // RFC 9180 §5.2: the nonce for sequence number seq is base_nonce XOR I2OSP(seq, Nn)
+    json.push_str("\"encryptions\":[");
+    if A::AEAD_ID != EXPORT_ONLY_AEAD_ID {
+        for seq in 0..NUM_ENCRYPTIONS {
+            if seq > 0 {
+                json.push(',');
+            }
+            let mut aad = String::new();
+            let _ = write!(aad, "Count-{}", seq);
+            let ciphertext = ctx.seal(PLAINTEXT, aad.as_bytes())?;
+
+            let mut nonce = base_nonce.clone();
+            let nonce_len = nonce.len();
+            for (i, b) in seq.to_be_bytes().iter().enumerate() {
+                nonce[nonce_len - 4 + i] ^= b;
+            }
+
+            json.push('{');
+            write_field(json, "aad", aad.as_bytes());
+            write_field(json, "ct", &ciphertext);
+            write_field(json, "nonce", &nonce);
+            write_last_field(json, "pt", PLAINTEXT);
+            json.push('}');
+        }
+    }
+    json.push_str("],\"exports\":[");
+    for (i, exporter_ctx) in EXPORTER_CONTEXTS.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let mut exported_value = [0u8; EXPORT_LEN];
+        ctx.export(exporter_ctx, &mut exported_value)?;
+
+        json.push('{');
+        write_field(json, "exporter_context", exporter_ctx);
+        let _ = write!(json, "\"L\":{},", EXPORT_LEN);
+        write_last_field(json, "exported_value", &exported_value);
+        json.push('}');
+    }
+    json.push_str("]}");
+
+    Ok(())
+}
+
+// Appends `"name":"<hex of value>",`
+fn write_field(json: &mut String, name: &str, value: &[u8]) {
+    write_last_field(json, name, value);
+    json.push(',');
+}
+
+// Appends `"name":"<hex of value>"`
+fn write_last_field(json: &mut String, name: &str, value: &[u8]) {
+    let _ = write!(json, "\"{}\":\"", name);
+    for b in value {
+        let _ = write!(json, "{:02x}", b);
+    }
+    json.push('"');
+}
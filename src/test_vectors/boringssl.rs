@@ -0,0 +1,449 @@
+use crate::{
+    dyn_suite::{self, DynOpModeR, DynOpModeS},
+    HpkeError, PskBundle, Vec,
+};
+
+use rand_core::{CryptoRng, RngCore};
+
+// BoringSSL's vectors are all for this KEM, so they can leave out kem_id
+const DEFAULT_KEM_ID: u16 = 0x0020;
+
+/// One encryption in a `TestVector`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EncryptionVector {
+    /// The associated data
+    pub aad: Vec<u8>,
+    /// The ciphertext, with the tag appended
+    pub ct: Vec<u8>,
+    /// The plaintext
+    pub pt: Vec<u8>,
+}
+
+/// One export in a `TestVector`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportVector {
+    /// The exporter context
+    pub exporter_context: Vec<u8>,
+    /// The exported value. Its length is the `L` of the vector.
+    pub exported_value: Vec<u8>,
+}
+
+/// An HPKE test vector, with the fields that a receiver needs to check it. Read these from
+/// BoringSSL's format with `parse_boringssl_test_vectors`, and check them against this crate with
+/// `check_test_vector`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestVector {
+    /// The mode ID from RFC 9180 §5 Table 1: 0 for Base, 1 for Psk, 2 for Auth, and 3 for AuthPsk
+    pub mode: u8,
+    /// The KEM's algorithm identifier
+    pub kem_id: u16,
+    /// The KDF's algorithm identifier
+    pub kdf_id: u16,
+    /// The AEAD's algorithm identifier
+    pub aead_id: u16,
+    /// The info string
+    pub info: Vec<u8>,
+    /// The recipient's private key
+    pub sk_r: Vec<u8>,
+    /// The recipient's public key
+    pub pk_r: Vec<u8>,
+    /// The sender's identity public key, in the auth modes
+    pub pk_s: Option<Vec<u8>>,
+    /// The PSK and PSK ID, in the PSK modes
+    pub psk: Option<(Vec<u8>, Vec<u8>)>,
+    /// The encapsulated key
+    pub enc: Vec<u8>,
+    /// The encryptions, in sequence number order, starting from 0
+    pub encryptions: Vec<EncryptionVector>,
+    /// The exports
+    pub exports: Vec<ExportVector>,
+}
+
+/// Parses test vectors in the format of BoringSSL's `crypto/hpke/hpke_test_vectors.txt`. Each
+/// vector is a block of `key = value` lines, and blocks are separated by blank lines. Lines
+/// starting with `#` are comments, and lines in square brackets are ignored.
+///
+/// Values are hex, except `mode`, the algorithm identifiers, and export lengths, which are
+/// decimal. A value in double quotes is taken as the bytes between them. The keys are the RFC
+/// 9180 JSON field names: `mode`, `kem_id`, `kdf_id`, `aead_id`, `info`, `skRm`, `pkRm`, `pkSm`,
+/// `psk`, `psk_id`, `enc`, then `aad`, `ct`, and `pt` for each encryption, and
+/// `exporter_context`, `L`, and `exported_value` for each export, in order. The older names
+/// `plaintext`, `exportContext`, `exportLength`, and `exportValue` are also accepted. If `kem_id`
+/// is missing, it's DHKEM(X25519, HKDF-SHA256), and if `enc` is missing, it's `pkEm`, which is
+/// the same thing for DHKEMs. Other keys are ignored.
+///
+/// Return Value
+/// ============
+/// Returns the vectors on success. If a line isn't a `key = value` pair, a value doesn't parse,
+/// a vector is missing a field its mode needs, or a vector's encryptions or exports are missing a
+/// field, returns `Err(HpkeError::ValidationError)`.
+pub fn parse_boringssl_test_vectors(text: &str) -> Result<Vec<TestVector>, HpkeError> {
+    let mut vectors = Vec::new();
+    let mut block = Vec::new();
+    for line in text.lines().map(str::trim).chain(core::iter::once("")) {
+        if line.is_empty() {
+            if !block.is_empty() {
+                vectors.push(parse_block(&block)?);
+                block.clear();
+            }
+        } else if !line.starts_with('#') && !line.starts_with('[') {
+            let (key, value) = line.split_once('=').ok_or(HpkeError::ValidationError)?;
+            block.push((key.trim(), value.trim()));
+        }
+    }
+
+    Ok(vectors)
+}
+
+// Parses one block of key-value pairs into a test vector
+fn parse_block(block: &[(&str, &str)]) -> Result<TestVector, HpkeError> {
+    // Required single fields, and the ones each mode needs
+    let get = |names: &[&str]| {
+        block
+            .iter()
+            .find(|(k, _)| names.contains(k))
+            .map(|&(_, v)| v)
+    };
+    let required = |name: &str| get(&[name]).ok_or(HpkeError::ValidationError);
+    // Repeated fields, in the order they appear
+    let all = |names: &[&str]| -> Result<Vec<Vec<u8>>, HpkeError> {
+        block
+            .iter()
+            .filter(|(k, _)| names.contains(k))
+            .map(|&(_, v)| parse_bytes(v))
+            .collect()
+    };
+
+    let mode = parse_int(required("mode")?)?;
+    let mode = u8::try_from(mode)
+        .ok()
+        .filter(|m| *m < 4)
+        .ok_or(HpkeError::ValidationError)?;
+    let (is_psk, is_auth) = (mode & 1 != 0, mode & 2 != 0);
+
+    let kem_id = match get(&["kem_id"]) {
+        Some(v) => parse_id(v)?,
+        None => DEFAULT_KEM_ID,
+    };
+    let enc = match get(&["enc"]) {
+        Some(v) => parse_bytes(v)?,
+        None => parse_bytes(required("pkEm")?)?,
+    };
+    let pk_s = if is_auth {
+        Some(parse_bytes(required("pkSm")?)?)
+    } else {
+        None
+    };
+    let psk = if is_psk {
+        Some((
+            parse_bytes(required("psk")?)?,
+            parse_bytes(required("psk_id")?)?,
+        ))
+    } else {
+        None
+    };
+
+    let (aads, cts, pts) = (all(&["aad"])?, all(&["ct"])?, all(&["pt", "plaintext"])?);
+    if aads.len() != cts.len() || aads.len() != pts.len() {
+        return Err(HpkeError::ValidationError);
+    }
+    let encryptions = aads
+        .into_iter()
+        .zip(cts)
+        .zip(pts)
+        .map(|((aad, ct), pt)| EncryptionVector { aad, ct, pt })
+        .collect();
+
+    let contexts = all(&["exporter_context", "exportContext"])?;
+    let values = all(&["exported_value", "exportValue"])?;
+    let lens = block
+        .iter()
+        .filter(|(k, _)| *k == "L" || *k == "exportLength")
+        .map(|&(_, v)| parse_int(v))
+        .collect::<Result<Vec<_>, _>>()?;
+    if contexts.len() != values.len() || contexts.len() != lens.len() {
+        return Err(HpkeError::ValidationError);
+    }
+    if values
+        .iter()
+        .zip(lens.iter())
+        .any(|(v, l)| v.len() as u64 != *l)
+    {
+        return Err(HpkeError::ValidationError);
+    }
+    let exports = contexts
+        .into_iter()
+        .zip(values)
+        .map(|(exporter_context, exported_value)| ExportVector {
+            exporter_context,
+            exported_value,
+        })
+        .collect();
+
+    Ok(TestVector {
+        mode,
+        kem_id,
+        kdf_id: parse_id(required("kdf_id")?)?,
+        aead_id: parse_id(required("aead_id")?)?,
+        info: parse_bytes(required("info")?)?,
+        sk_r: parse_bytes(required("skRm")?)?,
+        pk_r: parse_bytes(required("pkRm")?)?,
+        pk_s,
+        psk,
+        enc,
+        encryptions,
+        exports,
+    })
+}
+
+fn parse_int(value: &str) -> Result<u64, HpkeError> {
+    value.parse().map_err(|_| HpkeError::ValidationError)
+}
+
+fn parse_id(value: &str) -> Result<u16, HpkeError> {
+    u16::try_from(parse_int(value)?).map_err(|_| HpkeError::ValidationError)
+}
+
+// Parses hex, or a string in double quotes
+fn parse_bytes(value: &str) -> Result<Vec<u8>, HpkeError> {
+    if let Some(s) = value.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        return Ok(s.as_bytes().to_vec());
+    }
+
+    let hex_val = |c: u8| (c as char).to_digit(16).ok_or(HpkeError::ValidationError);
+    let bytes = value.as_bytes();
+    if bytes.len() % 2 != 0 {
+        return Err(HpkeError::ValidationError);
+    }
+    bytes
+        .chunks(2)
+        .map(|pair| Ok((hex_val(pair[0])? << 4 | hex_val(pair[1])?) as u8))
+        .collect()
+}
+
+/// Checks a test vector against this crate, as its receiver. This checks that `pk_r` is the
+/// public key of `sk_r`, then decapsulates `enc`, opens every ciphertext in order and compares it
+/// to its plaintext, and compares every export.
+///
+/// The suite can be any that's compiled in, including DHKEM(K-256, HKDF-SHA256) and the other
+/// private-use suites, so vectors generated elsewhere for those can be checked here too.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(())` if the vector checks out. If the suite isn't compiled in, returns
+/// `Err(HpkeError::ValidationError)`. Use `dyn_suite::by_id` to skip those instead. If `pk_r`
+/// isn't the public key of `sk_r`, an opened plaintext is different, or an exported value is
+/// different, returns `Err(HpkeError::ValidationError)`. Otherwise, returns the error that
+/// parsing a key, decapsulating, opening, or exporting returned. `csprng` is only used to check
+/// the recipient keypair.
+pub fn check_test_vector<R>(tv: &TestVector, csprng: &mut R) -> Result<(), HpkeError>
+where
+    R: CryptoRng + RngCore,
+{
+    let suite =
+        dyn_suite::by_id(tv.kem_id, tv.kdf_id, tv.aead_id).ok_or(HpkeError::ValidationError)?;
+
+    let psk = tv
+        .psk
+        .as_ref()
+        .map(|(psk, psk_id)| PskBundle { psk, psk_id });
+    let pk_s = tv.pk_s.as_deref();
+    let mode = match (psk, pk_s) {
+        (None, None) => DynOpModeR::Base,
+        (Some(psk), None) => DynOpModeR::Psk(psk),
+        (None, Some(pk_s)) => DynOpModeR::Auth(pk_s),
+        (Some(psk), Some(pk_s)) => DynOpModeR::AuthPsk(pk_s, psk),
+    };
+
+    // There's no way to get a public key from a private key through a trait object, so check
+    // that the keypair goes together by encrypting to one and decrypting with the other
+    let (enc, ctx_s) = suite.setup_sender(&DynOpModeS::Base, &tv.pk_r, b"", csprng)?;
+    let ctx_r = suite.setup_receiver(&DynOpModeR::Base, &tv.sk_r, &enc, b"")?;
+    let (mut exported_s, mut exported_r) = ([0u8; 32], [0u8; 32]);
+    ctx_s.export(b"", &mut exported_s)?;
+    ctx_r.export(b"", &mut exported_r)?;
+    if exported_s != exported_r {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let mut ctx = suite.setup_receiver(&mode, &tv.sk_r, &tv.enc, &tv.info)?;
+    for encryption in tv.encryptions.iter() {
+        if ctx.open(&encryption.ct, &encryption.aad)? != encryption.pt {
+            return Err(HpkeError::ValidationError);
+        }
+    }
+    for export in tv.exports.iter() {
+        let mut exported_value = vec![0u8; export.exported_value.len()];
+        ctx.export(&export.exporter_context, &mut exported_value)?;
+        if exported_value != export.exported_value {
+            return Err(HpkeError::ValidationError);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, any(feature = "x25519", feature = "k256")))]
+mod test {
+    use super::{check_test_vector, parse_boringssl_test_vectors};
+    #[cfg(feature = "x25519")]
+    use crate::HpkeError;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // The first two encryptions and the exports of the RFC 9180 A.1.2 vector, DHKEM(X25519,
+    // HKDF-SHA256), HKDF-SHA256, AES-128-GCM in Psk mode, in BoringSSL's format. There's no
+    // kem_id or enc, and one exporter context is a quoted string.
+    #[cfg(feature = "x25519")]
+    const BORINGSSL_VECTOR: &str = "\
+mode = 1
+kdf_id = 1
+aead_id = 1
+info = 4f6465206f6e2061204772656369616e2055726e
+skRm = c5eb01eb457fe6c6f57577c5413b931550a162c71a03ac8d196babbd4e5ce0fd
+skEm = 463426a9ffb42bb17dbe6044b9abd1d4e4d95f9041cef0e99d7824eef2b6f588
+pkRm = 9fed7e8c17387560e92cc6462a68049657246a09bfa8ade7aefe589672016366
+pkEm = 0ad0950d9fb9588e59690b74f1237ecdf1d775cd60be2eca57af5a4b0471c91b
+psk = 0247fd33b913760fa1fa51e1892d9f307fbe65eb171e8132c2af18555a738b82
+psk_id = 456e6e796e20447572696e206172616e204d6f726961
+# encryptions[0]
+aad = 436f756e742d30
+ct = e52c6fed7f758d0cf7145689f21bc1be6ec9ea097fef4e959440012f4feb73fb611b946199e681f4cfc34db8ea
+pt = 4265617574792069732074727574682c20747275746820626561757479
+# encryptions[1]
+aad = 436f756e742d31
+ct = 49f3b19b28a9ea9f43e8c71204c00d4a490ee7f61387b6719db765e948123b45b61633ef059ba22cd62437c8ba
+pt = 4265617574792069732074727574682c20747275746820626561757479
+# exports[0]
+exporter_context =
+L = 32
+exported_value = dff17af354c8b41673567db6259fd6029967b4e1aad13023c2ae5df8f4f43bf6
+# exports[1]
+exporter_context = 00
+L = 32
+exported_value = 6a847261d8207fe596befb52928463881ab493da345b10e1dcc645e3b94e2d95
+# exports[2]
+exporter_context = \"TestContext\"
+L = 32
+exported_value = 8aff52b45a1be3a734bc7a41e20b4e055ad4c4d22104b0c20285a7c4302401cd
+";
+
+    /// Tests that an RFC 9180 vector in BoringSSL's format parses and checks out, and that
+    /// tampering with it or its format is caught
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_boringssl_vector() {
+        let mut csprng = StdRng::from_entropy();
+
+        // Two copies, to check that blocks are split on blank lines
+        let text = [BORINGSSL_VECTOR, BORINGSSL_VECTOR].join("\n");
+        let tvs = parse_boringssl_test_vectors(&text).unwrap();
+        assert_eq!(tvs.len(), 2);
+        let tv = &tvs[0];
+        assert_eq!(
+            (tv.mode, tv.kem_id, tv.kdf_id, tv.aead_id),
+            (1, 0x0020, 1, 1)
+        );
+        assert_eq!(tv.encryptions.len(), 2);
+        assert_eq!(tv.exports[0].exporter_context, b"");
+        assert_eq!(tv.exports[2].exporter_context, b"TestContext");
+        check_test_vector(tv, &mut csprng).unwrap();
+
+        // Encryptions have to be opened in order
+        let mut swapped = tv.clone();
+        swapped.encryptions.swap(0, 1);
+        assert_eq!(
+            check_test_vector(&swapped, &mut csprng),
+            Err(HpkeError::OpenError)
+        );
+        let mut bad_export = tv.clone();
+        bad_export.exports[1].exported_value[0] ^= 1;
+        assert_eq!(
+            check_test_vector(&bad_export, &mut csprng),
+            Err(HpkeError::ValidationError)
+        );
+        let mut mismatched_keys = tv.clone();
+        mismatched_keys.pk_r = tv.enc.clone();
+        assert_eq!(
+            check_test_vector(&mismatched_keys, &mut csprng),
+            Err(HpkeError::ValidationError)
+        );
+        let mut unknown_suite = tv.clone();
+        unknown_suite.kdf_id = 0x1234;
+        assert_eq!(
+            check_test_vector(&unknown_suite, &mut csprng),
+            Err(HpkeError::ValidationError)
+        );
+
+        // Malformed text
+        for bad in [
+            BORINGSSL_VECTOR.replace("mode = 1", "mode = 4"),
+            BORINGSSL_VECTOR.replace("psk = ", "ppsk = "),
+            BORINGSSL_VECTOR.replace("L = 32", "L = 31"),
+            BORINGSSL_VECTOR.replace("aad = 436f756e742d31\n", ""),
+            BORINGSSL_VECTOR.replace("info = 4f", "info = 4"),
+            BORINGSSL_VECTOR.replace("info = 4f", "info = zz"),
+            BORINGSSL_VECTOR.replace("info = ", "info "),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                parse_boringssl_test_vectors(bad),
+                Err(HpkeError::ValidationError)
+            );
+        }
+    }
+
+    /// Tests that K-256 vectors generated by `generate_test_vectors`, written in BoringSSL's
+    /// format, parse and check out, in every mode
+    #[cfg(feature = "k256")]
+    #[test]
+    fn test_boringssl_k256_vectors() {
+        use crate::{
+            aead::ChaCha20Poly1305,
+            kdf::HkdfSha256,
+            kem::{DhK256HkdfSha256, Kem as KemTrait},
+            test_vectors::generate_test_vectors,
+        };
+        #[cfg(not(feature = "std"))]
+        use alloc::string::String;
+        #[cfg(feature = "std")]
+        use std::string::String;
+
+        let mut csprng = StdRng::from_entropy();
+        let json =
+            generate_test_vectors::<ChaCha20Poly1305, HkdfSha256, DhK256HkdfSha256, _>(&mut csprng)
+                .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        // One `key = value` line per field, with a comment line before each encryption and export
+        let mut text = String::new();
+        for tv in json.as_array().unwrap() {
+            for (key, value) in tv.as_object().unwrap() {
+                match value {
+                    serde_json::Value::Array(entries) => {
+                        for entry in entries {
+                            text.push_str(&format!("# {}\n", key));
+                            for (key, value) in entry.as_object().unwrap() {
+                                text.push_str(&format!("{} = {}\n", key, value));
+                            }
+                        }
+                    }
+                    _ => text.push_str(&format!("{} = {}\n", key, value)),
+                }
+            }
+            text.push('\n');
+        }
+        // serde_json quotes strings, which would make them literal bytes here
+        let text = text.replace('"', "");
+
+        let tvs = parse_boringssl_test_vectors(&text).unwrap();
+        assert_eq!(tvs.len(), 4);
+        for (mode, tv) in tvs.iter().enumerate() {
+            assert_eq!(tv.mode as usize, mode);
+            assert_eq!(tv.kem_id, DhK256HkdfSha256::KEM_ID);
+            assert_eq!(tv.encryptions.len(), 257);
+            check_test_vector(tv, &mut csprng).unwrap();
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Deterministic randomness for tests and bug reports. Never use anything here in production.
+//!
+//! `DeterministicRng` turns a seed into a fixed stream of bytes, so a test that sets up with it
+//! gets the same keys, encapsulated keys, and ciphertexts on every run. `RecordingRng` wraps any
+//! RNG and keeps a copy of every byte drawn from it, and `ReplayRng` plays those bytes back. So a
+//! failing interop case that was set up with the system RNG can be reproduced byte for byte from
+//! the recording, e.g., attached to a bug report as hex.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     setup_sender,
+//!     testing::{DeterministicRng, RecordingRng, ReplayRng},
+//!     Kem, OpModeS, Serializable,
+//! };
+//! use hpke::rand_core::SeedableRng;
+//!
+//! let (_, pk_recip) = X25519HkdfSha256::gen_keypair(&mut DeterministicRng::seed_from_u64(1));
+//!
+//! // Record the randomness a setup draws
+//! let mut rng = RecordingRng::new(DeterministicRng::seed_from_u64(2));
+//! let (encapped_key, _) = setup_sender::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256, _>(
+//!     &OpModeS::Base,
+//!     &pk_recip,
+//!     b"info",
+//!     &mut rng,
+//! )
+//! .unwrap();
+//!
+//! // Replaying it gives the same encapsulated key
+//! let mut replay = ReplayRng::new(rng.into_recording());
+//! let (replayed_key, _) = setup_sender::<ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256, _>(
+//!     &OpModeS::Base,
+//!     &pk_recip,
+//!     b"info",
+//!     &mut replay,
+//! )
+//! .unwrap();
+//! assert_eq!(encapped_key.to_bytes(), replayed_key.to_bytes());
+//! # }
+//! ```
+
+use crate::Vec;
+
+use core::num::NonZeroU32;
+
+use hmac::{Hmac, Mac};
+use rand_core::{impls, CryptoRng, Error, RngCore, SeedableRng};
+use sha2::Sha256;
+
+// The stream is block_0 || block_1 || ..., where block_i = HMAC-SHA256(seed, label || I2OSP(i, 8))
+const DETERMINISTIC_RNG_LABEL: &[u8] = b"hpke test rng";
+
+/// A seedable RNG whose output is a fixed function of its seed. Its stream is HMAC-SHA256, keyed
+/// with the seed, over a counter, so it's stable across versions of this crate and of its
+/// dependencies. It implements `CryptoRng` so it can be passed to every function here, but it's
+/// only as unpredictable as its seed. Only use it in tests.
+#[derive(Clone, Debug)]
+pub struct DeterministicRng {
+    seed: [u8; 32],
+    /// The index of the next block to compute
+    counter: u64,
+    /// The current block
+    block: [u8; 32],
+    /// How many bytes of `block` have been used
+    pos: usize,
+}
+
+impl SeedableRng for DeterministicRng {
+    type Seed = [u8; 32];
+
+    fn from_seed(seed: [u8; 32]) -> Self {
+        DeterministicRng {
+            seed,
+            counter: 0,
+            block: [0u8; 32],
+            pos: 32,
+        }
+    }
+}
+
+impl DeterministicRng {
+    // Computes the next block into self.block
+    fn refill(&mut self) {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.seed).unwrap();
+        mac.update(DETERMINISTIC_RNG_LABEL);
+        mac.update(&self.counter.to_be_bytes());
+        self.block.copy_from_slice(&mac.finalize().into_bytes());
+        // A test would have to draw 2^69 bytes for this to wrap
+        self.counter = self.counter.wrapping_add(1);
+        self.pos = 0;
+    }
+}
+
+impl RngCore for DeterministicRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let mut written = 0;
+        while written < dest.len() {
+            if self.pos == self.block.len() {
+                self.refill();
+            }
+            let n = core::cmp::min(dest.len() - written, self.block.len() - self.pos);
+            dest[written..written + n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+            self.pos += n;
+            written += n;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for DeterministicRng {}
+
+/// Wraps an RNG and records every byte drawn from it, in order. Pass `&mut RecordingRng` wherever
+/// an RNG is taken, and then get the bytes with `recording` or `into_recording`. Feed them to a
+/// `ReplayRng` to draw them again.
+#[derive(Clone, Debug)]
+pub struct RecordingRng<R> {
+    inner: R,
+    recording: Vec<u8>,
+}
+
+impl<R: RngCore> RecordingRng<R> {
+    /// Wraps `inner`, with nothing recorded yet
+    pub fn new(inner: R) -> Self {
+        RecordingRng {
+            inner,
+            recording: Vec::new(),
+        }
+    }
+
+    /// Returns every byte drawn so far
+    pub fn recording(&self) -> &[u8] {
+        &self.recording
+    }
+
+    /// Returns every byte drawn, and drops the wrapped RNG
+    pub fn into_recording(self) -> Vec<u8> {
+        self.recording
+    }
+}
+
+impl<R: RngCore> RngCore for RecordingRng<R> {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.inner.fill_bytes(dest);
+        self.recording.extend_from_slice(dest);
+    }
+
+    // Only what was actually drawn is recorded, so a failed draw records nothing
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.inner.try_fill_bytes(dest)?;
+        self.recording.extend_from_slice(dest);
+        Ok(())
+    }
+}
+
+// Recording doesn't change the output
+impl<R: CryptoRng> CryptoRng for RecordingRng<R> {}
+
+/// Plays back bytes, e.g., from `RecordingRng::into_recording`. Draws must be made in the same
+/// sizes and order as when recording, which they are if the same code runs with the same inputs.
+#[derive(Clone, Debug)]
+pub struct ReplayRng {
+    recording: Vec<u8>,
+    pos: usize,
+}
+
+impl ReplayRng {
+    /// Makes an RNG that returns `recording`, and then runs dry
+    pub fn new(recording: impl Into<Vec<u8>>) -> Self {
+        ReplayRng {
+            recording: recording.into(),
+            pos: 0,
+        }
+    }
+
+    /// Returns how many recorded bytes haven't been drawn yet. If this isn't 0 at the end of a
+    /// replay, the replayed code drew less than the recorded code did.
+    pub fn remaining(&self) -> usize {
+        self.recording.len() - self.pos
+    }
+}
+
+impl RngCore for ReplayRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    /// Panics
+    /// ======
+    /// Panics if fewer than `dest.len()` recorded bytes are left
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("replayed more randomness than was recorded")
+    }
+
+    /// Returns an error, and draws nothing, if fewer than `dest.len()` recorded bytes are left
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        if dest.len() > self.remaining() {
+            return Err(Error::from(NonZeroU32::new(Error::CUSTOM_START).unwrap()));
+        }
+        dest.copy_from_slice(&self.recording[self.pos..self.pos + dest.len()]);
+        self.pos += dest.len();
+        Ok(())
+    }
+}
+
+impl CryptoRng for ReplayRng {}
+
+#[cfg(test)]
+mod test {
+    use super::{DeterministicRng, RecordingRng, ReplayRng};
+
+    use hex_literal::hex;
+    use rand::{rngs::StdRng, SeedableRng};
+    use rand_core::RngCore;
+
+    /// Tests that DeterministicRng gives the same stream for the same seed, however it's drawn,
+    /// and that the stream is pinned
+    #[test]
+    fn test_deterministic_rng() {
+        let mut whole = [0u8; 100];
+        DeterministicRng::from_seed([7u8; 32]).fill_bytes(&mut whole);
+
+        let mut rng = DeterministicRng::from_seed([7u8; 32]);
+        let mut chunked = [0u8; 100];
+        for chunk in chunked.chunks_mut(9) {
+            rng.fill_bytes(chunk);
+        }
+        assert_eq!(whole, chunked);
+        assert_ne!(whole[..32], whole[32..64]);
+
+        let mut other = [0u8; 100];
+        DeterministicRng::from_seed([8u8; 32]).fill_bytes(&mut other);
+        assert_ne!(whole, other);
+
+        // The first block is HMAC-SHA256([0x07; 32], "hpke test rng" || I2OSP(0, 8))
+        assert_eq!(
+            whole[..32],
+            hex!("88dfdedf3abfd8ca651804a64b3efd3cbf52fc81205a050199f991a8a7e6598e")
+        );
+    }
+
+    /// Tests that a replay draws exactly what was recorded, and then runs dry
+    #[test]
+    fn test_record_replay() {
+        let mut rng = RecordingRng::new(StdRng::from_entropy());
+        let (a, b) = (rng.next_u32(), rng.next_u64());
+        let mut buf = [0u8; 40];
+        rng.fill_bytes(&mut buf);
+        assert_eq!(rng.recording().len(), 4 + 8 + 40);
+
+        let mut replay = ReplayRng::new(rng.into_recording());
+        assert_eq!(replay.next_u32(), a);
+        assert_eq!(replay.next_u64(), b);
+        let mut replayed = [0u8; 40];
+        replay.fill_bytes(&mut replayed);
+        assert_eq!(replayed, buf);
+
+        assert_eq!(replay.remaining(), 0);
+        assert!(replay.try_fill_bytes(&mut [0u8; 1]).is_err());
+        assert!(replay.try_fill_bytes(&mut []).is_ok());
+    }
+
+    /// Tests that replaying a recorded setup reproduces the encapsulated key and ciphertext
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_replay_setup() {
+        use crate::{
+            aead::ChaCha20Poly1305,
+            kdf::HkdfSha256,
+            kem::{Kem as KemTrait, X25519HkdfSha256},
+            setup_sender, OpModeS, Serializable,
+        };
+
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = X25519HkdfSha256;
+
+        let (_, pk_recip) = Kem::gen_keypair(&mut DeterministicRng::seed_from_u64(1));
+
+        let mut rng = RecordingRng::new(StdRng::from_entropy());
+        let (encapped_key, mut ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"", &mut rng).unwrap();
+        let ciphertext = ctx.seal(b"msg", b"").unwrap();
+
+        let mut replay = ReplayRng::new(rng.recording());
+        let (replayed_key, mut replayed_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"", &mut replay).unwrap();
+        assert_eq!(encapped_key.to_bytes(), replayed_key.to_bytes());
+        assert_eq!(replay.remaining(), 0);
+
+        // Sealing again with the same key and nonce is the point here
+        #[cfg(feature = "nonce-reuse-check")]
+        replayed_ctx.forget_sealed_nonces();
+        assert_eq!(replayed_ctx.seal(b"msg", b"").unwrap(), ciphertext);
+    }
+}
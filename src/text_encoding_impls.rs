@@ -0,0 +1,287 @@
+//! This module defines hex and base64 encodings for public keys, encapsulated keys, and tags, for
+//! putting them in config files and on command lines. This is gated under the `text-encoding`
+//! feature.
+//!
+//! Each of these types gets `to_hex`, `from_hex`, `to_base64`, and `from_base64` methods, where
+//! base64 is standard and padded (RFC 4648 §4). `Display` writes lowercase hex, and `FromStr`
+//! reads hex in either case, so `value.to_string().parse()` round-trips. Private keys are left
+//! out on purpose, so they don't end up in logs by way of `Display`.
+
+use crate::{
+    aead::{Aead, AeadTag},
+    dhkex, kem, Deserializable, HpkeError, Serializable,
+};
+
+use core::{fmt, str::FromStr};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::string::String;
+
+use base64ct::Encoding;
+
+// Encodes the Serializable encoding of t as lowercase hex
+fn to_hex<T: Serializable>(t: &T) -> String {
+    hex::encode(t.to_bytes())
+}
+
+// Decodes hex, in either case, and parses the result as a T
+fn from_hex<T: Deserializable>(encoded: &str) -> Result<T, HpkeError> {
+    let bytes = hex::decode(encoded).map_err(|_| HpkeError::ValidationError)?;
+    T::from_bytes(&bytes)
+}
+
+// Encodes the Serializable encoding of t as standard, padded base64
+fn to_base64<T: Serializable>(t: &T) -> String {
+    base64ct::Base64::encode_string(&t.to_bytes())
+}
+
+// Decodes standard, padded base64, and parses the result as a T
+fn from_base64<T: Deserializable>(encoded: &str) -> Result<T, HpkeError> {
+    let bytes = base64ct::Base64::decode_vec(encoded).map_err(|_| HpkeError::ValidationError)?;
+    T::from_bytes(&bytes)
+}
+
+// Writes the Serializable encoding of t as lowercase hex, without allocating
+fn fmt_hex<T: Serializable>(t: &T, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    t.to_bytes().iter().try_for_each(|b| write!(f, "{:02x}", b))
+}
+
+impl<A: Aead> AeadTag<A> {
+    /// Encodes this tag as lowercase hex
+    pub fn to_hex(&self) -> String {
+        to_hex(self)
+    }
+
+    /// Decodes a tag from hex, in either case
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input isn't hex, and
+    /// `Err(HpkeError::IncorrectInputLength)` if it's the wrong length.
+    pub fn from_hex(encoded: &str) -> Result<Self, HpkeError> {
+        from_hex(encoded)
+    }
+
+    /// Encodes this tag as standard, padded base64 (RFC 4648 §4)
+    pub fn to_base64(&self) -> String {
+        to_base64(self)
+    }
+
+    /// Decodes a tag from standard, padded base64 (RFC 4648 §4)
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input isn't base64, and
+    /// `Err(HpkeError::IncorrectInputLength)` if it's the wrong length.
+    pub fn from_base64(encoded: &str) -> Result<Self, HpkeError> {
+        from_base64(encoded)
+    }
+}
+
+impl<A: Aead> fmt::Display for AeadTag<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_hex(self, f)
+    }
+}
+
+impl<A: Aead> FromStr for AeadTag<A> {
+    type Err = HpkeError;
+
+    fn from_str(s: &str) -> Result<Self, HpkeError> {
+        from_hex(s)
+    }
+}
+
+// Implements the text encodings over type t. This is identical to the above.
+macro_rules! impl_text_encoding_noparam {
+    ($t:ty) => {
+        impl $t {
+            /// Encodes this as lowercase hex
+            pub fn to_hex(&self) -> String {
+                to_hex(self)
+            }
+
+            /// Decodes this from hex, in either case
+            ///
+            /// Return Value
+            /// ============
+            /// Returns `Err(HpkeError::ValidationError)` if the input isn't hex. Otherwise,
+            /// returns whatever error `from_bytes` returns on the decoded bytes.
+            pub fn from_hex(encoded: &str) -> Result<Self, HpkeError> {
+                from_hex(encoded)
+            }
+
+            /// Encodes this as standard, padded base64 (RFC 4648 §4)
+            pub fn to_base64(&self) -> String {
+                to_base64(self)
+            }
+
+            /// Decodes this from standard, padded base64 (RFC 4648 §4)
+            ///
+            /// Return Value
+            /// ============
+            /// Returns `Err(HpkeError::ValidationError)` if the input isn't base64. Otherwise,
+            /// returns whatever error `from_bytes` returns on the decoded bytes.
+            pub fn from_base64(encoded: &str) -> Result<Self, HpkeError> {
+                from_base64(encoded)
+            }
+        }
+
+        /// Writes lowercase hex
+        impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt_hex(self, f)
+            }
+        }
+
+        /// Reads hex, in either case
+        impl FromStr for $t {
+            type Err = HpkeError;
+
+            fn from_str(s: &str) -> Result<Self, HpkeError> {
+                from_hex(s)
+            }
+        }
+    };
+}
+
+// Implement the text encodings for all PublicKey and EncappedKey types, as features permit
+
+#[cfg(feature = "x25519")]
+impl_text_encoding_noparam!(dhkex::x25519::PublicKey);
+#[cfg(feature = "x25519")]
+impl_text_encoding_noparam!(kem::DhEncappedKey<dhkex::X25519>);
+
+#[cfg(feature = "p256")]
+impl_text_encoding_noparam!(dhkex::ecdh_nistp::PublicKey);
+#[cfg(feature = "p256")]
+impl_text_encoding_noparam!(kem::DhEncappedKey<dhkex::DhP256>);
+
+#[cfg(feature = "k256")]
+impl_text_encoding_noparam!(dhkex::ecdh_k256::PublicKey);
+#[cfg(feature = "k256")]
+impl_text_encoding_noparam!(kem::DhEncappedKey<dhkex::DhK256>);
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        aead::{AeadTag, AesGcm128},
+        kem::Kem as KemTrait,
+        HpkeError, Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that hex, base64, and Display/FromStr round-trip for public keys and encapped keys,
+    /// and that bad input is rejected
+    macro_rules! test_text_encoding_roundtrip {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                type PublicKey = <Kem as KemTrait>::PublicKey;
+                type EncappedKey = <Kem as KemTrait>::EncappedKey;
+
+                let mut csprng = StdRng::from_entropy();
+                let (_, pk) = Kem::gen_keypair(&mut csprng);
+                let (_, encapped_key) = Kem::encap(&pk, None, &mut csprng).unwrap();
+                let pk_bytes = pk.to_bytes();
+
+                assert_eq!(pk.to_hex(), hex::encode(&pk_bytes));
+                assert_eq!(format!("{}", pk), pk.to_hex());
+                assert_eq!(
+                    PublicKey::from_hex(&pk.to_hex()).unwrap().to_bytes(),
+                    pk_bytes
+                );
+                assert_eq!(
+                    PublicKey::from_hex(&pk.to_hex().to_uppercase())
+                        .unwrap()
+                        .to_bytes(),
+                    pk_bytes
+                );
+                assert_eq!(
+                    PublicKey::from_base64(&pk.to_base64()).unwrap().to_bytes(),
+                    pk_bytes
+                );
+                assert_eq!(
+                    format!("{}", pk).parse::<PublicKey>().unwrap().to_bytes(),
+                    pk_bytes
+                );
+
+                let enc_bytes = encapped_key.to_bytes();
+                assert_eq!(
+                    EncappedKey::from_hex(&encapped_key.to_hex())
+                        .unwrap()
+                        .to_bytes(),
+                    enc_bytes
+                );
+                assert_eq!(
+                    EncappedKey::from_base64(&encapped_key.to_base64())
+                        .unwrap()
+                        .to_bytes(),
+                    enc_bytes
+                );
+                assert_eq!(
+                    format!("{}", encapped_key)
+                        .parse::<EncappedKey>()
+                        .unwrap()
+                        .to_bytes(),
+                    enc_bytes
+                );
+
+                // Not hex, not base64, and the wrong length
+                assert!(matches!(
+                    PublicKey::from_hex("zz"),
+                    Err(HpkeError::ValidationError)
+                ));
+                assert!(matches!(
+                    PublicKey::from_base64("not base64!"),
+                    Err(HpkeError::ValidationError)
+                ));
+                assert!(matches!(
+                    "00".parse::<PublicKey>(),
+                    Err(HpkeError::IncorrectInputLength(_, 1))
+                ));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_text_encoding_roundtrip!(test_text_encoding_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_text_encoding_roundtrip!(test_text_encoding_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_text_encoding_roundtrip!(test_text_encoding_k256, crate::kem::DhK256HkdfSha256);
+
+    /// Tests the text encodings of tags
+    #[test]
+    fn test_tag_text_encoding() {
+        let tag_bytes = [0xabu8; 16];
+        let tag = <AeadTag<AesGcm128> as crate::Deserializable>::from_bytes(&tag_bytes).unwrap();
+
+        assert_eq!(format!("{}", tag), "ab".repeat(16));
+        assert_eq!(tag.to_hex(), format!("{}", tag));
+        assert_eq!(tag.to_base64(), "q6urq6urq6urq6urq6urqw==");
+        assert_eq!(
+            AeadTag::<AesGcm128>::from_base64(&tag.to_base64())
+                .unwrap()
+                .to_bytes()
+                .as_slice(),
+            tag_bytes
+        );
+        assert_eq!(
+            "AB".repeat(16)
+                .parse::<AeadTag<AesGcm128>>()
+                .unwrap()
+                .to_bytes()
+                .as_slice(),
+            tag_bytes
+        );
+        assert!(matches!(
+            "ab".parse::<AeadTag<AesGcm128>>(),
+            Err(HpkeError::IncorrectInputLength(16, 1))
+        ));
+    }
+}
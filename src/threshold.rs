@@ -0,0 +1,455 @@
+//! Threshold decapsulation for DHKEM(K-256, HKDF-SHA256), where the recipient's private key is
+//! split among `n` holders, and any `t` of them can together decapsulate without anyone ever
+//! holding the whole key.
+//!
+//! The key is Shamir-shared over the K-256 scalar field. To decapsulate, each holder multiplies
+//! the encapsulated key by their share and hands back the resulting point, a `PartialDecap`. The
+//! combiner weights each partial result by its Lagrange coefficient and adds them up. This gives
+//! `skR * pkE`, the same Diffie-Hellman result `Kem::decap` computes, so the shared secret, and
+//! everything derived from it, is the same too. The private key itself only ever exists
+//! additively, as a weighted sum of shares the combiner never sees.
+//!
+//! Only the Base and Psk modes are supported. The Auth modes would also need a partial result for
+//! the sender's public key.
+//!
+//! Partial results carry no proof of correctness. A holder who returns garbage makes the combined
+//! shared secret wrong, which shows up as `HpkeError::OpenError` on the first `open`, but doesn't
+//! reveal which holder it was.
+//!
+//! ```
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     kdf::HkdfSha256,
+//!     kem::DhK256HkdfSha256,
+//!     threshold::{setup_receiver_threshold, split_private_key},
+//!     Kem, OpModeR, OpModeS,
+//! };
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk, pk) = DhK256HkdfSha256::gen_keypair(&mut csprng);
+//!
+//! // 2-of-3. The dealer hands out the shares, then throws sk away.
+//! let shares = split_private_key(&sk, 2, 3, &mut csprng).unwrap();
+//! drop(sk);
+//!
+//! let (encapped_key, mut sender_ctx) =
+//!     hpke::setup_sender::<ChaCha20Poly1305, HkdfSha256, DhK256HkdfSha256, _>(
+//!         &OpModeS::Base,
+//!         &pk,
+//!         b"info",
+//!         &mut csprng,
+//!     )
+//!     .unwrap();
+//! let ciphertext = sender_ctx.seal(b"hello", b"").unwrap();
+//!
+//! // Holders 1 and 3 respond
+//! let partials = [
+//!     shares[0].partial_decap(&encapped_key).unwrap(),
+//!     shares[2].partial_decap(&encapped_key).unwrap(),
+//! ];
+//! let mut receiver_ctx = setup_receiver_threshold::<ChaCha20Poly1305, HkdfSha256>(
+//!     &OpModeR::Base,
+//!     &pk,
+//!     &encapped_key,
+//!     &partials,
+//!     b"info",
+//! )
+//! .unwrap();
+//! assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), b"hello");
+//! ```
+
+use crate::{
+    aead::{Aead, AeadCtxR},
+    dhkex::ecdh_k256::{PrivateKey, PublicKey},
+    kdf::{extract_and_expand, Kdf as KdfTrait},
+    kem::{DhK256HkdfSha256, Kem as KemTrait, SharedSecret},
+    op_mode::OpModeR,
+    setup::derive_enc_ctx,
+    util::kem_suite_id,
+    HpkeError, Serializable, Vec,
+};
+
+use k256::{
+    elliptic_curve::{ff::PrimeField, sec1::ToEncodedPoint, AffineXCoordinate, Field, Group},
+    AffinePoint, FieldBytes, ProjectivePoint, Scalar,
+};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+type EncappedKey = <DhK256HkdfSha256 as KemTrait>::EncappedKey;
+
+/// One holder's share of a K-256 private key. Shares are secret. Any `threshold` of them can
+/// decapsulate together.
+///
+/// The encoding, `to_bytes`, is
+///
+/// ```text
+/// threshold (1) || index (1) || share (32)
+/// ```
+///
+/// where `share` is a big-endian scalar.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SecretShare {
+    threshold: u8,
+    index: u8,
+    scalar: Scalar,
+}
+
+impl SecretShare {
+    /// The number of holders needed to decapsulate
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// The index of this share. This is in the range `[1, n]`, and is different for every share
+    /// of the same key.
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Computes this holder's part of the decapsulation of `encapped_key`. This is safe to send
+    /// to the combiner. It reveals nothing about the share.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::DecapError)` if the result is the point at infinity. This only
+    /// happens if the share is 0.
+    pub fn partial_decap(&self, encapped_key: &EncappedKey) -> Result<PartialDecap, HpkeError> {
+        let point = encapped_key.0 .0.to_projective() * self.scalar;
+        if bool::from(point.is_identity()) {
+            return Err(HpkeError::DecapError);
+        }
+        Ok(PartialDecap {
+            threshold: self.threshold,
+            index: self.index,
+            point: point.to_affine(),
+        })
+    }
+
+    /// Encodes this share in the format described in `SecretShare`
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut out = Zeroizing::new(Vec::with_capacity(34));
+        out.push(self.threshold);
+        out.push(self.index);
+        out.extend_from_slice(&self.scalar.to_repr());
+        out
+    }
+
+    /// Decodes a share in the format described in `SecretShare`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input is the wrong length, the threshold
+    /// or index is 0, or the share isn't a nonzero scalar less than the group order.
+    pub fn from_bytes(encoded: &[u8]) -> Result<SecretShare, HpkeError> {
+        if encoded.len() != 34 || encoded[0] == 0 || encoded[1] == 0 {
+            return Err(HpkeError::ValidationError);
+        }
+        let scalar =
+            Option::<Scalar>::from(Scalar::from_repr(*FieldBytes::from_slice(&encoded[2..])))
+                .filter(|s| !bool::from(s.is_zero()))
+                .ok_or(HpkeError::ValidationError)?;
+        Ok(SecretShare {
+            threshold: encoded[0],
+            index: encoded[1],
+            scalar,
+        })
+    }
+}
+
+/// One holder's part of a decapsulation, made by `SecretShare::partial_decap`. This is public.
+///
+/// The encoding, `to_bytes`, is
+///
+/// ```text
+/// threshold (1) || index (1) || point (65)
+/// ```
+///
+/// where `point` is an uncompressed SEC1 point.
+#[derive(Clone)]
+pub struct PartialDecap {
+    threshold: u8,
+    index: u8,
+    point: AffinePoint,
+}
+
+impl PartialDecap {
+    /// The index of the share this was made with
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Encodes this in the format described in `PartialDecap`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(67);
+        out.push(self.threshold);
+        out.push(self.index);
+        out.extend_from_slice(self.point.to_encoded_point(false).as_bytes());
+        out
+    }
+
+    /// Decodes a partial decapsulation in the format described in `PartialDecap`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ValidationError)` if the input is the wrong length, the threshold
+    /// or index is 0, or the point isn't an uncompressed point on the curve other than the point
+    /// at infinity.
+    pub fn from_bytes(encoded: &[u8]) -> Result<PartialDecap, HpkeError> {
+        if encoded.len() != 67 || encoded[0] == 0 || encoded[1] == 0 || encoded[2] != 0x04 {
+            return Err(HpkeError::ValidationError);
+        }
+        let point = k256::PublicKey::from_sec1_bytes(&encoded[2..])
+            .map_err(|_| HpkeError::ValidationError)?;
+        Ok(PartialDecap {
+            threshold: encoded[0],
+            index: encoded[1],
+            point: *point.as_affine(),
+        })
+    }
+}
+
+/// Splits `sk` into `n` shares, any `t` of which can decapsulate together
+///
+/// Return Value
+/// ============
+/// Returns the shares, with indices `1` through `n`, on success. Returns
+/// `Err(HpkeError::ValidationError)` unless `1 <= t <= n <= 255`.
+pub fn split_private_key<R>(
+    sk: &PrivateKey,
+    t: u8,
+    n: u8,
+    csprng: &mut R,
+) -> Result<Vec<SecretShare>, HpkeError>
+where
+    R: CryptoRng + RngCore + ?Sized,
+{
+    if t == 0 || t > n {
+        return Err(HpkeError::ValidationError);
+    }
+
+    // The key is the constant term of a random polynomial of degree t-1. coeffs[i] is the
+    // coefficient of x^(i+1).
+    let secret = Zeroizing::new(*sk.0.to_nonzero_scalar());
+    let mut coeffs: Zeroizing<Vec<Scalar>> =
+        Zeroizing::new((1..t).map(|_| Scalar::random(&mut *csprng)).collect());
+
+    let shares = (1..=n)
+        .map(|x| {
+            // Horner's rule, highest coefficient first
+            let x_scalar = Scalar::from(x as u32);
+            let mut share = Scalar::zero();
+            for c in coeffs.iter().rev() {
+                share = share * x_scalar + c;
+            }
+            SecretShare {
+                threshold: t,
+                index: x,
+                scalar: share * x_scalar + *secret,
+            }
+        })
+        .collect();
+
+    coeffs.zeroize();
+    Ok(shares)
+}
+
+// RFC 9180 §4.1
+// def Decap(enc, skR):
+//   pkE = DeserializePublicKey(enc)
+//   dh = DH(skR, pkE)
+//
+//   enc = SerializePublicKey(pkE)
+//   pkRm = SerializePublicKey(pk(skR))
+//   kem_context = concat(enc, pkRm)
+//
+//   shared_secret = ExtractAndExpand(dh, kem_context)
+//   return shared_secret
+
+/// Combines the partial decapsulations of `encapped_key` into the KEM shared secret. This is the
+/// same value `Kem::decap` would return with the unsplit private key. If more than the threshold
+/// number of partials are given, the first ones are used.
+///
+/// Return Value
+/// ============
+/// Returns the shared secret on success. Returns `Err(HpkeError::ValidationError)` if there are
+/// fewer partials than the threshold, they don't all have the same threshold, or two of them have
+/// the same index. Returns `Err(HpkeError::DecapError)` if they combine to the point at infinity.
+/// If the partials are for a different key or encapsulated key, the result is a wrong shared
+/// secret.
+pub fn combine(
+    pk_recip: &PublicKey,
+    encapped_key: &EncappedKey,
+    partials: &[PartialDecap],
+) -> Result<SharedSecret<DhK256HkdfSha256>, HpkeError> {
+    let first = partials.first().ok_or(HpkeError::ValidationError)?;
+    let t = first.threshold as usize;
+    if partials.len() < t || partials.iter().any(|p| p.threshold != first.threshold) {
+        return Err(HpkeError::ValidationError);
+    }
+    let partials = &partials[..t];
+    for (i, p) in partials.iter().enumerate() {
+        if partials[..i].iter().any(|other| other.index == p.index) {
+            return Err(HpkeError::ValidationError);
+        }
+    }
+
+    // Lagrange interpolation at x = 0, in the exponent. The basis polynomial of partial i at 0 is
+    // the product over j != i of x_j / (x_j - x_i). The indices are distinct, so the denominator
+    // is never 0.
+    let mut dh = ProjectivePoint::IDENTITY;
+    for (i, pi) in partials.iter().enumerate() {
+        let xi = Scalar::from(pi.index as u32);
+        let mut num = Scalar::one();
+        let mut den = Scalar::one();
+        for (j, pj) in partials.iter().enumerate() {
+            if i != j {
+                let xj = Scalar::from(pj.index as u32);
+                num *= xj;
+                den *= xj - xi;
+            }
+        }
+        let basis = num * den.invert().unwrap();
+        dh += ProjectivePoint::from(pi.point) * basis;
+    }
+    if bool::from(dh.is_identity()) {
+        return Err(HpkeError::DecapError);
+    }
+
+    // The DH result is the x-coordinate of the point
+    let mut dh_bytes = dh.to_affine().x();
+
+    let (kem_context_buf, kem_context_size) =
+        concat_with_known_maxlen!(130, &encapped_key.to_bytes(), &pk_recip.to_bytes());
+    let kem_context = &kem_context_buf[..kem_context_size];
+
+    let mut shared_secret = <SharedSecret<DhK256HkdfSha256> as Default>::default();
+    extract_and_expand::<crate::kdf::HkdfSha256>(
+        &dh_bytes,
+        &kem_suite_id::<DhK256HkdfSha256>(),
+        kem_context,
+        &mut shared_secret.0,
+    )
+    .expect("shared secret is way too big");
+    dh_bytes.zeroize();
+    Ok(shared_secret)
+}
+
+/// Initiates a decryption context from the partial decapsulations of `encapped_key`, made by at
+/// least the threshold number of holders of shares of `pk_recip`'s private key. This is
+/// `setup_receiver`, with `combine` in place of `Kem::decap`.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. Returns `Err(HpkeError::ValidationError)` if `mode`
+/// is an Auth mode, and otherwise whatever error `combine` returns.
+pub fn setup_receiver_threshold<A, Kdf>(
+    mode: &OpModeR<DhK256HkdfSha256>,
+    pk_recip: &PublicKey,
+    encapped_key: &EncappedKey,
+    partials: &[PartialDecap],
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, DhK256HkdfSha256>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+{
+    if mode.get_pk_sender_id().is_some() {
+        return Err(HpkeError::ValidationError);
+    }
+    let shared_secret = combine(pk_recip, encapped_key, partials)?;
+    let enc_ctx = derive_enc_ctx::<_, _, DhK256HkdfSha256, _>(mode, shared_secret, &[info]);
+    Ok(enc_ctx.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{combine, split_private_key, PartialDecap, SecretShare};
+    use crate::{kem::DhK256HkdfSha256, HpkeError, Kem as KemTrait};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that every t-subset of the holders decapsulates to the same shared secret as
+    /// `Kem::decap`, that fewer don't, and that shares and partials survive encoding
+    #[test]
+    fn test_threshold_decap() {
+        type Kem = DhK256HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, pk) = Kem::gen_keypair(&mut csprng);
+        let (expected, encapped_key) = Kem::encap(&pk, None, &mut csprng).unwrap();
+
+        let shares = split_private_key(&sk, 3, 5, &mut csprng).unwrap();
+        assert_eq!(shares.len(), 5);
+        let partials: crate::Vec<PartialDecap> = shares
+            .iter()
+            .map(|s| {
+                let s = SecretShare::from_bytes(&s.to_bytes()).unwrap();
+                s.partial_decap(&encapped_key).unwrap()
+            })
+            .collect();
+
+        for a in 0..5 {
+            for b in (a + 1)..5 {
+                for c in (b + 1)..5 {
+                    let subset = [
+                        partials[c].clone(),
+                        PartialDecap::from_bytes(&partials[a].to_bytes()).unwrap(),
+                        partials[b].clone(),
+                    ];
+                    let shared_secret = combine(&pk, &encapped_key, &subset).unwrap();
+                    assert_eq!(shared_secret.as_bytes(), expected.as_bytes());
+                }
+            }
+        }
+
+        assert!(matches!(
+            combine(&pk, &encapped_key, &partials[..2]),
+            Err(HpkeError::ValidationError)
+        ));
+        let duplicated = [
+            partials[0].clone(),
+            partials[1].clone(),
+            partials[0].clone(),
+        ];
+        assert!(matches!(
+            combine(&pk, &encapped_key, &duplicated),
+            Err(HpkeError::ValidationError)
+        ));
+
+        // A partial for a different encapsulated key gives a different shared secret
+        let (_, other_encapped_key) = Kem::encap(&pk, None, &mut csprng).unwrap();
+        let mixed = [
+            partials[0].clone(),
+            partials[1].clone(),
+            shares[2].partial_decap(&other_encapped_key).unwrap(),
+        ];
+        assert_ne!(
+            combine(&pk, &encapped_key, &mixed).unwrap().as_bytes(),
+            expected.as_bytes()
+        );
+    }
+
+    /// Tests that bad parameters and encodings are rejected
+    #[test]
+    fn test_threshold_bad_params() {
+        type Kem = DhK256HkdfSha256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, _) = Kem::gen_keypair(&mut csprng);
+        assert!(split_private_key(&sk, 0, 3, &mut csprng).is_err());
+        assert!(split_private_key(&sk, 4, 3, &mut csprng).is_err());
+
+        assert!(SecretShare::from_bytes(&[1u8; 33]).is_err());
+        assert!(SecretShare::from_bytes(&[0u8; 34]).is_err());
+        let mut zero_share = [0u8; 34];
+        zero_share[..2].copy_from_slice(&[1, 1]);
+        assert!(SecretShare::from_bytes(&zero_share).is_err());
+        assert!(SecretShare::from_bytes(&[0xffu8; 34]).is_err());
+
+        let mut bad_point = [0u8; 67];
+        bad_point[..3].copy_from_slice(&[1, 1, 4]);
+        assert!(PartialDecap::from_bytes(&bad_point).is_err());
+    }
+}
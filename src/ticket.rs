@@ -0,0 +1,476 @@
+//! Session resumption, so a client that already has an HPKE context with a server can set up a
+//! new one later without another encapsulation. This saves the KEM operations, and the
+//! encapsulated key on the wire, on every reconnect.
+//!
+//! After the first handshake, both sides derive a `ResumptionSecret` from their contexts. The
+//! server seals its copy, and whatever state it wants back, into a ticket under a `TicketKey` that
+//! only it holds, and hands the ticket to the client. The client stores the ticket and its own
+//! `ResumptionSecret`. To resume, the client calls `resume_sender`, and sends the ticket and the
+//! resumption nonce it gets back. The server calls `redeem_ticket`, then `resume_receiver`.
+//!
+//! ```
+//! # #[cfg(feature = "x25519")]
+//! # {
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! use hpke::{
+//!     aead::ChaCha20Poly1305,
+//!     kdf::HkdfSha256,
+//!     kem::X25519HkdfSha256,
+//!     ticket::{self, ResumptionSecret, TicketKey},
+//!     Kem, OpModeR, OpModeS,
+//! };
+//!
+//! type A = ChaCha20Poly1305;
+//! type Kdf = HkdfSha256;
+//! type K = X25519HkdfSha256;
+//!
+//! let mut csprng = StdRng::from_entropy();
+//! let (sk_server, pk_server) = K::gen_keypair(&mut csprng);
+//! let ticket_key = TicketKey::generate(&mut csprng);
+//!
+//! // The first connection is an ordinary HPKE setup
+//! let (encapped_key, client_ctx) =
+//!     hpke::setup_sender::<A, Kdf, K, _>(&OpModeS::Base, &pk_server, b"v1", &mut csprng)
+//!         .unwrap();
+//! let server_ctx =
+//!     hpke::setup_receiver::<A, Kdf, K>(&OpModeR::Base, &sk_server, &encapped_key, b"v1")
+//!         .unwrap();
+//! let client_secret = ResumptionSecret::from_sender_ctx(&client_ctx);
+//! let tkt = ticket::issue_ticket(
+//!     &ticket_key,
+//!     &ResumptionSecret::from_receiver_ctx(&server_ctx),
+//!     b"device 7",
+//!     u64::MAX,
+//!     &mut csprng,
+//! );
+//!
+//! // Later, the client reconnects with the ticket
+//! let (nonce, mut client_ctx) =
+//!     ticket::resume_sender::<A, Kdf, K, _>(&client_secret, b"v1", &mut csprng);
+//! let ciphertext = client_ctx.seal(b"hello again", b"").unwrap();
+//!
+//! let (server_secret, state) = ticket::redeem_ticket(&ticket_key, &tkt, 1_700_000_000).unwrap();
+//! assert_eq!(state, b"device 7");
+//! let mut server_ctx =
+//!     ticket::resume_receiver::<A, Kdf, K>(&server_secret, &nonce, b"v1").unwrap();
+//! assert_eq!(server_ctx.open(&ciphertext, b"").unwrap(), b"hello again");
+//! # }
+//! ```
+//!
+//! A ticket is
+//!
+//! ```text
+//! version (1) || nonce (12) || ChaCha20Poly1305(ticket_key, nonce, aad = "HPKE ticket" || version,
+//!                                               expires_at (8) || resumption_secret (32) || state)
+//! ```
+//!
+//! and a resumed context is the result of the RFC 9180 key schedule in PSK mode, with
+//!
+//! ```text
+//! psk           = resumption_secret
+//! psk_id        = "HPKE resumption"
+//! shared_secret = LabeledExpand(LabeledExtract(resumption_nonce, "resume_prk", resumption_secret),
+//!                               "shared_secret", "", Nsecret)
+//! ```
+//!
+//! DANGER
+//! ======
+//! A resumed context has no fresh DH, so it has no forward secrecy beyond the first context's:
+//! anyone who gets the ticket key and a ticket, or the client's stored secret, can read every
+//! context resumed from it. Rotate ticket keys, and give tickets short lifetimes.
+//!
+//! An attacker can also replay a client's resumption nonce and first messages to the server, which
+//! will open them again, like TLS 1.3 0-RTT data. Either make those messages safe to process twice,
+//! or have the server remember the resumption nonces it has accepted until the ticket expires.
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS, ChaCha20Poly1305},
+    kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
+    kem::{Kem as KemTrait, SharedSecret},
+    op_mode::{OpModeS, PskBundle},
+    setup::derive_enc_ctx,
+    util::full_suite_id,
+    HpkeError, Vec,
+};
+
+use aead::{AeadInPlace, NewAead};
+use byteorder::{BigEndian, ByteOrder};
+use generic_array::GenericArray;
+use rand_core::{CryptoRng, RngCore};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::Zeroizing;
+
+/// The length of a `ResumptionSecret`
+pub const RESUMPTION_SECRET_LEN: usize = 32;
+
+/// The length of the resumption nonce that `resume_sender` makes and `resume_receiver` takes
+pub const RESUMPTION_NONCE_LEN: usize = 32;
+
+/// The version byte that `issue_ticket` writes, and the only one `redeem_ticket` accepts
+pub const TICKET_VERSION: u8 = 1;
+
+// The exporter context the resumption secret is exported under
+const RESUMPTION_EXPORTER_CTX: &[u8] = b"HPKE resumption";
+// The PSK ID of every resumed context
+const RESUMPTION_PSK_ID: &[u8] = b"HPKE resumption";
+// The ticket AAD, before the version byte
+const TICKET_AAD_PREFIX: &[u8] = b"HPKE ticket";
+
+const TICKET_NONCE_LEN: usize = 12;
+const TICKET_TAG_LEN: usize = 16;
+// version || nonce
+const TICKET_HEADER_LEN: usize = 1 + TICKET_NONCE_LEN;
+
+type TicketAead = ChaCha20Poly1305;
+
+/// A secret exported from an HPKE context, from which contexts can later be resumed. Both sides
+/// of a context derive the same one. It's zeroized on drop.
+#[derive(Clone)]
+pub struct ResumptionSecret(Zeroizing<[u8; RESUMPTION_SECRET_LEN]>);
+
+impl ResumptionSecret {
+    /// Exports the resumption secret from a sender's context
+    pub fn from_sender_ctx<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(
+        ctx: &AeadCtxS<A, Kdf, Kem>,
+    ) -> ResumptionSecret {
+        let mut secret = Zeroizing::new([0u8; RESUMPTION_SECRET_LEN]);
+        ctx.export(RESUMPTION_EXPORTER_CTX, secret.as_mut())
+            .expect("resumption secret is short enough to export");
+        ResumptionSecret(secret)
+    }
+
+    /// Exports the resumption secret from a receiver's context. This equals the sender's.
+    pub fn from_receiver_ctx<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(
+        ctx: &AeadCtxR<A, Kdf, Kem>,
+    ) -> ResumptionSecret {
+        let mut secret = Zeroizing::new([0u8; RESUMPTION_SECRET_LEN]);
+        ctx.export(RESUMPTION_EXPORTER_CTX, secret.as_mut())
+            .expect("resumption secret is short enough to export");
+        ResumptionSecret(secret)
+    }
+
+    /// Returns the secret's bytes, for the client to store
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    /// Reads a secret stored with `as_bytes`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::IncorrectInputLength)` if `bytes` isn't
+    /// `RESUMPTION_SECRET_LEN` bytes long
+    pub fn from_bytes(bytes: &[u8]) -> Result<ResumptionSecret, HpkeError> {
+        let mut secret = Zeroizing::new([0u8; RESUMPTION_SECRET_LEN]);
+        if bytes.len() != secret.len() {
+            return Err(HpkeError::IncorrectInputLength(secret.len(), bytes.len()));
+        }
+        secret.copy_from_slice(bytes);
+        Ok(ResumptionSecret(secret))
+    }
+}
+
+impl ConstantTimeEq for ResumptionSecret {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.as_bytes().ct_eq(other.as_bytes())
+    }
+}
+
+/// The server's key for sealing and opening tickets. Only the server holds it. It's zeroized on
+/// drop.
+#[derive(Clone)]
+pub struct TicketKey(Zeroizing<[u8; 32]>);
+
+impl TicketKey {
+    /// Makes a random ticket key
+    pub fn generate<R: CryptoRng + RngCore + ?Sized>(csprng: &mut R) -> TicketKey {
+        let mut key = Zeroizing::new([0u8; 32]);
+        csprng.fill_bytes(key.as_mut());
+        TicketKey(key)
+    }
+
+    /// Makes a ticket key from 32 bytes, e.g., one that's shared by a fleet of servers
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::IncorrectInputLength)` if `bytes` isn't 32 bytes long
+    pub fn from_bytes(bytes: &[u8]) -> Result<TicketKey, HpkeError> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        if bytes.len() != key.len() {
+            return Err(HpkeError::IncorrectInputLength(key.len(), bytes.len()));
+        }
+        key.copy_from_slice(bytes);
+        Ok(TicketKey(key))
+    }
+
+    /// Returns the key's bytes, for storing it or sharing it between servers
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+/// Seals `secret` and the server's `state` into a ticket, which the client sends back to resume.
+/// The ticket stops being accepted once the Unix time in seconds passes `expires_at`. `state`
+/// isn't visible to the client, but its length is.
+pub fn issue_ticket<R>(
+    key: &TicketKey,
+    secret: &ResumptionSecret,
+    state: &[u8],
+    expires_at: u64,
+    csprng: &mut R,
+) -> Vec<u8>
+where
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let plaintext_len = 8 + RESUMPTION_SECRET_LEN + state.len();
+    let mut ticket = Vec::with_capacity(TICKET_HEADER_LEN + plaintext_len + TICKET_TAG_LEN);
+    ticket.push(TICKET_VERSION);
+    ticket.resize(TICKET_HEADER_LEN, 0);
+    csprng.fill_bytes(&mut ticket[1..TICKET_HEADER_LEN]);
+
+    // expires_at || resumption_secret || state
+    let mut expires_at_buf = [0u8; 8];
+    BigEndian::write_u64(&mut expires_at_buf, expires_at);
+    ticket.extend_from_slice(&expires_at_buf);
+    ticket.extend_from_slice(secret.as_bytes());
+    ticket.extend_from_slice(state);
+
+    let cipher = <TicketAead as Aead>::AeadImpl::new(GenericArray::from_slice(key.as_bytes()));
+    let (header, plaintext) = ticket.split_at_mut(TICKET_HEADER_LEN);
+    let tag = cipher
+        .encrypt_in_place_detached(
+            GenericArray::from_slice(&header[1..]),
+            &ticket_aad(),
+            plaintext,
+        )
+        .expect("ticket is short enough to seal");
+    ticket.extend_from_slice(&tag);
+
+    ticket
+}
+
+/// Opens a ticket made by `issue_ticket`, and returns the resumption secret and the server's
+/// state. `now` is the current Unix time in seconds.
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if the ticket is malformed, is of another version, or
+/// expired before `now`. Returns `Err(HpkeError::OpenError)` if it wasn't made under `key`, or was
+/// modified.
+pub fn redeem_ticket(
+    key: &TicketKey,
+    ticket: &[u8],
+    now: u64,
+) -> Result<(ResumptionSecret, Vec<u8>), HpkeError> {
+    let min_len = TICKET_HEADER_LEN + 8 + RESUMPTION_SECRET_LEN + TICKET_TAG_LEN;
+    if ticket.len() < min_len || ticket[0] != TICKET_VERSION {
+        return Err(HpkeError::ValidationError);
+    }
+
+    let (header, rest) = ticket.split_at(TICKET_HEADER_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TICKET_TAG_LEN);
+    let mut plaintext = Zeroizing::new(ciphertext.to_vec());
+    let cipher = <TicketAead as Aead>::AeadImpl::new(GenericArray::from_slice(key.as_bytes()));
+    cipher
+        .decrypt_in_place_detached(
+            GenericArray::from_slice(&header[1..]),
+            &ticket_aad(),
+            &mut plaintext,
+            GenericArray::from_slice(tag),
+        )
+        .map_err(|_| HpkeError::OpenError)?;
+
+    // expires_at || resumption_secret || state
+    if BigEndian::read_u64(&plaintext[..8]) < now {
+        return Err(HpkeError::ValidationError);
+    }
+    let secret = ResumptionSecret::from_bytes(&plaintext[8..8 + RESUMPTION_SECRET_LEN])?;
+    let state = plaintext[8 + RESUMPTION_SECRET_LEN..].to_vec();
+
+    Ok((secret, state))
+}
+
+/// Starts a resumed context as the client. Send the returned resumption nonce to the server along
+/// with the ticket. Every call gives a new nonce, and so a new context. `info` plays the same part
+/// as in `setup_sender`, and must match the server's.
+pub fn resume_sender<A, Kdf, Kem, R>(
+    secret: &ResumptionSecret,
+    info: &[u8],
+    csprng: &mut R,
+) -> ([u8; RESUMPTION_NONCE_LEN], AeadCtxS<A, Kdf, Kem>)
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore + ?Sized,
+{
+    let mut nonce = [0u8; RESUMPTION_NONCE_LEN];
+    csprng.fill_bytes(&mut nonce);
+    let ctx = resumed_ctx::<A, Kdf, Kem>(secret, &nonce, info);
+    (nonce, ctx.into())
+}
+
+/// Starts a resumed context as the server, from the secret in the client's ticket and the
+/// client's resumption nonce. See the module docs about replayed nonces.
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::IncorrectInputLength)` if `nonce` isn't `RESUMPTION_NONCE_LEN` bytes
+/// long
+pub fn resume_receiver<A, Kdf, Kem>(
+    secret: &ResumptionSecret,
+    nonce: &[u8],
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if nonce.len() != RESUMPTION_NONCE_LEN {
+        return Err(HpkeError::IncorrectInputLength(
+            RESUMPTION_NONCE_LEN,
+            nonce.len(),
+        ));
+    }
+    Ok(resumed_ctx::<A, Kdf, Kem>(secret, nonce, info).into())
+}
+
+// Returns "HPKE ticket" || version
+fn ticket_aad() -> [u8; TICKET_AAD_PREFIX.len() + 1] {
+    let mut aad = [0u8; TICKET_AAD_PREFIX.len() + 1];
+    aad[..TICKET_AAD_PREFIX.len()].copy_from_slice(TICKET_AAD_PREFIX);
+    aad[TICKET_AAD_PREFIX.len()] = TICKET_VERSION;
+    aad
+}
+
+// Runs the key schedule with the resumption secret as the PSK, and a shared secret derived from
+// the resumption secret and nonce, as described in the module docs
+fn resumed_ctx<A, Kdf, Kem>(
+    secret: &ResumptionSecret,
+    nonce: &[u8],
+    info: &[u8],
+) -> crate::aead::AeadCtx<A, Kdf, Kem>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let suite_id = full_suite_id::<A, Kdf, Kem>();
+
+    let (_, prk) = labeled_extract::<Kdf>(nonce, &suite_id, b"resume_prk", secret.as_bytes());
+    let mut shared_secret = <SharedSecret<Kem> as Default>::default();
+    prk.labeled_expand(&suite_id, b"shared_secret", b"", &mut shared_secret.0)
+        .expect("shared secret is way too big");
+
+    let mode = OpModeS::<Kem>::Psk(PskBundle {
+        psk: secret.as_bytes(),
+        psk_id: RESUMPTION_PSK_ID,
+    });
+    derive_enc_ctx::<A, Kdf, Kem, _>(&mode, shared_secret, &[info])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        issue_ticket, redeem_ticket, resume_receiver, resume_sender, ResumptionSecret, TicketKey,
+    };
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait, setup_receiver,
+        setup_sender, HpkeError, OpModeR, OpModeS,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use subtle::ConstantTimeEq;
+
+    macro_rules! test_ticket_resumption {
+        ($test_name:ident, $kem:ty) => {
+            /// Tests a full resumption, that each resumption gets its own context, and that bad
+            /// tickets and nonces are rejected
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let ticket_key = TicketKey::generate(&mut csprng);
+
+                let (encapped_key, client_ctx) =
+                    setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+                        .unwrap();
+                let server_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    b"info",
+                )
+                .unwrap();
+                let client_secret = ResumptionSecret::from_sender_ctx(&client_ctx);
+                let server_secret = ResumptionSecret::from_receiver_ctx(&server_ctx);
+                assert!(bool::from(client_secret.ct_eq(&server_secret)));
+
+                let ticket = issue_ticket(&ticket_key, &server_secret, b"state", 100, &mut csprng);
+
+                // Resume twice. The contexts must differ.
+                let (nonce1, mut ctx1) =
+                    resume_sender::<A, Kdf, Kem, _>(&client_secret, b"info", &mut csprng);
+                let (nonce2, mut ctx2) =
+                    resume_sender::<A, Kdf, Kem, _>(&client_secret, b"info", &mut csprng);
+                let ct1 = ctx1.seal(b"msg", b"").unwrap();
+                let ct2 = ctx2.seal(b"msg", b"").unwrap();
+                assert_ne!(ct1, ct2);
+
+                let (secret, state) = redeem_ticket(&ticket_key, &ticket, 100).unwrap();
+                assert_eq!(state, b"state");
+                let mut server1 =
+                    resume_receiver::<A, Kdf, Kem>(&secret, &nonce1, b"info").unwrap();
+                assert_eq!(server1.open(&ct1, b"").unwrap(), b"msg");
+                let mut server2 =
+                    resume_receiver::<A, Kdf, Kem>(&secret, &nonce2, b"info").unwrap();
+                assert!(server2.open(&ct1, b"").is_err());
+
+                // A resumed context isn't the original one
+                let mut original = server_ctx;
+                assert!(original.open(&ct1, b"").is_err());
+
+                // Expired, wrong key, tampered, and truncated tickets
+                assert_eq!(
+                    redeem_ticket(&ticket_key, &ticket, 101).err(),
+                    Some(HpkeError::ValidationError)
+                );
+                let other_key = TicketKey::generate(&mut csprng);
+                assert_eq!(
+                    redeem_ticket(&other_key, &ticket, 0).err(),
+                    Some(HpkeError::OpenError)
+                );
+                let mut tampered = ticket.clone();
+                *tampered.last_mut().unwrap() ^= 1;
+                assert_eq!(
+                    redeem_ticket(&ticket_key, &tampered, 0).err(),
+                    Some(HpkeError::OpenError)
+                );
+                assert_eq!(
+                    redeem_ticket(&ticket_key, &ticket[..20], 0).err(),
+                    Some(HpkeError::ValidationError)
+                );
+
+                assert_eq!(
+                    resume_receiver::<A, Kdf, Kem>(&secret, &nonce1[..31], b"info").err(),
+                    Some(HpkeError::IncorrectInputLength(32, 31))
+                );
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519")]
+    test_ticket_resumption!(test_ticket_resumption_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_ticket_resumption!(
+        test_ticket_resumption_nistp256,
+        crate::kem::DhP256HkdfSha256
+    );
+    #[cfg(feature = "k256")]
+    test_ticket_resumption!(test_ticket_resumption_k256, crate::kem::DhK256HkdfSha256);
+}
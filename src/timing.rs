@@ -0,0 +1,421 @@
+//! Statistical timing-leakage tests in the style of dudect ("Dude, is my code constant time?",
+//! Reparaz, Balasch, and Verbauwhede, 2017), for gathering evidence that secret-dependent
+//! operations take the same time regardless of the secret.
+//!
+//! Each test splits its inputs into two classes, e.g., one fixed private key and many random
+//! ones, runs the operation on both in a random interleaving, and compares the two timing
+//! distributions with Welch's t-test. If the operation's timing doesn't depend on its input, the
+//! t statistic stays small no matter how many samples are taken. If it does, |t| grows with the
+//! number of samples. As in dudect, the test is also run on the measurements below several
+//! percentiles, since a leak can hide under the long tail that interrupts and cache misses add.
+//!
+//! These are statistical tests on a real machine. They can't prove the absence of a leak, only
+//! fail to find one at the given sample count. Run them on an otherwise idle machine, in release
+//! mode, with as many samples as you can afford. Millions is typical.
+//!
+//! ```no_run
+//! # #[cfg(feature = "k256")] {
+//! use hpke::timing::{check_k256_dh, Verdict};
+//! use rand::{rngs::StdRng, SeedableRng};
+//!
+//! let report = check_k256_dh(1_000_000, &mut StdRng::from_entropy());
+//! println!("max |t| = {:.2}", report.max_t);
+//! assert_ne!(report.verdict(), Verdict::DefiniteLeak);
+//! # }
+//! ```
+
+use crate::{
+    aead::{Aead, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    setup_receiver, setup_sender, Deserializable, OpModeR, OpModeS, Serializable, Vec,
+};
+
+use core::hint::black_box;
+use rand_core::{CryptoRng, RngCore};
+use std::time::Instant;
+
+#[cfg(feature = "k256")]
+use crate::dhkex::{DhK256, DhKeyExchange};
+
+/// dudect's threshold for |t| above which there's probably a leak. Below it, the test found no
+/// evidence of one.
+pub const POSSIBLE_LEAK_T: f64 = 4.5;
+
+/// dudect's threshold for |t| above which there's definitely a leak
+pub const DEFINITE_LEAK_T: f64 = 10.0;
+
+// The number of cropped tests, besides the uncropped one. Test i only keeps measurements below
+// the 1 - 0.5^(10 * (i + 1) / CROPS) percentile, as in dudect.
+const CROPS: usize = 20;
+
+/// Which of the two input classes a measurement belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    /// The first class. By convention, this is the fixed input.
+    Fixed,
+    /// The second class. By convention, this is the random input.
+    Random,
+}
+
+/// What a leakage test concluded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// |t| stayed under `POSSIBLE_LEAK_T`
+    NoLeakDetected,
+    /// |t| was at least `POSSIBLE_LEAK_T` but under `DEFINITE_LEAK_T`. Rerun with more samples.
+    PossibleLeak,
+    /// |t| was at least `DEFINITE_LEAK_T`
+    DefiniteLeak,
+}
+
+/// The result of a leakage test
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimingReport {
+    /// The number of measurements taken, across both classes
+    pub samples: usize,
+    /// The largest |t| over the uncropped and cropped tests
+    pub max_t: f64,
+    /// The percentile the measurements were cropped at in the test that gave `max_t`, or `None`
+    /// if it was the uncropped test
+    pub crop_percentile: Option<f64>,
+}
+
+impl TimingReport {
+    /// Classifies `max_t` against `POSSIBLE_LEAK_T` and `DEFINITE_LEAK_T`
+    pub fn verdict(&self) -> Verdict {
+        if self.max_t >= DEFINITE_LEAK_T {
+            Verdict::DefiniteLeak
+        } else if self.max_t >= POSSIBLE_LEAK_T {
+            Verdict::PossibleLeak
+        } else {
+            Verdict::NoLeakDetected
+        }
+    }
+}
+
+// Welford's online mean and variance, one per class, and Welch's t statistic between them
+#[derive(Clone, Copy, Default)]
+struct WelchT {
+    n: [f64; 2],
+    mean: [f64; 2],
+    m2: [f64; 2],
+}
+
+impl WelchT {
+    fn push(&mut self, class: Class, x: f64) {
+        let i = class as usize;
+        self.n[i] += 1.0;
+        let delta = x - self.mean[i];
+        self.mean[i] += delta / self.n[i];
+        self.m2[i] += delta * (x - self.mean[i]);
+    }
+
+    // Returns |t|, or 0 if either class has fewer than 2 measurements
+    fn t(&self) -> f64 {
+        if self.n[0] < 2.0 || self.n[1] < 2.0 {
+            return 0.0;
+        }
+        let var0 = self.m2[0] / (self.n[0] - 1.0);
+        let var1 = self.m2[1] / (self.n[1] - 1.0);
+        let denom = (var0 / self.n[0] + var1 / self.n[1]).sqrt();
+        if denom == 0.0 {
+            return 0.0;
+        }
+        ((self.mean[0] - self.mean[1]) / denom).abs()
+    }
+}
+
+// Runs Welch's t-test on the measurements, uncropped and cropped at each of the CROPS percentiles
+fn analyze(measurements: &[(Class, u64)]) -> TimingReport {
+    let mut sorted: Vec<u64> = measurements.iter().map(|&(_, t)| t).collect();
+    sorted.sort_unstable();
+
+    let mut percentiles = [0f64; CROPS];
+    let mut thresholds = [0u64; CROPS];
+    for i in 0..CROPS {
+        let p = 1.0 - 0.5f64.powf(10.0 * (i + 1) as f64 / CROPS as f64);
+        percentiles[i] = p;
+        thresholds[i] = sorted
+            .get((p * sorted.len() as f64) as usize)
+            .copied()
+            .unwrap_or(u64::MAX);
+    }
+
+    let mut uncropped = WelchT::default();
+    let mut cropped = [WelchT::default(); CROPS];
+    for &(class, t) in measurements {
+        uncropped.push(class, t as f64);
+        for (test, &threshold) in cropped.iter_mut().zip(thresholds.iter()) {
+            if t < threshold {
+                test.push(class, t as f64);
+            }
+        }
+    }
+
+    let mut report = TimingReport {
+        samples: measurements.len(),
+        max_t: uncropped.t(),
+        crop_percentile: None,
+    };
+    for (test, &p) in cropped.iter().zip(percentiles.iter()) {
+        let t = test.t();
+        if t > report.max_t {
+            report.max_t = t;
+            report.crop_percentile = Some(p);
+        }
+    }
+    report
+}
+
+/// Runs a dudect-style leakage test on `op`. `make_input` is called `samples` times, each with a
+/// uniformly random class, to make the inputs ahead of time. Then `op` is run on each input in
+/// that order, and only `op` is timed. `op` gets its input mutably so that it can, e.g., decrypt
+/// in place.
+pub fn run_leakage_test<T, R, G, F>(
+    samples: usize,
+    csprng: &mut R,
+    mut make_input: G,
+    mut op: F,
+) -> TimingReport
+where
+    R: CryptoRng + RngCore,
+    G: FnMut(Class, &mut R) -> T,
+    F: FnMut(&mut T),
+{
+    let mut inputs: Vec<(Class, T)> = (0..samples)
+        .map(|_| {
+            let class = if csprng.next_u32() & 1 == 0 {
+                Class::Fixed
+            } else {
+                Class::Random
+            };
+            (class, make_input(class, csprng))
+        })
+        .collect();
+
+    let measurements: Vec<(Class, u64)> = inputs
+        .iter_mut()
+        .map(|(class, input)| {
+            let start = Instant::now();
+            op(black_box(input));
+            let elapsed = start.elapsed();
+            (*class, elapsed.as_nanos() as u64)
+        })
+        .collect();
+
+    analyze(&measurements)
+}
+
+/// Tests whether parsing a K-256 private key takes time that depends on the key. The fixed class
+/// parses one fixed key, and the random class parses uniformly random 32-byte strings, which are
+/// valid keys with overwhelming probability.
+#[cfg(feature = "k256")]
+pub fn check_k256_private_key_parsing<R: CryptoRng + RngCore>(
+    samples: usize,
+    csprng: &mut R,
+) -> TimingReport {
+    let fixed = random_k256_sk_bytes(csprng);
+    run_leakage_test(
+        samples,
+        csprng,
+        |class, rng| match class {
+            Class::Fixed => fixed,
+            Class::Random => random_k256_sk_bytes(rng),
+        },
+        |bytes| {
+            let _ = black_box(<DhK256 as DhKeyExchange>::PrivateKey::from_bytes(bytes));
+        },
+    )
+}
+
+/// Tests whether K-256 Diffie-Hellman takes time that depends on the private key. Both classes
+/// use the same public key. The fixed class uses one fixed private key, and the random class uses
+/// fresh random ones. Decapsulation's only secret-dependent step is this one.
+#[cfg(feature = "k256")]
+pub fn check_k256_dh<R: CryptoRng + RngCore>(samples: usize, csprng: &mut R) -> TimingReport {
+    type PrivateKey = <DhK256 as DhKeyExchange>::PrivateKey;
+
+    let random_sk = |rng: &mut R| loop {
+        if let Ok(sk) = PrivateKey::from_bytes(&random_k256_sk_bytes(rng)) {
+            break sk;
+        }
+    };
+    let fixed_sk = random_sk(csprng);
+    let pk = DhK256::sk_to_pk(&random_sk(csprng));
+    run_leakage_test(
+        samples,
+        csprng,
+        |class, rng| match class {
+            Class::Fixed => fixed_sk.clone(),
+            Class::Random => random_sk(rng),
+        },
+        |sk| {
+            let _ = black_box(DhK256::dh(sk, &pk));
+        },
+    )
+}
+
+#[cfg(feature = "k256")]
+fn random_k256_sk_bytes<R: CryptoRng + RngCore>(csprng: &mut R) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    csprng.fill_bytes(&mut buf);
+    buf
+}
+
+/// Tests whether a failed `open` takes time that depends on where the tag is wrong, i.e., whether
+/// tag comparison is constant-time. Every input is a valid ciphertext with one bit of its tag
+/// flipped. In the fixed class, that's always in the first byte of the tag. In the random class,
+/// it's in a random byte.
+pub fn check_open_failure<A, Kdf, Kem, R>(samples: usize, csprng: &mut R) -> TimingReport
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    let (sk_recip, pk_recip) = Kem::gen_keypair(csprng);
+    let (encapped_key, mut sender_ctx) =
+        setup_sender::<A, Kdf, Kem, R>(&OpModeS::Base, &pk_recip, b"timing", csprng).unwrap();
+    let mut receiver_ctx =
+        setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"timing").unwrap();
+
+    // Opening fails without advancing the context, so every input is sealed under the same nonce.
+    // That's fine here, since nothing is ever successfully opened.
+    let mut ciphertext = [0u8; 64];
+    csprng.fill_bytes(&mut ciphertext);
+    let tag = sender_ctx
+        .seal_in_place_detached(&mut ciphertext, b"")
+        .unwrap()
+        .to_bytes();
+
+    run_leakage_test(
+        samples,
+        csprng,
+        |class, rng| {
+            let idx = match class {
+                Class::Fixed => 0,
+                Class::Random => rng.next_u32() as usize % tag.len(),
+            };
+            let mut bad_tag = tag.clone();
+            bad_tag[idx] ^= 1;
+            (ciphertext, AeadTag::<A>::from_bytes(&bad_tag).unwrap())
+        },
+        |(ciphertext, bad_tag)| {
+            let _ = black_box(receiver_ctx.open_in_place_detached(ciphertext, b"", bad_tag));
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{analyze, Class, TimingReport, Verdict, WelchT};
+    use crate::Vec;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Tests Welch's t statistic against a hand-computed value
+    #[test]
+    fn test_welch_t() {
+        let mut test = WelchT::default();
+        for x in [1.0, 2.0, 3.0, 4.0] {
+            test.push(Class::Fixed, x);
+        }
+        for x in [2.0, 3.0, 4.0, 5.0] {
+            test.push(Class::Random, x);
+        }
+        // Means 2.5 and 3.5, both variances 5/3, so t = 1 / sqrt(5/6)
+        assert!((test.t() - 1.0 / (5.0f64 / 6.0).sqrt()).abs() < 1e-12);
+
+        // Too few measurements in a class
+        let mut test = WelchT::default();
+        test.push(Class::Fixed, 1.0);
+        test.push(Class::Random, 1.0);
+        assert_eq!(test.t(), 0.0);
+    }
+
+    /// Tests that the analysis finds nothing in identically distributed classes, and finds a
+    /// shift between classes even when it's hidden under a long tail of outliers
+    #[test]
+    fn test_analyze() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let class = |rng: &mut StdRng| {
+            if rng.gen::<bool>() {
+                Class::Fixed
+            } else {
+                Class::Random
+            }
+        };
+        // Noise around 1000, with 5% huge outliers
+        let noisy = |rng: &mut StdRng| {
+            if rng.gen_range(0..20) == 0 {
+                rng.gen_range(100_000..1_000_000)
+            } else {
+                rng.gen_range(950..1050)
+            }
+        };
+
+        let same: Vec<(Class, u64)> = (0..100_000)
+            .map(|_| (class(&mut rng), noisy(&mut rng)))
+            .collect();
+        let report = analyze(&same);
+        assert_eq!(report.samples, 100_000);
+        assert_eq!(report.verdict(), Verdict::NoLeakDetected);
+
+        // The fixed class takes 5ns longer. The outliers swamp that in the uncropped test, but
+        // not in the cropped ones.
+        let shifted: Vec<(Class, u64)> = (0..100_000)
+            .map(|_| {
+                let c = class(&mut rng);
+                let t = noisy(&mut rng) + if c == Class::Fixed { 5 } else { 0 };
+                (c, t)
+            })
+            .collect();
+        let report = analyze(&shifted);
+        assert_eq!(report.verdict(), Verdict::DefiniteLeak);
+        assert!(report.crop_percentile.is_some());
+    }
+
+    #[test]
+    fn test_verdict() {
+        let report = |max_t| TimingReport {
+            samples: 0,
+            max_t,
+            crop_percentile: None,
+        };
+        assert_eq!(report(4.4).verdict(), Verdict::NoLeakDetected);
+        assert_eq!(report(4.5).verdict(), Verdict::PossibleLeak);
+        assert_eq!(report(10.0).verdict(), Verdict::DefiniteLeak);
+    }
+
+    /// Runs the real checks. Timing on a shared CI machine is too noisy to assert on, so this is
+    /// ignored by default. Run it with
+    /// `cargo test --release --features timing-tests,k256 timing -- --ignored --nocapture`.
+    #[cfg(feature = "k256")]
+    #[test]
+    #[ignore]
+    fn test_k256_timing() {
+        use super::{check_k256_dh, check_k256_private_key_parsing, check_open_failure};
+        use crate::{aead::AesGcm128, kdf::HkdfSha256, kem::DhK256HkdfSha256};
+
+        extern crate std;
+        use std::println;
+
+        let mut rng = StdRng::from_entropy();
+        let samples = 200_000;
+        for (name, report) in [
+            (
+                "private key parsing",
+                check_k256_private_key_parsing(samples, &mut rng),
+            ),
+            ("dh", check_k256_dh(samples, &mut rng)),
+            (
+                "open failure",
+                check_open_failure::<AesGcm128, HkdfSha256, DhK256HkdfSha256, _>(samples, &mut rng),
+            ),
+        ] {
+            println!("{}: {:?} -> {:?}", name, report, report.verdict());
+            assert_ne!(report.verdict(), Verdict::DefiniteLeak, "{}", name);
+        }
+    }
+}
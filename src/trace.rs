@@ -0,0 +1,195 @@
+//! Spans and events for the `tracing` feature. Only public values are recorded: the KEM, KDF, and
+//! AEAD IDs, the mode ID, sequence numbers, and which error happened. Keys, shared secrets,
+//! plaintexts, and ciphertexts never are.
+//!
+//! Without the feature, these compile to nothing, so call sites don't need `cfg`s.
+
+// The suite types are only read when there's something to record
+#![cfg_attr(not(feature = "tracing"), allow(clippy::extra_unused_type_parameters))]
+
+use crate::{aead::Aead, kdf::Kdf as KdfTrait, kem::Kem as KemTrait, HpkeError};
+
+/// Keeps the span from `enter_setup` entered until it's dropped
+pub(crate) struct SetupSpan {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+}
+
+/// Enters an `hpke_setup` span for a sender or receiver setup with mode ID `mode_id`. Events
+/// from `setup_error` are recorded in it.
+#[inline(always)]
+pub(crate) fn enter_setup<A, Kdf, Kem>(role: &'static str, mode_id: u8) -> SetupSpan
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    #[cfg(feature = "tracing")]
+    return SetupSpan {
+        _entered: tracing::debug_span!(
+            "hpke_setup",
+            role,
+            kem_id = Kem::KEM_ID,
+            kdf_id = Kdf::KDF_ID,
+            aead_id = A::AEAD_ID,
+            mode = mode_id,
+        )
+        .entered(),
+    };
+
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (role, mode_id);
+        SetupSpan {}
+    }
+}
+
+/// Records that encapsulation or decapsulation failed with `err`, and returns it
+#[inline(always)]
+pub(crate) fn setup_error(err: HpkeError) -> HpkeError {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error = %err, "HPKE setup failed");
+    err
+}
+
+/// Records that opening the message with sequence number `seq` failed with `err`, and returns it
+#[inline(always)]
+pub(crate) fn open_error<A, Kdf, Kem>(seq: u64, err: HpkeError) -> HpkeError
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        kem_id = Kem::KEM_ID,
+        kdf_id = Kdf::KDF_ID,
+        aead_id = A::AEAD_ID,
+        seq,
+        error = %err,
+        "HPKE open failed",
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = seq;
+    err
+}
+
+/// Records that a sender or receiver context ran out of sequence numbers, and returns
+/// `HpkeError::MessageLimitReached`
+#[inline(always)]
+pub(crate) fn limit_reached<A, Kdf, Kem>(role: &'static str) -> HpkeError
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        role,
+        kem_id = Kem::KEM_ID,
+        kdf_id = Kdf::KDF_ID,
+        aead_id = A::AEAD_ID,
+        "HPKE message limit reached",
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = role;
+    HpkeError::MessageLimitReached
+}
+
+#[cfg(all(test, feature = "tracing", feature = "std", feature = "x25519"))]
+mod test {
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        op_mode::{OpModeR, OpModeS},
+        setup_receiver, setup_sender,
+    };
+
+    use std::{
+        string::{String, ToString},
+        sync::{Arc, Mutex},
+        vec::Vec,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = X25519HkdfSha256;
+
+    /// Collects the fields of every event as "name=value" strings
+    #[derive(Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<Vec<String>>>>);
+
+    struct FieldVisitor<'a>(&'a mut Vec<String>);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+            self.0.push(std::format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Vec::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            self.0.lock().unwrap().push(fields);
+        }
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    /// Tests that failed opens are recorded with their suite and sequence number, and that no
+    /// event contains the plaintext
+    #[test]
+    fn test_open_failure_events() {
+        let collector = Collector::default();
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+        tracing::subscriber::with_default(collector.clone(), || {
+            let (encapped_key, mut sender_ctx) =
+                setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"", &mut csprng)
+                    .unwrap();
+            let mut receiver_ctx =
+                setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, b"")
+                    .unwrap();
+
+            let ciphertext = sender_ctx.seal(b"secret plaintext", b"").unwrap();
+            assert_eq!(
+                receiver_ctx.open(&ciphertext, b"").unwrap(),
+                b"secret plaintext"
+            );
+            // Sequence number 1 is sealed, but the receiver opens it with the wrong AAD
+            let ciphertext = sender_ctx.seal(b"secret plaintext", b"").unwrap();
+            assert!(receiver_ctx.open(&ciphertext, b"wrong aad").is_err());
+        });
+
+        let events = collector.0.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        let fields = &events[0];
+        for expected in [
+            "message=HPKE open failed",
+            "kem_id=32",
+            "kdf_id=1",
+            "aead_id=3",
+            "seq=1",
+        ] {
+            assert!(fields.contains(&expected.to_string()), "{:?}", fields);
+        }
+        assert!(!fields.iter().any(|f| f.contains("secret")));
+    }
+}
@@ -3,11 +3,11 @@ use crate::{aead::Aead, kdf::Kdf as KdfTrait, kem::Kem as KemTrait, HpkeError};
 use byteorder::{BigEndian, ByteOrder};
 
 /// Represents a ciphersuite context. That's "KEMXX", where `XX` is the KEM ID
-pub(crate) type KemSuiteId = [u8; 5];
+pub type KemSuiteId = [u8; 5];
 
 /// Represents a ciphersuite context. That's "HPKEXXYYZZ", where `XX` is the KEM ID, `YY` is the
 /// KDF ID, and `ZZ` is the AEAD ID
-pub(crate) type FullSuiteId = [u8; 10];
+pub type FullSuiteId = [u8; 10];
 
 // RFC 9180 §5.1
 // suite_id = concat(
@@ -18,7 +18,7 @@ pub(crate) type FullSuiteId = [u8; 10];
 // )
 
 /// Constructs the `suite_id` used as binding context in all functions in `setup` and `aead`
-pub(crate) fn full_suite_id<A, Kdf, Kem>() -> FullSuiteId
+pub fn full_suite_id<A, Kdf, Kem>() -> FullSuiteId
 where
     A: Aead,
     Kdf: KdfTrait,
@@ -39,7 +39,7 @@ where
 // suite_id = concat("KEM", I2OSP(kem_id, 2))
 
 /// Constructs the `suite_id` used as binding context in all functions in `kem`
-pub(crate) fn kem_suite_id<Kem: KemTrait>() -> KemSuiteId {
+pub fn kem_suite_id<Kem: KemTrait>() -> KemSuiteId {
     // XX is the KEM ID
     let mut suite_id = *b"KEMXX";
 
@@ -86,7 +86,7 @@ pub(crate) fn write_to_buf<'a>(buf: &'a mut [u8], to_write: &[u8]) -> &'a mut [u
 }
 
 /// Takes two lengths and returns an `Err(Error::IncorrectInputLength)` iff they don't match
-pub(crate) fn enforce_equal_len(expected_len: usize, given_len: usize) -> Result<(), HpkeError> {
+pub fn enforce_equal_len(expected_len: usize, given_len: usize) -> Result<(), HpkeError> {
     if given_len != expected_len {
         Err(HpkeError::IncorrectInputLength(expected_len, given_len))
     } else {
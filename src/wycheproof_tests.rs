@@ -0,0 +1,338 @@
+//! Runs the Wycheproof ECDH and AEAD test vectors against this crate. The vectors are not
+//! vendored. Check out https://github.com/C2SP/wycheproof into `./wycheproof`, or point the
+//! `WYCHEPROOF_DIR` environment variable at its `testvectors_v1` directory, and run
+//! `cargo test --features wycheproof`.
+
+use crate::{
+    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS, AeadKey, AeadNonce},
+    dhkex::DhKeyExchange,
+    kdf::HkdfSha256,
+    setup::ExporterSecret,
+    Deserializable, Serializable,
+};
+
+extern crate std;
+use std::{env, fs::File, path::PathBuf, string::String, vec::Vec};
+
+use generic_array::GenericArray;
+use serde::{de::Error as SError, Deserialize, Deserializer};
+use serde_derive::Deserialize;
+
+// AEAD contexts carry a KEM type parameter, but the KEM plays no part in seal/open. Use whichever
+// one is compiled in.
+#[cfg(feature = "x25519")]
+type CtxKem = crate::kem::X25519HkdfSha256;
+#[cfg(all(not(feature = "x25519"), feature = "p256"))]
+type CtxKem = crate::kem::DhP256HkdfSha256;
+#[cfg(all(not(feature = "x25519"), not(feature = "p256"), feature = "k256"))]
+type CtxKem = crate::kem::DhK256HkdfSha256;
+
+// Tells serde how to deserialize bytes from the hex representation
+fn bytes_from_hex<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hex_str = String::deserialize(deserializer)?;
+    hex::decode(hex_str).map_err(|e| SError::custom(format!("{:?}", e)))
+}
+
+/// The verdict Wycheproof assigns to each test case
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Verdict {
+    /// The operation must succeed and produce the given output
+    Valid,
+    /// The operation must fail
+    Invalid,
+    /// The operation may fail. If it succeeds, it must produce the given output.
+    Acceptable,
+}
+
+#[derive(Deserialize)]
+struct TestFile<G> {
+    #[serde(rename = "testGroups")]
+    test_groups: Vec<G>,
+}
+
+#[derive(Deserialize)]
+struct EcdhTestGroup {
+    curve: String,
+    encoding: String,
+    tests: Vec<EcdhTestVector>,
+}
+
+#[derive(Deserialize)]
+struct EcdhTestVector {
+    #[serde(rename = "tcId")]
+    tc_id: usize,
+    #[serde(rename = "public", deserialize_with = "bytes_from_hex")]
+    pk: Vec<u8>,
+    #[serde(rename = "private", deserialize_with = "bytes_from_hex")]
+    sk: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    shared: Vec<u8>,
+    result: Verdict,
+}
+
+#[derive(Deserialize)]
+struct AeadTestGroup {
+    #[serde(rename = "ivSize")]
+    iv_size: usize,
+    #[serde(rename = "keySize")]
+    key_size: usize,
+    #[serde(rename = "tagSize")]
+    tag_size: usize,
+    tests: Vec<AeadTestVector>,
+}
+
+#[derive(Deserialize)]
+struct AeadTestVector {
+    #[serde(rename = "tcId")]
+    tc_id: usize,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    key: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    iv: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    aad: Vec<u8>,
+    #[serde(rename = "msg", deserialize_with = "bytes_from_hex")]
+    plaintext: Vec<u8>,
+    #[serde(rename = "ct", deserialize_with = "bytes_from_hex")]
+    ciphertext: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    tag: Vec<u8>,
+    result: Verdict,
+}
+
+/// Opens the given Wycheproof file and parses its test groups. If `WYCHEPROOF_DIR` is unset and
+/// there's no checkout in the default location, returns `None` so the caller can skip. If
+/// `WYCHEPROOF_DIR` is set, a missing file is a hard failure, since a self-certification run that
+/// silently skips vectors is worse than useless.
+fn load_test_groups<G: for<'de> Deserialize<'de>>(filename: &str) -> Option<Vec<G>> {
+    let (dir, explicit) = match env::var_os("WYCHEPROOF_DIR") {
+        Some(dir) => (PathBuf::from(dir), true),
+        None => (PathBuf::from("wycheproof/testvectors_v1"), false),
+    };
+    let path = dir.join(filename);
+
+    if !explicit && !path.exists() {
+        std::eprintln!("skipping {}: no Wycheproof checkout found", filename);
+        return None;
+    }
+
+    let file =
+        File::open(&path).unwrap_or_else(|e| panic!("couldn't open {}: {}", path.display(), e));
+    let tf: TestFile<G> = serde_json::from_reader(file).expect("malformed Wycheproof file");
+    Some(tf.test_groups)
+}
+
+/// Converts a Wycheproof private key, which is a big-endian integer with arbitrary zero padding,
+/// into a fixed-width big-endian encoding. Returns `None` if the integer doesn't fit.
+fn normalize_scalar(bytes: &[u8], width: usize) -> Option<Vec<u8>> {
+    let first_nonzero = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+    let stripped = &bytes[first_nonzero..];
+    if stripped.len() > width {
+        return None;
+    }
+
+    let mut out = vec![0u8; width];
+    out[width - stripped.len()..].copy_from_slice(stripped);
+    Some(out)
+}
+
+// The DER prefixes of a SubjectPublicKeyInfo holding an uncompressed point on each curve. The
+// point itself is the remaining 65 bytes.
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+const K256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x56, 0x30, 0x10, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x05, 0x2b,
+    0x81, 0x04, 0x00, 0x0a, 0x03, 0x42, 0x00,
+];
+
+/// Extracts the encoded point from a public key in the given Wycheproof encoding. We only
+/// understand uncompressed SPKIs. Anything else is returned as-is and left for
+/// `PublicKey::from_bytes` to reject.
+fn extract_point<'a>(encoding: &str, curve: &str, pk: &'a [u8]) -> &'a [u8] {
+    let prefix = match curve {
+        "secp256r1" => P256_SPKI_PREFIX,
+        "secp256k1" => K256_SPKI_PREFIX,
+        _ => panic!("unsupported curve {}", curve),
+    };
+
+    match encoding {
+        "ecpoint" => pk,
+        "asn" if pk.starts_with(prefix) => &pk[prefix.len()..],
+        "asn" => pk,
+        _ => panic!("unsupported public key encoding {}", encoding),
+    }
+}
+
+/// Runs every test in the given ECDH test groups against `Kex::dh`
+fn run_ecdh_groups<Kex: DhKeyExchange>(groups: &[EcdhTestGroup], curve: &str) -> usize {
+    let mut num_run = 0;
+
+    for group in groups.iter().filter(|g| g.curve == curve) {
+        for tv in &group.tests {
+            let sk_bytes = normalize_scalar(&tv.sk, <Kex::PrivateKey as Serializable>::size());
+            let pk_bytes = extract_point(&group.encoding, &group.curve, &tv.pk);
+
+            // Every step is allowed to fail. We collect the result and judge it afterwards.
+            let dh_res = sk_bytes
+                .and_then(|b| Kex::PrivateKey::from_bytes(&b).ok())
+                .zip(Kex::PublicKey::from_bytes(pk_bytes).ok())
+                .and_then(|(sk, pk)| Kex::dh(&sk, &pk).ok());
+
+            match (tv.result, dh_res) {
+                (Verdict::Invalid, None) | (Verdict::Acceptable, None) => (),
+                (Verdict::Valid, None) => panic!("tcId {}: valid vector was rejected", tv.tc_id),
+                (Verdict::Invalid, Some(_)) => {
+                    panic!("tcId {}: invalid vector was accepted", tv.tc_id)
+                }
+                (_, Some(shared)) => assert_eq!(
+                    shared.to_bytes().as_slice(),
+                    tv.shared.as_slice(),
+                    "tcId {}: shared secret mismatch",
+                    tv.tc_id
+                ),
+            }
+
+            num_run += 1;
+        }
+    }
+
+    num_run
+}
+
+/// Runs every applicable test in the given AEAD test groups through a fresh `AeadCtxS` and
+/// `AeadCtxR`. At sequence number 0, the context nonce is exactly the base nonce, so the IV can be
+/// used as the base nonce directly.
+fn run_aead_groups<A: Aead>(groups: &[AeadTestGroup]) -> usize {
+    let key_size = <AeadKey<A> as Default>::default().0.len();
+    let nonce_size = <AeadNonce<A> as Default>::default().0.len();
+    let tag_size = crate::aead::AeadTag::<A>::size();
+    let mut num_run = 0;
+
+    // HPKE fixes the key, nonce, and tag sizes, so skip groups which use other ones
+    let applicable_groups = groups.iter().filter(|g| {
+        g.key_size == 8 * key_size && g.iv_size == 8 * nonce_size && g.tag_size == 8 * tag_size
+    });
+
+    for group in applicable_groups {
+        for tv in &group.tests {
+            let key = AeadKey::<A>(GenericArray::clone_from_slice(&tv.key));
+            let make_nonce = || AeadNonce::<A>(GenericArray::clone_from_slice(&tv.iv));
+            let mut sender_ctx: AeadCtxS<A, HkdfSha256, CtxKem> =
+                AeadCtx::new(&key, make_nonce(), ExporterSecret::default()).into();
+            let mut receiver_ctx: AeadCtxR<A, HkdfSha256, CtxKem> =
+                AeadCtx::new(&key, make_nonce(), ExporterSecret::default()).into();
+
+            let mut expected_ciphertext = tv.ciphertext.clone();
+            expected_ciphertext.extend_from_slice(&tv.tag);
+
+            // Valid vectors must seal to the given ciphertext. Invalid ones have mangled
+            // ciphertexts or tags, so there's nothing to compare the seal() output to.
+            if tv.result == Verdict::Valid {
+                let ciphertext = sender_ctx.seal(&tv.plaintext, &tv.aad).unwrap();
+                assert_eq!(
+                    ciphertext, expected_ciphertext,
+                    "tcId {}: ciphertext mismatch",
+                    tv.tc_id
+                );
+            }
+
+            match (tv.result, receiver_ctx.open(&expected_ciphertext, &tv.aad)) {
+                (Verdict::Invalid, Err(_)) | (Verdict::Acceptable, Err(_)) => (),
+                (Verdict::Valid, Err(e)) => {
+                    panic!("tcId {}: valid vector failed to open: {}", tv.tc_id, e)
+                }
+                (Verdict::Invalid, Ok(_)) => {
+                    panic!("tcId {}: invalid vector opened", tv.tc_id)
+                }
+                (_, Ok(plaintext)) => assert_eq!(
+                    plaintext, tv.plaintext,
+                    "tcId {}: plaintext mismatch",
+                    tv.tc_id
+                ),
+            }
+
+            num_run += 1;
+        }
+    }
+
+    num_run
+}
+
+/// Checks the harness itself against the RFC 5903 §8.1 ECDH vector, written in Wycheproof form.
+/// This runs even when no Wycheproof checkout is present.
+#[cfg(feature = "p256")]
+#[test]
+fn test_ecdh_harness() {
+    let json = r#"{"testGroups": [{
+        "curve": "secp256r1",
+        "encoding": "ecpoint",
+        "tests": [{
+            "tcId": 1,
+            "public": "04d12dfb5289c8d4f81208b70270398c342296970a0bccb74c736fc7554494bf6356fbf3ca366cc23e8157854c13c58d6aac23f046ada30f8353e74f33039872ab",
+            "private": "00c88f01f510d9ac3f70a292daa2316de544e9aab8afe84049c62a9c57862d1433",
+            "shared": "d6840f6b42f6edafd13116e0e12565202fef8e9ece7dce03812464d04b9442de",
+            "result": "valid"
+        }, {
+            "tcId": 2,
+            "public": "02d12dfb5289c8d4f81208b70270398c342296970a0bccb74c736fc7554494bf63",
+            "private": "c88f01f510d9ac3f70a292daa2316de544e9aab8afe84049c62a9c57862d1433",
+            "shared": "d6840f6b42f6edafd13116e0e12565202fef8e9ece7dce03812464d04b9442de",
+            "result": "acceptable"
+        }, {
+            "tcId": 3,
+            "public": "04d12dfb5289c8d4f81208b70270398c342296970a0bccb74c736fc7554494bf6356fbf3ca366cc23e8157854c13c58d6aac23f046ada30f8353e74f33039872ac",
+            "private": "c88f01f510d9ac3f70a292daa2316de544e9aab8afe84049c62a9c57862d1433",
+            "shared": "",
+            "result": "invalid"
+        }]
+    }]}"#;
+
+    let tf: TestFile<EcdhTestGroup> = serde_json::from_str(json).unwrap();
+    let num_run = run_ecdh_groups::<crate::dhkex::DhP256>(&tf.test_groups, "secp256r1");
+    assert_eq!(num_run, 3);
+}
+
+#[cfg(feature = "p256")]
+#[test]
+fn test_wycheproof_ecdh_p256() {
+    let Some(groups) = load_test_groups("ecdh_secp256r1_ecpoint_test.json") else {
+        return;
+    };
+    let num_run = run_ecdh_groups::<crate::dhkex::DhP256>(&groups, "secp256r1");
+    assert!(num_run > 0);
+}
+
+#[cfg(feature = "k256")]
+#[test]
+fn test_wycheproof_ecdh_k256() {
+    let Some(groups) = load_test_groups("ecdh_secp256k1_test.json") else {
+        return;
+    };
+    let num_run = run_ecdh_groups::<crate::dhkex::DhK256>(&groups, "secp256k1");
+    assert!(num_run > 0);
+}
+
+#[test]
+fn test_wycheproof_aes_gcm() {
+    let Some(groups) = load_test_groups("aes_gcm_test.json") else {
+        return;
+    };
+    let num_run = run_aead_groups::<crate::aead::AesGcm128>(&groups)
+        + run_aead_groups::<crate::aead::AesGcm256>(&groups);
+    assert!(num_run > 0);
+}
+
+#[test]
+fn test_wycheproof_chacha20poly1305() {
+    let Some(groups) = load_test_groups("chacha20_poly1305_test.json") else {
+        return;
+    };
+    let num_run = run_aead_groups::<crate::aead::ChaCha20Poly1305>(&groups);
+    assert!(num_run > 0);
+}
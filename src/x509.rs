@@ -0,0 +1,586 @@
+//! Sender identity keys from X.509 certificates, for Auth mode between parties that already have
+//! a PKI. This is gated under the `x509` feature.
+//!
+//! A sender presents its certificate chain, leaf first. The receiver checks it against its trust
+//! anchors with `verify_chain`, which gives back the sender's public key, or does that and the
+//! Auth mode setup in one step with `setup_receiver_x509`. Only P-256 and K-256 keys, and
+//! ECDSA-with-SHA256 signatures, are supported.
+//!
+//! The checks are: every certificate is within its validity period, each one names the next as
+//! its issuer and is signed by it, each issuer is a CA (basicConstraints, keyUsage, and path
+//! length), and the last one is a trust anchor or is signed by one. If the leaf has a keyUsage
+//! extension, it must allow keyAgreement. A certificate in the chain with any other critical
+//! extension is rejected, as RFC 5280 §4.2 requires of extensions that aren't processed.
+//! Revocation isn't checked. Callers that need it should check it separately.
+
+use crate::{
+    aead::{Aead, AeadCtxR},
+    dhkex,
+    kdf::Kdf as KdfTrait,
+    kem::{DhKem, Kem as KemTrait},
+    op_mode::OpModeR,
+    setup_receiver, Deserializable, HpkeError, Vec,
+};
+
+use x509_cert::{
+    der::{
+        oid::{AssociatedOid, ObjectIdentifier},
+        Decode, Encode,
+    },
+    ext::pkix::{BasicConstraints, KeyUsage},
+    Certificate,
+};
+
+// RFC 5480 §2.1.1: id-ecPublicKey
+const ID_EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+// RFC 5758 §3.2: ecdsa-with-SHA256
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+// RFC 5480 §2.1.1.1: secp256r1
+#[cfg(feature = "p256")]
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+// SEC 2 §A.2: secp256k1
+#[cfg(feature = "k256")]
+const SECP256K1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.10");
+
+/// A KEM whose public keys can be read out of X.509 certificates. This is implemented for DHKEMs
+/// over P-256 and K-256, with any KDF and KEM ID.
+pub trait X509Kem: KemTrait {
+    /// The named curve that the certificate's key must be on
+    #[doc(hidden)]
+    const CURVE_OID: ObjectIdentifier;
+}
+
+#[cfg(feature = "p256")]
+impl<Kdf: KdfTrait, const KEM_ID: u16> X509Kem for DhKem<dhkex::DhP256, Kdf, KEM_ID> {
+    const CURVE_OID: ObjectIdentifier = SECP256R1;
+}
+
+#[cfg(feature = "k256")]
+impl<Kdf: KdfTrait, const KEM_ID: u16> X509Kem for DhKem<dhkex::DhK256, Kdf, KEM_ID> {
+    const CURVE_OID: ObjectIdentifier = SECP256K1;
+}
+
+// Parses a DER certificate
+fn parse_cert(cert_der: &[u8]) -> Result<Certificate, HpkeError> {
+    Certificate::from_der(cert_der).map_err(|_| HpkeError::ValidationError)
+}
+
+// Returns the named curve and SEC1-encoded point of the certificate's key, if it's an EC key
+fn ec_key(cert: &Certificate) -> Option<(ObjectIdentifier, &[u8])> {
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    if spki.algorithm.oid != ID_EC_PUBLIC_KEY {
+        return None;
+    }
+    let curve = spki.algorithm.parameters.as_ref()?.decode_as().ok()?;
+    Some((curve, spki.subject_public_key.as_bytes()?))
+}
+
+// Returns the certificate's key as a Kem::PublicKey
+fn cert_public_key<Kem: X509Kem>(cert: &Certificate) -> Result<Kem::PublicKey, HpkeError> {
+    match ec_key(cert) {
+        Some((curve, point)) if curve == Kem::CURVE_OID => Kem::PublicKey::from_bytes(point),
+        _ => Err(HpkeError::ValidationError),
+    }
+}
+
+// Returns the certificate's extension of type T, if it has one
+fn extension<T>(cert: &Certificate) -> Result<Option<T>, HpkeError>
+where
+    T: AssociatedOid + for<'a> Decode<'a>,
+{
+    let extensions = cert.tbs_certificate.extensions.iter().flatten();
+    match extensions.into_iter().find(|ext| ext.extn_id == T::OID) {
+        Some(ext) => T::from_der(ext.extn_value.as_bytes())
+            .map(Some)
+            .map_err(|_| HpkeError::UntrustedSender),
+        None => Ok(None),
+    }
+}
+
+// Checks that every critical extension of the certificate is one this module processes. RFC 5280
+// §4.2 says to reject a certificate with a critical extension that isn't recognized.
+fn check_critical_extensions(cert: &Certificate) -> Result<(), HpkeError> {
+    let extensions = cert.tbs_certificate.extensions.iter().flatten();
+    let known = [BasicConstraints::OID, KeyUsage::OID];
+    if extensions
+        .into_iter()
+        .any(|ext| ext.critical && !known.contains(&ext.extn_id))
+    {
+        Err(HpkeError::UntrustedSender)
+    } else {
+        Ok(())
+    }
+}
+
+// Returns whether sig_der is a valid DER ECDSA-with-SHA256 signature of msg under the SEC1 point
+// on the given curve
+fn verify_ecdsa_sha256(curve: ObjectIdentifier, point: &[u8], msg: &[u8], sig_der: &[u8]) -> bool {
+    #[cfg(feature = "p256")]
+    if curve == SECP256R1 {
+        use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+        return match (
+            VerifyingKey::from_sec1_bytes(point),
+            Signature::from_der(sig_der),
+        ) {
+            (Ok(vk), Ok(sig)) => vk.verify(msg, &sig).is_ok(),
+            _ => false,
+        };
+    }
+
+    #[cfg(feature = "k256")]
+    if curve == SECP256K1 {
+        use k256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+        return match (
+            VerifyingKey::from_sec1_bytes(point),
+            Signature::from_der(sig_der),
+        ) {
+            (Ok(vk), Ok(sig)) => vk.verify(msg, &sig).is_ok(),
+            _ => false,
+        };
+    }
+
+    false
+}
+
+// Checks that `issuer` issued `cert`, and may issue certificates with `depth` intermediate
+// certificates below it
+fn check_issued_by(
+    cert: &Certificate,
+    issuer: &Certificate,
+    depth: usize,
+) -> Result<(), HpkeError> {
+    if cert.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+        return Err(HpkeError::UntrustedSender);
+    }
+
+    // The issuer has to be a CA, allowed to sign certificates, at this depth
+    let basic_constraints = extension::<BasicConstraints>(issuer)?;
+    let is_ca = basic_constraints.as_ref().is_some_and(|bc| bc.ca);
+    let path_len_ok = basic_constraints
+        .and_then(|bc| bc.path_len_constraint)
+        .map(|max| depth <= max as usize)
+        .unwrap_or(true);
+    let can_sign = extension::<KeyUsage>(issuer)?
+        .map(|ku| ku.key_cert_sign())
+        .unwrap_or(true);
+    if !(is_ca && path_len_ok && can_sign) {
+        return Err(HpkeError::UntrustedSender);
+    }
+
+    // The outer and inner signature algorithms must agree (RFC 5280 §4.1.1.2)
+    let alg = &cert.signature_algorithm;
+    if alg.oid != ECDSA_WITH_SHA256 || *alg != cert.tbs_certificate.signature {
+        return Err(HpkeError::UntrustedSender);
+    }
+    let tbs = cert
+        .tbs_certificate
+        .to_der()
+        .map_err(|_| HpkeError::ValidationError)?;
+    let sig = cert
+        .signature
+        .as_bytes()
+        .ok_or(HpkeError::UntrustedSender)?;
+    let (curve, point) = ec_key(issuer).ok_or(HpkeError::UntrustedSender)?;
+
+    if verify_ecdsa_sha256(curve, point, &tbs, sig) {
+        Ok(())
+    } else {
+        Err(HpkeError::UntrustedSender)
+    }
+}
+
+/// Reads the public key out of a DER certificate, without checking anything else about it. Use
+/// `verify_chain` to get a key that's trusted.
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::ValidationError)` if the certificate doesn't parse, or its key isn't
+/// an uncompressed point on `Kem`'s curve.
+pub fn public_key_from_cert<Kem: X509Kem>(cert_der: &[u8]) -> Result<Kem::PublicKey, HpkeError> {
+    cert_public_key::<Kem>(&parse_cert(cert_der)?)
+}
+
+/// Verifies the DER certificate chain `chain`, leaf first, against the DER certificates in
+/// `trust_anchors`, at the time `now_unix` in seconds since the Unix epoch. The last certificate
+/// in the chain can be a trust anchor itself, or be issued by one. See the module documentation
+/// for what's checked.
+///
+/// Return Value
+/// ============
+/// Returns the leaf certificate's public key on success. Returns
+/// `Err(HpkeError::ValidationError)` if `chain` is empty, a certificate doesn't parse, or the
+/// leaf's key isn't an uncompressed point on `Kem`'s curve. Returns
+/// `Err(HpkeError::UntrustedSender)` if any check on the chain fails.
+pub fn verify_chain<Kem: X509Kem>(
+    chain: &[&[u8]],
+    trust_anchors: &[&[u8]],
+    now_unix: u64,
+) -> Result<Kem::PublicKey, HpkeError> {
+    let certs = chain
+        .iter()
+        .map(|c| parse_cert(c))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (leaf, top) = match (certs.first(), certs.last()) {
+        (Some(leaf), Some(top)) => (leaf, top),
+        _ => return Err(HpkeError::ValidationError),
+    };
+
+    for cert in &certs {
+        let validity = &cert.tbs_certificate.validity;
+        let not_before = validity.not_before.to_unix_duration().as_secs();
+        let not_after = validity.not_after.to_unix_duration().as_secs();
+        if now_unix < not_before || now_unix > not_after {
+            return Err(HpkeError::UntrustedSender);
+        }
+        check_critical_extensions(cert)?;
+    }
+
+    // certs[i + 1] issued certs[i], and has the i intermediates certs[1..=i] below it
+    for (depth, pair) in certs.windows(2).enumerate() {
+        check_issued_by(&pair[0], &pair[1], depth)?;
+    }
+
+    // Anchor the top of the chain
+    let top_der = chain[chain.len() - 1];
+    let top_depth = certs.len() - 1;
+    let mut anchored = false;
+    for anchor in trust_anchors {
+        if *anchor == top_der {
+            anchored = true;
+        } else if let Ok(anchor) = parse_cert(anchor) {
+            anchored = check_issued_by(top, &anchor, top_depth).is_ok();
+        }
+        if anchored {
+            break;
+        }
+    }
+    if !anchored {
+        return Err(HpkeError::UntrustedSender);
+    }
+
+    // The leaf's key is for key agreement
+    if let Some(key_usage) = extension::<KeyUsage>(leaf)? {
+        if !key_usage.key_agreement() {
+            return Err(HpkeError::UntrustedSender);
+        }
+    }
+
+    cert_public_key::<Kem>(leaf)
+}
+
+/// Verifies the sender's certificate chain with `verify_chain`, then initiates a decryption
+/// context in Auth mode with the leaf certificate's key as the sender's identity key. The chain
+/// is checked before decapsulation, so an untrusted sender costs no DH operations.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. Otherwise, returns the errors `verify_chain` and
+/// `setup_receiver` do.
+pub fn setup_receiver_x509<A, Kdf, Kem>(
+    chain: &[&[u8]],
+    trust_anchors: &[&[u8]],
+    now_unix: u64,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: X509Kem,
+{
+    let pk_sender_id = verify_chain::<Kem>(chain, trust_anchors, now_unix)?;
+    setup_receiver(&OpModeR::Auth(pk_sender_id), sk_recip, encapped_key, info)
+}
+
+#[cfg(all(test, feature = "p256"))]
+mod test {
+    use super::{
+        public_key_from_cert, setup_receiver_x509, verify_chain, ECDSA_WITH_SHA256,
+        ID_EC_PUBLIC_KEY, SECP256R1,
+    };
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{DhP256HkdfSha256, Kem as KemTrait},
+        setup_sender, HpkeError, OpModeS, Serializable, Vec,
+    };
+
+    use core::{str::FromStr, time::Duration};
+
+    use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use rand::{rngs::StdRng, SeedableRng};
+    use x509_cert::{
+        der::{
+            asn1::{BitString, OctetString, UtcTime},
+            oid::{AssociatedOid, ObjectIdentifier},
+            Any, Encode,
+        },
+        ext::{
+            pkix::{BasicConstraints, KeyUsage, KeyUsages},
+            Extension,
+        },
+        name::Name,
+        serial_number::SerialNumber,
+        spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned},
+        time::{Time, Validity},
+        Certificate, TbsCertificate, Version,
+    };
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+    type Kem = DhP256HkdfSha256;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn time(unix: u64) -> Time {
+        Time::UtcTime(UtcTime::from_unix_duration(Duration::from_secs(unix)).unwrap())
+    }
+
+    fn ext<T: AssociatedOid + Encode>(value: T) -> Extension {
+        Extension {
+            extn_id: T::OID,
+            critical: true,
+            extn_value: OctetString::new(value.to_der().unwrap()).unwrap(),
+        }
+    }
+
+    // Makes a certificate for the P-256 point `pk`, signed by `signer`
+    fn make_cert(
+        subject: &str,
+        issuer: &str,
+        pk: &[u8],
+        signer: &SigningKey,
+        extensions: Vec<Extension>,
+        not_after: u64,
+    ) -> Vec<u8> {
+        let sig_alg = AlgorithmIdentifierOwned {
+            oid: ECDSA_WITH_SHA256,
+            parameters: None,
+        };
+        let tbs_certificate = TbsCertificate {
+            version: Version::V3,
+            serial_number: SerialNumber::new(&[1]).unwrap(),
+            signature: sig_alg.clone(),
+            issuer: Name::from_str(issuer).unwrap(),
+            validity: Validity {
+                not_before: time(NOW - 1000),
+                not_after: time(not_after),
+            },
+            subject: Name::from_str(subject).unwrap(),
+            subject_public_key_info: SubjectPublicKeyInfoOwned {
+                algorithm: AlgorithmIdentifierOwned {
+                    oid: ID_EC_PUBLIC_KEY,
+                    parameters: Some(Any::encode_from(&SECP256R1).unwrap()),
+                },
+                subject_public_key: BitString::from_bytes(pk).unwrap(),
+            },
+            issuer_unique_id: None,
+            subject_unique_id: None,
+            extensions: Some(extensions),
+        };
+        let sig: Signature = signer.sign(&tbs_certificate.to_der().unwrap());
+        Certificate {
+            tbs_certificate,
+            signature_algorithm: sig_alg,
+            signature: BitString::from_bytes(sig.to_der().as_bytes()).unwrap(),
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    fn point(key: &SigningKey) -> Vec<u8> {
+        key.verifying_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn ca_exts(path_len: Option<u8>) -> Vec<Extension> {
+        vec![
+            ext(BasicConstraints {
+                ca: true,
+                path_len_constraint: path_len,
+            }),
+            ext(KeyUsage(KeyUsages::KeyCertSign.into())),
+        ]
+    }
+
+    /// Tests that a root -> intermediate -> leaf chain verifies and opens in Auth mode, and that
+    /// broken chains don't
+    #[test]
+    fn test_x509_chain() {
+        let mut csprng = StdRng::from_entropy();
+        let root = SigningKey::random(&mut csprng);
+        let inter = SigningKey::random(&mut csprng);
+        let (sk_sender, pk_sender) = Kem::gen_keypair(&mut csprng);
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+        let root_cert = make_cert(
+            "CN=Root",
+            "CN=Root",
+            &point(&root),
+            &root,
+            ca_exts(None),
+            NOW + 1000,
+        );
+        let inter_cert = make_cert(
+            "CN=Inter",
+            "CN=Root",
+            &point(&inter),
+            &root,
+            ca_exts(Some(0)),
+            NOW + 1000,
+        );
+        let leaf_exts = vec![ext(KeyUsage(KeyUsages::KeyAgreement.into()))];
+        let leaf_cert = make_cert(
+            "CN=Sender",
+            "CN=Inter",
+            &pk_sender.to_bytes(),
+            &inter,
+            leaf_exts.clone(),
+            NOW + 1000,
+        );
+        let chain: [&[u8]; 2] = [&leaf_cert, &inter_cert];
+        let anchors: [&[u8]; 1] = [&root_cert];
+
+        let pk = verify_chain::<Kem>(&chain, &anchors, NOW).unwrap();
+        assert_eq!(pk.to_bytes(), pk_sender.to_bytes());
+        assert_eq!(
+            public_key_from_cert::<Kem>(&leaf_cert).unwrap().to_bytes(),
+            pk_sender.to_bytes()
+        );
+        // The root can also be sent along
+        let full: [&[u8]; 3] = [&leaf_cert, &inter_cert, &root_cert];
+        verify_chain::<Kem>(&full, &anchors, NOW).unwrap();
+
+        let sender_mode = OpModeS::Auth((sk_sender, pk_sender.clone()));
+        let (encapped_key, mut sender_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&sender_mode, &pk_recip, b"info", &mut csprng).unwrap();
+        let ciphertext = sender_ctx.seal(b"msg", b"").unwrap();
+        let mut receiver_ctx = setup_receiver_x509::<A, Kdf, Kem>(
+            &chain,
+            &anchors,
+            NOW,
+            &sk_recip,
+            &encapped_key,
+            b"info",
+        )
+        .unwrap();
+        assert_eq!(receiver_ctx.open(&ciphertext, b"").unwrap(), b"msg");
+
+        let untrusted = Some(HpkeError::UntrustedSender);
+        // Expired, not yet valid, and unanchored chains
+        assert_eq!(
+            verify_chain::<Kem>(&chain, &anchors, NOW + 1001).err(),
+            untrusted
+        );
+        assert_eq!(
+            verify_chain::<Kem>(&chain, &anchors, NOW - 1001).err(),
+            untrusted
+        );
+        assert_eq!(verify_chain::<Kem>(&chain, &[], NOW).err(), untrusted);
+        // A missing intermediate
+        assert_eq!(
+            verify_chain::<Kem>(&chain[..1], &anchors, NOW).err(),
+            untrusted
+        );
+
+        // A leaf signed by the wrong key, a non-CA issuer, and a path that's too long
+        let forged = make_cert(
+            "CN=Sender",
+            "CN=Inter",
+            &pk_sender.to_bytes(),
+            &root,
+            leaf_exts.clone(),
+            NOW + 1000,
+        );
+        assert_eq!(
+            verify_chain::<Kem>(&[&forged, &inter_cert], &anchors, NOW).err(),
+            untrusted
+        );
+        let not_ca = make_cert(
+            "CN=Inter",
+            "CN=Root",
+            &point(&inter),
+            &root,
+            vec![],
+            NOW + 1000,
+        );
+        assert_eq!(
+            verify_chain::<Kem>(&[&leaf_cert, &not_ca], &anchors, NOW).err(),
+            untrusted
+        );
+        let inter2 = SigningKey::random(&mut csprng);
+        let inter2_cert = make_cert(
+            "CN=Inter2",
+            "CN=Inter",
+            &point(&inter2),
+            &inter,
+            ca_exts(None),
+            NOW + 1000,
+        );
+        let deep_leaf = make_cert(
+            "CN=Sender",
+            "CN=Inter2",
+            &pk_sender.to_bytes(),
+            &inter2,
+            leaf_exts,
+            NOW + 1000,
+        );
+        assert_eq!(
+            verify_chain::<Kem>(&[&deep_leaf, &inter2_cert, &inter_cert], &anchors, NOW).err(),
+            untrusted
+        );
+
+        // A leaf that isn't for key agreement
+        let signing_leaf = make_cert(
+            "CN=Sender",
+            "CN=Inter",
+            &pk_sender.to_bytes(),
+            &inter,
+            vec![ext(KeyUsage(KeyUsages::DigitalSignature.into()))],
+            NOW + 1000,
+        );
+        assert_eq!(
+            verify_chain::<Kem>(&[&signing_leaf, &inter_cert], &anchors, NOW).err(),
+            untrusted
+        );
+
+        // A leaf with a critical extension that isn't processed, and the same one non-critical
+        let mut unknown_ext = ext(KeyUsage(KeyUsages::KeyAgreement.into()));
+        unknown_ext.extn_id = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.99999.1");
+        let mut exts = vec![ext(KeyUsage(KeyUsages::KeyAgreement.into())), unknown_ext];
+        let critical_leaf = make_cert(
+            "CN=Sender",
+            "CN=Inter",
+            &pk_sender.to_bytes(),
+            &inter,
+            exts.clone(),
+            NOW + 1000,
+        );
+        assert_eq!(
+            verify_chain::<Kem>(&[&critical_leaf, &inter_cert], &anchors, NOW).err(),
+            untrusted
+        );
+        exts[1].critical = false;
+        let noncritical_leaf = make_cert(
+            "CN=Sender",
+            "CN=Inter",
+            &pk_sender.to_bytes(),
+            &inter,
+            exts,
+            NOW + 1000,
+        );
+        verify_chain::<Kem>(&[&noncritical_leaf, &inter_cert], &anchors, NOW).unwrap();
+
+        assert_eq!(
+            verify_chain::<Kem>(&[], &anchors, NOW).err(),
+            Some(HpkeError::ValidationError)
+        );
+        assert_eq!(
+            public_key_from_cert::<Kem>(b"not a certificate").err(),
+            Some(HpkeError::ValidationError)
+        );
+    }
+}